@@ -0,0 +1,152 @@
+//! Codec micro-benchmarks
+//!
+//! Run with `cargo bench --bench codecs` (optionally followed by a filter
+//! such as `-- lzss_decompress`). Inputs are generated in-process so results
+//! are reproducible without game data; any LF2 files in
+//! `test_assets/generated/` are added to the decompression group.
+
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use retro_decode::decoder::Orientation;
+use retro_decode::formats::toheart::encode_profile::EncodeProfile;
+use retro_decode::formats::toheart::lf2::{Lf2Image, Rgb};
+use retro_decode::formats::toheart::naive_scan_lzss::{
+    compress_hash_chain, compress_naive_backward_window, HashMode,
+};
+use retro_decode::formats::toheart::okumura_lzss::compress_okumura;
+use retro_decode::lzss::LzssSpec;
+use retro_decode::output::{write_rgb565, Endianness};
+
+/// Deterministic image with runs, gradients and some noise, roughly the
+/// mix found in character sprites
+fn synthetic_lf2(width: u16, height: u16) -> Lf2Image {
+    let mut seed = 0x1234_5678u32;
+    let pixels = (0..width as usize * height as usize)
+        .map(|i| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            if (seed >> 16) % 8 == 0 {
+                ((seed >> 20) % 64) as u8
+            } else {
+                ((i % width as usize / 13 + i / width as usize / 7) % 64) as u8
+            }
+        })
+        .collect();
+    Lf2Image {
+        width,
+        height,
+        x_offset: 0,
+        y_offset: 0,
+        transparent_color: 0,
+        color_count: 64,
+        palette: (0..64u8).map(|c| Rgb { r: c * 4, g: 255 - c * 4, b: c }).collect(),
+        pixels,
+    }
+}
+
+/// `(name, LF2 file bytes)` for the decompression group
+fn lf2_corpus() -> Vec<(String, Vec<u8>)> {
+    let mut corpus = vec![
+        ("synthetic_640x480".to_string(), synthetic_lf2(640, 480).to_lf2_bytes_okumura().unwrap()),
+        ("synthetic_128x128".to_string(), synthetic_lf2(128, 128).to_lf2_bytes_okumura().unwrap()),
+    ];
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_assets/generated");
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("lf2")) {
+                if let Ok(data) = std::fs::read(&path) {
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    corpus.push((name, data));
+                }
+            }
+        }
+    }
+    corpus
+}
+
+fn lzss_decompress(c: &mut Criterion) {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut group = c.benchmark_group("lzss_decompress");
+    for (name, data) in lf2_corpus() {
+        let Ok(image) = Lf2Image::from_data(&data) else { continue };
+        let stream = &data[0x18 + image.color_count as usize * 3..];
+        let len = image.pixels.len();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("serial", &name), &stream, |b, s| {
+            b.iter(|| LzssSpec::LF2.decompress(black_box(s), len))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", &name), &stream, |b, s| {
+            b.iter(|| LzssSpec::LF2.decompress_parallel(black_box(s), len, threads))
+        });
+    }
+    group.finish();
+}
+
+fn match_finders(c: &mut Criterion) {
+    let image = synthetic_lf2(256, 128);
+    let input = image.stored_pixels();
+    let mut group = c.benchmark_group("match_finders");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("okumura_tree", |b| b.iter(|| compress_okumura(black_box(&input))));
+    group.bench_function("hash_chain_best", |b| {
+        b.iter(|| compress_hash_chain(black_box(&input), HashMode::BestMatch))
+    });
+    group.bench_function("linear_scan_window", |b| {
+        b.iter(|| compress_naive_backward_window(black_box(&input), false, 0x1000 - 18))
+    });
+    for profile in EncodeProfile::ALL {
+        group.bench_function(BenchmarkId::new("profile", profile.name()), |b| {
+            b.iter(|| image.to_lf2_bytes_with_params(&profile.params()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn palette_conversion(c: &mut Criterion) {
+    let image = synthetic_lf2(640, 480);
+    let mut group = c.benchmark_group("palette_conversion");
+    group.throughput(Throughput::Elements(image.pixels.len() as u64));
+    for orientation in [Orientation::Display, Orientation::Stored] {
+        group.bench_function(BenchmarkId::new("to_rgba_image", format!("{:?}", orientation)), |b| {
+            b.iter(|| image.to_rgba_image(black_box(orientation)))
+        });
+    }
+    group.finish();
+}
+
+fn writers(c: &mut Criterion) {
+    let rgba = synthetic_lf2(640, 480).to_rgba_image(Orientation::Display);
+    let mut group = c.benchmark_group("writers");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(rgba.pixels().len() as u64));
+    group.bench_function("png", |b| {
+        b.iter(|| {
+            let mut out = std::io::Cursor::new(Vec::new());
+            rgba.write_to(&mut out, image::ImageOutputFormat::Png).unwrap();
+            out.into_inner()
+        })
+    });
+    group.bench_function("bmp", |b| {
+        b.iter(|| {
+            let mut out = std::io::Cursor::new(Vec::new());
+            rgba.write_to(&mut out, image::ImageOutputFormat::Bmp).unwrap();
+            out.into_inner()
+        })
+    });
+    for order in [Endianness::Little, Endianness::Big] {
+        group.bench_function(BenchmarkId::new("rgb565", format!("{:?}", order)), |b| {
+            b.iter(|| {
+                let mut out = Vec::with_capacity(rgba.pixels().len() * 2);
+                write_rgb565(&mut out, rgba.pixels().map(|p| [p[0], p[1], p[2]]), order).unwrap();
+                out
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lzss_decompress, match_finders, palette_conversion, writers);
+criterion_main!(benches);
@@ -3,6 +3,7 @@
 
 use retro_decode::formats::toheart::test_transparency::create_test_transparency_image;
 use retro_decode::formats::toheart::lf2::{Lf2Image, Rgb};
+use retro_decode::formats::kanon::pdt::{PdtImage, PdtLayout, RgbColor};
 use retro_decode::DecodeConfig;
 use std::path::Path;
 use std::fs;
@@ -20,8 +21,8 @@ fn main() -> anyhow::Result<()> {
     // Generate various LF2 test images
     generate_lf2_test_files(&config)?;
     
-    // Generate PDT test files would go here when PDT generation is implemented
-    // generate_pdt_test_files(&config)?;
+    // Generate PDT test files (both header layouts)
+    generate_pdt_test_files()?;
     
     println!("\n✅ Test asset generation completed!");
     println!("\nGenerated files are safe to commit to the repository.");
@@ -73,6 +74,46 @@ fn generate_lf2_test_files(config: &DecodeConfig) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn generate_pdt_test_files() -> anyhow::Result<()> {
+    println!("\n🎨 Generating PDT test files...");
+    
+    // 8x8 gradient with a transparent diagonal
+    let (width, height) = (8u32, 8u32);
+    let mut pixels = Vec::new();
+    let mut alpha_mask = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(RgbColor {
+                r: (x * 32) as u8,
+                g: (y * 32) as u8,
+                b: ((x + y) * 16) as u8,
+            });
+            alpha_mask.push(if x == y { 0 } else { 255 });
+        }
+    }
+    let image = PdtImage {
+        width,
+        height,
+        file_length: 0,
+        layout: PdtLayout::Standard,
+        mask_offset: 0,
+        pixels,
+        alpha_mask,
+    };
+    
+    // Standard layout with mask, and the early mask-less layout
+    let files = [
+        ("test_assets/pdt/synthetic_standard_8x8.pdt", PdtLayout::Standard),
+        ("test_assets/pdt/synthetic_legacy_8x8.pdt", PdtLayout::Legacy),
+    ];
+    for (path, layout) in &files {
+        fs::write(path, image.to_pdt_bytes(*layout))?;
+        println!("  ✓ Created {}", path);
+    }
+    
+    Ok(())
+}
+
 /// Create a larger test image with more complex patterns
 fn create_test_large_image() -> Lf2Image {
     let width = 16;
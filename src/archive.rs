@@ -0,0 +1,320 @@
+//! One-shot archival of a game directory (`archive-game`)
+//!
+//! Chains the steps an archival run otherwise takes one command at a time:
+//! scan the game directory, unpack every PAK into `extracted/`, decode every
+//! image (PAK entries and MGR sub-images included) to PNG under `images/`,
+//! and describe the result in `catalog.json` and a browsable `index.html`.
+//! The game directory is only read; an output directory inside it is
+//! skipped by the scan. Files that fail to decode are noted in the catalog
+//! and the run carries on.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::container::open_container;
+use crate::formats::FormatType;
+use crate::formats::toheart::PakArchive;
+use crate::DecodeConfig;
+
+/// Raw PAK contents, mirroring the game directory
+pub const EXTRACTED_DIR: &str = "extracted";
+/// Decoded PNGs, mirroring the game directory
+pub const IMAGES_DIR: &str = "images";
+pub const CATALOG_FILE: &str = "catalog.json";
+pub const GALLERY_FILE: &str = "index.html";
+
+/// `catalog.json`
+#[derive(Debug, Clone, Serialize)]
+pub struct Catalog {
+    pub tool_version: String,
+    pub game_dir: String,
+    pub files: Vec<CatalogFile>,
+}
+
+/// One file of the game directory
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogFile {
+    /// Relative to the game directory, `/`-separated
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// `None` for files retro-decode does not recognise
+    pub format: Option<String>,
+    /// Where a PAK was unpacked, relative to the output directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extracted: Option<String>,
+    pub images: Vec<CatalogImage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// A decoded image
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogImage {
+    /// Entry name inside the file (the file name for plain images)
+    pub entry: String,
+    /// PNG relative to the output directory, `/`-separated
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Counts of an archival run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    pub files: usize,
+    /// PAK archives unpacked
+    pub archives: usize,
+    pub images: usize,
+    /// Files or entries that could not be read or decoded
+    pub failures: usize,
+}
+
+/// Archive `game_dir` into `out`
+pub fn archive_game(game_dir: &Path, out: &Path, direct: bool) -> Result<ArchiveSummary> {
+    let game_dir = game_dir.canonicalize()
+        .map_err(|e| anyhow!("Failed to open {}: {}", game_dir.display(), e))?;
+    std::fs::create_dir_all(out)?;
+    let out = out.canonicalize()?;
+    if game_dir.starts_with(&out) {
+        return Err(anyhow!("Output directory {} contains the game directory", out.display()));
+    }
+
+    let config = DecodeConfig { direct_writes: direct, ..Default::default() };
+    let mut summary = ArchiveSummary::default();
+    let mut files = Vec::new();
+    for path in crate::paths::files_in(&game_dir, true)? {
+        if path.starts_with(&out) {
+            continue;
+        }
+        let relative = path.strip_prefix(&game_dir)?.to_path_buf();
+        let file = archive_file(&path, &relative, &out, &config, &mut summary)?;
+        summary.files += 1;
+        files.push(file);
+    }
+
+    let catalog = Catalog {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        game_dir: game_dir.display().to_string(),
+        files,
+    };
+    crate::output::write_bytes(&out.join(CATALOG_FILE), direct, &serde_json::to_vec_pretty(&catalog)?)?;
+    crate::output::write_bytes(&out.join(GALLERY_FILE), direct, gallery_html(&catalog).as_bytes())?;
+    Ok(summary)
+}
+
+fn archive_file(
+    path: &Path,
+    relative: &Path,
+    out: &Path,
+    config: &DecodeConfig,
+    summary: &mut ArchiveSummary,
+) -> Result<CatalogFile> {
+    let data = std::fs::read(path)?;
+    let format = FormatType::from_path(path).or_else(|_| FormatType::from_bytes(&data)).ok();
+    let mut file = CatalogFile {
+        path: slash_path(relative),
+        size: data.len() as u64,
+        sha256: crate::checksum::sha256_hex(&data),
+        format: format.as_ref().map(|f| f.to_string()),
+        extracted: None,
+        images: Vec::new(),
+        errors: Vec::new(),
+    };
+    let Some(format) = format else { return Ok(file) };
+    drop(data);
+
+    let fail = |file: &mut CatalogFile, message: String| {
+        warn!("{}: {}", path.display(), message);
+        file.errors.push(message);
+    };
+
+    let unpacked = relative.with_extension("");
+    if format == FormatType::ToHeartPak {
+        let dir = Path::new(EXTRACTED_DIR).join(&unpacked);
+        match PakArchive::open(path).and_then(|mut pak| pak.extract(&out.join(&dir), config)) {
+            Ok(()) => file.extracted = Some(slash_path(&dir)),
+            Err(e) => fail(&mut file, format!("extract: {}", e)),
+        }
+    }
+
+    let mut container = match open_container(path) {
+        Ok(container) => container,
+        Err(e) => {
+            fail(&mut file, e.to_string());
+            summary.failures += file.errors.len();
+            return Ok(file);
+        }
+    };
+    // Single images sit next to their siblings, multi-image files get a
+    // directory of their own
+    let image_dir = if format == FormatType::ToHeartPak || format == FormatType::SilkyMgr {
+        Path::new(IMAGES_DIR).join(&unpacked)
+    } else {
+        Path::new(IMAGES_DIR).join(relative.parent().unwrap_or(Path::new("")))
+    };
+    for index in 0..container.entries().len() {
+        let entry = container.entries()[index].clone();
+        if entry.format.is_none() {
+            continue;
+        }
+        let image = container.decode_entry(index).and_then(|decoded| {
+            image::RgbaImage::from_raw(decoded.width, decoded.height, decoded.rgba)
+                .ok_or_else(|| anyhow!("decoded buffer does not match {}x{}", decoded.width, decoded.height))
+        });
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                fail(&mut file, format!("{}: {}", entry.name, e));
+                continue;
+            }
+        };
+        let stem = Path::new(&entry.name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let png = image_dir.join(format!("{}.png", stem));
+        std::fs::create_dir_all(out.join(&image_dir))?;
+        crate::output::write_rgba_image(&image, &out.join(&png), config.direct_writes)?;
+        file.images.push(CatalogImage {
+            entry: entry.name,
+            path: slash_path(&png),
+            width: image.width(),
+            height: image.height(),
+        });
+        summary.images += 1;
+    }
+    if file.extracted.is_some() {
+        summary.archives += 1;
+    }
+    summary.failures += file.errors.len();
+    Ok(file)
+}
+
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `index.html`: every image, grouped by the directory it was written to
+pub fn gallery_html(catalog: &Catalog) -> String {
+    let mut groups: BTreeMap<&str, Vec<&CatalogImage>> = BTreeMap::new();
+    for image in catalog.files.iter().flat_map(|f| &f.images) {
+        let dir = image.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        groups.entry(dir).or_default().push(image);
+    }
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>retro-decode archive</title>\n\
+         <style>\n\
+         body { font-family: sans-serif; margin: 1em; }\n\
+         figure { display: inline-block; margin: 0.5em; vertical-align: top; }\n\
+         img { max-width: 320px; image-rendering: pixelated; \
+         background: repeating-conic-gradient(#ccc 0 25%, #fff 0 50%) 0 0 / 16px 16px; }\n\
+         figcaption { font-size: small; }\n\
+         </style></head><body>\n",
+    );
+    let _ = writeln!(
+        html,
+        "<h1>{}</h1>\n<p>{} images from {} files</p>",
+        html_escape(&catalog.game_dir),
+        groups.values().map(Vec::len).sum::<usize>(),
+        catalog.files.len()
+    );
+    for (dir, images) in &groups {
+        let _ = writeln!(html, "<h2>{}</h2>", html_escape(dir));
+        for image in images {
+            let href = html_escape(&percent_encode(&image.path));
+            let _ = writeln!(
+                html,
+                "<figure><a href=\"{0}\"><img src=\"{0}\" loading=\"lazy\" alt=\"\"></a>\
+                 <figcaption>{1} {2}x{3}</figcaption></figure>",
+                href,
+                html_escape(&image.entry),
+                image.width,
+                image.height
+            );
+        }
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+    use crate::formats::toheart::pak::write_archive;
+
+    fn lf2(width: u16, height: u16) -> Vec<u8> {
+        Lf2Image {
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 0, b: 0 }],
+            pixels: (0..width as usize * height as usize).map(|i| (i % 2) as u8).collect(),
+        }
+        .to_lf2_bytes()
+        .unwrap()
+    }
+
+    #[test]
+    fn archives_images_and_pak_entries() {
+        let game = tempfile::tempdir().unwrap();
+        std::fs::create_dir(game.path().join("sprite")).unwrap();
+        std::fs::write(game.path().join("sprite/C0101.LF2"), lf2(4, 3)).unwrap();
+        std::fs::write(game.path().join("README.TXT"), b"ToHeart").unwrap();
+        let files = vec![
+            ("S01.LF2".to_string(), lf2(2, 2)),
+            ("BROKEN.LF2".to_string(), b"LEAF256\0".to_vec()),
+            ("MUSIC.DAT".to_string(), vec![0; 16]),
+        ];
+        std::fs::write(game.path().join("LVNS3DAT.PAK"), write_archive(&files, &[0x2a; 11]).unwrap()).unwrap();
+
+        // Output inside the game directory is left out of the scan
+        let out = game.path().join("archive");
+        let summary = archive_game(game.path(), &out, false).unwrap();
+        assert_eq!(summary, ArchiveSummary { files: 3, archives: 1, images: 2, failures: 1 });
+        assert!(out.join("extracted/LVNS3DAT/MUSIC.DAT").exists());
+        assert!(out.join("images/sprite/C0101.png").exists());
+        assert!(out.join("images/LVNS3DAT/S01.png").exists());
+
+        let catalog: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out.join(CATALOG_FILE)).unwrap()).unwrap();
+        let files = catalog["files"].as_array().unwrap();
+        let paths: Vec<&str> = files.iter().map(|f| f["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["LVNS3DAT.PAK", "README.TXT", "sprite/C0101.LF2"]);
+        assert_eq!(files[0]["extracted"], "extracted/LVNS3DAT");
+        assert_eq!(files[0]["errors"].as_array().unwrap().len(), 1);
+        assert!(files[1]["format"].is_null());
+        assert_eq!(files[2]["images"][0]["width"], 4);
+        assert_eq!(files[2]["sha256"], crate::checksum::sha256_hex(&lf2(4, 3)));
+
+        let html = std::fs::read_to_string(out.join(GALLERY_FILE)).unwrap();
+        assert!(html.contains("<img src=\"images/sprite/C0101.png\""));
+        assert!(html.contains("<h2>images/LVNS3DAT</h2>"));
+        assert!(html.contains("2 images from 3 files"));
+    }
+}
@@ -0,0 +1,168 @@
+//! Async decoding for server contexts
+//!
+//! Decoding a large LF2 or MAG is CPU-bound work that would stall a tokio
+//! worker thread. [`AsyncDecoder`] runs [`Decoder::decode`](crate::decoder::Decoder::decode)
+//! on the blocking pool, caps how many decodes run at once and rejects new
+//! requests with [`AsyncDecodeError::Busy`] once the wait queue is full, so a
+//! web service can answer 503 instead of piling up work.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use crate::decoder::{decoder_for, DecodeLimits, DecodedImage};
+use crate::formats::FormatType;
+
+/// Admission limits for [`AsyncDecoder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionLimits {
+    /// Decodes running on the blocking pool at the same time
+    pub max_concurrent: usize,
+    /// Requests allowed to wait for a free slot before new ones are refused
+    pub max_queued: usize,
+    /// Largest accepted input, in bytes
+    pub max_input_bytes: usize,
+    /// Caps applied inside each decode
+    pub decode: DecodeLimits,
+}
+
+impl Default for AdmissionLimits {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self { max_concurrent: cpus, max_queued: cpus * 4, max_input_bytes: 64 << 20, decode: DecodeLimits::default() }
+    }
+}
+
+/// Errors from [`AsyncDecoder`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AsyncDecodeError {
+    /// Every running and queued slot is taken
+    Busy,
+    /// The input exceeds [`AdmissionLimits::max_input_bytes`]
+    TooLarge { size: usize, limit: usize },
+    /// The file extension is not a known format
+    UnknownFormat(String),
+    Io(std::io::Error),
+    Decode(crate::decoder::Error),
+    /// The decoder panicked or the blocking task was cancelled
+    Aborted,
+}
+
+impl fmt::Display for AsyncDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsyncDecodeError::Busy => write!(f, "decoder is busy, try again later"),
+            AsyncDecodeError::TooLarge { size, limit } => write!(f, "input of {} bytes exceeds the {} byte limit", size, limit),
+            AsyncDecodeError::UnknownFormat(message) => write!(f, "{}", message),
+            AsyncDecodeError::Io(e) => write!(f, "{}", e),
+            AsyncDecodeError::Decode(e) => write!(f, "{}", e),
+            AsyncDecodeError::Aborted => write!(f, "decode task aborted"),
+        }
+    }
+}
+
+impl std::error::Error for AsyncDecodeError {}
+
+/// Shared, cloneable front end for decoding on the blocking pool
+#[derive(Debug, Clone)]
+pub struct AsyncDecoder {
+    limits: AdmissionLimits,
+    /// Running + queued requests
+    admitted: Arc<Semaphore>,
+    running: Arc<Semaphore>,
+}
+
+impl AsyncDecoder {
+    pub fn new(limits: AdmissionLimits) -> Self {
+        Self {
+            admitted: Arc::new(Semaphore::new(limits.max_concurrent + limits.max_queued)),
+            running: Arc::new(Semaphore::new(limits.max_concurrent.max(1))),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> AdmissionLimits {
+        self.limits
+    }
+
+    /// Decode in-memory `data` of `format`
+    pub async fn decode(&self, format: FormatType, data: Vec<u8>) -> Result<DecodedImage, AsyncDecodeError> {
+        if data.len() > self.limits.max_input_bytes {
+            return Err(AsyncDecodeError::TooLarge { size: data.len(), limit: self.limits.max_input_bytes });
+        }
+        let _admitted = self.admitted.clone().try_acquire_owned().map_err(|_| AsyncDecodeError::Busy)?;
+        let _running = self.running.clone().acquire_owned().await.map_err(|_| AsyncDecodeError::Aborted)?;
+
+        let limits = self.limits.decode;
+        tokio::task::spawn_blocking(move || decoder_for(&format)?.decode_with_limits(&data, &limits))
+            .await
+            .map_err(|_| AsyncDecodeError::Aborted)?
+            .map_err(AsyncDecodeError::Decode)
+    }
+
+    /// Read `path` without blocking and decode it, detecting the format by
+    /// extension
+    pub async fn decode_file<P: AsRef<Path>>(&self, path: P) -> Result<DecodedImage, AsyncDecodeError> {
+        let path = path.as_ref();
+        let format = FormatType::from_path(path).map_err(|e| AsyncDecodeError::UnknownFormat(e.to_string()))?;
+        let size = tokio::fs::metadata(path).await.map_err(AsyncDecodeError::Io)?.len() as usize;
+        if size > self.limits.max_input_bytes {
+            return Err(AsyncDecodeError::TooLarge { size, limit: self.limits.max_input_bytes });
+        }
+        let data = tokio::fs::read(path).await.map_err(AsyncDecodeError::Io)?;
+        self.decode(format, data).await
+    }
+}
+
+impl Default for AsyncDecoder {
+    fn default() -> Self {
+        Self::new(AdmissionLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[tokio::test]
+    async fn decodes_and_refuses_when_full() {
+        let image = Lf2Image {
+            width: 2,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0xff,
+            color_count: 1,
+            palette: vec![Rgb { r: 9, g: 8, b: 7 }],
+            pixels: vec![0, 0],
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+        let decoder = AsyncDecoder::new(AdmissionLimits {
+            max_concurrent: 1,
+            max_queued: 0,
+            max_input_bytes: 1024,
+            decode: DecodeLimits::default(),
+        });
+
+        let decoded = decoder.decode(FormatType::ToHeartLf2, data.clone()).await.unwrap();
+        assert_eq!(decoded.rgba, vec![9, 8, 7, 255, 9, 8, 7, 255]);
+
+        // Occupy the only slot, as an in-flight request would
+        let held = decoder.admitted.clone().try_acquire_owned().unwrap();
+        assert!(matches!(decoder.decode(FormatType::ToHeartLf2, data.clone()).await, Err(AsyncDecodeError::Busy)));
+        drop(held);
+
+        assert!(matches!(
+            decoder.decode(FormatType::ToHeartLf2, vec![0; 2048]).await,
+            Err(AsyncDecodeError::TooLarge { size: 2048, limit: 1024 })
+        ));
+        assert!(matches!(
+            decoder.decode(FormatType::ToHeartPak, data).await,
+            Err(AsyncDecodeError::Decode(_))
+        ));
+    }
+}
@@ -12,6 +12,9 @@
 //! モード B (ヒストグラム集計):
 //!     cargo run --release --bin lf2_first_diff -- --histogram <input_dir>
 //!
+//! モード A + ring buffer 画像:
+//!     cargo run --release --bin lf2_first_diff -- <file.LF2> --ring-png <output_dir>
+//!
 //! モード C (全決定点データセット生成):
 //!     cargo run --release --bin lf2_first_diff -- --full-dataset <input_dir> <output.csv>
 //!
@@ -19,6 +22,13 @@
 //!     - stdout: 1 行 1 ファイルの CSV（発散のあったファイルのみ）
 //!     - stderr: 集計サマリ
 //!
+//! `--ring-png` を付けると、発散トークンを適用した直後の ring buffer を
+//! 64×64 (1 byte = 1 pixel、ファイルのパレットで着色) にして
+//! `<stem>.ring.leaf.png` / `<stem>.ring.okumura.png` と、左から
+//! Leaf・奥村・差分（食い違うバイトを赤、書き込み位置 r を緑）を並べた
+//! `<stem>.ring.png` を書き出す。4096 バイトのテキストダンプを目で
+//! 突き合わせるより、どこが書き換わったかが一目で分かる。
+//!
 //! 全決定点モードの出力:
 //!     - stdout: CSV フォーマット（ヘッダ行 + 各決定点）
 //!     - stderr: 処理進捗
@@ -46,6 +56,7 @@ struct Header {
     width: u16,
     height: u16,
     color_count: u8,
+    palette: Vec<[u8; 3]>,
     payload_start: usize,
 }
 
@@ -63,10 +74,16 @@ fn parse_header(data: &[u8]) -> anyhow::Result<Header> {
     if payload_start > data.len() {
         anyhow::bail!("payload_start past EOF");
     }
+    // BGR 順
+    let palette = data[0x18..payload_start]
+        .chunks_exact(3)
+        .map(|c| [c[2], c[1], c[0]])
+        .collect();
     Ok(Header {
         width,
         height,
         color_count,
+        palette,
         payload_start,
     })
 }
@@ -400,6 +417,58 @@ fn analyze(data: &[u8]) -> anyhow::Result<(AnalyzeResult, Header)> {
     ))
 }
 
+/// ring buffer 画像の一辺（0x1000 = 64×64）
+const RING_SIDE: u32 = 64;
+/// 3 枚を並べるときの隙間
+const RING_GAP: u32 = 4;
+
+/// ring buffer 1 バイト = 1 pixel。パレット外のインデックスはグレースケール
+fn ring_image(ring: &[u8; 0x1000], palette: &[[u8; 3]]) -> image::RgbImage {
+    image::RgbImage::from_fn(RING_SIDE, RING_SIDE, |x, y| {
+        let b = ring[(y * RING_SIDE + x) as usize];
+        image::Rgb(palette.get(b as usize).copied().unwrap_or([b, b, b]))
+    })
+}
+
+/// 発散トークンをそれぞれ適用した後の ring を画像にして `dir` に書き出す
+fn write_ring_pngs(path: &Path, dir: &Path, div: &DivergenceInfo, hdr: &Header, input: &[u8]) -> anyhow::Result<()> {
+    let after = |t: &UniToken| {
+        let mut ring = div.ring.clone();
+        let (mut r, mut s) = (div.ring_r, div.s);
+        apply_token(&mut ring, &mut r, &mut s, input, t);
+        ring
+    };
+    let (leaf_ring, oku_ring) = (after(&div.leaf), after(&div.oku));
+    let leaf = ring_image(&leaf_ring, &hdr.palette);
+    let oku = ring_image(&oku_ring, &hdr.palette);
+
+    let mut side = image::RgbImage::from_pixel(RING_SIDE * 3 + RING_GAP * 2, RING_SIDE, image::Rgb([255, 255, 255]));
+    image::imageops::replace(&mut side, &leaf, 0, 0);
+    image::imageops::replace(&mut side, &oku, (RING_SIDE + RING_GAP) as i64, 0);
+    let diff_x = (RING_SIDE + RING_GAP) * 2;
+    for i in 0..0x1000u32 {
+        let [r, g, b] = leaf.get_pixel(i % RING_SIDE, i / RING_SIDE).0;
+        let color = if leaf_ring[i as usize] != oku_ring[i as usize] {
+            [255, 0, 0]
+        } else if i as usize == div.ring_r {
+            [0, 255, 0]
+        } else {
+            // 一致部分は薄く
+            [r / 4 + 96, g / 4 + 96, b / 4 + 96]
+        };
+        side.put_pixel(diff_x + i % RING_SIDE, i / RING_SIDE, image::Rgb(color));
+    }
+
+    fs::create_dir_all(dir)?;
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("ring");
+    for (suffix, img) in [("ring.leaf", &leaf), ("ring.okumura", &oku), ("ring", &side)] {
+        let out = dir.join(format!("{}.{}.png", stem, suffix));
+        img.save(&out)?;
+        println!("Wrote {}", out.display());
+    }
+    Ok(())
+}
+
 fn uni_kind_str(t: &UniToken) -> &'static str {
     if t.is_match {
         "match"
@@ -408,7 +477,7 @@ fn uni_kind_str(t: &UniToken) -> &'static str {
     }
 }
 
-fn print_single(path: &Path) -> anyhow::Result<()> {
+fn print_single(path: &Path, ring_png: Option<&Path>) -> anyhow::Result<()> {
     let data = fs::read(path)?;
     let (res, hdr) = analyze(&data)?;
     let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("?");
@@ -507,6 +576,13 @@ fn print_single(path: &Path) -> anyhow::Result<()> {
         div.longer_than_leaf_count
     );
 
+    if let Some(dir) = ring_png {
+        println!();
+        let payload = &data[hdr.payload_start..];
+        let input = decompress_to_tokens(payload, hdr.width, hdr.height)?.ring_input;
+        write_ring_pngs(path, dir, &div.info, &hdr, &input)?;
+    }
+
     Ok(())
 }
 
@@ -917,7 +993,7 @@ fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         eprintln!("usage:");
-        eprintln!("  {} <file.LF2>                             # モード A: 単一ファイル詳細", args[0]);
+        eprintln!("  {} <file.LF2> [--ring-png <dir>]          # モード A: 単一ファイル詳細", args[0]);
         eprintln!("  {} --histogram <input_dir>                # モード B: ヒストグラム", args[0]);
         eprintln!("  {} --full-dataset <input_dir> <output.csv> # モード C: 全決定点データセット", args[0]);
         eprintln!("  {} --tiebreaks <file.LF2>                 # モード D: 同最大長タイの全マッチ TSV", args[0]);
@@ -973,7 +1049,15 @@ fn main() -> ExitCode {
     }
 
     let path = PathBuf::from(&args[1]);
-    if let Err(e) = print_single(&path) {
+    let ring_png = match &args[2..] {
+        [] => None,
+        [flag, dir] if flag == "--ring-png" => Some(PathBuf::from(dir)),
+        _ => {
+            eprintln!("usage: {} <file.LF2> [--ring-png <dir>]", args[0]);
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(e) = print_single(&path, ring_png.as_deref()) {
         eprintln!("error: {}", e);
         return ExitCode::from(1);
     }
@@ -1,10 +1,17 @@
 //! Naive backward linear-scan LZSS bench (strict vs equal) against real LF2 corpus.
+//! Also times the sequential match-finder against the block-parallel one and
+//! checks that both emit the same tokens.
 
 use std::env;
 use std::fs;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
+use retro_decode::formats::toheart::naive_scan_lzss::{
+    compress_naive_backward, compress_naive_backward_parallel,
+};
+use retro_decode::formats::toheart::okumura_lzss::N;
 use retro_decode::formats::toheart::Lf2Image;
 
 #[derive(Default)]
@@ -103,6 +110,10 @@ fn main() -> ExitCode {
     let mut strict = Stats::default();
     let mut equal = Stats::default();
     let mut errored = 0usize;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut sequential_time = Duration::ZERO;
+    let mut parallel_time = Duration::ZERO;
+    let mut parallel_mismatch = 0usize;
 
     for path in &entries {
         let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("?");
@@ -122,6 +133,17 @@ fn main() -> ExitCode {
                 continue;
             }
         };
+        let t = Instant::now();
+        let sequential = compress_naive_backward(&lf2.pixels, false);
+        sequential_time += t.elapsed();
+        let t = Instant::now();
+        let parallel = compress_naive_backward_parallel(&lf2.pixels, false, N - 1, threads);
+        parallel_time += t.elapsed();
+        if parallel != sequential {
+            eprintln!("parallel token mismatch {}", name);
+            parallel_mismatch += 1;
+        }
+
         match lf2.to_lf2_bytes_naive_strict() {
             Ok(b) => strict.record(&original, &b),
             Err(e) => {
@@ -141,6 +163,15 @@ fn main() -> ExitCode {
     strict.print("naive_strict");
     println!();
     equal.print("naive_equal");
+    println!();
+    println!("Match-finder ({} threads)", threads);
+    println!("  sequential: {:.2} s", sequential_time.as_secs_f64());
+    println!(
+        "  parallel  : {:.2} s ({:.2}x)",
+        parallel_time.as_secs_f64(),
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64().max(f64::EPSILON)
+    );
+    println!("  token mismatches: {}", parallel_mismatch);
     if errored > 0 {
         eprintln!("errors: {}", errored);
     }
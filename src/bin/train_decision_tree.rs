@@ -11,7 +11,8 @@
 //!
 //! 出力:
 //!   - stdout: 学習統計
-//!   - --tree-out: 決定木をバイナリ (bincode) で保存。推論時にこのファイルをロード
+//!   - --tree-out: 決定木をルールセット形式（`LF2RULES` + bincode）で保存。
+//!     推論時にこのファイルをロード。--early-literals N も一緒に記録する
 
 use std::collections::HashMap;
 use std::fs::File;
@@ -40,8 +41,16 @@ struct Args {
     /// 木の最大深さ（デフォルト: 制限なし）
     #[arg(long)]
     max_depth: Option<usize>,
+
+    /// 先頭 N 出力バイトを常にリテラルにする規則（ルールセットに記録）
+    #[arg(long, default_value = "0")]
+    early_literals: u64,
 }
 
+/// ルールセット形式（推論側の decision_tree::RULES_MAGIC / RULES_VERSION と一致）
+const RULES_MAGIC: &[u8; 8] = b"LF2RULES";
+const RULES_VERSION: u32 = 1;
+
 /// 1つのデータポイント
 #[derive(Clone, Debug)]
 struct DataPoint {
@@ -449,8 +458,10 @@ fn main() -> Result<()> {
         println!("⚠ Training accuracy {:.2}%. Some classes remain mixed.", accuracy * 100.0);
     }
 
-    // 決定木バイナリ保存（推論側がロードして走査する）
-    let serialized = bincode::serialize(&tree)
+    // ルールセット保存（推論側がロードして走査する）。
+    // 本体は推論側 LearnedRules { version, early_literals, tree } と同じ並び
+    let mut serialized = RULES_MAGIC.to_vec();
+    bincode::serialize_into(&mut serialized, &(RULES_VERSION, args.early_literals, &tree))
         .map_err(|e| anyhow!("Failed to serialize tree: {}", e))?;
     let mut out = BufWriter::new(File::create(&args.tree_out)
         .map_err(|e| anyhow!("Failed to create tree output: {}", e))?);
@@ -459,6 +470,7 @@ fn main() -> Result<()> {
     println!();
     println!("=== Tree binary saved ===");
     println!("Path: {}", args.tree_out.display());
+    println!("Early literals: {}", args.early_literals);
     println!("Size: {} bytes", serialized.len());
 
     Ok(())
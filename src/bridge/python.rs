@@ -1,5 +1,6 @@
 //! Python bridge for external script execution
 
+use std::ffi::OsString;
 use std::path::Path;
 use std::process::Command;
 use anyhow::{Result, anyhow};
@@ -23,6 +24,11 @@ pub fn process(
         FormatType::ToHeartScn => "toheart_scn.py",
         FormatType::KanonPdt => "kanon_pdt.py",
         FormatType::KanonG00 => "kanon_g00.py",
+        FormatType::ElfGph => "elf_gph.py",
+        FormatType::SilkyMgr => "silky_mgr.py",
+        FormatType::Pc98Planar => "pc98_planar.py",
+        FormatType::Pc98Mag => "pc98_mag.py",
+        FormatType::Pc98Pi => "pc98_pi.py",
     };
     
     let script_path = Path::new("scripts/python").join(script_name);
@@ -32,31 +38,31 @@ pub fn process(
     }
     
     // Build command arguments
-    let mut args = vec![
-        script_path.to_string_lossy().to_string(),
-        "--input".to_string(),
-        input_path.to_string_lossy().to_string(),
-        "--output".to_string(),
-        output_path.to_string_lossy().to_string(),
+    let mut args: Vec<OsString> = vec![
+        script_path.as_os_str().to_owned(),
+        "--input".into(),
+        input_path.as_os_str().to_owned(),
+        "--output".into(),
+        output_path.as_os_str().to_owned(),
     ];
     
     if config.parallel {
-        args.push("--parallel".to_string());
+        args.push("--parallel".into());
     }
     
     if config.gpu {
-        args.push("--gpu".to_string());
+        args.push("--gpu".into());
     }
     
     if config.step_by_step {
-        args.push("--step-by-step".to_string());
+        args.push("--step-by-step".into());
     }
     
     if config.verbose {
-        args.push("--verbose".to_string());
+        args.push("--verbose".into());
     }
     
-    debug!("Executing: python {}", args.join(" "));
+    debug!("Executing: python {}", args.iter().map(|a| a.to_string_lossy()).collect::<Vec<_>>().join(" "));
     
     // Try uvx first (if available), then fall back to python
     let result = Command::new("uvx")
@@ -1,5 +1,6 @@
 //! TypeScript bridge for external script execution
 
+use std::ffi::OsString;
 use std::path::Path;
 use std::process::Command;
 use anyhow::{Result, anyhow};
@@ -23,6 +24,11 @@ pub fn process(
         FormatType::ToHeartScn => "toheart_scn.ts",
         FormatType::KanonPdt => "kanon_pdt.ts",
         FormatType::KanonG00 => "kanon_g00.ts",
+        FormatType::ElfGph => "elf_gph.ts",
+        FormatType::SilkyMgr => "silky_mgr.ts",
+        FormatType::Pc98Planar => "pc98_planar.ts",
+        FormatType::Pc98Mag => "pc98_mag.ts",
+        FormatType::Pc98Pi => "pc98_pi.ts",
     };
     
     let script_path = Path::new("scripts/typescript").join(script_name);
@@ -32,30 +38,30 @@ pub fn process(
     }
     
     // Build command arguments
-    let mut args = vec![
-        "run".to_string(),
-        "--allow-read".to_string(),
-        "--allow-write".to_string(),
-        script_path.to_string_lossy().to_string(),
-        "--input".to_string(),
-        input_path.to_string_lossy().to_string(),
-        "--output".to_string(),
-        output_path.to_string_lossy().to_string(),
+    let mut args: Vec<OsString> = vec![
+        "run".into(),
+        "--allow-read".into(),
+        "--allow-write".into(),
+        script_path.as_os_str().to_owned(),
+        "--input".into(),
+        input_path.as_os_str().to_owned(),
+        "--output".into(),
+        output_path.as_os_str().to_owned(),
     ];
     
     if config.parallel {
-        args.push("--parallel".to_string());
+        args.push("--parallel".into());
     }
     
     if config.step_by_step {
-        args.push("--step-by-step".to_string());
+        args.push("--step-by-step".into());
     }
     
     if config.verbose {
-        args.push("--verbose".to_string());
+        args.push("--verbose".into());
     }
     
-    debug!("Executing: deno {}", args.join(" "));
+    debug!("Executing: deno {}", args.iter().map(|a| a.to_string_lossy()).collect::<Vec<_>>().join(" "));
     
     // Execute Deno script
     let output = Command::new("deno")
@@ -0,0 +1,327 @@
+//! Images embedded at arbitrary offsets of other files
+//!
+//! `carve` scans memory dumps, unknown archives and executables for the LF2
+//! (`LEAF256\0`) and PDT10 signatures. Each hit is sized from its own
+//! header and compressed streams (the LZSS stream of an LF2 ends where its
+//! pixels are complete; a PDT gives its length in the header), then decoded
+//! to tell real images from stray signature bytes. G00 has no signature
+//! (and no decoder yet), so it cannot be carved.
+//!
+//! Large dumps are scanned in [`CHUNK_SIZE`] chunks, one per worker thread.
+//! A signature belongs to the chunk it starts in; the match and the image
+//! may run past the chunk end, so nothing is lost at the boundaries. After
+//! every round of chunks the [`ScanState`] can be saved, and an interrupted
+//! scan continues from the last completed offset (`carve --resume`).
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::decoder::{decoder_for, DecodeLimits};
+use crate::formats::FormatType;
+use crate::formats::kanon::pdt::{AlphaStream, PdtLayout, RgbStream};
+use crate::formats::magic::{LF2_HEADER_SIZE, LF2_MAGIC, PDT_HEADER_SIZE, PDT_LEGACY_HEADER_SIZE, PDT_MAGIC};
+use crate::lzss::LzssSpec;
+
+/// Largest image a hit may claim; bigger headers are stray bytes
+pub const MAX_PIXELS: u64 = 4096 * 4096;
+
+/// Signatures `carve` looks for
+const SIGNATURES: &[(&[u8], FormatType)] = &[
+    (LF2_MAGIC, FormatType::ToHeartLf2),
+    (PDT_MAGIC, FormatType::KanonPdt),
+];
+
+/// Bytes one worker scans at a time
+pub const CHUNK_SIZE: usize = 16 << 20;
+
+/// One signature found in the input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CarvedImage {
+    pub offset: usize,
+    pub format: FormatType,
+    /// Bytes from `offset` the image spans
+    pub length: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Decodes without error within [`MAX_PIXELS`]
+    pub valid: bool,
+    /// Why it is not a usable image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CarvedImage {
+    /// The image's bytes within `data`
+    pub fn bytes<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..self.offset + self.length]
+    }
+
+    /// `<stem>_<offset>.<ext>`, e.g. `dump_0001a2b0.lf2`
+    pub fn file_name(&self, stem: &str) -> String {
+        let extension = match self.format {
+            FormatType::KanonPdt => "pdt",
+            _ => "lf2",
+        };
+        format!("{}_{:08x}.{}", stem, self.offset, extension)
+    }
+}
+
+/// Every signature in `data`, in offset order
+pub fn scan(data: &[u8]) -> Vec<CarvedImage> {
+    scan_range(data, 0..data.len())
+}
+
+/// Signatures starting inside `range`, in offset order; bytes past
+/// `range.end` are read as far as the match and the image need
+pub fn scan_range(data: &[u8], range: Range<usize>) -> Vec<CarvedImage> {
+    let mut hits = Vec::new();
+    for offset in range.start..range.end.min(data.len()) {
+        for (magic, format) in SIGNATURES {
+            if data[offset..].starts_with(magic) {
+                hits.push(inspect(data, offset, format.clone()));
+            }
+        }
+    }
+    hits
+}
+
+/// Progress of a carve over one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanState {
+    pub source: PathBuf,
+    /// Size of `source`; a state for a different size is not resumed
+    pub size: u64,
+    /// Offsets below this have been scanned
+    pub scanned: usize,
+    pub hits: Vec<CarvedImage>,
+}
+
+impl ScanState {
+    pub fn new(source: &Path, size: u64) -> Self {
+        Self { source: source.to_path_buf(), size, scanned: 0, hits: Vec::new() }
+    }
+
+    /// `<dir>/.<file name>.carve.json`
+    pub fn path_for(dir: &Path, source: &Path) -> PathBuf {
+        dir.join(format!(".{}.carve.json", source.file_name().unwrap_or_default().to_string_lossy()))
+    }
+
+    /// The state saved at `path` if it is for `source` of `size` bytes,
+    /// otherwise a fresh one
+    pub fn resume(path: &Path, source: &Path, size: u64) -> Result<Self> {
+        if path.exists() {
+            let state: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+            if state.source == source && state.size == size {
+                return Ok(state);
+            }
+        }
+        Ok(Self::new(source, size))
+    }
+
+    pub fn save(&self, path: &Path, direct: bool) -> Result<()> {
+        crate::output::write_bytes(path, direct, &serde_json::to_vec(self)?)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.scanned as u64 >= self.size
+    }
+
+    /// Scan the rest of `data` in `chunk_size` chunks on `threads` workers,
+    /// calling `checkpoint` after every round of chunks
+    pub fn run<F>(&mut self, data: &[u8], threads: usize, chunk_size: usize, mut checkpoint: F) -> Result<()>
+    where
+        F: FnMut(&Self) -> Result<()>,
+    {
+        let chunk_size = chunk_size.max(1);
+        while self.scanned < data.len() {
+            let chunks: Vec<Range<usize>> = (0..threads.max(1))
+                .map(|i| self.scanned + i * chunk_size)
+                .take_while(|&start| start < data.len())
+                .map(|start| start..(start + chunk_size).min(data.len()))
+                .collect();
+            let round_end = chunks.last().map_or(data.len(), |c| c.end);
+            let found = std::thread::scope(|scope| {
+                let workers: Vec<_> = chunks.into_iter()
+                    .map(|chunk| scope.spawn(move || scan_range(data, chunk)))
+                    .collect();
+                workers.into_iter()
+                    .map(|worker| worker.join().map_err(|_| anyhow!("Carve worker panicked")))
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            self.hits.extend(found.into_iter().flatten());
+            self.scanned = round_end;
+            checkpoint(self)?;
+        }
+        Ok(())
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u32 {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as u32
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Dimensions and extent of the image at `offset`
+fn extent(blob: &[u8], format: &FormatType) -> Result<(u32, u32, usize)> {
+    match format {
+        FormatType::ToHeartLf2 => {
+            if blob.len() < LF2_HEADER_SIZE {
+                return Err(anyhow!("header runs past the end of the input"));
+            }
+            let (width, height) = (u16_at(blob, 0x0c), u16_at(blob, 0x0e));
+            check_size(width, height)?;
+            let stream_start = LF2_HEADER_SIZE + blob[0x16] as usize * 3;
+            let stream = blob.get(stream_start..).ok_or_else(|| anyhow!("palette runs past the end of the input"))?;
+            let output = LzssSpec::LF2.decompress_stream(stream, (width * height) as usize);
+            if output.data.len() < (width * height) as usize {
+                return Err(anyhow!("pixel stream ends after {} of {} pixels", output.data.len(), width * height));
+            }
+            Ok((width, height, stream_start + output.consumed))
+        }
+        _ => {
+            if blob.len() < PDT_HEADER_SIZE {
+                return Err(anyhow!("header runs past the end of the input"));
+            }
+            let (width, height) = (u32_at(blob, 0x0c), u32_at(blob, 0x10));
+            check_size(width, height)?;
+            let file_length = u32_at(blob, 0x08) as usize;
+            if (PDT_LEGACY_HEADER_SIZE..=blob.len()).contains(&file_length) {
+                return Ok((width, height, file_length));
+            }
+            // No usable length field: the streams end where their pixels do
+            let pixels = (width * height) as usize;
+            let layout = PdtLayout::detect(blob);
+            let rgb_start = layout.header_size();
+            let mut rgb = RgbStream::new(&blob[rgb_start..]);
+            if rgb.by_ref().take(pixels).count() < pixels {
+                return Err(anyhow!("RGB stream ends early and the length field is unusable"));
+            }
+            let mut end = rgb_start + rgb.consumed();
+            let mask_offset = match layout {
+                PdtLayout::Standard => u32_at(blob, 0x1c) as usize,
+                PdtLayout::Legacy => 0,
+            };
+            if mask_offset > 0 && mask_offset < blob.len() {
+                let mut alpha = AlphaStream::new(&blob[mask_offset..]);
+                alpha.by_ref().take(pixels).for_each(drop);
+                end = end.max(mask_offset + alpha.consumed());
+            }
+            Ok((width, height, end))
+        }
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<()> {
+    let pixels = width as u64 * height as u64;
+    if pixels == 0 || pixels > MAX_PIXELS {
+        return Err(anyhow!("implausible size {}x{}", width, height));
+    }
+    Ok(())
+}
+
+fn inspect(data: &[u8], offset: usize, format: FormatType) -> CarvedImage {
+    let blob = &data[offset..];
+    let mut hit = CarvedImage { offset, format, length: 0, width: 0, height: 0, valid: false, error: None };
+    match extent(blob, &hit.format) {
+        Ok((width, height, length)) => {
+            (hit.width, hit.height, hit.length) = (width, height, length);
+            let limits = DecodeLimits { max_pixels: Some(MAX_PIXELS), ..Default::default() };
+            let decoded = decoder_for(&hit.format).and_then(|d| d.decode_with_limits(&blob[..length], &limits));
+            match decoded {
+                Ok(_) => hit.valid = true,
+                Err(e) => hit.error = Some(e.to_string()),
+            }
+        }
+        Err(e) => hit.error = Some(e.to_string()),
+    }
+    hit
+}
+
+/// Write the valid images of `hits` as files named after `source` into
+/// `dir`; returns how many were written
+pub fn extract(data: &[u8], hits: &[CarvedImage], source: &Path, dir: &Path, direct: bool) -> Result<usize> {
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let mut written = 0;
+    for hit in hits.iter().filter(|h| h.valid) {
+        crate::output::write_bytes(&dir.join(hit.file_name(&stem)), direct, hit.bytes(data))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::kanon::pdt::{PdtImage, RgbColor};
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn finds_images_between_junk() {
+        let lf2 = Lf2Image {
+            width: 4,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 9, g: 8, b: 7 }; 2],
+            pixels: vec![1, 0, 1, 0, 1, 1, 1, 1],
+        }.to_lf2_bytes().unwrap();
+        let mut pdt = PdtImage {
+            width: 3,
+            height: 1,
+            file_length: 0,
+            layout: PdtLayout::Standard,
+            mask_offset: 0,
+            pixels: vec![RgbColor::default(); 3],
+            alpha_mask: vec![255, 0, 255],
+        }.to_pdt_bytes(PdtLayout::Standard);
+
+        let mut blob = vec![0xaa; 13];
+        blob.extend_from_slice(&lf2);
+        blob.extend_from_slice(b"junkLEAF256\0");
+        blob.extend_from_slice(&[0xff; 40]);
+        let pdt_at = blob.len();
+        // A zeroed length field makes the carver size the PDT by its stream
+        pdt[8..12].copy_from_slice(&[0; 4]);
+        blob.extend_from_slice(&pdt);
+        blob.extend_from_slice(&[0x55; 7]);
+
+        let hits = scan(&blob);
+        assert_eq!(hits.len(), 3);
+        assert_eq!((hits[0].offset, hits[0].length, hits[0].valid), (13, lf2.len(), true));
+        assert_eq!((hits[0].width, hits[0].height), (4, 2));
+        assert_eq!(hits[0].bytes(&blob), &lf2[..]);
+        assert!(!hits[1].valid && hits[1].error.is_some());
+        assert_eq!((hits[2].offset, hits[2].length, hits[2].valid), (pdt_at, pdt.len(), true));
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(extract(&blob, &hits, Path::new("dump.bin"), dir.path(), false).unwrap(), 2);
+        assert_eq!(std::fs::read(dir.path().join("dump_0000000d.lf2")).unwrap(), lf2);
+
+        // Chunks smaller than a signature still find everything, and a
+        // scan stopped after the first round resumes to the same result
+        let source = Path::new("dump.bin");
+        let mut chunked = ScanState::new(source, blob.len() as u64);
+        chunked.run(&blob, 3, 5, |_| Ok(())).unwrap();
+        assert_eq!(chunked.hits, hits);
+
+        let path = ScanState::path_for(dir.path(), source);
+        let mut first = ScanState::new(source, blob.len() as u64);
+        let _ = first.run(&blob, 2, 7, |state| {
+            state.save(&path, false)?;
+            Err(anyhow!("interrupted"))
+        });
+        let mut resumed = ScanState::resume(&path, source, blob.len() as u64).unwrap();
+        assert_eq!(resumed.scanned, 14);
+        resumed.run(&blob, 2, 7, |_| Ok(())).unwrap();
+        assert!(resumed.is_complete());
+        assert_eq!(resumed.hits, hits);
+        assert_eq!(ScanState::resume(&path, source, 1).unwrap().scanned, 0);
+    }
+}
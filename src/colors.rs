@@ -0,0 +1,254 @@
+//! Colour statistics of one decoded image
+//!
+//! `info --colors` lists which palette entries an indexed image uses, how
+//! many pixels each covers, and which entries no pixel references: the
+//! slots a modder can recolour or fill with new colours without touching
+//! existing art. Direct-colour images get the number of distinct colours
+//! and the most frequent ones instead.
+//!
+//! [`lint`] checks the transparent index against the palette and the pixels
+//! (`info` prints the result, `verify` adds it to its records): indices
+//! past the palette and a transparent entry sharing its colour with drawn
+//! entries are the usual reasons a conversion does not look like the game.
+
+use std::collections::HashMap;
+use serde::Serialize;
+
+use crate::decoder::DecodedImage;
+
+/// Most frequent colours listed for direct-colour images
+pub const TOP_COLORS: usize = 16;
+
+/// One palette entry and the pixels that use it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntryUsage {
+    pub index: u8,
+    pub color: [u8; 3],
+    pub pixels: usize,
+}
+
+/// Palette usage of an indexed image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PaletteUsage {
+    pub palette_size: usize,
+    pub pixels: usize,
+    /// Entries referenced by at least one pixel, in index order
+    pub used: Vec<EntryUsage>,
+    /// Entries no pixel references
+    pub unused: Vec<u8>,
+    /// Indices past the end of the palette and their pixel counts
+    pub out_of_range: Vec<(u8, usize)>,
+}
+
+/// Colour counts of a direct-colour image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColorCounts {
+    pub pixels: usize,
+    pub distinct: usize,
+    /// The [`TOP_COLORS`] most frequent RGBA colours, most frequent first
+    pub top: Vec<([u8; 4], usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "kind")]
+pub enum ColorStats {
+    Indexed(PaletteUsage),
+    Direct(ColorCounts),
+}
+
+impl PaletteUsage {
+    pub fn new(palette: &[[u8; 3]], indices: &[u8]) -> Self {
+        let mut counts = [0usize; 256];
+        for &index in indices {
+            counts[index as usize] += 1;
+        }
+        let mut usage = Self {
+            palette_size: palette.len(),
+            pixels: indices.len(),
+            used: Vec::new(),
+            unused: Vec::new(),
+            out_of_range: Vec::new(),
+        };
+        for (index, &pixels) in counts.iter().enumerate() {
+            match palette.get(index) {
+                Some(&color) if pixels > 0 => usage.used.push(EntryUsage { index: index as u8, color, pixels }),
+                Some(_) => usage.unused.push(index as u8),
+                None if pixels > 0 => usage.out_of_range.push((index as u8, pixels)),
+                None => {}
+            }
+        }
+        usage
+    }
+}
+
+impl ColorCounts {
+    pub fn new(rgba: &[u8]) -> Self {
+        let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+        for px in rgba.chunks_exact(4) {
+            *counts.entry([px[0], px[1], px[2], px[3]]).or_default() += 1;
+        }
+        let distinct = counts.len();
+        let mut top: Vec<([u8; 4], usize)> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(TOP_COLORS);
+        Self { pixels: rgba.len() / 4, distinct, top }
+    }
+}
+
+impl ColorStats {
+    /// Palette usage when `image` has a palette, colour counts otherwise
+    pub fn of(image: &DecodedImage) -> Self {
+        match (&image.palette, &image.indices) {
+            (Some(palette), Some(indices)) => Self::Indexed(PaletteUsage::new(palette, indices)),
+            _ => Self::Direct(ColorCounts::new(&image.rgba)),
+        }
+    }
+}
+
+/// Kind of [`PaletteWarning`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningCode {
+    /// The transparent index is not a palette entry
+    TransparentOutOfRange,
+    /// Pixels use indices past the palette
+    IndexOutOfRange,
+    /// A drawn entry has the transparent entry's colour
+    TransparentColorShared,
+    /// The transparent index is a palette entry no pixel uses
+    TransparentUnused,
+}
+
+impl WarningCode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::TransparentOutOfRange => "transparent-out-of-range",
+            Self::IndexOutOfRange => "index-out-of-range",
+            Self::TransparentColorShared => "transparent-color-shared",
+            Self::TransparentUnused => "transparent-unused",
+        }
+    }
+}
+
+/// One palette / transparency inconsistency
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PaletteWarning {
+    pub code: WarningCode,
+    /// Palette indices involved
+    pub indices: Vec<u8>,
+    /// Pixels affected
+    pub pixels: usize,
+    pub message: String,
+}
+
+/// Transparency inconsistencies of an indexed image whose pixels with
+/// index `transparent` are see-through
+pub fn lint(palette: &[[u8; 3]], indices: &[u8], transparent: u8) -> Vec<PaletteWarning> {
+    let usage = PaletteUsage::new(palette, indices);
+    let pixels_of = |index: u8| usage.used.iter().find(|e| e.index == index).map_or(0, |e| e.pixels);
+    let mut warnings = Vec::new();
+
+    match palette.get(transparent as usize) {
+        None => {
+            let pixels = usage.out_of_range.iter().find(|&&(i, _)| i == transparent).map_or(0, |&(_, n)| n);
+            warnings.push(PaletteWarning {
+                code: WarningCode::TransparentOutOfRange,
+                indices: vec![transparent],
+                pixels,
+                message: format!(
+                    "transparent index {} is past the {}-colour palette; re-encoding pads the palette up to it",
+                    transparent, palette.len()
+                ),
+            });
+        }
+        Some(_) if pixels_of(transparent) == 0 => warnings.push(PaletteWarning {
+            code: WarningCode::TransparentUnused,
+            indices: vec![transparent],
+            pixels: 0,
+            message: format!("transparent index {} is never used; the image is fully opaque", transparent),
+        }),
+        Some(&color) => {
+            let shared: Vec<&EntryUsage> = usage.used.iter()
+                .filter(|e| e.index != transparent && e.color == color)
+                .collect();
+            if !shared.is_empty() {
+                let [r, g, b] = color;
+                warnings.push(PaletteWarning {
+                    code: WarningCode::TransparentColorShared,
+                    indices: shared.iter().map(|e| e.index).collect(),
+                    pixels: shared.iter().map(|e| e.pixels).sum(),
+                    message: format!(
+                        "entries {:?} are drawn with #{:02x}{:02x}{:02x}, the colour of transparent entry {}; \
+                         outputs without alpha (raw, rgb565) and colour-keyed tools cannot tell them apart",
+                        shared.iter().map(|e| e.index).collect::<Vec<_>>(), r, g, b, transparent
+                    ),
+                });
+            }
+        }
+    }
+
+    let stray: Vec<(u8, usize)> = usage.out_of_range.iter().copied().filter(|&(i, _)| i != transparent).collect();
+    if !stray.is_empty() {
+        let pixels = stray.iter().map(|&(_, n)| n).sum();
+        warnings.push(PaletteWarning {
+            code: WarningCode::IndexOutOfRange,
+            indices: stray.iter().map(|&(i, _)| i).collect(),
+            pixels,
+            message: format!(
+                "{} pixels use indices {:?} past the {}-colour palette; they decode as transparent black",
+                pixels, stray.iter().map(|&(i, _)| i).collect::<Vec<_>>(), palette.len()
+            ),
+        });
+    }
+    warnings
+}
+
+/// [`lint`] for an LF2 file; other formats have no transparent index
+pub fn lint_lf2(image: &crate::formats::toheart::Lf2Image) -> Vec<PaletteWarning> {
+    let palette: Vec<[u8; 3]> = image.palette.iter().map(|c| [c.r, c.g, c.b]).collect();
+    lint(&palette, &image.pixels, image.transparent_color)
+}
+
+/// Share of `pixels` in `total`, in percent
+pub fn percent(pixels: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { pixels as f64 * 100.0 / total as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_used_and_free_entries() {
+        let palette = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let usage = PaletteUsage::new(&palette, &[1, 1, 3, 1, 5]);
+        assert_eq!(usage.used, vec![
+            EntryUsage { index: 1, color: [255, 0, 0], pixels: 3 },
+            EntryUsage { index: 3, color: [0, 0, 255], pixels: 1 },
+        ]);
+        assert_eq!(usage.unused, vec![0, 2]);
+        assert_eq!(usage.out_of_range, vec![(5, 1)]);
+
+        let counts = ColorCounts::new(&[1, 2, 3, 255, 9, 9, 9, 0, 1, 2, 3, 255]);
+        assert_eq!(counts.distinct, 2);
+        assert_eq!(counts.top[0], ([1, 2, 3, 255], 2));
+        assert_eq!(percent(1, 4), 25.0);
+    }
+
+    #[test]
+    fn flags_transparency_conflicts() {
+        let palette = [[0, 0, 0], [255, 0, 0], [0, 0, 0]];
+        let codes = |indices: &[u8], transparent| -> Vec<WarningCode> {
+            lint(&palette, indices, transparent).iter().map(|w| w.code).collect()
+        };
+        assert_eq!(codes(&[0, 1, 1], 0), vec![]);
+        assert_eq!(codes(&[1, 1], 0), vec![WarningCode::TransparentUnused]);
+        assert_eq!(codes(&[0, 1, 5], 5), vec![WarningCode::TransparentOutOfRange]);
+        assert_eq!(codes(&[0, 1, 2], 9), vec![WarningCode::TransparentOutOfRange]);
+        assert_eq!(codes(&[0, 1, 7, 7], 1), vec![WarningCode::IndexOutOfRange]);
+
+        let shared = lint(&palette, &[0, 1, 2, 2], 0);
+        assert_eq!(shared[0].code, WarningCode::TransparentColorShared);
+        assert_eq!((shared[0].indices.clone(), shared[0].pixels), (vec![2], 2));
+    }
+}
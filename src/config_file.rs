@@ -0,0 +1,273 @@
+//! Default options from `retro-decode.toml`
+//!
+//! Options used on every run (output directory, format, parallelism, engine)
+//! can live in a config file instead of being repeated on each command line.
+//! The file is looked up in the current directory first, then in the user
+//! config directory (`$XDG_CONFIG_HOME/retro-decode/` or
+//! `~/.config/retro-decode/`); only the first one found is read. Flags given
+//! on the command line always win over the file.
+//!
+//! ```toml
+//! output = "converted"
+//! format = "png"
+//! parallel = true
+//! threads = 4           # default: RAYON_NUM_THREADS or every core
+//! language = "rust"     # rust / python / typescript
+//! recursive = true
+//! layout = "mirror"     # mirror / flat / versioned
+//! sidecar = false
+//! fail_fast = false     # stop batch runs at the first failure
+//!
+//! [formats.pdt]         # per input extension
+//! format = "bmp"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::formats::FormatType;
+use crate::paths::TemplateValues;
+use crate::project::OutputLayout;
+use crate::Config;
+
+/// File name searched for in the current and the user config directory
+pub const CONFIG_FILE: &str = "retro-decode.toml";
+
+/// Engines the command line accepts
+const LANGUAGES: &[&str] = &["rust", "python", "typescript"];
+
+/// Contents of `retro-decode.toml`; unset keys leave the built-in defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub output: Option<PathBuf>,
+    pub format: Option<String>,
+    pub parallel: Option<bool>,
+    /// Worker threads with `parallel`
+    pub threads: Option<usize>,
+    pub language: Option<String>,
+    pub recursive: Option<bool>,
+    pub layout: Option<OutputLayout>,
+    pub sidecar: Option<bool>,
+    /// Stop batch runs at the first failure; `--keep-going` overrides it
+    pub fail_fast: Option<bool>,
+    /// Overrides for inputs with the given extension (lowercase)
+    pub formats: BTreeMap<String, FormatOverrides>,
+}
+
+/// `[formats.<extension>]`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatOverrides {
+    /// Output format for these inputs
+    pub format: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let file: Self = toml::from_str(&text)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))?;
+        file.validate().map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(file)
+    }
+
+    /// The config file in effect: `retro-decode.toml` in the current
+    /// directory, else in the user config directory
+    pub fn discover() -> Option<PathBuf> {
+        let local = PathBuf::from(CONFIG_FILE);
+        if local.is_file() {
+            return Some(local);
+        }
+        user_path().filter(|path| path.is_file())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let formats = self.formats.values().filter_map(|o| o.format.as_ref());
+        for format in self.format.iter().chain(formats) {
+            if !crate::output::is_output_format(format) {
+                return Err(anyhow!("format must be one of {}, got {}", crate::output::output_formats().join(", "), format));
+            }
+        }
+        if let Some(language) = &self.language {
+            if !LANGUAGES.contains(&language.as_str()) {
+                return Err(anyhow!("language must be one of {}, got {}", LANGUAGES.join(", "), language));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the options of `config` this file defines, except those for
+    /// which `applies` returns false. `applies` takes the command-line
+    /// option id; the CLI refuses options given as flags or not defined for
+    /// the command.
+    pub fn apply(&self, config: &mut Config, applies: impl Fn(&str) -> bool) {
+        if applies("output") {
+            if let Some(output) = &self.output {
+                config.output = output.clone();
+            }
+        }
+        if applies("format") {
+            if let Some(format) = &self.format {
+                config.format = format.clone();
+            }
+        }
+        if applies("parallel") {
+            if let Some(parallel) = self.parallel {
+                config.parallel = parallel;
+            }
+            if self.threads.is_some() {
+                config.threads = self.threads.filter(|&n| n > 0);
+            }
+        }
+        if applies("lang") {
+            if let Some(language) = &self.language {
+                config.language = language.clone();
+            }
+        }
+        if applies("recursive") {
+            if let Some(recursive) = self.recursive {
+                config.recursive = recursive;
+            }
+        }
+        if applies("layout") {
+            if let Some(layout) = self.layout {
+                config.layout = layout;
+            }
+        }
+        if applies("sidecar") {
+            if let Some(sidecar) = self.sidecar {
+                config.sidecar = sidecar;
+            }
+        }
+        if applies("fail-fast") && applies("keep-going") {
+            if let Some(fail_fast) = self.fail_fast {
+                config.fail_fast = fail_fast;
+            }
+        }
+        // A --format flag names the format of every output
+        if applies("format") {
+            for (extension, overrides) in &self.formats {
+                if let Some(format) = &overrides.format {
+                    config.format_overrides.insert(extension.to_ascii_lowercase(), format.clone());
+                }
+            }
+        }
+    }
+}
+
+/// `retro-decode.toml` in the user config directory
+pub fn user_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("retro-decode").join(CONFIG_FILE))
+}
+
+/// Commented starting point written by `config init`
+pub fn template() -> String {
+    "\
+# retro-decode defaults; command-line flags override every key
+# output = \"converted\"
+# format = \"png\"         # bmp / png / raw / rgba / rgb565
+# parallel = true
+# threads = 4            # default: RAYON_NUM_THREADS or every core
+# language = \"rust\"      # rust / python / typescript
+# recursive = true
+# layout = \"mirror\"      # mirror / flat / versioned
+# sidecar = false
+# fail_fast = false      # stop batch runs at the first failure
+
+# Output format for inputs with a given extension
+# [formats.pdt]
+# format = \"bmp\"
+"
+    .to_string()
+}
+
+impl Config {
+    /// Config with the built-in defaults, overridden by the file at `path`
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut config = Self {
+            output: PathBuf::from("./"),
+            format: "bmp".to_string(),
+            language: "rust".to_string(),
+            ..Default::default()
+        };
+        ConfigFile::load(path)?.apply(&mut config, |_| true);
+        Ok(config)
+    }
+
+    /// Output format for `input`: its `[formats.<extension>]` override, if
+    /// any, else `format`
+    pub fn output_format(&self, input: &Path) -> &str {
+        crate::paths::extension_lower(input)
+            .and_then(|extension| self.format_overrides.get(&extension))
+            .unwrap_or(&self.format)
+    }
+
+    /// Output file for `input`, found below `input_dir`: `--output-template`
+    /// if set, else `<stem>.<format>` in the `layout` directory. Archives are
+    /// extracted next to the returned path, so they always use the layout.
+    pub fn output_file(&self, input_dir: &Path, input: &Path, format: &FormatType) -> Result<PathBuf> {
+        let extension = self.output_format(input);
+        match &self.output_template {
+            Some(template) if !matches!(format, FormatType::ToHeartPak | FormatType::SilkyMgr) => {
+                let dimensions = if template.needs_dimensions() {
+                    crate::formats::header::inspect(input)?.dimensions()
+                } else {
+                    None
+                };
+                let values = TemplateValues {
+                    input,
+                    input_dir,
+                    extension,
+                    format: &crate::project::format_dir(format),
+                    dimensions,
+                };
+                template.render(&self.output, &values)
+            }
+            _ => {
+                let output_dir = self.layout.target_dir(&self.output, input_dir, input, format);
+                crate::paths::output_file_for(&output_dir, input, extension)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_fills_in_unset_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, "output = \"converted\"\nformat = \"png\"\nparallel = true\nthreads = 4\n\n[formats.PDT]\nformat = \"bmp\"\n").unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.output, PathBuf::from("converted"));
+        assert_eq!((config.parallel, config.threads), (true, Some(4)));
+        assert_eq!(config.language, "rust");
+        assert_eq!(config.output_format(Path::new("a/B.LF2")), "png");
+        assert_eq!(config.output_format(Path::new("a/B.pdt")), "bmp");
+
+        // Options given on the command line stay
+        let mut config = Config { format: "rgba".to_string(), ..Default::default() };
+        ConfigFile::load(&path).unwrap().apply(&mut config, |id| id != "format");
+        assert_eq!(config.format, "rgba");
+        assert!(config.format_overrides.is_empty());
+        assert_eq!(config.output, PathBuf::from("converted"));
+
+        std::fs::write(&path, "format = \"jpeg\"\n").unwrap();
+        assert!(Config::from_file(&path).is_err());
+        std::fs::write(&path, "ouput = \"typo\"\n").unwrap();
+        assert!(Config::from_file(&path).is_err());
+        let template: ConfigFile = toml::from_str(&template()).unwrap();
+        assert_eq!(template, ConfigFile::default());
+    }
+}
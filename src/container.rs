@@ -0,0 +1,165 @@
+//! Uniform access to the images inside a file
+//!
+//! [`open_container`] turns any supported input into a list of entries that
+//! can be decoded one at a time, so callers (GUI browsers, gallery
+//! generators) handle PAK archives, multi-image MGR files and plain images
+//! the same way. A plain image is a container with a single entry.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::decoder::{decoder_for, DecodedImage, Orientation};
+use crate::formats::FormatType;
+use crate::formats::elf::MgrArchive;
+use crate::formats::toheart::PakArchive;
+
+/// One item inside a [`Container`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEntry {
+    pub name: String,
+    /// Stored size in bytes (before decompression)
+    pub size: usize,
+    /// Image format of the entry, `None` if it cannot be decoded to an image
+    pub format: Option<FormatType>,
+}
+
+enum Source {
+    Pak(PakArchive),
+    Mgr(MgrArchive),
+    Single(Vec<u8>),
+}
+
+/// An opened file and the entries it holds
+pub struct Container {
+    format: FormatType,
+    entries: Vec<ContainerEntry>,
+    source: Source,
+}
+
+/// Open `path`, detecting its format by extension
+pub fn open_container<P: AsRef<Path>>(path: P) -> Result<Container> {
+    let path = path.as_ref();
+    let format = FormatType::detect(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+    if format == FormatType::ToHeartPak {
+        return Container::from_pak(PakArchive::open(path)?);
+    }
+    Container::from_data(&name, format, std::fs::read(path)?)
+}
+
+impl Container {
+    /// Wrap an opened PAK archive; entries are typed by their extension
+    pub fn from_pak(pak: PakArchive) -> Result<Self> {
+        let entries = pak
+            .entries()
+            .map(|e| ContainerEntry {
+                name: e.name.clone(),
+                size: e.length as usize,
+                format: FormatType::from_path(&e.name).ok().filter(|f| decoder_for(f).is_ok()),
+            })
+            .collect();
+        Ok(Self { format: FormatType::ToHeartPak, entries, source: Source::Pak(pak) })
+    }
+
+    /// Container for in-memory `data` of `format`; `name` labels the entries
+    pub fn from_data(name: &str, format: FormatType, data: Vec<u8>) -> Result<Self> {
+        match format {
+            FormatType::ToHeartPak => Err(anyhow!("PAK archives must be opened from a file")),
+            FormatType::SilkyMgr => {
+                let archive = MgrArchive::from_data(data)?;
+                let stem = Path::new(name).file_stem().unwrap_or_default().to_string_lossy();
+                let entries = archive
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| ContainerEntry {
+                        name: format!("{}_{:03}", stem, i),
+                        size: e.packed_size,
+                        format: Some(FormatType::SilkyMgr),
+                    })
+                    .collect();
+                Ok(Self { format, entries, source: Source::Mgr(archive) })
+            }
+            _ => {
+                let entries = vec![ContainerEntry {
+                    name: name.to_string(),
+                    size: data.len(),
+                    format: Some(format.clone()).filter(|f| decoder_for(f).is_ok()),
+                }];
+                Ok(Self { format, entries, source: Source::Single(data) })
+            }
+        }
+    }
+
+    /// Format of the container itself
+    pub fn format(&self) -> FormatType {
+        self.format.clone()
+    }
+
+    pub fn entries(&self) -> &[ContainerEntry] {
+        &self.entries
+    }
+
+    /// Decode entry `index` to an image in display orientation
+    pub fn decode_entry(&mut self, index: usize) -> Result<DecodedImage> {
+        let entry = self.entries.get(index)
+            .ok_or_else(|| anyhow!("Entry {} does not exist", index))?;
+        let format = entry.format.clone()
+            .ok_or_else(|| anyhow!("{} is not a supported image", entry.name))?;
+
+        match &mut self.source {
+            Source::Pak(pak) => {
+                let data = pak.read_entry(index)?;
+                Ok(decoder_for(&format)?.decode(&data)?)
+            }
+            Source::Mgr(archive) => {
+                let image = archive.image(index)?;
+                Ok(DecodedImage {
+                    format,
+                    width: image.width(),
+                    height: image.height(),
+                    orientation: Orientation::Display,
+                    rgba: image.into_raw(),
+                    palette: None,
+                    indices: None,
+                })
+            }
+            Source::Single(data) => Ok(decoder_for(&format)?.decode(data)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn single_image_is_one_entry() {
+        let image = Lf2Image {
+            width: 2,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0xff,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: vec![0, 1, 1, 0],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("C0101.LF2");
+        std::fs::write(&path, image.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let mut container = open_container(&path).unwrap();
+        assert_eq!(container.format(), FormatType::ToHeartLf2);
+        assert_eq!(container.entries().len(), 1);
+        assert_eq!(container.entries()[0].name, "C0101.LF2");
+        let decoded = container.decode_entry(0).unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+        assert!(container.decode_entry(1).is_err());
+
+        let g00 = Container::from_data("x.g00", FormatType::KanonG00, vec![0; 4]).unwrap();
+        assert_eq!(g00.entries()[0].format, None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Before/after dashboard of two `verify --json` runs
+//!
+//! Encoder research proceeds by small changes to the tie-breaking rules,
+//! each checked against the whole corpus. The byte-identical count alone
+//! hides what moved: a change can fix forty files and break three. The
+//! `dashboard` subcommand pairs the records of two runs by file, classifies
+//! each file as improved, regressed, unchanged, added or removed, and
+//! writes a self-contained HTML page listing the changes first.
+//!
+//! A file is ranked by status (identical, then similar, then differs, then
+//! error), then by differing bytes, then by how far its re-encoded size is
+//! from the original. Moving up that order is an improvement.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::report::VerifyStatus;
+
+/// The fields of a [`VerifyRecord`](crate::report::VerifyRecord) the
+/// dashboard compares
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyResult {
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    pub status: VerifyStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub differing_bytes: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_delta: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl VerifyResult {
+    fn status_rank(&self) -> u8 {
+        match self.status {
+            VerifyStatus::Identical => 0,
+            VerifyStatus::Similar => 1,
+            VerifyStatus::Differs => 2,
+            VerifyStatus::Error => 3,
+        }
+    }
+
+    /// `Less` if `self` is the better result
+    fn rank(&self, other: &Self) -> Ordering {
+        self.status_rank()
+            .cmp(&other.status_rank())
+            .then(self.differing_bytes.unwrap_or(0).cmp(&other.differing_bytes.unwrap_or(0)))
+            .then(self.size_delta.unwrap_or(0).unsigned_abs().cmp(&other.size_delta.unwrap_or(0).unsigned_abs()))
+    }
+}
+
+/// Read the records of one run: JSON Lines as printed by `verify --json`,
+/// or a JSON array of them
+pub fn load(path: &Path) -> Result<Vec<VerifyResult>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(&text).with_context(|| format!("{} is not a verify result array", path.display()));
+    }
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("{}:{}: not a verify record", path.display(), i + 1))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Regressed,
+    Improved,
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl Change {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Regressed => "regressed",
+            Self::Improved => "improved",
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// One file in either run
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub file: String,
+    pub change: Change,
+    pub before: Option<VerifyResult>,
+    pub after: Option<VerifyResult>,
+}
+
+/// Totals of one run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RunTotals {
+    pub files: usize,
+    pub identical: usize,
+    pub differing_bytes: usize,
+    pub size_delta: i64,
+}
+
+impl RunTotals {
+    fn of(results: &[VerifyResult]) -> Self {
+        RunTotals {
+            files: results.len(),
+            identical: results.iter().filter(|r| r.status == VerifyStatus::Identical).count(),
+            differing_bytes: results.iter().filter_map(|r| r.differing_bytes).sum(),
+            size_delta: results.iter().filter_map(|r| r.size_delta).sum(),
+        }
+    }
+}
+
+/// Every file of two runs, regressions first
+#[derive(Debug, Clone, Serialize)]
+pub struct Dashboard {
+    pub before: RunTotals,
+    pub after: RunTotals,
+    pub files: Vec<FileChange>,
+}
+
+impl Dashboard {
+    pub fn compare(before: Vec<VerifyResult>, after: Vec<VerifyResult>) -> Self {
+        let totals = (RunTotals::of(&before), RunTotals::of(&after));
+        let mut paired: BTreeMap<String, (Option<VerifyResult>, Option<VerifyResult>)> = BTreeMap::new();
+        for result in before {
+            let file = result.file.clone();
+            paired.entry(file).or_default().0 = Some(result);
+        }
+        for result in after {
+            let file = result.file.clone();
+            paired.entry(file).or_default().1 = Some(result);
+        }
+        let mut files: Vec<FileChange> = paired
+            .into_iter()
+            .map(|(file, (before, after))| {
+                let change = match (&before, &after) {
+                    (Some(b), Some(a)) => match a.rank(b) {
+                        Ordering::Less => Change::Improved,
+                        Ordering::Greater => Change::Regressed,
+                        Ordering::Equal => Change::Unchanged,
+                    },
+                    (None, _) => Change::Added,
+                    (_, None) => Change::Removed,
+                };
+                FileChange { file, change, before, after }
+            })
+            .collect();
+        // BTreeMap order keeps files sorted by name within each change
+        files.sort_by_key(|f| f.change);
+        Dashboard { before: totals.0, after: totals.1, files }
+    }
+
+    pub fn count(&self, change: Change) -> usize {
+        self.files.iter().filter(|f| f.change == change).count()
+    }
+
+    /// Self-contained HTML page: totals, then one table row per file
+    pub fn to_html(&self, before_name: &str, after_name: &str) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>retro-decode verify dashboard</title>\n\
+             <style>\n\
+             body { font-family: sans-serif; margin: 1em; }\n\
+             table { border-collapse: collapse; }\n\
+             th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: right; }\n\
+             td:first-child, th:first-child { text-align: left; }\n\
+             tr.regressed { background: #fdd; }\n\
+             tr.improved { background: #dfd; }\n\
+             tr.added, tr.removed { background: #eef; }\n\
+             tr.unchanged { color: #777; }\n\
+             </style></head><body>\n",
+        );
+        let _ = writeln!(
+            html,
+            "<h1>{} &rarr; {}</h1>",
+            html_escape(before_name),
+            html_escape(after_name)
+        );
+        let _ = writeln!(
+            html,
+            "<p>{} improved, {} regressed, {} unchanged, {} added, {} removed</p>",
+            self.count(Change::Improved),
+            self.count(Change::Regressed),
+            self.count(Change::Unchanged),
+            self.count(Change::Added),
+            self.count(Change::Removed)
+        );
+        html.push_str("<table>\n<tr><th></th><th>files</th><th>identical</th><th>differing bytes</th><th>size delta</th></tr>\n");
+        for (name, totals) in [("before", &self.before), ("after", &self.after)] {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:+}</td></tr>",
+                name, totals.files, totals.identical, totals.differing_bytes, totals.size_delta
+            );
+        }
+        html.push_str("</table>\n<h2>Files</h2>\n<table>\n<tr><th>file</th><th>change</th>\
+                       <th>status</th><th>differing bytes</th><th>size delta</th></tr>\n");
+        for file in &self.files {
+            let cell = |field: fn(&VerifyResult) -> String| {
+                let before = file.before.as_ref().map_or("-".to_string(), field);
+                let after = file.after.as_ref().map_or("-".to_string(), field);
+                if before == after { after } else { format!("{} &rarr; {}", before, after) }
+            };
+            let _ = writeln!(
+                html,
+                "<tr class=\"{0}\"><td>{1}</td><td>{0}</td><td>{2}</td><td>{3}</td><td>{4}</td></tr>",
+                file.change.name(),
+                html_escape(&file.file),
+                cell(|r| r.status.name().to_string()),
+                cell(|r| r.differing_bytes.map_or("-".to_string(), |n| n.to_string())),
+                cell(|r| r.size_delta.map_or("-".to_string(), |n| format!("{:+}", n)))
+            );
+        }
+        html.push_str("</table>\n</body></html>\n");
+        html
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(file: &str, status: VerifyStatus, differing_bytes: Option<usize>) -> VerifyResult {
+        VerifyResult { file: file.to_string(), encoder: None, status, differing_bytes, size_delta: None, error: None }
+    }
+
+    #[test]
+    fn classifies_and_renders_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let before_path = dir.path().join("before.jsonl");
+        std::fs::write(
+            &before_path,
+            "{\"file\":\"a.lf2\",\"encoder\":\"okumura\",\"status\":\"differs\",\"first_diff\":3,\"differing_bytes\":4}\n\
+             {\"file\":\"b.lf2\",\"status\":\"identical\",\"size_delta\":0}\n\
+             {\"file\":\"<c>.lf2\",\"status\":\"differs\",\"differing_bytes\":2}\n",
+        )
+        .unwrap();
+        let before = load(&before_path).unwrap();
+        let after = vec![
+            result("a.lf2", VerifyStatus::Identical, None),
+            result("b.lf2", VerifyStatus::Differs, Some(1)),
+            result("<c>.lf2", VerifyStatus::Differs, Some(2)),
+            result("d.lf2", VerifyStatus::Error, None),
+        ];
+
+        let dashboard = Dashboard::compare(before, after);
+        let changes: Vec<(&str, Change)> = dashboard.files.iter().map(|f| (f.file.as_str(), f.change)).collect();
+        assert_eq!(
+            changes,
+            [
+                ("b.lf2", Change::Regressed),
+                ("a.lf2", Change::Improved),
+                ("d.lf2", Change::Added),
+                ("<c>.lf2", Change::Unchanged),
+            ]
+        );
+        assert_eq!((dashboard.before.identical, dashboard.after.identical), (1, 1));
+
+        let html = dashboard.to_html("before", "after");
+        assert!(html.contains("1 improved, 1 regressed"));
+        assert!(html.contains("differs &rarr; identical"));
+        assert!(html.contains("&lt;c&gt;.lf2"));
+    }
+}
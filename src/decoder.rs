@@ -0,0 +1,501 @@
+//! Stable decoding API
+//!
+//! [`Decoder`], [`DecodedImage`] and [`Error`] are the semver-stable surface
+//! for downstream crates (emulators, asset pipelines). They decode from
+//! in-memory bytes and never touch the filesystem; the CLI-oriented
+//! `decode`/`save_as_*` methods on the format types may change between minor
+//! releases.
+
+use std::fmt;
+
+use crate::formats::FormatType;
+use crate::formats::elf::MgrArchive;
+use crate::formats::kanon::PdtImage;
+use crate::formats::pc98::MagImage;
+use crate::formats::toheart::Lf2Image;
+pub use crate::messages::Hint;
+
+/// Errors returned by the stable API
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The format has no image decoder (archives, unimplemented formats)
+    Unsupported(FormatType),
+    /// The input is not valid data for the format
+    Invalid { format: FormatType, message: String },
+    /// The input would exceed one of the caller's [`DecodeLimits`]
+    LimitExceeded { format: FormatType, limit: Limit, value: u64, max: u64 },
+    /// The header declares an empty image or one wider or taller than
+    /// [`MAX_DIMENSION`]
+    BadDimensions { format: FormatType, width: u64, height: u64 },
+    /// The input ends at byte `len`, before the `needed` bytes its header
+    /// declares
+    Truncated { format: FormatType, len: u64, needed: u64 },
+    /// The magic names a revision of the format that has no decoder
+    UnsupportedVersion { format: FormatType, version: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported(format) => write!(f, "{} cannot be decoded to an image", format),
+            Error::Invalid { format, message } => write!(f, "invalid {} data: {}", format, message),
+            Error::LimitExceeded { format, limit, value, max } => {
+                write!(f, "{} exceeds the {} limit: {} > {}", format, limit, value, max)
+            }
+            Error::BadDimensions { format, width, height } => {
+                write!(f, "{} header declares a {}x{} image (expected 1..={} per side)", format, width, height, MAX_DIMENSION)
+            }
+            Error::Truncated { format, len, needed } => {
+                write!(f, "{} file truncated at byte {} ({} bytes needed)", format, len, needed)
+            }
+            Error::UnsupportedVersion { format, version } => write!(f, "{} version {} is not supported", format, version),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// What the user can do about the error, if anything
+    pub fn hint(&self) -> Option<Hint> {
+        match self {
+            Error::Unsupported(FormatType::ToHeartPak) => Some(Hint::ExtractArchive),
+            Error::Unsupported(_) => None,
+            Error::Invalid { format, .. } | Error::BadDimensions { format, .. } => {
+                Some(Hint::CheckFormat { format: format.clone() })
+            }
+            Error::LimitExceeded { limit, .. } => Some(Hint::RaiseLimit { limit: *limit }),
+            Error::Truncated { len, .. } => Some(Hint::Redump { len: *len }),
+            Error::UnsupportedVersion { format, version } => {
+                Some(Hint::UnsupportedVersion { format: format.clone(), version: version.clone() })
+            }
+        }
+    }
+}
+
+/// The first [`Error`] in the chain of `e`, for callers holding an
+/// `anyhow::Error`
+pub fn find_error(e: &anyhow::Error) -> Option<&Error> {
+    e.chain().find_map(|cause| cause.downcast_ref::<Error>())
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Largest width or height any supported format uses in practice; PC-98
+/// screens are 640x400 and the largest Windows-era CGs 1280x960. Headers
+/// beyond it are corrupt or hostile and would only lead to huge
+/// allocations.
+pub const MAX_DIMENSION: u64 = 16384;
+
+/// Fail with [`Error::BadDimensions`] unless both sides are in
+/// 1..=[`MAX_DIMENSION`]. Every parser checks this before allocating
+/// pixel buffers, so [`DecodeLimits::UNLIMITED`] still bounds memory.
+pub fn check_dimensions(format: &FormatType, width: u64, height: u64) -> Result<()> {
+    if (1..=MAX_DIMENSION).contains(&width) && (1..=MAX_DIMENSION).contains(&height) {
+        Ok(())
+    } else {
+        Err(Error::BadDimensions { format: format.clone(), width, height })
+    }
+}
+
+/// Fail with [`Error::Truncated`] unless `data` holds at least `needed`
+/// bytes
+pub fn check_length(format: &FormatType, data: &[u8], needed: usize) -> Result<()> {
+    if data.len() >= needed {
+        Ok(())
+    } else {
+        Err(Error::Truncated { format: format.clone(), len: data.len() as u64, needed: needed as u64 })
+    }
+}
+
+/// Which of the [`DecodeLimits`] was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Pixels,
+    Steps,
+    OutputBytes,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Pixels => write!(f, "pixel"),
+            Limit::Steps => write!(f, "step"),
+            Limit::OutputBytes => write!(f, "output size"),
+        }
+    }
+}
+
+/// Resource caps for decoding untrusted input. Built-in decoders check
+/// them against the header before decompressing anything; `None` means
+/// unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DecodeLimits {
+    /// `width * height`
+    pub max_pixels: Option<u64>,
+    /// Steps recorded by step-by-step decoding
+    pub max_steps: Option<usize>,
+    /// Bytes produced by decompression, including the RGBA result
+    pub max_output_bytes: Option<u64>,
+}
+
+impl DecodeLimits {
+    pub const UNLIMITED: DecodeLimits = DecodeLimits { max_pixels: None, max_steps: None, max_output_bytes: None };
+
+    /// Fail with [`Error::LimitExceeded`] if `value` is over `limit`
+    pub fn check(&self, format: &FormatType, limit: Limit, value: u64) -> Result<()> {
+        let max = match limit {
+            Limit::Pixels => self.max_pixels,
+            Limit::Steps => self.max_steps.map(|m| m as u64),
+            Limit::OutputBytes => self.max_output_bytes,
+        };
+        match max {
+            Some(max) if value > max => Err(Error::LimitExceeded { format: format.clone(), limit, value, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the pixel count and the RGBA output size of a `width` x `height` image
+    pub fn check_image(&self, format: &FormatType, width: u64, height: u64) -> Result<()> {
+        // Saturating: corrupt 32-bit headers can overflow the product
+        let pixels = width.saturating_mul(height);
+        self.check(format, Limit::Pixels, pixels)?;
+        self.check(format, Limit::OutputBytes, pixels.saturating_mul(4))
+    }
+}
+
+/// Row order of decoded pixel data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    /// Rows in the order the compressed stream produces them (bottom-up for
+    /// LF2/SCN, top-down for everything else)
+    Stored,
+    /// Top row first, as the image is shown
+    #[default]
+    Display,
+}
+
+/// A fully decoded image; decoders return it in [`Orientation::Display`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    pub format: FormatType,
+    pub width: u32,
+    pub height: u32,
+    /// Row order of `rgba` and `indices`
+    pub orientation: Orientation,
+    /// RGBA8, `width * height * 4` bytes; transparent pixels have alpha 0
+    pub rgba: Vec<u8>,
+    /// Palette for indexed formats (RGB)
+    pub palette: Option<Vec<[u8; 3]>>,
+    /// Palette indices for indexed formats, `width * height` bytes
+    pub indices: Option<Vec<u8>>,
+}
+
+impl DecodedImage {
+    /// Reorder rows into `orientation`. Formats stored top-down are only
+    /// relabelled.
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        if orientation != self.orientation && self.format.stores_bottom_up() {
+            let width = self.width as usize;
+            self.rgba = crate::formats::flip_rows(&self.rgba, width * 4);
+            if let Some(indices) = &self.indices {
+                self.indices = Some(crate::formats::flip_rows(indices, width));
+            }
+        }
+        self.orientation = orientation;
+        self
+    }
+}
+
+/// Decoder for a single container format
+pub trait Decoder {
+    /// Format handled by this decoder
+    fn format(&self) -> FormatType;
+
+    /// Decode a complete file held in memory
+    fn decode(&self, data: &[u8]) -> Result<DecodedImage>;
+
+    /// [`decode`](Self::decode) within `limits`. The default implementation
+    /// only checks the result; built-in decoders refuse oversized input
+    /// before decompressing it.
+    fn decode_with_limits(&self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedImage> {
+        let image = self.decode(data)?;
+        limits.check_image(&image.format, image.width as u64, image.height as u64)?;
+        Ok(image)
+    }
+}
+
+/// ToHeart / Kizuato LF2 (and SCN, which is LF2 internally)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lf2Decoder;
+
+impl Decoder for Lf2Decoder {
+    fn format(&self) -> FormatType {
+        FormatType::ToHeartLf2
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<DecodedImage> {
+        self.decode_with_limits(data, &DecodeLimits::UNLIMITED)
+    }
+
+    fn decode_with_limits(&self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedImage> {
+        if data.len() >= 16 {
+            let u16_at = |o: usize| u16::from_le_bytes([data[o], data[o + 1]]) as u64;
+            limits.check_image(&self.format(), u16_at(12), u16_at(14))?;
+        }
+        let image = Lf2Image::from_data(data).map_err(|e| invalid(self.format(), e))?;
+
+        let rgba = image.to_rgba_image(Orientation::Display).into_raw();
+
+        Ok(DecodedImage {
+            format: self.format(),
+            width: image.width as u32,
+            height: image.height as u32,
+            orientation: Orientation::Display,
+            rgba,
+            palette: Some(image.palette.iter().map(|c| [c.r, c.g, c.b]).collect()),
+            indices: Some(image.pixels),
+        })
+    }
+}
+
+/// Kanon PDT (PDT10)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PdtDecoder;
+
+impl Decoder for PdtDecoder {
+    fn format(&self) -> FormatType {
+        FormatType::KanonPdt
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<DecodedImage> {
+        self.decode_with_limits(data, &DecodeLimits::UNLIMITED)
+    }
+
+    fn decode_with_limits(&self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedImage> {
+        if data.len() >= 20 {
+            let u32_at = |o: usize| u32::from_le_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]) as u64;
+            limits.check_image(&self.format(), u32_at(12), u32_at(16))?;
+        }
+        let image = PdtImage::from_data(data).map_err(|e| invalid(self.format(), e))?;
+
+        let mut rgba = Vec::with_capacity(image.pixels.len() * 4);
+        for (i, c) in image.pixels.iter().enumerate() {
+            let alpha = image.alpha_mask.get(i).copied().unwrap_or(255);
+            rgba.extend_from_slice(&[c.r, c.g, c.b, alpha]);
+        }
+
+        Ok(DecodedImage {
+            format: self.format(),
+            width: image.width,
+            height: image.height,
+            orientation: Orientation::Display,
+            rgba,
+            palette: None,
+            indices: None,
+        })
+    }
+}
+
+/// Silky's MGR (first image of the archive; use
+/// [`MgrArchive`](crate::formats::elf::MgrArchive) for the others)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MgrDecoder;
+
+impl Decoder for MgrDecoder {
+    fn format(&self) -> FormatType {
+        FormatType::SilkyMgr
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<DecodedImage> {
+        self.decode_with_limits(data, &DecodeLimits::UNLIMITED)
+    }
+
+    fn decode_with_limits(&self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedImage> {
+        let archive = MgrArchive::from_data(data.to_vec()).map_err(|e| invalid(self.format(), e))?;
+        limits.check(&self.format(), Limit::OutputBytes, archive.entries[0].unpacked_size as u64)?;
+        let bmp = archive.entry_data(0).map_err(|e| invalid(self.format(), e))?;
+        if bmp.len() >= 26 {
+            let i32_at = |o: usize| i32::from_le_bytes([bmp[o], bmp[o + 1], bmp[o + 2], bmp[o + 3]]).unsigned_abs() as u64;
+            limits.check_image(&self.format(), i32_at(18), i32_at(22))?;
+        }
+        let image = archive.image(0).map_err(|e| invalid(self.format(), e))?;
+
+        Ok(DecodedImage {
+            format: self.format(),
+            width: image.width(),
+            height: image.height(),
+            orientation: Orientation::Display,
+            rgba: image.into_raw(),
+            palette: None,
+            indices: None,
+        })
+    }
+}
+
+/// MAG (MAKI02), 16 or 256 colors
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MagDecoder;
+
+impl Decoder for MagDecoder {
+    fn format(&self) -> FormatType {
+        FormatType::Pc98Mag
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<DecodedImage> {
+        self.decode_with_limits(data, &DecodeLimits::UNLIMITED)
+    }
+
+    fn decode_with_limits(&self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedImage> {
+        let (width, height) = MagImage::dimensions(data).map_err(|e| invalid(self.format(), e))?;
+        limits.check_image(&self.format(), width as u64, height as u64)?;
+        let image = MagImage::from_data(data).map_err(|e| invalid(self.format(), e))?;
+
+        Ok(DecodedImage {
+            format: self.format(),
+            width: image.width as u32,
+            height: image.height as u32,
+            orientation: Orientation::Display,
+            rgba: image.to_rgba().into_raw(),
+            palette: Some(image.palette.clone()),
+            indices: Some(image.pixels),
+        })
+    }
+}
+
+/// Decoder for `format`, if it decodes to a single image
+pub fn decoder_for(format: &FormatType) -> Result<Box<dyn Decoder>> {
+    match format {
+        FormatType::ToHeartLf2 | FormatType::ToHeartScn => Ok(Box::new(Lf2Decoder)),
+        FormatType::KanonPdt => Ok(Box::new(PdtDecoder)),
+        FormatType::SilkyMgr => Ok(Box::new(MgrDecoder)),
+        FormatType::Pc98Mag => Ok(Box::new(MagDecoder)),
+        other => Err(Error::Unsupported(other.clone())),
+    }
+}
+
+/// `e` as [`Error::Invalid`], unless a parser already returned a structured
+/// error (e.g. [`Error::BadDimensions`])
+fn invalid(format: FormatType, e: anyhow::Error) -> Error {
+    match e.downcast::<Error>() {
+        Ok(e) => e,
+        Err(e) => Error::Invalid { format, message: e.to_string() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+
+    #[test]
+    fn lf2_decodes_through_trait_object() {
+        let image = Lf2Image {
+            width: 2,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 1, g: 2, b: 3 }, Rgb { r: 255, g: 0, b: 0 }],
+            pixels: vec![0, 1],
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+
+        let decoder = decoder_for(&FormatType::ToHeartLf2).unwrap();
+        let decoded = decoder.decode(&data).unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 1));
+        assert_eq!(decoded.rgba, vec![1, 2, 3, 0, 255, 0, 0, 255]);
+        assert_eq!(decoded.indices, Some(vec![0, 1]));
+
+        assert!(matches!(decoder.decode(b"garbage"), Err(Error::Invalid { .. })));
+        assert!(matches!(decoder_for(&FormatType::ToHeartPak), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn errors_carry_remediation_hints() {
+        let lf2 = Lf2Image {
+            width: 1,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 4,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 4],
+            pixels: vec![0],
+        }
+        .to_lf2_bytes()
+        .unwrap();
+        let error = Lf2Decoder.decode(&lf2[..30]).unwrap_err();
+        assert!(matches!(error, Error::Truncated { len: 30, needed: 36, .. }), "{}", error);
+        assert_eq!(error.hint(), Some(Hint::Redump { len: 30 }));
+
+        let mut pdt11 = b"PDT11\0\0\0".to_vec();
+        pdt11.resize(64, 0);
+        let error = PdtDecoder.decode(&pdt11).unwrap_err();
+        assert!(matches!(&error, Error::UnsupportedVersion { version, .. } if version == "PDT11"), "{}", error);
+        assert!(error.hint().unwrap().to_string().contains("PDT10"));
+
+        let wrapped = anyhow::Error::from(error).context("S01.PDT");
+        assert!(matches!(find_error(&wrapped), Some(Error::UnsupportedVersion { .. })));
+        assert_eq!(decoder_for(&FormatType::ToHeartPak).err().and_then(|e| e.hint()), Some(Hint::ExtractArchive));
+    }
+
+    #[test]
+    fn limits_refuse_oversized_images() {
+        let image = Lf2Image {
+            width: 4,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 1,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }],
+            pixels: vec![0; 8],
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+
+        let fits = DecodeLimits { max_pixels: Some(8), max_output_bytes: Some(32), ..Default::default() };
+        assert!(Lf2Decoder.decode_with_limits(&data, &fits).is_ok());
+
+        let few_pixels = DecodeLimits { max_pixels: Some(7), ..Default::default() };
+        assert!(matches!(
+            Lf2Decoder.decode_with_limits(&data, &few_pixels),
+            Err(Error::LimitExceeded { limit: Limit::Pixels, value: 8, max: 7, .. })
+        ));
+        let small_output = DecodeLimits { max_output_bytes: Some(31), ..Default::default() };
+        assert!(matches!(
+            Lf2Decoder.decode_with_limits(&data, &small_output),
+            Err(Error::LimitExceeded { limit: Limit::OutputBytes, value: 32, .. })
+        ));
+    }
+
+    #[test]
+    fn lf2_stored_orientation_matches_stream() {
+        let image = Lf2Image {
+            width: 2,
+            height: 3,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0xff,
+            color_count: 3,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 3],
+            pixels: vec![0, 0, 1, 1, 2, 2],
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+
+        let decoded = Lf2Decoder.decode(&data).unwrap();
+        assert_eq!(decoded.orientation, Orientation::Display);
+        let stored = decoded.clone().with_orientation(Orientation::Stored);
+        assert_eq!(stored.indices, Some(vec![2, 2, 1, 1, 0, 0]));
+        assert_eq!(stored.indices.as_deref(), Some(&image.stored_pixels()[..]));
+        assert_eq!(
+            stored.indices.as_deref(),
+            Some(&crate::lzss::LzssSpec::LF2.decompress(&data[0x18 + 3 * 3..], 6)[..])
+        );
+        assert_eq!(stored.with_orientation(Orientation::Display), decoded);
+    }
+}
@@ -0,0 +1,311 @@
+//! Named hypotheses about the original LF2 encoder, checked over a corpus
+//!
+//! Each [`Hypothesis`] looks at one decoded file at a time and either holds
+//! or returns a counterexample. [`run`] checks a set of them over every
+//! file and reports pass / fail with the evidence: how many files were
+//! checked and the first counterexamples found. Results are appended to an
+//! archive (one JSON line per hypothesis per run, like the batch journal)
+//! so a verdict can be traced back to the corpus and date it came from.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::formats::magic::{LF2_HEADER_SIZE, LF2_MAGIC};
+use crate::formats::toheart::lf2_tokens::{decompress_to_tokens, LeafToken};
+use crate::formats::toheart::okumura_lzss::{compress_okumura, Token as OkuToken};
+use crate::lzss::LzssSpec;
+
+/// Counterexamples kept per hypothesis
+pub const MAX_COUNTEREXAMPLES: usize = 10;
+
+/// One LF2 file decoded to tokens
+pub struct CorpusFile {
+    pub path: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    pub tokens: Vec<LeafToken>,
+    /// Decoded bytes in stream order
+    pub ring_input: Vec<u8>,
+}
+
+impl CorpusFile {
+    pub fn from_data(path: &Path, data: &[u8]) -> Result<Self> {
+        if data.len() < LF2_HEADER_SIZE || !data.starts_with(LF2_MAGIC) {
+            return Err(anyhow!("{}: not an LF2 file", path.display()));
+        }
+        let width = u16::from_le_bytes([data[12], data[13]]);
+        let height = u16::from_le_bytes([data[14], data[15]]);
+        let payload_start = LF2_HEADER_SIZE + data[0x16] as usize * 3;
+        let payload = data.get(payload_start..)
+            .ok_or_else(|| anyhow!("{}: palette runs past the end of the file", path.display()))?;
+        let decoded = decompress_to_tokens(payload, width, height)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            width: width as usize,
+            height: height as usize,
+            tokens: decoded.tokens,
+            ring_input: decoded.ring_input,
+        })
+    }
+
+    /// Tokens with the stream offset of the first byte each one writes
+    pub fn tokens_at(&self) -> impl Iterator<Item = (usize, &LeafToken)> {
+        self.tokens.iter().scan(0, |s, token| {
+            let start = *s;
+            *s += match token {
+                LeafToken::Literal(_) => 1,
+                LeafToken::Match { len, .. } => *len as usize,
+            };
+            Some((start, token))
+        })
+    }
+}
+
+/// A claim about every file of the corpus
+pub trait Hypothesis {
+    /// Short kebab-case name used on the command line and in the archive
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// `None` if `file` agrees, otherwise why it does not
+    fn check(&self, file: &CorpusFile) -> Option<String>;
+}
+
+/// The LF2 token stream is exactly what Okumura's `lzss.c` (binary tree
+/// search) produces for the same pixels
+pub struct OkumuraTree;
+
+impl Hypothesis for OkumuraTree {
+    fn name(&self) -> &'static str {
+        "okumura-tree"
+    }
+
+    fn description(&self) -> &'static str {
+        "Leaf encoded with Okumura's lzss.c binary tree search"
+    }
+
+    fn check(&self, file: &CorpusFile) -> Option<String> {
+        let ours = compress_okumura(&file.ring_input);
+        let same = |leaf: &LeafToken, oku: &OkuToken| match (leaf, oku) {
+            (LeafToken::Literal(a), OkuToken::Literal(b)) => a == b,
+            (LeafToken::Match { pos: p1, len: l1 }, OkuToken::Match { pos: p2, len: l2 }) => p1 == p2 && l1 == l2,
+            _ => false,
+        };
+        match file.tokens.iter().zip(&ours).position(|(leaf, oku)| !same(leaf, oku)) {
+            Some(i) => Some(format!("token {}: Leaf {:?}, Okumura {:?}", i, file.tokens[i], ours[i])),
+            None if file.tokens.len() != ours.len() => {
+                Some(format!("{} tokens, Okumura {}", file.tokens.len(), ours.len()))
+            }
+            None => None,
+        }
+    }
+}
+
+/// No match writes across the end of a scanline
+pub struct MatchesWithinScanline;
+
+impl Hypothesis for MatchesWithinScanline {
+    fn name(&self) -> &'static str {
+        "matches-within-scanline"
+    }
+
+    fn description(&self) -> &'static str {
+        "Matches never cross scanlines"
+    }
+
+    fn check(&self, file: &CorpusFile) -> Option<String> {
+        let width = file.width.max(1);
+        file.tokens_at().find_map(|(start, token)| match *token {
+            LeafToken::Match { len, .. } if start / width != (start + len as usize - 1) / width => Some(format!(
+                "match of {} at stream offset {} crosses row {}",
+                len, start, start / width
+            )),
+            _ => None,
+        })
+    }
+}
+
+/// Matches only copy bytes the encoder wrote, never the initial ring fill
+pub struct NoInitialFillReads;
+
+impl Hypothesis for NoInitialFillReads {
+    fn name(&self) -> &'static str {
+        "no-initial-fill-reads"
+    }
+
+    fn description(&self) -> &'static str {
+        "Matches never read ring bytes that still hold the initial 0x20 fill"
+    }
+
+    fn check(&self, file: &CorpusFile) -> Option<String> {
+        let spec = LzssSpec::LF2;
+        let mask = spec.window_size - 1;
+        let mut written = vec![false; spec.window_size];
+        let mut r = spec.initial_position;
+        for (start, token) in file.tokens_at() {
+            match *token {
+                LeafToken::Literal(_) => {
+                    written[r] = true;
+                    r = (r + 1) & mask;
+                }
+                LeafToken::Match { pos, len } => {
+                    for k in 0..len as usize {
+                        let source = (pos as usize + k) & mask;
+                        if !written[source] {
+                            return Some(format!(
+                                "match at stream offset {} reads unwritten ring position {:#05x}",
+                                start, source
+                            ));
+                        }
+                        written[r] = true;
+                        r = (r + 1) & mask;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Every built-in hypothesis
+pub fn builtin() -> Vec<Box<dyn Hypothesis>> {
+    vec![Box::new(OkumuraTree), Box::new(MatchesWithinScanline), Box::new(NoInitialFillReads)]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// Every checked file agrees
+    Pass,
+    /// At least one counterexample
+    Fail,
+    /// No file could be checked
+    Inconclusive,
+}
+
+/// A file that disagrees with a hypothesis
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counterexample {
+    pub file: PathBuf,
+    pub detail: String,
+}
+
+/// Outcome of one hypothesis over a corpus; one archive line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HypothesisResult {
+    pub name: String,
+    pub description: String,
+    pub verdict: Verdict,
+    pub checked: usize,
+    /// Files that agree
+    pub supporting: usize,
+    /// Files that disagree
+    pub failing: usize,
+    /// The first [`MAX_COUNTEREXAMPLES`] of them
+    pub counterexamples: Vec<Counterexample>,
+    /// Corpus roots the run was given
+    pub corpus: Vec<PathBuf>,
+    pub run_at: DateTime<Utc>,
+}
+
+/// Check `hypotheses` against every file; `corpus` is recorded with the results
+pub fn run(hypotheses: &[Box<dyn Hypothesis>], files: &[CorpusFile], corpus: &[PathBuf]) -> Vec<HypothesisResult> {
+    let run_at = Utc::now();
+    hypotheses.iter()
+        .map(|hypothesis| {
+            let mut counterexamples = Vec::new();
+            let mut failing = 0;
+            for file in files {
+                if let Some(detail) = hypothesis.check(file) {
+                    failing += 1;
+                    if counterexamples.len() < MAX_COUNTEREXAMPLES {
+                        counterexamples.push(Counterexample { file: file.path.clone(), detail });
+                    }
+                }
+            }
+            let verdict = match (files.len(), failing) {
+                (0, _) => Verdict::Inconclusive,
+                (_, 0) => Verdict::Pass,
+                _ => Verdict::Fail,
+            };
+            HypothesisResult {
+                name: hypothesis.name().to_string(),
+                description: hypothesis.description().to_string(),
+                verdict,
+                checked: files.len(),
+                supporting: files.len() - failing,
+                failing,
+                counterexamples,
+                corpus: corpus.to_vec(),
+                run_at,
+            }
+        })
+        .collect()
+}
+
+/// Append `results` to the JSON Lines archive at `path`
+pub fn archive(path: &Path, results: &[HypothesisResult]) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for result in results {
+        writeln!(file, "{}", serde_json::to_string(result)?)?;
+    }
+    Ok(())
+}
+
+/// Every result archived at `path`, oldest first
+pub fn load_archive(path: &Path) -> Result<Vec<HypothesisResult>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(Into::into))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    fn corpus_file(pixels: Vec<u8>, width: u16) -> CorpusFile {
+        let image = Lf2Image {
+            width,
+            height: (pixels.len() / width as usize) as u16,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels,
+        };
+        CorpusFile::from_data(Path::new("t.lf2"), &image.to_lf2_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn verdicts_and_archive() {
+        // A 4-wide image of one colour: the greedy encoder's long match
+        // runs across rows
+        let flat = corpus_file(vec![1; 32], 4);
+        assert!(MatchesWithinScanline.check(&flat).is_some());
+        assert!(NoInitialFillReads.check(&flat).is_none());
+
+        let results = run(&builtin(), std::slice::from_ref(&flat), &[PathBuf::from("corpus")]);
+        let by_name = |name: &str| results.iter().find(|r| r.name == name).unwrap();
+        assert_eq!(by_name("matches-within-scanline").verdict, Verdict::Fail);
+        assert_eq!(by_name("matches-within-scanline").counterexamples[0].file, PathBuf::from("t.lf2"));
+        assert_eq!(by_name("no-initial-fill-reads").verdict, Verdict::Pass);
+        assert!(run(&builtin(), &[], &[]).iter().all(|r| r.verdict == Verdict::Inconclusive));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hypotheses.jsonl");
+        archive(&path, &results).unwrap();
+        archive(&path, &results[..1]).unwrap();
+        let archived = load_archive(&path).unwrap();
+        assert_eq!(archived.len(), results.len() + 1);
+        assert_eq!(archived[0].name, results[0].name);
+        assert_eq!(archived[0].run_at, results[0].run_at);
+    }
+}
@@ -0,0 +1,7 @@
+//! Executable research artifacts
+//!
+//! Findings about how the original tools worked used to live in issue
+//! threads; the modules here turn them into checks that can be re-run
+//! against a corpus whenever the evidence changes.
+
+pub mod hypothesis;
@@ -0,0 +1,175 @@
+//! Windows BMP reader for encode inputs
+//!
+//! Re-encoding starts from an edited export, and many of the paint tools
+//! used on these images save BMP. The image crate decodes BMP to RGBA but
+//! drops the colour table, so an 8-bit file comes back as colours that have
+//! to be matched against the palette again (ambiguous when two entries share
+//! a colour). This reader keeps the indices and the colour table of
+//! uncompressed 8-bit BMPs and reads 24-bit BMPs as opaque RGBA.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::formats::flipped_row;
+use crate::formats::toheart::lf2::Rgb;
+
+/// Decoded BMP, rows in display order (top row first)
+#[derive(Debug, Clone)]
+pub struct BmpImage {
+    pub width: u32,
+    pub height: u32,
+    /// Colour table of an 8-bit BMP
+    pub palette: Option<Vec<Rgb>>,
+    /// Palette indices of an 8-bit BMP, `width * height` bytes
+    pub indices: Option<Vec<u8>>,
+    /// `width * height * 4` bytes; fully opaque
+    pub rgba: Vec<u8>,
+}
+
+impl BmpImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        Self::from_data(&data).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Whether `data` is an uncompressed 8-bit or 24-bit BMP, the kinds
+    /// [`from_data`](Self::from_data) reads
+    pub fn supports(data: &[u8]) -> bool {
+        data.len() >= 54
+            && &data[..2] == b"BM"
+            && matches!(u16::from_le_bytes([data[28], data[29]]), 8 | 24)
+            && data[30..34] == [0; 4]
+    }
+
+    /// Parse an uncompressed 8-bit or 24-bit BMP (BITMAPINFOHEADER or later)
+    pub fn from_data(data: &[u8]) -> Result<Self> {
+        if data.len() < 54 || &data[..2] != b"BM" {
+            return Err(anyhow!("Not a BMP file"));
+        }
+        let u16_at = |o: usize| u16::from_le_bytes([data[o], data[o + 1]]);
+        let u32_at = |o: usize| u32::from_le_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]);
+
+        let pixel_offset = u32_at(10) as usize;
+        let header_size = u32_at(14) as usize;
+        if header_size < 40 {
+            return Err(anyhow!("BMP core headers are not supported"));
+        }
+        let width = i32::from_le_bytes([data[18], data[19], data[20], data[21]]);
+        let raw_height = i32::from_le_bytes([data[22], data[23], data[24], data[25]]);
+        let bits = u16_at(28);
+        let compression = u32_at(30);
+        if width <= 0 || raw_height == 0 {
+            return Err(anyhow!("BMP has no pixels ({}x{})", width, raw_height));
+        }
+        if compression != 0 {
+            return Err(anyhow!("Compressed BMPs are not supported (compression {})", compression));
+        }
+        // Negative height: rows are stored top-down
+        let bottom_up = raw_height > 0;
+        let (width, height) = (width as usize, raw_height.unsigned_abs() as usize);
+
+        let bytes_per_pixel = match bits {
+            8 => 1,
+            24 => 3,
+            _ => return Err(anyhow!("{}-bit BMPs are not supported (8 or 24 bits)", bits)),
+        };
+        let stride = (width * bytes_per_pixel + 3) / 4 * 4;
+        let end = pixel_offset + stride * height;
+        if pixel_offset < 14 + header_size || data.len() < end {
+            return Err(anyhow!("BMP pixel data truncated"));
+        }
+        let row = |y: usize| {
+            let stored = if bottom_up { flipped_row(y, height) } else { y };
+            let start = pixel_offset + stored * stride;
+            &data[start..start + width * bytes_per_pixel]
+        };
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        let (palette, indices) = if bits == 8 {
+            let colors = match u32_at(46) {
+                0 => 256,
+                n => (n as usize).min(256),
+            };
+            let table = 14 + header_size;
+            if table + colors * 4 > pixel_offset {
+                return Err(anyhow!("BMP colour table truncated"));
+            }
+            let palette: Vec<Rgb> = data[table..table + colors * 4]
+                .chunks_exact(4)
+                .map(|c| Rgb { r: c[2], g: c[1], b: c[0] })
+                .collect();
+            let indices: Vec<u8> = (0..height).flat_map(|y| row(y).iter().copied()).collect();
+            for &index in &indices {
+                let c = palette.get(index as usize)
+                    .ok_or_else(|| anyhow!("BMP index {} outside its {}-colour table", index, colors))?;
+                rgba.extend_from_slice(&[c.r, c.g, c.b, 255]);
+            }
+            (Some(palette), Some(indices))
+        } else {
+            for y in 0..height {
+                for bgr in row(y).chunks_exact(3) {
+                    rgba.extend_from_slice(&[bgr[2], bgr[1], bgr[0], 255]);
+                }
+            }
+            (None, None)
+        };
+
+        Ok(Self { width: width as u32, height: height as u32, palette, indices, rgba })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal BMP: 40-byte header, optional colour table, padded rows
+    fn bmp(width: i32, height: i32, bits: u16, table: &[[u8; 4]], rows: &[&[u8]]) -> Vec<u8> {
+        let offset = 54 + table.len() * 4;
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        for entry in table {
+            data.extend_from_slice(entry);
+        }
+        for row in rows {
+            data.extend_from_slice(row);
+            data.resize(data.len() + (4 - row.len() % 4) % 4, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_indexed_and_truecolor_bmps() {
+        // 3x2 bottom-up, 3 colours: stored rows are bottom first
+        let table = [[0, 0, 255, 0], [0, 255, 0, 0], [255, 0, 0, 0]];
+        let indexed = BmpImage::from_data(&bmp(3, 2, 8, &table, &[&[2, 2, 1], &[0, 1, 2]])).unwrap();
+        assert_eq!(indexed.indices.as_deref(), Some(&[0, 1, 2, 2, 2, 1][..]));
+        let palette = indexed.palette.unwrap();
+        assert_eq!((palette[0].r, palette[0].g, palette[0].b), (255, 0, 0));
+        assert_eq!((palette[2].r, palette[2].g, palette[2].b), (0, 0, 255));
+        assert_eq!(&indexed.rgba[..8], &[255, 0, 0, 255, 0, 255, 0, 255]);
+
+        // Top-down (negative height) 24-bit, BGR on disk
+        let truecolor = BmpImage::from_data(&bmp(1, -2, 24, &[], &[&[1, 2, 3], &[4, 5, 6]])).unwrap();
+        assert_eq!((truecolor.width, truecolor.height), (1, 2));
+        assert_eq!(truecolor.rgba, [3, 2, 1, 255, 6, 5, 4, 255]);
+        assert!(truecolor.indices.is_none());
+
+        assert!(BmpImage::from_data(&bmp(1, 1, 8, &table, &[&[3]])).is_err());
+        assert!(BmpImage::from_data(&bmp(1, 1, 16, &[], &[&[0, 0]])).is_err());
+        assert!(BmpImage::from_data(b"PNG").is_err());
+        assert!(BmpImage::supports(&bmp(1, 1, 24, &[], &[&[0, 0, 0]])));
+        assert!(!BmpImage::supports(&bmp(1, 1, 32, &[], &[&[0, 0, 0, 0]])));
+    }
+}
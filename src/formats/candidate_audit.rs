@@ -0,0 +1,207 @@
+//! Candidate-set completeness audit for the LF2 encoders
+//!
+//! A re-encode can miss an original reference for two different reasons:
+//! the match finder never looks where the original match is (a search-space
+//! bug: window too small, initial fill not searched, a match shorter than
+//! what is available at that position), or it finds it but ranks another
+//! candidate higher (a tie-break difference). [`audit_lf2`] walks every
+//! reference token of an original file and sorts it into one of those
+//! buckets, so the two kinds of file can be worked on separately.
+//!
+//! The ring buffer contents at a given output position only depend on the
+//! pixels, not on how they were parsed, so presence is checked against the
+//! original parse without running the finder there.
+
+use std::collections::HashMap;
+use anyhow::Result;
+use serde::Serialize;
+
+use super::reencode::Lf2Encoder;
+use super::toheart::lf2::Lf2Image;
+use crate::lzss::LzssSpec;
+
+const RING_SIZE: usize = 0x1000;
+const RING_START: usize = 0x0fee;
+const MAX_MATCH: usize = 18;
+
+/// Ring positions a match finder considers from a given write position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchSpace {
+    /// Largest distance back from the write position
+    pub max_distance: usize,
+    /// Initial-fill positions (just before the first write) that are
+    /// searched on top of the ones already written; `RING_SIZE` for all
+    pub fill_positions: usize,
+}
+
+impl SearchSpace {
+    /// Whether a reference `distance` back is searched after `written`
+    /// bytes have been output
+    pub fn contains(&self, distance: usize, written: usize) -> bool {
+        (1..=self.max_distance).contains(&distance) && distance <= written + self.fill_positions
+    }
+}
+
+/// Why an original reference is not among the finder's candidates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Absence {
+    /// The position is outside the finder's [`SearchSpace`]
+    OutsideSearchSpace,
+    /// A longer match is available at the same position, and the finders
+    /// always take the longest one there
+    ShorterThanAvailable { available: usize },
+}
+
+/// An original reference the finder cannot produce
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AbsentMatch {
+    /// Output offset (stored pixel order) of the reference
+    pub output: usize,
+    pub distance: usize,
+    pub len: usize,
+    pub reason: Absence,
+}
+
+/// Per-file audit result
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CandidateAudit {
+    /// Reference tokens in the original stream
+    pub matches: usize,
+    /// Emitted by the encoder exactly as in the original
+    pub reproduced: usize,
+    /// Among the candidates, but the encoder emitted something else at the
+    /// same position
+    pub outranked: usize,
+    /// Among the candidates, but the encoder's parse has no token starting
+    /// at that position, so its choice there cannot be compared
+    pub unaligned: usize,
+    pub absent: Vec<AbsentMatch>,
+}
+
+impl CandidateAudit {
+    /// Missing candidates point at the search space, not the tie-break
+    pub fn is_search_space_miss(&self) -> bool {
+        !self.absent.is_empty()
+    }
+
+    pub fn is_tie_break_mismatch(&self) -> bool {
+        self.absent.is_empty() && self.outranked > 0
+    }
+}
+
+/// Audit every reference of the LF2 file `data` against `encoder`
+pub fn audit_lf2(data: &[u8], encoder: Lf2Encoder) -> Result<CandidateAudit> {
+    let image = Lf2Image::from_data(data)?;
+    let pixels = image.stored_pixels();
+    let stream_start = 0x18 + image.color_count as usize * 3;
+    let spec = LzssSpec::LF2;
+    let space = encoder.search_space();
+
+    let rebuilt = encoder.encode(&image)?;
+    let emitted: HashMap<usize, Vec<u8>> = spec
+        .tokens(rebuilt.get(stream_start..).unwrap_or_default(), pixels.len())
+        .into_iter()
+        .map(|t| (t.output.start, t.bytes))
+        .collect();
+
+    let mut ring = [spec.initial_fill; RING_SIZE];
+    let mut audit = CandidateAudit::default();
+    for token in spec.tokens(&data[stream_start..], pixels.len()) {
+        let s = token.output.start;
+        if token.bytes.len() == 2 {
+            audit.matches += 1;
+            let (b0, b1) = (token.bytes[0] ^ spec.xor_key, token.bytes[1] ^ spec.xor_key);
+            let pos = (b0 >> 4) as usize | (b1 as usize) << 4;
+            let len = (b0 & 0x0f) as usize + spec.min_match;
+            let r = (RING_START + s) % RING_SIZE;
+            let distance = (r + RING_SIZE - pos - 1) % RING_SIZE + 1;
+            let available = longest_match(&ring, &pixels, s, pos);
+
+            let reason = if !space.contains(distance, s) {
+                Some(Absence::OutsideSearchSpace)
+            } else if available > len {
+                Some(Absence::ShorterThanAvailable { available })
+            } else {
+                None
+            };
+            match (reason, emitted.get(&s)) {
+                (Some(reason), _) => audit.absent.push(AbsentMatch { output: s, distance, len, reason }),
+                (None, Some(bytes)) if *bytes == token.bytes => audit.reproduced += 1,
+                (None, Some(_)) => audit.outranked += 1,
+                (None, None) => audit.unaligned += 1,
+            }
+        }
+        for i in token.output {
+            ring[(RING_START + i) % RING_SIZE] = pixels[i];
+        }
+    }
+    Ok(audit)
+}
+
+/// Longest match at ring position `pos` for `pixels[s..]`, reading back
+/// bytes the copy itself writes when the source overlaps the write position
+fn longest_match(ring: &[u8; RING_SIZE], pixels: &[u8], s: usize, pos: usize) -> usize {
+    let r = (RING_START + s) % RING_SIZE;
+    let cap = MAX_MATCH.min(pixels.len() - s);
+    (0..cap)
+        .take_while(|&l| {
+            let src = (pos + l) % RING_SIZE;
+            let ahead = (src + RING_SIZE - r) % RING_SIZE;
+            let byte = if ahead < l { pixels[s + ahead] } else { ring[src] };
+            byte == pixels[s + l]
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+    use crate::formats::toheart::encode_profile::EncodeProfile;
+
+    #[test]
+    fn separates_absent_from_outranked_candidates() {
+        let image = Lf2Image {
+            width: 8,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: (0..32).map(|i| (i / 3 % 2) as u8).collect(),
+        };
+        let original = image.to_lf2_bytes_okumura().unwrap();
+
+        let own = audit_lf2(&original, Lf2Encoder::Okumura).unwrap();
+        assert!(own.matches > 0);
+        assert_eq!((own.reproduced, own.outranked, own.absent.len()), (own.matches, 0, 0));
+
+        // 1 2 3 9 [1 2 3] 8 [1 2 3]: the original takes the farther of the
+        // two equally long repeats at the end, the strict scan the nearer
+        let repeats = Lf2Image { width: 11, height: 1, pixels: vec![1, 2, 3, 9, 1, 2, 3, 8, 1, 2, 3], ..image };
+        let stream_start = 0x18 + 2 * 3;
+        let mut data = repeats.to_lf2_bytes_okumura().unwrap()[..stream_start].to_vec();
+        let reference = |s: usize, distance: usize| {
+            let pos = (RING_START + s - distance) % RING_SIZE;
+            [((pos & 0x0f) << 4) as u8 ^ 0xff, (pos >> 4) as u8 ^ 0xff]
+        };
+        data.push(0b1111_0100 ^ 0xff);
+        data.extend_from_slice(&[1 ^ 0xff, 2 ^ 0xff, 3 ^ 0xff, 9 ^ 0xff]);
+        data.extend_from_slice(&reference(4, 4));
+        data.push(8 ^ 0xff);
+        data.extend_from_slice(&reference(8, 8));
+        let audit = audit_lf2(&data, Lf2Encoder::NaiveStrict).unwrap();
+        assert_eq!((audit.matches, audit.reproduced, audit.outranked), (2, 1, 1));
+        assert!(audit.is_tie_break_mismatch());
+
+        // Pixels equal to the 0x20 initial fill: the exhaustive linear scan
+        // references the fill, which Okumura without dummy inserts never searches
+        let spaces = Lf2Image { width: 8, height: 4, pixels: vec![0x20; 32], ..repeats };
+        let data = spaces.to_lf2_bytes_with_params(&EncodeProfile::Exhaustive.params()).unwrap();
+        let audit = audit_lf2(&data, Lf2Encoder::Profile(EncodeProfile::Faithful)).unwrap();
+        assert!(audit.is_search_space_miss());
+        assert!(audit.absent.iter().all(|a| a.reason == Absence::OutsideSearchSpace));
+    }
+}
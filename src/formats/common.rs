@@ -0,0 +1,223 @@
+//! Helpers shared by the format implementations
+//!
+//! The LZSS-family streams interleave flag bytes with item payloads: one
+//! flag byte announces the next eight items, one bit each. Formats differ in
+//! which end of the byte comes first and whether the stored flag is
+//! inverted (LF2 XORs it with 0xff). [`BitFlagWriter`] and [`BitFlagReader`]
+//! handle that framing; payload bytes are passed through untouched.
+
+use crate::lzss::BitOrder;
+
+/// How a flag byte is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Stored as is
+    Normal,
+    /// Stored XORed with 0xff
+    Inverted,
+}
+
+impl Polarity {
+    fn apply(self, byte: u8) -> u8 {
+        match self {
+            Self::Normal => byte,
+            Self::Inverted => !byte,
+        }
+    }
+}
+
+/// Mask of bit `index` (0..8) of a flag byte consumed in `order`
+fn bit_mask(order: BitOrder, index: u8) -> u8 {
+    match order {
+        BitOrder::MsbFirst => 0x80 >> index,
+        BitOrder::LsbFirst => 1 << index,
+    }
+}
+
+/// Builds a flag-framed stream: [`push_flag`](Self::push_flag) for each
+/// item, then its payload bytes
+#[derive(Debug, Clone)]
+pub struct BitFlagWriter {
+    order: BitOrder,
+    polarity: Polarity,
+    out: Vec<u8>,
+    /// Offset of the current flag byte in `out`
+    flag_pos: usize,
+    flag: u8,
+    /// Bits of the current flag byte used so far (8 = start a new one)
+    bits: u8,
+}
+
+impl BitFlagWriter {
+    pub fn new(order: BitOrder, polarity: Polarity) -> Self {
+        Self { order, polarity, out: Vec::new(), flag_pos: 0, flag: 0, bits: 8 }
+    }
+
+    /// Start the next item, opening a new flag byte every eight items.
+    /// Unused bits of the last flag byte stay clear (before polarity).
+    pub fn push_flag(&mut self, bit: bool) {
+        if self.bits == 8 {
+            self.flag_pos = self.out.len();
+            self.flag = 0;
+            self.bits = 0;
+            self.out.push(self.polarity.apply(0));
+        }
+        if bit {
+            self.flag |= bit_mask(self.order, self.bits);
+        }
+        self.bits += 1;
+        self.out[self.flag_pos] = self.polarity.apply(self.flag);
+    }
+
+    /// Append payload of the current item
+    pub fn push(&mut self, byte: u8) {
+        self.out.push(byte);
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    /// Bytes written so far, flag bytes included
+    pub fn len(&self) -> usize {
+        self.out.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.out.is_empty()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.out
+    }
+
+    /// Like [`finish`](Self::finish), but with the unused bits of the last
+    /// flag byte set to `fill` instead of left clear
+    pub fn finish_padded(mut self, fill: bool) -> Vec<u8> {
+        if fill && self.bits < 8 {
+            for index in self.bits..8 {
+                self.flag |= bit_mask(self.order, index);
+            }
+            self.out[self.flag_pos] = self.polarity.apply(self.flag);
+        }
+        self.out
+    }
+}
+
+/// Reads a flag-framed stream written as [`BitFlagWriter`] does
+#[derive(Debug, Clone)]
+pub struct BitFlagReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    order: BitOrder,
+    polarity: Polarity,
+    flag: u8,
+    /// Bits of the current flag byte consumed so far (8 = read a new one)
+    bits: u8,
+}
+
+impl<'a> BitFlagReader<'a> {
+    pub fn new(data: &'a [u8], order: BitOrder, polarity: Polarity) -> Self {
+        Self { data, pos: 0, order, polarity, flag: 0, bits: 8 }
+    }
+
+    /// Flag bit of the next item, reading a new flag byte every eight items;
+    /// `None` only when a new flag byte is due and the input is exhausted.
+    /// Callers stop on the output size or a failed payload read.
+    pub fn next_flag(&mut self) -> Option<bool> {
+        if self.bits == 8 {
+            self.flag = self.polarity.apply(self.read_u8()?);
+            self.bits = 0;
+        }
+        let bit = self.flag & bit_mask(self.order, self.bits) != 0;
+        self.bits += 1;
+        Some(bit)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// The next `n` payload bytes, or `None` (consuming nothing) if fewer remain
+    pub fn read_bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    /// Input bytes consumed so far
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_and_reader_agree() {
+        let items: Vec<(bool, Vec<u8>)> = (0..19u8)
+            .map(|i| if i % 3 == 0 { (false, vec![0xaa, i]) } else { (true, vec![i]) })
+            .collect();
+
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            for polarity in [Polarity::Normal, Polarity::Inverted] {
+                let mut writer = BitFlagWriter::new(order, polarity);
+                for (bit, payload) in &items {
+                    writer.push_flag(*bit);
+                    writer.extend_from_slice(payload);
+                }
+                let stream = writer.finish();
+
+                let mut reader = BitFlagReader::new(&stream, order, polarity);
+                for (bit, payload) in &items {
+                    assert_eq!(reader.next_flag(), Some(*bit));
+                    assert_eq!(reader.read_bytes(payload.len()), Some(&payload[..]));
+                }
+                // Padding bits of the last flag byte still read as flags
+                assert!(reader.is_at_end());
+                assert_eq!(reader.next_flag(), Some(false));
+                assert_eq!(reader.read_u8(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn flag_layout() {
+        // literal, reference, literal: bits 1, 0, 1
+        let mut writer = BitFlagWriter::new(BitOrder::MsbFirst, Polarity::Inverted);
+        for bit in [true, false, true] {
+            writer.push_flag(bit);
+        }
+        assert_eq!(writer.finish(), vec![!0b1010_0000]);
+
+        let mut writer = BitFlagWriter::new(BitOrder::LsbFirst, Polarity::Normal);
+        for bit in [true, false, true] {
+            writer.push_flag(bit);
+        }
+        assert_eq!(writer.finish(), vec![0b0000_0101]);
+
+        // Padding fills only the bits after the last item
+        let mut writer = BitFlagWriter::new(BitOrder::MsbFirst, Polarity::Inverted);
+        for bit in [false, true, false] {
+            writer.push_flag(bit);
+        }
+        assert_eq!(writer.finish_padded(true), vec![!0b0101_1111]);
+        let mut writer = BitFlagWriter::new(BitOrder::LsbFirst, Polarity::Normal);
+        writer.push_flag(false);
+        assert_eq!(writer.finish_padded(true), vec![0b1111_1110]);
+
+        let mut reader = BitFlagReader::new(&[0x80, 7], BitOrder::MsbFirst, Polarity::Normal);
+        assert_eq!(reader.next_flag(), Some(true));
+        assert_eq!(reader.read_bytes(2), None);
+        assert_eq!(reader.read_u8(), Some(7));
+        assert_eq!(reader.position(), 2);
+    }
+}
@@ -0,0 +1,35 @@
+//! Elf GPH image format
+//! TODO: Format specification needs analysis - placeholder implementation
+//!
+//! GPH files are recognised so mixed-era collections report a clear
+//! "not implemented" error instead of "unsupported extension"; decoding
+//! needs sample files to pin down the header and compression.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::{DecodeConfig, DecodingState};
+
+/// GPH image structure (placeholder)
+pub struct GphImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl GphImage {
+    /// Open GPH file (TODO: implement based on format analysis)
+    pub fn open<P: AsRef<Path>>(_path: P) -> Result<Self> {
+        Err(anyhow!("GPH format not yet implemented - needs analysis"))
+    }
+
+    /// Decode GPH (placeholder)
+    pub fn decode(&self, _output_path: &Path, _config: &DecodeConfig) -> Result<()> {
+        Err(anyhow!("GPH decode not yet implemented"))
+    }
+
+    /// Decode with step-by-step visualization (placeholder)
+    pub fn decode_with_steps(&self, _output_path: &Path, _state: &mut DecodingState, _config: &DecodeConfig) -> Result<()> {
+        Err(anyhow!("GPH step-by-step decode not yet implemented"))
+    }
+}
@@ -0,0 +1,273 @@
+//! Silky's MGR image archive
+//!
+//! Layout (little endian):
+//! - `u16` entry count
+//! - count == 1: the entry follows immediately at 0x02
+//! - count > 1: `u32` offset table, one per entry, starting at 0x02
+//!
+//! Each entry is `u32 unpacked_size`, `u32 packed_size`, then `packed_size`
+//! bytes of LZF-style compressed data that expand to a complete BMP file.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use tracing::debug;
+
+use crate::{DecodeConfig, DecodingState, DecodeStep};
+
+/// Sanity limit for a single unpacked entry (a 1024x768x32 BMP is ~3 MiB)
+const MAX_UNPACKED_SIZE: usize = 64 << 20;
+
+/// One compressed entry
+#[derive(Debug, Clone)]
+pub struct MgrEntry {
+    pub offset: usize,
+    pub unpacked_size: usize,
+    pub packed_size: usize,
+}
+
+/// MGR archive held in memory
+pub struct MgrArchive {
+    data: Vec<u8>,
+    pub entries: Vec<MgrEntry>,
+}
+
+impl MgrArchive {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::from_data(data)
+    }
+
+    /// Parse the entry table
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn from_data(data: Vec<u8>) -> Result<Self> {
+        if data.len() < 10 {
+            return Err(anyhow!("MGR file too small"));
+        }
+        let count = u16::from_le_bytes([data[0], data[1]]) as usize;
+        if count == 0 {
+            return Err(anyhow!("MGR archive has no entries"));
+        }
+
+        let offsets: Vec<usize> = if count == 1 {
+            vec![2]
+        } else {
+            let table_end = 2 + count * 4;
+            if table_end > data.len() {
+                return Err(anyhow!("MGR offset table exceeds file size"));
+            }
+            (0..count)
+                .map(|i| read_u32(&data, 2 + i * 4) as usize)
+                .collect()
+        };
+
+        let mut entries = Vec::with_capacity(count);
+        for (i, &offset) in offsets.iter().enumerate() {
+            if offset + 8 > data.len() {
+                return Err(anyhow!("MGR entry {} header out of bounds", i));
+            }
+            let unpacked_size = read_u32(&data, offset) as usize;
+            let packed_size = read_u32(&data, offset + 4) as usize;
+            if offset + 8 + packed_size > data.len() {
+                return Err(anyhow!("MGR entry {} data out of bounds", i));
+            }
+            if unpacked_size > MAX_UNPACKED_SIZE {
+                return Err(anyhow!("MGR entry {} unpacked size {} is implausible", i, unpacked_size));
+            }
+            debug!("MGR entry {}: offset {:#x}, {} -> {} bytes", i, offset, packed_size, unpacked_size);
+            entries.push(MgrEntry { offset, unpacked_size, packed_size });
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    /// Unpacked BMP bytes of entry `index`
+    pub fn entry_data(&self, index: usize) -> Result<Vec<u8>> {
+        let entry = self.entries.get(index)
+            .ok_or_else(|| anyhow!("MGR entry {} does not exist", index))?;
+        let start = entry.offset + 8;
+        let packed = &self.data[start..start + entry.packed_size];
+        crate::timing::time(crate::timing::Phase::Decompress, || decompress(packed, entry.unpacked_size))
+    }
+
+    /// Decode entry `index` to an RGBA image
+    pub fn image(&self, index: usize) -> Result<image::RgbaImage> {
+        let bmp = self.entry_data(index)?;
+        if bmp.len() >= 26 {
+            let i32_at = |o: usize| i32::from_le_bytes([bmp[o], bmp[o + 1], bmp[o + 2], bmp[o + 3]]).unsigned_abs() as u64;
+            crate::decoder::check_dimensions(&crate::FormatType::SilkyMgr, i32_at(18), i32_at(22))?;
+        }
+        let img = image::load_from_memory_with_format(&bmp, image::ImageFormat::Bmp)
+            .map_err(|e| anyhow!("MGR entry {} is not a valid BMP: {}", index, e))?;
+        let rgba = img.to_rgba8();
+        crate::timing::image(rgba.width(), rgba.height(), || rgba.pixels().filter(|px| px[3] == 0).count());
+        Ok(rgba)
+    }
+
+    /// Output path for entry `index`: `output_path` itself for single-image
+    /// archives, otherwise `<stem>_NNN.<ext>` next to it
+    pub fn entry_output_path(&self, output_path: &Path, index: usize) -> PathBuf {
+        if self.entries.len() == 1 {
+            return output_path.to_path_buf();
+        }
+        let mut name = output_path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("_{:03}", index));
+        if let Some(ext) = output_path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        output_path.with_file_name(name)
+    }
+
+    /// Decode every entry, choosing the output format by extension
+    pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        if config.no_output {
+            return Ok(());
+        }
+        for index in 0..self.entries.len() {
+            let img = self.image(index)?;
+            let path = self.entry_output_path(output_path, index);
+            crate::output::write_rgba_image_ordered(&img, &path, config.direct_writes, config.rgb565_order)?;
+        }
+        Ok(())
+    }
+
+    /// Decode with step-by-step visualization (one step per entry)
+    pub fn decode_with_steps(&self, output_path: &Path, state: &mut DecodingState, config: &DecodeConfig) -> Result<()> {
+        state.metadata.insert("entries".to_string(), self.entries.len().to_string());
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            let header_bytes = self.data[entry.offset..entry.offset + 8].to_vec();
+            state.try_add_step(&crate::FormatType::SilkyMgr, DecodeStep {
+                step_number: i + 1,
+                description: format!("MGRエントリ {} を展開", i),
+                explanation: format!(
+                    "オフセット 0x{:x} のエントリを展開します。\n\
+                     圧縮サイズ {} バイト → 展開後 {} バイトの BMP。\n\
+                     制御バイト 0x00-0x1f はリテラル列、0x20 以上は過去出力への参照です。",
+                    entry.offset, entry.packed_size, entry.unpacked_size
+                ),
+                operation_type: crate::formats::StepOperationType::Header,
+                raw_bytes: header_bytes,
+                data_offset: entry.offset,
+                data_length: entry.packed_size + 8,
+                pixels_decoded: 0,
+                memory_state: vec![],
+                ring_position: 0,
+                partial_image: None,
+            })?;
+        }
+
+        self.decode(output_path, config)
+    }
+}
+
+/// Expand Silky's LZF-style stream.
+///
+/// A control byte below 0x20 copies `ctl + 1` literal bytes. Otherwise it is
+/// a back-reference: length `(ctl >> 5) + 2` (a length field of 7 is extended
+/// by the next byte) and distance `((ctl & 0x1f) << 8 | next) + 1`.
+pub fn decompress(input: &[u8], unpacked_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(unpacked_size);
+    let mut pos = 0;
+
+    while out.len() < unpacked_size && pos < input.len() {
+        let ctl = input[pos] as usize;
+        pos += 1;
+
+        if ctl < 0x20 {
+            let count = ctl + 1;
+            let literal = input.get(pos..pos + count)
+                .ok_or_else(|| anyhow!("MGR literal run past end of data at {:#x}", pos))?;
+            out.extend_from_slice(literal);
+            pos += count;
+        } else {
+            let mut count = ctl >> 5;
+            if count == 7 {
+                count += *input.get(pos).ok_or_else(|| anyhow!("Truncated MGR reference"))? as usize;
+                pos += 1;
+            }
+            count += 2;
+            let low = *input.get(pos).ok_or_else(|| anyhow!("Truncated MGR reference"))? as usize;
+            pos += 1;
+            let distance = ((ctl & 0x1f) << 8) + low + 1;
+            if distance > out.len() {
+                return Err(anyhow!("MGR reference distance {} exceeds output size {}", distance, out.len()));
+            }
+            // Overlapping copy: byte by byte
+            let start = out.len() - distance;
+            for i in 0..count {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+
+    if out.len() < unpacked_size {
+        return Err(anyhow!("MGR data ended after {} of {} bytes", out.len(), unpacked_size));
+    }
+    out.truncate(unpacked_size);
+    Ok(out)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Literal-only packing (valid input for `decompress`)
+    fn pack_literals(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for chunk in data.chunks(32) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn decompress_handles_overlapping_references() {
+        // "ab" then a length-6 reference at distance 2 -> "abababab"
+        let packed = [0x01, b'a', b'b', 4 << 5, 0x01];
+        assert_eq!(decompress(&packed, 8).unwrap(), b"abababab");
+        // Extended length: 7 + 3 + 2 = 12 copies of the previous byte
+        let packed = [0x00, b'z', 0xe0, 0x03, 0x00];
+        assert_eq!(decompress(&packed, 13).unwrap(), vec![b'z'; 13]);
+        assert!(decompress(&[0x20, 0x05], 4).is_err());
+    }
+
+    #[test]
+    fn multi_entry_archive_decodes_bmps() {
+        let mut bmps = Vec::new();
+        for color in [[255u8, 0, 0, 255], [0, 0, 255, 255]] {
+            let img = image::RgbaImage::from_pixel(3, 2, image::Rgba(color));
+            let mut bmp = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::ImageRgba8(img).to_rgb8()
+                .write_to(&mut bmp, image::ImageOutputFormat::Bmp).unwrap();
+            bmps.push(bmp.into_inner());
+        }
+
+        let mut data = vec![2, 0];
+        let table = data.len();
+        data.resize(table + 8, 0);
+        for (i, bmp) in bmps.iter().enumerate() {
+            let offset = data.len() as u32;
+            data[table + i * 4..table + i * 4 + 4].copy_from_slice(&offset.to_le_bytes());
+            let packed = pack_literals(bmp);
+            data.extend_from_slice(&(bmp.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            data.extend_from_slice(&packed);
+        }
+
+        let archive = MgrArchive::from_data(data).unwrap();
+        assert_eq!(archive.entries.len(), 2);
+        assert_eq!(archive.image(0).unwrap().get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(archive.image(1).unwrap().get_pixel(2, 1).0, [0, 0, 255, 255]);
+        assert_eq!(
+            archive.entry_output_path(Path::new("out/EV01.png"), 1),
+            Path::new("out/EV01_001.png")
+        );
+    }
+}
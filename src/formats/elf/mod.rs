@@ -0,0 +1,60 @@
+//! Elf / Silky's engine format support
+//!
+//! Handles MGR image archives and (recognition only, for now) GPH images
+
+use std::path::Path;
+use anyhow::Result;
+use tracing::info;
+
+use crate::{DecodeConfig, DecodingState, FormatType};
+
+pub mod mgr;
+pub mod gph;
+
+pub use mgr::MgrArchive;
+pub use gph::GphImage;
+
+/// Decode MGR archive to specific file (`<stem>_NNN.<ext>` for multi-image archives)
+pub fn decode_mgr_direct(
+    input_path: &Path,
+    output_file: &Path,
+    config: &DecodeConfig,
+) -> Result<()> {
+    info!("Decoding MGR archive: {:?}", input_path);
+
+    let mgr = MgrArchive::open(input_path)?;
+
+    if config.step_by_step {
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        mgr.decode_with_steps(output_file, &mut state, config)?;
+        crate::trace::save_requested(config, FormatType::SilkyMgr, input_path, state)?;
+    } else {
+        mgr.decode(output_file, config)?;
+    }
+
+    if config.verbose {
+        info!("MGR archive contained {} images", mgr.entries.len());
+    }
+
+    Ok(())
+}
+
+/// Decode GPH image to specific file
+pub fn decode_gph_direct(
+    input_path: &Path,
+    output_file: &Path,
+    config: &DecodeConfig,
+) -> Result<()> {
+    info!("Decoding GPH image: {:?}", input_path);
+
+    let gph = GphImage::open(input_path)?;
+
+    if config.step_by_step {
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        gph.decode_with_steps(output_file, &mut state, config)?;
+    } else {
+        gph.decode(output_file, config)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Encode plain images into LF2
+//!
+//! `reencode` rebuilds a file from an export and its sidecar; `encode`
+//! starts from images that never had one (new sprites, repainted frames).
+//! Every frame is hardened and quantized with [`QuantizeOptions`], with
+//! palette index 0 as the transparent colour. With a shared palette
+//! (`--shared-palette`) one median-cut palette is computed over all frames
+//! and every frame is mapped onto it, so a character set stays consistent
+//! and frames can be palette-animated; each frame reports how far its
+//! pixels moved. Frames exported with `--sidecar` keep their on-screen
+//! offset, so a repainted sprite lands where the original did.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use super::bmp::BmpImage;
+use super::reencode::LF2_MAX_COLORS;
+use super::sidecar::{sidecar_path, ImageMetadata};
+use super::toheart::lf2::{Lf2Image, Rgb};
+use crate::quantize::{self, QuantizeOptions};
+
+/// Palette index of transparent pixels in encoded frames
+pub const TRANSPARENT_INDEX: u8 = 0;
+
+/// Opaque colours a frame palette can hold next to the transparent entry
+const OPAQUE_COLORS: usize = LF2_MAX_COLORS - 1;
+
+/// An input image, rows top first
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+    /// Screen position of the top-left pixel (the LF2 `x_offset`/`y_offset`)
+    pub offset: (u16, u16),
+}
+
+impl Frame {
+    /// Read a PNG, BMP or other image the image crate knows; 8/24-bit BMPs
+    /// go through [`BmpImage`]. The offset comes from a sidecar next to the
+    /// image when there is one.
+    pub fn open(path: &Path) -> Result<Self> {
+        let offset = sidecar_offset(path)?;
+        let is_bmp = crate::paths::extension_lower(path).as_deref() == Some("bmp");
+        if is_bmp {
+            let data = std::fs::read(path)?;
+            if BmpImage::supports(&data) {
+                let bmp = BmpImage::from_data(&data)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+                return Ok(Self { width: bmp.width, height: bmp.height, rgba: bmp.rgba, offset });
+            }
+        }
+        let image = image::open(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+            .to_rgba8();
+        Ok(Self { width: image.width(), height: image.height(), rgba: image.into_raw(), offset })
+    }
+
+    /// RGB of the pixels that stay opaque under `options`
+    fn opaque_colors(&self, options: &QuantizeOptions) -> Vec<[u8; 3]> {
+        options.harden_alpha(&self.rgba, self.width as usize)
+            .chunks_exact(4)
+            .filter(|px| px[3] != 0)
+            .map(|px| [px[0], px[1], px[2]])
+            .collect()
+    }
+}
+
+/// Offset recorded in the sidecar of an exported image, (0, 0) without one.
+/// A `--trim` export starts that far into the original canvas, so the trim
+/// origin is added.
+fn sidecar_offset(path: &Path) -> Result<(u16, u16)> {
+    let sidecar = sidecar_path(path);
+    if !sidecar.is_file() {
+        return Ok((0, 0));
+    }
+    let meta = ImageMetadata::load(&sidecar)?;
+    let (trim_x, trim_y) = meta.trim.map_or((0, 0), |rect| (rect.x, rect.y));
+    let x = meta.x_offset.unwrap_or(0) as u32 + trim_x;
+    let y = meta.y_offset.unwrap_or(0) as u32 + trim_y;
+    match (u16::try_from(x), u16::try_from(y)) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(anyhow!("{}: offset ({}, {}) does not fit LF2", sidecar.display(), x, y)),
+    }
+}
+
+/// How far quantization moved a frame's opaque pixels
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QuantizationError {
+    /// Opaque pixels whose colour changed
+    pub changed: usize,
+    /// Root mean square RGB distance over all opaque pixels
+    pub rmse: f64,
+}
+
+/// A frame mapped onto a palette
+#[derive(Debug, Clone)]
+pub struct EncodedFrame {
+    pub image: Lf2Image,
+    pub error: QuantizationError,
+}
+
+/// One palette of at most 254 opaque colours for all `frames`
+pub fn shared_palette(frames: &[Frame], options: &QuantizeOptions) -> Result<Vec<[u8; 3]>> {
+    let histogram = quantize::color_histogram(frames.iter().flat_map(|f| f.opaque_colors(options)));
+    quantize::fit_palette(&histogram, OPAQUE_COLORS, options.auto_quantize, "a shared LF2 palette")
+}
+
+/// Map `frame` onto `palette` (opaque colours; the transparent entry is
+/// added in front) by exact match or nearest colour
+pub fn encode_with_palette(frame: &Frame, palette: &[[u8; 3]], options: &QuantizeOptions) -> Result<EncodedFrame> {
+    let (Ok(width), Ok(height)) = (u16::try_from(frame.width), u16::try_from(frame.height)) else {
+        return Err(anyhow!("{}x{} is too large for LF2", frame.width, frame.height));
+    };
+    let rgba = options.harden_alpha(&frame.rgba, frame.width as usize);
+
+    let mut pixels = Vec::with_capacity(rgba.len() / 4);
+    let (mut changed, mut squared, mut opaque) = (0, 0u64, 0u64);
+    for px in rgba.chunks_exact(4) {
+        if px[3] == 0 {
+            pixels.push(TRANSPARENT_INDEX);
+            continue;
+        }
+        let color = [px[0], px[1], px[2]];
+        let index = quantize::nearest(palette, color, None);
+        let entry = palette.get(index).ok_or_else(|| anyhow!("Opaque pixels need a non-empty palette"))?;
+        let distance: u64 = (0..3).map(|ch| (entry[ch] as i64 - color[ch] as i64).pow(2) as u64).sum();
+        if distance > 0 {
+            changed += 1;
+            squared += distance;
+        }
+        opaque += 1;
+        pixels.push(index as u8 + 1);
+    }
+
+    let palette: Vec<Rgb> = std::iter::once(Rgb { r: 0, g: 0, b: 0 })
+        .chain(palette.iter().map(|&[r, g, b]| Rgb { r, g, b }))
+        .collect();
+    let rmse = if opaque == 0 { 0.0 } else { (squared as f64 / opaque as f64).sqrt() };
+    Ok(EncodedFrame {
+        image: Lf2Image {
+            width,
+            height,
+            x_offset: frame.offset.0,
+            y_offset: frame.offset.1,
+            transparent_color: TRANSPARENT_INDEX,
+            color_count: palette.len() as u8,
+            palette,
+            pixels,
+        },
+        error: QuantizationError { changed, rmse },
+    })
+}
+
+/// Quantize every frame, against one palette computed over all of them when
+/// `shared` is set, each against its own otherwise
+pub fn encode_frames(frames: &[Frame], options: &QuantizeOptions, shared: bool) -> Result<Vec<EncodedFrame>> {
+    if shared {
+        let palette = shared_palette(frames, options)?;
+        return frames.iter().map(|f| encode_with_palette(f, &palette, options)).collect();
+    }
+    frames.iter()
+        .map(|f| {
+            let histogram = quantize::color_histogram(f.opaque_colors(options));
+            let palette = quantize::fit_palette(&histogram, OPAQUE_COLORS, options.auto_quantize, "LF2")?;
+            encode_with_palette(f, &palette, options)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(colors: &[[u8; 4]]) -> Frame {
+        Frame { width: colors.len() as u32, height: 1, rgba: colors.concat(), offset: (0, 0) }
+    }
+
+    #[test]
+    fn shared_palette_covers_every_frame() {
+        let frames = [
+            frame(&[[255, 0, 0, 255], [0, 0, 0, 0], [0, 0, 255, 255]]),
+            frame(&[[0, 0, 255, 255], [0, 255, 0, 255], [255, 0, 0, 255]]),
+        ];
+        let options = QuantizeOptions::default();
+        let encoded = encode_frames(&frames, &options, true).unwrap();
+        let palette = &encoded[0].image.palette;
+        assert_eq!(palette.len(), 4);
+        assert!(encoded.iter().all(|e| e.image.palette.len() == 4 && e.error == QuantizationError::default()));
+        // Same colour, same index in both frames
+        assert_eq!(encoded[0].image.pixels[0], encoded[1].image.pixels[2]);
+        assert_eq!(encoded[0].image.pixels[1], TRANSPARENT_INDEX);
+        let bytes = encoded[1].image.to_lf2_bytes().unwrap();
+        assert_eq!(Lf2Image::from_data(&bytes).unwrap().pixels, encoded[1].image.pixels);
+
+        // Separate palettes only hold each frame's own colours
+        let separate = encode_frames(&frames, &options, false).unwrap();
+        assert_eq!(separate[0].image.palette.len(), 3);
+
+        // Mapped onto a palette without green and with shifted red/blue
+        let palette = [[250, 0, 0], [0, 0, 250]];
+        let moved = encode_with_palette(&frames[1], &palette, &options).unwrap();
+        assert_eq!(moved.error.changed, 3);
+        assert!(moved.error.rmse > 100.0);
+    }
+
+    #[test]
+    fn sidecar_offsets_survive_export_and_encode() {
+        use crate::trim::TrimRect;
+
+        let dir = tempfile::tempdir().unwrap();
+        let original = Lf2Image {
+            width: 3,
+            height: 2,
+            x_offset: 120,
+            y_offset: 40,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 200, g: 10, b: 10 }],
+            pixels: vec![0, 1, 1, 0, 1, 0],
+        };
+        let source = dir.path().join("C0101.LF2");
+        let data = original.to_lf2_bytes().unwrap();
+        std::fs::write(&source, &data).unwrap();
+
+        let png = dir.path().join("C0101.png");
+        original.to_rgba_image(crate::decoder::Orientation::Display).save(&png).unwrap();
+        let mut meta = ImageMetadata::from_lf2(&source, &data, crate::FormatType::ToHeartLf2, &original);
+        meta.save(&sidecar_path(&png)).unwrap();
+
+        let frame = Frame::open(&png).unwrap();
+        assert_eq!(frame.offset, (120, 40));
+        let encoded = encode_frames(&[frame], &QuantizeOptions::default(), false).unwrap();
+        let decoded = Lf2Image::from_data(&encoded[0].image.to_lf2_bytes().unwrap()).unwrap();
+        assert_eq!((decoded.x_offset, decoded.y_offset), (120, 40));
+
+        // A trimmed export sits further into the canvas
+        meta.trim = Some(TrimRect { x: 1, y: 1, width: 2, height: 1 });
+        meta.save(&sidecar_path(&png)).unwrap();
+        assert_eq!(Frame::open(&png).unwrap().offset, (121, 41));
+
+        // Without a sidecar frames start at the origin, unless placed by hand
+        std::fs::remove_file(sidecar_path(&png)).unwrap();
+        assert_eq!(Frame::open(&png).unwrap().offset, (0, 0));
+        assert_eq!(original.with_offset(7, 9).x_offset, 7);
+    }
+}
@@ -0,0 +1,276 @@
+//! Header fields of every supported format, read without decoding
+//!
+//! `inspect` prints these as text or, with `--json`, one [`HeaderReport`]
+//! per line. Formats with a fixed header take their fields from the
+//! [`magic`](super::magic) layouts, so the report names and offsets match
+//! the `export-spec` templates; archives and composite G00 files add their
+//! file or region table as `entries`.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::FormatType;
+use super::magic::{self, FormatLayout};
+
+/// One header value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeaderField {
+    pub name: String,
+    /// File offset of the value, if it is stored at one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    pub value: Value,
+}
+
+/// Header of one file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeaderReport {
+    pub format: FormatType,
+    pub file_size: u64,
+    pub fields: Vec<HeaderField>,
+    /// File table of archives, region table of composite G00 images
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<Vec<HeaderField>>,
+}
+
+impl HeaderReport {
+    /// Image size from the header fields, where the format stores one
+    pub fn dimensions(&self) -> Option<(u64, u64)> {
+        let field = |name: &str| self.fields.iter().find(|f| f.name == name).and_then(|f| f.value.as_u64());
+        if let (Some(width), Some(height)) = (field("width"), field("height")) {
+            return Some((width, height));
+        }
+        // MAG stores the inclusive corner coordinates
+        let (x0, y0, x1, y1) = (field("x0")?, field("y0")?, field("x1")?, field("y1")?);
+        Some((x1.checked_sub(x0)? + 1, y1.checked_sub(y0)? + 1))
+    }
+}
+
+fn stored(name: &str, offset: usize, value: impl Into<Value>) -> HeaderField {
+    HeaderField { name: name.to_string(), offset: Some(offset), value: value.into() }
+}
+
+fn derived(name: &str, value: impl Into<Value>) -> HeaderField {
+    HeaderField { name: name.to_string(), offset: None, value: value.into() }
+}
+
+/// `layout`'s fields read from the header at `base`, numbers as integers
+/// and `magic` as text
+fn layout_fields(layout: &FormatLayout, data: &[u8], base: usize) -> Result<Vec<HeaderField>> {
+    layout.fields.iter()
+        .map(|f| {
+            let offset = base + f.offset;
+            let bytes = data.get(offset..offset + f.size)
+                .ok_or_else(|| anyhow!("{} header truncated at {}", layout.name, f.name))?;
+            let value: Value = match f.size {
+                1 | 2 | 4 if f.name != "magic" => {
+                    let fold = |acc: u64, &b: &u8| (acc << 8) | b as u64;
+                    if layout.big_endian {
+                        bytes.iter().fold(0, fold).into()
+                    } else {
+                        bytes.iter().rev().fold(0, fold).into()
+                    }
+                }
+                _ => String::from_utf8_lossy(bytes).trim_end_matches('\0').trim_end().into(),
+            };
+            Ok(HeaderField { name: f.name.to_string(), offset: Some(offset), value })
+        })
+        .collect()
+}
+
+/// Read the header of the file at `path`, detecting the format by extension
+/// or, failing that, by content
+pub fn inspect(path: &Path) -> Result<HeaderReport> {
+    let format = FormatType::detect(path)?;
+    let file_size = std::fs::metadata(path)?.len();
+    if format == FormatType::ToHeartPak {
+        // Only the header and the table at the end are read
+        let pak = super::toheart::PakArchive::open(path)?;
+        let (count, kind, table) = pak.info();
+        let fields = vec![
+            stored("magic", 0, "LEAFPACK"),
+            stored("file_count", 8, count),
+            derived("archive_type", format!("{:?}", kind)),
+        ];
+        let entries = table.iter()
+            .map(|e| vec![derived("name", e.name.as_str()), derived("position", e.position), derived("length", e.length)])
+            .collect();
+        return Ok(HeaderReport { format, file_size, fields, entries });
+    }
+    let data = std::fs::read(path)?;
+    let (fields, entries) = parse(&format, &data)?;
+    Ok(HeaderReport { format, file_size, fields, entries })
+}
+
+/// Header fields and table of `data` in `format` (anything but PAK, whose
+/// table needs the archive file)
+pub fn parse(format: &FormatType, data: &[u8]) -> Result<(Vec<HeaderField>, Vec<Vec<HeaderField>>)> {
+    let mut entries = Vec::new();
+    let fields = match format {
+        FormatType::ToHeartLf2 | FormatType::ToHeartScn => {
+            if !data.starts_with(magic::LF2_MAGIC) {
+                return Err(anyhow!("Invalid LF2 magic number"));
+            }
+            let mut fields = layout_fields(&magic::LF2, data, 0)?;
+            let colors = data[0x16] as usize;
+            fields.push(derived("palette_offset", magic::LF2_HEADER_SIZE));
+            fields.push(derived("pixel_offset", magic::LF2_HEADER_SIZE + colors * 3));
+            fields
+        }
+        FormatType::KanonPdt => {
+            if !data.starts_with(magic::PDT_MAGIC) {
+                return Err(anyhow!("Invalid PDT magic number"));
+            }
+            let layout = super::kanon::pdt::PdtLayout::detect(data);
+            let header_size = layout.header_size();
+            let mut fields = layout_fields(&magic::PDT, data, 0)?;
+            fields.retain(|f| f.offset.is_some_and(|o| o < header_size));
+            fields.push(derived("layout", match layout {
+                super::kanon::pdt::PdtLayout::Standard => "standard",
+                super::kanon::pdt::PdtLayout::Legacy => "legacy",
+            }));
+            fields.push(derived("rgb_offset", header_size));
+            fields
+        }
+        FormatType::KanonG00 => {
+            let header = super::kanon::g00::G00Header::parse(data)?;
+            entries = header.regions.iter()
+                .map(|r| vec![
+                    derived("x1", r.x1), derived("y1", r.y1), derived("x2", r.x2), derived("y2", r.y2),
+                    derived("origin_x", r.origin_x), derived("origin_y", r.origin_y),
+                ])
+                .collect();
+            vec![
+                stored("type", 0, header.kind),
+                stored("width", 1, header.width),
+                stored("height", 3, header.height),
+                derived("region_count", header.regions.len()),
+                stored("compressed_size", header.data_offset - 8, header.compressed_size),
+                stored("uncompressed_size", header.data_offset - 4, header.uncompressed_size),
+                derived("data_offset", header.data_offset),
+            ]
+        }
+        FormatType::SilkyMgr => {
+            let archive = super::elf::MgrArchive::from_data(data.to_vec())?;
+            entries = archive.entries.iter()
+                .map(|e| vec![
+                    derived("offset", e.offset),
+                    derived("packed_size", e.packed_size),
+                    derived("unpacked_size", e.unpacked_size),
+                ])
+                .collect();
+            vec![stored("entry_count", 0, archive.entries.len())]
+        }
+        FormatType::Pc98Mag => {
+            let (comment, base) = super::pc98::MagImage::locate_header(data)?;
+            let mut fields = vec![stored("magic", 0, "MAKI02"), derived("comment", comment), derived("header_offset", base)];
+            fields.extend(layout_fields(&magic::MAG, data, base)?);
+            fields
+        }
+        FormatType::Pc98Pi => {
+            let header = super::pc98::pi::PiHeader::parse(data)?;
+            vec![
+                stored("magic", 0, "Pi"),
+                derived("comment", header.comment),
+                derived("mode", header.mode),
+                derived("aspect", format!("{}:{}", header.aspect.0, header.aspect.1)),
+                derived("plane_bits", header.plane_bits),
+                derived("machine", String::from_utf8_lossy(&header.machine).into_owned()),
+                derived("width", header.width),
+                derived("height", header.height),
+                derived("palette_size", header.palette.as_ref().map_or(0, Vec::len)),
+                derived("data_offset", header.data_offset),
+            ]
+        }
+        FormatType::ElfGph => {
+            // Layout unknown: show the leading bytes for comparison
+            let head: Vec<String> = data.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+            vec![stored("leading_bytes", 0, head.join(" "))]
+        }
+        FormatType::ToHeartPak => return Err(anyhow!("PAK headers are read from the archive file")),
+        FormatType::Pc98Planar => return Err(anyhow!("Planar screens have no header")),
+    };
+    Ok((fields, entries))
+}
+
+impl HeaderReport {
+    /// Human-readable dump, one field per line
+    pub fn print_text(&self, path: &Path) {
+        println!("{}: {}, {} bytes", path.display(), self.format, self.file_size);
+        for field in &self.fields {
+            let offset = field.offset.map_or(String::new(), |o| format!("{:#06x}", o));
+            println!("  {:<20} {:>8}  {}", field.name, offset, display(&field.value));
+        }
+        if !self.entries.is_empty() {
+            println!("  entries ({}):", self.entries.len());
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            let values: Vec<String> = entry.iter().map(|f| format!("{}={}", f.name, display(&f.value))).collect();
+            println!("    [{}] {}", i, values.join(" "));
+        }
+    }
+}
+
+/// Strings without JSON quotes
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value<'a>(fields: &'a [HeaderField], name: &str) -> &'a Value {
+        &fields.iter().find(|f| f.name == name).unwrap().value
+    }
+
+    #[test]
+    fn reads_headers_without_decoding() {
+        use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+        let lf2 = Lf2Image {
+            width: 3,
+            height: 2,
+            x_offset: 7,
+            y_offset: 9,
+            transparent_color: 1,
+            color_count: 2,
+            palette: vec![Rgb { r: 1, g: 2, b: 3 }; 2],
+            pixels: vec![0, 1, 0, 1, 0, 1],
+        };
+        let (fields, entries) = parse(&FormatType::ToHeartLf2, &lf2.to_lf2_bytes().unwrap()).unwrap();
+        assert_eq!(value(&fields, "magic"), "LEAF256");
+        assert_eq!(value(&fields, "width"), 3);
+        assert_eq!(value(&fields, "transparent_color"), 1);
+        assert_eq!(value(&fields, "pixel_offset"), 0x18 + 6);
+        assert!(entries.is_empty());
+
+        // Type 2 G00 with one region
+        let mut g00 = vec![2, 0x80, 0x02, 0xe0, 0x01, 1, 0, 0, 0];
+        for v in [10u32, 20, 109, 119, 5, 6, 100, 400] {
+            g00.extend_from_slice(&v.to_le_bytes());
+        }
+        let (fields, entries) = parse(&FormatType::KanonG00, &g00).unwrap();
+        assert_eq!((value(&fields, "width"), value(&fields, "height")), (&Value::from(640), &Value::from(480)));
+        assert_eq!(value(&fields, "compressed_size"), 100);
+        assert_eq!(value(&fields, "data_offset"), g00.len());
+        assert_eq!(value(&entries[0], "x2"), 109);
+        assert!(parse(&FormatType::KanonG00, &g00[..20]).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let pak = dir.path().join("TEST.PAK");
+        let files: Vec<(String, Vec<u8>)> = (0..3).map(|i| (format!("F{}.LF2", i), vec![i; 4])).collect();
+        std::fs::write(&pak, crate::formats::toheart::pak::write_archive(&files, &[7; 11]).unwrap()).unwrap();
+        let report = inspect(&pak).unwrap();
+        assert_eq!(value(&report.fields, "file_count"), 3);
+        assert_eq!(value(&report.entries[2], "name"), "F2.LF2");
+        assert_eq!(value(&report.entries[1], "position"), 14);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["format"], "ToHeartPak");
+        assert_eq!(json["fields"][0]["offset"], 0);
+    }
+}
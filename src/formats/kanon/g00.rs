@@ -1,11 +1,91 @@
 //! Kanon G00 image format implementation
 //! TODO: Format specification needs analysis - placeholder implementation
+//!
+//! Only the header is read ([`G00Header`], for `inspect`), following the
+//! RealLive layout: a type byte, u16 width and height, for type 2 a table of
+//! regions (six u32 each: x1, y1, x2, y2, origin x and y), then the
+//! compressed and uncompressed sizes of the LZ stream. Still to be checked
+//! against sample files.
 
 use std::path::Path;
 use anyhow::{Result, anyhow};
 
 use crate::{DecodeConfig, DecodingState};
 
+/// Bytes per region of a type 2 G00
+pub const G00_REGION_SIZE: usize = 24;
+
+/// One region of a type 2 (composite) G00
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct G00Region {
+    pub x1: u32,
+    pub y1: u32,
+    pub x2: u32,
+    pub y2: u32,
+    pub origin_x: u32,
+    pub origin_y: u32,
+}
+
+/// Parsed G00 header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct G00Header {
+    /// 0: 24-bit, 1: 8-bit paletted, 2: composite of regions
+    pub kind: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Region table of type 2 files, empty otherwise
+    pub regions: Vec<G00Region>,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    /// Offset of the compressed stream
+    pub data_offset: usize,
+}
+
+impl G00Header {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let u32_at = |o: usize| -> Result<u32> {
+            data.get(o..o + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| anyhow!("G00 header truncated"))
+        };
+        if data.len() < 5 {
+            return Err(anyhow!("G00 header truncated"));
+        }
+        let kind = data[0];
+        let width = u16::from_le_bytes([data[1], data[2]]);
+        let height = u16::from_le_bytes([data[3], data[4]]);
+
+        let mut pos = 5;
+        let mut regions = Vec::new();
+        match kind {
+            0 | 1 => {}
+            2 => {
+                let count = u32_at(pos)? as usize;
+                pos += 4;
+                if count.saturating_mul(G00_REGION_SIZE) > data.len() - pos {
+                    return Err(anyhow!("G00 region table ({} regions) exceeds file size", count));
+                }
+                for i in 0..count {
+                    let r = pos + i * G00_REGION_SIZE;
+                    regions.push(G00Region {
+                        x1: u32_at(r)?,
+                        y1: u32_at(r + 4)?,
+                        x2: u32_at(r + 8)?,
+                        y2: u32_at(r + 12)?,
+                        origin_x: u32_at(r + 16)?,
+                        origin_y: u32_at(r + 20)?,
+                    });
+                }
+                pos += count * G00_REGION_SIZE;
+            }
+            _ => return Err(anyhow!("Unknown G00 type {}", kind)),
+        }
+        let compressed_size = u32_at(pos)?;
+        let uncompressed_size = u32_at(pos + 4)?;
+        Ok(Self { kind, width, height, regions, compressed_size, uncompressed_size, data_offset: pos + 8 })
+    }
+}
+
 /// G00 image structure (placeholder)
 pub struct G00Image {
     pub width: u32,
@@ -6,7 +6,7 @@ use std::path::Path;
 use anyhow::Result;
 use tracing::{info, debug};
 
-use crate::{DecodeConfig, DecodingState};
+use crate::{DecodeConfig, DecodingState, FormatType};
 
 pub mod pdt;
 pub mod g00;
@@ -20,9 +20,7 @@ pub fn decode_pdt(
     output_path: &Path,
     config: &DecodeConfig,
 ) -> Result<()> {
-    let output_file = output_path.join(
-        input_path.file_stem().unwrap_or_default()
-    ).with_extension("bmp");
+    let output_file = crate::paths::output_file_for(output_path, input_path, "bmp")?;
     
     decode_pdt_direct(input_path, &output_file, config)
 }
@@ -35,10 +33,18 @@ pub fn decode_pdt_direct(
 ) -> Result<()> {
     info!("Decoding PDT image: {:?}", input_path);
     
-    let pdt = PdtImage::open(input_path)?;
+    let data = std::fs::read(input_path)?;
+    let (width, height) = PdtImage::dimensions(&data)?;
+    config.limits.check_image(&FormatType::KanonPdt, width as u64, height as u64)?;
+    
+    if config.low_memory && !config.step_by_step {
+        return pdt::stream_to_file(&data, output_file, config);
+    }
+    
+    let pdt = crate::progress::with_callback(config.progress.as_ref(), || PdtImage::from_data(&data))?;
     
     if config.step_by_step {
-        let mut state = DecodingState::new();
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
         pdt.decode_with_steps(output_file, &mut state, config)?;
         
         if config.verbose {
@@ -49,6 +55,7 @@ pub fn decode_pdt_direct(
                     .unwrap_or(0.0)
             );
         }
+        crate::trace::save_requested(config, FormatType::KanonPdt, input_path, state)?;
     } else {
         pdt.decode(output_file, config)?;
     }
@@ -62,9 +69,7 @@ pub fn decode_g00(
     output_path: &Path,
     config: &DecodeConfig,
 ) -> Result<()> {
-    let output_file = output_path.join(
-        input_path.file_stem().unwrap_or_default()
-    ).with_extension("bmp");
+    let output_file = crate::paths::output_file_for(output_path, input_path, "bmp")?;
     
     decode_g00_direct(input_path, &output_file, config)
 }
@@ -80,7 +85,7 @@ pub fn decode_g00_direct(
     let g00 = G00Image::open(input_path)?;
     
     if config.step_by_step {
-        let mut state = DecodingState::new();
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
         g00.decode_with_steps(output_file, &mut state, config)?;
         
         if config.verbose {
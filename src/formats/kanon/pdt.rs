@@ -6,9 +6,9 @@ use anyhow::{Result, anyhow};
 use tracing::debug;
 
 use crate::{DecodeConfig, DecodingState, DecodeStep};
-
-/// Magic number for PDT format
-const PDT_MAGIC: &[u8] = b"PDT10\0\0\0";
+use crate::formats::common::{BitFlagReader, BitFlagWriter, Polarity};
+use crate::formats::magic::{PDT_HEADER_SIZE, PDT_LEGACY_HEADER_SIZE, PDT_MAGIC};
+use crate::lzss::BitOrder;
 
 /// 24-bit RGB color
 #[derive(Debug, Clone, Copy, Default)]
@@ -18,11 +18,51 @@ pub struct RgbColor {
     pub b: u8,
 }
 
+/// PDT10 header layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PdtLayout {
+    /// 32-byte header with the mask offset at 0x1c
+    #[default]
+    Standard,
+    /// 28-byte header without a mask offset field (early releases);
+    /// pixel data starts at 0x1c and there is no alpha mask
+    Legacy,
+}
+
+impl PdtLayout {
+    /// Header size in bytes (= offset of the RGB stream)
+    pub fn header_size(self) -> usize {
+        match self {
+            PdtLayout::Standard => PDT_HEADER_SIZE,
+            PdtLayout::Legacy => PDT_LEGACY_HEADER_SIZE,
+        }
+    }
+
+    /// Tell the layouts apart by whether 0x1c holds a usable mask offset.
+    ///
+    /// In legacy files those bytes are the start of the RGB stream (a flag
+    /// byte followed by BGR data), which practically never forms an offset
+    /// pointing past the header and inside the file.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.len() < 32 {
+            return PdtLayout::Legacy;
+        }
+        let mask_offset = u32::from_le_bytes([data[28], data[29], data[30], data[31]]) as usize;
+        if mask_offset == 0 || (32..data.len()).contains(&mask_offset) {
+            PdtLayout::Standard
+        } else {
+            PdtLayout::Legacy
+        }
+    }
+}
+
 /// PDT image structure
 pub struct PdtImage {
     pub width: u32,
     pub height: u32,
     pub file_length: u32,
+    pub layout: PdtLayout,
+    /// Alpha mask offset (always 0 for [`PdtLayout::Legacy`])
     pub mask_offset: u32,
     pub pixels: Vec<RgbColor>,
     pub alpha_mask: Vec<u8>,
@@ -36,209 +76,167 @@ impl PdtImage {
     }
     
     /// Parse PDT from byte data (optimized)
+    #[tracing::instrument(level = "trace", skip_all)]
     pub fn from_data(data: &[u8]) -> Result<Self> {
-        if data.len() < 32 {
-            return Err(anyhow!("PDT file too small"));
-        }
+        let mut image = Self::from_header(data)?;
+        let (width, height) = (image.width, image.height);
         
-        // Check magic number
-        if &data[0..8] != PDT_MAGIC {
+        crate::timing::time(crate::timing::Phase::Decompress, || -> Result<()> {
+            // Decompress RGB data following the header
+            image.pixels = Self::decompress_rgb_lzss(&data[image.layout.header_size()..], width, height)?;
+            
+            // Decompress alpha mask if present
+            image.alpha_mask = match image.mask_data(data) {
+                Some(mask) => Self::decompress_alpha_lzss(mask, width, height)?,
+                None => vec![255u8; (width * height) as usize], // Fully opaque
+            };
+            Ok(())
+        })?;
+        crate::timing::image(width, height, || image.alpha_mask.iter().filter(|&&alpha| alpha < 255).count());
+        
+        Ok(image)
+    }
+    
+    /// Image size from the header alone, without decompressing
+    pub fn dimensions(data: &[u8]) -> Result<(u32, u32)> {
+        let header = Self::from_header(data)?;
+        Ok((header.width, header.height))
+    }
+
+    /// Header fields only; `pixels` and `alpha_mask` are left empty
+    fn from_header(data: &[u8]) -> Result<Self> {
+        // Check magic number; other PDT revisions (PDT11) share the prefix
+        let magic_len = data.len().min(PDT_MAGIC.len());
+        if data[..magic_len] != PDT_MAGIC[..magic_len] {
+            if data.len() >= 5 && &data[..3] == b"PDT" {
+                let version = String::from_utf8_lossy(&data[..5]).into_owned();
+                return Err(crate::decoder::Error::UnsupportedVersion { format: crate::FormatType::KanonPdt, version }.into());
+            }
             return Err(anyhow!("Invalid PDT magic number"));
         }
+        crate::decoder::check_length(&crate::FormatType::KanonPdt, data, PdtLayout::Legacy.header_size())?;
         
         // Parse header using direct memory access
         let file_length = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
         let width = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
         let height = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
-        let mask_offset = u32::from_le_bytes([data[28], data[29], data[30], data[31]]);
-        
-        debug!("PDT: {}x{}, length: {}, mask_offset: {}", width, height, file_length, mask_offset);
-        
-        // Decompress RGB data starting at offset 32
-        let pixels = Self::decompress_rgb_lzss(&data[32..], width, height)?;
-        
-        // Decompress alpha mask if present
-        let alpha_mask = if mask_offset > 0 && (mask_offset as usize) < data.len() {
-            Self::decompress_alpha_lzss(&data[mask_offset as usize..], width, height)?
-        } else {
-            vec![255u8; (width * height) as usize] // Fully opaque
+        let layout = PdtLayout::detect(data);
+        let mask_offset = match layout {
+            PdtLayout::Standard => u32::from_le_bytes([data[28], data[29], data[30], data[31]]),
+            PdtLayout::Legacy => 0,
         };
         
+        debug!("PDT: {}x{}, length: {}, layout: {:?}, mask_offset: {}", width, height, file_length, layout, mask_offset);
+        crate::decoder::check_dimensions(&crate::FormatType::KanonPdt, width as u64, height as u64)?;
+        
         Ok(Self {
             width,
             height,
             file_length,
+            layout,
             mask_offset,
-            pixels,
-            alpha_mask,
+            pixels: Vec::new(),
+            alpha_mask: Vec::new(),
         })
     }
     
-    /// Simple RGB LZSS decompression
-    fn decompress_rgb_lzss(compressed_data: &[u8], width: u32, height: u32) -> Result<Vec<RgbColor>> {
-        let total_pixels = (width * height) as usize;
-        let mut ring_buffer = [RgbColor::default(); 0x1000]; // 4KB ring buffer
-        let mut ring_pos = 0usize;
-        let mut pixels = vec![RgbColor::default(); total_pixels];
-        let mut pixel_idx = 0;
+    /// The alpha mask stream of `data`, if the header points at one
+    fn mask_data<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let offset = self.mask_offset as usize;
+        (offset > 0).then(|| data.get(offset..)).flatten().filter(|mask| !mask.is_empty())
+    }
+    
+    /// Serialize as PDT10 using literal-only LZSS (no back-references).
+    ///
+    /// Meant for synthetic fixtures: the output decodes with the regular
+    /// reader but is larger than the original encoder's. The legacy layout
+    /// has no mask, so `alpha_mask` is dropped for it.
+    pub fn to_pdt_bytes(&self, layout: PdtLayout) -> Vec<u8> {
+        let mut rgb_stream = BitFlagWriter::new(BitOrder::MsbFirst, Polarity::Normal);
+        for c in &self.pixels {
+            rgb_stream.push_flag(true);
+            rgb_stream.extend_from_slice(&[c.b, c.g, c.r]);
+        }
+        // Every flag byte 0xff, as the original literal-only writer emits
+        let rgb_stream = rgb_stream.finish_padded(true);
         
-        let mut data_pos = 0;
-        let mut flag = 0u8;
-        let mut flag_count = 0;
+        let mut data = Vec::with_capacity(layout.header_size() + rgb_stream.len());
+        data.extend_from_slice(PDT_MAGIC);
+        data.extend_from_slice(&[0; 4]); // file length, patched below
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        if layout == PdtLayout::Standard {
+            data.extend_from_slice(&[0; 4]); // mask offset, patched below
+        }
+        data.extend_from_slice(&rgb_stream);
         
-        while pixel_idx < total_pixels && data_pos < compressed_data.len() {
-            // Read flag byte every 8 operations
-            if flag_count == 0 {
-                if data_pos >= compressed_data.len() {
-                    break;
-                }
-                flag = compressed_data[data_pos];
-                data_pos += 1;
-                flag_count = 8;
+        if layout == PdtLayout::Standard && self.alpha_mask.iter().any(|&a| a != 255) {
+            let mask_offset = data.len() as u32;
+            data[28..32].copy_from_slice(&mask_offset.to_le_bytes());
+            let mut mask_stream = BitFlagWriter::new(BitOrder::MsbFirst, Polarity::Normal);
+            for &a in &self.alpha_mask {
+                mask_stream.push_flag(true);
+                mask_stream.push(a);
             }
-            
-            if (flag & 0x80) != 0 {
-                // Direct RGB pixel (3 bytes) - BGR order in file
-                if data_pos + 2 >= compressed_data.len() {
-                    break;
-                }
-                
-                let color = RgbColor {
-                    b: compressed_data[data_pos],
-                    g: compressed_data[data_pos + 1],
-                    r: compressed_data[data_pos + 2],
-                };
-                data_pos += 3;
-                
-                // Store in ring buffer and output
-                ring_buffer[ring_pos] = color;
-                ring_pos = (ring_pos + 1) & 0x0fff;
-                pixels[pixel_idx] = color;
-                pixel_idx += 1;
-            } else {
-                // Reference to ring buffer (2 bytes)
-                if data_pos + 1 >= compressed_data.len() {
-                    break;
-                }
-                
-                let word = u16::from_le_bytes([compressed_data[data_pos], compressed_data[data_pos + 1]]);
-                data_pos += 2;
-                
-                let copy_length = ((word & 0x0f) as usize) + 1;
-                let copy_position = ((word >> 4) as usize) & 0x0fff;
-                let mut back_pos = (ring_pos.wrapping_sub(copy_position).wrapping_sub(1)) & 0x0fff;
-                
-                // Copy from ring buffer
-                for _ in 0..copy_length {
-                    if pixel_idx >= total_pixels {
-                        break;
-                    }
-                    
-                    let color = ring_buffer[back_pos];
-                    ring_buffer[ring_pos] = color;
-                    ring_pos = (ring_pos + 1) & 0x0fff;
-                    back_pos = (back_pos + 1) & 0x0fff;
-                    pixels[pixel_idx] = color;
-                    pixel_idx += 1;
-                }
-            }
-            
-            flag <<= 1;
-            flag_count -= 1;
+            data.extend_from_slice(&mask_stream.finish_padded(true));
         }
         
-        Ok(pixels)
+        let file_length = data.len() as u32;
+        data[8..12].copy_from_slice(&file_length.to_le_bytes());
+        data
     }
     
-    /// Alpha mask decompression (single byte per pixel)
-    fn decompress_alpha_lzss(compressed_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    /// Simple RGB LZSS decompression; a truncated stream leaves the rest black
+    fn decompress_rgb_lzss(compressed_data: &[u8], width: u32, height: u32) -> Result<Vec<RgbColor>> {
         let total_pixels = (width * height) as usize;
-        let mut ring_buffer = [0u8; 0x1000];
-        let mut ring_pos = 0usize;
-        
-        let mut pixels = Vec::with_capacity(total_pixels);
-        let mut data_pos = 0;
-        let mut flag = 0u8;
-        let mut flag_count = 0;
-        
-        while pixels.len() < total_pixels && data_pos < compressed_data.len() {
-            if flag_count == 0 {
-                if data_pos >= compressed_data.len() {
-                    break;
-                }
-                flag = compressed_data[data_pos];
-                data_pos += 1;
-                flag_count = 8;
-            }
-            
-            if (flag & 0x80) != 0 {
-                // Direct alpha value
-                if data_pos >= compressed_data.len() {
-                    break;
-                }
-                
-                let alpha = compressed_data[data_pos];
-                data_pos += 1;
-                
-                ring_buffer[ring_pos] = alpha;
-                ring_pos = (ring_pos + 1) & 0x0fff;
-                pixels.push(alpha);
-            } else {
-                // Reference to ring buffer
-                if data_pos + 1 >= compressed_data.len() {
-                    break;
-                }
-                
-                let word = u16::from_le_bytes([compressed_data[data_pos], compressed_data[data_pos + 1]]);
-                data_pos += 2;
-                
-                let length = ((word & 0xff) as usize) + 2; // Different from RGB version!
-                let position = ((word >> 8) as usize) & 0x0fff;
-                let back_offset = (ring_pos as isize - position as isize - 1) & 0x0fff;
-                
-                for i in 0..length {
-                    if pixels.len() >= total_pixels {
-                        break;
-                    }
-                    
-                    let src_pos = (back_offset as usize + i) & 0x0fff;
-                    let alpha = ring_buffer[src_pos];
-                    
-                    ring_buffer[ring_pos] = alpha;
-                    ring_pos = (ring_pos + 1) & 0x0fff;
-                    pixels.push(alpha);
-                }
+        let mut stream = RgbStream::new(compressed_data).take(total_pixels);
+        let mut pixels: Vec<RgbColor> = Vec::with_capacity(total_pixels);
+        // One row at a time, reporting progress in between
+        loop {
+            let before = pixels.len();
+            pixels.extend(stream.by_ref().take(width as usize));
+            if pixels.len() == before {
+                break;
             }
-            
-            flag <<= 1;
-            flag_count -= 1;
+            crate::progress::tick(crate::progress::ProgressUnit::Pixels, pixels.len() as u64, total_pixels as u64);
         }
-        
+        pixels.resize(total_pixels, RgbColor::default());
         Ok(pixels)
     }
     
+    /// Alpha mask decompression (single byte per pixel); a truncated stream
+    /// gives a shorter mask
+    fn decompress_alpha_lzss(compressed_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        let total_pixels = (width * height) as usize;
+        Ok(AlphaStream::new(compressed_data).take(total_pixels).collect())
+    }
+    
     /// Save in multiple formats based on extension (like LF2)
     pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        config.limits.check_image(&crate::FormatType::KanonPdt, self.width as u64, self.height as u64)?;
         // Skip file output for benchmark mode
         if config.no_output {
             return Ok(());
         }
         
-        let extension = output_path.extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("bmp")
-            .to_lowercase();
+        let extension = crate::paths::extension_lower(output_path)
+            .unwrap_or_else(|| "bmp".to_string());
             
         match extension.as_str() {
             "png" => self.save_as_png(output_path, config),
             "raw" => self.save_as_raw_rgb(output_path, config),
             "rgba" => self.save_as_raw_rgba(output_path, config),
+            "rgb565" => self.save_as_rgb565(output_path, config),
+            ext if crate::output::registered_writer(ext).is_some() => {
+                crate::output::write_rgba_image(&self.to_rgba_image()?, output_path, config.direct_writes)
+            }
             _ => self.save_as_bmp_32bit(output_path, config),
         }
     }
     
     /// Save as 32-bit BGRA BMP (original format, includes transparency)
-    pub fn save_as_bmp_32bit(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
+    pub fn save_as_bmp_32bit(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         let mut rgba_data = Vec::with_capacity(self.pixels.len() * 4);
         
         // Convert RGB + Alpha to RGBA
@@ -260,45 +258,60 @@ impl PdtImage {
         let img = image::RgbaImage::from_raw(self.width, self.height, rgba_data)
             .ok_or_else(|| anyhow!("Failed to create RGBA image"))?;
         
-        img.save(output_path)?;
-        Ok(())
+        crate::output::write_with(output_path, config.direct_writes, |w| {
+            img.write_to(w, image::ImageOutputFormat::Bmp)?;
+            Ok(())
+        })
     }
     
     /// Save as raw RGB (fastest, no transparency)
-    pub fn save_as_raw_rgb(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        use std::fs::File;
+    pub fn save_as_raw_rgb(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         use std::io::Write;
         
-        let mut file = File::create(output_path)?;
-        
-        for &pixel in &self.pixels {
-            file.write_all(&[pixel.r, pixel.g, pixel.b])?;
-        }
-        
-        Ok(())
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            for &pixel in &self.pixels {
+                file.write_all(&[pixel.r, pixel.g, pixel.b])?;
+            }
+            Ok(())
+        })
+    }
+    
+    /// Save as headerless RGB565 for 16-bit framebuffers (no transparency)
+    pub fn save_as_rgb565(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            let pixels = self.pixels.iter().map(|p| [p.r, p.g, p.b]);
+            crate::output::write_rgb565(file, pixels, config.rgb565_order)
+        })
     }
     
     /// Save as raw RGBA (fast, includes transparency) 
-    pub fn save_as_raw_rgba(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        use std::fs::File;
+    pub fn save_as_raw_rgba(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         use std::io::Write;
         
-        let mut file = File::create(output_path)?;
-        
-        for (i, &pixel) in self.pixels.iter().enumerate() {
-            let alpha = if i < self.alpha_mask.len() {
-                self.alpha_mask[i]
-            } else {
-                255
-            };
-            file.write_all(&[pixel.r, pixel.g, pixel.b, alpha])?;
-        }
-        
-        Ok(())
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            for (i, &pixel) in self.pixels.iter().enumerate() {
+                let alpha = if i < self.alpha_mask.len() {
+                    self.alpha_mask[i]
+                } else {
+                    255
+                };
+                file.write_all(&[pixel.r, pixel.g, pixel.b, alpha])?;
+            }
+            Ok(())
+        })
     }
     
     /// Save as PNG with transparency (slowest due to compression)
-    pub fn save_as_png(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
+    pub fn save_as_png(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        let img = self.to_rgba_image()?;
+        crate::output::write_with(output_path, config.direct_writes, |w| {
+            img.write_to(w, image::ImageOutputFormat::Png)?;
+            Ok(())
+        })
+    }
+
+    /// RGB pixels with the alpha mask applied (opaque where it is missing)
+    pub fn to_rgba_image(&self) -> Result<image::RgbaImage> {
         let mut rgba_data = Vec::with_capacity(self.pixels.len() * 4);
         
         for (i, &pixel) in self.pixels.iter().enumerate() {
@@ -310,11 +323,8 @@ impl PdtImage {
             rgba_data.extend_from_slice(&[pixel.r, pixel.g, pixel.b, alpha]);
         }
         
-        let img = image::RgbaImage::from_raw(self.width, self.height, rgba_data)
-            .ok_or_else(|| anyhow!("Failed to create image"))?;
-        
-        img.save(output_path)?;
-        Ok(())
+        image::RgbaImage::from_raw(self.width, self.height, rgba_data)
+            .ok_or_else(|| anyhow!("Failed to create image"))
     }
     
     /// Decode with step-by-step visualization
@@ -326,6 +336,7 @@ impl PdtImage {
         state.metadata.insert("width".to_string(), self.width.to_string());
         state.metadata.insert("height".to_string(), self.height.to_string());
         state.metadata.insert("mask_offset".to_string(), self.mask_offset.to_string());
+        state.metadata.insert("layout".to_string(), format!("{:?}", self.layout));
         
         // Calculate compression ratio
         let uncompressed_size = self.pixels.len() * 3 + self.alpha_mask.len();
@@ -345,15 +356,253 @@ impl PdtImage {
             ),
             operation_type: crate::formats::StepOperationType::Header,
             raw_bytes: vec![],
-            data_offset: 32,
+            data_offset: self.layout.header_size(),
             data_length: self.pixels.len() * 3,
             pixels_decoded: self.pixels.len(),
             memory_state: vec![], // Ring buffer state would go here
             ring_position: 0,
             partial_image: None,
         };
-        state.add_step(step);
+        state.try_add_step(&crate::FormatType::KanonPdt, step)?;
         
         self.decode(output_path, config)
     }
-}
\ No newline at end of file
+}
+
+/// RGB pixels of a PDT stream, decoded on demand through the 4096-pixel
+/// ring buffer; ends when the stream does
+pub struct RgbStream<'a> {
+    reader: BitFlagReader<'a>,
+    ring_buffer: [RgbColor; 0x1000],
+    ring_pos: usize,
+    back_pos: usize,
+    /// Pixels left of the current reference
+    remaining: usize,
+}
+
+impl<'a> RgbStream<'a> {
+    pub fn new(compressed_data: &'a [u8]) -> Self {
+        Self {
+            reader: BitFlagReader::new(compressed_data, BitOrder::MsbFirst, Polarity::Normal),
+            ring_buffer: [RgbColor::default(); 0x1000],
+            ring_pos: 0,
+            back_pos: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Compressed bytes read so far
+    pub fn consumed(&self) -> usize {
+        self.reader.position()
+    }
+
+    fn emit(&mut self, color: RgbColor) -> Option<RgbColor> {
+        self.ring_buffer[self.ring_pos] = color;
+        self.ring_pos = (self.ring_pos + 1) & 0x0fff;
+        Some(color)
+    }
+}
+
+impl Iterator for RgbStream<'_> {
+    type Item = RgbColor;
+
+    fn next(&mut self) -> Option<RgbColor> {
+        if self.remaining == 0 {
+            if self.reader.next_flag()? {
+                // Direct RGB pixel (3 bytes) - BGR order in file
+                let &[b, g, r] = self.reader.read_bytes(3)? else { return None };
+                return self.emit(RgbColor { r, g, b });
+            }
+            // Reference to ring buffer (2 bytes)
+            let &[lo, hi] = self.reader.read_bytes(2)? else { return None };
+            let word = u16::from_le_bytes([lo, hi]);
+            self.remaining = ((word & 0x0f) as usize) + 1;
+            let copy_position = ((word >> 4) as usize) & 0x0fff;
+            self.back_pos = (self.ring_pos.wrapping_sub(copy_position).wrapping_sub(1)) & 0x0fff;
+        }
+        let color = self.ring_buffer[self.back_pos];
+        self.back_pos = (self.back_pos + 1) & 0x0fff;
+        self.remaining -= 1;
+        self.emit(color)
+    }
+}
+
+/// Alpha values of a PDT mask stream, decoded on demand like [`RgbStream`]
+pub struct AlphaStream<'a> {
+    reader: BitFlagReader<'a>,
+    ring_buffer: [u8; 0x1000],
+    ring_pos: usize,
+    back_pos: usize,
+    remaining: usize,
+}
+
+impl<'a> AlphaStream<'a> {
+    pub fn new(compressed_data: &'a [u8]) -> Self {
+        Self {
+            reader: BitFlagReader::new(compressed_data, BitOrder::MsbFirst, Polarity::Normal),
+            ring_buffer: [0; 0x1000],
+            ring_pos: 0,
+            back_pos: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Compressed bytes read so far
+    pub fn consumed(&self) -> usize {
+        self.reader.position()
+    }
+
+    fn emit(&mut self, alpha: u8) -> Option<u8> {
+        self.ring_buffer[self.ring_pos] = alpha;
+        self.ring_pos = (self.ring_pos + 1) & 0x0fff;
+        Some(alpha)
+    }
+}
+
+impl Iterator for AlphaStream<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.remaining == 0 {
+            if self.reader.next_flag()? {
+                let alpha = self.reader.read_u8()?;
+                return self.emit(alpha);
+            }
+            let &[lo, hi] = self.reader.read_bytes(2)? else { return None };
+            let word = u16::from_le_bytes([lo, hi]);
+            self.remaining = ((word & 0xff) as usize) + 2; // Different from RGB version!
+            let position = ((word >> 8) as usize) & 0x0fff;
+            self.back_pos = (self.ring_pos.wrapping_sub(position).wrapping_sub(1)) & 0x0fff;
+        }
+        let alpha = self.ring_buffer[self.back_pos];
+        self.back_pos = (self.back_pos + 1) & 0x0fff;
+        self.remaining -= 1;
+        self.emit(alpha)
+    }
+}
+
+/// `--low-memory`: decode `data` scanline by scanline straight into
+/// `output_path` without materializing the image. Only formats written in
+/// row order can do this: bmp (top-down), raw, rgba and rgb565.
+pub fn stream_to_file(data: &[u8], output_path: &Path, config: &DecodeConfig) -> Result<()> {
+    let header = PdtImage::from_header(data)?;
+    let (width, height) = (header.width as usize, header.height as usize);
+    let extension = crate::paths::extension_lower(output_path).unwrap_or_else(|| "bmp".to_string());
+    if !matches!(extension.as_str(), "bmp" | "raw" | "rgba" | "rgb565") {
+        return Err(anyhow!("--low-memory writes bmp, raw, rgba or rgb565, not {}", extension));
+    }
+
+    // Truncated streams decode as the full-image path does: black, opaque
+    let rgb = RgbStream::new(&data[header.layout.header_size()..]).chain(std::iter::repeat(RgbColor::default()));
+    let alpha = header.mask_data(data).map(AlphaStream::new).into_iter().flatten().chain(std::iter::repeat(255));
+    let mut pixels = rgb.zip(alpha);
+
+    crate::output::write_with(output_path, config.direct_writes, |w| {
+        use std::io::Write;
+
+        if extension == "bmp" {
+            crate::output::write_bmp32_header(w, header.width, header.height)?;
+        }
+        let mut row = Vec::with_capacity(width * 4);
+        for _ in 0..height {
+            row.clear();
+            let scanline = pixels.by_ref().take(width);
+            match extension.as_str() {
+                "raw" => scanline.for_each(|(c, _)| row.extend_from_slice(&[c.r, c.g, c.b])),
+                "rgba" => scanline.for_each(|(c, a)| row.extend_from_slice(&[c.r, c.g, c.b, a])),
+                "rgb565" => crate::output::write_rgb565(&mut row, scanline.map(|(c, _)| [c.r, c.g, c.b]), config.rgb565_order)?,
+                _ => scanline.for_each(|(c, a)| row.extend_from_slice(&[c.b, c.g, c.r, a])),
+            }
+            w.write_all(&row)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> PdtImage {
+        let pixels: Vec<RgbColor> = (0..12u8)
+            .map(|i| RgbColor { r: i * 20, g: 255 - i, b: i })
+            .collect();
+        PdtImage {
+            width: 4,
+            height: 3,
+            file_length: 0,
+            layout: PdtLayout::Standard,
+            mask_offset: 0,
+            alpha_mask: (0..12u8).map(|i| if i % 3 == 0 { 0 } else { 255 }).collect(),
+            pixels,
+        }
+    }
+
+    #[test]
+    fn decodes_both_header_layouts() {
+        let image = sample();
+        for layout in [PdtLayout::Standard, PdtLayout::Legacy] {
+            let data = image.to_pdt_bytes(layout);
+            assert_eq!(PdtLayout::detect(&data), layout);
+
+            let decoded = PdtImage::from_data(&data).unwrap();
+            assert_eq!(decoded.layout, layout);
+            assert_eq!((decoded.width, decoded.height), (4, 3));
+            let rgb = |p: &[RgbColor]| p.iter().map(|c| (c.r, c.g, c.b)).collect::<Vec<_>>();
+            assert_eq!(rgb(&decoded.pixels), rgb(&image.pixels));
+            match layout {
+                PdtLayout::Standard => assert_eq!(decoded.alpha_mask, image.alpha_mask),
+                PdtLayout::Legacy => assert!(decoded.alpha_mask.iter().all(|&a| a == 255)),
+            }
+        }
+    }
+
+    #[test]
+    fn literal_flag_bytes_are_all_set() {
+        // The literal-only writer opens every block of up to 8 items with
+        // 0xff, the short last block included
+        let image = sample();
+        let mut expected = image.to_pdt_bytes(PdtLayout::Standard)[..PdtLayout::Standard.header_size()].to_vec();
+        for chunk in image.pixels.chunks(8) {
+            expected.push(0xff);
+            for c in chunk {
+                expected.extend_from_slice(&[c.b, c.g, c.r]);
+            }
+        }
+        for chunk in image.alpha_mask.chunks(8) {
+            expected.push(0xff);
+            expected.extend_from_slice(chunk);
+        }
+        assert_eq!(image.to_pdt_bytes(PdtLayout::Standard), expected);
+    }
+
+    #[test]
+    fn low_memory_output_matches_full_decode() {
+        let data = sample().to_pdt_bytes(PdtLayout::Standard);
+        let full = PdtImage::from_data(&data).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let config = DecodeConfig::default();
+        for extension in ["bmp", "raw", "rgba", "rgb565"] {
+            let expected = dir.path().join(format!("full.{}", extension));
+            let streamed = dir.path().join(format!("streamed.{}", extension));
+            full.decode(&expected, &config).unwrap();
+            stream_to_file(&data, &streamed, &config).unwrap();
+            if extension == "bmp" {
+                let decode = |p: &Path| image::open(p).unwrap().to_rgba8();
+                assert_eq!(decode(&streamed), decode(&expected));
+            } else {
+                assert_eq!(std::fs::read(&streamed).unwrap(), std::fs::read(&expected).unwrap(), "{}", extension);
+            }
+        }
+        assert!(stream_to_file(&data, &dir.path().join("x.png"), &config).is_err());
+
+        // One literal, then a reference repeating it three times
+        let mut referenced = PdtImage { width: 2, height: 2, ..sample() }.to_pdt_bytes(PdtLayout::Legacy);
+        referenced.truncate(PdtLayout::Legacy.header_size());
+        referenced.extend_from_slice(&[0x80, 3, 2, 1, 0x02, 0x00]);
+        let streamed = dir.path().join("referenced.raw");
+        stream_to_file(&referenced, &streamed, &config).unwrap();
+        assert_eq!(std::fs::read(&streamed).unwrap(), [1, 2, 3].repeat(4));
+        assert!(PdtImage::from_data(&referenced).unwrap().pixels.iter().all(|c| (c.r, c.g, c.b) == (1, 2, 3)));
+    }
+}
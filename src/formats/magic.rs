@@ -0,0 +1,309 @@
+//! Magic numbers, header layouts and token grammars of the supported formats
+//!
+//! The decoders read these values inline; this module is the one place they
+//! are written down, so that tools outside the crate (hex editor templates,
+//! reimplementations in other languages) can take them from [`LAYOUTS`]
+//! instead of re-deriving them from the decoders. Everything here is
+//! `Serialize`; the WASM build hands [`LAYOUTS`] out as JSON-like objects
+//! (`format_layouts()`).
+//!
+//! Offsets are in bytes from the start of the file unless a layout says
+//! otherwise.
+
+use serde::Serialize;
+
+use crate::lzss::{BitOrder, LzssSpec};
+
+/// ToHeart / Kizuato LF2 image
+pub const LF2_MAGIC: &[u8] = b"LEAF256\0";
+/// LF2 header size; the BGR palette follows
+pub const LF2_HEADER_SIZE: usize = 0x18;
+
+/// Kanon PDT10 image
+pub const PDT_MAGIC: &[u8] = b"PDT10\0\0\0";
+/// PDT10 header size; the RGB stream follows
+pub const PDT_HEADER_SIZE: usize = 32;
+/// Header size of early PDT10 files without a mask offset field
+pub const PDT_LEGACY_HEADER_SIZE: usize = 28;
+
+/// ToHeart LEAFPACK archive
+pub const LEAFPACK_MAGIC: &[u8] = b"LEAFPACK";
+/// Magic plus the file count
+pub const LEAFPACK_HEADER_SIZE: usize = 10;
+/// Size of one (encrypted) file table entry; the table ends the archive
+pub const LEAFPACK_ENTRY_SIZE: usize = 24;
+
+/// PC-98 MAG (MAKI02) image
+pub const MAG_MAGIC: &[u8] = b"MAKI02  ";
+/// MAG header size, counted from the header start after the comment
+pub const MAG_HEADER_SIZE: usize = 32;
+
+/// PC-98 Pi image
+pub const PI_MAGIC: &[u8] = b"Pi";
+
+/// One fixed-position field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// One flag-byte LZSS stream of a format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TokenGrammar {
+    /// What the stream decodes to
+    pub stream: &'static str,
+    pub flag_order: BitOrder,
+    /// Flag bit value that marks a literal
+    pub literal_flag: bool,
+    /// Every stream byte (flags included) is XORed with this key
+    pub xor_key: u8,
+    /// Bytes per literal
+    pub literal_size: usize,
+    /// Bytes per reference
+    pub reference_size: usize,
+    /// How a reference packs its position and length
+    pub reference: &'static str,
+    /// Ring buffer size, in decoded units
+    pub window_size: usize,
+    /// First ring buffer write position
+    pub initial_position: usize,
+    /// Value the ring buffer is pre-filled with
+    pub initial_fill: u8,
+    pub min_match: usize,
+    pub max_match: usize,
+}
+
+/// Everything fixed about one format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FormatLayout {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Bytes every file starts with
+    pub magic: &'static [u8],
+    /// Whether `fields` are file offsets; otherwise the header follows
+    /// variable-length data described in `notes` and they count from its start
+    pub header_at_fixed_offset: bool,
+    /// Multi-byte fields are big-endian
+    pub big_endian: bool,
+    /// Fixed header size, if there is one
+    pub header_size: Option<usize>,
+    pub fields: &'static [Field],
+    pub tokens: &'static [TokenGrammar],
+    pub notes: &'static str,
+}
+
+const fn field(name: &'static str, offset: usize, size: usize) -> Field {
+    Field { name, offset, size }
+}
+
+/// The LF2 pixel stream, from [`LzssSpec::LF2`]
+pub const LF2_TOKENS: TokenGrammar = TokenGrammar {
+    stream: "palette indices, bottom row first",
+    flag_order: LzssSpec::LF2.flag_order,
+    literal_flag: LzssSpec::LF2.literal_flag,
+    xor_key: LzssSpec::LF2.xor_key,
+    literal_size: 1,
+    reference_size: 2,
+    reference: "length - 3 in the low nibble of byte 0; position = byte0 >> 4 | byte1 << 4",
+    window_size: LzssSpec::LF2.window_size,
+    initial_position: LzssSpec::LF2.initial_position,
+    initial_fill: LzssSpec::LF2.initial_fill,
+    min_match: LzssSpec::LF2.min_match,
+    max_match: LzssSpec::LF2.max_match,
+};
+
+/// The PDT RGB stream; the ring buffer holds whole pixels
+pub const PDT_RGB_TOKENS: TokenGrammar = TokenGrammar {
+    stream: "BGR pixels, top row first",
+    flag_order: BitOrder::MsbFirst,
+    literal_flag: true,
+    xor_key: 0,
+    literal_size: 3,
+    reference_size: 2,
+    reference: "u16 word: length = (word & 0x0f) + 1, distance = (word >> 4) + 1 pixels back",
+    window_size: 0x1000,
+    initial_position: 0,
+    initial_fill: 0,
+    min_match: 1,
+    max_match: 16,
+};
+
+/// The PDT alpha mask stream
+pub const PDT_MASK_TOKENS: TokenGrammar = TokenGrammar {
+    stream: "alpha bytes, top row first",
+    flag_order: BitOrder::MsbFirst,
+    literal_flag: true,
+    xor_key: 0,
+    literal_size: 1,
+    reference_size: 2,
+    reference: "u16 word: length = (word & 0xff) + 2, distance = (word >> 8) + 1 bytes back",
+    window_size: 0x1000,
+    initial_position: 0,
+    initial_fill: 0,
+    min_match: 2,
+    max_match: 257,
+};
+
+/// Fields of one LEAFPACK file table entry, each byte stored plus the
+/// running 11-byte key
+pub const LEAFPACK_ENTRY_FIELDS: &[Field] = &[
+    field("name", 0, 12),
+    field("position", 12, 4),
+    field("length", 16, 4),
+    field("next_position", 20, 4),
+];
+
+pub const LF2: FormatLayout = FormatLayout {
+    name: "LF2",
+    extensions: &["lf2", "scn"],
+    magic: LF2_MAGIC,
+    header_at_fixed_offset: true,
+    big_endian: false,
+    header_size: Some(LF2_HEADER_SIZE),
+    fields: &[
+        field("magic", 0x00, 8),
+        field("x_offset", 0x08, 2),
+        field("y_offset", 0x0a, 2),
+        field("width", 0x0c, 2),
+        field("height", 0x0e, 2),
+        field("transparent_color", 0x12, 1),
+        field("color_count", 0x16, 1),
+    ],
+    tokens: &[LF2_TOKENS],
+    notes: "color_count BGR triples follow the header, then the pixel stream",
+};
+
+pub const PDT: FormatLayout = FormatLayout {
+    name: "PDT10",
+    extensions: &["pdt"],
+    magic: PDT_MAGIC,
+    header_at_fixed_offset: true,
+    big_endian: false,
+    header_size: Some(PDT_HEADER_SIZE),
+    fields: &[
+        field("magic", 0x00, 8),
+        field("file_length", 0x08, 4),
+        field("width", 0x0c, 4),
+        field("height", 0x10, 4),
+        field("mask_offset", 0x1c, 4),
+    ],
+    tokens: &[PDT_RGB_TOKENS, PDT_MASK_TOKENS],
+    notes: "Legacy files have a 28-byte header without mask_offset and no mask; \
+            a mask_offset of 0 means no mask",
+};
+
+pub const LEAFPACK: FormatLayout = FormatLayout {
+    name: "LEAFPACK",
+    extensions: &["pak"],
+    magic: LEAFPACK_MAGIC,
+    header_at_fixed_offset: true,
+    big_endian: false,
+    header_size: Some(LEAFPACK_HEADER_SIZE),
+    fields: &[
+        field("magic", 0x00, 8),
+        field("file_count", 0x08, 2),
+    ],
+    tokens: &[],
+    notes: "The file table (file_count entries of 24 bytes) ends the archive",
+};
+
+pub const MAG: FormatLayout = FormatLayout {
+    name: "MAG",
+    extensions: &["mag", "mki"],
+    magic: MAG_MAGIC,
+    header_at_fixed_offset: false,
+    big_endian: false,
+    header_size: Some(MAG_HEADER_SIZE),
+    fields: &[
+        field("machine_code", 0x01, 1),
+        field("machine_flags", 0x02, 1),
+        field("screen_mode", 0x03, 1),
+        field("x0", 0x04, 2),
+        field("y0", 0x06, 2),
+        field("x1", 0x08, 2),
+        field("y1", 0x0a, 2),
+        field("flag_a_offset", 0x0c, 4),
+        field("flag_b_offset", 0x10, 4),
+        field("flag_b_size", 0x14, 4),
+        field("pixel_offset", 0x18, 4),
+        field("pixel_size", 0x1c, 4),
+    ],
+    tokens: &[],
+    notes: "The header starts after machine code, user name and a comment \
+            terminated by 0x1a; offsets in it are relative to its start. \
+            A GRB palette follows",
+};
+
+pub const PI: FormatLayout = FormatLayout {
+    name: "Pi",
+    extensions: &["pi"],
+    magic: PI_MAGIC,
+    header_at_fixed_offset: false,
+    big_endian: true,
+    header_size: None,
+    fields: &[],
+    tokens: &[],
+    notes: "Comment terminated by 0x1a, padding terminated by 0x00, then mode, \
+            aspect n/m, plane bits, 4-byte machine code, big-endian u16 \
+            extension size and data, big-endian u16 width and height",
+};
+
+/// Every format with a fixed signature
+pub const LAYOUTS: &[FormatLayout] = &[LF2, PDT, LEAFPACK, MAG, PI];
+
+/// The layout whose magic `data` starts with
+pub fn detect(data: &[u8]) -> Option<&'static FormatLayout> {
+    LAYOUTS.iter().find(|layout| data.starts_with(layout.magic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_match_the_codecs() {
+        use crate::formats::kanon::pdt::{PdtImage, PdtLayout, RgbColor};
+        use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+        let lf2 = Lf2Image {
+            width: 3,
+            height: 2,
+            x_offset: 7,
+            y_offset: 9,
+            transparent_color: 1,
+            color_count: 2,
+            palette: vec![Rgb { r: 1, g: 2, b: 3 }; 2],
+            pixels: vec![0, 1, 0, 1, 0, 1],
+        };
+        let bytes = lf2.to_lf2_bytes().unwrap();
+        assert_eq!(detect(&bytes), Some(&LF2));
+        let at = |name: &str| LF2.fields.iter().find(|f| f.name == name).unwrap().offset;
+        assert_eq!(bytes[at("x_offset")], 7);
+        assert_eq!(bytes[at("width")], 3);
+        assert_eq!(bytes[at("transparent_color")], 1);
+        assert_eq!(bytes[at("color_count")], 2);
+        assert_eq!(bytes[LF2_HEADER_SIZE], 3);
+
+        let pdt = PdtImage {
+            width: 5,
+            height: 1,
+            file_length: 0,
+            layout: PdtLayout::Standard,
+            mask_offset: 0,
+            pixels: vec![RgbColor::default(); 5],
+            alpha_mask: vec![255; 5],
+        };
+        let bytes = pdt.to_pdt_bytes(PdtLayout::Standard);
+        assert_eq!(detect(&bytes), Some(&PDT));
+        assert_eq!(bytes[0x0c], 5);
+        assert_eq!(PdtLayout::Standard.header_size(), PDT_HEADER_SIZE);
+        assert_eq!(PdtLayout::Legacy.header_size(), PDT_LEGACY_HEADER_SIZE);
+
+        assert_eq!(detect(b"MAKI02  PC98"), Some(&MAG));
+        assert_eq!(detect(b"BM"), None);
+        assert!(LAYOUTS.iter().all(|l| l.fields.iter().all(|f| l.header_size.map_or(true, |s| f.offset + f.size <= s))));
+        assert_eq!(LEAFPACK_ENTRY_FIELDS.iter().map(|f| f.size).sum::<usize>(), LEAFPACK_ENTRY_SIZE);
+    }
+}
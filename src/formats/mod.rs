@@ -3,15 +3,64 @@
 use std::fmt;
 use std::path::Path;
 use anyhow::{anyhow, Result};
+use tracing::info;
 use serde::{Serialize, Deserialize};
 
+pub mod common;
 pub mod toheart;
 pub mod kanon;
+pub mod elf;
+pub mod pc98;
+pub mod bmp;
+pub mod magic;
+pub mod header;
+pub mod spec_export;
+pub mod encode;
+pub mod sidecar;
+pub mod reencode;
+// Encoder research: divergence fixtures and candidate audits for `verify`.
+// Only public with the `unstable` feature.
+#[cfg(feature = "unstable")]
+pub mod repro;
+#[cfg(feature = "unstable")]
+pub mod candidate_audit;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod repro;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod candidate_audit;
 
 use crate::DecodeConfig;
 
+/// Row `row` of an image `height` rows high, counted from the other edge
+///
+/// This is the only mapping between display order (top row first) and the
+/// bottom-up order LF2 streams and BMP files use; it is its own inverse.
+/// Every Y-flip goes through it or [`flip_rows`].
+pub(crate) fn flipped_row(row: usize, height: usize) -> usize {
+    debug_assert!(row < height, "row {} outside height {}", row, height);
+    height - 1 - row
+}
+
+/// Reverse the order of `row_len`-byte rows. `data` must hold whole rows.
+pub(crate) fn flip_rows(data: &[u8], row_len: usize) -> Vec<u8> {
+    if row_len == 0 {
+        return data.to_vec();
+    }
+    debug_assert_eq!(data.len() % row_len, 0, "partial row in a {}-byte row image", row_len);
+    let height = data.len() / row_len;
+    (0..height)
+        .flat_map(|row| {
+            let start = flipped_row(row, height) * row_len;
+            &data[start..start + row_len]
+        })
+        .copied()
+        .collect()
+}
+
 /// Supported format types
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FormatType {
     // ToHeart formats
     ToHeartPak,
@@ -21,6 +70,15 @@ pub enum FormatType {
     // Kanon formats
     KanonPdt,
     KanonG00,
+    
+    // Elf / Silky's formats
+    ElfGph,
+    SilkyMgr,
+    
+    // PC-98 formats
+    Pc98Planar,
+    Pc98Mag,
+    Pc98Pi,
 }
 
 impl fmt::Display for FormatType {
@@ -31,19 +89,27 @@ impl fmt::Display for FormatType {
             FormatType::ToHeartScn => write!(f, "ToHeart SCN Scene"),
             FormatType::KanonPdt => write!(f, "Kanon PDT Image"),
             FormatType::KanonG00 => write!(f, "Kanon G00 Image"),
+            FormatType::ElfGph => write!(f, "Elf GPH Image"),
+            FormatType::SilkyMgr => write!(f, "Silky's MGR Image Archive"),
+            FormatType::Pc98Planar => write!(f, "PC-98 16-color Planar Image"),
+            FormatType::Pc98Mag => write!(f, "MAG Image"),
+            FormatType::Pc98Pi => write!(f, "Pi Image"),
         }
     }
 }
 
 impl FormatType {
+    /// Whether the compressed stream produces rows bottom-up (LF2 and SCN);
+    /// see [`Orientation`](crate::decoder::Orientation)
+    pub fn stores_bottom_up(&self) -> bool {
+        matches!(self, FormatType::ToHeartLf2 | FormatType::ToHeartScn)
+    }
+
     /// Detect format from file extension (case-insensitive)
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
-        let extension = path
-            .extension()
-            .ok_or_else(|| anyhow!("No file extension found"))?
-            .to_string_lossy()
-            .to_lowercase();
+        let extension = crate::paths::extension_lower(path)
+            .ok_or_else(|| anyhow!("No file extension found"))?;
 
         match extension.as_str() {
             "pak" => Ok(FormatType::ToHeartPak),
@@ -51,9 +117,116 @@ impl FormatType {
             "scn" => Ok(FormatType::ToHeartScn),
             "pdt" => Ok(FormatType::KanonPdt),
             "g00" => Ok(FormatType::KanonG00),
+            "gph" => Ok(FormatType::ElfGph),
+            "mgr" => Ok(FormatType::SilkyMgr),
+            "mag" | "mki" => Ok(FormatType::Pc98Mag),
+            "pi" => Ok(FormatType::Pc98Pi),
             _ => Err(anyhow!("Unsupported file extension: {}", extension)),
         }
     }
+
+    /// Detect the format from the file contents
+    ///
+    /// LF2, PAK, PDT (any `PDTnn` version, so unsupported ones still reach
+    /// the decoder's version error), MAG and Pi are recognised by their
+    /// magic. G00 has none, so it is accepted only when the header parses
+    /// and its compressed size accounts for the rest of the file.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        use magic::{LEAFPACK_MAGIC, LF2_MAGIC, MAG_MAGIC, PI_MAGIC};
+
+        if data.starts_with(LF2_MAGIC) {
+            return Ok(FormatType::ToHeartLf2);
+        }
+        if data.starts_with(LEAFPACK_MAGIC) {
+            return Ok(FormatType::ToHeartPak);
+        }
+        if data.len() >= 5 && data.starts_with(b"PDT") && data[3..5].iter().all(u8::is_ascii_digit) {
+            return Ok(FormatType::KanonPdt);
+        }
+        if data.starts_with(MAG_MAGIC) {
+            return Ok(FormatType::Pc98Mag);
+        }
+        if data.starts_with(PI_MAGIC) && pc98::pi::PiHeader::parse(data).is_ok() {
+            return Ok(FormatType::Pc98Pi);
+        }
+        if let Ok(header) = kanon::g00::G00Header::parse(data) {
+            let compressed = header.compressed_size as usize;
+            // The stored size counts its own 8-byte size fields in some
+            // releases and not in others
+            let plausible = header.width > 0
+                && header.height > 0
+                && (header.data_offset - 8 + compressed == data.len()
+                    || header.data_offset + compressed == data.len());
+            if plausible {
+                return Ok(FormatType::KanonG00);
+            }
+        }
+        Err(anyhow!("Unrecognised file contents (no known magic number)"))
+    }
+
+    /// Detect the format from the extension, falling back to the contents
+    /// when the extension is missing or unknown
+    pub fn detect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        FormatType::from_path(path).or_else(|extension_error| {
+            let data = std::fs::read(path)?;
+            FormatType::from_bytes(&data).map_err(|_| extension_error)
+        })
+    }
+}
+
+/// Support matrix entry for a single format
+#[derive(Debug, Clone, Serialize)]
+pub struct FormatCapability {
+    pub format: FormatType,
+    /// File extensions recognised by `FormatType::from_path` (lowercase)
+    pub extensions: &'static [&'static str],
+    /// Known container versions / magic signatures
+    pub versions: &'static [&'static str],
+    pub decode: bool,
+    pub encode: bool,
+    /// Whether `decode_with_steps` records a step trace
+    pub step_trace: bool,
+}
+
+/// Machine-readable table of supported formats and what each one can do.
+/// The flags come from the decoder and encoder dispatch, so the table
+/// cannot claim more than the code does.
+pub fn capabilities() -> Vec<FormatCapability> {
+    let formats: [(FormatType, &'static [&'static str], &'static [&'static str]); 10] = [
+        (FormatType::ToHeartPak, &["pak"], &["LEAFPACK"]),
+        (FormatType::ToHeartLf2, &["lf2"], &["LEAF256"]),
+        (FormatType::ToHeartScn, &["scn"], &["LEAF256"]),
+        (FormatType::KanonPdt, &["pdt"], &["PDT10"]),
+        (FormatType::KanonG00, &["g00"], &[]),
+        (FormatType::ElfGph, &["gph"], &[]),
+        (FormatType::SilkyMgr, &["mgr"], &["LZF+BMP"]),
+        (FormatType::Pc98Planar, &[], &["4-plane B/R/G/I"]),
+        (FormatType::Pc98Mag, &["mag", "mki"], &["MAKI02"]),
+        (FormatType::Pc98Pi, &["pi"], &["Pi"]),
+    ];
+    formats
+        .into_iter()
+        .map(|(format, extensions, versions)| {
+            // Planar images decode (with steps) through the `planar` subcommand
+            let decode = direct_decoder(&format).is_some() || format == FormatType::Pc98Planar;
+            FormatCapability {
+                encode: can_encode(&format),
+                decode,
+                step_trace: decode,
+                format,
+                extensions,
+                versions,
+            }
+        })
+        .collect()
+}
+
+/// Whether retro-decode can write `format`: images through
+/// [`reencode`](reencode::supports), PAK archives through `pack`
+/// ([`write_archive`](toheart::pak::write_archive))
+fn can_encode(format: &FormatType) -> bool {
+    reencode::supports(format) || *format == FormatType::ToHeartPak
 }
 
 /// Represents a single step in the decoding process
@@ -92,6 +265,8 @@ pub enum StepOperationType {
     Header,
     /// Palette data
     Palette,
+    /// Merging one byte from each bit plane into 8 palette indices
+    PlaneMerge { x: usize, y: usize },
 }
 
 /// State of the decoding process
@@ -103,6 +278,10 @@ pub struct DecodingState {
     pub decoded_pixels: usize,
     pub ring_buffer: Vec<u8>,
     pub metadata: std::collections::HashMap<String, String>,
+    /// Most steps [`try_add_step`](Self::try_add_step) records; see
+    /// [`DecodeLimits::max_steps`](crate::decoder::DecodeLimits::max_steps)
+    #[serde(skip)]
+    pub step_limit: Option<usize>,
 }
 
 impl DecodingState {
@@ -114,13 +293,30 @@ impl DecodingState {
             decoded_pixels: 0,
             ring_buffer: Vec::new(),
             metadata: std::collections::HashMap::new(),
+            step_limit: None,
         }
     }
 
+    /// Empty state whose [`try_add_step`](Self::try_add_step) fails after
+    /// `limit` steps
+    pub fn with_step_limit(limit: Option<usize>) -> Self {
+        Self { step_limit: limit, ..Self::new() }
+    }
+
     pub fn add_step(&mut self, step: DecodeStep) {
         self.steps.push(step);
     }
 
+    /// Record `step` of a `format` decode. Past the step limit this fails
+    /// with [`Error::LimitExceeded`](crate::decoder::Error::LimitExceeded),
+    /// so the decode stops before it writes any output.
+    pub fn try_add_step(&mut self, format: &FormatType, step: DecodeStep) -> crate::decoder::Result<()> {
+        let limits = crate::decoder::DecodeLimits { max_steps: self.step_limit, ..Default::default() };
+        limits.check(format, crate::decoder::Limit::Steps, self.steps.len() as u64 + 1)?;
+        self.add_step(step);
+        Ok(())
+    }
+
     pub fn progress(&self) -> f32 {
         if self.total_pixels == 0 {
             0.0
@@ -136,39 +332,338 @@ impl Default for DecodingState {
     }
 }
 
+/// Decodes one input file to `output_file` (or, for archives, next to it)
+pub type DirectDecoder = fn(&Path, &Path, &DecodeConfig) -> Result<()>;
+
+/// The file-to-file decoder [`process_rust`] uses for `format`. `None` for
+/// G00, GPH and Pi, whose modules parse headers but not yet pixels, and for
+/// headerless planar images, which need the `planar` subcommand's geometry.
+/// Every decoder here records a step trace with `step_by_step`.
+pub fn direct_decoder(format: &FormatType) -> Option<DirectDecoder> {
+    match format {
+        FormatType::ToHeartPak => Some(|input, output_file, config| {
+            // For PAK archives, use parent directory of output_file
+            toheart::extract_pak(input, output_file.parent().unwrap_or(Path::new("./")), config)
+        }),
+        FormatType::ToHeartLf2 => Some(toheart::decode_lf2_direct),
+        FormatType::ToHeartScn => Some(toheart::decode_scn_direct),
+        FormatType::KanonPdt => Some(kanon::decode_pdt_direct),
+        FormatType::SilkyMgr => Some(elf::decode_mgr_direct),
+        FormatType::Pc98Mag => Some(pc98::decode_mag_direct),
+        FormatType::KanonG00 | FormatType::ElfGph | FormatType::Pc98Pi | FormatType::Pc98Planar => None,
+    }
+}
+
 /// Main processing function for Rust engine
+#[tracing::instrument(level = "trace", skip_all)]
 pub fn process_rust(
     input_path: &Path,
     output_file: &Path,
     format_type: FormatType,
     config: &crate::Config,
 ) -> Result<()> {
-    let decode_config = DecodeConfig {
-        parallel: config.parallel,
-        gpu: config.gpu,
-        step_by_step: config.step_by_step,
-        verbose: config.verbose,
-        benchmark: config.benchmark,
-        no_output: false, // TODO: Add to main Config if needed
-    };
+    // Deep game trees can exceed MAX_PATH on Windows
+    let input_path = &crate::paths::long_path(input_path);
+    let output_file = &crate::paths::long_path(output_file);
 
-    match format_type {
-        FormatType::ToHeartPak => {
-            // For PAK archives, use parent directory of output_file
-            let output_dir = output_file.parent().unwrap_or(Path::new("./"));
-            toheart::extract_pak(input_path, output_dir, &decode_config)
+    let decode_config = config.decode_config();
+
+    let is_archive = matches!(format_type, FormatType::ToHeartPak | FormatType::SilkyMgr);
+    if let Some(kind) = config.also_indices {
+        if !is_archive {
+            export_index_plane(input_path, output_file, format_type.clone(), kind, config.trim, &decode_config)?;
         }
-        FormatType::ToHeartLf2 => {
-            toheart::decode_lf2_direct(input_path, output_file, &decode_config)
+    }
+
+    if !config.export_palette.is_empty() && !is_archive {
+        export_palette(input_path, output_file, format_type.clone(), &config.export_palette, &decode_config)?;
+    }
+
+    if let Some(mode) = config.palettes {
+        if format_type != FormatType::ToHeartLf2 {
+            return Err(anyhow!("--palettes only applies to LF2 images"));
         }
-        FormatType::ToHeartScn => {
-            toheart::decode_scn_direct(input_path, output_file, &decode_config)
+        toheart::palette_variants::export(input_path, output_file, mode, &decode_config)?;
+        return Ok(());
+    }
+
+    if config.trim {
+        let rect = export_trimmed(input_path, output_file, format_type.clone(), &decode_config)?;
+        stamp_png(input_path, output_file, &format_type, config)?;
+        if decode_config.sidecar {
+            let mut meta = sidecar::ImageMetadata::from_source(input_path, format_type)?;
+            meta.trim = Some(rect);
+            meta.save_with(&sidecar::sidecar_path(output_file), decode_config.direct_writes)?;
         }
-        FormatType::KanonPdt => {
-            kanon::decode_pdt_direct(input_path, output_file, &decode_config)
+        return Ok(());
+    }
+
+    if let Some(tile_size) = config.tiles {
+        export_tiles(input_path, output_file, format_type.clone(), tile_size, &decode_config)?;
+        return stamp_png(input_path, output_file, &format_type, config);
+    }
+
+    let decode = direct_decoder(&format_type).ok_or_else(|| match format_type {
+        FormatType::Pc98Planar => {
+            anyhow!("Planar images have no header; use the `planar` subcommand with explicit geometry")
         }
-        FormatType::KanonG00 => {
-            kanon::decode_g00_direct(input_path, output_file, &decode_config)
+        ref other => anyhow!("{} pixel decoding is not implemented yet", other),
+    })?;
+    decode(input_path, output_file, &decode_config)?;
+
+    // Archives produce many outputs; sidecars apply to single images only
+    if !is_archive {
+        stamp_png(input_path, output_file, &format_type, config)?;
+    }
+    if decode_config.sidecar && !is_archive {
+        sidecar::write_sidecar(input_path, output_file, format_type, decode_config.direct_writes)?;
+    }
+
+    Ok(())
+}
+
+/// Embed conversion provenance if `output_file` is a PNG
+fn stamp_png(input_path: &Path, output_file: &Path, format_type: &FormatType, config: &crate::Config) -> Result<()> {
+    if crate::paths::extension_lower(output_file).as_deref() != Some("png") {
+        return Ok(());
+    }
+    let data = std::fs::read(input_path)?;
+    let settings = serde_json::json!({
+        "output_format": config.output_format(input_path),
+        "orientation": config.orientation,
+        "tiles": config.tiles.map(|t| t.to_string()),
+        "trim": config.trim,
+    });
+    crate::provenance::Provenance::for_conversion(input_path, &data, format_type, &settings)
+        .stamp_file(output_file, config.direct_writes)
+}
+
+/// Decode a single image and write it as a tileset strip plus map
+fn export_tiles(
+    input_path: &Path,
+    output_file: &Path,
+    format_type: FormatType,
+    tile_size: crate::tiles::TileSize,
+    config: &DecodeConfig,
+) -> Result<()> {
+    let data = std::fs::read(input_path)?;
+    let decoded = crate::decoder::decoder_for(&format_type)?
+        .decode(&data)?
+        .with_orientation(config.orientation);
+    let img = image::RgbaImage::from_raw(decoded.width, decoded.height, decoded.rgba)
+        .ok_or_else(|| anyhow!("Decoded image has the wrong size"))?;
+
+    let tileset = crate::tiles::Tileset::from_image(&img, tile_size);
+    let map_path = tileset.write(output_file, config.direct_writes, config.rgb565_order)?;
+    info!(
+        "{}: {} cells, {} unique {} tiles -> {}, {}",
+        input_path.display(), tileset.map.len(), tileset.tiles.len(), tile_size,
+        output_file.display(), map_path.display()
+    );
+    Ok(())
+}
+
+/// Decode a single image and write the part inside its non-transparent
+/// bounding box. Returns the box in display coordinates.
+fn export_trimmed(
+    input_path: &Path,
+    output_file: &Path,
+    format_type: FormatType,
+    config: &DecodeConfig,
+) -> Result<crate::trim::TrimRect> {
+    let data = std::fs::read(input_path)?;
+    let decoded = crate::decoder::decoder_for(&format_type)?.decode(&data)?;
+    let rect = crate::trim::TrimRect::bounding_box(&decoded.rgba, decoded.width, decoded.height);
+    let trimmed = crate::decoder::DecodedImage {
+        width: rect.width,
+        height: rect.height,
+        rgba: rect.crop(&decoded.rgba, decoded.width, 4),
+        indices: decoded.indices.as_ref().map(|indices| rect.crop(indices, decoded.width, 1)),
+        ..decoded
+    }
+    .with_orientation(config.orientation);
+    let img = image::RgbaImage::from_raw(trimmed.width, trimmed.height, trimmed.rgba)
+        .ok_or_else(|| anyhow!("Decoded image has the wrong size"))?;
+
+    crate::output::write_rgba_image_ordered(&img, output_file, config.direct_writes, config.rgb565_order)?;
+    info!(
+        "{}: trimmed {}x{} to {}x{} at ({}, {}) -> {}",
+        input_path.display(), decoded.width, decoded.height,
+        rect.width, rect.height, rect.x, rect.y, output_file.display()
+    );
+    Ok(rect)
+}
+
+/// Write the palette index plane next to `output_file` as `<name>.pgm` or
+/// `<name>.idx`, cropped like the `--trim` export when `trim` is set.
+/// Direct-colour images have no index plane and are skipped.
+fn export_index_plane(
+    input_path: &Path,
+    output_file: &Path,
+    format_type: FormatType,
+    kind: crate::output::IndexPlane,
+    trim: bool,
+    config: &DecodeConfig,
+) -> Result<()> {
+    let data = std::fs::read(input_path)?;
+    let decoded = crate::decoder::decoder_for(&format_type)?.decode(&data)?;
+    if decoded.indices.is_none() {
+        info!("{}: not an indexed image, no index plane written", input_path.display());
+        return Ok(());
+    }
+    let decoded = if trim {
+        let rect = crate::trim::TrimRect::bounding_box(&decoded.rgba, decoded.width, decoded.height);
+        crate::decoder::DecodedImage {
+            width: rect.width,
+            height: rect.height,
+            rgba: rect.crop(&decoded.rgba, decoded.width, 4),
+            indices: decoded.indices.as_ref().map(|indices| rect.crop(indices, decoded.width, 1)),
+            ..decoded
+        }
+    } else {
+        decoded
+    }
+    .with_orientation(config.orientation);
+    let (width, height) = (decoded.width, decoded.height);
+    let indices = decoded.indices.unwrap_or_default();
+
+    let path = output_file.with_extension(kind.extension());
+    crate::output::write_with(&path, config.direct_writes, |w| {
+        crate::output::write_index_plane(w, width, height, &indices, kind)
+    })?;
+    info!("{}: index plane -> {}", input_path.display(), path.display());
+    Ok(())
+}
+
+/// `--export-palette`: the palette of an indexed image as `kinds`, next to
+/// `output_file`. The transparent index comes from the header where the
+/// format stores one.
+fn export_palette(
+    input_path: &Path,
+    output_file: &Path,
+    format_type: FormatType,
+    kinds: &[crate::output::PaletteFile],
+    config: &DecodeConfig,
+) -> Result<()> {
+    let data = std::fs::read(input_path)?;
+    let Some(palette) = crate::decoder::decoder_for(&format_type)?.decode(&data)?.palette else {
+        info!("{}: not an indexed image, no palette written", input_path.display());
+        return Ok(());
+    };
+    let transparent = header::inspect(input_path)
+        .ok()
+        .and_then(|report| report.fields.into_iter().find(|f| f.name == "transparent_color"))
+        .and_then(|field| field.value.as_u64())
+        .and_then(|index| u8::try_from(index).ok());
+    let name = output_file.file_stem().unwrap_or_default().to_string_lossy();
+
+    for &kind in kinds {
+        let path = output_file.with_extension(kind.extension());
+        crate::output::write_with(&path, config.direct_writes, |w| {
+            crate::output::write_palette(w, &palette, transparent, &name, kind)
+        })?;
+        info!("{}: palette -> {}", input_path.display(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_match_extension_detection() {
+        for cap in capabilities() {
+            for ext in cap.extensions {
+                let detected = FormatType::from_path(format!("sample.{}", ext)).unwrap();
+                assert_eq!(detected, cap.format);
+            }
         }
+
+        let flags = |format: FormatType| {
+            let cap = capabilities().into_iter().find(|c| c.format == format).unwrap();
+            (cap.decode, cap.encode)
+        };
+        assert_eq!(flags(FormatType::ToHeartPak), (true, true));
+        assert_eq!(flags(FormatType::KanonPdt), (true, true));
+        assert_eq!(flags(FormatType::SilkyMgr), (true, false));
+        assert_eq!(flags(FormatType::KanonG00), (false, false));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sniffs_magic_numbers() {
+        let sniff = |data: &[u8]| FormatType::from_bytes(data).ok();
+        assert_eq!(sniff(b"LEAF256\0rest"), Some(FormatType::ToHeartLf2));
+        assert_eq!(sniff(b"LEAFPACK\x01\x00"), Some(FormatType::ToHeartPak));
+        assert_eq!(sniff(b"PDT10\0\0\0"), Some(FormatType::KanonPdt));
+        assert_eq!(sniff(b"PDT11\0\0\0"), Some(FormatType::KanonPdt));
+        assert_eq!(sniff(b"MAKI02  "), Some(FormatType::Pc98Mag));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), None);
+
+        // Type 1 G00, 4x2, a 3-byte stream whose size counts the size fields
+        let mut g00 = vec![1, 4, 0, 2, 0];
+        g00.extend_from_slice(&11u32.to_le_bytes());
+        g00.extend_from_slice(&8u32.to_le_bytes());
+        g00.extend_from_slice(&[0xff, 0, 0]);
+        assert_eq!(sniff(&g00), Some(FormatType::KanonG00));
+        g00.push(0);
+        assert_eq!(sniff(&g00), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CG0001");
+        std::fs::write(&path, b"LEAF256\0").unwrap();
+        assert_eq!(FormatType::detect(&path).unwrap(), FormatType::ToHeartLf2);
+        assert!(FormatType::detect(dir.path().join("missing.lf2")).is_ok());
+    }
+
+    #[test]
+    fn step_limit_aborts_the_decode() {
+        let step = DecodeStep {
+            step_number: 1,
+            description: String::new(),
+            explanation: String::new(),
+            operation_type: StepOperationType::Header,
+            raw_bytes: vec![],
+            data_offset: 0,
+            data_length: 0,
+            pixels_decoded: 0,
+            memory_state: vec![],
+            ring_position: 0,
+            partial_image: None,
+        };
+        let lf2 = FormatType::ToHeartLf2;
+        let mut state = DecodingState::with_step_limit(Some(2));
+        for _ in 0..2 {
+            state.try_add_step(&lf2, step.clone()).unwrap();
+        }
+        assert!(matches!(
+            state.try_add_step(&lf2, step.clone()),
+            Err(crate::decoder::Error::LimitExceeded { value: 3, max: 2, .. })
+        ));
+        assert_eq!(state.steps.len(), 2);
+        // The infallible form keeps recording
+        state.add_step(step.clone());
+        assert_eq!(state.steps.len(), 3);
+        assert!(DecodingState::new().try_add_step(&lf2, step).is_ok());
+    }
+
+    #[test]
+    fn flip_rows_golden() {
+        // 1 row high: nothing moves
+        assert_eq!(flip_rows(&[1, 2, 3], 3), [1, 2, 3]);
+        // Odd height: the middle row stays, the outer rows swap
+        assert_eq!(flip_rows(&[1, 2, 3, 4, 5, 6], 2), [5, 6, 3, 4, 1, 2]);
+        assert_eq!(flip_rows(&[1, 2, 3, 4, 5], 1), [5, 4, 3, 2, 1]);
+        // Even height
+        assert_eq!(flip_rows(&[1, 2, 3, 4], 1), [4, 3, 2, 1]);
+        assert_eq!(flip_rows(&[], 4), [] as [u8; 0]);
+
+        for height in 1..=7 {
+            let rows: Vec<usize> = (0..height).map(|row| flipped_row(row, height)).collect();
+            assert_eq!(rows, (0..height).rev().collect::<Vec<_>>());
+            assert!((0..height).all(|row| flipped_row(flipped_row(row, height), height) == row));
+            let data: Vec<u8> = (0..height as u8 * 3).collect();
+            assert_eq!(flip_rows(&flip_rows(&data, 3), 3), data);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! MAG (MAKI02) image format
+//!
+//! The de facto PC-98 image format of the BBS era. After the `MAKI02  `
+//! signature, machine code, user name and a comment terminated by 0x1a comes
+//! a 32-byte header (offsets below are relative to its start):
+//!
+//! | off | size | field |
+//! |-----|------|-------|
+//! | 0x00 | 1 | always 0 |
+//! | 0x01 | 1 | machine code |
+//! | 0x02 | 1 | machine flags |
+//! | 0x03 | 1 | screen mode (0x80 = 256 colors) |
+//! | 0x04 | 2×4 | x0, y0, x1, y1 |
+//! | 0x0c | 4 | flag A offset |
+//! | 0x10 | 4 | flag B offset |
+//! | 0x14 | 4 | flag B size |
+//! | 0x18 | 4 | pixel data offset |
+//! | 0x1c | 4 | pixel data size |
+//!
+//! followed by the palette as G, R, B bytes. Pixels are handled in 2-byte
+//! units (4 pixels at 16 colors, 2 at 256). Each unit has a 4-bit flag: 0
+//! reads the unit from the pixel stream, anything else copies it from one of
+//! 15 earlier positions. Flags are XOR-delta coded against the line above,
+//! with flag A marking which flag bytes changed and flag B holding the deltas.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use tracing::debug;
+
+use crate::{DecodeConfig, DecodingState, DecodeStep};
+use crate::formats::StepOperationType;
+
+pub use crate::formats::magic::MAG_MAGIC;
+
+/// Copy source for flags 1..=15: (units back, lines up)
+const COPY_POSITIONS: [(usize, usize); 16] = [
+    (0, 0), (1, 0), (2, 0), (4, 0),
+    (0, 1), (1, 1),
+    (0, 2), (1, 2), (2, 2),
+    (0, 4), (1, 4), (2, 4),
+    (0, 8), (1, 8), (2, 8),
+    (0, 16),
+];
+
+/// Decoded MAG image (palette indices, top-down)
+pub struct MagImage {
+    pub width: usize,
+    pub height: usize,
+    pub x_offset: u16,
+    pub y_offset: u16,
+    /// 16 or 256 entries
+    pub palette: Vec<[u8; 3]>,
+    pub pixels: Vec<u8>,
+    /// Comment text (Shift-JIS, decoded lossily)
+    pub comment: String,
+}
+
+impl MagImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::from_data(&data)
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn from_data(data: &[u8]) -> Result<Self> {
+        Self::parse(data, None)
+    }
+
+    /// Decode recording header, palette and the first line's flag handling
+    pub fn from_data_with_steps(data: &[u8], state: &mut DecodingState) -> Result<Self> {
+        Self::parse(data, Some(state))
+    }
+
+    /// Image size from the header alone, without decoding
+    pub fn dimensions(data: &[u8]) -> Result<(usize, usize)> {
+        let (_, h) = Self::locate_header(data)?;
+        let (x0, y0, x1, y1) = Self::rectangle(&data[h..h + 32])?;
+        Ok((x1 - x0 + 1, y1 - y0 + 1))
+    }
+
+    /// Comment text and offset of the 32-byte header
+    pub(crate) fn locate_header(data: &[u8]) -> Result<(String, usize)> {
+        if data.len() < 32 || &data[..8] != MAG_MAGIC {
+            return Err(anyhow!("Invalid MAG magic number"));
+        }
+        let comment_end = data[8..].iter().position(|&b| b == 0x1a)
+            .map(|p| p + 8)
+            .ok_or_else(|| anyhow!("MAG comment is not terminated"))?;
+        let comment = String::from_utf8_lossy(data.get(30..comment_end).unwrap_or(&[])).trim().to_string();
+
+        let h = comment_end + 1;
+        crate::decoder::check_length(&crate::FormatType::Pc98Mag, data, h + 32)?;
+        Ok((comment, h))
+    }
+
+    /// `(x0, y0, x1, y1)` with x aligned to whole pixel units, checked to
+    /// span a plausible size
+    fn rectangle(header: &[u8]) -> Result<(usize, usize, usize, usize)> {
+        let u16_at = |o: usize| u16::from_le_bytes([header[o], header[o + 1]]) as usize;
+        let align = if header[3] & 0x80 != 0 { 4 } else { 8 };
+
+        let x0 = u16_at(4) / align * align;
+        let y0 = u16_at(6);
+        let x1 = u16_at(8) / align * align + align - 1;
+        let y1 = u16_at(10);
+        if x1 < x0 || y1 < y0 {
+            return Err(anyhow!("MAG coordinates out of order"));
+        }
+        crate::decoder::check_dimensions(&crate::FormatType::Pc98Mag, (x1 - x0 + 1) as u64, (y1 - y0 + 1) as u64)?;
+        Ok((x0, y0, x1, y1))
+    }
+
+    fn parse(data: &[u8], mut state: Option<&mut DecodingState>) -> Result<Self> {
+        let (comment, h) = Self::locate_header(data)?;
+        let header = &data[h..h + 32];
+        let u32_at = |o: usize| u32::from_le_bytes([header[o], header[o + 1], header[o + 2], header[o + 3]]) as usize;
+
+        let screen_mode = header[3];
+        let colors256 = screen_mode & 0x80 != 0;
+        let (pixels_per_unit, colors) = if colors256 { (2, 256) } else { (4, 16) };
+
+        let (x0, y0, x1, y1) = Self::rectangle(header)?;
+        let width = x1 - x0 + 1;
+        let height = y1 - y0 + 1;
+
+        let flag_a_offset = h + u32_at(12);
+        let flag_b_offset = h + u32_at(16);
+        let flag_b_size = u32_at(20);
+        let pixel_offset = h + u32_at(24);
+        let pixel_size = u32_at(28);
+
+        let palette_start = h + 32;
+        crate::decoder::check_length(&crate::FormatType::Pc98Mag, data, palette_start + colors * 3)?;
+        let palette_bytes = &data[palette_start..palette_start + colors * 3];
+        let palette: Vec<[u8; 3]> = palette_bytes.chunks_exact(3)
+            .map(|grb| [grb[1], grb[0], grb[2]])
+            .collect();
+
+        debug!("MAG: {}x{} at ({},{}), {} colors, mode {:#04x}", width, height, x0, y0, colors, screen_mode);
+
+        if let Some(state) = state.as_deref_mut() {
+            state.total_pixels = width * height;
+            state.metadata.insert("width".to_string(), width.to_string());
+            state.metadata.insert("height".to_string(), height.to_string());
+            state.metadata.insert("colors".to_string(), colors.to_string());
+            state.try_add_step(&crate::FormatType::Pc98Mag, DecodeStep {
+                step_number: state.steps.len() + 1,
+                description: "MAGヘッダ解析".to_string(),
+                explanation: format!(
+                    "座標 ({},{})-({},{})、{} 色。\n\
+                     フラグA 0x{:x}、フラグB 0x{:x} ({} バイト)、ピクセル 0x{:x} ({} バイト)。",
+                    x0, y0, x1, y1, colors, flag_a_offset, flag_b_offset, flag_b_size, pixel_offset, pixel_size
+                ),
+                operation_type: StepOperationType::Header,
+                raw_bytes: header.to_vec(),
+                data_offset: h,
+                data_length: 32,
+                pixels_decoded: 0,
+                memory_state: vec![],
+                ring_position: 0,
+                partial_image: None,
+            })?;
+            state.try_add_step(&crate::FormatType::Pc98Mag, DecodeStep {
+                step_number: state.steps.len() + 1,
+                description: "パレット読み込み".to_string(),
+                explanation: format!("{} 色のパレットを G,R,B の順で読み込みます。", colors),
+                operation_type: StepOperationType::Palette,
+                raw_bytes: palette_bytes.to_vec(),
+                data_offset: palette_start,
+                data_length: palette_bytes.len(),
+                pixels_decoded: 0,
+                memory_state: vec![],
+                ring_position: 0,
+                partial_image: None,
+            })?;
+        }
+
+        let flag_a = data.get(flag_a_offset..flag_b_offset.min(data.len()))
+            .ok_or_else(|| anyhow!("MAG flag A out of bounds"))?;
+        let flag_b = data.get(flag_b_offset..(flag_b_offset + flag_b_size).min(data.len()))
+            .ok_or_else(|| anyhow!("MAG flag B out of bounds"))?;
+        let pixel_data = data.get(pixel_offset..(pixel_offset + pixel_size).min(data.len()))
+            .ok_or_else(|| anyhow!("MAG pixel data out of bounds"))?;
+
+        let decompress = crate::timing::start(crate::timing::Phase::Decompress);
+        let units_per_line = width / pixels_per_unit;
+        let line_bytes = units_per_line * 2;
+        let mut flag_line = vec![0u8; units_per_line / 2];
+        let mut raw = vec![0u8; line_bytes * height];
+
+        let mut a_bit = 0usize;
+        let mut b_pos = 0usize;
+        let mut p_pos = 0usize;
+
+        for y in 0..height {
+            for flag in flag_line.iter_mut() {
+                let byte = *flag_a.get(a_bit / 8).ok_or_else(|| anyhow!("MAG flag A exhausted"))?;
+                if byte & (0x80 >> (a_bit % 8)) != 0 {
+                    *flag ^= *flag_b.get(b_pos).ok_or_else(|| anyhow!("MAG flag B exhausted"))?;
+                    b_pos += 1;
+                }
+                a_bit += 1;
+            }
+
+            for (i, &flag) in flag_line.iter().enumerate() {
+                for (half, nibble) in [flag >> 4, flag & 0x0f].into_iter().enumerate() {
+                    let unit = i * 2 + half;
+                    let dst = y * line_bytes + unit * 2;
+                    if nibble == 0 {
+                        let src = pixel_data.get(p_pos..p_pos + 2)
+                            .ok_or_else(|| anyhow!("MAG pixel data exhausted at line {}", y))?;
+                        raw[dst..dst + 2].copy_from_slice(src);
+                        p_pos += 2;
+                    } else {
+                        let (dx, dy) = COPY_POSITIONS[nibble as usize];
+                        let back = dy * line_bytes + dx * 2;
+                        if back > dst {
+                            return Err(anyhow!("MAG copy before image start at line {}", y));
+                        }
+                        raw.copy_within(dst - back..dst - back + 2, dst);
+                    }
+
+                    if y == 0 && unit < 8 {
+                        if let Some(state) = state.as_deref_mut() {
+                            record_unit_step(state, nibble, &raw[dst..dst + 2], unit, pixels_per_unit)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        let pixels = if colors256 {
+            raw
+        } else {
+            raw.iter().flat_map(|&b| [b >> 4, b & 0x0f]).collect()
+        };
+        drop(decompress);
+        crate::timing::image(width as u32, height as u32, || 0);
+
+        if let Some(state) = state {
+            state.decoded_pixels = pixels.len();
+        }
+
+        Ok(Self {
+            width,
+            height,
+            x_offset: x0 as u16,
+            y_offset: y0 as u16,
+            palette,
+            pixels,
+            comment,
+        })
+    }
+
+    pub fn to_rgba(&self) -> image::RgbaImage {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for &index in &self.pixels {
+            let [r, g, b] = self.palette.get(index as usize).copied().unwrap_or([0, 0, 0]);
+            rgba.extend_from_slice(&[r, g, b, 255]);
+        }
+        image::RgbaImage::from_raw(self.width as u32, self.height as u32, rgba)
+            .expect("buffer matches dimensions")
+    }
+
+    /// Save as png / bmp / raw / rgba by extension
+    pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        if config.no_output {
+            return Ok(());
+        }
+        crate::output::write_rgba_image_ordered(&self.to_rgba(), output_path, config.direct_writes, config.rgb565_order)
+    }
+}
+
+fn record_unit_step(state: &mut DecodingState, flag: u8, bytes: &[u8], unit: usize, pixels_per_unit: usize) -> crate::decoder::Result<()> {
+    let (description, explanation, operation_type) = if flag == 0 {
+        (
+            format!("ユニット {} をピクセルデータから読む", unit),
+            "フラグ 0: ピクセルデータから 2 バイトを直接読み込みます。".to_string(),
+            StepOperationType::DirectPixel { palette_index: bytes[0] },
+        )
+    } else {
+        let (dx, dy) = COPY_POSITIONS[flag as usize];
+        (
+            format!("ユニット {} を {} 左・{} 上からコピー", unit, dx, dy),
+            format!("フラグ {}: {} ユニット左、{} ライン上の 2 バイトをコピーします。", flag, dx, dy),
+            StepOperationType::LzssMatch { distance: dx + dy, length: 1 },
+        )
+    };
+    state.try_add_step(&crate::FormatType::Pc98Mag, DecodeStep {
+        step_number: state.steps.len() + 1,
+        description,
+        explanation,
+        operation_type,
+        raw_bytes: bytes.to_vec(),
+        data_offset: 0,
+        data_length: 2,
+        pixels_decoded: (unit + 1) * pixels_per_unit,
+        memory_state: vec![],
+        ring_position: 0,
+        partial_image: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16x2, 16 colors. Line 0: units read from pixel data except unit 2,
+    /// which copies unit 1 (flag 1). Line 1: every unit copies line 0 (flag 4).
+    fn sample() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAG_MAGIC);
+        data.extend_from_slice(b"PC98");
+        data.extend_from_slice(b"tester            ");
+        data.extend_from_slice(b"test");
+        data.push(0x1a);
+
+        // Line 0 flags: units 0..4 -> 0, 0, 1, 0 => bytes 0x00, 0x10
+        // Line 1 flags: 4, 4, 4, 4 => XOR deltas 0x44, 0x54
+        let flag_a = [0b0111_0000];
+        let flag_b = [0x10, 0x44, 0x54];
+        let pixels = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab];
+
+        let flag_a_off = 32 + 48;
+        let flag_b_off = flag_a_off + flag_a.len();
+        let pixel_off = flag_b_off + flag_b.len();
+
+        let mut header = vec![0u8, 0, 0, 0];
+        for v in [0u16, 0, 15, 1] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [flag_a_off, flag_b_off, flag_b.len(), pixel_off, pixels.len()] {
+            header.extend_from_slice(&(v as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&header);
+        for i in 0..16u8 {
+            data.extend_from_slice(&[i * 16, i * 16, i * 16]);
+        }
+        data.extend_from_slice(&flag_a);
+        data.extend_from_slice(&flag_b);
+        data.extend_from_slice(&pixels);
+        data
+    }
+
+    #[test]
+    fn decodes_literal_and_copy_units() {
+        let image = MagImage::from_data(&sample()).unwrap();
+        assert_eq!((image.width, image.height), (16, 2));
+        assert_eq!(image.comment, "test");
+        let line0: Vec<u8> = vec![0, 1, 2, 3, 4, 5, 6, 7, 4, 5, 6, 7, 8, 9, 10, 11];
+        assert_eq!(&image.pixels[..16], &line0[..]);
+        assert_eq!(&image.pixels[16..], &line0[..]);
+    }
+
+    #[test]
+    fn records_header_and_unit_steps() {
+        let mut state = DecodingState::new();
+        MagImage::from_data_with_steps(&sample(), &mut state).unwrap();
+        // header + palette + 4 units of line 0
+        assert_eq!(state.steps.len(), 6);
+        assert_eq!(state.decoded_pixels, 32);
+    }
+}
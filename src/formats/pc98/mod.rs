@@ -0,0 +1,94 @@
+//! PC-98 era format support
+//!
+//! Handles headerless 16-color planar screens (Shizuku / Kizuato PC-98
+//! originals), whose geometry and interleave are supplied by the caller, and
+//! the MAG / Pi formats common in fan material of the same era.
+
+use std::path::Path;
+use anyhow::Result;
+use tracing::info;
+
+use crate::{DecodeConfig, DecodingState, FormatType};
+
+pub mod planar;
+pub mod mag;
+pub mod pi;
+
+pub use planar::{PlanarImage, PlanarSpec, PlaneInterleave};
+pub use mag::MagImage;
+pub use pi::PiImage;
+
+/// Decode MAG image to specific file
+pub fn decode_mag_direct(
+    input_path: &Path,
+    output_file: &Path,
+    config: &DecodeConfig,
+) -> Result<()> {
+    info!("Decoding MAG image: {:?}", input_path);
+
+    let data = std::fs::read(input_path)?;
+    let (width, height) = MagImage::dimensions(&data)?;
+    config.limits.check_image(&FormatType::Pc98Mag, width as u64, height as u64)?;
+    let image = if config.step_by_step {
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        let image = MagImage::from_data_with_steps(&data, &mut state)?;
+        crate::trace::save_requested(config, FormatType::Pc98Mag, input_path, state)?;
+        image
+    } else {
+        MagImage::from_data(&data)?
+    };
+
+    if config.verbose && !image.comment.is_empty() {
+        info!("MAG comment: {}", image.comment);
+    }
+    image.decode(output_file, config)
+}
+
+/// Decode Pi image to specific file
+pub fn decode_pi_direct(
+    input_path: &Path,
+    output_file: &Path,
+    config: &DecodeConfig,
+) -> Result<()> {
+    info!("Decoding Pi image: {:?}", input_path);
+
+    let pi = PiImage::open(input_path)?;
+
+    if config.step_by_step {
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        pi.decode_with_steps(output_file, &mut state, config)?;
+    } else {
+        pi.decode(output_file, config)?;
+    }
+
+    Ok(())
+}
+
+/// Decode a planar dump starting at `offset` in `input_path`
+pub fn decode_planar_direct(
+    input_path: &Path,
+    output_file: &Path,
+    offset: usize,
+    spec: &PlanarSpec,
+    palette: [planar::Rgb; 16],
+    config: &DecodeConfig,
+) -> Result<()> {
+    info!("Decoding PC-98 planar image: {:?} ({}x{}, {:?})", input_path, spec.width, spec.height, spec.interleave);
+
+    let data = std::fs::read(input_path)?;
+    let data = data.get(offset..).unwrap_or(&[]);
+
+    let image = if config.step_by_step {
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        let image = PlanarImage::from_planes_with_steps(data, spec, palette, &mut state)?;
+        if config.verbose {
+            info!("Planar merge recorded {} steps", state.steps.len());
+        }
+        crate::trace::save_requested(config, FormatType::Pc98Planar, input_path, state)?;
+        image
+    } else {
+        PlanarImage::from_planes(data, spec, palette)?
+    };
+
+    image.decode(output_file, config)
+}
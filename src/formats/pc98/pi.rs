@@ -0,0 +1,123 @@
+//! Pi image format (Yanagisawa)
+//! TODO: pixel stream decoding (adaptive color table + position/length
+//! codes) needs verification against real files - header parsing only
+//!
+//! Header: `Pi`, comment terminated by 0x1a, padding terminated by 0x00,
+//! then mode, aspect n/m, plane bits (4 or 8), 4-byte machine code, a
+//! big-endian u16 extension size plus extension data, big-endian u16 width
+//! and height, and (unless mode bit 0x80 is set) an RGB palette.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::{DecodeConfig, DecodingState};
+
+pub use crate::formats::magic::PI_MAGIC;
+
+/// Parsed Pi header
+#[derive(Debug, Clone)]
+pub struct PiHeader {
+    pub comment: String,
+    pub mode: u8,
+    pub aspect: (u8, u8),
+    pub plane_bits: u8,
+    pub machine: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    /// RGB palette (`1 << plane_bits` entries) unless the file uses the default
+    pub palette: Option<Vec<[u8; 3]>>,
+    /// Offset of the compressed pixel stream
+    pub data_offset: usize,
+}
+
+impl PiHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(PI_MAGIC) {
+            return Err(anyhow!("Invalid Pi magic number"));
+        }
+        let comment_end = data.iter().position(|&b| b == 0x1a)
+            .ok_or_else(|| anyhow!("Pi comment is not terminated"))?;
+        let comment = String::from_utf8_lossy(&data[2..comment_end]).trim().to_string();
+        let dummy_end = data[comment_end..].iter().position(|&b| b == 0)
+            .map(|p| comment_end + p)
+            .ok_or_else(|| anyhow!("Pi header padding is not terminated"))?;
+
+        let mut pos = dummy_end + 1;
+        let fixed = data.get(pos..pos + 10).ok_or_else(|| anyhow!("Pi header truncated"))?;
+        let mode = fixed[0];
+        let aspect = (fixed[1], fixed[2]);
+        let plane_bits = fixed[3];
+        let machine = [fixed[4], fixed[5], fixed[6], fixed[7]];
+        let ext_size = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        pos += 10 + ext_size;
+
+        let size = data.get(pos..pos + 4).ok_or_else(|| anyhow!("Pi header truncated"))?;
+        let width = u16::from_be_bytes([size[0], size[1]]);
+        let height = u16::from_be_bytes([size[2], size[3]]);
+        pos += 4;
+
+        if plane_bits != 4 && plane_bits != 8 {
+            return Err(anyhow!("Unsupported Pi plane depth: {}", plane_bits));
+        }
+        let palette = if mode & 0x80 == 0 {
+            let len = (1usize << plane_bits) * 3;
+            let bytes = data.get(pos..pos + len).ok_or_else(|| anyhow!("Pi palette truncated"))?;
+            pos += len;
+            Some(bytes.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+        } else {
+            None
+        };
+
+        Ok(Self { comment, mode, aspect, plane_bits, machine, width, height, palette, data_offset: pos })
+    }
+}
+
+/// Pi image (header only, placeholder)
+pub struct PiImage {
+    pub header: PiHeader,
+}
+
+impl PiImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let header = PiHeader::parse(&data)?;
+        crate::decoder::check_dimensions(&crate::FormatType::Pc98Pi, header.width as u64, header.height as u64)?;
+        Ok(Self { header })
+    }
+
+    /// Decode Pi (placeholder)
+    pub fn decode(&self, _output_path: &Path, _config: &DecodeConfig) -> Result<()> {
+        Err(anyhow!(
+            "Pi pixel decoding not yet implemented ({}x{}, {} bpp)",
+            self.header.width, self.header.height, self.header.plane_bits
+        ))
+    }
+
+    /// Decode with step-by-step visualization (placeholder)
+    pub fn decode_with_steps(&self, output_path: &Path, _state: &mut DecodingState, config: &DecodeConfig) -> Result<()> {
+        self.decode(output_path, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_header() {
+        let mut data = b"Pi comment\x1a\x00".to_vec();
+        data.extend_from_slice(&[0x00, 1, 1, 4]);
+        data.extend_from_slice(b"PC98");
+        data.extend_from_slice(&2u16.to_be_bytes());
+        data.extend_from_slice(&[0xaa, 0xbb]);
+        data.extend_from_slice(&640u16.to_be_bytes());
+        data.extend_from_slice(&400u16.to_be_bytes());
+        data.extend_from_slice(&[0x11; 48]);
+
+        let header = PiHeader::parse(&data).unwrap();
+        assert_eq!(header.comment, "comment");
+        assert_eq!((header.width, header.height, header.plane_bits), (640, 400, 4));
+        assert_eq!(header.palette.as_ref().map(Vec::len), Some(16));
+        assert_eq!(header.data_offset, data.len());
+    }
+}
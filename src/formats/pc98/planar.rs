@@ -0,0 +1,303 @@
+//! PC-98 16-color planar images
+//!
+//! PC-98 VRAM holds a 640x400 16-color screen as four 1-bit planes (B, R, G
+//! and intensity). Each byte of a plane covers 8 horizontal pixels, MSB
+//! leftmost; a pixel's palette index is assembled from the same bit of all
+//! four planes. Leaf's and Elf's PC-98 releases store screens in this layout,
+//! either plane after plane or interleaved per scanline.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::{DecodeConfig, DecodingState, DecodeStep};
+use crate::formats::StepOperationType;
+
+/// How the four planes are arranged in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaneInterleave {
+    /// Whole plane 0, then whole plane 1, ... (VRAM dump order)
+    #[default]
+    Plane,
+    /// For each scanline: plane 0 row, plane 1 row, ...
+    Line,
+}
+
+impl PlaneInterleave {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "plane" => Ok(Self::Plane),
+            "line" => Ok(Self::Line),
+            _ => Err(anyhow!("Unknown plane interleave: {}", name)),
+        }
+    }
+}
+
+/// Geometry and bit layout of a planar image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanarSpec {
+    /// Width in pixels (multiple of 8)
+    pub width: usize,
+    pub height: usize,
+    pub interleave: PlaneInterleave,
+    /// Palette index bit fed by each stored plane (default B, R, G, I = 0, 1, 2, 3)
+    pub plane_bits: [u8; 4],
+}
+
+impl Default for PlanarSpec {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 400,
+            interleave: PlaneInterleave::Plane,
+            plane_bits: [0, 1, 2, 3],
+        }
+    }
+}
+
+impl PlanarSpec {
+    /// Bytes per plane row
+    pub fn row_bytes(&self) -> usize {
+        self.width / 8
+    }
+
+    /// Total bytes of planar data
+    pub fn data_size(&self) -> usize {
+        self.row_bytes() * self.height * 4
+    }
+
+    /// Offset of the byte holding pixels `x_byte*8..x_byte*8+8` of row `y` in `plane`
+    fn offset(&self, plane: usize, y: usize, x_byte: usize) -> usize {
+        let row = self.row_bytes();
+        match self.interleave {
+            PlaneInterleave::Plane => plane * row * self.height + y * row + x_byte,
+            PlaneInterleave::Line => (y * 4 + plane) * row + x_byte,
+        }
+    }
+}
+
+/// RGB palette entry (8 bits per channel)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Scale a 4-bit PC-98 analog palette level to 8 bits
+fn level(v: u8) -> u8 {
+    (v & 0x0f) * 17
+}
+
+/// Default 16-color palette: index bits B, R, G, I. Colors 1-7 at half
+/// intensity, 8 gray, 9-15 full intensity.
+pub fn default_palette() -> [Rgb; 16] {
+    let mut palette = [Rgb::default(); 16];
+    for (i, entry) in palette.iter_mut().enumerate() {
+        let on = match i {
+            0 => 0,
+            8 => {
+                *entry = Rgb { r: level(7), g: level(7), b: level(7) };
+                continue;
+            }
+            1..=7 => level(10),
+            _ => level(15),
+        };
+        *entry = Rgb {
+            b: if i & 1 != 0 { on } else { 0 },
+            r: if i & 2 != 0 { on } else { 0 },
+            g: if i & 4 != 0 { on } else { 0 },
+        };
+    }
+    palette
+}
+
+/// Parse a 48-byte analog palette: 16 entries of G, R, B levels (0-15), the
+/// order the PC-98 palette I/O ports take them in
+pub fn parse_grb_palette(data: &[u8]) -> Result<[Rgb; 16]> {
+    if data.len() < 48 {
+        return Err(anyhow!("PC-98 palette needs 48 bytes, got {}", data.len()));
+    }
+    let mut palette = [Rgb::default(); 16];
+    for (entry, grb) in palette.iter_mut().zip(data.chunks_exact(3)) {
+        *entry = Rgb { g: level(grb[0]), r: level(grb[1]), b: level(grb[2]) };
+    }
+    Ok(palette)
+}
+
+/// Decoded planar image (palette indices 0-15, top-down)
+pub struct PlanarImage {
+    pub width: usize,
+    pub height: usize,
+    pub palette: [Rgb; 16],
+    pub pixels: Vec<u8>,
+}
+
+impl PlanarImage {
+    /// Merge four planes into palette indices
+    pub fn from_planes(data: &[u8], spec: &PlanarSpec, palette: [Rgb; 16]) -> Result<Self> {
+        Self::merge(data, spec, palette, None)
+    }
+
+    fn merge(data: &[u8], spec: &PlanarSpec, palette: [Rgb; 16], mut state: Option<&mut DecodingState>) -> Result<Self> {
+        if spec.width == 0 || spec.width % 8 != 0 || spec.height == 0 {
+            return Err(anyhow!("Planar width must be a non-zero multiple of 8 (got {}x{})", spec.width, spec.height));
+        }
+        if data.len() < spec.data_size() {
+            return Err(anyhow!(
+                "Planar data too small: {}x{} needs {} bytes, got {}",
+                spec.width, spec.height, spec.data_size(), data.len()
+            ));
+        }
+
+        let mut pixels = vec![0u8; spec.width * spec.height];
+        for y in 0..spec.height {
+            for x_byte in 0..spec.row_bytes() {
+                let mut planes = [0u8; 4];
+                for (plane, byte) in planes.iter_mut().enumerate() {
+                    *byte = data[spec.offset(plane, y, x_byte)];
+                }
+                let base = y * spec.width + x_byte * 8;
+                for bit in 0..8 {
+                    let mask = 0x80 >> bit;
+                    let mut index = 0u8;
+                    for (plane, byte) in planes.iter().enumerate() {
+                        if byte & mask != 0 {
+                            index |= 1 << spec.plane_bits[plane];
+                        }
+                    }
+                    pixels[base + bit] = index;
+                }
+
+                if let Some(state) = state.as_deref_mut() {
+                    record_merge_step(state, spec, &planes, &pixels[base..base + 8], x_byte, y)?;
+                }
+            }
+        }
+
+        Ok(Self { width: spec.width, height: spec.height, palette, pixels })
+    }
+
+    /// Merge with step recording: one step per plane, then the first row's
+    /// 8-pixel groups in detail (later groups are identical in shape)
+    pub fn from_planes_with_steps(data: &[u8], spec: &PlanarSpec, palette: [Rgb; 16], state: &mut DecodingState) -> Result<Self> {
+        state.total_pixels = spec.width * spec.height;
+        state.metadata.insert("width".to_string(), spec.width.to_string());
+        state.metadata.insert("height".to_string(), spec.height.to_string());
+        state.metadata.insert("interleave".to_string(), format!("{:?}", spec.interleave));
+
+        let names = ["B", "R", "G", "I"];
+        for plane in 0..4 {
+            let start = spec.offset(plane, 0, 0);
+            let bit = spec.plane_bits[plane] as usize;
+            state.try_add_step(&crate::FormatType::Pc98Planar, DecodeStep {
+                step_number: state.steps.len() + 1,
+                description: format!("プレーン {} ({}) の位置", plane, names.get(bit).unwrap_or(&"?")),
+                explanation: format!(
+                    "プレーン {} は 0x{:x} から始まり、1 バイトが横 8 ピクセル分の 1 ビットを持ちます。\n\
+                     このプレーンのビットはパレット番号のビット {} (値 {}) になります。",
+                    plane, start, bit, 1 << bit
+                ),
+                operation_type: StepOperationType::Header,
+                raw_bytes: data.get(start..start + spec.row_bytes().min(16)).unwrap_or(&[]).to_vec(),
+                data_offset: start,
+                data_length: spec.row_bytes() * spec.height,
+                pixels_decoded: 0,
+                memory_state: vec![],
+                ring_position: 0,
+                partial_image: None,
+            })?;
+        }
+
+        let image = Self::merge(data, spec, palette, Some(state))?;
+        state.decoded_pixels = image.pixels.len();
+        Ok(image)
+    }
+
+    /// Convert to RGBA (planar formats have no transparency)
+    pub fn to_rgba(&self) -> image::RgbaImage {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for &index in &self.pixels {
+            let c = self.palette[(index & 0x0f) as usize];
+            rgba.extend_from_slice(&[c.r, c.g, c.b, 255]);
+        }
+        image::RgbaImage::from_raw(self.width as u32, self.height as u32, rgba)
+            .expect("buffer matches dimensions")
+    }
+
+    /// Save as png / bmp / raw / rgba by extension
+    pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        if config.no_output {
+            return Ok(());
+        }
+        crate::output::write_rgba_image_ordered(&self.to_rgba(), output_path, config.direct_writes, config.rgb565_order)
+    }
+}
+
+/// Steps recorded per 8-pixel group are limited to the first scanline
+const DETAILED_GROUPS: usize = 80;
+
+fn record_merge_step(state: &mut DecodingState, spec: &PlanarSpec, planes: &[u8; 4], indices: &[u8], x_byte: usize, y: usize) -> crate::decoder::Result<()> {
+    if y > 0 || x_byte >= DETAILED_GROUPS {
+        return Ok(());
+    }
+    let bits: Vec<String> = planes.iter().map(|b| format!("{:08b}", b)).collect();
+    state.try_add_step(&crate::FormatType::Pc98Planar, DecodeStep {
+        step_number: state.steps.len() + 1,
+        description: format!("x={}..{} のプレーン合成", x_byte * 8, x_byte * 8 + 7),
+        explanation: format!(
+            "4 プレーンの同じ位置のバイト [{}] を縦に重ね、\n\
+             各列のビットを組み合わせてパレット番号 {:?} を得ます。",
+            bits.join(", "), indices
+        ),
+        operation_type: StepOperationType::PlaneMerge { x: x_byte * 8, y },
+        raw_bytes: planes.to_vec(),
+        data_offset: spec.offset(0, y, x_byte),
+        data_length: 4,
+        pixels_decoded: x_byte * 8 + 8,
+        memory_state: indices.to_vec(),
+        ring_position: 0,
+        partial_image: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_planes_in_both_interleaves() {
+        // 8x2: row 0 = indices 0..8, row 1 = 15 everywhere
+        let plane_row0 = |bit: u8| -> u8 {
+            (0..8).fold(0, |acc, x| if (x >> bit) & 1 != 0 { acc | (0x80 >> x) } else { acc })
+        };
+        let mut plane_major = Vec::new();
+        for bit in 0..4 {
+            plane_major.push(plane_row0(bit));
+            plane_major.push(0xff);
+        }
+        let mut line_major = Vec::new();
+        for bit in 0..4 {
+            line_major.push(plane_row0(bit));
+        }
+        line_major.extend_from_slice(&[0xff; 4]);
+
+        let expected: Vec<u8> = (0..8).chain(std::iter::repeat(15).take(8)).collect();
+        for (data, interleave) in [(plane_major, PlaneInterleave::Plane), (line_major, PlaneInterleave::Line)] {
+            let spec = PlanarSpec { width: 8, height: 2, interleave, ..Default::default() };
+            let image = PlanarImage::from_planes(&data, &spec, default_palette()).unwrap();
+            assert_eq!(image.pixels, expected, "{:?}", interleave);
+        }
+    }
+
+    #[test]
+    fn records_plane_and_merge_steps() {
+        let spec = PlanarSpec { width: 16, height: 1, ..Default::default() };
+        let mut state = DecodingState::new();
+        let image = PlanarImage::from_planes_with_steps(&[0xff; 8], &spec, default_palette(), &mut state).unwrap();
+        assert!(image.pixels.iter().all(|&p| p == 15));
+        // 4 plane steps + 2 merge groups
+        assert_eq!(state.steps.len(), 6);
+        assert_eq!(state.steps[5].operation_type, StepOperationType::PlaneMerge { x: 8, y: 0 });
+        assert_eq!(parse_grb_palette(&[0x0f; 48]).unwrap()[3], Rgb { r: 255, g: 255, b: 255 });
+    }
+}
@@ -0,0 +1,929 @@
+//! Re-encode an exported image back into its original container
+//!
+//! Uses the `.meta.json` sidecar written at export time to restore everything
+//! PNG/BMP conversion drops (palette order, offsets, transparent index) and
+//! checks the rebuilt file against the recorded source hash. 8-bit BMP
+//! exports are read through [`BmpImage`], so their palette indices are used
+//! as they are instead of being matched by colour.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::FormatType;
+use super::bmp::BmpImage;
+use super::kanon::pdt::{PdtImage, PdtLayout, RgbColor};
+use super::candidate_audit::SearchSpace;
+use super::sidecar::{ImageMetadata, sidecar_path};
+use super::toheart::encode_profile::{EncodeProfile, EncoderParams, MatchFinder, TieBreak, MAX_SEARCH_DEPTH};
+use super::toheart::strategy_rng::DEFAULT_SEED;
+use super::toheart::lf2::{Lf2Image, Rgb};
+use super::toheart::palette_swap::parse_hex_color;
+use crate::checksum::sha256_hex;
+use crate::quantize::{self, QuantizeOptions};
+
+/// LF2 encoder used for reconstruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lf2Encoder {
+    /// Okumura lzss.c binary-tree port (closest known match to the original)
+    Okumura,
+    /// Phase 3 decision-tree guided encoder (needs the trained model)
+    DecisionTree,
+    /// Naive backward scan, strict `>` tie-break
+    NaiveStrict,
+    /// Naive backward scan, `>=` tie-break
+    NaiveEqual,
+    /// Named match-finder / search depth / tie-break combination
+    Profile(EncodeProfile),
+    /// Linear scan over the whole window, picking the first or last of
+    /// equally long matches at random (reproducible from `seed`)
+    Randomized { seed: u64 },
+    /// Linear scan whose references never run past the end of a row
+    Scanline,
+}
+
+impl Lf2Encoder {
+    /// Every encoder, for differential testing
+    pub const ALL: [Lf2Encoder; 10] = [
+        Self::Okumura,
+        Self::DecisionTree,
+        Self::NaiveStrict,
+        Self::NaiveEqual,
+        Self::Profile(EncodeProfile::Fast),
+        Self::Profile(EncodeProfile::Balanced),
+        Self::Profile(EncodeProfile::Exhaustive),
+        Self::Profile(EncodeProfile::Faithful),
+        Self::Randomized { seed: DEFAULT_SEED },
+        Self::Scanline,
+    ];
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "okumura" => Ok(Self::Okumura),
+            "decision-tree" => Ok(Self::DecisionTree),
+            "naive-strict" => Ok(Self::NaiveStrict),
+            "naive-equal" => Ok(Self::NaiveEqual),
+            "randomized" => Ok(Self::Randomized { seed: DEFAULT_SEED }),
+            "scanline" => Ok(Self::Scanline),
+            _ => EncodeProfile::from_name(name)
+                .map(Self::Profile)
+                .map_err(|_| anyhow!("Unknown LF2 encoder: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Okumura => "okumura",
+            Self::DecisionTree => "decision-tree",
+            Self::NaiveStrict => "naive-strict",
+            Self::NaiveEqual => "naive-equal",
+            Self::Profile(profile) => profile.name(),
+            Self::Randomized { .. } => "randomized",
+            Self::Scanline => "scanline",
+        }
+    }
+
+    /// Seed of a randomized encoder; `None` for the deterministic ones
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            Self::Randomized { seed } => Some(*seed),
+            _ => None,
+        }
+    }
+
+    /// Reseed a randomized encoder; the others are returned unchanged
+    pub fn with_seed(self, seed: u64) -> Self {
+        match self {
+            Self::Randomized { .. } => Self::Randomized { seed },
+            other => other,
+        }
+    }
+
+    /// Ring positions this encoder's match finder searches
+    pub fn search_space(&self) -> SearchSpace {
+        const RING: usize = 0x1000;
+        // Okumura's dummy inserts put the 18 fill positions before the
+        // first write into the tree
+        let (max_distance, fill_positions) = match self {
+            Self::Okumura => (MAX_SEARCH_DEPTH, 18),
+            Self::NaiveStrict | Self::NaiveEqual | Self::Randomized { .. } | Self::Scanline => (MAX_SEARCH_DEPTH, RING),
+            Self::DecisionTree => (RING - 1, RING),
+            Self::Profile(profile) => {
+                let params = profile.params();
+                match params.finder {
+                    MatchFinder::HashChain => (RING - 1, 0),
+                    MatchFinder::LinearScan => (params.search_depth, RING),
+                    MatchFinder::OkumuraTree => (MAX_SEARCH_DEPTH, 0),
+                }
+            }
+        };
+        SearchSpace { max_distance, fill_positions }
+    }
+
+    /// Encode `image` into a complete LF2 file
+    #[tracing::instrument(level = "trace", skip_all, fields(encoder = self.name()))]
+    pub fn encode(&self, image: &Lf2Image) -> Result<Vec<u8>> {
+        match self {
+            Self::Okumura => image.to_lf2_bytes_okumura(),
+            Self::DecisionTree => {
+                use super::toheart::lf2::CompressionStrategy;
+                image.to_lf2_bytes_with_strategy(CompressionStrategy::DecisionTreeGuided)
+            }
+            Self::NaiveStrict => image.to_lf2_bytes_naive_strict(),
+            Self::NaiveEqual => image.to_lf2_bytes_naive_equal(),
+            Self::Profile(profile) => image.to_lf2_bytes_with_params(&profile.params()),
+            Self::Randomized { seed } => image.to_lf2_bytes_with_params(&EncoderParams {
+                finder: MatchFinder::LinearScan,
+                search_depth: MAX_SEARCH_DEPTH,
+                tie_break: TieBreak::Seeded,
+                seed: *seed,
+                per_scanline: false,
+                early_literals: 0,
+            }),
+            Self::Scanline => image.to_lf2_bytes_with_params(&EncoderParams {
+                finder: MatchFinder::LinearScan,
+                search_depth: MAX_SEARCH_DEPTH,
+                tie_break: TieBreak::First,
+                seed: DEFAULT_SEED,
+                per_scanline: true,
+                early_literals: 0,
+            }),
+        }
+    }
+}
+
+/// Name, plus the seed for randomized encoders
+impl fmt::Display for Lf2Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.seed() {
+            Some(seed) => write!(f, "{} (seed {})", self.name(), seed),
+            None => f.write_str(self.name()),
+        }
+    }
+}
+
+/// Check that `bytes` is a conforming LF2 encoding of `image`: same header
+/// and palette, a pixel stream that ends exactly after its last token,
+/// references no further back than [`MAX_SEARCH_DEPTH`] (anything further
+/// reads ring bytes the decoder has not written yet) and pixels that decode
+/// back to `image`
+#[tracing::instrument(level = "trace", skip_all)]
+pub fn check_lf2_encoding(image: &Lf2Image, bytes: &[u8]) -> Result<()> {
+    let decoded = Lf2Image::from_data(bytes)?;
+    if (decoded.width, decoded.height, decoded.x_offset, decoded.y_offset) != (image.width, image.height, image.x_offset, image.y_offset)
+        || decoded.transparent_color != image.transparent_color
+        || decoded.color_count != image.color_count
+    {
+        return Err(anyhow!("Header does not match the source image"));
+    }
+    if decoded.palette.iter().zip(&image.palette).any(|(a, b)| (a.r, a.g, a.b) != (b.r, b.g, b.b)) {
+        return Err(anyhow!("Palette does not match the source image"));
+    }
+
+    let spec = crate::lzss::LzssSpec::LF2;
+    let stream = &bytes[0x18 + image.color_count as usize * 3..];
+    let pixels = image.stored_pixels();
+    let output = spec.decompress_stream(stream, pixels.len());
+    if !output.clean_end || output.data.len() != pixels.len() {
+        return Err(anyhow!("Stream decodes to {} of {} pixels", output.data.len(), pixels.len()));
+    }
+    if output.consumed != stream.len() {
+        return Err(anyhow!("{} bytes after the last token", stream.len() - output.consumed));
+    }
+    for token in spec.tokens(stream, pixels.len()) {
+        if let [b0, b1] = token.bytes[..] {
+            let pos = ((b0 ^ 0xff) >> 4) as usize | ((b1 ^ 0xff) as usize) << 4;
+            let distance = (0x0fee + token.output.start + 0x1000 - pos - 1) % 0x1000 + 1;
+            if distance > MAX_SEARCH_DEPTH {
+                return Err(anyhow!("Reference at pixel {} reaches {} back, past the lookahead", token.output.start, distance));
+            }
+        }
+    }
+    if let Some(i) = output.data.iter().zip(&pixels).position(|(a, b)| a != b) {
+        return Err(anyhow!("Pixel {} decodes to {} instead of {}", i, output.data[i], pixels[i]));
+    }
+    Ok(())
+}
+
+/// Result of a sidecar-driven re-encode
+#[derive(Debug, Clone)]
+pub struct ReencodeOutcome {
+    pub bytes: Vec<u8>,
+    pub format: FormatType,
+    /// LF2 encoder used; `None` for formats without a choice of encoder
+    pub encoder: Option<Lf2Encoder>,
+    /// `Some(true)` when the rebuilt file is byte-identical to the recorded source
+    pub hash_match: Option<bool>,
+}
+
+/// Most palette entries an LF2 header can declare (`color_count` is a byte)
+pub(crate) const LF2_MAX_COLORS: usize = u8::MAX as usize;
+
+/// Rebuild an LF2 image from exported RGBA pixels and sidecar metadata
+///
+/// Alpha is hardened first (see [`QuantizeOptions::harden_alpha`]).
+/// Opaque colours missing from the sidecar palette are an error reporting
+/// how many there are, unless `auto_quantize` maps each to the nearest
+/// palette entry. Without a sidecar palette one is built from the image;
+/// `auto_quantize` lets it be reduced by median cut when it does not fit.
+pub fn lf2_from_rgba(rgba: &[u8], meta: &ImageMetadata, options: &QuantizeOptions) -> Result<Lf2Image> {
+    let auto_quantize = options.auto_quantize;
+    let transparent_color = meta.transparent_color.unwrap_or(0);
+    let transparent = transparent_color as usize;
+
+    let total = (meta.width as usize) * (meta.height as usize);
+    if rgba.len() != total * 4 {
+        return Err(anyhow!(
+            "Image size mismatch: sidecar says {}x{}, got {} RGBA bytes",
+            meta.width, meta.height, rgba.len()
+        ));
+    }
+    let rgba = options.harden_alpha(rgba, meta.width as usize);
+    let rgba = rgba.as_ref();
+    let opaque = || rgba.chunks_exact(4).filter(|px| px[3] != 0).map(|px| [px[0], px[1], px[2]]);
+
+    let palette: Vec<[u8; 3]> = match &meta.palette {
+        Some(hex) => hex.iter()
+            .map(|c| parse_hex_color(c).map(|c| [c.r, c.g, c.b]))
+            .collect::<Result<_>>()?,
+        None => {
+            let histogram = quantize::color_histogram(opaque());
+            let mut palette = quantize::fit_palette(&histogram, LF2_MAX_COLORS - 1, auto_quantize, "an LF2 palette")?;
+            if palette.len() < transparent {
+                palette.resize(transparent, [0, 0, 0]);
+            }
+            palette.insert(transparent, [0, 0, 0]);
+            palette
+        }
+    };
+    // Exports never emit opaque pixels for the transparent index, so skip
+    // it when resolving duplicate palette colors; the first entry wins.
+    let exact: HashMap<[u8; 3], u8> = palette.iter().enumerate().rev()
+        .filter(|&(idx, _)| idx != transparent)
+        .map(|(idx, &c)| (c, idx as u8))
+        .collect();
+
+    let mut pixels = Vec::with_capacity(total);
+    for (i, px) in rgba.chunks_exact(4).enumerate() {
+        if px[3] == 0 {
+            pixels.push(transparent_color);
+            continue;
+        }
+        let color = [px[0], px[1], px[2]];
+        let index = match exact.get(&color) {
+            Some(&index) => index,
+            None if auto_quantize => quantize::nearest(&palette, color, Some(transparent)) as u8,
+            None => {
+                let histogram = quantize::color_histogram(opaque());
+                let missing = histogram.iter().filter(|(c, _)| !exact.contains_key(c)).count();
+                return Err(anyhow!(
+                    "Pixel {} color #{:02x}{:02x}{:02x} is not in the sidecar palette: the export has {} unique \
+                     colours, {} of them missing from the {}-colour palette; pass --auto-quantize to map \
+                     them to the nearest entry",
+                    i, px[0], px[1], px[2], histogram.len(), missing, palette.len()
+                ));
+            }
+        };
+        pixels.push(index);
+    }
+
+    Ok(Lf2Image {
+        width: meta.width as u16,
+        height: meta.height as u16,
+        x_offset: meta.x_offset.unwrap_or(0),
+        y_offset: meta.y_offset.unwrap_or(0),
+        transparent_color,
+        color_count: palette.len() as u8,
+        palette: palette.iter().map(|&[r, g, b]| Rgb { r, g, b }).collect(),
+        pixels,
+    })
+}
+
+/// Rebuild an LF2 image from an 8-bit BMP's indices, taking the palette
+/// from the sidecar or, when it has none, from the BMP colour table.
+/// Truecolor and trimmed BMPs go through [`lf2_from_rgba`].
+pub fn lf2_from_bmp(bmp: &BmpImage, meta: &ImageMetadata, options: &QuantizeOptions) -> Result<Lf2Image> {
+    let (Some(indices), Some(table), None) = (&bmp.indices, &bmp.palette, meta.trim) else {
+        let rgba = match meta.trim {
+            Some(rect) => rect.untrim(&bmp.rgba, meta.width, meta.height)?,
+            None => bmp.rgba.clone(),
+        };
+        return lf2_from_rgba(&rgba, meta, options);
+    };
+    if (bmp.width, bmp.height) != (meta.width, meta.height) {
+        return Err(anyhow!(
+            "Image size mismatch: sidecar says {}x{}, BMP is {}x{}",
+            meta.width, meta.height, bmp.width, bmp.height
+        ));
+    }
+    let transparent_color = meta.transparent_color.unwrap_or(0);
+    let palette: Vec<Rgb> = match &meta.palette {
+        Some(hex) => hex.iter().map(|c| parse_hex_color(c)).collect::<Result<_>>()?,
+        None => {
+            // Exporters pad the table to 256 entries; keep the ones in use
+            let used = indices.iter().copied().chain([transparent_color]).max().unwrap_or(0);
+            table[..=used as usize].to_vec()
+        }
+    };
+    if let Some(i) = indices.iter().position(|&index| index as usize >= palette.len()) {
+        return Err(anyhow!("Pixel {} uses index {}, past the {}-colour palette", i, indices[i], palette.len()));
+    }
+
+    Ok(Lf2Image {
+        width: meta.width as u16,
+        height: meta.height as u16,
+        x_offset: meta.x_offset.unwrap_or(0),
+        y_offset: meta.y_offset.unwrap_or(0),
+        transparent_color,
+        color_count: palette.len() as u8,
+        palette,
+        pixels: indices.clone(),
+    })
+}
+
+/// Rebuild a PDT image from exported RGBA pixels; alpha becomes the mask
+pub fn pdt_from_rgba(rgba: &[u8], meta: &ImageMetadata) -> Result<PdtImage> {
+    if rgba.len() != meta.width as usize * meta.height as usize * 4 {
+        return Err(anyhow!(
+            "Image size mismatch: sidecar says {}x{}, got {} RGBA bytes",
+            meta.width, meta.height, rgba.len()
+        ));
+    }
+    Ok(PdtImage {
+        width: meta.width,
+        height: meta.height,
+        file_length: 0,
+        layout: PdtLayout::Standard,
+        mask_offset: 0,
+        pixels: rgba.chunks_exact(4).map(|px| RgbColor { r: px[0], g: px[1], b: px[2] }).collect(),
+        alpha_mask: rgba.chunks_exact(4).map(|px| px[3]).collect(),
+    })
+}
+
+/// The export as a [`BmpImage`] when it is an 8-bit or 24-bit BMP; other
+/// files (and 32-bit BMPs) are left to the image crate
+fn export_bmp(export_path: &Path) -> Result<Option<BmpImage>> {
+    if crate::paths::extension_lower(export_path).as_deref() != Some("bmp") {
+        return Ok(None);
+    }
+    let data = std::fs::read(export_path)?;
+    if !BmpImage::supports(&data) {
+        return Ok(None);
+    }
+    BmpImage::from_data(&data)
+        .map(Some)
+        .map_err(|e| anyhow!("Failed to read {}: {}", export_path.display(), e))
+}
+
+/// RGBA pixels of an export on the full sidecar canvas
+fn export_rgba(export_path: &Path, meta: &ImageMetadata) -> Result<Vec<u8>> {
+    let rgba = match export_bmp(export_path)? {
+        Some(bmp) => bmp.rgba,
+        None => image::open(export_path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", export_path.display(), e))?
+            .to_rgba8()
+            .into_raw(),
+    };
+    match meta.trim {
+        Some(rect) => rect.untrim(&rgba, meta.width, meta.height),
+        None => Ok(rgba),
+    }
+}
+
+/// Formats [`reencode_from_export`] can write back
+pub fn supports(format: &FormatType) -> bool {
+    matches!(format, FormatType::ToHeartLf2 | FormatType::ToHeartScn | FormatType::KanonPdt)
+}
+
+/// Re-encode `export_path` (PNG/BMP) using its sidecar
+pub fn reencode_from_export(export_path: &Path, encoder: Option<Lf2Encoder>) -> Result<ReencodeOutcome> {
+    reencode_from_export_with(export_path, encoder, &QuantizeOptions::default())
+}
+
+/// [`reencode_from_export`] with control over how RGBA pixels become
+/// palette indices (see [`lf2_from_rgba`])
+pub fn reencode_from_export_with(
+    export_path: &Path,
+    encoder: Option<Lf2Encoder>,
+    options: &QuantizeOptions,
+) -> Result<ReencodeOutcome> {
+    let meta = ImageMetadata::load(&sidecar_path(export_path))?;
+
+    if !supports(&meta.format) {
+        return Err(anyhow!("Re-encoding {} is not supported", meta.format));
+    }
+    if meta.format == FormatType::KanonPdt {
+        return reencode_pdt(export_path, &meta);
+    }
+
+    let image = match export_bmp(export_path)? {
+        Some(bmp) => lf2_from_bmp(&bmp, &meta, options)?,
+        None => lf2_from_rgba(&export_rgba(export_path, &meta)?, &meta, options)?,
+    };
+
+    let encoder = match (encoder, meta.encoder.as_deref()) {
+        (Some(e), _) => e,
+        (None, Some(name)) => Lf2Encoder::from_name(name)?.with_seed(meta.seed.unwrap_or(DEFAULT_SEED)),
+        (None, None) => Lf2Encoder::Okumura,
+    };
+    info!("Re-encoding {} with {} encoder", export_path.display(), encoder);
+
+    let bytes = encoder.encode(&image)?;
+
+    let check = Lf2Image::from_data(&bytes)?;
+    if check.pixels != image.pixels {
+        return Err(anyhow!("Re-encoded LF2 does not round-trip"));
+    }
+
+    let hash_match = source_hash_match(&bytes, &meta);
+    Ok(ReencodeOutcome { bytes, format: meta.format, encoder: Some(encoder), hash_match })
+}
+
+/// PDT exports are rebuilt with the literal-only writer, which decodes to
+/// the same pixels but is never byte-identical to the original
+fn reencode_pdt(export_path: &Path, meta: &ImageMetadata) -> Result<ReencodeOutcome> {
+    let image = pdt_from_rgba(&export_rgba(export_path, meta)?, meta)?;
+    info!("Re-encoding {} as PDT", export_path.display());
+    let bytes = image.to_pdt_bytes(image.layout);
+
+    let check = PdtImage::from_data(&bytes)?;
+    let same = |a: &[RgbColor], b: &[RgbColor]| a.iter().zip(b).all(|(x, y)| (x.r, x.g, x.b) == (y.r, y.g, y.b));
+    if !same(&check.pixels, &image.pixels) || check.alpha_mask != image.alpha_mask {
+        return Err(anyhow!("Re-encoded PDT does not round-trip"));
+    }
+
+    let hash_match = source_hash_match(&bytes, meta);
+    Ok(ReencodeOutcome { bytes, format: meta.format.clone(), encoder: None, hash_match })
+}
+
+fn source_hash_match(bytes: &[u8], meta: &ImageMetadata) -> Option<bool> {
+    if meta.source_sha256.is_empty() {
+        return None;
+    }
+    let matches = sha256_hex(bytes) == meta.source_sha256;
+    if !matches {
+        warn!("Re-encoded file is pixel-identical but not byte-identical to {}", meta.source_file);
+    }
+    Some(matches)
+}
+
+/// Result of re-encoding an original LF2 file and comparing the bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Identical,
+    /// Same pixels, but the bytes differ; `first_diff` is the first differing
+    /// offset (the shorter length if one file is a prefix of the other)
+    Differs {
+        first_diff: usize,
+        /// Offsets whose bytes differ, counting every byte past the end of
+        /// the shorter file
+        differing_bytes: usize,
+        /// Re-encoded size minus original size
+        size_delta: i64,
+    },
+}
+
+/// Decode `data` as LF2, re-encode it with `encoder` and compare byte by byte
+pub fn verify_lf2(data: &[u8], encoder: Lf2Encoder) -> Result<VerifyOutcome> {
+    let image = Lf2Image::from_data(data)?;
+    let bytes = encoder.encode(&image)?;
+    let first_diff = data.iter().zip(&bytes).position(|(a, b)| a != b)
+        .or_else(|| (data.len() != bytes.len()).then(|| data.len().min(bytes.len())));
+    Ok(match first_diff {
+        None => VerifyOutcome::Identical,
+        Some(first_diff) => VerifyOutcome::Differs {
+            first_diff,
+            differing_bytes: data.iter().zip(&bytes).filter(|(a, b)| a != b).count() + data.len().abs_diff(bytes.len()),
+            size_delta: bytes.len() as i64 - data.len() as i64,
+        },
+    })
+}
+
+/// How many files of a corpus one encoder reproduces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderScore {
+    pub encoder: Lf2Encoder,
+    pub identical: usize,
+    pub differs: usize,
+    /// Files the encoder failed on (the decision tree without its model)
+    pub errors: usize,
+}
+
+/// Verify every original with every encoder, best-scoring encoder first;
+/// each encoder stands for one hypothesis about the original tool
+pub fn scoreboard<'a, I>(originals: I, encoders: &[Lf2Encoder]) -> Vec<EncoderScore>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut scores: Vec<EncoderScore> = encoders.iter()
+        .map(|&encoder| EncoderScore { encoder, identical: 0, differs: 0, errors: 0 })
+        .collect();
+    for data in originals {
+        for score in &mut scores {
+            match verify_lf2(data, score.encoder) {
+                Ok(VerifyOutcome::Identical) => score.identical += 1,
+                Ok(VerifyOutcome::Differs { .. }) => score.differs += 1,
+                Err(_) => score.errors += 1,
+            }
+        }
+    }
+    scores.sort_by(|a, b| b.identical.cmp(&a.identical).then(a.errors.cmp(&b.errors)));
+    scores
+}
+
+/// Consecutive display rows touched by mismatching tokens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffRegion {
+    /// First and last display row, inclusive
+    pub rows: (u32, u32),
+    /// Leftmost and rightmost affected column, inclusive
+    pub columns: (u32, u32),
+    /// Mismatching tokens of the original stream starting in these rows
+    pub tokens: usize,
+    /// Distinct flag blocks those tokens belong to
+    pub flag_blocks: usize,
+    /// Of `tokens`, those in the flag block that finishes their scanline
+    pub at_row_end: usize,
+}
+
+/// Where an original LF2 and its re-encoding disagree, in image terms
+#[derive(Debug, Clone)]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    /// Differing bytes in the header and palette
+    pub header_bytes: usize,
+    /// Tokens in the original pixel stream
+    pub tokens: usize,
+    /// Original tokens with no identical token at the same output position
+    /// in the re-encoding
+    pub mismatched_tokens: usize,
+    pub regions: Vec<DiffRegion>,
+    /// Display-order mask, true for pixels produced by a mismatching token
+    pub mask: Vec<bool>,
+}
+
+impl DiffReport {
+    /// [`mask`](Self::mask) as an image: affected pixels white, others black
+    pub fn mask_image(&self) -> image::GrayImage {
+        image::GrayImage::from_fn(self.width, self.height, |x, y| {
+            image::Luma([if self.mask[(y * self.width + x) as usize] { 255 } else { 0 }])
+        })
+    }
+}
+
+/// Re-encode `data` with `encoder` and map every original token that the
+/// encoder did not reproduce back to the pixels it decodes to. Tokens are
+/// compared by output position, so one diverging match choice does not
+/// flag the rest of the stream the way a byte-offset comparison would.
+pub fn localize_lf2_diff(data: &[u8], encoder: Lf2Encoder) -> Result<DiffReport> {
+    let image = Lf2Image::from_data(data)?;
+    let bytes = encoder.encode(&image)?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    let total = width * height;
+    let stream_start = 0x18 + image.color_count as usize * 3;
+    let header_bytes = data[..stream_start].iter().zip(&bytes[..stream_start.min(bytes.len())])
+        .filter(|(a, b)| a != b)
+        .count();
+
+    let spec = crate::lzss::LzssSpec::LF2;
+    let original = spec.tokens(&data[stream_start..], total);
+    let rebuilt: std::collections::HashMap<usize, Vec<u8>> = spec
+        .tokens(bytes.get(stream_start..).unwrap_or_default(), total)
+        .into_iter()
+        .map(|t| (t.output.start, t.bytes))
+        .collect();
+
+    // Stream offsets are stored order (bottom row first)
+    let display_row = |pixel: usize| crate::formats::flipped_row(pixel / width.max(1), height);
+    let mut row_end_block = vec![None; height];
+    for token in &original {
+        if let Some(last) = token.output.end.checked_sub(1) {
+            for stored_row in token.output.start / width.max(1)..=last / width.max(1) {
+                if (stored_row + 1) * width <= token.output.end {
+                    row_end_block[crate::formats::flipped_row(stored_row, height)] = Some(token.flag_offset);
+                }
+            }
+        }
+    }
+
+    let mut mask = vec![false; total];
+    let mut per_row: Vec<Vec<&crate::lzss::Token>> = vec![Vec::new(); height];
+    for token in &original {
+        if rebuilt.get(&token.output.start) == Some(&token.bytes) {
+            continue;
+        }
+        for pixel in token.output.clone() {
+            mask[display_row(pixel) * width + pixel % width] = true;
+        }
+        per_row[display_row(token.output.start)].push(token);
+    }
+
+    // Flag blocks are collected per region since one can span rows
+    let mut regions: Vec<(DiffRegion, Vec<usize>)> = Vec::new();
+    for (row, tokens) in per_row.iter().enumerate() {
+        if tokens.is_empty() {
+            continue;
+        }
+        let row = row as u32;
+        let columns = tokens.iter()
+            .flat_map(|t| t.output.clone())
+            .map(|p| (p % width) as u32)
+            .fold((u32::MAX, 0), |(lo, hi), c| (lo.min(c), hi.max(c)));
+        let blocks = tokens.iter().map(|t| t.flag_offset);
+        let at_row_end = tokens.iter().filter(|t| row_end_block[row as usize] == Some(t.flag_offset)).count();
+
+        match regions.last_mut() {
+            Some((region, region_blocks)) if region.rows.1 + 1 == row => {
+                region.rows.1 = row;
+                region.columns = (region.columns.0.min(columns.0), region.columns.1.max(columns.1));
+                region.tokens += tokens.len();
+                region.at_row_end += at_row_end;
+                region_blocks.extend(blocks);
+            }
+            _ => regions.push((
+                DiffRegion { rows: (row, row), columns, tokens: tokens.len(), flag_blocks: 0, at_row_end },
+                blocks.collect(),
+            )),
+        }
+    }
+    let regions = regions.into_iter()
+        .map(|(mut region, mut blocks)| {
+            blocks.sort_unstable();
+            blocks.dedup();
+            region.flag_blocks = blocks.len();
+            region
+        })
+        .collect();
+
+    Ok(DiffReport {
+        width: width as u32,
+        height: height as u32,
+        header_bytes,
+        tokens: original.len(),
+        mismatched_tokens: per_row.iter().map(Vec::len).sum(),
+        regions,
+        mask,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scanline_encoder_and_scoreboard() {
+        use crate::formats::toheart::lf2_tokens::{decompress_to_tokens, LeafToken};
+
+        let image = Lf2Image {
+            width: 16,
+            height: 8,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 7,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 7],
+            pixels: (0..128).map(|i| (i % 7) as u8).collect(),
+        };
+        let scanline = Lf2Encoder::from_name("scanline").unwrap().encode(&image).unwrap();
+        check_lf2_encoding(&image, &scanline).unwrap();
+        let tokens = decompress_to_tokens(&scanline[0x18 + 3 * 7..], 16, 8).unwrap().tokens;
+        let mut position = 0;
+        for token in tokens {
+            let len = match token {
+                LeafToken::Literal(_) => 1,
+                LeafToken::Match { len, .. } => len as usize,
+            };
+            assert!(position % 16 + len <= 16, "token at {} crosses a row", position);
+            position += len;
+        }
+
+        let okumura = Lf2Encoder::Okumura.encode(&image).unwrap();
+        assert_ne!(okumura, scanline);
+        let scores = scoreboard(
+            [&okumura[..], &scanline[..], &scanline[..]],
+            &[Lf2Encoder::Okumura, Lf2Encoder::Scanline],
+        );
+        assert_eq!(scores, [
+            EncoderScore { encoder: Lf2Encoder::Scanline, identical: 2, differs: 1, errors: 0 },
+            EncoderScore { encoder: Lf2Encoder::Okumura, identical: 1, differs: 2, errors: 0 },
+        ]);
+    }
+
+    #[test]
+    fn randomized_encoder_is_reproducible() {
+        use crate::formats::toheart::strategy_rng::StrategyRng;
+
+        let noise = StrategyRng::new(7);
+        let image = Lf2Image {
+            width: 64,
+            height: 16,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 3],
+            pixels: (0..1024).map(|i| noise.below(i / 3, 3) as u8).collect(),
+        };
+        let encoder = Lf2Encoder::from_name("randomized").unwrap().with_seed(42);
+        assert_eq!(encoder.seed(), Some(42));
+        assert_eq!(encoder.to_string(), "randomized (seed 42)");
+        assert_eq!(Lf2Encoder::Okumura.with_seed(42).seed(), None);
+
+        let bytes = encoder.encode(&image).unwrap();
+        check_lf2_encoding(&image, &bytes).unwrap();
+        assert_eq!(encoder.encode(&image).unwrap(), bytes);
+        assert_ne!(encoder.with_seed(43).encode(&image).unwrap(), bytes);
+    }
+
+    #[test]
+    fn lf2_export_reencodes_to_same_pixels() {
+        let original = Lf2Image {
+            width: 3,
+            height: 2,
+            x_offset: 4,
+            y_offset: 8,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![
+                Rgb { r: 255, g: 0, b: 0 },
+                Rgb { r: 255, g: 0, b: 0 },
+                Rgb { r: 0, g: 0, b: 255 },
+            ],
+            pixels: vec![0, 1, 2, 2, 1, 0],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("S.LF2");
+        std::fs::write(&source, original.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let export = dir.path().join("S.png");
+        original.save_as_png(&export, &Default::default()).unwrap();
+        crate::formats::sidecar::write_sidecar(&source, &export, FormatType::ToHeartLf2, false).unwrap();
+
+        let outcome = reencode_from_export(&export, None).unwrap();
+        let rebuilt = Lf2Image::from_data(&outcome.bytes).unwrap();
+        assert_eq!(rebuilt.pixels, original.pixels);
+        assert_eq!((rebuilt.x_offset, rebuilt.y_offset), (4, 8));
+        assert_eq!(outcome.hash_match, Some(true));
+
+        // A colour painted in after export: rejected with counts, or snapped
+        let mut edited = image::open(&export).unwrap().to_rgba8();
+        edited.put_pixel(1, 0, image::Rgba([250, 10, 10, 255]));
+        edited.save(&export).unwrap();
+        let error = reencode_from_export(&export, None).unwrap_err().to_string();
+        assert!(error.contains("3 unique colours, 1 of them missing") && error.contains("--auto-quantize"), "{}", error);
+        let options = QuantizeOptions { auto_quantize: true, ..Default::default() };
+        let snapped = reencode_from_export_with(&export, None, &options).unwrap();
+        assert_eq!(Lf2Image::from_data(&snapped.bytes).unwrap().pixels, original.pixels);
+    }
+
+    #[test]
+    fn bmp_exports_reencode() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Palette entries 1 and 2 share a colour; only the BMP indices tell them apart
+        let original = Lf2Image {
+            width: 5,
+            height: 3,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 9, g: 9, b: 9 }, Rgb { r: 9, g: 9, b: 9 }],
+            pixels: (0..15).map(|i| (i % 3) as u8).collect(),
+        };
+        let source = dir.path().join("B.LF2");
+        std::fs::write(&source, original.to_lf2_bytes_okumura().unwrap()).unwrap();
+        let export = dir.path().join("B.bmp");
+        original.save_as_bmp_8bit(&export, &Default::default()).unwrap();
+        let sidecar = crate::formats::sidecar::write_sidecar(&source, &export, FormatType::ToHeartLf2, false).unwrap();
+
+        let outcome = reencode_from_export(&export, None).unwrap();
+        assert_eq!(Lf2Image::from_data(&outcome.bytes).unwrap().pixels, original.pixels);
+        assert_eq!(outcome.hash_match, Some(true));
+
+        // Without a palette in the sidecar the BMP colour table is used
+        let mut meta = ImageMetadata::load(&sidecar).unwrap();
+        meta.palette = None;
+        meta.save(&sidecar).unwrap();
+        let rebuilt = Lf2Image::from_data(&reencode_from_export(&export, None).unwrap().bytes).unwrap();
+        assert_eq!(rebuilt.pixels, original.pixels);
+        assert_eq!(rebuilt.palette.len(), 3);
+
+        // PDT from a 24-bit BMP
+        let pdt = PdtImage {
+            width: 3,
+            height: 2,
+            file_length: 0,
+            layout: PdtLayout::Standard,
+            mask_offset: 0,
+            pixels: (0..6u8).map(|i| RgbColor { r: i * 40, g: 7, b: 255 - i }).collect(),
+            alpha_mask: vec![255; 6],
+        };
+        let source = dir.path().join("P.PDT");
+        std::fs::write(&source, pdt.to_pdt_bytes(PdtLayout::Standard)).unwrap();
+        let export = dir.path().join("P.bmp");
+        image::RgbImage::from_fn(3, 2, |x, y| {
+            let c = pdt.pixels[(y * 3 + x) as usize];
+            image::Rgb([c.r, c.g, c.b])
+        }).save(&export).unwrap();
+        crate::formats::sidecar::write_sidecar(&source, &export, FormatType::KanonPdt, false).unwrap();
+
+        let outcome = reencode_from_export(&export, None).unwrap();
+        assert_eq!(outcome.encoder, None);
+        assert_eq!(outcome.hash_match, Some(true));
+        let rebuilt = PdtImage::from_data(&outcome.bytes).unwrap();
+        assert_eq!(rebuilt.pixels.iter().map(|c| c.r).collect::<Vec<_>>(), [0, 40, 80, 120, 160, 200]);
+    }
+
+    #[test]
+    fn trimmed_export_reencodes_to_full_canvas() {
+        // 6x4 canvas of transparent index 0 with a 2x2 sprite at (3, 1)
+        let mut pixels = vec![0; 24];
+        for i in [9, 10, 15, 16] {
+            pixels[i] = 1 + (i % 2) as u8;
+        }
+        let original = Lf2Image {
+            width: 6,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 0, b: 0 }, Rgb { r: 0, g: 0, b: 255 }],
+            pixels,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("T.LF2");
+        std::fs::write(&source, original.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let export = dir.path().join("T.png");
+        let config = crate::Config { format: "png".to_string(), trim: true, sidecar: true, ..Default::default() };
+        crate::formats::process_rust(&source, &export, FormatType::ToHeartLf2, &config).unwrap();
+        assert_eq!(image::image_dimensions(&export).unwrap(), (2, 2));
+        let meta = ImageMetadata::load(&sidecar_path(&export)).unwrap();
+        assert_eq!(meta.trim, Some(crate::trim::TrimRect { x: 3, y: 1, width: 2, height: 2 }));
+
+        let outcome = reencode_from_export(&export, None).unwrap();
+        assert_eq!(Lf2Image::from_data(&outcome.bytes).unwrap().pixels, original.pixels);
+        assert_eq!(outcome.hash_match, Some(true));
+    }
+
+    #[test]
+    fn verify_reports_first_differing_byte() {
+        let image = Lf2Image {
+            width: 8,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: (0..32).map(|i| (i / 3 % 2) as u8).collect(),
+        };
+        let mut data = image.to_lf2_bytes_okumura().unwrap();
+        assert_eq!(verify_lf2(&data, Lf2Encoder::Okumura).unwrap(), VerifyOutcome::Identical);
+
+        // Trailing garbage decodes to the same pixels but cannot re-encode
+        let len = data.len();
+        data.push(0);
+        assert_eq!(
+            verify_lf2(&data, Lf2Encoder::Okumura).unwrap(),
+            VerifyOutcome::Differs { first_diff: len, differing_bytes: 1, size_delta: -1 }
+        );
+    }
+
+    #[test]
+    fn localize_maps_mismatched_tokens_to_pixels() {
+        let image = Lf2Image {
+            width: 4,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: vec![1; 8],
+        };
+        let reencoded = image.to_lf2_bytes_okumura().unwrap();
+        assert_eq!(localize_lf2_diff(&reencoded, Lf2Encoder::Okumura).unwrap().mismatched_tokens, 0);
+
+        // Same pixels stored as eight literals: only the first one matches
+        // what Okumura emits, the rest of the run becomes one reference
+        let stream_start = 0x18 + 2 * 3;
+        let mut data = reencoded[..stream_start].to_vec();
+        data.push(0x00);
+        data.extend_from_slice(&[0xfe; 8]);
+
+        let report = localize_lf2_diff(&data, Lf2Encoder::Okumura).unwrap();
+        assert_eq!(report.header_bytes, 0);
+        assert_eq!((report.tokens, report.mismatched_tokens), (8, 7));
+        assert_eq!(report.regions, vec![DiffRegion { rows: (0, 1), columns: (0, 3), tokens: 7, flag_blocks: 1, at_row_end: 7 }]);
+        // Stored pixel 0 is the bottom-left one
+        assert!(!report.mask[4] && report.mask[5] && report.mask[0]);
+        assert_eq!(report.mask_image().get_pixel(1, 1).0, [255]);
+    }
+}
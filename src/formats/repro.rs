@@ -0,0 +1,222 @@
+//! Minimal reproductions of LF2 re-encode mismatches
+//!
+//! A file that `verify` cannot reproduce is large and usually differs in
+//! one decision at first: the first token where the original and the
+//! re-encode disagree. Everything before it is identical, so the decision
+//! only depends on the ring buffer at that point and the next (at most
+//! 18) pixels. [`ReproFixture`] stores exactly that, plus the original and
+//! the re-encoded token, as a small JSON file that unit tests can load
+//! without the game data.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use super::reencode::Lf2Encoder;
+use super::toheart::lf2::Lf2Image;
+use super::toheart::lf2_tokens::{enumerate_match_candidates_with_writeback, MatchCandidate};
+use crate::checksum::sha256_hex;
+use crate::formats::magic::LF2_HEADER_SIZE;
+use crate::lzss::{LzssEvent, LzssSpec};
+
+/// One decoded token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ReproToken {
+    Literal { byte: u8 },
+    Match { position: usize, length: usize },
+}
+
+impl ReproToken {
+    /// Output bytes this token writes
+    pub fn length(&self) -> usize {
+        match self {
+            Self::Literal { .. } => 1,
+            Self::Match { length, .. } => *length,
+        }
+    }
+}
+
+/// The first diverging decision of a re-encode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReproFixture {
+    pub source_file: String,
+    pub source_sha256: String,
+    pub encoder: String,
+    pub width: u16,
+    pub height: u16,
+    /// Output offset (stream order) of the diverging token
+    pub output_offset: usize,
+    /// Ring buffer write position before the token
+    pub ring_position: usize,
+    /// Ring buffer before the token
+    #[serde(with = "hex_bytes")]
+    pub ring: Vec<u8>,
+    /// The pixels from `output_offset` on, at most one maximal match
+    #[serde(with = "hex_bytes")]
+    pub lookahead: Vec<u8>,
+    /// What the original file has
+    pub expected: ReproToken,
+    /// What `encoder` produced
+    pub actual: ReproToken,
+}
+
+/// `(output offset, token)` for every literal and reference of `stream`
+fn decode_tokens(stream: &[u8], total: usize) -> (Vec<u8>, Vec<(usize, ReproToken)>) {
+    let mut tokens = Vec::new();
+    let output = LzssSpec::LF2.decompress_events(stream, total, |event, cursor| match *event {
+        LzssEvent::Literal { byte, .. } => tokens.push((cursor.output.len() - 1, ReproToken::Literal { byte })),
+        LzssEvent::Match { position, length, .. } => {
+            let written = length.min(cursor.output.len());
+            tokens.push((cursor.output.len() - written, ReproToken::Match { position, length }));
+        }
+        LzssEvent::Flag { .. } => {}
+    });
+    (output.data, tokens)
+}
+
+impl ReproFixture {
+    /// Re-encode `data` with `encoder` and cut out the first token that
+    /// differs; `None` if every token is reproduced
+    pub fn extract(data: &[u8], encoder: Lf2Encoder, source_file: &str) -> Result<Option<Self>> {
+        let image = Lf2Image::from_data(data)?;
+        let rebuilt = encoder.encode(&image)?;
+        let total = image.width as usize * image.height as usize;
+        let stream_start = LF2_HEADER_SIZE + image.color_count as usize * 3;
+        let (output, original) = decode_tokens(&data[stream_start..], total);
+        let (_, ours) = decode_tokens(rebuilt.get(stream_start..).unwrap_or_default(), total);
+
+        let Some(i) = original.iter().zip(&ours).position(|(a, b)| a != b)
+            .or_else(|| (original.len() != ours.len()).then(|| original.len().min(ours.len())))
+        else {
+            return Ok(None);
+        };
+        let Some(&(offset, expected)) = original.get(i) else {
+            return Err(anyhow!("{} re-encode has tokens past the end of the original", encoder));
+        };
+        let actual = ours.get(i).map(|&(_, t)| t)
+            .ok_or_else(|| anyhow!("{} re-encode ends before token {}", encoder, i))?;
+
+        let spec = LzssSpec::LF2;
+        let mask = spec.window_size - 1;
+        let mut ring = vec![spec.initial_fill; spec.window_size];
+        for (k, &byte) in output[..offset].iter().enumerate() {
+            ring[(spec.initial_position + k) & mask] = byte;
+        }
+        let lookahead = output[offset..output.len().min(offset + spec.max_match)].to_vec();
+
+        Ok(Some(Self {
+            source_file: source_file.to_string(),
+            source_sha256: sha256_hex(data),
+            encoder: encoder.name().to_string(),
+            width: image.width,
+            height: image.height,
+            output_offset: offset,
+            ring_position: (spec.initial_position + offset) & mask,
+            ring,
+            lookahead,
+            expected,
+            actual,
+        }))
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        serde_json::from_slice(&std::fs::read(path)?).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    fn ring_array(&self) -> Result<[u8; 0x1000]> {
+        self.ring.as_slice().try_into().map_err(|_| anyhow!("ring has {} bytes, expected 4096", self.ring.len()))
+    }
+
+    /// Every reference that reproduces the lookahead from this ring state
+    pub fn candidates(&self) -> Result<Vec<MatchCandidate>> {
+        Ok(enumerate_match_candidates_with_writeback(&self.ring_array()?, &self.lookahead, 0, self.ring_position))
+    }
+
+    /// Whether `token` writes the next pixels
+    pub fn reproduces(&self, token: ReproToken) -> Result<bool> {
+        Ok(match token {
+            ReproToken::Literal { byte } => self.lookahead.first() == Some(&byte),
+            ReproToken::Match { position, length } => self.candidates()?
+                .iter()
+                .any(|c| c.pos as usize == position && c.len as usize == length),
+        })
+    }
+
+    /// The fixture is self-contained: both tokens are valid choices for the
+    /// recorded state and they differ
+    pub fn check(&self) -> Result<()> {
+        for (name, token) in [("expected", self.expected), ("actual", self.actual)] {
+            if !self.reproduces(token)? {
+                return Err(anyhow!("{} token {:?} does not reproduce the lookahead", name, token));
+            }
+        }
+        if self.expected == self.actual {
+            return Err(anyhow!("expected and actual tokens are the same"));
+        }
+        Ok(())
+    }
+}
+
+/// Byte vectors as lowercase hex strings
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.len() % 2 != 0 {
+            return Err(serde::de::Error::custom("odd-length hex string"));
+        }
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+
+    #[test]
+    fn extracts_first_diverging_token() {
+        // One colour, 4 wide: Okumura's matches run across rows, the
+        // scanline encoder's stop at the end of each row
+        let image = Lf2Image {
+            width: 4,
+            height: 8,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: vec![1; 32],
+        };
+        let original = image.to_lf2_bytes_okumura().unwrap();
+        assert_eq!(ReproFixture::extract(&original, Lf2Encoder::Okumura, "flat.lf2").unwrap(), None);
+
+        let fixture = ReproFixture::extract(&original, Lf2Encoder::Scanline, "flat.lf2").unwrap().unwrap();
+        assert_eq!(fixture.encoder, "scanline");
+        assert!(fixture.lookahead.iter().all(|&b| b == 1) && !fixture.lookahead.is_empty());
+        assert!(fixture.actual.length() < fixture.expected.length());
+        fixture.check().unwrap();
+
+        let json = fixture.to_json().unwrap();
+        assert!(json.len() < 10_000);
+        let loaded: ReproFixture = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, fixture);
+
+        let broken = ReproFixture { actual: ReproToken::Literal { byte: 7 }, ..fixture };
+        assert!(broken.check().is_err());
+    }
+}
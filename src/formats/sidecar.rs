@@ -0,0 +1,180 @@
+//! Metadata sidecar (`.meta.json`) written next to exported images
+//!
+//! PNG/BMP/raw exports drop information that only the original container
+//! carries: header offsets, the transparent palette index, palette order and
+//! the exact source bytes. The sidecar keeps that information so an export can
+//! later be re-encoded faithfully or traced back to its source file.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use super::FormatType;
+use super::toheart::Lf2Image;
+use super::kanon::PdtImage;
+use crate::checksum::sha256_hex;
+
+/// Sidecar schema version, bumped when fields change meaning
+pub const SIDECAR_VERSION: u32 = 1;
+
+/// Metadata captured for a single exported image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    pub sidecar_version: u32,
+    /// Original file name (lossy UTF-8 for display)
+    pub source_file: String,
+    pub source_sha256: String,
+    pub source_size: u64,
+    pub format: FormatType,
+    /// Container version / magic signature
+    pub version: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_offset: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y_offset: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparent_color: Option<u8>,
+    /// Palette entries in file order as `#rrggbb`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_offset: Option<u32>,
+    /// Preferred encoder for `reencode`, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    /// Seed for a randomized `encoder`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Area of the `width` x `height` canvas a `--trim` export holds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim: Option<crate::trim::TrimRect>,
+    pub tool_version: String,
+}
+
+impl ImageMetadata {
+    /// Build metadata for an LF2 (or SCN) image
+    pub fn from_lf2(source: &Path, data: &[u8], format: FormatType, image: &Lf2Image) -> Self {
+        Self {
+            x_offset: Some(image.x_offset),
+            y_offset: Some(image.y_offset),
+            transparent_color: Some(image.transparent_color),
+            palette: Some(image.palette.iter()
+                .map(|c| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b))
+                .collect()),
+            ..Self::base(source, data, format, "LEAF256", image.width as u32, image.height as u32)
+        }
+    }
+
+    /// Build metadata for a PDT image
+    pub fn from_pdt(source: &Path, data: &[u8], image: &PdtImage) -> Self {
+        Self {
+            mask_offset: Some(image.mask_offset),
+            ..Self::base(source, data, FormatType::KanonPdt, "PDT10", image.width, image.height)
+        }
+    }
+
+    fn base(source: &Path, data: &[u8], format: FormatType, version: &str, width: u32, height: u32) -> Self {
+        Self {
+            sidecar_version: SIDECAR_VERSION,
+            source_file: source.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            source_sha256: sha256_hex(data),
+            source_size: data.len() as u64,
+            format,
+            version: version.to_string(),
+            width,
+            height,
+            x_offset: None,
+            y_offset: None,
+            transparent_color: None,
+            palette: None,
+            mask_offset: None,
+            encoder: None,
+            seed: None,
+            trim: None,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Read metadata from a source file, re-parsing its header
+    pub fn from_source(source: &Path, format: FormatType) -> Result<Self> {
+        let data = std::fs::read(source)?;
+        match format {
+            FormatType::ToHeartLf2 | FormatType::ToHeartScn => {
+                let image = Lf2Image::from_data(&data)?;
+                Ok(Self::from_lf2(source, &data, format, &image))
+            }
+            FormatType::KanonPdt => {
+                let image = PdtImage::from_data(&data)?;
+                Ok(Self::from_pdt(source, &data, &image))
+            }
+            other => Err(anyhow!("Sidecar metadata not supported for {}", other)),
+        }
+    }
+
+    /// Save as pretty JSON
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.save_with(path, false)
+    }
+
+    /// Save as pretty JSON, optionally bypassing the temp file + rename
+    pub fn save_with(&self, path: &Path, direct: bool) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        crate::output::write_bytes(path, direct, json.as_bytes())
+    }
+
+    /// Load from JSON
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read sidecar {}: {}", path.display(), e))?;
+        serde_json::from_str(&text)
+            .map_err(|e| anyhow!("Invalid sidecar {}: {}", path.display(), e))
+    }
+}
+
+/// Sidecar path for an exported image: `out.png` -> `out.meta.json`
+pub fn sidecar_path(output_file: &Path) -> PathBuf {
+    output_file.with_extension("meta.json")
+}
+
+/// Write the sidecar for `output_file` describing `input_path`
+pub fn write_sidecar(input_path: &Path, output_file: &Path, format: FormatType, direct: bool) -> Result<PathBuf> {
+    let meta = ImageMetadata::from_source(input_path, format)?;
+    let path = sidecar_path(output_file);
+    meta.save_with(&path, direct)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+
+    #[test]
+    fn lf2_sidecar_roundtrip() {
+        let image = Lf2Image {
+            width: 2,
+            height: 2,
+            x_offset: 16,
+            y_offset: 32,
+            transparent_color: 1,
+            color_count: 2,
+            palette: vec![Rgb { r: 0x12, g: 0x34, b: 0x56 }, Rgb { r: 0, g: 0, b: 0 }],
+            pixels: vec![0, 1, 1, 0],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("C0101.LF2");
+        std::fs::write(&source, image.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let written = write_sidecar(&source, &dir.path().join("C0101.png"), FormatType::ToHeartLf2, false).unwrap();
+        assert_eq!(written, dir.path().join("C0101.meta.json"));
+
+        let meta = ImageMetadata::load(&written).unwrap();
+        assert_eq!(meta.source_file, "C0101.LF2");
+        assert_eq!((meta.x_offset, meta.y_offset), (Some(16), Some(32)));
+        assert_eq!(meta.transparent_color, Some(1));
+        assert_eq!(meta.palette.unwrap()[0], "#123456");
+        assert_eq!(meta.source_sha256, sha256_hex(&std::fs::read(&source).unwrap()));
+    }
+}
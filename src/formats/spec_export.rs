@@ -0,0 +1,382 @@
+//! Kaitai Struct and 010 Editor templates from [`magic::LAYOUTS`]
+//!
+//! `export-spec --format lf2 --as kaitai` writes a `.ksy` and `--as 010` a
+//! `.bt` binary template, so a file can be taken apart field by field in
+//! the usual reverse-engineering tools. Both are generated from the same
+//! layout table the codecs use. Headers at a fixed offset become the
+//! template's top level, with unnamed gaps as `reserved_<offset>` bytes; a
+//! header that follows variable-length data (MAG) is emitted as a type to
+//! apply by hand. Token grammars are not expressible in either language and
+//! go into the documentation.
+//!
+//! The `spec` subcommand renders the same tables, plus the [`LzssSpec`]
+//! presets, as a Markdown specification ([`markdown`]) with offset tables
+//! and decoder pseudo-code. `docs/format-spec.md` is its output, and a test
+//! fails when the two disagree, so the document cannot drift from the code.
+
+use std::fmt::Write;
+use anyhow::{Result, anyhow};
+
+use super::magic::{self, FormatLayout, TokenGrammar};
+use crate::lzss::{BitOrder, LzssSpec, ReferenceLayout};
+
+/// Template language of [`export`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    /// Kaitai Struct `.ksy`
+    Kaitai,
+    /// 010 Editor binary template `.bt`
+    Bt,
+}
+
+impl TemplateKind {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "kaitai" | "ksy" => Ok(Self::Kaitai),
+            "010" | "bt" => Ok(Self::Bt),
+            _ => Err(anyhow!("Unknown template kind '{}' (kaitai or 010)", name)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Kaitai => "ksy",
+            Self::Bt => "bt",
+        }
+    }
+}
+
+/// The layout named `name` (case-insensitive) or with extension `name`
+pub fn find_layout(name: &str) -> Result<&'static FormatLayout> {
+    let name = name.to_ascii_lowercase();
+    magic::LAYOUTS.iter()
+        .find(|l| l.name.to_ascii_lowercase() == name || l.extensions.contains(&name.as_str()))
+        .ok_or_else(|| {
+            let known: Vec<&str> = magic::LAYOUTS.iter().map(|l| l.name).collect();
+            anyhow!("No layout for '{}' (known: {})", name, known.join(", "))
+        })
+}
+
+/// Template for `layout` in `kind`
+pub fn export(layout: &FormatLayout, kind: TemplateKind) -> String {
+    match kind {
+        TemplateKind::Kaitai => kaitai(layout),
+        TemplateKind::Bt => binary_template(layout),
+    }
+}
+
+/// A header field or an unnamed gap
+struct Slot {
+    name: String,
+    size: usize,
+}
+
+/// Header fields with the gaps between them (and up to the header size)
+/// filled by `reserved_<offset>` entries
+fn header_slots(layout: &FormatLayout) -> Vec<Slot> {
+    let mut slots = Vec::new();
+    let gap = |slots: &mut Vec<Slot>, from: usize, to: usize| {
+        if to > from {
+            slots.push(Slot { name: format!("reserved_{:02x}", from), size: to - from });
+        }
+    };
+    let mut end = 0;
+    for field in layout.fields {
+        gap(&mut slots, end, field.offset);
+        slots.push(Slot { name: field.name.to_string(), size: field.size });
+        end = field.offset + field.size;
+    }
+    if let Some(size) = layout.header_size {
+        gap(&mut slots, end, size);
+    }
+    slots
+}
+
+/// Identifier form of the layout name (`PDT10` -> `pdt10`)
+fn ident(layout: &FormatLayout) -> String {
+    layout.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+/// Notes and token grammars as plain lines
+fn doc_lines(layout: &FormatLayout) -> Vec<String> {
+    let mut lines = vec![format!("{} - generated by retro-decode export-spec", layout.name)];
+    if !layout.notes.is_empty() {
+        lines.push(layout.notes.to_string());
+    }
+    for tokens in layout.tokens {
+        lines.push(format!(
+            "LZSS stream of {}: flag bits {:?}, literal flag {}, XOR {:#04x}, literal {} byte(s), \
+             reference {} bytes ({}), window {:#x} from {:#x} filled with {:#04x}, matches {}..={}",
+            tokens.stream, tokens.flag_order, tokens.literal_flag as u8, tokens.xor_key,
+            tokens.literal_size, tokens.reference_size, tokens.reference, tokens.window_size,
+            tokens.initial_position, tokens.initial_fill, tokens.min_match, tokens.max_match
+        ));
+    }
+    lines
+}
+
+fn kaitai(layout: &FormatLayout) -> String {
+    let mut out = String::new();
+    let id = ident(layout);
+    let _ = writeln!(out, "meta:");
+    let _ = writeln!(out, "  id: {}", id);
+    let _ = writeln!(out, "  title: {}", layout.name);
+    let _ = writeln!(out, "  file-extension: [{}]", layout.extensions.join(", "));
+    let _ = writeln!(out, "  endian: {}", if layout.big_endian { "be" } else { "le" });
+    let _ = writeln!(out, "doc: |");
+    for line in doc_lines(layout) {
+        let _ = writeln!(out, "  {}", line);
+    }
+
+    let field = |out: &mut String, indent: &str, f: &Slot| {
+        let _ = writeln!(out, "{}- id: {}", indent, f.name);
+        if f.name == "magic" {
+            return;
+        }
+        match f.size {
+            1 | 2 | 4 | 8 => { let _ = writeln!(out, "{}  type: u{}", indent, f.size); }
+            n => { let _ = writeln!(out, "{}  size: {}", indent, n); }
+        }
+    };
+    let magic = |out: &mut String| {
+        let bytes: Vec<String> = layout.magic.iter().map(|b| format!("{:#04x}", b)).collect();
+        let _ = writeln!(out, "  - id: magic");
+        let _ = writeln!(out, "    contents: [{}]", bytes.join(", "));
+    };
+
+    let _ = writeln!(out, "seq:");
+    if layout.header_at_fixed_offset {
+        for f in header_slots(layout) {
+            if f.name == "magic" {
+                magic(&mut out);
+            } else {
+                field(&mut out, "  ", &f);
+            }
+        }
+    } else {
+        magic(&mut out);
+    }
+    let _ = writeln!(out, "  - id: body");
+    let _ = writeln!(out, "    size-eos: true");
+
+    if !layout.header_at_fixed_offset && !layout.fields.is_empty() {
+        let _ = writeln!(out, "types:");
+        let _ = writeln!(out, "  header:");
+        let _ = writeln!(out, "    doc: Starts after variable-length data, see the format doc");
+        let _ = writeln!(out, "    seq:");
+        for f in header_slots(layout) {
+            field(&mut out, "      ", &f);
+        }
+    }
+    out
+}
+
+fn binary_template(layout: &FormatLayout) -> String {
+    let mut out = String::new();
+    let header_type = format!("{}_HEADER", ident(layout).to_ascii_uppercase());
+    for line in doc_lines(layout) {
+        let _ = writeln!(out, "// {}", line);
+    }
+    let _ = writeln!(out, "{}();", if layout.big_endian { "BigEndian" } else { "LittleEndian" });
+    let _ = writeln!(out);
+
+    if !layout.fields.is_empty() {
+        let _ = writeln!(out, "typedef struct {{");
+        for f in header_slots(layout) {
+            let _ = match (f.name.as_str(), f.size) {
+                ("magic", n) => writeln!(out, "    char magic[{}];", n),
+                (name, 1) => writeln!(out, "    ubyte {};", name),
+                (name, 2) => writeln!(out, "    uint16 {};", name),
+                (name, 4) => writeln!(out, "    uint32 {};", name),
+                (name, 8) => writeln!(out, "    uint64 {};", name),
+                (name, n) => writeln!(out, "    ubyte {}[{}];", name, n),
+            };
+        }
+        let _ = writeln!(out, "}} {};", header_type);
+        let _ = writeln!(out);
+    }
+
+    if layout.header_at_fixed_offset && !layout.fields.is_empty() {
+        let _ = writeln!(out, "{} header;", header_type);
+    } else {
+        let _ = writeln!(out, "char magic[{}];", layout.magic.len());
+        if !layout.fields.is_empty() {
+            let _ = writeln!(out, "// {} starts after variable-length data; apply it at that offset", header_type);
+        }
+    }
+    let _ = writeln!(out, "if (FTell() < FileSize())");
+    let _ = writeln!(out, "    ubyte body[FileSize() - FTell()];");
+    out
+}
+
+/// Named [`LzssSpec`] presets documented by [`markdown`]
+const LZSS_PRESETS: &[(&str, LzssSpec)] = &[("LF2", LzssSpec::LF2), ("Okumura lzss.c", LzssSpec::OKUMURA)];
+
+/// Markdown specification of `layouts`, or of every layout and the LZSS
+/// presets when `layouts` is [`magic::LAYOUTS`]
+pub fn markdown(layouts: &[FormatLayout]) -> String {
+    let mut out = String::from(
+        "# Format specification\n\n\
+         Generated by `retro-decode spec` from the layout tables the decoders use; \
+         do not edit by hand.\n",
+    );
+    for layout in layouts {
+        markdown_layout(&mut out, layout);
+    }
+    if layouts == magic::LAYOUTS {
+        let _ = writeln!(out, "\n## LZSS presets\n");
+        let _ = writeln!(out, "| Preset | Window | Fill | Start | Match | XOR | Literal flag | Flag bits | Reference |");
+        let _ = writeln!(out, "|---|---|---|---|---|---|---|---|---|");
+        for (name, spec) in LZSS_PRESETS {
+            let _ = writeln!(
+                out,
+                "| {} | {:#x} | {:#04x} | {:#x} | {}..={} | {:#04x} | {} | {} | {} |",
+                name, spec.window_size, spec.initial_fill, spec.initial_position, spec.min_match,
+                spec.max_match, spec.xor_key, spec.literal_flag as u8, bit_order(spec.flag_order),
+                cell(match spec.reference {
+                    ReferenceLayout::LengthFirst => "length first: `len - min` in the low nibble of byte 0, position = `byte0 >> 4 | byte1 << 4`",
+                    ReferenceLayout::PositionFirst => "position first: position = `byte0 | (byte1 & 0xf0) << 4`, `len - min` in the low nibble of byte 1",
+                })
+            );
+        }
+    }
+    out
+}
+
+/// Text for a Markdown table cell: `|` would end the cell, even in code
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn bit_order(order: BitOrder) -> &'static str {
+    match order {
+        BitOrder::MsbFirst => "MSB first",
+        BitOrder::LsbFirst => "LSB first",
+    }
+}
+
+fn markdown_layout(out: &mut String, layout: &FormatLayout) {
+    let magic_text: String = layout.magic.iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { (b as char).to_string() } else { format!("\\x{:02x}", b) })
+        .collect();
+    let magic_hex: Vec<String> = layout.magic.iter().map(|b| format!("{:02x}", b)).collect();
+    let _ = writeln!(out, "\n## {}\n", layout.name);
+    let _ = writeln!(out, "- Extensions: {}", layout.extensions.iter().map(|e| format!("`.{}`", e)).collect::<Vec<_>>().join(", "));
+    let _ = writeln!(out, "- Magic: `{}` ({})", magic_text, magic_hex.join(" "));
+    let _ = writeln!(out, "- Byte order: {}", if layout.big_endian { "big-endian" } else { "little-endian" });
+    if let Some(size) = layout.header_size {
+        let _ = writeln!(out, "- Header size: {:#x} ({}) bytes", size, size);
+    }
+    if !layout.notes.is_empty() {
+        let _ = writeln!(out, "\n{}.", layout.notes.trim_end_matches('.'));
+    }
+
+    if !layout.fields.is_empty() {
+        let _ = writeln!(
+            out,
+            "\n### Header{}\n",
+            if layout.header_at_fixed_offset { "" } else { " (offsets from the header start)" }
+        );
+        let _ = writeln!(out, "| Offset | Size | Field |");
+        let _ = writeln!(out, "|---|---|---|");
+        let mut offset = 0;
+        for slot in header_slots(layout) {
+            let _ = writeln!(out, "| {:#04x} | {} | {} |", offset, slot.size, slot.name);
+            offset += slot.size;
+        }
+    }
+
+    for tokens in layout.tokens {
+        markdown_tokens(out, tokens);
+    }
+}
+
+fn markdown_tokens(out: &mut String, tokens: &TokenGrammar) {
+    let _ = writeln!(out, "\n### LZSS stream: {}\n", tokens.stream);
+    let _ = writeln!(out, "| Parameter | Value |");
+    let _ = writeln!(out, "|---|---|");
+    let rows: [(&str, String); 9] = [
+        ("Flag bits", bit_order(tokens.flag_order).to_string()),
+        ("Literal flag", (tokens.literal_flag as u8).to_string()),
+        ("XOR key", format!("{:#04x}", tokens.xor_key)),
+        ("Literal", format!("{} byte(s)", tokens.literal_size)),
+        ("Reference", format!("{} bytes: {}", tokens.reference_size, tokens.reference)),
+        ("Window", format!("{:#x} units", tokens.window_size)),
+        ("Initial position", format!("{:#x}", tokens.initial_position)),
+        ("Initial fill", format!("{:#04x}", tokens.initial_fill)),
+        ("Match length", format!("{}..={}", tokens.min_match, tokens.max_match)),
+    ];
+    for (name, value) in rows {
+        let _ = writeln!(out, "| {} | {} |", name, cell(&value));
+    }
+
+    let xor = if tokens.xor_key == 0 { String::new() } else { format!(" ^ {:#04x}", tokens.xor_key) };
+    let bits = match tokens.flag_order {
+        BitOrder::MsbFirst => "bit 7 down to bit 0",
+        BitOrder::LsbFirst => "bit 0 up to bit 7",
+    };
+    let _ = writeln!(out, "\n```text");
+    let _ = writeln!(out, "ring[0..{:#x}] = {:#04x}; r = {:#x}", tokens.window_size, tokens.initial_fill, tokens.initial_position);
+    let _ = writeln!(out, "until the output is complete:");
+    let _ = writeln!(out, "    flags = read(1){}", xor);
+    let _ = writeln!(out, "    for each flag bit, {}:", bits);
+    let _ = writeln!(out, "        if bit == {}:", tokens.literal_flag as u8);
+    let _ = writeln!(out, "            unit = read({}){}", tokens.literal_size, xor);
+    let _ = writeln!(out, "            emit unit; ring[r] = unit; r = (r + 1) % {:#x}", tokens.window_size);
+    let _ = writeln!(out, "        else:");
+    let _ = writeln!(out, "            ref = read({}){}", tokens.reference_size, xor);
+    let _ = writeln!(out, "            decode ref: {}", tokens.reference);
+    let _ = writeln!(out, "            repeat length ({}..={}) times:", tokens.min_match, tokens.max_match);
+    let _ = writeln!(out, "                copy the referenced unit: emit it, ring[r] = it, r = (r + 1) % {:#x}", tokens.window_size);
+    let _ = writeln!(out, "```");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_cover_the_header() {
+        let lf2 = find_layout("LF2").unwrap();
+        assert_eq!(find_layout("scn").unwrap(), lf2);
+        assert!(find_layout("gif").is_err());
+        assert!(TemplateKind::parse("ksy").is_ok() && TemplateKind::parse("png").is_err());
+
+        let slots = header_slots(lf2);
+        assert_eq!(slots.iter().map(|f| f.size).sum::<usize>(), magic::LF2_HEADER_SIZE);
+        assert_eq!(slots[5].name, "reserved_10");
+
+        let ksy = export(lf2, TemplateKind::Kaitai);
+        assert!(ksy.contains("  id: lf2\n"), "{}", ksy);
+        assert!(ksy.contains("contents: [0x4c, 0x45, 0x41, 0x46, 0x32, 0x35, 0x36, 0x00]"));
+        assert!(ksy.contains("  - id: color_count\n    type: u1\n"));
+        assert!(ksy.contains("XOR 0xff"));
+        assert!(!ksy.contains("types:"));
+
+        let bt = export(lf2, TemplateKind::Bt);
+        assert!(bt.starts_with("// LF2"));
+        assert!(bt.contains("    uint16 width;\n") && bt.contains("} LF2_HEADER;\n\nLF2_HEADER header;\n"), "{}", bt);
+
+        // MAG's header follows the comment: a type, not the top level
+        let mag = find_layout("mki").unwrap();
+        assert!(export(mag, TemplateKind::Kaitai).contains("types:\n  header:"));
+        assert!(!export(mag, TemplateKind::Bt).contains("MAG_HEADER header;"));
+        assert!(export(find_layout("pi").unwrap(), TemplateKind::Kaitai).contains("endian: be"));
+    }
+
+    #[test]
+    fn markdown_spec_matches_docs() {
+        let spec = markdown(magic::LAYOUTS);
+        assert!(spec.contains("| 0x0c | 2 | width |"), "{}", spec);
+        assert!(spec.contains("| 0x10 | 2 | reserved_10 |"));
+        assert!(spec.contains("- Magic: `LEAF256\\x00` (4c 45 41 46 32 35 36 00)"));
+        assert!(spec.contains("    flags = read(1) ^ 0xff\n"));
+        assert!(spec.contains("| Okumura lzss.c | 0x1000 |"));
+        assert!(!markdown(&[magic::PI]).contains("LZSS presets"));
+
+        let docs = include_str!("../../docs/format-spec.md");
+        assert!(docs == spec, "docs/format-spec.md is out of date; regenerate it with `retro-decode spec -o docs/format-spec.md`");
+    }
+}
@@ -12,6 +12,11 @@
 //! - 環境変数 `RETRO_DECODE_TREE_PATH` が指定されていればそのパスから読む
 //! - そうでなければ `models/lf2_decision_tree.bin`（リポルートからの相対）
 //! - どちらも失敗したらエラー
+//!
+//! ファイル形式（学習済みルールセット）:
+//! - 先頭が [`RULES_MAGIC`] なら、続きは [`LearnedRules`] の bincode
+//!   （早期リテラル数 N と決定木）
+//! - それ以外は旧形式（`TreeNode` 単体の bincode）。N = 0 として読む
 
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
@@ -74,6 +79,54 @@ impl TreeNode {
     }
 }
 
+/// ルールセット形式の先頭 8 バイト
+pub const RULES_MAGIC: &[u8; 8] = b"LF2RULES";
+
+/// 現行のルールセット形式バージョン
+pub const RULES_VERSION: u32 = 1;
+
+/// 学習済みルール一式: 決定木と、その前に適用する早期リテラル規則
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LearnedRules {
+    pub version: u32,
+    /// 先頭 N 出力バイトは候補に関係なくリテラル
+    /// （`EncoderParams::early_literals` と同じ規則）
+    pub early_literals: u64,
+    pub tree: TreeNode,
+}
+
+impl LearnedRules {
+    pub fn new(tree: TreeNode, early_literals: u64) -> Self {
+        Self { version: RULES_VERSION, early_literals, tree }
+    }
+
+    /// ルールセット形式・旧形式のどちらも読む
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.strip_prefix(RULES_MAGIC) {
+            Some(body) => {
+                let rules: LearnedRules = bincode::deserialize(body)
+                    .map_err(|e| anyhow!("decision tree deserialize failed: {}", e))?;
+                if rules.version > RULES_VERSION {
+                    return Err(anyhow!("ruleset version {} is newer than supported ({})", rules.version, RULES_VERSION));
+                }
+                Ok(rules)
+            }
+            None => {
+                let tree: TreeNode = bincode::deserialize(bytes)
+                    .map_err(|e| anyhow!("decision tree deserialize failed: {}", e))?;
+                Ok(Self::new(tree, 0))
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = RULES_MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self)
+            .map_err(|e| anyhow!("Failed to serialize ruleset: {}", e))?;
+        Ok(bytes)
+    }
+}
+
 fn resolve_tree_path() -> PathBuf {
     if let Ok(p) = std::env::var("RETRO_DECODE_TREE_PATH") {
         return PathBuf::from(p);
@@ -84,22 +137,49 @@ fn resolve_tree_path() -> PathBuf {
     PathBuf::from(manifest).join("models/lf2_decision_tree.bin")
 }
 
-fn load_tree() -> Result<TreeNode> {
+fn load_rules() -> Result<LearnedRules> {
     let path = resolve_tree_path();
     let bytes = fs::read(&path)
         .map_err(|e| anyhow!("decision tree load failed at {}: {}", path.display(), e))?;
-    let tree: TreeNode = bincode::deserialize(&bytes)
-        .map_err(|e| anyhow!("decision tree deserialize failed: {}", e))?;
-    Ok(tree)
+    LearnedRules::from_bytes(&bytes)
 }
 
-static TREE: OnceLock<Result<TreeNode, String>> = OnceLock::new();
+static RULES: OnceLock<Result<LearnedRules, String>> = OnceLock::new();
 
-/// グローバル決定木へのアクセサ。最初の呼び出し時にロードしてキャッシュする。
-pub fn global_tree() -> Result<&'static TreeNode> {
-    let cell = TREE.get_or_init(|| load_tree().map_err(|e| e.to_string()));
+/// グローバルなルールセットへのアクセサ。最初の呼び出し時にロードしてキャッシュする。
+pub fn global_rules() -> Result<&'static LearnedRules> {
+    let cell = RULES.get_or_init(|| load_rules().map_err(|e| e.to_string()));
     match cell {
-        Ok(t) => Ok(t),
+        Ok(r) => Ok(r),
         Err(e) => Err(anyhow!("{}", e)),
     }
 }
+
+/// グローバル決定木へのアクセサ
+pub fn global_tree() -> Result<&'static TreeNode> {
+    global_rules().map(|rules| &rules.tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ruleset_round_trip_and_legacy_tree() {
+        let tree = TreeNode::Internal {
+            split: Split { feature: "image_x".to_string(), threshold: 3.5 },
+            left: Box::new(TreeNode::Leaf { choice: 0, count: 4, coverage: 4 }),
+            right: Box::new(TreeNode::Leaf { choice: 2, count: 1, coverage: 1 }),
+            samples: 5,
+        };
+        let bytes = LearnedRules::new(tree.clone(), 640).to_bytes().unwrap();
+        assert!(bytes.starts_with(RULES_MAGIC));
+        let rules = LearnedRules::from_bytes(&bytes).unwrap();
+        assert_eq!((rules.version, rules.early_literals), (RULES_VERSION, 640));
+        assert_eq!(rules.tree.predict(5.0, 0.0, 0.0, 0.0), 2);
+
+        let legacy = LearnedRules::from_bytes(&bincode::serialize(&tree).unwrap()).unwrap();
+        assert_eq!(legacy.early_literals, 0);
+        assert_eq!(legacy.tree.predict(1.0, 0.0, 0.0, 0.0), 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! LF2 encoder profiles
+//!
+//! The research encoders differ in three knobs: which match-finder runs,
+//! how many candidates it examines, and which of several equally long
+//! matches wins. [`EncoderParams`] names those knobs; [`EncodeProfile`] picks
+//! the combinations worth offering on the command line.
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use super::okumura_lzss::{Token, N, THRESHOLD};
+use super::strategy_rng::DEFAULT_SEED;
+
+/// Largest usable match distance: the 4 KiB window minus the 18-byte
+/// lookahead, which the decoder has not written yet
+pub const MAX_SEARCH_DEPTH: usize = 0x1000 - 18;
+
+/// Match-finder used to choose references
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchFinder {
+    /// zlib-style hash chain, newest first; takes the first match of length
+    /// 3 or more (`search_depth` and `tie_break` are ignored)
+    HashChain,
+    /// Linear scan backwards from the write position over `search_depth`
+    /// distances
+    LinearScan,
+    /// Okumura `lzss.c` binary tree without the dummy inserts; the closest
+    /// known match to the original ToHeart encoder (whole window searched)
+    OkumuraTree,
+}
+
+/// Which of several equally long matches is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieBreak {
+    /// First one in search order (`>`)
+    First,
+    /// Last one in search order (`>=`)
+    Last,
+    /// First or last, picked per input position by a
+    /// [`StrategyRng`](super::strategy_rng::StrategyRng) seeded with
+    /// [`EncoderParams::seed`]; linear scan only
+    Seeded,
+}
+
+/// Concrete encoder configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncoderParams {
+    pub finder: MatchFinder,
+    /// Largest match distance considered, 1..=[`MAX_SEARCH_DEPTH`]
+    pub search_depth: usize,
+    pub tie_break: TieBreak,
+    /// Seed for [`TieBreak::Seeded`]; ignored by the other tie-breaks
+    #[serde(default)]
+    pub seed: u64,
+    /// Never let a reference run past the end of an image row (hypothesis:
+    /// the original tool compressed line by line); linear scan only
+    #[serde(default)]
+    pub per_scanline: bool,
+    /// Emit the first `early_literals` output bytes as literals
+    /// (see [`force_early_literals`])
+    #[serde(default)]
+    pub early_literals: usize,
+}
+
+/// Early-literal rule: original streams open with a run of literals while
+/// the ring is still mostly the 0x20 fill. Tokens covering the first
+/// `early` output bytes become literals; a reference straddling the
+/// boundary keeps its tail as a reference (same bytes, source advanced)
+/// when at least 3 bytes remain.
+pub(crate) fn force_early_literals(tokens: Vec<Token>, input: &[u8], early: usize) -> Vec<Token> {
+    if early == 0 {
+        return tokens;
+    }
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut position = 0;
+    for token in tokens {
+        match token {
+            Token::Match { pos, len } if position < early => {
+                let literals = (early - position).min(len as usize);
+                let rest = len as usize - literals;
+                let tail = if rest > THRESHOLD { literals } else { len as usize };
+                out.extend(input[position..position + tail].iter().map(|&b| Token::Literal(b)));
+                if tail < len as usize {
+                    out.push(Token::Match {
+                        pos: (pos + tail as u16) & (N as u16 - 1),
+                        len: rest as u8,
+                    });
+                }
+                position += len as usize;
+            }
+            Token::Match { len, .. } => {
+                out.push(token);
+                position += len as usize;
+            }
+            Token::Literal(_) => {
+                out.push(token);
+                position += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Named speed/accuracy trade-offs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodeProfile {
+    /// Hash chain, first match: quickest, larger output
+    Fast,
+    /// Linear scan over the nearest 1 KiB
+    Balanced,
+    /// Linear scan over the whole window
+    Exhaustive,
+    /// Okumura tree: best byte-identical rate against original files
+    Faithful,
+}
+
+impl EncodeProfile {
+    pub const ALL: [EncodeProfile; 4] = [Self::Fast, Self::Balanced, Self::Exhaustive, Self::Faithful];
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL.into_iter()
+            .find(|p| p.name() == name)
+            .ok_or_else(|| anyhow!("Unknown encode profile: {}", name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Balanced => "balanced",
+            Self::Exhaustive => "exhaustive",
+            Self::Faithful => "faithful",
+        }
+    }
+
+    pub fn params(&self) -> EncoderParams {
+        let (finder, search_depth) = match self {
+            Self::Fast => (MatchFinder::HashChain, MAX_SEARCH_DEPTH),
+            Self::Balanced => (MatchFinder::LinearScan, 0x400),
+            Self::Exhaustive => (MatchFinder::LinearScan, MAX_SEARCH_DEPTH),
+            Self::Faithful => (MatchFinder::OkumuraTree, MAX_SEARCH_DEPTH),
+        };
+        EncoderParams { finder, search_depth, tie_break: TieBreak::First, seed: DEFAULT_SEED, per_scanline: false, early_literals: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn every_profile_round_trips() {
+        let image = Lf2Image {
+            width: 24,
+            height: 12,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 3],
+            pixels: (0..288).map(|i| ((i / 5 + i / 24) % 3) as u8).collect(),
+        };
+        for profile in EncodeProfile::ALL {
+            let bytes = image.to_lf2_bytes_with_params(&profile.params()).unwrap();
+            assert_eq!(Lf2Image::from_data(&bytes).unwrap().pixels, image.pixels, "{}", profile.name());
+            assert_eq!(EncodeProfile::from_name(profile.name()).unwrap(), profile);
+        }
+
+        // The default needs no trained model
+        let exhaustive = image.to_lf2_bytes_with_params(&EncodeProfile::Exhaustive.params()).unwrap();
+        assert_eq!(image.to_lf2_bytes().unwrap(), exhaustive);
+
+        let params = EncoderParams { search_depth: 0, ..EncodeProfile::Balanced.params() };
+        assert!(image.to_lf2_bytes_with_params(&params).is_err());
+
+        for early in [1, 7, 100, 1000] {
+            let params = EncoderParams { early_literals: early, ..EncodeProfile::Exhaustive.params() };
+            let bytes = image.to_lf2_bytes_with_params(&params).unwrap();
+            assert_eq!(Lf2Image::from_data(&bytes).unwrap().pixels, image.pixels, "early {}", early);
+        }
+        let tokens = crate::formats::toheart::okumura_lzss::compress_okumura(&image.pixels);
+        let forced = force_early_literals(tokens, &image.pixels, 7);
+        assert!(forced[..7].iter().all(|t| matches!(t, Token::Literal(_))));
+    }
+}
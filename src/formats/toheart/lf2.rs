@@ -6,16 +6,35 @@ use anyhow::{Result, anyhow};
 use tracing::debug;
 
 use crate::{DecodeConfig, DecodingState, DecodeStep};
+use crate::decoder::Orientation;
+use crate::formats::{flip_rows, flipped_row};
+use crate::lzss::{BitOrder, LzssSpec};
+use crate::timing::{self, Phase};
+use crate::formats::common::{BitFlagWriter, Polarity};
+use crate::formats::magic::{LF2_HEADER_SIZE, LF2_MAGIC};
 use crate::formats::toheart::lf2_tokens::{
     enumerate_match_candidates_with_writeback,
     MatchCandidate as TokenCandidate,
 };
-use crate::formats::toheart::decision_tree::global_tree;
+use crate::formats::toheart::decision_tree::{global_rules, LearnedRules};
+use crate::formats::toheart::encode_profile::{
+    force_early_literals, EncodeProfile, EncoderParams, MatchFinder, TieBreak, MAX_SEARCH_DEPTH,
+};
 
 /// 圧縮戦略選択
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub enum CompressionStrategy {
-    /// 決定木ガイド（Phase 3: CART decision tree, 学習済みバイナリをロード）
+    /// 仕様準拠の貪欲法（既定）: 有効な窓全体から最長一致、同長なら最も近いもの
+    /// （`EncodeProfile::Exhaustive` と同じ）。オリジナルとのバイト一致は狙わない
+    /// が、常に正しくデコードできる。
+    #[default]
+    Greedy,
+    /// 最大一致長を制限した貪欲法: `Greedy` と同じ探索だが、参照は最長
+    /// `n` バイト（3..=18）まで。18 で `Greedy` と同一、3 で全参照が 3 バイトになる。
+    /// 参照長の分布がエンコーダ推定に与える影響を調べる実験用
+    MatchLengthCap(u8),
+    /// 決定木ガイド（Phase 3: CART decision tree, 学習済みバイナリをロード）。
+    /// 研究用: 学習済みモデルが無いと失敗する
     ///
     /// Phase 3 移行で唯一の正規ルートに統合。以前あった 5 戦略
     /// (PerfectAccuracy / OriginalReplication / MachineLearningGuided /
@@ -24,9 +43,6 @@ pub enum CompressionStrategy {
     DecisionTreeGuided,
 }
 
-/// Magic number for LF2 format
-const LF2_MAGIC: &[u8] = b"LEAF256\0";
-
 /// RGB color structure
 #[derive(Debug, Clone, Copy)]
 pub struct Rgb {
@@ -36,6 +52,7 @@ pub struct Rgb {
 }
 
 /// LF2 image structure
+#[derive(Debug, Clone)]
 pub struct Lf2Image {
     pub width: u16,
     pub height: u16,
@@ -48,21 +65,36 @@ pub struct Lf2Image {
 }
 
 impl Lf2Image {
-    /// Create LF2Image from RGB data with quantization
+    /// Create LF2Image from RGB data with at most `max_colors` colours.
+    /// More colours are an error that reports the count; see
+    /// [`from_rgb_image_with`](Self::from_rgb_image_with) to quantize instead.
+    /// The image is placed at (0, 0); see [`with_offset`](Self::with_offset).
     pub fn from_rgb_image(
         width: u16, 
         height: u16, 
         rgb_data: &[u8], 
         max_colors: u8,
         transparent_color: Option<u8>
+    ) -> Result<Self> {
+        Self::from_rgb_image_with(width, height, rgb_data, max_colors, transparent_color, false)
+    }
+
+    /// [`from_rgb_image`](Self::from_rgb_image), reducing an image with too
+    /// many colours to a median-cut palette when `auto_quantize` is set
+    pub fn from_rgb_image_with(
+        width: u16,
+        height: u16,
+        rgb_data: &[u8],
+        max_colors: u8,
+        transparent_color: Option<u8>,
+        auto_quantize: bool,
     ) -> Result<Self> {
         if rgb_data.len() != (width as usize * height as usize * 3) {
             return Err(anyhow!("RGB data size mismatch: expected {} bytes, got {}", 
                 width as usize * height as usize * 3, rgb_data.len()));
         }
         
-        // Create palette from RGB data using simple quantization
-        let (palette, pixels) = Self::quantize_image(rgb_data, width, height, max_colors)?;
+        let (palette, pixels) = Self::quantize_image(rgb_data, max_colors, auto_quantize)?;
         
         let transparent_color = transparent_color.unwrap_or(0);
         
@@ -78,109 +110,61 @@ impl Lf2Image {
         })
     }
     
-    /// Simple color quantization (median cut algorithm would be better)
-    fn quantize_image(rgb_data: &[u8], width: u16, height: u16, max_colors: u8) -> Result<(Vec<Rgb>, Vec<u8>)> {
+    /// The same image placed at (`x`, `y`) on screen
+    pub fn with_offset(self, x: u16, y: u16) -> Self {
+        Self { x_offset: x, y_offset: y, ..self }
+    }
+
+    /// Palette and indices for `rgb_data`: the colours as they are when they
+    /// fit, a median-cut palette with nearest-colour mapping otherwise
+    fn quantize_image(rgb_data: &[u8], max_colors: u8, auto_quantize: bool) -> Result<(Vec<Rgb>, Vec<u8>)> {
         use std::collections::HashMap;
-        
-        let total_pixels = (width as usize) * (height as usize);
-        let mut color_map: HashMap<(u8, u8, u8), usize> = HashMap::new();
-        let mut unique_colors = Vec::new();
-        
-        // Count unique colors
-        for i in 0..total_pixels {
-            let r = rgb_data[i * 3];
-            let g = rgb_data[i * 3 + 1];
-            let b = rgb_data[i * 3 + 2];
-            let color = (r, g, b);
-            
-            if let std::collections::hash_map::Entry::Vacant(e) = color_map.entry(color) {
-                if unique_colors.len() >= max_colors as usize {
-                    break; // Simple truncation - could be improved
-                }
-                e.insert(unique_colors.len());
-                unique_colors.push(Rgb { r, g, b });
-            }
-        }
-        
-        // Create palette
-        let palette = unique_colors;
-        
-        // Map pixels to palette indices
-        let mut pixels = Vec::with_capacity(total_pixels);
-        for i in 0..total_pixels {
-            let r = rgb_data[i * 3];
-            let g = rgb_data[i * 3 + 1]; 
-            let b = rgb_data[i * 3 + 2];
-            let color = (r, g, b);
-            
-            // Find closest color in palette (simple exact match for now)
-            let index = color_map.get(&color)
-                .copied()
-                .unwrap_or_else(|| Self::find_closest_color(&palette, r, g, b));
-            
-            pixels.push(index as u8);
-        }
-        
+        use crate::quantize;
+
+        let colors = || rgb_data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]);
+        let histogram = quantize::color_histogram(colors());
+        let palette = quantize::fit_palette(&histogram, max_colors as usize, auto_quantize, "LF2")?;
+
+        let mut index: HashMap<[u8; 3], u8> = HashMap::new();
+        let pixels = colors()
+            .map(|c| *index.entry(c).or_insert_with(|| quantize::nearest(&palette, c, None) as u8))
+            .collect();
+        let palette = palette.into_iter().map(|[r, g, b]| Rgb { r, g, b }).collect();
         Ok((palette, pixels))
     }
     
-    /// Find closest color in palette (simple Euclidean distance)
-    fn find_closest_color(palette: &[Rgb], r: u8, g: u8, b: u8) -> usize {
-        let mut min_distance = u32::MAX;
-        let mut closest_index = 0;
-        
-        for (i, color) in palette.iter().enumerate() {
-            let dr = (r as i32 - color.r as i32).unsigned_abs();
-            let dg = (g as i32 - color.g as i32).unsigned_abs();
-            let db = (b as i32 - color.b as i32).unsigned_abs();
-            let distance = dr * dr + dg * dg + db * db;
-            
-            if distance < min_distance {
-                min_distance = distance;
-                closest_index = i;
-            }
-        }
-        
-        closest_index
-    }
-    
     /// Save as LF2 format
     pub fn save_as_lf2<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let lf2_data = self.to_lf2_bytes()?;
-        std::fs::write(path, lf2_data)?;
-        Ok(())
+        crate::output::write_bytes(path.as_ref(), false, &lf2_data)
     }
     
-    /// Convert to LF2 binary format (Phase 3: decision tree guided)
+    /// Convert to LF2 binary format with the default (spec-conformant
+    /// greedy) strategy
     pub fn to_lf2_bytes(&self) -> Result<Vec<u8>> {
-        self.to_lf2_bytes_with_strategy(CompressionStrategy::DecisionTreeGuided)
+        self.to_lf2_bytes_with_strategy(CompressionStrategy::default())
     }
 
     /// Convert to LF2 binary format with compression strategy selection
     pub fn to_lf2_bytes_with_strategy(&self, strategy: CompressionStrategy) -> Result<Vec<u8>> {
-        let mut data = Vec::new();
-        data.extend_from_slice(LF2_MAGIC);
-        data.extend_from_slice(&self.x_offset.to_le_bytes());
-        data.extend_from_slice(&self.y_offset.to_le_bytes());
-        data.extend_from_slice(&self.width.to_le_bytes());
-        data.extend_from_slice(&self.height.to_le_bytes());
-        data.extend_from_slice(&[0; 2]); // padding to 0x12
-        data.push(self.transparent_color);
-        data.extend_from_slice(&[0; 3]); // padding to 0x16
-        data.push(self.color_count);
-        data.push(0); // padding to 0x18
-        for color in &self.palette {
-            data.push(color.b);
-            data.push(color.g);
-            data.push(color.r);
+        // Every strategy only chooses tokens; encode_tokens writes the header
+        // and framing
+        match strategy {
+            CompressionStrategy::Greedy => self.to_lf2_bytes_with_params(&EncodeProfile::Exhaustive.params()),
+            CompressionStrategy::MatchLengthCap(max_len) => {
+                use super::naive_scan_lzss::compress_naive_backward_capped;
+                if !(3..=18).contains(&max_len) {
+                    return Err(anyhow!("Match length cap must be 3..=18, got {}", max_len));
+                }
+                Ok(self.encode_tokens(|pixels| {
+                    compress_naive_backward_capped(pixels, max_len as usize, MAX_SEARCH_DEPTH)
+                }))
+            }
+            CompressionStrategy::DecisionTreeGuided => {
+                let rules = global_rules().map_err(|e| anyhow!("decision tree not loaded: {}", e))?;
+                Ok(self.encode_tokens(|pixels| self.decision_tree_tokens(rules, pixels)))
+            }
         }
-
-        let compressed_pixels = match strategy {
-            CompressionStrategy::DecisionTreeGuided => self.compress_lzss_with_decision_tree()?,
-        };
-        data.extend_from_slice(&compressed_pixels);
-
-        Ok(data)
     }
 
     /// 奥村晴彦 lzss.c (1989) 二分木版 Encode を用いた再エンコード（研究用途）。
@@ -192,83 +176,7 @@ impl Lf2Image {
     ///
     /// 戻り値は LF2 完全ファイルバイト列（ヘッダ+パレット+圧縮ペイロード）。
     pub fn to_lf2_bytes_okumura(&self) -> Result<Vec<u8>> {
-        use super::okumura_lzss::{compress_okumura as okumura_encode, Token};
-
-        // ヘッダ・パレットは既存と同じ組み立て（to_lf2_bytes_with_strategy を参照）
-        let mut data = Vec::new();
-        data.extend_from_slice(LF2_MAGIC);
-        data.extend_from_slice(&self.x_offset.to_le_bytes());
-        data.extend_from_slice(&self.y_offset.to_le_bytes());
-        data.extend_from_slice(&self.width.to_le_bytes());
-        data.extend_from_slice(&self.height.to_le_bytes());
-        data.extend_from_slice(&[0; 2]);
-        data.push(self.transparent_color);
-        data.extend_from_slice(&[0; 3]);
-        data.push(self.color_count);
-        data.push(0);
-        for color in &self.palette {
-            data.push(color.b);
-            data.push(color.g);
-            data.push(color.r);
-        }
-
-        // Y-flip 前処理（既存 compress_lzss_ml_guided と同じ）。
-        // デコーダは Y 反転後のバイト列を展開するので、エンコーダ側も
-        // Y 反転後のバイト列を圧縮する必要がある。
-        let w = self.width as usize;
-        let h = self.height as usize;
-        let total_pixels = w * h;
-        let mut input_pixels = vec![0u8; total_pixels];
-        for (pixel_idx, dst) in input_pixels.iter_mut().enumerate() {
-            let x = pixel_idx % w;
-            let y = pixel_idx / w;
-            let flipped_y = h - 1 - y;
-            let output_idx = flipped_y * w + x;
-            if output_idx < self.pixels.len() {
-                *dst = self.pixels[output_idx];
-            }
-        }
-
-        let tokens = okumura_encode(&input_pixels);
-
-        // トークン列を LF2 framing に詰める:
-        // - 8 トークンごとに flag byte（リテラル=1, マッチ=0, MSB ファースト）
-        // - リテラル:   pixel
-        // - マッチ:     upper = (len-3) | ((pos & 0x0f) << 4)
-        //              lower = (pos >> 4) & 0xff
-        // - 全出力バイトに XOR 0xff
-        let mut compressed: Vec<u8> = Vec::new();
-        let mut i = 0usize;
-        while i < tokens.len() {
-            let flag_pos = compressed.len();
-            compressed.push(0); // placeholder
-
-            let mut flag_byte: u8 = 0;
-            let mut bits_used = 0;
-            while bits_used < 8 && i < tokens.len() {
-                match tokens[i] {
-                    Token::Literal(b) => {
-                        flag_byte |= 1 << (7 - bits_used);
-                        compressed.push(b ^ 0xff);
-                    }
-                    Token::Match { pos, len } => {
-                        let encoded_pos = (pos as usize) & 0x0fff;
-                        let encoded_len = ((len as usize) - 3) & 0x0f;
-                        let upper = (encoded_len | ((encoded_pos & 0x0f) << 4)) as u8;
-                        let lower = ((encoded_pos >> 4) & 0xff) as u8;
-                        compressed.push(upper ^ 0xff);
-                        compressed.push(lower ^ 0xff);
-                    }
-                }
-                bits_used += 1;
-                i += 1;
-            }
-
-            compressed[flag_pos] = flag_byte ^ 0xff;
-        }
-
-        data.extend_from_slice(&compressed);
-        Ok(data)
+        Ok(self.encode_tokens(super::okumura_lzss::compress_okumura))
     }
 
     pub fn to_lf2_bytes_naive_strict(&self) -> Result<Vec<u8>> {
@@ -280,7 +188,74 @@ impl Lf2Image {
     }
 
     fn to_lf2_bytes_naive(&self, allow_equal: bool) -> Result<Vec<u8>> {
-        use super::naive_scan_lzss::compress_naive_backward;
+        use super::naive_scan_lzss::{compress_naive_backward, compress_naive_backward_parallel};
+        use super::okumura_lzss::N;
+
+        // Scans the full N - 1 distances (including ones the decoder cannot
+        // reproduce); kept as is for the Issue #3 benches
+        let threads = crate::threads::effective(None);
+        Ok(self.encode_tokens(|pixels| {
+            if threads >= 4 {
+                compress_naive_backward_parallel(pixels, allow_equal, N - 1, threads)
+            } else {
+                compress_naive_backward(pixels, allow_equal)
+            }
+        }))
+    }
+
+    /// Encode with an explicit match-finder configuration
+    /// (see [`EncodeProfile`](super::encode_profile::EncodeProfile))
+    pub fn to_lf2_bytes_with_params(&self, params: &EncoderParams) -> Result<Vec<u8>> {
+        use super::naive_scan_lzss::{
+            compress_hash_chain, compress_naive_backward_parallel, compress_naive_backward_window,
+            compress_naive_backward_scanline, compress_naive_backward_window_with, HashMode,
+        };
+        use super::strategy_rng::StrategyRng;
+        use super::okumura_lzss::{compress_okumura_no_dummy, compress_okumura_no_dummy_eq};
+
+        if !(1..=MAX_SEARCH_DEPTH).contains(&params.search_depth) {
+            return Err(anyhow!("Search depth must be 1..={}, got {}", MAX_SEARCH_DEPTH, params.search_depth));
+        }
+
+        if (params.tie_break == TieBreak::Seeded || params.per_scanline) && params.finder != MatchFinder::LinearScan {
+            return Err(anyhow!("Seeded tie-breaks and per-scanline matching need the linear scan match finder"));
+        }
+        if params.per_scanline && params.tie_break != TieBreak::First {
+            return Err(anyhow!("Per-scanline matching supports the first-match tie-break only"));
+        }
+
+        let allow_equal = params.tie_break == TieBreak::Last;
+        let threads = crate::threads::effective(None);
+        let rng = StrategyRng::new(params.seed);
+        Ok(self.encode_tokens(|pixels| {
+            let tokens = match params.finder {
+                MatchFinder::LinearScan if params.per_scanline => {
+                    compress_naive_backward_scanline(pixels, self.width as usize, params.search_depth)
+                }
+                MatchFinder::LinearScan if params.tie_break == TieBreak::Seeded => {
+                    compress_naive_backward_window_with(pixels, |position| rng.coin(position), params.search_depth)
+                }
+                MatchFinder::HashChain => compress_hash_chain(pixels, HashMode::FirstMatch),
+                // Both produce the same tokens; the parallel scan does several
+                // times the work, so it only wins with enough cores
+                MatchFinder::LinearScan if threads >= 4 => {
+                    compress_naive_backward_parallel(pixels, allow_equal, params.search_depth, threads)
+                }
+                MatchFinder::LinearScan => compress_naive_backward_window(pixels, allow_equal, params.search_depth),
+                MatchFinder::OkumuraTree if allow_equal => compress_okumura_no_dummy_eq(pixels),
+                MatchFinder::OkumuraTree => compress_okumura_no_dummy(pixels),
+            };
+            force_early_literals(tokens, pixels, params.early_literals)
+        }))
+    }
+
+    /// Header + palette, then the pixels in stored order
+    /// compressed by `encode` and packed into LF2 framing
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn encode_tokens<F>(&self, encode: F) -> Vec<u8>
+    where
+        F: FnOnce(&[u8]) -> Vec<super::okumura_lzss::Token>,
+    {
         use super::okumura_lzss::Token;
 
         let mut data = Vec::new();
@@ -289,65 +264,42 @@ impl Lf2Image {
         data.extend_from_slice(&self.y_offset.to_le_bytes());
         data.extend_from_slice(&self.width.to_le_bytes());
         data.extend_from_slice(&self.height.to_le_bytes());
-        data.extend_from_slice(&[0; 2]);
+        data.extend_from_slice(&[0; 2]); // padding to 0x12
         data.push(self.transparent_color);
-        data.extend_from_slice(&[0; 3]);
+        data.extend_from_slice(&[0; 3]); // padding to 0x16
         data.push(self.color_count);
-        data.push(0);
+        data.push(0); // padding to 0x18
         for color in &self.palette {
             data.push(color.b);
             data.push(color.g);
             data.push(color.r);
         }
 
-        let w = self.width as usize;
-        let h = self.height as usize;
-        let total_pixels = w * h;
-        let mut input_pixels = vec![0u8; total_pixels];
-        for (pixel_idx, dst) in input_pixels.iter_mut().enumerate() {
-            let x = pixel_idx % w;
-            let y = pixel_idx / w;
-            let flipped_y = h - 1 - y;
-            let output_idx = flipped_y * w + x;
-            if output_idx < self.pixels.len() {
-                *dst = self.pixels[output_idx];
-            }
-        }
+        let input_pixels = self.stored_pixels();
 
-        let tokens = compress_naive_backward(&input_pixels, allow_equal);
-
-        let mut compressed: Vec<u8> = Vec::new();
-        let mut i = 0usize;
-        while i < tokens.len() {
-            let flag_pos = compressed.len();
-            compressed.push(0);
-
-            let mut flag_byte: u8 = 0;
-            let mut bits_used = 0;
-            while bits_used < 8 && i < tokens.len() {
-                match tokens[i] {
-                    Token::Literal(b) => {
-                        flag_byte |= 1 << (7 - bits_used);
-                        compressed.push(b ^ 0xff);
-                    }
-                    Token::Match { pos, len } => {
-                        let encoded_pos = (pos as usize) & 0x0fff;
-                        let encoded_len = ((len as usize) - 3) & 0x0f;
-                        let upper = (encoded_len | ((encoded_pos & 0x0f) << 4)) as u8;
-                        let lower = ((encoded_pos >> 4) & 0xff) as u8;
-                        compressed.push(upper ^ 0xff);
-                        compressed.push(lower ^ 0xff);
-                    }
+        let tokens = tracing::trace_span!("compress").in_scope(|| encode(&input_pixels));
+
+        // Literal = 1, reference = 0, MSB first; every stored byte XOR 0xff
+        let mut writer = BitFlagWriter::new(BitOrder::MsbFirst, Polarity::Inverted);
+        for token in tokens {
+            match token {
+                Token::Literal(b) => {
+                    writer.push_flag(true);
+                    writer.push(b ^ 0xff);
+                }
+                Token::Match { pos, len } => {
+                    let encoded_pos = (pos as usize) & 0x0fff;
+                    let encoded_len = ((len as usize) - 3) & 0x0f;
+                    let upper = (encoded_len | ((encoded_pos & 0x0f) << 4)) as u8;
+                    let lower = ((encoded_pos >> 4) & 0xff) as u8;
+                    writer.push_flag(false);
+                    writer.extend_from_slice(&[upper ^ 0xff, lower ^ 0xff]);
                 }
-                bits_used += 1;
-                i += 1;
             }
-
-            compressed[flag_pos] = flag_byte ^ 0xff;
         }
 
-        data.extend_from_slice(&compressed);
-        Ok(data)
+        data.extend_from_slice(&writer.finish());
+        data
     }
 
     /// Open LF2 file with high-speed implementation
@@ -357,21 +309,38 @@ impl Lf2Image {
     }
     
     /// Parse LF2 from byte data (optimized for speed)
+    #[tracing::instrument(level = "trace", skip_all)]
     pub fn from_data(data: &[u8]) -> Result<Self> {
-        if data.len() < 24 {
-            return Err(anyhow!("LF2 file too small"));
-        }
-        
-        // Check magic number
-        if &data[0..8] != LF2_MAGIC {
+        Self::parse(data, None)
+    }
+
+    /// [`from_data`](Self::from_data), recording one step per flag byte,
+    /// literal and reference of the pixel stream
+    pub fn from_data_with_steps(data: &[u8], state: &mut DecodingState) -> Result<Self> {
+        Self::parse(data, Some(state))
+    }
+
+    /// Image size from the header alone, without decompressing
+    pub fn dimensions(data: &[u8]) -> Result<(u16, u16)> {
+        // Check magic number; a file cut short inside it still counts as LF2
+        let magic_len = data.len().min(LF2_MAGIC.len());
+        if data[..magic_len] != LF2_MAGIC[..magic_len] {
             return Err(anyhow!("Invalid LF2 magic number"));
         }
+        crate::decoder::check_length(&crate::FormatType::ToHeartLf2, data, 24)?;
+
+        let width = u16::from_le_bytes([data[12], data[13]]);
+        let height = u16::from_le_bytes([data[14], data[15]]);
+        crate::decoder::check_dimensions(&crate::FormatType::ToHeartLf2, width as u64, height as u64)?;
+        Ok((width, height))
+    }
+
+    fn parse(data: &[u8], state: Option<&mut DecodingState>) -> Result<Self> {
+        let (width, height) = Self::dimensions(data)?;
         
         // Parse header using direct memory access for speed
         let x_offset = u16::from_le_bytes([data[8], data[9]]);
         let y_offset = u16::from_le_bytes([data[10], data[11]]);
-        let width = u16::from_le_bytes([data[12], data[13]]);
-        let height = u16::from_le_bytes([data[14], data[15]]);
         
         let transparent_color = data[0x12];
         let color_count = data[0x16];
@@ -380,7 +349,8 @@ impl Lf2Image {
         
         // Read palette (optimized bulk copy)
         let mut palette = Vec::with_capacity(color_count as usize);
-        let palette_start = 0x18;
+        let palette_start = LF2_HEADER_SIZE;
+        crate::decoder::check_length(&crate::FormatType::ToHeartLf2, data, palette_start + color_count as usize * 3)?;
         for i in 0..color_count {
             let base = palette_start + (i as usize) * 3;
             palette.push(Rgb {
@@ -392,7 +362,11 @@ impl Lf2Image {
         
         // Extract compressed pixel data
         let pixel_data_start = palette_start + (color_count as usize) * 3;
-        let pixels = Self::decompress_lzss(&data[pixel_data_start..], width, height)?;
+        let pixels = timing::time(Phase::Decompress, || match state {
+            Some(state) => Ok(Self::decompress_lzss_with_steps(data, pixel_data_start, width, height, state)?),
+            None => Self::decompress_lzss(&data[pixel_data_start..], width, height),
+        })?;
+        timing::image(width as u32, height as u32, || pixels.iter().filter(|&&p| p == transparent_color).count());
         
         Ok(Self {
             width,
@@ -406,125 +380,122 @@ impl Lf2Image {
         })
     }
     
-    /// High-speed LZSS decompression based on original C algorithm
+    /// Decompress the pixel stream and reorder it into display order
+    #[tracing::instrument(level = "trace", skip_all)]
     fn decompress_lzss(compressed_data: &[u8], width: u16, height: u16) -> Result<Vec<u8>> {
         let total_pixels = (width as usize) * (height as usize);
-        let mut pixels = vec![0u8; total_pixels];
-        
-        // Ring buffer for LZSS decompression (4KB = 0x1000)  
-        // Initialize ring buffer to match original C implementation exactly
-        let mut ring = [0x20u8; 0x1000]; // Fill with spaces (0x20) as per original
-        let mut ring_pos = 0x0fee; // Initial position: 4078 (0x0fee)
-        
-        let mut data_pos = 0;
-        let mut pixel_idx = 0;
-        let mut flag = 0u8;
-        let mut flag_count = 0;
-        
-        while pixel_idx < total_pixels && data_pos < compressed_data.len() {
-            // Read flag byte every 8 operations
-            if flag_count == 0 {
-                if data_pos >= compressed_data.len() {
-                    break;
-                }
-                flag = compressed_data[data_pos] ^ 0xff; // XOR with 0xff
-                data_pos += 1;
-                flag_count = 8;
-            }
-            
-            if (flag & 0x80) != 0 {
-                // Direct pixel data
-                if data_pos >= compressed_data.len() {
-                    break;
-                }
-                let pixel = compressed_data[data_pos] ^ 0xff; // XOR with 0xff
-                data_pos += 1;
-                
-                // Store in ring buffer
-                ring[ring_pos] = pixel;
-                ring_pos = (ring_pos + 1) & 0x0fff;
-                
-                // Store in output (with Y-flip for correct orientation)
-                let x = pixel_idx % (width as usize);
-                let y = pixel_idx / (width as usize);
-                let flipped_y = (height as usize) - 1 - y;
-                let output_idx = flipped_y * (width as usize) + x;
-                
-                if output_idx < pixels.len() {
-                    pixels[output_idx] = pixel;
-                }
-                
-                pixel_idx += 1;
-            } else {
-                // Reference to ring buffer
-                if data_pos + 1 >= compressed_data.len() {
-                    break;
-                }
-                
-                let upper = compressed_data[data_pos] ^ 0xff;
-                let lower = compressed_data[data_pos + 1] ^ 0xff;
-                data_pos += 2;
-                
-                let length = ((upper & 0x0f) as usize) + 3;
-                let position = (((upper >> 4) as usize) + ((lower as usize) << 4)) & 0x0fff;
-                
-                // Copy from ring buffer - match C implementation exactly
-                let mut copy_pos = position;
-                for _ in 0..length {
-                    if pixel_idx >= total_pixels {
-                        break;
-                    }
-                    
-                    let pixel = ring[copy_pos];
-                    
-                    // Update ring buffer
-                    ring[ring_pos] = pixel;
-                    ring_pos = (ring_pos + 1) & 0x0fff;
-                    copy_pos = (copy_pos + 1) & 0x0fff;
-                    
-                    // Store in output (with Y-flip matching C implementation)
-                    let x = pixel_idx % (width as usize);
-                    let y = pixel_idx / (width as usize);
-                    let flipped_y = (height as usize) - 1 - y;
-                    let output_idx = flipped_y * (width as usize) + x;
-                    
-                    if output_idx < pixels.len() {
-                        pixels[output_idx] = pixel;
-                    }
-                    
-                    pixel_idx += 1;
-                }
-            }
-            
-            flag <<= 1;
-            flag_count -= 1;
-        }
-        
-        Ok(pixels)
+        let output = LzssSpec::LF2.decompress_stream(compressed_data, total_pixels);
+        timing::tokens(&output);
+        let mut stored = output.data;
+        // A truncated stream leaves the remaining (top) rows at index 0
+        stored.resize(total_pixels, 0);
+        Ok(flip_rows(&stored, width as usize))
     }
+
+    /// [`decompress_lzss`](Self::decompress_lzss) through the event hook of
+    /// the same decoder, turning every event into a [`DecodeStep`]
+    fn decompress_lzss_with_steps(
+        data: &[u8],
+        pixel_data_start: usize,
+        width: u16,
+        height: u16,
+        state: &mut DecodingState,
+    ) -> crate::decoder::Result<Vec<u8>> {
+        use crate::formats::StepOperationType;
+        use crate::lzss::LzssEvent;
+
+        let total_pixels = (width as usize) * (height as usize);
+        state.total_pixels = total_pixels;
+        state.metadata.insert("width".to_string(), width.to_string());
+        state.metadata.insert("height".to_string(), height.to_string());
+
+        let mut produced = 0;
+        let output = LzssSpec::LF2.try_decompress_events(&data[pixel_data_start..], total_pixels, |event, cursor| {
+            let (offset, length, description, explanation, operation_type) = match *event {
+                LzssEvent::Flag { offset, flag } => (
+                    offset,
+                    1,
+                    "フラグバイト".to_string(),
+                    format!("フラグ 0x{:02x}: 続く 8 項目のうち、ビットが 1 のものはリテラル、0 のものは参照です。", flag),
+                    StepOperationType::FlagByte,
+                ),
+                LzssEvent::Literal { offset, byte } => (
+                    offset,
+                    1,
+                    format!("リテラル {}", byte),
+                    format!("パレット番号 {} をそのまま出力し、リングバッファにも書き込みます。", byte),
+                    StepOperationType::DirectPixel { palette_index: byte },
+                ),
+                LzssEvent::Match { offset, position, distance, length } => (
+                    offset,
+                    2,
+                    format!("参照 {}バイト", length),
+                    format!(
+                        "リングバッファ位置 0x{:03x}（{} バイト前）から {} バイトをコピーします。",
+                        position, distance, length
+                    ),
+                    StepOperationType::LzssMatch { distance, length },
+                ),
+            };
+            let written = &cursor.output[produced..];
+            state.try_add_step(&crate::FormatType::ToHeartLf2, DecodeStep {
+                step_number: state.steps.len() + 1,
+                description,
+                explanation,
+                operation_type,
+                raw_bytes: data[pixel_data_start + offset..pixel_data_start + offset + length].to_vec(),
+                data_offset: pixel_data_start + offset,
+                data_length: length,
+                pixels_decoded: cursor.output.len(),
+                memory_state: written.to_vec(),
+                ring_position: cursor.ring_position,
+                partial_image: None,
+            })?;
+            produced = cursor.output.len();
+            Ok(())
+        })?;
+
+        timing::tokens(&output);
+        let mut stored = output.data;
+        state.decoded_pixels = stored.len();
+        stored.resize(total_pixels, 0);
+        Ok(flip_rows(&stored, width as usize))
+    }
+
+    /// Pixel indices in stored order: bottom row first, as the compressed
+    /// stream produces them and the encoders consume them
+    pub fn stored_pixels(&self) -> Vec<u8> {
+        let total_pixels = (self.width as usize) * (self.height as usize);
+        let mut display = self.pixels.clone();
+        display.resize(total_pixels, 0);
+        flip_rows(&display, self.width as usize)
+    }
+
     /// Save in multiple formats based on extension
     pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        config.limits.check_image(&crate::FormatType::ToHeartLf2, self.width as u64, self.height as u64)?;
         // Skip file output for benchmark mode
         if config.no_output {
             return Ok(());
         }
         
-        let extension = output_path.extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("bmp")
-            .to_lowercase();
+        let extension = crate::paths::extension_lower(output_path)
+            .unwrap_or_else(|| "bmp".to_string());
             
         match extension.as_str() {
             "png" => self.save_as_png(output_path, config),
             "raw" => self.save_as_raw_rgb(output_path, config),
             "rgba" => self.save_as_raw_rgba(output_path, config),
+            "rgb565" => self.save_as_rgb565(output_path, config),
+            ext if crate::output::registered_writer(ext).is_some() => {
+                crate::output::write_rgba_image(&self.to_rgba_image(config.orientation), output_path, config.direct_writes)
+            }
             _ => self.save_as_bmp_8bit(output_path, config),
         }
     }
     
     /// Save as authentic 8-bit BMP with palette (fastest, no transparency)
-    pub fn save_as_bmp_8bit(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        use std::fs::File;
+    pub fn save_as_bmp_8bit(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         use std::io::Write;
         
         let width = self.width as u32;
@@ -537,130 +508,156 @@ impl Lf2Image {
         let palette_size = palette_entries * 4; // 4 bytes per color (BGRA)
         let file_size = 54 + palette_size + pixel_data_size as usize; // Standard header + palette + data
         
-        let mut file = File::create(output_path)?;
-        
-        // BMP file header (14 bytes)
-        file.write_all(b"BM")?;                    // Signature
-        file.write_all(&(file_size as u32).to_le_bytes())?;     // File size
-        file.write_all(&0u32.to_le_bytes())?;     // Reserved
-        file.write_all(&(54 + palette_size as u32).to_le_bytes())?; // Offset to pixel data
-        
-        // DIB header (40 bytes) - Standard BITMAPINFOHEADER
-        file.write_all(&40u32.to_le_bytes())?;    // Header size
-        file.write_all(&(width as i32).to_le_bytes())?;         // Width
-        file.write_all(&(height as i32).to_le_bytes())?;        // Height
-        file.write_all(&1u16.to_le_bytes())?;     // Planes
-        file.write_all(&8u16.to_le_bytes())?;     // Bits per pixel (8-bit indexed)
-        file.write_all(&0u32.to_le_bytes())?;     // Compression (none)
-        file.write_all(&pixel_data_size.to_le_bytes())?; // Image size
-        file.write_all(&2835u32.to_le_bytes())?;  // X pixels per meter (72 DPI)
-        file.write_all(&2835u32.to_le_bytes())?;  // Y pixels per meter (72 DPI)
-        file.write_all(&(palette_entries as u32).to_le_bytes())?; // Colors used
-        file.write_all(&0u32.to_le_bytes())?;     // Important colors (0 = all)
-        
-        // Color palette (256 entries × 4 bytes BGRA)
-        for i in 0..palette_entries {
-            if i < self.palette.len() {
-                let color = self.palette[i];
-                file.write_all(&[color.b, color.g, color.r, 0])?; // BGRA format
-            } else {
-                file.write_all(&[0, 0, 0, 0])?; // Black for unused entries
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            // BMP file header (14 bytes)
+            file.write_all(b"BM")?;                    // Signature
+            file.write_all(&(file_size as u32).to_le_bytes())?;     // File size
+            file.write_all(&0u32.to_le_bytes())?;     // Reserved
+            file.write_all(&(54 + palette_size as u32).to_le_bytes())?; // Offset to pixel data
+        
+            // DIB header (40 bytes) - Standard BITMAPINFOHEADER
+            file.write_all(&40u32.to_le_bytes())?;    // Header size
+            file.write_all(&(width as i32).to_le_bytes())?;         // Width
+            file.write_all(&(height as i32).to_le_bytes())?;        // Height
+            file.write_all(&1u16.to_le_bytes())?;     // Planes
+            file.write_all(&8u16.to_le_bytes())?;     // Bits per pixel (8-bit indexed)
+            file.write_all(&0u32.to_le_bytes())?;     // Compression (none)
+            file.write_all(&pixel_data_size.to_le_bytes())?; // Image size
+            file.write_all(&2835u32.to_le_bytes())?;  // X pixels per meter (72 DPI)
+            file.write_all(&2835u32.to_le_bytes())?;  // Y pixels per meter (72 DPI)
+            file.write_all(&(palette_entries as u32).to_le_bytes())?; // Colors used
+            file.write_all(&0u32.to_le_bytes())?;     // Important colors (0 = all)
+        
+            // Color palette (256 entries × 4 bytes BGRA)
+            for i in 0..palette_entries {
+                if i < self.palette.len() {
+                    let color = self.palette[i];
+                    file.write_all(&[color.b, color.g, color.r, 0])?; // BGRA format
+                } else {
+                    file.write_all(&[0, 0, 0, 0])?; // Black for unused entries
+                }
             }
-        }
         
-        // Pixel data (bottom-up scan order with row padding)
-        for y in (0..height).rev() {
-            for x in 0..width {
-                let idx = (y * width + x) as usize;
-                let pixel = if idx < self.pixels.len() { 
-                    self.pixels[idx] 
-                } else { 
-                    0 
+            // Pixel data (BMP rows are bottom-up, with row padding)
+            for row in 0..height {
+                let y = match config.orientation {
+                    Orientation::Display => flipped_row(row as usize, height as usize) as u32,
+                    Orientation::Stored => row,
                 };
-                file.write_all(&[pixel])?;
-            }
+                for x in 0..width {
+                    let idx = (y * width + x) as usize;
+                    let pixel = if idx < self.pixels.len() { 
+                        self.pixels[idx] 
+                    } else { 
+                        0 
+                    };
+                    file.write_all(&[pixel])?;
+                }
             
-            // Pad row to 4-byte boundary
-            for _ in width..row_size {
-                file.write_all(&[0])?;
+                // Pad row to 4-byte boundary
+                for _ in width..row_size {
+                    file.write_all(&[0])?;
+                }
             }
-        }
-        
-        Ok(())
+            Ok(())
+        })
     }
     
     /// Save as raw RGB (fastest, no header, no transparency)
-    pub fn save_as_raw_rgb(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        use std::fs::File;
+    pub fn save_as_raw_rgb(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         use std::io::Write;
         
-        let mut file = File::create(output_path)?;
-        
-        for &pixel_index in &self.pixels {
-            let color = if (pixel_index as usize) < self.palette.len() {
-                self.palette[pixel_index as usize]
-            } else {
-                Rgb { r: 0, g: 0, b: 0 }
-            };
-            
-            // Handle transparency by using black for transparent pixels
-            if pixel_index == self.transparent_color || (pixel_index as usize) >= self.palette.len() {
-                file.write_all(&[0, 0, 0])?; // Black for transparent
-            } else {
-                file.write_all(&[color.r, color.g, color.b])?;
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            for (_, _, px) in self.pixels_rgba_in(config.orientation) {
+                // Transparent pixels become black without an alpha channel
+                let [r, g, b, a] = px.0;
+                if a == 0 {
+                    file.write_all(&[0, 0, 0])?;
+                } else {
+                    file.write_all(&[r, g, b])?;
+                }
             }
-        }
-        
-        Ok(())
+            Ok(())
+        })
+    }
+    
+    /// Save as headerless RGB565 for 16-bit framebuffers (transparent
+    /// pixels become black, as in raw RGB)
+    pub fn save_as_rgb565(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            let pixels = self.pixels_rgba_in(config.orientation)
+                .map(|(_, _, px)| if px[3] == 0 { [0, 0, 0] } else { [px[0], px[1], px[2]] });
+            crate::output::write_rgb565(file, pixels, config.rgb565_order)
+        })
     }
     
     /// Save as raw RGBA (fast, includes transparency) 
-    pub fn save_as_raw_rgba(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        use std::fs::File;
+    pub fn save_as_raw_rgba(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
         use std::io::Write;
         
-        let mut file = File::create(output_path)?;
-        
-        for &pixel_index in &self.pixels {
-            let color = if (pixel_index as usize) < self.palette.len() {
-                self.palette[pixel_index as usize]
-            } else {
-                Rgb { r: 0, g: 0, b: 0 }
-            };
-            
-            let alpha = if pixel_index == self.transparent_color || (pixel_index as usize) >= self.palette.len() { 0 } else { 255 };
-            file.write_all(&[color.r, color.g, color.b, alpha])?;
-        }
-        
-        Ok(())
+        crate::output::write_with(output_path, config.direct_writes, |file| {
+            for (_, _, px) in self.pixels_rgba_in(config.orientation) {
+                file.write_all(&px.0)?;
+            }
+            Ok(())
+        })
     }
     
     /// Save as PNG with transparency (slowest due to compression)
-    pub fn save_as_png(&self, output_path: &Path, _config: &DecodeConfig) -> Result<()> {
-        let mut rgba_data = Vec::with_capacity(self.pixels.len() * 4);
+    pub fn save_as_png(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
+        let img = self.to_rgba_image(config.orientation);
         
-        for &pixel_index in &self.pixels {
-            let color = if (pixel_index as usize) < self.palette.len() {
-                self.palette[pixel_index as usize]
-            } else {
-                Rgb { r: 0, g: 0, b: 0 }
+        crate::output::write_with(output_path, config.direct_writes, |w| {
+            img.write_to(w, image::ImageOutputFormat::Png)?;
+            Ok(())
+        })
+    }
+
+    /// Every pixel as `(x, y, color)` in display order (top row first).
+    ///
+    /// Palette lookup and transparency are resolved here: the transparent
+    /// index keeps its palette color with alpha 0, and indices outside the
+    /// palette are transparent black.
+    pub fn pixels_rgba(&self) -> impl Iterator<Item = (u32, u32, image::Rgba<u8>)> + '_ {
+        self.pixels_rgba_in(Orientation::Display)
+    }
+
+    /// [`pixels_rgba`](Self::pixels_rgba) in the given row order; with
+    /// [`Orientation::Stored`] row `y = 0` is the bottom row of the picture
+    pub fn pixels_rgba_in(&self, orientation: Orientation) -> impl Iterator<Item = (u32, u32, image::Rgba<u8>)> + '_ {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        (0..height).flat_map(move |y| {
+            let row = match orientation {
+                Orientation::Display => y,
+                Orientation::Stored => flipped_row(y, height),
             };
-            
-            let alpha = if pixel_index == self.transparent_color || (pixel_index as usize) >= self.palette.len() { 0 } else { 255 };
-            rgba_data.extend_from_slice(&[color.r, color.g, color.b, alpha]);
+            (0..width).filter_map(move |x| {
+                let index = *self.pixels.get(row * width + x)?;
+                let color = match self.palette.get(index as usize) {
+                    Some(c) if index != self.transparent_color => [c.r, c.g, c.b, 255],
+                    Some(c) => [c.r, c.g, c.b, 0],
+                    None => [0, 0, 0, 0],
+                };
+                Some((x as u32, y as u32, image::Rgba(color)))
+            })
+        })
+    }
+
+    /// The whole image as RGBA (see [`pixels_rgba_in`](Self::pixels_rgba_in));
+    /// missing trailing pixels stay transparent
+    pub fn to_rgba_image(&self, orientation: Orientation) -> image::RgbaImage {
+        let mut img = image::RgbaImage::new(self.width as u32, self.height as u32);
+        for (x, y, px) in self.pixels_rgba_in(orientation) {
+            img.put_pixel(x, y, px);
         }
-        
-        let img = image::RgbaImage::from_raw(self.width as u32, self.height as u32, rgba_data)
-            .ok_or_else(|| anyhow!("Failed to create image"))?;
-        
-        img.save(output_path)?;
-        Ok(())
+        img
     }
     
     /// Decode with step-by-step visualization
     pub fn decode_with_steps(&self, output_path: &Path, state: &mut DecodingState, config: &DecodeConfig) -> Result<()> {
-        // For step-by-step, we'd need to re-decompress with tracking
-        // This is a simplified version - full implementation would re-parse the file
+        // Token-level steps are recorded while parsing (from_data_with_steps);
+        // this only closes the trace
         state.total_pixels = self.pixels.len();
         state.decoded_pixels = self.pixels.len();
         
@@ -678,127 +675,266 @@ impl Lf2Image {
             ring_position: 0,
             partial_image: None,
         };
-        state.add_step(step);
+        state.try_add_step(&crate::FormatType::ToHeartLf2, step)?;
         
         self.decode(output_path, config)
     }
     
-    fn compress_lzss_with_decision_tree(&self) -> Result<Vec<u8>> {
-        // Y-flip pixel data preparation
-        let w = self.width as usize;
-        let h = self.height as usize;
-        let total_pixels = w * h;
-        let mut input_pixels = vec![0u8; total_pixels];
-
-        for (pixel_idx, dst) in input_pixels.iter_mut().enumerate() {
-            let x = pixel_idx % w;
-            let y = pixel_idx / w;
-            let flipped_y = h - 1 - y;
-            let output_idx = flipped_y * w + x;
-
-            if output_idx < self.pixels.len() {
-                *dst = self.pixels[output_idx];
-            }
-        }
+    /// Tokens for `input_pixels` (stored order), choosing among the match
+    /// candidates at each position with the learned decision tree
+    fn decision_tree_tokens(&self, rules: &LearnedRules, input_pixels: &[u8]) -> Vec<super::okumura_lzss::Token> {
+        use super::okumura_lzss::Token;
 
-        let mut compressed = Vec::new();
+        let mut tokens = Vec::new();
         let mut ring = [0x20u8; 0x1000];
         let mut ring_pos: usize = 0x0fee;
         let mut pos: usize = 0;
+        let early_literals = rules.early_literals as usize;
 
         while pos < input_pixels.len() {
-            let mut flag_byte = 0u8;
-            let mut flag_bits_used = 0;
-            let flag_pos = compressed.len();
-            compressed.push(0);
-
-            while flag_bits_used < 8 && pos < input_pixels.len() {
-                let image_x = (pos % (self.width as usize)) as f64;
-                let image_y = (pos / (self.width as usize)) as f64;
-                let ring_r = ring_pos as f64;
-
-                // 学習データ生成と同じ候補列挙関数を使う（自己オーバーラップ対応 +
-                // (pos, len) 全組み合わせ + pos 昇順 → len 昇順）。
-                // 学習時と推論時で候補集合とインデックスが完全一致することが大前提。
-                let matches: Vec<TokenCandidate> = enumerate_match_candidates_with_writeback(
-                    &ring,
-                    &input_pixels,
-                    pos,
-                    ring_pos,
-                );
-
-                if !matches.is_empty() {
-                    // distance 計算は学習時 (lf2_first_diff::full_dataset) と同一式。
-                    let distance_of = |c: &TokenCandidate| -> usize {
-                        let p = c.pos as usize;
-                        if p <= ring_pos {
-                            ring_pos - p
-                        } else {
-                            (0x1000 - p) + ring_pos
-                        }
-                    };
-                    let mut min_distance = usize::MAX;
-                    let mut min_distance_length: f64 = 0.0;
-                    for candidate in &matches {
-                        let d = distance_of(candidate);
-                        if d < min_distance {
-                            min_distance = d;
-                            min_distance_length = candidate.len as f64;
-                        }
+            let image_x = (pos % (self.width as usize)) as f64;
+            let image_y = (pos / (self.width as usize)) as f64;
+            let ring_r = ring_pos as f64;
+
+            // 学習データ生成と同じ候補列挙関数を使う（自己オーバーラップ対応 +
+            // (pos, len) 全組み合わせ + pos 昇順 → len 昇順）。
+            // 学習時と推論時で候補集合とインデックスが完全一致することが大前提。
+            let matches: Vec<TokenCandidate> = enumerate_match_candidates_with_writeback(
+                &ring,
+                input_pixels,
+                pos,
+                ring_pos,
+            );
+
+            if !matches.is_empty() && pos >= early_literals {
+                // distance 計算は学習時 (lf2_first_diff::full_dataset) と同一式。
+                let distance_of = |c: &TokenCandidate| -> usize {
+                    let p = c.pos as usize;
+                    if p <= ring_pos {
+                        ring_pos - p
+                    } else {
+                        (0x1000 - p) + ring_pos
                     }
+                };
+                let mut min_distance = usize::MAX;
+                let mut min_distance_length: f64 = 0.0;
+                for candidate in &matches {
+                    let d = distance_of(candidate);
+                    if d < min_distance {
+                        min_distance = d;
+                        min_distance_length = candidate.len as f64;
+                    }
+                }
 
-                    let tree = global_tree().map_err(|e| anyhow!(
-                        "decision tree not loaded: {}", e
-                    ))?;
-                    let best_idx = tree.predict(image_x, min_distance_length, image_y, ring_r);
-                    let best_idx = std::cmp::min(best_idx, matches.len() - 1);
-                    let best_match = &matches[best_idx];
-
-                    if best_match.len >= 3 {
-                        let position = best_match.pos as usize;
-                        let match_len = best_match.len as usize;
-
-                        let encoded_pos = position & 0x0fff;
-                        let encoded_len = (match_len - 3) & 0x0f;
-
-                        let upper_byte = (encoded_len | ((encoded_pos & 0x0f) << 4)) as u8;
-                        let lower_byte = ((encoded_pos >> 4) & 0xff) as u8;
+                let best_idx = rules.tree.predict(image_x, min_distance_length, image_y, ring_r);
+                let best_idx = std::cmp::min(best_idx, matches.len() - 1);
+                let best_match = &matches[best_idx];
 
-                        compressed.push(upper_byte ^ 0xff);
-                        compressed.push(lower_byte ^ 0xff);
+                if best_match.len >= 3 {
+                    let position = best_match.pos as usize;
+                    let match_len = best_match.len as usize;
 
-                        let mut copy_pos = position;
-                        for _ in 0..match_len {
-                            let byte_from_ring = ring[copy_pos];
-                            ring[ring_pos] = byte_from_ring;
-                            ring_pos = (ring_pos + 1) & 0x0fff;
-                            copy_pos = (copy_pos + 1) & 0x0fff;
-                        }
+                    tokens.push(Token::Match { pos: position as u16, len: match_len as u8 });
 
-                        pos += match_len;
-                    } else {
-                        flag_byte |= 1 << (7 - flag_bits_used);
-                        compressed.push(input_pixels[pos] ^ 0xff);
-
-                        ring[ring_pos] = input_pixels[pos];
+                    let mut copy_pos = position;
+                    for _ in 0..match_len {
+                        let byte_from_ring = ring[copy_pos];
+                        ring[ring_pos] = byte_from_ring;
                         ring_pos = (ring_pos + 1) & 0x0fff;
-                        pos += 1;
+                        copy_pos = (copy_pos + 1) & 0x0fff;
                     }
+
+                    pos += match_len;
                 } else {
-                    flag_byte |= 1 << (7 - flag_bits_used);
-                    compressed.push(input_pixels[pos] ^ 0xff);
+                    tokens.push(Token::Literal(input_pixels[pos]));
 
                     ring[ring_pos] = input_pixels[pos];
                     ring_pos = (ring_pos + 1) & 0x0fff;
                     pos += 1;
                 }
+            } else {
+                tokens.push(Token::Literal(input_pixels[pos]));
 
-                flag_bits_used += 1;
+                ring[ring_pos] = input_pixels[pos];
+                ring_pos = (ring_pos + 1) & 0x0fff;
+                pos += 1;
             }
+        }
+
+        tokens
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2_tokens::{decompress_to_tokens, LeafToken};
+
+    #[test]
+    fn match_length_cap_limits_every_reference() {
+        let image = Lf2Image {
+            width: 32,
+            height: 8,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: (0..256).map(|i| (i / 40 % 2) as u8).collect(),
+        };
+        let payload_start = LF2_HEADER_SIZE + 3 * image.palette.len();
+
+        for cap in 3..=18u8 {
+            let bytes = image.to_lf2_bytes_with_strategy(CompressionStrategy::MatchLengthCap(cap)).unwrap();
+            assert_eq!(Lf2Image::from_data(&bytes).unwrap().pixels, image.pixels, "cap {}", cap);
+            let tokens = decompress_to_tokens(&bytes[payload_start..], image.width, image.height).unwrap().tokens;
+            let longest = tokens.iter()
+                .filter_map(|t| match t {
+                    LeafToken::Match { len, .. } => Some(*len),
+                    LeafToken::Literal(_) => None,
+                })
+                .max();
+            assert_eq!(longest, Some(cap), "cap {}", cap);
+        }
+
+        let greedy = image.to_lf2_bytes_with_strategy(CompressionStrategy::Greedy).unwrap();
+        assert_eq!(image.to_lf2_bytes_with_strategy(CompressionStrategy::MatchLengthCap(18)).unwrap(), greedy);
+        assert!(image.to_lf2_bytes_with_strategy(CompressionStrategy::MatchLengthCap(2)).is_err());
+        assert!(image.to_lf2_bytes_with_strategy(CompressionStrategy::MatchLengthCap(19)).is_err());
+    }
+
+    #[test]
+    fn strategies_share_one_header() {
+        let image = Lf2Image {
+            width: 20,
+            height: 6,
+            x_offset: 3,
+            y_offset: 5,
+            transparent_color: 1,
+            color_count: 3,
+            palette: vec![Rgb { r: 1, g: 2, b: 3 }, Rgb { r: 4, g: 5, b: 6 }, Rgb { r: 7, g: 8, b: 9 }],
+            pixels: (0..120).map(|i| (i / 9 % 3) as u8).collect(),
+        };
+        let payload_start = LF2_HEADER_SIZE + 3 * image.palette.len();
+        let mut strategies = vec![CompressionStrategy::Greedy, CompressionStrategy::MatchLengthCap(5)];
+        // The learned tree is a build artefact that may be absent
+        if global_rules().is_ok() {
+            strategies.push(CompressionStrategy::DecisionTreeGuided);
+        }
+
+        let header = image.to_lf2_bytes_okumura().unwrap()[..payload_start].to_vec();
+        for strategy in strategies {
+            let bytes = image.to_lf2_bytes_with_strategy(strategy).unwrap();
+            assert_eq!(bytes[..payload_start], header[..], "{:?}", strategy);
+            let decoded = Lf2Image::from_data(&bytes).unwrap();
+            assert_eq!(decoded.pixels, image.pixels, "{:?}", strategy);
+            assert_eq!((decoded.x_offset, decoded.y_offset, decoded.transparent_color), (3, 5, 1));
+        }
+    }
+
+    #[test]
+    fn limits_stop_the_decode_before_writing() {
+        let image = Lf2Image {
+            width: 16,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: (0..64).map(|i| (i % 3 % 2) as u8).collect(),
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("C0101.LF2");
+        std::fs::write(&input, image.to_lf2_bytes_with_strategy(CompressionStrategy::Greedy).unwrap()).unwrap();
+        let output = dir.path().join("C0101.png");
+
+        let too_many_pixels = DecodeConfig {
+            limits: crate::decoder::DecodeLimits { max_pixels: Some(63), ..Default::default() },
+            ..Default::default()
+        };
+        let too_many_steps = DecodeConfig {
+            step_by_step: true,
+            limits: crate::decoder::DecodeLimits { max_steps: Some(3), ..Default::default() },
+            ..Default::default()
+        };
+        for config in [too_many_pixels, too_many_steps] {
+            let err = super::super::decode_lf2_direct(&input, &output, &config).unwrap_err();
+            assert!(matches!(
+                crate::decoder::find_error(&err),
+                Some(crate::decoder::Error::LimitExceeded { .. })
+            ));
+            assert!(!output.exists());
+        }
+
+        super::super::decode_lf2_direct(&input, &output, &DecodeConfig::default()).unwrap();
+        assert!(output.exists());
+    }
 
-            compressed[flag_pos] = flag_byte ^ 0xff;
+    #[test]
+    fn step_decode_matches_fast_decode() {
+        let image = Lf2Image {
+            width: 24,
+            height: 10,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 4,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 4],
+            pixels: (0..240).map(|i| ((i / 7 + i / 24) % 4) as u8).collect(),
+        };
+        let bytes = image.to_lf2_bytes().unwrap();
+        // Whole file, and one cut off in the middle of the pixel stream
+        for data in [&bytes[..], &bytes[..bytes.len() * 2 / 3]] {
+            let mut state = DecodingState::new();
+            let stepped = Lf2Image::from_data_with_steps(data, &mut state).unwrap();
+            let fast = Lf2Image::from_data(data).unwrap();
+            assert_eq!(stepped.pixels, fast.pixels);
+
+            // The steps account for every decoded byte, in order
+            let replayed: Vec<u8> = state.steps.iter().flat_map(|s| s.memory_state.iter().copied()).collect();
+            assert_eq!(replayed, fast.stored_pixels()[..state.decoded_pixels]);
+            assert_eq!(state.steps.last().unwrap().pixels_decoded, state.decoded_pixels);
+            assert!(state.steps.iter().any(|s| matches!(s.operation_type, crate::formats::StepOperationType::LzssMatch { .. })));
         }
+    }
 
-        Ok(compressed)
+    #[test]
+    fn y_flip_golden_for_short_and_odd_heights() {
+        let dir = tempfile::tempdir().unwrap();
+        for height in [1u16, 3, 5] {
+            // Row y is drawn in colour y, so any mirroring shows up as a wrong index
+            let image = Lf2Image {
+                width: 3,
+                height,
+                x_offset: 0,
+                y_offset: 0,
+                transparent_color: 0xff,
+                color_count: 8,
+                palette: (0..8).map(|i| Rgb { r: i * 30, g: 0, b: 0 }).collect(),
+                pixels: (0..height).flat_map(|y| [y as u8; 3]).collect(),
+            };
+            let stored: Vec<u8> = (0..height).rev().flat_map(|y| [y as u8; 3]).collect();
+            assert_eq!(image.stored_pixels(), stored, "height {}", height);
+
+            let bytes = image.to_lf2_bytes().unwrap();
+            assert_eq!(Lf2Image::from_data(&bytes).unwrap().pixels, image.pixels, "height {}", height);
+            let mut state = DecodingState::new();
+            assert_eq!(Lf2Image::from_data_with_steps(&bytes, &mut state).unwrap().pixels, image.pixels);
+
+            let first_row: Vec<u8> = image.pixels_rgba_in(Orientation::Stored).take(3).map(|(_, _, px)| px.0[0]).collect();
+            assert_eq!(first_row, [30 * (height as u8 - 1); 3]);
+
+            // BMP rows are bottom-up, padded to 4 bytes; the first row written
+            // is the bottom one unless the output stays in stored order
+            for (orientation, first) in [(Orientation::Display, height as u8 - 1), (Orientation::Stored, 0)] {
+                let path = dir.path().join(format!("{}.bmp", height));
+                let config = DecodeConfig { orientation, ..Default::default() };
+                image.save_as_bmp_8bit(&path, &config).unwrap();
+                let bmp = std::fs::read(&path).unwrap();
+                let rows: Vec<u8> = bmp[54 + 256 * 4..].chunks(4).map(|row| row[0]).collect();
+                let expected: Vec<u8> = (0..height as u8).map(|i| if first == 0 { i } else { first - i }).collect();
+                assert_eq!(rows, expected, "height {} {:?}", height, orientation);
+            }
+        }
     }
-}
\ No newline at end of file
+}
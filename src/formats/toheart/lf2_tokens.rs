@@ -19,6 +19,9 @@
 
 use anyhow::{anyhow, Result};
 
+use crate::formats::common::{BitFlagReader, Polarity};
+use crate::lzss::BitOrder;
+
 /// Leaf 側の圧縮トークン 1 個。`pos` は 0..N=4096 の絶対リングバッファ位置、
 /// `len` は実長（3..=18）。
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -57,40 +60,27 @@ pub fn decompress_to_tokens(
     let mut ring = [0x20u8; 0x1000];
     let mut ring_pos: usize = 0x0fee;
 
-    let mut data_pos = 0usize;
+    let mut reader = BitFlagReader::new(compressed, BitOrder::MsbFirst, Polarity::Inverted);
     let mut produced = 0usize;
-    let mut flag: u8 = 0;
-    let mut flag_count: u8 = 0;
 
     let mut tokens: Vec<LeafToken> = Vec::new();
     let mut ring_input: Vec<u8> = Vec::with_capacity(total_pixels);
 
     while produced < total_pixels {
-        if flag_count == 0 {
-            if data_pos >= compressed.len() {
-                return Err(anyhow!(
-                    "unexpected end of payload at flag byte (produced {}/{}, data_pos {})",
-                    produced,
-                    total_pixels,
-                    data_pos
-                ));
-            }
-            flag = compressed[data_pos] ^ 0xff;
-            data_pos += 1;
-            flag_count = 8;
-        }
-
-        if (flag & 0x80) != 0 {
+        let literal = reader.next_flag().ok_or_else(|| anyhow!(
+            "unexpected end of payload at flag byte (produced {}/{}, data_pos {})",
+            produced,
+            total_pixels,
+            reader.position()
+        ))?;
+
+        if literal {
             // リテラル
-            if data_pos >= compressed.len() {
-                return Err(anyhow!(
-                    "unexpected end of payload at literal byte (produced {}/{})",
-                    produced,
-                    total_pixels
-                ));
-            }
-            let pixel = compressed[data_pos] ^ 0xff;
-            data_pos += 1;
+            let pixel = reader.read_u8().ok_or_else(|| anyhow!(
+                "unexpected end of payload at literal byte (produced {}/{})",
+                produced,
+                total_pixels
+            ))? ^ 0xff;
 
             tokens.push(LeafToken::Literal(pixel));
             ring[ring_pos] = pixel;
@@ -99,16 +89,13 @@ pub fn decompress_to_tokens(
             produced += 1;
         } else {
             // マッチ
-            if data_pos + 1 >= compressed.len() {
-                return Err(anyhow!(
-                    "unexpected end of payload at match pair (produced {}/{})",
-                    produced,
-                    total_pixels
-                ));
-            }
-            let upper = compressed[data_pos] ^ 0xff;
-            let lower = compressed[data_pos + 1] ^ 0xff;
-            data_pos += 2;
+            let pair = reader.read_bytes(2).ok_or_else(|| anyhow!(
+                "unexpected end of payload at match pair (produced {}/{})",
+                produced,
+                total_pixels
+            ))?;
+            let upper = pair[0] ^ 0xff;
+            let lower = pair[1] ^ 0xff;
 
             let length = ((upper & 0x0f) as usize) + 3;
             let position =
@@ -132,9 +119,6 @@ pub fn decompress_to_tokens(
                 produced += 1;
             }
         }
-
-        flag <<= 1;
-        flag_count -= 1;
     }
 
     Ok(LeafDecode { tokens, ring_input })
@@ -6,21 +6,53 @@ use std::path::Path;
 use anyhow::Result;
 use tracing::{info, debug};
 
-use crate::{DecodeConfig, DecodingState};
+use crate::{DecodeConfig, DecodingState, FormatType};
 
 pub mod pak;
 pub mod lf2;
 pub mod scn;
+pub mod palette_swap;
+pub mod palette_variants;
+
+// Encoder research (Issue #3). Used internally by the LF2 encoders; only
+// public with the `unstable` feature since the APIs change between sessions.
+#[cfg(feature = "unstable")]
 pub mod okumura_lzss;
+#[cfg(feature = "unstable")]
 pub mod naive_scan_lzss;
+#[cfg(feature = "unstable")]
 pub mod lf2_tokens;
+#[cfg(feature = "unstable")]
 pub mod decision_tree;
+#[cfg(feature = "unstable")]
+pub mod encode_profile;
+#[cfg(feature = "unstable")]
+pub mod strategy_rng;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod okumura_lzss;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod naive_scan_lzss;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod lf2_tokens;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod decision_tree;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod encode_profile;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod strategy_rng;
 
 pub mod test_transparency;
 
 pub use pak::PakArchive;
 pub use lf2::Lf2Image;
 pub use scn::ScnScene;
+pub use palette_swap::PaletteMapping;
 
 /// Extract PAK archive
 pub fn extract_pak(
@@ -33,7 +65,7 @@ pub fn extract_pak(
     let mut pak = PakArchive::open(input_path)?;
     
     if config.step_by_step {
-        let mut state = DecodingState::new();
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
         pak.extract_with_steps(output_path, &mut state, config)?;
         
         if config.verbose {
@@ -55,9 +87,7 @@ pub fn decode_lf2(
     output_path: &Path,
     config: &DecodeConfig,
 ) -> Result<()> {
-    let output_file = output_path.join(
-        input_path.file_stem().unwrap_or_default()
-    ).with_extension("bmp");
+    let output_file = crate::paths::output_file_for(output_path, input_path, "bmp")?;
     
     decode_lf2_direct(input_path, &output_file, config)
 }
@@ -70,18 +100,22 @@ pub fn decode_lf2_direct(
 ) -> Result<()> {
     info!("Decoding LF2 image: {:?}", input_path);
     
-    let lf2 = Lf2Image::open(input_path)?;
+    let data = std::fs::read(input_path)?;
+    let (width, height) = Lf2Image::dimensions(&data)?;
+    config.limits.check_image(&FormatType::ToHeartLf2, width as u64, height as u64)?;
     
     if config.step_by_step {
-        let mut state = DecodingState::new();
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
+        let lf2 = Lf2Image::from_data_with_steps(&data, &mut state)?;
         lf2.decode_with_steps(output_file, &mut state, config)?;
         
         if config.verbose {
             info!("Decoding completed in {} steps", state.steps.len());
             info!("Ring buffer size: {}", state.ring_buffer.len());
         }
+        crate::trace::save_requested(config, FormatType::ToHeartLf2, input_path, state)?;
     } else {
-        lf2.decode(output_file, config)?;
+        Lf2Image::from_data(&data)?.decode(output_file, config)?;
     }
     
     Ok(())
@@ -93,9 +127,7 @@ pub fn decode_scn(
     output_path: &Path,
     config: &DecodeConfig,
 ) -> Result<()> {
-    let output_file = output_path.join(
-        input_path.file_stem().unwrap_or_default()
-    ).with_extension("bmp");
+    let output_file = crate::paths::output_file_for(output_path, input_path, "bmp")?;
     
     decode_scn_direct(input_path, &output_file, config)
 }
@@ -108,11 +140,15 @@ pub fn decode_scn_direct(
 ) -> Result<()> {
     info!("Decoding SCN scene: {:?}", input_path);
     
-    let scn = ScnScene::open(input_path)?;
+    let data = std::fs::read(input_path)?;
+    let (width, height) = Lf2Image::dimensions(&data)?;
+    config.limits.check_image(&FormatType::ToHeartScn, width as u64, height as u64)?;
+    let scn = ScnScene::from_data(&data)?;
     
     if config.step_by_step {
-        let mut state = DecodingState::new();
+        let mut state = DecodingState::with_step_limit(config.limits.max_steps);
         scn.decode_with_steps(output_file, &mut state, config)?;
+        crate::trace::save_requested(config, FormatType::ToHeartScn, input_path, state)?;
     } else {
         scn.decode(output_file, config)?;
     }
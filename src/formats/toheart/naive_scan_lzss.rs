@@ -2,7 +2,83 @@
 
 use super::okumura_lzss::{Token, F, N, THRESHOLD};
 
+/// Backward scan over distances 1..=max_distance from `r`: returns
+/// `(len, pos)` of the best match under the strict `>` / `>=` tie-break,
+/// stopping at the first match of length F.
+fn longest_match_backward(
+    text_buf: &[u8; N + F - 1],
+    r: usize,
+    max_match: usize,
+    allow_equal: bool,
+    max_distance: usize,
+) -> (usize, usize) {
+    let mut best_len: usize = 0;
+    let mut best_pos: usize = 0;
+
+    for d in 1..=max_distance.min(N - 1) {
+        let pos = (r + N - d) & (N - 1);
+        let mut ml = 0usize;
+        while ml < max_match && text_buf[pos + ml] == text_buf[r + ml] {
+            ml += 1;
+        }
+        let better = if allow_equal {
+            ml >= best_len && ml > 0
+        } else {
+            ml > best_len
+        };
+        if better {
+            best_len = ml;
+            best_pos = pos;
+            if best_len >= F {
+                break;
+            }
+        }
+    }
+    (best_len, best_pos)
+}
+
+/// Scans the whole usable window. Distances beyond `N - F` would land in
+/// the lookahead, which `text_buf` already holds but the decoder has not
+/// written yet, so they are never searched.
 pub fn compress_naive_backward(input: &[u8], allow_equal: bool) -> Vec<Token> {
+    compress_naive_backward_window(input, allow_equal, N - F)
+}
+
+/// [`compress_naive_backward`] considering only distances up to `max_distance`
+pub fn compress_naive_backward_window(input: &[u8], allow_equal: bool, max_distance: usize) -> Vec<Token> {
+    compress_naive_backward_window_with(input, |_| allow_equal, max_distance)
+}
+
+/// [`compress_naive_backward_window`] with the tie-break chosen per token:
+/// `allow_equal(position)` gets the input position the token starts at
+pub fn compress_naive_backward_window_with<T>(input: &[u8], allow_equal: T, max_distance: usize) -> Vec<Token>
+where
+    T: FnMut(usize) -> bool,
+{
+    compress_backward(input, allow_equal, max_distance, |_| F)
+}
+
+/// [`compress_naive_backward_window`] (strict `>`) with references no longer
+/// than `max_len`; below `THRESHOLD + 1` every pixel is a literal
+pub fn compress_naive_backward_capped(input: &[u8], max_len: usize, max_distance: usize) -> Vec<Token> {
+    compress_backward(input, |_| false, max_distance, |_| max_len.min(F))
+}
+
+/// [`compress_naive_backward_window`] (strict `>`) where no reference runs
+/// past the end of a `row_len`-pixel scanline, for encoders that compressed
+/// line by line
+pub fn compress_naive_backward_scanline(input: &[u8], row_len: usize, max_distance: usize) -> Vec<Token> {
+    let row_len = row_len.max(1);
+    compress_backward(input, |_| false, max_distance, |position| F.min(row_len - position % row_len))
+}
+
+/// Shared loop: `allow_equal(p)` picks the tie-break and `max_len(p)` the
+/// longest reference for the token starting at input position `p`
+fn compress_backward<T, L>(input: &[u8], mut allow_equal: T, max_distance: usize, mut max_len: L) -> Vec<Token>
+where
+    T: FnMut(usize) -> bool,
+    L: FnMut(usize) -> usize,
+{
     let mut text_buf = [0x20u8; N + F - 1];
     let mut out: Vec<Token> = Vec::new();
 
@@ -22,29 +98,9 @@ pub fn compress_naive_backward(input: &[u8], allow_equal: bool) -> Vec<Token> {
     }
 
     loop {
-        let max_match = len.min(F);
-        let mut best_len: usize = 0;
-        let mut best_pos: usize = 0;
-
-        for d in 1..N {
-            let pos = (r + N - d) & (N - 1);
-            let mut ml = 0usize;
-            while ml < max_match && text_buf[pos + ml] == text_buf[r + ml] {
-                ml += 1;
-            }
-            let better = if allow_equal {
-                ml >= best_len && ml > 0
-            } else {
-                ml > best_len
-            };
-            if better {
-                best_len = ml;
-                best_pos = pos;
-                if best_len >= F {
-                    break;
-                }
-            }
-        }
+        let position = input_idx - len;
+        let tie = allow_equal(position);
+        let (best_len, best_pos) = longest_match_backward(&text_buf, r, len.min(max_len(position)), tie, max_distance);
 
         let last_match_length = if best_len <= THRESHOLD {
             out.push(Token::Literal(text_buf[r]));
@@ -88,6 +144,90 @@ pub fn compress_naive_backward(input: &[u8], allow_equal: bool) -> Vec<Token> {
     out
 }
 
+/// Block-parallel [`compress_naive_backward_window`] with identical output.
+///
+/// The ring contents when the encoder reaches input position `p` depend only
+/// on `input[..p + F]`, never on earlier token choices, so the best match at
+/// every position is computed up front by `threads` workers, each replaying
+/// the ring for its own contiguous block. Tokens are then emitted
+/// sequentially by walking those results. This scans every position rather
+/// than only token starts (several times the work on typical sprites), so it
+/// only pays off with four or more threads.
+pub fn compress_naive_backward_parallel(
+    input: &[u8],
+    allow_equal: bool,
+    max_distance: usize,
+    threads: usize,
+) -> Vec<Token> {
+    let n = input.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let block = (n + threads.max(1) - 1) / threads.max(1);
+
+    let mut best = vec![(0usize, 0usize); n];
+    std::thread::scope(|scope| {
+        for (b, chunk) in best.chunks_mut(block).enumerate() {
+            scope.spawn(move || find_matches_backward(input, b * block, chunk, allow_equal, max_distance));
+        }
+    });
+
+    let mut out = Vec::new();
+    let mut p = 0;
+    while p < n {
+        let (best_len, best_pos) = best[p];
+        if best_len <= THRESHOLD {
+            out.push(Token::Literal(input[p]));
+            p += 1;
+        } else {
+            out.push(Token::Match {
+                pos: (best_pos as u16) & ((N as u16) - 1),
+                len: best_len as u8,
+            });
+            p += best_len;
+        }
+    }
+    out
+}
+
+/// Fill `out[k]` with the backward-scan match at input position `start + k`
+fn find_matches_backward(
+    input: &[u8],
+    start: usize,
+    out: &mut [(usize, usize)],
+    allow_equal: bool,
+    max_distance: usize,
+) {
+    let n = input.len();
+    let mut text_buf = [0x20u8; N + F - 1];
+
+    // Input byte i lives at ring position (N - F + i); only the last N
+    // writes before `start` survive.
+    let written = (start + F).min(n);
+    for (i, &c) in input.iter().enumerate().take(written).skip(written.saturating_sub(N)) {
+        let at = (N - F + i) & (N - 1);
+        text_buf[at] = c;
+        if at < F - 1 {
+            text_buf[at + N] = c;
+        }
+    }
+
+    let mut r = (N - F + start) & (N - 1);
+    for (k, slot) in out.iter_mut().enumerate() {
+        let p = start + k;
+        *slot = longest_match_backward(&text_buf, r, (n - p).min(F), allow_equal, max_distance);
+
+        if p + F < n {
+            let s = (r + F) & (N - 1);
+            text_buf[s] = input[p + F];
+            if s < F - 1 {
+                text_buf[s + N] = input[p + F];
+            }
+        }
+        r = (r + 1) & (N - 1);
+    }
+}
+
 /// pos = 0..N 絶対位置昇順で全候補スキャン (= leftmost first 全数)。
 /// best 採用、tie は allow_equal で制御。
 pub fn compress_naive_forward_pos(input: &[u8], allow_equal: bool) -> Vec<Token> {
@@ -656,6 +796,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parallel_matches_sequential() {
+        // Long enough for the ring to wrap, with repeats, bytes equal to the
+        // 0x20 fill, and a tail shorter than F
+        let long: Vec<u8> = (0..9000u32)
+            .map(|i| if i % 700 < 350 { (i % 13) as u8 + 26 } else { (i * 7 / 5 % 31) as u8 })
+            .collect();
+        for input in [&long[..], b"  ab  ab  ab"] {
+            for &eq in &[false, true] {
+                let sequential = compress_naive_backward(input, eq);
+                for threads in [1, 3, 8] {
+                    let parallel = compress_naive_backward_parallel(input, eq, N - F, threads);
+                    assert_eq!(parallel, sequential, "allow_equal={} threads={}", eq, threads);
+                }
+                let capped = compress_naive_backward_window(input, eq, 100);
+                assert_eq!(compress_naive_backward_parallel(input, eq, 100, 3), capped);
+                assert_eq!(decode_tokens(&capped), input);
+            }
+        }
+        assert!(compress_naive_backward_parallel(&[], false, N - 1, 4).is_empty());
+    }
+
+    #[test]
+    fn never_references_the_lookahead() {
+        // At the first byte the run is only present in the lookahead, one
+        // position past the far end of the window
+        for &eq in &[false, true] {
+            let input = [1, 1, 1, 1];
+            assert_eq!(decode_tokens(&compress_naive_backward(&input, eq)), input);
+        }
+    }
+
     #[test]
     fn roundtrip_spaces() {
         let input = vec![b' '; 50];
@@ -1,17 +1,18 @@
 //! ToHeart PAK archive format implementation
 //! Based on leafpak.c analysis
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::{Read, Seek, SeekFrom};
 use std::fs::File;
 use anyhow::{Result, anyhow};
 use tracing::{debug, trace};
 
 use crate::{DecodeConfig, DecodingState, DecodeStep};
+use crate::formats::magic::{LEAFPACK_MAGIC, LEAFPACK_HEADER_SIZE, LEAFPACK_ENTRY_SIZE};
+use crate::romanize::NameMapper;
 
-/// Magic number for LEAFPACK format
-const LEAFPACK_MAGIC: &[u8] = b"LEAFPACK";
-const KEY_LEN: usize = 11;
+/// Length of the additive key cycled over table and data (leafpak.c)
+pub const KEY_LEN: usize = 11;
 
 /// ToHeart archive type detection by file count
 #[derive(Debug, Clone, PartialEq)]
@@ -30,6 +31,14 @@ pub struct PakEntry {
     pub next_position: u32,
 }
 
+impl PakEntry {
+    /// Name bytes as stored in the archive (usually Shift-JIS); `name` holds
+    /// them one byte per char
+    pub fn raw_name(&self) -> Vec<u8> {
+        self.name.chars().map(|c| c as u32 as u8).collect()
+    }
+}
+
 /// PAK archive handler
 pub struct PakArchive {
     file_count: u16,
@@ -199,9 +208,42 @@ impl PakArchive {
     
     /// Extract single file (optimized version)
     pub fn extract_file(&mut self, name: &str, output_path: &Path) -> Result<()> {
-        let entry = self.entries.iter()
-            .find(|e| e.name.eq_ignore_ascii_case(name))
-            .ok_or_else(|| anyhow!("File not found: {}", name))?;
+        self.extract_file_to(name, output_path, false)
+    }
+
+    /// Extract single file; `direct` bypasses the temp file + rename
+    fn extract_file_to(&mut self, name: &str, output_path: &Path, direct: bool) -> Result<()> {
+        let index = self.index_of(name).ok_or_else(|| anyhow!("File not found: {}", name))?;
+        let data = self.read_entry(index)?;
+        crate::output::write_bytes(output_path, direct, &data)
+    }
+
+    /// The entry table, in archive order
+    pub fn entries(&self) -> std::slice::Iter<'_, PakEntry> {
+        self.entries.iter()
+    }
+
+    /// Index of the entry called `name` (ASCII case-insensitive)
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Extract entry `index` into `output_dir` under the name a full
+    /// extraction would give it (romanized with `config.romanize`, without
+    /// the manifest). Returns the path written.
+    pub fn extract_entry(&mut self, index: usize, output_dir: &Path, config: &DecodeConfig) -> Result<PathBuf> {
+        let data = self.read_entry(index)?;
+        let (names, _) = self.output_names(config);
+        std::fs::create_dir_all(output_dir)?;
+        let path = output_dir.join(&names[index]);
+        crate::output::write_bytes(&path, config.direct_writes, &data)?;
+        Ok(path)
+    }
+
+    /// Decrypted contents of entry `index`
+    pub fn read_entry(&mut self, index: usize) -> Result<Vec<u8>> {
+        let entry = self.entries.get(index)
+            .ok_or_else(|| anyhow!("PAK entry {} does not exist", index))?;
         
         self.file.seek(SeekFrom::Start(entry.position as u64))?;
         
@@ -216,8 +258,16 @@ impl PakArchive {
             key_index = (key_index + 1) % KEY_LEN;
         }
         
-        std::fs::write(output_path, encrypted_data)?;
-        Ok(())
+        Ok(encrypted_data)
+    }
+    
+    /// File name for each entry on disk, romanized with `config.romanize`
+    fn output_names(&self, config: &DecodeConfig) -> (Vec<String>, NameMapper) {
+        let mut mapper = NameMapper::default();
+        let names = self.entries.iter()
+            .map(|e| if config.romanize { mapper.map(&e.raw_name()) } else { e.name.clone() })
+            .collect();
+        (names, mapper)
     }
     
     /// Extract with step-by-step visualization
@@ -226,9 +276,12 @@ impl PakArchive {
         
         // Collect entries to avoid borrow checker issues
         let entries: Vec<_> = self.entries.to_vec();
+        let (names, mapper) = self.output_names(config);
         
-        for (i, entry) in entries.iter().enumerate() {
-            if config.step_by_step {
+        // Record every step first so a step limit stops the extraction
+        // before any file is written
+        if config.step_by_step {
+            for (i, entry) in entries.iter().enumerate() {
                 let step = DecodeStep {
                     step_number: i + 1,
                     description: format!("展開中: {}", entry.name),
@@ -245,16 +298,18 @@ impl PakArchive {
                     ring_position: 0,
                     partial_image: None,
                 };
-                state.add_step(step);
+                state.try_add_step(&crate::FormatType::ToHeartPak, step)?;
             }
-            
-            let output_file = output_dir.join(&entry.name);
-            self.extract_file(&entry.name, &output_file)?;
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            let output_file = output_dir.join(&names[i]);
+            self.extract_file_to(&entry.name, &output_file, config.direct_writes)?;
             
             state.decoded_pixels = i + 1;
         }
         
-        Ok(())
+        mapper.write_manifest(output_dir, config.direct_writes)
     }
     
     /// Extract all files (optimized batch version)
@@ -263,23 +318,118 @@ impl PakArchive {
         
         if config.parallel {
             // TODO: Parallel implementation for educational comparison
-            self.extract_sequential(output_dir)
+            self.extract_sequential(output_dir, config)
         } else {
-            self.extract_sequential(output_dir)
+            self.extract_sequential(output_dir, config)
         }
     }
     
     /// Sequential extraction (for comparison with parallel version)
-    fn extract_sequential(&mut self, output_dir: &Path) -> Result<()> {
-        for entry in &self.entries.clone() {
-            let output_file = output_dir.join(&entry.name);
-            self.extract_file(&entry.name, &output_file)?;
+    fn extract_sequential(&mut self, output_dir: &Path, config: &DecodeConfig) -> Result<()> {
+        let (names, mapper) = self.output_names(config);
+        for (index, name) in names.iter().enumerate() {
+            let data = self.read_entry(index)?;
+            crate::output::write_bytes(&output_dir.join(name), config.direct_writes, &data)?;
+            if let Some(progress) = &config.progress {
+                progress.report(&crate::progress::ProgressTick {
+                    unit: crate::progress::ProgressUnit::Files,
+                    done: index as u64 + 1,
+                    total: names.len() as u64,
+                });
+            }
         }
-        Ok(())
+        mapper.write_manifest(output_dir, config.direct_writes)
     }
     
     /// Get archive information
     pub fn info(&self) -> (u16, ArchiveType, &[PakEntry]) {
         (self.file_count, self.archive_type.clone(), &self.entries)
     }
-}
\ No newline at end of file
+}
+
+/// Build a LEAFPACK archive holding `files` (name, contents) in order,
+/// encrypted with `key`
+///
+/// Readers recover the key from the first three table entries, which only
+/// works when data starts right after the header and every entry's
+/// `next_position` is the following entry's position, so at least three
+/// files are needed. Names must be ASCII 8.3.
+pub fn write_archive(files: &[(String, Vec<u8>)], key: &[u8; KEY_LEN]) -> Result<Vec<u8>> {
+    if files.len() < 3 {
+        return Err(anyhow!("LEAFPACK archives need at least 3 files, got {}", files.len()));
+    }
+    let file_count = u16::try_from(files.len())
+        .map_err(|_| anyhow!("LEAFPACK archives hold at most {} files", u16::MAX))?;
+
+    let mut out = Vec::with_capacity(
+        LEAFPACK_HEADER_SIZE + files.iter().map(|(_, d)| d.len()).sum::<usize>() + files.len() * LEAFPACK_ENTRY_SIZE,
+    );
+    out.extend_from_slice(LEAFPACK_MAGIC);
+    out.extend_from_slice(&file_count.to_le_bytes());
+
+    let mut table = Vec::with_capacity(files.len() * LEAFPACK_ENTRY_SIZE);
+    for (name, data) in files {
+        let position = u32::try_from(out.len()).map_err(|_| anyhow!("Archive exceeds 4 GiB"))?;
+        let length = u32::try_from(data.len()).map_err(|_| anyhow!("{} exceeds 4 GiB", name))?;
+        out.extend(data.iter().zip(key.iter().cycle()).map(|(&b, &k)| b.wrapping_add(k)));
+
+        table.extend_from_slice(&encode_filename(name)?);
+        table.extend_from_slice(&position.to_le_bytes());
+        table.extend_from_slice(&length.to_le_bytes());
+        table.extend_from_slice(&(position + length).to_le_bytes());
+    }
+    out.extend(table.iter().zip(key.iter().cycle()).map(|(&b, &k)| b.wrapping_add(k)));
+    Ok(out)
+}
+
+/// "C0101.LF2" -> "C0101   LF2\0", the inverse of `parse_filename`
+fn encode_filename(name: &str) -> Result<[u8; 12]> {
+    let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    if !name.is_ascii() || stem.is_empty() || stem.len() > 8 || ext.len() > 3 || stem.contains(' ') {
+        return Err(anyhow!("{} is not an ASCII 8.3 name", name));
+    }
+    let mut bytes = [0u8; 12];
+    bytes[..8].fill(b' ');
+    bytes[..stem.len()].copy_from_slice(stem.as_bytes());
+    bytes[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_archive_reads_back() {
+        let files = vec![
+            ("C0101.LF2".to_string(), vec![1, 2, 3, 4]),
+            ("MAX_C.SCN".to_string(), (0..=255).collect()),
+            ("README".to_string(), b"leaf".to_vec()),
+        ];
+        let key = [0x13, 0x5a, 0x00, 0xff, 0x42, 0x01, 0x99, 0x7e, 0x20, 0xc3, 0x08];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST.PAK");
+        std::fs::write(&path, write_archive(&files, &key).unwrap()).unwrap();
+
+        let mut pak = PakArchive::open(&path).unwrap();
+        assert_eq!(pak.decryption_key, key);
+        let names: Vec<String> = pak.info().2.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(names, ["C0101.LF2", "MAX_C.SCN", "README."]);
+        for (i, (_, data)) in files.iter().enumerate() {
+            assert_eq!(&pak.read_entry(i).unwrap(), data);
+        }
+
+        assert!(write_archive(&files[..2], &key).is_err());
+        assert!(encode_filename("TOOLONGNAME.LF2").is_err());
+
+        let sizes: Vec<u32> = pak.entries().map(|e| e.length).collect();
+        assert_eq!(sizes, [4, 256, 4]);
+        let index = pak.index_of("max_c.scn").unwrap();
+        let out = dir.path().join("out");
+        let written = pak.extract_entry(index, &out, &DecodeConfig::default()).unwrap();
+        assert_eq!(written, out.join("MAX_C.SCN"));
+        assert_eq!(std::fs::read(&written).unwrap(), files[1].1);
+        assert_eq!(std::fs::read_dir(&out).unwrap().count(), 1);
+        assert!(pak.index_of("MISSING").is_none());
+    }
+}
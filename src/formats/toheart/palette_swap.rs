@@ -0,0 +1,194 @@
+//! Palette-swap batch operation for LF2 images
+//!
+//! Decodes an LF2 file, remaps palette indices and/or palette colors according
+//! to a JSON mapping file, and re-encodes it losslessly. Intended for bulk
+//! recoloring mods where every sprite of a character shares the same palette.
+//!
+//! Mapping file format:
+//!
+//! ```json
+//! {
+//!   "indices": { "3": 7, "4": 8 },
+//!   "colors": [ { "from": "#ff0000", "to": "#00ff00" } ]
+//! }
+//! ```
+//!
+//! - `indices`: pixel index remap (`old -> new`), applied to the pixel plane
+//! - `colors`: palette entry remap, every entry equal to `from` becomes `to`
+//!
+//! Re-encoding uses the Okumura LZSS port, which does not depend on the
+//! decision-tree model and always decodes back to the same pixels.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use tracing::info;
+
+use super::lf2::{Lf2Image, Rgb};
+
+/// Single palette color replacement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColorSwap {
+    /// Source color as `#rrggbb`
+    pub from: String,
+    /// Replacement color as `#rrggbb`
+    pub to: String,
+}
+
+/// Palette-swap mapping loaded from JSON
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaletteMapping {
+    #[serde(default)]
+    pub indices: BTreeMap<u8, u8>,
+    #[serde(default)]
+    pub colors: Vec<ColorSwap>,
+}
+
+/// Summary of a single palette-swap run
+#[derive(Debug, Clone, Default)]
+pub struct PaletteSwapReport {
+    pub remapped_pixels: usize,
+    pub replaced_colors: usize,
+}
+
+impl PaletteMapping {
+    /// Load mapping from a JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read palette mapping {}: {}", path.display(), e))?;
+        Self::from_json(&text)
+    }
+
+    /// Parse mapping from JSON text and validate color strings
+    pub fn from_json(text: &str) -> Result<Self> {
+        let mapping: Self = serde_json::from_str(text)
+            .map_err(|e| anyhow!("Invalid palette mapping: {}", e))?;
+        for swap in &mapping.colors {
+            parse_hex_color(&swap.from)?;
+            parse_hex_color(&swap.to)?;
+        }
+        Ok(mapping)
+    }
+
+    /// Apply the mapping to an image in place
+    pub fn apply(&self, image: &mut Lf2Image) -> Result<PaletteSwapReport> {
+        let mut report = PaletteSwapReport::default();
+
+        for (&from, &to) in &self.indices {
+            if (to as usize) >= image.palette.len() {
+                return Err(anyhow!(
+                    "Index mapping {} -> {} exceeds palette size {}",
+                    from, to, image.palette.len()
+                ));
+            }
+        }
+
+        if !self.indices.is_empty() {
+            for pixel in image.pixels.iter_mut() {
+                if let Some(&to) = self.indices.get(pixel) {
+                    if *pixel != to {
+                        *pixel = to;
+                        report.remapped_pixels += 1;
+                    }
+                }
+            }
+        }
+
+        for swap in &self.colors {
+            let from = parse_hex_color(&swap.from)?;
+            let to = parse_hex_color(&swap.to)?;
+            for color in image.palette.iter_mut() {
+                if color.r == from.r && color.g == from.g && color.b == from.b {
+                    *color = to;
+                    report.replaced_colors += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Parse `#rrggbb` (leading `#` optional)
+pub fn parse_hex_color(text: &str) -> Result<Rgb> {
+    let hex = text.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid color '{}': expected #rrggbb", text));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok(Rgb { r: channel(0), g: channel(2), b: channel(4) })
+}
+
+/// Decode `input`, apply `mapping`, and write the re-encoded LF2 to `output`.
+///
+/// The re-encoded payload is decoded again and compared against the remapped
+/// pixels before anything is written, so a swap never produces a lossy file.
+pub fn palette_swap_file(
+    input: &Path,
+    output: &Path,
+    mapping: &PaletteMapping,
+    direct: bool,
+) -> Result<PaletteSwapReport> {
+    info!("Palette swap: {:?} -> {:?}", input, output);
+
+    let mut image = Lf2Image::open(input)?;
+    let report = mapping.apply(&mut image)?;
+
+    let bytes = image.to_lf2_bytes_okumura()?;
+    let check = Lf2Image::from_data(&bytes)?;
+    if check.pixels != image.pixels {
+        return Err(anyhow!("Re-encoded LF2 does not round-trip: {}", input.display()));
+    }
+
+    crate::output::write_bytes(output, direct, &bytes)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_image() -> Lf2Image {
+        Lf2Image {
+            width: 4,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 3,
+            palette: vec![
+                Rgb { r: 0, g: 0, b: 0 },
+                Rgb { r: 255, g: 0, b: 0 },
+                Rgb { r: 0, g: 0, b: 255 },
+            ],
+            pixels: vec![0, 1, 1, 2, 2, 1, 0, 0],
+        }
+    }
+
+    #[test]
+    fn index_and_color_mapping() {
+        let mapping = PaletteMapping::from_json(
+            r##"{ "indices": { "1": 2 }, "colors": [ { "from": "#0000ff", "to": "#00ff00" } ] }"##,
+        ).unwrap();
+        let mut image = sample_image();
+        let report = mapping.apply(&mut image).unwrap();
+
+        assert_eq!(image.pixels, vec![0, 2, 2, 2, 2, 2, 0, 0]);
+        assert_eq!(report.remapped_pixels, 3);
+        assert_eq!(report.replaced_colors, 1);
+        assert_eq!((image.palette[2].r, image.palette[2].g, image.palette[2].b), (0, 255, 0));
+    }
+
+    #[test]
+    fn rejects_out_of_range_index() {
+        let mapping = PaletteMapping::from_json(r#"{ "indices": { "1": 9 } }"#).unwrap();
+        assert!(mapping.apply(&mut sample_image()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_color() {
+        assert!(PaletteMapping::from_json(r##"{ "colors": [ { "from": "red", "to": "#000000" } ] }"##).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Multi-palette LF2 images (`--palettes frames|gif`)
+//!
+//! Some Leaf sprites carry extra palettes for palette-cycling effects
+//! (flickering lights, water, glows). They sit after the compressed pixel
+//! stream as further blocks of `color_count` BGR triples, the same layout as
+//! the header palette. Plain decoding ignores them; this module finds them
+//! and renders the image once per palette, either as numbered frames or as
+//! an animated GIF cycling through them.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+
+use super::lf2::{Lf2Image, Rgb};
+use crate::decoder::Orientation;
+use crate::lzss::LzssSpec;
+use crate::DecodeConfig;
+
+/// Delay between palettes in the animated GIF
+const GIF_FRAME_MS: u32 = 100;
+
+/// How palette variants are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteExport {
+    /// One image per palette, `<stem>.pal<N>.<ext>`
+    Frames,
+    /// A single animated `<stem>.gif` cycling the palettes
+    Gif,
+}
+
+impl FromStr for PaletteExport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "frames" => Ok(Self::Frames),
+            "gif" => Ok(Self::Gif),
+            _ => Err(anyhow!("Palette export must be frames or gif, got {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for PaletteExport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Frames => "frames",
+            Self::Gif => "gif",
+        })
+    }
+}
+
+/// Every palette of the LF2 in `data`: the header palette first, then the
+/// auxiliary blocks. All-zero blocks are treated as padding and skipped, as
+/// is a trailing partial block.
+pub fn palettes(data: &[u8]) -> Result<Vec<Vec<Rgb>>> {
+    let image = Lf2Image::from_data(data)?;
+    let block_len = image.color_count as usize * 3;
+    let stream_start = 0x18 + block_len;
+    let total_pixels = image.width as usize * image.height as usize;
+    let stream = LzssSpec::LF2.decompress_stream(&data[stream_start..], total_pixels);
+
+    let mut palettes = vec![image.palette];
+    if block_len == 0 {
+        return Ok(palettes);
+    }
+    for block in data[stream_start + stream.consumed..].chunks_exact(block_len) {
+        if block.iter().all(|&b| b == 0) {
+            continue;
+        }
+        palettes.push(block.chunks_exact(3).map(|c| Rgb { b: c[0], g: c[1], r: c[2] }).collect());
+    }
+    Ok(palettes)
+}
+
+/// `image` rendered once per palette
+pub fn render_variants(image: &Lf2Image, palettes: &[Vec<Rgb>], orientation: Orientation) -> Vec<RgbaImage> {
+    palettes.iter()
+        .map(|palette| Lf2Image {
+            width: image.width,
+            height: image.height,
+            x_offset: image.x_offset,
+            y_offset: image.y_offset,
+            transparent_color: image.transparent_color,
+            color_count: image.color_count,
+            palette: palette.clone(),
+            pixels: image.pixels.clone(),
+        }.to_rgba_image(orientation))
+        .collect()
+}
+
+/// Write the palette variants of the LF2 at `input_path`, named after
+/// `output_file`, and return the written paths
+pub fn export(input_path: &Path, output_file: &Path, mode: PaletteExport, config: &DecodeConfig) -> Result<Vec<PathBuf>> {
+    let data = std::fs::read(input_path)?;
+    let image = Lf2Image::from_data(&data)?;
+    let palettes = palettes(&data)?;
+    let frames = render_variants(&image, &palettes, config.orientation);
+    info!("{}: {} palette(s)", input_path.display(), palettes.len());
+
+    match mode {
+        PaletteExport::Frames => {
+            let stem = output_file.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = output_file.extension().unwrap_or_default().to_string_lossy();
+            let mut written = Vec::new();
+            for (i, frame) in frames.iter().enumerate() {
+                let path = output_file.with_file_name(format!("{}.pal{}.{}", stem, i, extension));
+                crate::output::write_rgba_image_ordered(frame, &path, config.direct_writes, config.rgb565_order)?;
+                written.push(path);
+            }
+            Ok(written)
+        }
+        PaletteExport::Gif => {
+            use image::codecs::gif::{GifEncoder, Repeat};
+
+            let path = output_file.with_extension("gif");
+            crate::output::write_with(&path, config.direct_writes, |w| {
+                let mut encoder = GifEncoder::new(w);
+                encoder.set_repeat(Repeat::Infinite)?;
+                let delay = image::Delay::from_numer_denom_ms(GIF_FRAME_MS, 1);
+                encoder.encode_frames(frames.into_iter().map(|f| image::Frame::from_parts(f, 0, 0, delay)))?;
+                Ok(())
+            })?;
+            Ok(vec![path])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auxiliary_blocks_become_palettes() {
+        let image = Lf2Image {
+            width: 2,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0xff,
+            color_count: 2,
+            palette: vec![Rgb { r: 1, g: 2, b: 3 }, Rgb { r: 4, g: 5, b: 6 }],
+            pixels: vec![0, 1],
+        };
+        let mut data = image.to_lf2_bytes_okumura().unwrap();
+        assert_eq!(palettes(&data).unwrap().len(), 1);
+
+        // One auxiliary palette (BGR), a zero padding block and a stray byte
+        data.extend_from_slice(&[30, 20, 10, 60, 50, 40]);
+        data.extend_from_slice(&[0; 6]);
+        data.push(0xaa);
+        let found = palettes(&data).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[1][1].r, found[1][1].g, found[1][1].b), (40, 50, 60));
+
+        let frames = render_variants(&image, &found, Orientation::Display);
+        assert_eq!(frames[0].get_pixel(1, 0).0, [4, 5, 6, 255]);
+        assert_eq!(frames[1].get_pixel(0, 0).0, [10, 20, 30, 255]);
+    }
+}
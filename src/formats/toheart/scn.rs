@@ -18,6 +18,11 @@ impl ScnScene {
         let lf2_image = Lf2Image::open(path)?;
         Ok(Self { lf2_image })
     }
+
+    /// Parse SCN from byte data
+    pub fn from_data(data: &[u8]) -> Result<Self> {
+        Ok(Self { lf2_image: Lf2Image::from_data(data)? })
+    }
     
     /// Decode SCN to PNG
     pub fn decode(&self, output_path: &Path, config: &DecodeConfig) -> Result<()> {
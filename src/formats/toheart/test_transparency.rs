@@ -115,4 +115,17 @@ mod tests {
         
         println!("✓ Out-of-range palette index handled as transparent");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_pixels_rgba_coordinates() {
+        let test_image = create_test_transparency_image();
+        let pixels: Vec<_> = test_image.pixels_rgba().collect();
+        assert_eq!(pixels.len(), 16);
+
+        // (x=2, y=0) is index 2 (blue, transparent); (x=0, y=1) is index 1 (green)
+        assert_eq!(pixels[2], (2, 0, image::Rgba([0, 0, 255, 0])));
+        assert_eq!(pixels[4], (0, 1, image::Rgba([0, 255, 0, 255])));
+        assert_eq!(pixels[15].0, 3);
+        assert_eq!(pixels[15].1, 3);
+    }
+}
@@ -0,0 +1,151 @@
+//! Known hashes of original game files
+//!
+//! `inspect --hashes DB.json` compares files, and the entries of PAK
+//! archives, with a database of SHA-256 hashes of known releases and reports
+//! which release they came from. The database is a plain JSON file the user
+//! provides (the project does not ship one, since it is only as good as the
+//! dumps it was built from):
+//!
+//! ```json
+//! { "releases": [
+//!     { "game": "ToHeart", "release": "Windows", "version": "1.0",
+//!       "files": { "C0101.LF2": "e3b0c442...", "LVNS3DAT.PAK": "..." } } ] }
+//! ```
+//!
+//! A file matches a release when its hash is listed there, under any name.
+//! A file whose name a release lists with another hash is counted as
+//! differing: a patched file, or one from a different release.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+/// One release of a game and the hashes of its files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub game: String,
+    /// Platform or edition, e.g. "Windows" or "PC-98"
+    pub release: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// File name -> lowercase hex SHA-256
+    pub files: BTreeMap<String, String>,
+}
+
+impl Release {
+    /// "ToHeart Windows 1.0"
+    pub fn label(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{} {} {}", self.game, self.release, version),
+            None => format!("{} {}", self.game, self.release),
+        }
+    }
+
+    fn hash_of(&self, name: &str) -> Option<&str> {
+        self.files.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, h)| h.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashDatabase {
+    pub releases: Vec<Release>,
+}
+
+/// How well a set of files fits one [`Release`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReleaseMatch<'a> {
+    pub release: &'a Release,
+    /// Files whose hash the release lists
+    pub matched: usize,
+    /// Files the release lists under the same name with another hash
+    pub differing: Vec<String>,
+    /// Files the release does not list at all
+    pub unknown: usize,
+}
+
+impl HashDatabase {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::from_json(&data).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Parse a database, normalising hashes to lowercase
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        let mut db: Self = serde_json::from_slice(data)?;
+        for release in &mut db.releases {
+            if let Some(name) = release.files.iter()
+                .find(|(_, h)| h.len() != 64 || !h.bytes().all(|b| b.is_ascii_hexdigit()))
+                .map(|(n, _)| n)
+            {
+                return Err(anyhow!("{}: {} is not a SHA-256 hash", release.label(), name));
+            }
+            release.files.values_mut().for_each(|h| h.make_ascii_lowercase());
+        }
+        Ok(db)
+    }
+
+    /// Releases listing `sha256` (lowercase hex), with the name they list it under
+    pub fn lookup(&self, sha256: &str) -> Vec<(&Release, &str)> {
+        self.releases.iter()
+            .flat_map(|r| r.files.iter().filter(|(_, h)| *h == sha256).map(move |(n, _)| (r, n.as_str())))
+            .collect()
+    }
+
+    /// Releases matching at least one of `files` (name, lowercase hex
+    /// SHA-256), best fit first
+    pub fn identify(&self, files: &[(String, String)]) -> Vec<ReleaseMatch<'_>> {
+        let mut matches: Vec<ReleaseMatch> = self.releases.iter()
+            .map(|release| {
+                let mut m = ReleaseMatch { release, matched: 0, differing: Vec::new(), unknown: 0 };
+                for (name, hash) in files {
+                    if release.files.values().any(|h| h == hash) {
+                        m.matched += 1;
+                    } else if release.hash_of(name).is_some() {
+                        m.differing.push(name.clone());
+                    } else {
+                        m.unknown += 1;
+                    }
+                }
+                m
+            })
+            .filter(|m| m.matched > 0)
+            .collect();
+        matches.sort_by(|a, b| b.matched.cmp(&a.matched).then(a.differing.len().cmp(&b.differing.len())));
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::sha256_hex;
+
+    #[test]
+    fn identifies_release_by_majority() {
+        let json = format!(
+            r#"{{ "releases": [
+                {{ "game": "ToHeart", "release": "Windows", "version": "1.0",
+                   "files": {{ "A.LF2": "{a}", "B.LF2": "{b}", "C.LF2": "{c}" }} }},
+                {{ "game": "ToHeart", "release": "Windows", "version": "1.1",
+                   "files": {{ "A.LF2": "{a}", "B.LF2": "{b2}", "C.LF2": "{c}" }} }} ] }}"#,
+            a = sha256_hex(b"a").to_uppercase(), b = sha256_hex(b"b"), b2 = sha256_hex(b"b2"), c = sha256_hex(b"c"),
+        );
+        let db = HashDatabase::from_json(json.as_bytes()).unwrap();
+
+        let files: Vec<(String, String)> = [("a.lf2", &b"a"[..]), ("B.LF2", b"b2"), ("C.LF2", b"c"), ("D.LF2", b"d")]
+            .iter()
+            .map(|(name, data)| (name.to_string(), sha256_hex(data)))
+            .collect();
+        let matches = db.identify(&files);
+        assert_eq!(matches[0].release.label(), "ToHeart Windows 1.1");
+        assert_eq!((matches[0].matched, matches[0].differing.len(), matches[0].unknown), (3, 0, 1));
+        assert_eq!(matches[1].differing, vec!["B.LF2"]);
+
+        let found = db.lookup(&sha256_hex(b"a"));
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].1, "A.LF2");
+        assert!(db.lookup(&sha256_hex(b"d")).is_empty());
+        assert!(HashDatabase::from_json(br#"{"releases":[{"game":"g","release":"r","files":{"X":"zz"}}]}"#).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Bounded undo/redo history
+//!
+//! An editor keeps a [`History`] of snapshots of the state it edits: it
+//! [`record`](History::record)s the state as it was before each change, and
+//! [`undo`](History::undo) / [`redo`](History::redo) swap the current state
+//! with the neighbouring snapshot. Past the capacity the oldest snapshots
+//! are dropped. Histories serialize, so an editor can save them with its
+//! session and pick up where it left off.
+//!
+//! The `repl` parameter session is the only editor that uses it: its
+//! `undo` / `redo` commands and `repl --state` persistence are the whole
+//! undo/redo feature today. There is no GUI palette or annotation editor
+//! (neither the `gui` feature nor the web visualizer has one), so the GUI
+//! parts of that work, such as keyboard shortcuts and per-editor history
+//! saved with the GUI session, are not implemented. A GUI editor should
+//! keep a `History` of its own state rather than add another undo stack.
+
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+
+/// Snapshots kept when no capacity is given
+pub const DEFAULT_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History<T> {
+    capacity: usize,
+    /// Oldest first
+    undo: VecDeque<T>,
+    /// Most recently undone last
+    redo: Vec<T>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<T> History<T> {
+    /// Empty history keeping at most `capacity` undo steps
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, undo: VecDeque::new(), redo: Vec::new() }
+    }
+
+    /// Note `before`, the state a change is about to replace. Forgets
+    /// everything that could be redone.
+    pub fn record(&mut self, before: T) {
+        self.redo.clear();
+        self.push_undo(before);
+    }
+
+    /// Step back: `current` becomes the last recorded state. Returns false
+    /// when there is nothing to undo.
+    pub fn undo(&mut self, current: &mut T) -> bool {
+        match self.undo.pop_back() {
+            Some(previous) => {
+                self.redo.push(std::mem::replace(current, previous));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Step forward again after [`undo`](Self::undo). Returns false when
+    /// there is nothing to redo.
+    pub fn redo(&mut self, current: &mut T) -> bool {
+        match self.redo.pop() {
+            Some(next) => {
+                let previous = std::mem::replace(current, next);
+                self.push_undo(previous);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn undo_steps(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_steps(&self) -> usize {
+        self.redo.len()
+    }
+
+    fn push_undo(&mut self, state: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_redo_within_capacity() {
+        let mut history = History::new(2);
+        let mut value = 0;
+        for next in 1..=3 {
+            history.record(value);
+            value = next;
+        }
+        // The first edit fell out of the two-step history
+        assert!(history.undo(&mut value));
+        assert!(history.undo(&mut value));
+        assert_eq!(value, 1);
+        assert!(!history.undo(&mut value));
+
+        assert!(history.redo(&mut value));
+        assert_eq!((value, history.undo_steps(), history.redo_steps()), (2, 1, 1));
+
+        // A new edit drops the redo branch
+        history.record(value);
+        value = 10;
+        assert!(!history.redo(&mut value));
+        assert!(history.undo(&mut value));
+        assert_eq!(value, 2);
+
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(serde_json::from_str::<History<i32>>(&json).unwrap(), history);
+    }
+}
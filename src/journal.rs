@@ -0,0 +1,152 @@
+//! Progress journal for resumable batch runs
+//!
+//! Batch mode appends one JSON line per completed file to
+//! `<output>/.retro-decode-journal.jsonl`. An interrupted run restarted with
+//! `--resume` skips inputs that are already recorded, unchanged, and whose
+//! output still exists.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+
+/// Journal file name inside the output directory
+pub const JOURNAL_FILE: &str = ".retro-decode-journal.jsonl";
+
+/// One completed conversion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub input_size: u64,
+    /// Input modification time (seconds since the Unix epoch)
+    pub input_mtime: u64,
+}
+
+/// Append-only journal of completed conversions
+pub struct Journal {
+    path: PathBuf,
+    completed: HashMap<PathBuf, JournalEntry>,
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal in `output_dir`. With `resume` the existing entries
+    /// are loaded; otherwise the journal starts empty.
+    pub fn open(output_dir: &Path, resume: bool) -> Result<Self> {
+        let path = output_dir.join(JOURNAL_FILE);
+        let mut completed = HashMap::new();
+
+        if resume && path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for (line_no, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // A run killed mid-write can leave a truncated last line
+                match serde_json::from_str::<JournalEntry>(&line) {
+                    Ok(entry) => {
+                        completed.insert(entry.input.clone(), entry);
+                    }
+                    Err(e) => warn!("Ignoring journal line {}: {}", line_no + 1, e),
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(&path)?;
+
+        Ok(Self { path, completed, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of entries loaded or recorded so far
+    pub fn len(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty()
+    }
+
+    /// True if `input` was converted to `output` and neither side changed since
+    pub fn is_done(&self, input: &Path, output: &Path) -> bool {
+        let Some(entry) = self.completed.get(input) else {
+            return false;
+        };
+        if entry.output != output || !output.exists() {
+            return false;
+        }
+        let done = match input_stamp(input) {
+            Some((size, mtime)) => entry.input_size == size && entry.input_mtime == mtime,
+            None => false,
+        };
+        if done {
+            crate::metrics::add(crate::metrics::Counter::CacheHits, 1);
+        }
+        done
+    }
+
+    /// Record a completed conversion and flush it to disk immediately
+    pub fn record(&mut self, input: &Path, output: &Path) -> Result<()> {
+        let (input_size, input_mtime) = input_stamp(input).unwrap_or((0, 0));
+        let entry = JournalEntry {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            input_size,
+            input_mtime,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.flush()?;
+        self.completed.insert(entry.input.clone(), entry);
+        Ok(())
+    }
+}
+
+fn input_stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let mtime = meta.modified().ok()?
+        .duration_since(std::time::UNIX_EPOCH).ok()?
+        .as_secs();
+    Some((meta.len(), mtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_skips_recorded_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("A.LF2");
+        let output = dir.path().join("A.png");
+        std::fs::write(&input, b"lf2").unwrap();
+        std::fs::write(&output, b"png").unwrap();
+
+        {
+            let mut journal = Journal::open(dir.path(), false).unwrap();
+            assert!(!journal.is_done(&input, &output));
+            journal.record(&input, &output).unwrap();
+        }
+
+        let resumed = Journal::open(dir.path(), true).unwrap();
+        assert!(resumed.is_done(&input, &output));
+
+        std::fs::remove_file(&output).unwrap();
+        assert!(!resumed.is_done(&input, &output));
+
+        let fresh = Journal::open(dir.path(), false).unwrap();
+        assert!(fresh.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+//! Kaitai Struct (`.ksy`) subset for prototyping headers
+//!
+//! A format the crate has no parser for can be described in a `.ksy` and
+//! read with `probe --ksy`, which prints the header fields and adds the end
+//! of the header to the offsets the LZSS probe tries. Only a flat `seq` is
+//! understood:
+//!
+//! - `contents` (a string or a list of bytes / strings), checked against the file
+//! - `type: u1 u2 u4 u8 s1 s2 s4 s8`, with an optional `le` / `be` suffix
+//! - `size` as a number or the id of an earlier integer field
+//! - `size-eos: true`, which ends the header
+//!
+//! `meta/endian` sets the default byte order; `doc`, `title` and the other
+//! descriptive keys are ignored, and anything else is rejected by name so
+//! a template is never half-applied silently. The `.ksy` files written by
+//! `export-spec` are within the subset.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde_yaml::Value;
+
+/// Keys of a `seq` entry that do not affect parsing
+const DESCRIPTIVE_KEYS: &[&str] = &["id", "doc", "doc-ref", "-orig-id"];
+
+/// A parsed `.ksy`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KsySpec {
+    pub id: String,
+    pub big_endian: bool,
+    pub seq: Vec<KsyField>,
+}
+
+/// One `seq` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KsyField {
+    pub id: String,
+    pub kind: KsyKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KsyKind {
+    /// Fixed bytes the file must contain
+    Contents(Vec<u8>),
+    /// Integer of `size` bytes; `big_endian` overrides the default order
+    Int { size: usize, signed: bool, big_endian: Option<bool> },
+    Bytes(KsySize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KsySize {
+    Fixed(usize),
+    /// The value of an earlier integer field
+    Field(String),
+    /// Everything to the end of the file
+    Eos,
+}
+
+/// A field read from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedField {
+    pub id: String,
+    pub offset: usize,
+    pub size: usize,
+    pub value: FieldValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldValue {
+    Unsigned(u64),
+    Signed(i64),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned(v) => write!(f, "{} ({:#x})", v, v),
+            Self::Signed(v) => write!(f, "{}", v),
+            Self::Bytes(bytes) => {
+                for byte in bytes.iter().take(16) {
+                    write!(f, "{:02x} ", byte)?;
+                }
+                if bytes.len() > 16 {
+                    write!(f, "... ")?;
+                }
+                write!(f, "({} bytes)", bytes.len())
+            }
+        }
+    }
+}
+
+impl KsySpec {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::from_yaml(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self> {
+        let root: Value = serde_yaml::from_str(text)?;
+        let meta = root.get("meta");
+        let id = meta.and_then(|m| m.get("id")).and_then(Value::as_str).unwrap_or("unnamed").to_string();
+        let big_endian = match meta.and_then(|m| m.get("endian")) {
+            None => false,
+            Some(endian) => match endian.as_str() {
+                Some("le") => false,
+                Some("be") => true,
+                _ => return Err(anyhow!("meta/endian must be le or be (calculated endianness is not supported)")),
+            },
+        };
+        let seq = match root.get("seq") {
+            None => Vec::new(),
+            Some(seq) => seq.as_sequence()
+                .ok_or_else(|| anyhow!("seq must be a list"))?
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| parse_field(entry).map_err(|e| anyhow!("seq[{}]: {}", i, e)))
+                .collect::<Result<_>>()?,
+        };
+        Ok(Self { id, big_endian, seq })
+    }
+
+    /// Read the `seq` fields from the start of `data`
+    pub fn parse(&self, data: &[u8]) -> Result<Vec<ParsedField>> {
+        let mut fields = Vec::with_capacity(self.seq.len());
+        let mut integers: HashMap<&str, u64> = HashMap::new();
+        let mut offset = 0;
+        for field in &self.seq {
+            let size = match &field.kind {
+                KsyKind::Contents(bytes) => bytes.len(),
+                KsyKind::Int { size, .. } => *size,
+                KsyKind::Bytes(KsySize::Fixed(size)) => *size,
+                KsyKind::Bytes(KsySize::Field(name)) => {
+                    let value = integers.get(name.as_str())
+                        .ok_or_else(|| anyhow!("{}: size refers to '{}', which is not an earlier integer field", field.id, name))?;
+                    usize::try_from(*value).map_err(|_| anyhow!("{}: size {} is too large", field.id, value))?
+                }
+                KsyKind::Bytes(KsySize::Eos) => data.len().saturating_sub(offset),
+            };
+            let bytes = offset.checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or_else(|| anyhow!("{}: {} bytes at {:#x} run past the end of the file", field.id, size, offset))?;
+            let value = match &field.kind {
+                KsyKind::Contents(expected) => {
+                    if bytes != expected.as_slice() {
+                        return Err(anyhow!("{}: expected {:02x?} at {:#x}, found {:02x?}", field.id, expected, offset, bytes));
+                    }
+                    FieldValue::Bytes(bytes.to_vec())
+                }
+                KsyKind::Int { signed, big_endian, .. } => {
+                    let big_endian = big_endian.unwrap_or(self.big_endian);
+                    let mut raw = 0u64;
+                    for i in 0..size {
+                        let byte = if big_endian { bytes[i] } else { bytes[size - 1 - i] };
+                        raw = raw << 8 | byte as u64;
+                    }
+                    integers.insert(&field.id, raw);
+                    if *signed {
+                        let shift = 64 - 8 * size as u32;
+                        FieldValue::Signed((raw << shift) as i64 >> shift)
+                    } else {
+                        FieldValue::Unsigned(raw)
+                    }
+                }
+                KsyKind::Bytes(_) => FieldValue::Bytes(bytes.to_vec()),
+            };
+            fields.push(ParsedField { id: field.id.clone(), offset, size, value });
+            offset += size;
+        }
+        Ok(fields)
+    }
+
+    /// Offset just past the fields before the first `size-eos` one, where a
+    /// compressed stream may start
+    pub fn header_end(&self, fields: &[ParsedField]) -> usize {
+        self.seq.iter()
+            .zip(fields)
+            .take_while(|(field, _)| field.kind != KsyKind::Bytes(KsySize::Eos))
+            .last()
+            .map_or(0, |(_, parsed)| parsed.offset + parsed.size)
+    }
+}
+
+fn parse_field(entry: &Value) -> Result<KsyField> {
+    let map = entry.as_mapping().ok_or_else(|| anyhow!("entry must be a mapping"))?;
+    let id = entry.get("id").and_then(Value::as_str).ok_or_else(|| anyhow!("entry has no id"))?.to_string();
+    for key in map.keys() {
+        let key = key.as_str().unwrap_or("");
+        if !DESCRIPTIVE_KEYS.contains(&key) && !["contents", "type", "size", "size-eos"].contains(&key) {
+            return Err(anyhow!("{}: '{}' is outside the supported .ksy subset", id, key));
+        }
+    }
+
+    let kind = if let Some(contents) = entry.get("contents") {
+        KsyKind::Contents(parse_contents(contents).map_err(|e| anyhow!("{}: {}", id, e))?)
+    } else if let Some(ty) = entry.get("type") {
+        let ty = ty.as_str().ok_or_else(|| anyhow!("{}: type must be a string", id))?;
+        parse_int_type(ty).ok_or_else(|| anyhow!("{}: type '{}' is outside the supported .ksy subset", id, ty))?
+    } else if entry.get("size-eos").and_then(Value::as_bool) == Some(true) {
+        KsyKind::Bytes(KsySize::Eos)
+    } else if let Some(size) = entry.get("size") {
+        match (size.as_u64(), size.as_str()) {
+            (Some(n), _) => KsyKind::Bytes(KsySize::Fixed(n as usize)),
+            (None, Some(name)) if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                KsyKind::Bytes(KsySize::Field(name.to_string()))
+            }
+            _ => return Err(anyhow!("{}: size must be a number or a field id (expressions are not supported)", id)),
+        }
+    } else {
+        return Err(anyhow!("{}: needs contents, type, size or size-eos", id));
+    };
+    Ok(KsyField { id, kind })
+}
+
+fn parse_contents(contents: &Value) -> Result<Vec<u8>> {
+    let item = |v: &Value| -> Result<Vec<u8>> {
+        match (v.as_u64(), v.as_str()) {
+            (Some(n), _) => u8::try_from(n).map(|b| vec![b]).map_err(|_| anyhow!("contents byte {} is out of range", n)),
+            (None, Some(s)) => Ok(s.as_bytes().to_vec()),
+            _ => Err(anyhow!("contents must be bytes or strings")),
+        }
+    };
+    match contents.as_sequence() {
+        Some(items) => Ok(items.iter().map(item).collect::<Result<Vec<_>>>()?.concat()),
+        None => item(contents),
+    }
+}
+
+/// `u1`..`s8` with an optional `le` / `be` suffix
+fn parse_int_type(ty: &str) -> Option<KsyKind> {
+    let (base, big_endian) = match ty.len() {
+        2 => (ty, None),
+        4 if ty.ends_with("le") => (&ty[..2], Some(false)),
+        4 if ty.ends_with("be") => (&ty[..2], Some(true)),
+        _ => return None,
+    };
+    let signed = match &base[..1] {
+        "u" => false,
+        "s" => true,
+        _ => return None,
+    };
+    let size = match &base[1..] {
+        "1" => 1,
+        "2" => 2,
+        "4" => 4,
+        "8" => 8,
+        _ => return None,
+    };
+    Some(KsyKind::Int { size, signed, big_endian })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::spec_export::{export, find_layout, TemplateKind};
+
+    #[test]
+    fn reads_exported_and_hand_written_templates() {
+        // The export-spec output for LF2 parses a real LF2 header
+        let ksy = export(find_layout("lf2").unwrap(), TemplateKind::Kaitai);
+        let spec = KsySpec::from_yaml(&ksy).unwrap();
+        let image = crate::formats::toheart::lf2::Lf2Image {
+            width: 300,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 1,
+            palette: vec![crate::formats::toheart::lf2::Rgb { r: 0, g: 0, b: 0 }],
+            pixels: vec![0; 600],
+        };
+        let data = image.to_lf2_bytes().unwrap();
+        let fields = spec.parse(&data).unwrap();
+        let width = fields.iter().find(|f| f.id == "width").unwrap();
+        assert_eq!((width.offset, &width.value), (0x0c, &FieldValue::Unsigned(300)));
+        assert_eq!(spec.header_end(&fields), 0x18);
+        assert!(spec.parse(b"LEAF257\0").is_err());
+
+        // Big-endian, sizes from fields, signed and string contents
+        let spec = KsySpec::from_yaml(
+            "meta: {id: toy, endian: be}\n\
+             seq:\n\
+             - {id: magic, contents: [\"TY\", 0x01]}\n\
+             - {id: len, type: u2}\n\
+             - {id: name, size: len}\n\
+             - {id: delta, type: s1}\n\
+             - {id: count, type: u2le}\n\
+             - {id: rest, size-eos: true}\n",
+        ).unwrap();
+        let fields = spec.parse(b"TY\x01\x00\x03abc\xfe\x02\x01zz").unwrap();
+        assert_eq!(fields[2].value, FieldValue::Bytes(b"abc".to_vec()));
+        assert_eq!(fields[3].value, FieldValue::Signed(-2));
+        assert_eq!(fields[4].value, FieldValue::Unsigned(0x0102));
+        assert_eq!(fields[5].size, 2);
+        assert_eq!(spec.header_end(&fields), 11);
+
+        let error = KsySpec::from_yaml("seq:\n- {id: x, type: f4}\n").unwrap_err().to_string();
+        assert!(error.contains("f4"), "{}", error);
+        assert!(KsySpec::from_yaml("seq:\n- {id: x, size: 2, repeat: eos}\n").is_err());
+    }
+}
@@ -8,12 +8,95 @@
 //! - Step-by-step visualization of decoding processes
 //! - Cross-platform CLI and GUI interfaces
 //! - Educational insights into retro compression techniques
+//!
+//! # Stability
+//!
+//! Everything reachable from [`prelude`] follows semver: it only changes in
+//! breaking ways with a major version bump. Encoder research modules and
+//! the research tooling (`experiments`, `repl`, `probe`, `async_decode`)
+//! are public only with the `unstable` feature (on by
+//! default for the bundled research binaries); depend with
+//! `default-features = false` to make sure you build against the stable
+//! surface only.
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 pub mod formats;
+pub mod decoder;
+pub mod container;
+pub mod dashboard;
+pub mod archive;
+pub mod ksy;
+pub mod lzss;
+pub mod messages;
+pub mod metrics;
+pub mod montage;
+pub mod progress;
+pub mod progressive;
+pub mod provenance;
+pub mod quantize;
+pub mod report;
+pub mod romanize;
+pub mod sequences;
+pub mod session;
+pub mod stats;
+pub mod threads;
+pub mod timing;
+pub mod tiles;
+pub mod trim;
+pub mod carve;
+pub mod checksum;
+pub mod colors;
+pub mod config_file;
+pub mod vectors;
+pub mod hashdb;
+pub mod history;
+pub mod project;
+pub mod journal;
+pub mod paths;
+pub mod plan;
+pub mod perceptual;
+pub mod profile;
+pub mod output;
+pub mod palette_report;
+pub mod trace;
+
+// Research tooling: hypothesis runs, the exploratory REPL, headerless
+// probing and the async front end. Only public with the `unstable` feature,
+// like the encoder research modules.
+#[cfg(feature = "unstable")]
+pub mod experiments;
+#[cfg(feature = "unstable")]
+pub mod async_decode;
+#[cfg(feature = "unstable")]
+pub mod probe;
+#[cfg(feature = "unstable")]
+pub mod repl;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod experiments;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod async_decode;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod probe;
+#[cfg(not(feature = "unstable"))]
+#[allow(dead_code)]
+pub(crate) mod repl;
+
+#[cfg(feature = "bridges")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bridges")))]
 pub mod bridge;
 
+#[cfg(feature = "serve")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serve")))]
+pub mod serve;
+
+#[cfg(feature = "zip")]
+#[cfg_attr(docsrs, doc(cfg(feature = "zip")))]
+pub mod zip;
+
 #[cfg(feature = "gui")]
 #[cfg_attr(docsrs, doc(cfg(feature = "gui")))]
 pub mod gui;
@@ -25,9 +108,11 @@ pub mod wasm;
 use std::path::PathBuf;
 
 pub use formats::{FormatType, DecodeStep, DecodingState};
+pub use container::{open_container, Container, ContainerEntry};
 
 /// Configuration for the CLI application
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub input: Option<PathBuf>,
     pub input_dir: Option<PathBuf>,
@@ -35,15 +120,126 @@ pub struct Config {
     pub format: String,
     pub language: String,
     pub parallel: bool,
+    /// Worker threads with `parallel`; `RAYON_NUM_THREADS` or every core
+    /// when unset
+    pub threads: Option<usize>,
     pub gpu: bool,
     pub step_by_step: bool,
     pub verbose: bool,
     pub gui: bool,
     pub benchmark: bool,
+    /// Print `--benchmark` records as JSON Lines; same as a `json`
+    /// `benchmark_format`, which sessions recorded before it lack
+    pub json: bool,
+    pub benchmark_format: report::BenchmarkFormat,
+    pub palette_swap: Option<PathBuf>,
+    pub sidecar: bool,
+    pub resume: bool,
+    /// Batch runs write an [`ErrorReport`](report::ErrorReport) here
+    pub error_report: Option<PathBuf>,
+    /// Batch runs stop at the first failed file
+    pub fail_fast: bool,
+    pub direct_writes: bool,
+    pub trace: Option<PathBuf>,
+    pub orientation: decoder::Orientation,
+    pub rgb565_order: output::Endianness,
+    /// Export a deduplicated tileset and map instead of a single image
+    pub tiles: Option<tiles::TileSize>,
+    /// Crop exports to the non-transparent bounding box
+    pub trim: bool,
+    /// Write archive entries under ASCII romaji names
+    pub romanize: bool,
+    /// Render LF2 images once per palette instead of a single image
+    pub palettes: Option<formats::toheart::palette_variants::PaletteExport>,
+    /// Assemble numbered frames found by batch runs into animations
+    pub sequences: Option<sequences::AnimationFormat>,
+    /// Delay between animation frames; `DEFAULT_FRAME_DELAY_MS` when unset
+    pub frame_delay_ms: Option<u32>,
+    /// Also write the raw palette index plane of indexed images
+    pub also_indices: Option<output::IndexPlane>,
+    /// Also write the palette of indexed images in these file types
+    pub export_palette: Vec<output::PaletteFile>,
+    /// Write JSON Lines progress events to stderr during batch runs
+    pub progress_json: bool,
+    /// Batch runs descend into subdirectories and mirror their layout
+    /// under `output`
+    pub recursive: bool,
+    /// Batch runs only convert files matching one of these globs (all
+    /// when empty)
+    pub include: Vec<String>,
+    /// Batch runs skip files matching any of these globs
+    pub exclude: Vec<String>,
+    /// Stream decoded scanlines into the output instead of holding the image
+    pub low_memory: bool,
+    /// Draw no progress bars
+    pub quiet: bool,
+    /// Where outputs go below `output`
+    pub layout: project::OutputLayout,
+    /// Image output paths below `output`, replacing `layout` and the
+    /// `<stem>.<format>` name
+    pub output_template: Option<paths::OutputTemplate>,
+    /// Output format per input extension (lowercase), overriding `format`
+    pub format_overrides: std::collections::BTreeMap<String, String>,
+    /// Report the planned outputs instead of converting
+    pub dry_run: bool,
+    /// Caps for untrusted input, see [`DecodeConfig::limits`]
+    pub limits: decoder::DecodeLimits,
+}
+
+impl Config {
+    /// The per-file decoder settings of this run
+    pub fn decode_config(&self) -> DecodeConfig {
+        DecodeConfig {
+            parallel: self.parallel,
+            gpu: self.gpu,
+            step_by_step: self.step_by_step || self.trace.is_some(),
+            verbose: self.verbose,
+            benchmark: self.benchmark,
+            no_output: false,
+            sidecar: self.sidecar,
+            direct_writes: self.direct_writes,
+            trace_output: self.trace.clone(),
+            orientation: self.orientation,
+            rgb565_order: self.rgb565_order,
+            limits: self.limits,
+            romanize: self.romanize,
+            low_memory: self.low_memory,
+            progress: None,
+        }
+    }
+
+    /// Batch workers: 1 unless `parallel`, then [`threads::effective`]
+    pub fn worker_threads(&self) -> usize {
+        if self.parallel {
+            threads::effective(self.threads)
+        } else {
+            1
+        }
+    }
+
+    /// How `--benchmark` prints its records
+    pub fn benchmark_format(&self) -> report::BenchmarkFormat {
+        if self.json { report::BenchmarkFormat::Json } else { self.benchmark_format }
+    }
 }
 
-/// Re-export commonly used types
+/// Semver-stable API surface
+///
+/// ```no_run
+/// use retro_decode::prelude::*;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let data = std::fs::read("C0101.LF2")?;
+/// let image: DecodedImage = decoder_for(&FormatType::ToHeartLf2)?.decode(&data)?;
+/// println!("{}x{}, {} RGBA bytes", image.width, image.height, image.rgba.len());
+/// # Ok(())
+/// # }
+/// ```
 pub mod prelude {
+    pub use crate::container::{open_container, Container, ContainerEntry};
+    pub use crate::decoder::{decoder_for, DecodeLimits, DecodedImage, Decoder, Error, Limit, Lf2Decoder, MagDecoder, MgrDecoder, Orientation, PdtDecoder};
+    pub use crate::lzss::LzssSpec;
+    pub use crate::output::{register_writer, ImageWriter};
     pub use crate::formats::{FormatType, DecodeStep, DecodingState};
     pub use crate::formats::toheart::{PakArchive, Lf2Image};
     pub use crate::formats::kanon::{PdtImage, G00Image};
@@ -59,5 +255,28 @@ pub struct DecodeConfig {
     pub verbose: bool,
     pub benchmark: bool,
     pub no_output: bool,
+    /// Write a `.meta.json` sidecar next to each exported image
+    pub sidecar: bool,
+    /// Write outputs in place instead of temp file + rename
+    /// (for filesystems without atomic rename)
+    pub direct_writes: bool,
+    /// Save the step-by-step trace here (JSON, or CBOR for `.cbor`)
+    pub trace_output: Option<PathBuf>,
+    /// Row order of exported images (display by default)
+    pub orientation: decoder::Orientation,
+    /// Byte order of `.rgb565` exports
+    pub rgb565_order: output::Endianness,
+    /// Caps for untrusted input. The [`decoder`] API enforces all of them;
+    /// the file-based paths check `max_steps` everywhere and the pixel and
+    /// output caps for LF2, SCN, PDT and MAG before decompressing
+    pub limits: decoder::DecodeLimits,
+    /// Write archive entries under ASCII romaji names plus a
+    /// `romanize.json` manifest
+    pub romanize: bool,
+    /// Write decoded scanlines straight to the output where the format
+    /// allows (PDT to bmp / raw / rgba / rgb565)
+    pub low_memory: bool,
+    /// Receives archive entry and PDT pixel progress
+    pub progress: Option<progress::ProgressCallback>,
 }
 
@@ -0,0 +1,501 @@
+//! LZSS parameter sets
+//!
+//! The Leaf/Key era formats all use byte-oriented LZSS descended from
+//! Okumura's `lzss.c`: a flag byte announces eight items, each either a
+//! literal byte or a 2-byte ring buffer reference with a 12-bit position and
+//! 4-bit length. What differs per game is captured in [`LzssSpec`].
+
+use serde::{Serialize, Deserialize};
+
+/// Order in which a flag byte's bits are consumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+/// How the 12-bit position and 4-bit length are packed into two bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReferenceLayout {
+    /// Leaf LF2: length in the low nibble of byte 0,
+    /// position = `byte0 >> 4 | byte1 << 4`
+    LengthFirst,
+    /// Okumura `lzss.c`: position = `byte0 | (byte1 & 0xf0) << 4`,
+    /// length in the low nibble of byte 1
+    PositionFirst,
+}
+
+/// Parameters of a byte-oriented LZSS variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LzssSpec {
+    /// Ring buffer size (power of two, at most 0x1000)
+    pub window_size: usize,
+    /// Byte the ring buffer is pre-filled with
+    pub initial_fill: u8,
+    /// First ring buffer write position
+    pub initial_position: usize,
+    /// Shortest match a reference can encode (the length bias)
+    pub min_match: usize,
+    /// Longest match a reference can encode
+    pub max_match: usize,
+    /// Every stream byte (flags included) is XORed with this key
+    pub xor_key: u8,
+    /// Flag bit value that marks a literal
+    pub literal_flag: bool,
+    pub flag_order: BitOrder,
+    pub reference: ReferenceLayout,
+}
+
+/// Result of [`LzssSpec::decompress_stream`]
+#[derive(Debug, Clone)]
+pub struct LzssOutput {
+    pub data: Vec<u8>,
+    /// Input bytes consumed
+    pub consumed: usize,
+    pub literals: usize,
+    pub matches: usize,
+    /// False if the input ended in the middle of a literal or reference
+    pub clean_end: bool,
+}
+
+/// One item of a stream, reported by [`LzssSpec::decompress_events`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LzssEvent {
+    /// Flag byte (un-XORed) at input `offset`
+    Flag { offset: usize, flag: u8 },
+    /// Literal `byte` (un-XORed) at input `offset`
+    Literal { offset: usize, byte: u8 },
+    /// Two-byte reference at input `offset`: `length` bytes copied from ring
+    /// `position`, `distance` behind the write position
+    Match { offset: usize, position: usize, distance: usize, length: usize },
+}
+
+/// Decoder state right after an [`LzssEvent`] was applied
+#[derive(Debug, Clone, Copy)]
+pub struct LzssCursor<'a> {
+    /// Everything decoded so far
+    pub output: &'a [u8],
+    pub ring: &'a [u8],
+    /// Next ring buffer write position
+    pub ring_position: usize,
+}
+
+/// Result of [`LzssSpec::decompress_parallel`]
+#[derive(Debug, Clone)]
+pub struct ParallelOutput {
+    pub data: Vec<u8>,
+    /// Segments decoded concurrently (1 means the serial path was taken)
+    pub segments: usize,
+    /// Bytes copied from an earlier segment, filled in serially after the
+    /// concurrent pass
+    pub deferred: usize,
+}
+
+/// One literal or reference of a stream, from [`LzssSpec::tokens`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// Input offset of the flag byte announcing this token
+    pub flag_offset: usize,
+    /// Input offset of the token's first byte
+    pub offset: usize,
+    /// Raw (still XORed) token bytes: one for a literal, two for a reference
+    pub bytes: Vec<u8>,
+    /// Output bytes the token produces
+    pub output: std::ops::Range<usize>,
+}
+
+/// Decoded segment: bytes plus `(index, source)` pairs still to be copied
+/// from earlier output, both as absolute output offsets
+type Segment = (Vec<u8>, Vec<(usize, usize)>);
+
+impl LzssSpec {
+    /// ToHeart / Kizuato LF2 pixel stream
+    pub const LF2: LzssSpec = LzssSpec {
+        window_size: 0x1000,
+        initial_fill: 0x20,
+        initial_position: 0x0fee,
+        min_match: 3,
+        max_match: 18,
+        xor_key: 0xff,
+        literal_flag: true,
+        flag_order: BitOrder::MsbFirst,
+        reference: ReferenceLayout::LengthFirst,
+    };
+
+    /// Okumura's original `lzss.c`
+    pub const OKUMURA: LzssSpec = LzssSpec {
+        window_size: 0x1000,
+        initial_fill: 0x20,
+        initial_position: 0x0fee,
+        min_match: 3,
+        max_match: 18,
+        xor_key: 0x00,
+        literal_flag: true,
+        flag_order: BitOrder::LsbFirst,
+        reference: ReferenceLayout::PositionFirst,
+    };
+
+    /// Decompress `input` into exactly `output_len` bytes (stream order, no
+    /// Y-flip). Stops early and returns what was decoded if `input` runs out.
+    pub fn decompress(&self, input: &[u8], output_len: usize) -> Vec<u8> {
+        self.decompress_stream(input, output_len).data
+    }
+
+    /// Decompress until `input` is exhausted or `max_output` bytes were
+    /// produced, reporting how the stream ended
+    pub fn decompress_stream(&self, input: &[u8], max_output: usize) -> LzssOutput {
+        self.decompress_with(input, max_output, |_, _| {})
+    }
+
+    /// [`decompress_stream`](Self::decompress_stream), calling
+    /// `on_match(distance, length)` for every reference. The distance is how
+    /// far behind the write position the copy starts in the ring buffer.
+    pub fn decompress_with<F>(&self, input: &[u8], max_output: usize, mut on_match: F) -> LzssOutput
+    where
+        F: FnMut(usize, usize),
+    {
+        self.decompress_events(input, max_output, |event, _| {
+            if let LzssEvent::Match { distance, length, .. } = *event {
+                on_match(distance, length);
+            }
+        })
+    }
+
+    /// [`decompress_stream`](Self::decompress_stream), calling `on_event`
+    /// after every flag byte, literal and reference with the decoder state
+    /// it left behind. Every other decode path is a wrapper around this one,
+    /// so step-by-step traces cannot drift from the fast decoder.
+    pub fn decompress_events<F>(&self, input: &[u8], max_output: usize, mut on_event: F) -> LzssOutput
+    where
+        F: FnMut(&LzssEvent, LzssCursor<'_>),
+    {
+        let output = self.try_decompress_events(input, max_output, |event, cursor| {
+            on_event(event, cursor);
+            Ok::<(), std::convert::Infallible>(())
+        });
+        match output {
+            Ok(output) => output,
+            Err(never) => match never {},
+        }
+    }
+
+    /// [`decompress_events`](Self::decompress_events) with a hook that can
+    /// stop the decode; its first error is returned as is
+    pub fn try_decompress_events<F, E>(&self, input: &[u8], max_output: usize, mut on_event: F) -> Result<LzssOutput, E>
+    where
+        F: FnMut(&LzssEvent, LzssCursor<'_>) -> Result<(), E>,
+    {
+        let mask = self.window_size - 1;
+        let mut ring = vec![self.initial_fill; self.window_size];
+        let mut ring_pos = self.initial_position & mask;
+        let mut out = Vec::with_capacity(max_output.min(input.len() * 8));
+        let mut pos = 0;
+        let mut literals = 0;
+        let mut matches = 0;
+        let mut clean_end = true;
+        let byte_at = |i: usize| input[i] ^ self.xor_key;
+
+        'outer: while out.len() < max_output && pos < input.len() {
+            let flag = byte_at(pos);
+            on_event(
+                &LzssEvent::Flag { offset: pos, flag },
+                LzssCursor { output: &out, ring: &ring, ring_position: ring_pos },
+            )?;
+            pos += 1;
+            for i in 0..8 {
+                if out.len() >= max_output || pos >= input.len() {
+                    break 'outer;
+                }
+                let bit = match self.flag_order {
+                    BitOrder::MsbFirst => flag & (0x80 >> i) != 0,
+                    BitOrder::LsbFirst => flag & (1 << i) != 0,
+                };
+                if bit == self.literal_flag {
+                    let byte = byte_at(pos);
+                    ring[ring_pos] = byte;
+                    ring_pos = (ring_pos + 1) & mask;
+                    out.push(byte);
+                    literals += 1;
+                    on_event(
+                        &LzssEvent::Literal { offset: pos, byte },
+                        LzssCursor { output: &out, ring: &ring, ring_position: ring_pos },
+                    )?;
+                    pos += 1;
+                } else {
+                    if pos + 1 >= input.len() {
+                        clean_end = false;
+                        pos = input.len();
+                        break 'outer;
+                    }
+                    let (b0, b1) = (byte_at(pos), byte_at(pos + 1));
+                    let offset = pos;
+                    pos += 2;
+                    let (position, length_code) = match self.reference {
+                        ReferenceLayout::LengthFirst => ((b0 >> 4) as usize | (b1 as usize) << 4, b0 & 0x0f),
+                        ReferenceLayout::PositionFirst => (b0 as usize | ((b1 & 0xf0) as usize) << 4, b1 & 0x0f),
+                    };
+                    let length = length_code as usize + self.min_match;
+                    let mut copy_pos = position & mask;
+                    let distance = ring_pos.wrapping_sub(copy_pos) & mask;
+                    for _ in 0..length {
+                        if out.len() >= max_output {
+                            break;
+                        }
+                        let byte = ring[copy_pos];
+                        ring[ring_pos] = byte;
+                        ring_pos = (ring_pos + 1) & mask;
+                        copy_pos = (copy_pos + 1) & mask;
+                        out.push(byte);
+                    }
+                    matches += 1;
+                    on_event(
+                        &LzssEvent::Match { offset, position: position & mask, distance, length },
+                        LzssCursor { output: &out, ring: &ring, ring_position: ring_pos },
+                    )?;
+                }
+            }
+        }
+
+        Ok(LzssOutput { data: out, consumed: pos, literals, matches, clean_end })
+    }
+
+    /// Speculative parallel [`decompress`](Self::decompress)
+    ///
+    /// A cheap first pass walks the flag bytes (skipping literal payloads)
+    /// to find where each flag block starts in the input and the output. The
+    /// output is cut at those boundaries and every segment is decoded on its
+    /// own thread. A reference is only a distance back into the output (or
+    /// into the initial fill), so bytes that land in the segment's own range
+    /// are copied immediately; the rest are recorded and copied serially
+    /// once all earlier segments are complete. Falls back to the serial
+    /// decoder when `threads` or the stream is too small to split.
+    pub fn decompress_parallel(&self, input: &[u8], output_len: usize, threads: usize) -> ParallelOutput {
+        let blocks = self.flag_blocks(input, output_len);
+        let total = blocks.last().map_or(0, |&(_, out)| out);
+        let mut starts: Vec<(usize, usize)> = Vec::new();
+        for k in 0..threads.max(1) {
+            let target = total * k / threads.max(1);
+            let idx = blocks.partition_point(|&(_, out)| out < target).min(blocks.len() - 1);
+            if starts.last() != Some(&blocks[idx]) {
+                starts.push(blocks[idx]);
+            }
+        }
+        if starts.len() < 2 {
+            return ParallelOutput { data: self.decompress(input, output_len), segments: 1, deferred: 0 };
+        }
+
+        let segments: Vec<Segment> = std::thread::scope(|scope| {
+            let handles: Vec<_> = starts
+                .iter()
+                .enumerate()
+                .map(|(i, &(in_start, out_start))| {
+                    let in_end = starts.get(i + 1).map_or(input.len(), |&(p, _)| p);
+                    scope.spawn(move || self.decode_segment(&input[..in_end], in_start, out_start, output_len))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("segment decoder panicked")).collect()
+        });
+
+        let mut data = Vec::with_capacity(output_len);
+        let mut deferred = 0;
+        for (bytes, pending) in segments {
+            data.extend_from_slice(&bytes);
+            deferred += pending.len();
+            for (index, source) in pending {
+                data[index] = data[source];
+            }
+        }
+        ParallelOutput { data, segments: starts.len(), deferred }
+    }
+
+    /// `(input offset, output offset)` at the start of every flag block,
+    /// with a final entry for the end of the stream
+    fn flag_blocks(&self, input: &[u8], max_output: usize) -> Vec<(usize, usize)> {
+        let mut blocks = Vec::new();
+        let (mut pos, mut out) = (0, 0);
+        'outer: while out < max_output && pos < input.len() {
+            blocks.push((pos, out));
+            let flag = input[pos] ^ self.xor_key;
+            pos += 1;
+            for i in 0..8 {
+                if out >= max_output || pos >= input.len() {
+                    break 'outer;
+                }
+                if self.flag_bit(flag, i) == self.literal_flag {
+                    pos += 1;
+                    out += 1;
+                } else {
+                    if pos + 1 >= input.len() {
+                        break 'outer;
+                    }
+                    out = (out + self.reference_length(input[pos], input[pos + 1])).min(max_output);
+                    pos += 2;
+                }
+            }
+        }
+        blocks.push((pos, out));
+        blocks
+    }
+
+    /// Every token of `input` up to `max_output` output bytes, for mapping
+    /// compressed bytes to the output they produce
+    pub fn tokens(&self, input: &[u8], max_output: usize) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let (mut pos, mut out) = (0, 0);
+        'outer: while out < max_output && pos < input.len() {
+            let flag_offset = pos;
+            let flag = input[pos] ^ self.xor_key;
+            pos += 1;
+            for i in 0..8 {
+                if out >= max_output || pos >= input.len() {
+                    break 'outer;
+                }
+                let (byte_len, out_len) = if self.flag_bit(flag, i) == self.literal_flag {
+                    (1, 1)
+                } else if pos + 1 < input.len() {
+                    (2, self.reference_length(input[pos], input[pos + 1]))
+                } else {
+                    break 'outer;
+                };
+                let end = (out + out_len).min(max_output);
+                tokens.push(Token { flag_offset, offset: pos, bytes: input[pos..pos + byte_len].to_vec(), output: out..end });
+                pos += byte_len;
+                out = end;
+            }
+        }
+        tokens
+    }
+
+    /// Decode the flag blocks in `input[pos..]`, whose first byte lands at
+    /// output offset `out_start`
+    fn decode_segment(&self, input: &[u8], mut pos: usize, out_start: usize, max_output: usize) -> Segment {
+        let mask = self.window_size - 1;
+        let mut bytes: Vec<u8> = Vec::new();
+        // Whether each byte of `bytes` is still waiting for an earlier segment
+        let mut waiting: Vec<bool> = Vec::new();
+        let mut pending = Vec::new();
+        let byte_at = |i: usize| input[i] ^ self.xor_key;
+
+        'outer: while out_start + bytes.len() < max_output && pos < input.len() {
+            let flag = byte_at(pos);
+            pos += 1;
+            for i in 0..8 {
+                if out_start + bytes.len() >= max_output || pos >= input.len() {
+                    break 'outer;
+                }
+                if self.flag_bit(flag, i) == self.literal_flag {
+                    bytes.push(byte_at(pos));
+                    waiting.push(false);
+                    pos += 1;
+                } else {
+                    if pos + 1 >= input.len() {
+                        break 'outer;
+                    }
+                    let length = self.reference_length(input[pos], input[pos + 1]);
+                    let position = match self.reference {
+                        ReferenceLayout::LengthFirst => (byte_at(pos) >> 4) as usize | (byte_at(pos + 1) as usize) << 4,
+                        ReferenceLayout::PositionFirst => byte_at(pos) as usize | ((byte_at(pos + 1) & 0xf0) as usize) << 4,
+                    };
+                    pos += 2;
+                    let out = out_start + bytes.len();
+                    let ring_pos = (self.initial_position + out) & mask;
+                    // A distance of 0 reads the slot about to be overwritten
+                    let distance = match ring_pos.wrapping_sub(position) & mask {
+                        0 => self.window_size,
+                        d => d,
+                    };
+                    for k in 0..length.min(max_output - out) {
+                        let index = out + k;
+                        match index.checked_sub(distance) {
+                            // Before the first write: the initial fill
+                            None => {
+                                bytes.push(self.initial_fill);
+                                waiting.push(false);
+                            }
+                            Some(source) if source >= out_start && !waiting[source - out_start] => {
+                                bytes.push(bytes[source - out_start]);
+                                waiting.push(false);
+                            }
+                            Some(source) => {
+                                bytes.push(0);
+                                waiting.push(true);
+                                pending.push((index, source));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (bytes, pending)
+    }
+
+    fn flag_bit(&self, flag: u8, i: usize) -> bool {
+        match self.flag_order {
+            BitOrder::MsbFirst => flag & (0x80 >> i) != 0,
+            BitOrder::LsbFirst => flag & (1 << i) != 0,
+        }
+    }
+
+    /// Match length of a raw (still XORed) reference
+    fn reference_length(&self, b0: u8, b1: u8) -> usize {
+        let code = match self.reference {
+            ReferenceLayout::LengthFirst => (b0 ^ self.xor_key) & 0x0f,
+            ReferenceLayout::PositionFirst => (b1 ^ self.xor_key) & 0x0f,
+        };
+        code as usize + self.min_match
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn lf2_spec_matches_lf2_decoder() {
+        let image = Lf2Image {
+            width: 4,
+            height: 3,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: vec![0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 1, 1],
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+        let stream = LzssSpec::LF2.decompress(&data[0x18 + 2 * 3..], 12);
+
+        // The stream is bottom-up; LF2 images are stored top-down
+        let flipped: Vec<u8> = stream.chunks(4).rev().flatten().copied().collect();
+        assert_eq!(flipped, image.pixels);
+    }
+
+    #[test]
+    fn parallel_decode_matches_serial() {
+        let (width, height) = (96u16, 64u16);
+        let image = Lf2Image {
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 16,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 16],
+            pixels: (0..width as usize * height as usize).map(|i| ((i * 7 / 5 + i / 96) % 16) as u8).collect(),
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+        let stream = &data[0x18 + 16 * 3..];
+        let len = image.pixels.len();
+
+        let serial = LzssSpec::LF2.decompress(stream, len);
+        for threads in [1, 2, 3, 8, 64] {
+            let parallel = LzssSpec::LF2.decompress_parallel(stream, len, threads);
+            assert_eq!(parallel.data, serial, "{} threads", threads);
+        }
+        // Truncated output and truncated input stop where the serial decoder does
+        assert_eq!(LzssSpec::LF2.decompress_parallel(stream, 1000, 4).data, LzssSpec::LF2.decompress(stream, 1000));
+        let cut = &stream[..stream.len() / 2];
+        assert_eq!(LzssSpec::LF2.decompress_parallel(cut, len, 4).data, LzssSpec::LF2.decompress(cut, len));
+    }
+}
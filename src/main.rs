@@ -1,11 +1,177 @@
 use clap::{Arg, ArgAction, Command};
 use std::path::PathBuf;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 use retro_decode::{Config, formats::FormatType};
+use retro_decode::report::BenchmarkFormat;
 
 fn main() {
-    let matches = Command::new("retro-decode")
+    let matches = cli().get_matches();
+
+    if matches.get_flag("list-formats") {
+        print_format_list();
+        return;
+    }
+
+    if let Some(kind) = matches.get_one::<retro_decode::report::SchemaKind>("dump-schema") {
+        println!("{}", serde_json::to_string_pretty(&kind.schema()).unwrap());
+        return;
+    }
+
+    let (profile_guard, metrics_guard) = init_logging(&matches);
+
+    let result = match matches.subcommand() {
+        Some((name, sub)) => run_subcommand(name, sub),
+        None => run_legacy(&matches),
+    };
+    if let Err(e) = result {
+        let code = match e.downcast_ref::<ExitStatus>() {
+            Some(status) => status.0,
+            None => {
+                log_error("Error: ", &e);
+                1
+            }
+        };
+        drop(profile_guard);
+        drop(metrics_guard);
+        std::process::exit(code);
+    }
+}
+
+/// A run that completed but reports a non-zero exit status, such as
+/// `verify --strict` finding a mismatch. Returned as an error so `main`
+/// drops the profile and metrics guards before exiting.
+#[derive(Debug)]
+struct ExitStatus(i32);
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
+
+/// Install the log output and the `--profile-out` layer; the guards write
+/// the profile and the `--metrics` totals when dropped
+fn init_logging(
+    matches: &clap::ArgMatches,
+) -> (Option<retro_decode::profile::FlushGuard>, Option<retro_decode::metrics::DumpGuard>) {
+    let log_level = if matches.get_flag("verbose") {
+        "debug"
+    } else {
+        "info"
+    };
+
+    // Keep stdout parseable when it carries JSON records or CSV rows
+    let structured = |m: &clap::ArgMatches| {
+        flag(m, "json") || value::<BenchmarkFormat>(m, "benchmark-format").is_some_and(|f| f != BenchmarkFormat::Text)
+    };
+    let json_output = structured(matches) || matches.subcommand().is_some_and(|(_, sub)| structured(sub));
+    let writer = if json_output {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_filter(EnvFilter::new(format!("retro_decode={}", log_level)));
+    // The profile layer is unfiltered so it sees the trace-level spans
+    let (profile_layer, profile_guard) = match matches.get_one::<PathBuf>("profile-out") {
+        Some(path) => {
+            let (layer, guard) = retro_decode::profile::folded_layer(path, shared_config(matches).direct_writes);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry().with(fmt_layer).with(profile_layer).init();
+    // Printed when dropped, so runs that fail still report their counts
+    let metrics_guard = matches.get_flag("metrics").then(retro_decode::metrics::dump_on_drop);
+    (profile_guard, metrics_guard)
+}
+
+/// Run subcommand `name`. The global options are read into one
+/// [`shared_config`] here for the commands that write files; `decode` and
+/// `bench` get them through [`config_from_matches`]
+fn run_subcommand(name: &str, sub: &clap::ArgMatches) -> anyhow::Result<()> {
+    let shared = shared_config(sub);
+    match name {
+        "decode" => run_decode(sub),
+        "extract" => run_extract(sub, &shared),
+        "pack" => run_pack(sub, &shared),
+        "archive-game" => run_archive_game(sub, &shared),
+        "bench" => run_bench(sub),
+        "reencode" => run_reencode(sub, &shared),
+        "encode" => run_encode(sub, &shared),
+        "verify" => run_verify(sub, &shared),
+        "project" => run_project(sub),
+        "config" => run_config_command(sub, &shared),
+        "trace" => run_trace(sub, &shared),
+        "planar" => run_planar(sub, &shared),
+        "inspect" => run_inspect(sub),
+        "carve" => run_carve(sub, &shared),
+        "probe" => run_probe(sub),
+        "export-spec" => run_export_spec(sub, &shared),
+        "spec" => run_spec(sub, &shared),
+        "export-vectors" => run_export_vectors(sub, &shared),
+        "progressive" => run_progressive(sub, &shared),
+        "repl" => run_repl(sub, &shared),
+        "replay" => run_replay(sub),
+        "stats" => run_stats(sub, &shared),
+        "serve-static" => run_serve_static(sub),
+        "montage" => run_montage(sub, &shared),
+        "hypothesis" => run_hypothesis(sub),
+        "palette-report" => run_palette_report(sub, &shared),
+        "dashboard" => run_dashboard(sub, &shared),
+        _ => unreachable!("Unknown subcommand - should be caught by clap"),
+    }
+}
+
+/// The flat `--input` / `--input-dir` / `--gui` command line of earlier
+/// versions
+fn run_legacy(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let mut config = config_from_matches(matches)?;
+    config.input = matches.get_one::<PathBuf>("input").cloned();
+    config.input_dir = matches.get_one::<PathBuf>("input-dir").cloned();
+    config.gui = matches.get_flag("gui");
+
+    info!("RetroDecode P⁴ - Pixel by pixel, past preserved");
+
+    if config.gui {
+        #[cfg(feature = "gui")]
+        {
+            info!("Launching GUI interface...");
+            return retro_decode::gui::launch().map_err(|e| anyhow::anyhow!("Failed to launch GUI: {}", e));
+        }
+        #[cfg(not(feature = "gui"))]
+        {
+            return Err(anyhow::anyhow!("GUI feature not enabled. Rebuild with --features gui"));
+        }
+    }
+
+    // The flat flags stay as a shim for existing scripts
+    if config.input.is_some() || config.input_dir.is_some() {
+        let archive = config.input.as_deref()
+            .is_some_and(|path| FormatType::from_path(path).ok() == Some(FormatType::ToHeartPak));
+        let command = match (config.benchmark, archive) {
+            (true, _) => "bench",
+            (false, true) => "extract",
+            (false, false) => "decode",
+        };
+        warn!("--input and --input-dir are deprecated; use `retro-decode {}`", command);
+    }
+
+    run_recorded(matches, config)
+}
+
+/// The command line: every subcommand, the global options and the
+/// deprecated flat flags
+fn cli() -> Command {
+    Command::new("retro-decode")
         .version(env!("CARGO_PKG_VERSION"))
         .author("RetroDecode Contributors")
         .about("P⁴ - Pixel by pixel, past preserved\nEducational tool for analyzing retro game image formats")
@@ -17,21 +183,71 @@ Supported formats:
   • ToHeart: .pak/.PAK (archives), .lf2/.LF2, .scn/.SCN (images)
   • Kanon: .pdt/.PDT, .g00/.G00 (compressed images)  
   • Kizuato: .pak/.PAK, .lf2/.LF2 (same as ToHeart)
+  • Silky's: .mgr/.MGR (image archives); Elf .gph/.GPH recognised, not yet decoded
+  • PC-98: headerless 16-color planar screens (`planar` subcommand),
+    .mag/.MAG (MAKI02); .pi/.PI recognised, not yet decoded
 
 Examples:
-  retro-decode --input image.lf2
-  retro-decode --input image.lf2 --format png
-  retro-decode --input archive.pak --output ./extracted/
-  retro-decode --input file.pdt --output ./results/ --format rgba
-  retro-decode --input file.lf2 --lang python --gpu --parallel
+  retro-decode decode image.lf2
+  retro-decode decode image.lf2 --format png
+  retro-decode extract archive.pak --output ./extracted/
+  retro-decode pack ./extracted/ -o archive.pak
+  retro-decode inspect archive.pak
+  retro-decode decode file.pdt --output ./results/ --format rgba
+  retro-decode decode file.pdt --output ./gba/ --format rgb565 --rgb565-order little
+  retro-decode decode BG01.pdt --output ./tiles/ --format png --tiles 16x16
+  retro-decode decode file.lf2 --lang python --gpu --parallel
+  retro-decode bench ./toheart/lf2/ --parallel 4 --json
+  retro-decode decode sprites/ --palette-swap mapping.json --output ./recolored/
   retro-decode --gui
+  retro-decode reencode --from ./results/C0101.png
+  retro-decode reencode --from ./results/C0101.png --encode-profile fast
+  retro-decode verify --strict ./toheart/lf2/
+  retro-decode decode image.lf2 --trace C0101.trace.json
+  retro-decode planar SHIZUKU.VRAM --interleave line -o title.png
+  retro-decode stats sprites/ -o report/stats.json
+  retro-decode palette-report ./toheart/lf2/ -o report/palettes.json
+  retro-decode montage --input-dir ./decoded --columns 8 --out sheet.png
+  retro-decode trace migrate old.trace.json -o new.trace.cbor
+  retro-decode project run --file ./toheart/project.toml
+  retro-decode serve-static ./results --port 8080
+
+The flat --input / --input-dir flags of earlier versions still work but are
+deprecated in favour of decode, extract and bench.
         ")
+        .subcommand(decode_command())
+        .subcommand(extract_command())
+        .subcommand(pack_command())
+        .subcommand(archive_game_command())
+        .subcommand(bench_command())
+        .subcommand(reencode_command())
+        .subcommand(encode_command())
+        .subcommand(verify_command())
+        .subcommand(planar_command())
+        .subcommand(inspect_command())
+        .subcommand(carve_command())
+        .subcommand(probe_command())
+        .subcommand(export_spec_command())
+        .subcommand(spec_command())
+        .subcommand(export_vectors_command())
+        .subcommand(progressive_command())
+        .subcommand(stats_command())
+        .subcommand(hypothesis_command())
+        .subcommand(palette_report_command())
+        .subcommand(dashboard_command())
+        .subcommand(montage_command())
+        .subcommand(serve_static_command())
+        .subcommand(repl_command())
+        .subcommand(replay_command())
+        .subcommand(trace_command())
+        .subcommand(project_command())
+        .subcommand(config_command())
         .arg(
             Arg::new("input")
                 .long("input")
                 .short('i')
                 .value_name("FILE")
-                .help("Input file path")
+                .help("Input file path (legacy; use `decode` or `extract`)")
                 .value_parser(clap::value_parser!(PathBuf))
                 .conflicts_with("input-dir")
         )
@@ -39,385 +255,3112 @@ Examples:
             Arg::new("input-dir")
                 .long("input-dir")
                 .value_name("DIR")
-                .help("Input directory for batch processing")
+                .help("Input directory for batch processing (legacy; use `decode DIR`)")
                 .value_parser(clap::value_parser!(PathBuf))
                 .conflicts_with("input")
         )
+        .args(LEGACY_ARGS.iter().map(|&id| match id {
+            "resume" | "recursive" | "include" | "exclude" | "progress-json" => conversion_arg(id).requires("input-dir"),
+            _ => conversion_arg(id),
+        }))
         .arg(
-            Arg::new("output")
-                .long("output")
-                .short('o')
-                .value_name("DIR")
-                .help("Output directory")
-                .default_value("./")
-                .value_parser(clap::value_parser!(PathBuf))
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("Verbose output")
+                .global(true)
+                .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("format")
-                .long("format")
-                .short('f')
-                .value_name("FORMAT")
-                .help("Output format")
-                .value_parser(["bmp", "png", "raw", "rgba"])
-                .default_value("bmp")
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read default options from FILE instead of the discovered retro-decode.toml")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
         )
         .arg(
-            Arg::new("lang")
-                .long("lang")
-                .short('l')
-                .value_name("ENGINE")
-                .help("Processing engine")
-                .value_parser(["rust", "python", "typescript"])
-                .default_value("rust")
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Draw no progress bars")
+                .global(true)
+                .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("parallel")
-                .long("parallel")
-                .help("Enable parallel processing")
+            Arg::new("gui")
+                .long("gui")
+                .help("Launch GUI interface")
                 .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("gpu")
-                .long("gpu")
-                .help("Use GPU acceleration")
-                .action(ArgAction::SetTrue)
+            Arg::new("dump-schema")
+                .long("dump-schema")
+                .value_name("OUTPUT")
+                .help("Print the JSON Schema of the benchmark, verify, stats or errors JSON output and exit")
+                .value_parser(clap::value_parser!(retro_decode::report::SchemaKind))
         )
         .arg(
-            Arg::new("step-by-step")
-                .long("step-by-step")
-                .help("Enable educational step-by-step mode")
-                .action(ArgAction::SetTrue)
+            Arg::new("profile-out")
+                .long("profile-out")
+                .value_name("FILE")
+                .help("Time decode/encode spans and write a folded stack profile (flamegraph.pl / inferno input) when the run ends")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
         )
         .arg(
-            Arg::new("verbose")
-                .long("verbose")
-                .short('v')
-                .help("Verbose output")
+            Arg::new("metrics")
+                .long("metrics")
+                .help("Count tokens, matches vs literals, images, journal hits and bytes written, and print the totals to stderr when the run ends")
+                .global(true)
                 .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("gui")
-                .long("gui")
-                .help("Launch GUI interface")
+            Arg::new("no-atomic-writes")
+                .long("no-atomic-writes")
+                .help("Write outputs in place instead of temp file + rename (for filesystems without atomic rename)")
+                .global(true)
                 .action(ArgAction::SetTrue)
         )
         .arg(
-            Arg::new("benchmark")
-                .long("benchmark")
-                .help("Output structured benchmark information")
+            Arg::new("max-pixels")
+                .long("max-pixels")
+                .value_name("N")
+                .help("Refuse images of more than N pixels, checked against the header before decompressing")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("max-output-bytes")
+                .long("max-output-bytes")
+                .value_name("BYTES")
+                .help("Refuse images whose decoded RGBA output would exceed BYTES")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("max-steps")
+                .long("max-steps")
+                .value_name("N")
+                .help("Stop step-by-step decoding (--step-by-step, --trace) after N recorded steps")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("list-formats")
+                .long("list-formats")
+                .help("List supported formats and their capabilities")
                 .action(ArgAction::SetTrue)
         )
-        .get_matches();
-
-    // Initialize logging
-    let log_level = if matches.get_flag("verbose") {
-        "debug"
-    } else {
-        "info"
-    };
-    
-    tracing_subscriber::fmt()
-        .with_env_filter(format!("retro_decode={}", log_level))
-        .init();
-
-    let config = Config {
-        input: matches.get_one::<PathBuf>("input").cloned(),
-        input_dir: matches.get_one::<PathBuf>("input-dir").cloned(),
-        output: matches.get_one::<PathBuf>("output").cloned().unwrap(),
-        format: matches.get_one::<String>("format").cloned().unwrap(),
-        language: matches.get_one::<String>("lang").cloned().unwrap(),
-        parallel: matches.get_flag("parallel"),
-        gpu: matches.get_flag("gpu"),
-        step_by_step: matches.get_flag("step-by-step"),
-        verbose: matches.get_flag("verbose"),
-        gui: matches.get_flag("gui"),
-        benchmark: matches.get_flag("benchmark"),
-    };
-
-    info!("RetroDecode P⁴ - Pixel by pixel, past preserved");
-    
-    if config.gui {
-        #[cfg(feature = "gui")]
-        {
-            info!("Launching GUI interface...");
-            if let Err(e) = retro_decode::gui::launch() {
-                error!("Failed to launch GUI: {}", e);
-                std::process::exit(1);
-            }
-            return;
-        }
-        #[cfg(not(feature = "gui"))]
-        {
-            error!("GUI feature not enabled. Rebuild with --features gui");
-            std::process::exit(1);
-        }
-    }
+}
 
-    // Determine processing mode
-    match (config.input.clone(), config.input_dir.clone()) {
-        (Some(input_path), None) => {
-            // Single file processing
-            if let Err(e) = run_cli_single(config, input_path) {
-                error!("Error: {}", e);
-                std::process::exit(1);
-            }
-        }
-        (None, Some(input_dir)) => {
-            // Batch directory processing
-            if let Err(e) = run_cli_batch(config, input_dir) {
-                error!("Error: {}", e);
-                std::process::exit(1);
-            }
-        }
-        (None, None) => {
-            println!("RetroDecode - P⁴ (Pixel by pixel, past preserved)");
-            println!("Educational tool for analyzing retro game image formats");
-            println!("\nRun with --help for detailed usage information.");
-        }
-        (Some(_), Some(_)) => {
-            error!("Cannot specify both --input and --input-dir");
-            std::process::exit(1);
-        }
-    }
+fn decode_command() -> Command {
+    Command::new("decode")
+        .about("Convert an image, or every supported file in a directory")
+        .arg(
+            Arg::new("input")
+                .value_name("PATH")
+                .help("Image file or directory")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .args(DECODE_ARGS.iter().map(|&id| conversion_arg(id)))
 }
 
-fn run_cli_single(config: Config, input_path: PathBuf) -> anyhow::Result<()> {
-    info!("Processing file: {:?}", input_path);
-    info!("Output directory: {:?}", config.output);
-    info!("Output format: {}", config.format);
-    info!("Engine: {}", config.language);
-    
-    if config.parallel {
-        info!("Parallel processing enabled");
-    }
-    
-    if config.gpu {
-        info!("GPU acceleration requested");
-    }
+fn extract_command() -> Command {
+    Command::new("extract")
+        .about("Unpack the files of PAK archives")
+        .arg(
+            Arg::new("archive")
+                .value_name("ARCHIVE")
+                .help("PAK archives to unpack")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .help("Print the entry table (index, name, offset, size) instead of extracting")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["entry", "index"])
+        )
+        .arg(
+            Arg::new("entry")
+                .long("entry")
+                .value_name("NAME")
+                .help("Extract only the entry called NAME (case-insensitive)")
+                .conflicts_with("index")
+        )
+        .arg(
+            Arg::new("index")
+                .long("index")
+                .value_name("N")
+                .help("Extract only entry N, counted from 0 as --list shows")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .args(["output", "step-by-step", "romanize"].map(conversion_arg))
+}
 
-    // Detect format from file extension
-    let format_type = FormatType::from_path(&input_path)?;
-    info!("Detected format: {}", format_type);
+fn pack_command() -> Command {
+    Command::new("pack")
+        .about("Build a PAK (LEAFPACK) archive from files")
+        .arg(
+            Arg::new("inputs")
+                .value_name("PATH")
+                .help("Files to store, or directories whose files are stored in name order (ASCII 8.3 names, at least 3)")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Archive to write")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
 
-    // Create output directory
-    std::fs::create_dir_all(&config.output)?;
-    
-    // Build output file path with format extension
-    let output_file = config.output.join(
-        input_path.file_stem().unwrap_or_default()
-    ).with_extension(&config.format);
+fn archive_game_command() -> Command {
+    Command::new("archive-game")
+        .about("Scan, extract, decode and catalog a game directory into a browsable archive")
+        .arg(
+            Arg::new("game-dir")
+                .long("game-dir")
+                .value_name("DIR")
+                .help("Game installation or disc copy; only read")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .short('o')
+                .value_name("DIR")
+                .help("Directory for extracted/, images/, catalog.json and index.html")
+                .default_value("archive")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
 
-    // Process based on format and language
-    match config.language.as_str() {
-        "rust" => {
-            info!("Using Rust engine");
-            retro_decode::formats::process_rust(&input_path, &output_file, format_type.clone(), &config)?;
-        }
-        "python" => {
-            #[cfg(feature = "python-bridge")]
-            {
-                info!("Using Python bridge");
-                let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
-                retro_decode::bridge::python::process(&input_path, &output_file, format_type, &bridge_config)?;
-            }
-            #[cfg(not(feature = "python-bridge"))]
-            {
-                error!("Python bridge feature not enabled. Rebuild with --features python-bridge");
-                std::process::exit(1);
-            }
-        }
-        "typescript" => {
-            info!("Using TypeScript bridge");
-            let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
-            retro_decode::bridge::typescript::process(&input_path, &output_file, format_type.clone(), &bridge_config)?;
-        }
-        _ => unreachable!("Invalid language - should be caught by clap"),
-    }
+fn bench_command() -> Command {
+    Command::new("bench")
+        .about("Decode an image or directory and report timings (--benchmark of the legacy flags)")
+        .arg(
+            Arg::new("input")
+                .value_name("PATH")
+                .help("Image file or directory")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .args(BENCH_ARGS.iter().map(|&id| conversion_arg(id)))
+}
 
-    // Output benchmark information if requested
-    if config.benchmark {
-        output_benchmark_info(&input_path, &format_type, &config)?;
-    }
+fn reencode_command() -> Command {
+    Command::new("reencode")
+        .about("Rebuild the original container from an export and its .meta.json sidecar")
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("IMAGE")
+                .help("Exported PNG/BMP with a sidecar next to it")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Output file (default: original file name next to the export)")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("encoder")
+                .long("encoder")
+                .value_name("ENCODER")
+                .help("LF2 encoder (default: from sidecar, else okumura)")
+                .value_parser(["okumura", "decision-tree", "naive-strict", "naive-equal", "randomized", "scanline"])
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed for --encoder randomized [default: 0]")
+                .value_parser(clap::value_parser!(u64))
+                .requires("encoder")
+        )
+        .arg(
+            Arg::new("encode-profile")
+                .long("encode-profile")
+                .value_name("PROFILE")
+                .help("LF2 encoder profile: fast, balanced, exhaustive or faithful")
+                .value_parser(["fast", "balanced", "exhaustive", "faithful"])
+                .conflicts_with("encoder")
+        )
+        .arg(
+            Arg::new("auto-quantize")
+                .long("auto-quantize")
+                .help("Map colours missing from the palette to the nearest entry (median-cut a palette if the sidecar has none) instead of failing")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("alpha-threshold")
+                .long("alpha-threshold")
+                .value_name("0-255")
+                .help("Pixels with alpha below this become the transparent index [default: 1]")
+                .value_parser(clap::value_parser!(u8))
+        )
+        .arg(
+            Arg::new("defringe")
+                .long("defringe")
+                .help("Recolour semi-transparent edge pixels from their opaque neighbours to remove halos")
+                .action(clap::ArgAction::SetTrue)
+        )
+}
 
-    info!("Processing completed successfully");
-    Ok(())
+fn encode_command() -> Command {
+    Command::new("encode")
+        .about("Encode PNG/BMP images into LF2, optionally against one palette shared by all of them")
+        .arg(
+            Arg::new("inputs")
+                .value_name("IMAGE")
+                .help("Input frames (PNG, 8/24-bit BMP, ...)")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output-dir")
+                .long("output-dir")
+                .short('o')
+                .value_name("DIR")
+                .help("Directory for the .LF2 files (default: next to each input)")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("shared-palette")
+                .long("shared-palette")
+                .help("Compute one palette across all inputs and encode every frame against it")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("encoder")
+                .long("encoder")
+                .value_name("ENCODER")
+                .help("LF2 encoder: okumura, naive-strict, naive-equal, scanline or a profile [default: exhaustive]")
+                .value_parser(["okumura", "naive-strict", "naive-equal", "scanline", "fast", "balanced", "exhaustive", "faithful"])
+        )
+        .arg(
+            Arg::new("auto-quantize")
+                .long("auto-quantize")
+                .help("Reduce inputs with more than 254 colours by median cut instead of failing")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("alpha-threshold")
+                .long("alpha-threshold")
+                .value_name("0-255")
+                .help("Pixels with alpha below this become the transparent index [default: 1]")
+                .value_parser(clap::value_parser!(u8))
+        )
+        .arg(
+            Arg::new("defringe")
+                .long("defringe")
+                .help("Recolour semi-transparent edge pixels from their opaque neighbours to remove halos")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("offset")
+                .long("offset")
+                .value_name("X,Y")
+                .help("Screen position written to every frame [default: from each input's .meta.json sidecar, else 0,0]")
+                .value_parser(parse_offset)
+        )
 }
 
-fn run_cli_batch(config: Config, input_dir: PathBuf) -> anyhow::Result<()> {
-    info!("Batch processing directory: {:?}", input_dir);
-    info!("Output directory: {:?}", config.output);
-    info!("Output format: {}", config.format);
-    info!("Engine: {}", config.language);
-    
-    if config.parallel {
+fn verify_command() -> Command {
+    Command::new("verify")
+        .about("Check that original LF2 files re-encode byte-identically, or compare their pixels with edited copies (--against)")
+        .arg(
+            Arg::new("inputs")
+                .value_name("PATH")
+                .help("LF2 files or directories of them")
+                .required_unless_present("input")
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("input")
+                .long("input")
+                .value_name("PATH")
+                .help("Same as a PATH argument, in the form of the flat command line")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("encoder")
+                .long("encoder")
+                .value_name("ENCODER")
+                .help("LF2 encoder to verify (default: okumura)")
+                .value_parser(["okumura", "decision-tree", "naive-strict", "naive-equal", "randomized", "scanline"])
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed for the randomized encoder (--encoder randomized or --scoreboard) [default: 0]")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("encode-profile")
+                .long("encode-profile")
+                .value_name("PROFILE")
+                .help("LF2 encoder profile: fast, balanced, exhaustive or faithful")
+                .value_parser(["fast", "balanced", "exhaustive", "faithful"])
+                .conflicts_with("encoder")
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Print only failing files and exit with status 3 if any file is not byte-identical")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("regions")
+                .long("regions")
+                .help("For differing files, list the image rows and flag blocks whose tokens were not reproduced")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("scoreboard")
+                .long("scoreboard")
+                .help("Verify with every LF2 encoder and rank them by byte-identical files, to test encoder hypotheses corpus-wide")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["encoder", "encode-profile", "strict", "regions", "mask", "audit-candidates", "against", "json"])
+        )
+        .arg(
+            Arg::new("audit-candidates")
+                .long("audit-candidates")
+                .help("For differing files, check whether each original reference is among the encoder's match candidates and list search-space misses apart from tie-break mismatches")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("against")
+                .long("against")
+                .value_name("DIR")
+                .help("Compare decoded pixels with the same-named image in DIR (PNG, BMP, LF2, ...) instead of re-encoding")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["encoder", "encode-profile", "regions", "audit-candidates", "mask"])
+        )
+        .arg(
+            Arg::new("perceptual")
+                .long("perceptual")
+                .help("With --against, also compute SSIM and CIE76 ΔE and pass files within --min-ssim/--max-delta-e")
+                .action(ArgAction::SetTrue)
+                .requires("against")
+        )
+        .arg(
+            Arg::new("min-ssim")
+                .long("min-ssim")
+                .value_name("SSIM")
+                .help("Lowest mean SSIM a --perceptual match may have")
+                .default_value("0.98")
+                .value_parser(clap::value_parser!(f64))
+        )
+        .arg(
+            Arg::new("max-delta-e")
+                .long("max-delta-e")
+                .value_name("DELTA_E")
+                .help("Highest mean ΔE a --perceptual match may have (2.3 is about one just-noticeable difference)")
+                .default_value("2.3")
+                .value_parser(clap::value_parser!(f64))
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print one JSON record per file instead of text (schema: --dump-schema verify)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("mask")
+                .long("mask")
+                .value_name("DIR")
+                .help("For differing files, write <name>.diff.png marking the pixels of mismatching tokens")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("repro")
+                .long("repro")
+                .value_name("DIR")
+                .help("For differing files, write <name>.repro.json: the ring state and pixels around the first diverging token")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn planar_command() -> Command {
+    Command::new("planar")
+        .about("Decode a headerless PC-98 16-color planar screen")
+        .arg(
+            Arg::new("input")
+                .value_name("FILE")
+                .help("File containing the planar data")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Output image (png / bmp / raw / rgba by extension)")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .value_name("PIXELS")
+                .help("Width in pixels, multiple of 8")
+                .default_value("640")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .value_name("PIXELS")
+                .default_value("400")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("offset")
+                .long("offset")
+                .value_name("BYTES")
+                .help("Skip this many bytes before the first plane")
+                .default_value("0")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("interleave")
+                .long("interleave")
+                .value_name("MODE")
+                .help("plane: whole planes in sequence; line: planes interleaved per scanline")
+                .default_value("plane")
+                .value_parser(["plane", "line"])
+        )
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .value_name("FILE")
+                .help("48-byte analog palette (16 x G,R,B levels 0-15); default PC-98 16 colors")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("step-by-step")
+                .long("step-by-step")
+                .help("Record the plane merge step by step")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .value_name("FILE")
+                .help("Save the plane merge trace (implies --step-by-step)")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn inspect_command() -> Command {
+    Command::new("inspect")
+        .visible_alias("info")
+        .about("Show header fields of images and archives without decoding them")
+        .arg(
+            Arg::new("input")
+                .value_name("FILE")
+                .help("Images or archives to describe")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("colors")
+                .long("colors")
+                .help("Decode and list used palette entries with pixel counts and the unused (free) entries, plus LF2 transparency warnings; direct-colour images list their most frequent colours")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print one header record per file as JSON Lines")
+                .conflicts_with_all(["colors", "hashes"])
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("hashes")
+                .long("hashes")
+                .value_name("DB")
+                .help("Look the files (and archive entries) up in a JSON database of known release hashes and report which release they came from")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn carve_command() -> Command {
+    Command::new("carve")
+        .about("Find LF2 and PDT images embedded at any offset of other files (dumps, unknown archives)")
+        .arg(
+            Arg::new("input")
+                .value_name("FILE")
+                .help("Files to scan")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("DIR")
+                .help("Extract the images that decode as <name>_<offset>.<ext> into DIR; scan progress is saved there")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Continue interrupted scans from the progress saved in the output directory")
+                .requires("output")
+                .action(ArgAction::SetTrue)
+        )
+}
+
+fn probe_command() -> Command {
+    Command::new("probe")
+        .about("Try common LZSS parameterizations on an unrecognized file")
+        .arg(
+            Arg::new("input")
+                .value_name("FILE")
+                .help("File to probe")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("offset")
+                .long("offset")
+                .value_name("BYTES")
+                .help("Offset where the compressed stream may start (repeatable)")
+                .action(ArgAction::Append)
+                .default_value("0")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .value_name("N")
+                .help("Number of results to show")
+                .default_value("10")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("ksy")
+                .long("ksy")
+                .value_name("FILE")
+                .help("Kaitai Struct header description (flat seq subset); prints the fields and probes from the header end")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn export_spec_command() -> Command {
+    Command::new("export-spec")
+        .about("Write a Kaitai Struct or 010 Editor template for a format")
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Format name or extension (lf2, pdt, pak, mag, pi)")
+                .required(true)
+        )
+        .arg(
+            Arg::new("as")
+                .long("as")
+                .value_name("KIND")
+                .help("Template language")
+                .default_value("kaitai")
+                .value_parser(["kaitai", "010"])
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Write the template here instead of stdout")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn spec_command() -> Command {
+    Command::new("spec")
+        .about("Write the Markdown format specification generated from the layout and LZSS tables")
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Only this format (lf2, pdt, pak, mag, pi; repeatable) [default: all, with the LZSS presets]")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Write the specification here instead of stdout")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .value_name("FILE")
+                .help("Fail if FILE differs from the generated specification, for CI")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with("output")
+        )
+}
+
+fn export_vectors_command() -> Command {
+    Command::new("export-vectors")
+        .about("Write synthetic conformance vectors with their expected output")
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("DIR")
+                .help("Directory for the inputs, expected RGBA, traces and manifest.json")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn progressive_command() -> Command {
+    Command::new("progressive")
+        .about("Export an image as it filled in while loading, in the order of its LZSS tokens")
+        .arg(
+            Arg::new("input")
+                .value_name("IMAGE")
+                .help("LF2 or SCN image")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("DIR")
+                .help("Directory for <name>.progressive.png (APNG) or the numbered PNG frames")
+                .default_value(".")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("apng (one animation) or png (one file per frame)")
+                .default_value("apng")
+                .value_parser(clap::value_parser!(retro_decode::progressive::ProgressiveFormat))
+        )
+        .arg(
+            Arg::new("frames")
+                .long("frames")
+                .value_name("N")
+                .help("Frames, spread evenly over the tokens")
+                .default_value("48")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("frame-delay")
+                .long("frame-delay")
+                .value_name("MS")
+                .help("Delay between APNG frames")
+                .default_value("40")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+}
+
+fn stats_command() -> Command {
+    Command::new("stats")
+        .about("Compression statistics as JSON plus PNG charts")
+        .arg(
+            Arg::new("inputs")
+                .value_name("PATH")
+                .help("Files or directories (LF2 headers are detected; others use --spec/--offset)")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("JSON report; charts are written next to it as <stem>.<chart>.png")
+                .default_value("stats.json")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("spec")
+                .long("spec")
+                .value_name("PRESET")
+                .help("LZSS parameters for non-LF2 files")
+                .default_value("lf2")
+                .value_parser(["lf2", "okumura"])
+        )
+        .arg(
+            Arg::new("offset")
+                .long("offset")
+                .value_name("BYTES")
+                .help("Where the compressed stream starts in non-LF2 files")
+                .default_value("0")
+                .value_parser(clap::value_parser!(usize))
+        )
+}
+
+fn hypothesis_command() -> Command {
+    Command::new("hypothesis")
+        .about("Check named hypotheses about the original LF2 encoder over a corpus")
+        .arg(
+            Arg::new("inputs")
+                .value_name("PATH")
+                .help("LF2 files or directories of them")
+                .required_unless_present("list")
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("only")
+                .long("only")
+                .value_name("NAME")
+                .help("Check only this hypothesis (repeatable; see --list)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("archive")
+                .long("archive")
+                .value_name("FILE")
+                .help("JSON Lines archive the results are appended to")
+                .default_value("hypotheses.jsonl")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .help("List the hypotheses and exit")
+                .action(ArgAction::SetTrue)
+        )
+}
+
+fn palette_report_command() -> Command {
+    Command::new("palette-report")
+        .about("Find shared palettes and cluster similar ones across a set of images")
+        .arg(
+            Arg::new("inputs")
+                .value_name("PATH")
+                .help("Indexed images (LF2, MAG, ...) or directories of them")
+                .required(true)
+                .num_args(1..)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("JSON report; cluster swatches are written next to it as <stem>.swatches.png")
+                .default_value("palettes.json")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .value_name("DISTANCE")
+                .help("Largest mean nearest-colour RGB distance for two palettes to be clustered")
+                .default_value("8")
+                .value_parser(clap::value_parser!(f64))
+        )
+}
+
+fn dashboard_command() -> Command {
+    Command::new("dashboard")
+        .about("Compare two verify --json runs and write an HTML page of per-file regressions and improvements")
+        .arg(
+            Arg::new("before")
+                .value_name("BEFORE")
+                .help("verify --json output of the baseline run")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("after")
+                .value_name("AFTER")
+                .help("verify --json output of the run to review")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("HTML dashboard")
+                .default_value("dashboard.html")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn montage_command() -> Command {
+    Command::new("montage")
+        .about("Compose thumbnails of a directory of images into a labeled contact sheet")
+        .arg(
+            Arg::new("input-dir")
+                .long("input-dir")
+                .value_name("DIR")
+                .help("Directory of images (retro formats, PNG, BMP, ...)")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .short('o')
+                .value_name("FILE")
+                .help("Contact sheet image (PNG or BMP)")
+                .default_value("sheet.png")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("N")
+                .help("Thumbnails per row")
+                .default_value("8")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("thumb-size")
+                .long("thumb-size")
+                .value_name("PIXELS")
+                .help("Largest thumbnail edge; images are only scaled down")
+                .default_value("128")
+                .value_parser(clap::value_parser!(u32).range(8..))
+        )
+        .arg(
+            Arg::new("no-labels")
+                .long("no-labels")
+                .help("Leave out the file names")
+                .action(ArgAction::SetTrue)
+        )
+}
+
+fn serve_static_command() -> Command {
+    Command::new("serve-static")
+        .about("Serve an output directory over HTTP to browse galleries and reports")
+        .arg(
+            Arg::new("dir")
+                .value_name("DIR")
+                .help("Directory to serve")
+                .default_value("./")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .short('p')
+                .value_name("PORT")
+                .help("Port to listen on (0 picks a free one)")
+                .default_value("8080")
+                .value_parser(clap::value_parser!(u16))
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .value_name("ADDR")
+                .help("Address to listen on; use 0.0.0.0 to allow other machines")
+                .default_value("127.0.0.1")
+                .value_parser(clap::value_parser!(std::net::IpAddr))
+        )
+}
+
+fn repl_command() -> Command {
+    Command::new("repl")
+        .about("Interactively test LZSS format hypotheses (type `help` inside)")
+        .arg(
+            Arg::new("input")
+                .value_name("FILE")
+                .help("File to load on start")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("state")
+                .long("state")
+                .value_name("FILE")
+                .help("Restore the parameters and their undo history from FILE if it exists, and save them there on exit")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn replay_command() -> Command {
+    Command::new("replay")
+        .about("Re-run the conversions recorded with --record")
+        .arg(
+            Arg::new("session")
+                .value_name("FILE")
+                .help("Session file written by --record")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+}
+
+fn trace_command() -> Command {
+    Command::new("trace")
+        .about("Work with saved step-by-step decode traces")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("migrate")
+                .about("Convert a trace written by an older release to the current format")
+                .arg(
+                    Arg::new("input")
+                        .value_name("TRACE")
+                        .help("Trace file (JSON or CBOR, any known version)")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("Output file (default: overwrite the input)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("ENCODING")
+                        .help("Output encoding (default: from the output file extension)")
+                        .value_parser(["json", "cbor"])
+                )
+        )
+}
+
+fn project_command() -> Command {
+    Command::new("project")
+        .about("Run, inspect or clean a whole-game conversion project (project.toml)")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("file")
+                .long("file")
+                .value_name("PROJECT")
+                .help("Project file")
+                .default_value(retro_decode::project::PROJECT_FILE)
+                .value_parser(clap::value_parser!(PathBuf))
+                .global(true)
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Convert every pending input")
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Re-convert inputs whose outputs are up to date")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(Command::new("status").about("Show converted and pending inputs"))
+        .subcommand(Command::new("clean").about("Remove outputs produced by the project"))
+}
+
+fn config_command() -> Command {
+    Command::new("config")
+        .about("Manage retro-decode.toml default options")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("init")
+                .about("Write a commented retro-decode.toml to the current directory")
+                .arg(
+                    Arg::new("global")
+                        .long("global")
+                        .help("Write it to the user config directory (~/.config/retro-decode/) instead")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite an existing file")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+}
+
+/// Log `e` after `prefix`, followed by the remediation hint of a decoding
+/// error in the user's locale
+fn log_error(prefix: &str, e: &anyhow::Error) {
+    error!("{}{}", prefix, e);
+    if let Some(hint) = retro_decode::decoder::find_error(e).and_then(|e| e.hint()) {
+        error!("Hint: {}", hint.message(retro_decode::messages::Locale::from_env()));
+    }
+}
+
+/// Conversion options of the legacy flat command line, in help order
+const LEGACY_ARGS: &[&str] = &[
+    "output", "format", "lang", "parallel", "gpu", "step-by-step", "trace", "record", "benchmark", "json",
+    "benchmark-format", "resume", "report", "keep-going", "fail-fast", "recursive", "layout", "output-template", "include", "exclude", "dry-run", "low-memory", "progress-json", "sidecar", "tiles",
+    "trim", "palettes", "sequences", "frame-delay", "also-indices", "export-palette", "romanize", "rgb565-order", "orientation", "palette-swap",
+];
+
+/// Options of `decode`: the legacy ones minus benchmarking (`bench`) and
+/// archive naming (`extract`)
+const DECODE_ARGS: &[&str] = &[
+    "output", "format", "lang", "parallel", "gpu", "step-by-step", "trace", "record", "resume", "report", "keep-going", "fail-fast", "recursive",
+    "layout", "output-template", "include", "exclude", "dry-run", "low-memory", "progress-json", "sidecar", "tiles", "trim", "palettes", "sequences",
+    "frame-delay", "also-indices", "export-palette", "rgb565-order", "orientation", "palette-swap",
+];
+
+/// Options of `bench`
+const BENCH_ARGS: &[&str] = &[
+    "output", "format", "lang", "parallel", "recursive", "layout", "include", "exclude", "json", "benchmark-format",
+];
+
+/// Conversion option `id`, shared by the legacy flags and the subcommands
+fn conversion_arg(id: &str) -> Arg {
+    match id {
+        "output" => Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("DIR")
+            .help("Output directory")
+            .default_value("./")
+            .value_parser(clap::value_parser!(PathBuf)),
+        "format" => Arg::new("format")
+            .long("format")
+            .short('f')
+            .value_name("FORMAT")
+            .help("Output format: bmp, png, raw, rgba, rgb565 or a format registered with output::register_writer")
+            .value_parser(|name: &str| {
+                let name = name.to_ascii_lowercase();
+                if retro_decode::output::is_output_format(&name) {
+                    Ok(name)
+                } else {
+                    Err(format!("expected one of {}", retro_decode::output::output_formats().join(", ")))
+                }
+            })
+            .default_value("bmp"),
+        "lang" => Arg::new("lang")
+            .long("lang")
+            .short('l')
+            .value_name("ENGINE")
+            .help("Processing engine")
+            .value_parser(["rust", "python", "typescript"])
+            .default_value("rust"),
+        "parallel" => Arg::new("parallel")
+            .long("parallel")
+            .value_name("THREADS")
+            .help("Process batch files in parallel, on THREADS workers (default: RAYON_NUM_THREADS or every core)")
+            .num_args(0..=1)
+            .default_missing_value("0")
+            .value_parser(clap::value_parser!(usize)),
+        "gpu" => Arg::new("gpu")
+            .long("gpu")
+            .help("Use GPU acceleration")
+            .action(ArgAction::SetTrue),
+        "step-by-step" => Arg::new("step-by-step")
+            .long("step-by-step")
+            .help("Enable educational step-by-step mode")
+            .action(ArgAction::SetTrue),
+        "trace" => Arg::new("trace")
+            .long("trace")
+            .value_name("FILE")
+            .help("Save the step-by-step decode trace (JSON, or CBOR for .cbor; implies --step-by-step)")
+            .value_parser(clap::value_parser!(PathBuf)),
+        "record" => Arg::new("record")
+            .long("record")
+            .value_name("FILE")
+            .help("Append this run's effective configuration to a session file for `replay`")
+            .value_parser(clap::value_parser!(PathBuf)),
+        "benchmark" => Arg::new("benchmark")
+            .long("benchmark")
+            .help("Output structured benchmark information")
+            .action(ArgAction::SetTrue),
+        "json" => Arg::new("json")
+            .long("json")
+            .help("Print --benchmark records as JSON Lines (schema: --dump-schema benchmark)")
+            .action(ArgAction::SetTrue),
+        "benchmark-format" => Arg::new("benchmark-format")
+            .long("benchmark-format")
+            .value_name("FORMAT")
+            .help("How --benchmark prints its records [default: text, json with --json]")
+            .value_parser(clap::value_parser!(BenchmarkFormat))
+            .conflicts_with("json"),
+        "resume" => Arg::new("resume")
+            .long("resume")
+            .help("Resume an interrupted batch run, skipping files recorded in the progress journal")
+            .action(ArgAction::SetTrue),
+        "report" => Arg::new("report")
+            .long("report")
+            .value_name("FILE")
+            .help("Write a JSON summary of a batch run with every failed file and its error category (schema: --dump-schema errors)")
+            .value_parser(clap::value_parser!(PathBuf)),
+        "keep-going" => Arg::new("keep-going")
+            .long("keep-going")
+            .help("Convert every file of a batch run even after failures (default); the exit status is still non-zero if any failed")
+            .conflicts_with("fail-fast")
+            .action(ArgAction::SetTrue),
+        "fail-fast" => Arg::new("fail-fast")
+            .long("fail-fast")
+            .help("Stop a batch run at the first failed file")
+            .action(ArgAction::SetTrue),
+        "recursive" => Arg::new("recursive")
+            .long("recursive")
+            .short('r')
+            .help("Descend into subdirectories of a batch input, mirroring their layout under the output directory")
+            .action(ArgAction::SetTrue),
+        "layout" => Arg::new("layout")
+            .long("layout")
+            .value_name("LAYOUT")
+            .help("Output directory layout: mirror (default), flat, or versioned (<output>/<version>/<format>/...) to keep runs of different releases side by side")
+            .value_parser(clap::value_parser!(retro_decode::project::OutputLayout)),
+        "output-template" => Arg::new("output-template")
+            .long("output-template")
+            .value_name("TEMPLATE")
+            .help("Image output path below --output, replacing --layout: {stem}, {name}, {ext}, {dir}, {format}, {width}, {height} (e.g. {dir}/{stem}_{width}x{height}.{ext})")
+            .value_parser(clap::value_parser!(retro_decode::paths::OutputTemplate)),
+        "include" => Arg::new("include")
+            .long("include")
+            .value_name("GLOB")
+            .help("Only convert batch inputs matching this pattern (`*`, `?`; case-insensitive; repeatable). Patterns with `/` match the path below the input directory")
+            .action(ArgAction::Append),
+        "exclude" => Arg::new("exclude")
+            .long("exclude")
+            .value_name("GLOB")
+            .help("Skip batch inputs matching this pattern (repeatable; wins over --include)")
+            .action(ArgAction::Append),
+        "dry-run" => Arg::new("dry-run")
+            .long("dry-run")
+            .help("Detect formats and read headers only, then print each planned output with its dimensions and estimated size; nothing is decoded or written")
+            .action(ArgAction::SetTrue),
+        "low-memory" => Arg::new("low-memory")
+            .long("low-memory")
+            .help("Stream decoded scanlines straight into the output instead of holding the whole image (PDT to bmp, raw, rgba or rgb565)")
+            .action(ArgAction::SetTrue),
+        "progress-json" => Arg::new("progress-json")
+            .long("progress-json")
+            .help("Write line-delimited JSON progress events (start, file, done) to stderr during batch runs")
+            .action(ArgAction::SetTrue),
+        "sidecar" => Arg::new("sidecar")
+            .long("sidecar")
+            .help("Write a .meta.json sidecar with header, palette and source hash next to each output")
+            .action(ArgAction::SetTrue),
+        "tiles" => Arg::new("tiles")
+            .long("tiles")
+            .value_name("WxH")
+            .help("Export a deduplicated tileset strip plus <name>.map.json instead of a single image")
+            .value_parser(clap::value_parser!(retro_decode::tiles::TileSize)),
+        "trim" => Arg::new("trim")
+            .long("trim")
+            .help("Crop single-image exports to the non-transparent bounding box; the offsets go into the sidecar for reencode")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["tiles", "palettes"]),
+        "palettes" => Arg::new("palettes")
+            .long("palettes")
+            .value_name("MODE")
+            .help("Render LF2 images once per palette, including auxiliary palette blocks: frames (<name>.pal<N>.<ext>) or gif (animated)")
+            .value_parser(clap::value_parser!(retro_decode::formats::toheart::palette_variants::PaletteExport)),
+        "sequences" => Arg::new("sequences")
+            .long("sequences")
+            .value_name("FORMAT")
+            .help("In batch mode, assemble numbered frames (C0101, C0102, ...) into <first>-<last>.gif or .png (APNG), aligned by their offsets")
+            .value_parser(clap::value_parser!(retro_decode::sequences::AnimationFormat)),
+        "frame-delay" => Arg::new("frame-delay")
+            .long("frame-delay")
+            .value_name("MS")
+            .help("Delay between frames of --sequences animations [default: 100]")
+            .value_parser(clap::value_parser!(u32).range(1..)),
+        "also-indices" => Arg::new("also-indices")
+            .long("also-indices")
+            .value_name("KIND")
+            .help("Also write the 8-bit palette index plane next to each indexed image: pgm (P5 greyscale) or idx (headerless bytes)")
+            .value_parser(["pgm", "idx"])
+            .num_args(0..=1)
+            .default_missing_value("pgm"),
+        "export-palette" => Arg::new("export-palette")
+            .long("export-palette")
+            .value_name("KINDS")
+            .help("Also write the palette of each indexed image (LF2, SCN, MAG, ...): act, gpl (GIMP) and/or json, comma-separated [default: all three]")
+            .value_parser(clap::value_parser!(retro_decode::output::PaletteFile))
+            .value_delimiter(',')
+            .num_args(0..)
+            .default_missing_values(["act", "gpl", "json"]),
+        "romanize" => Arg::new("romanize")
+            .long("romanize")
+            .help("Extract archive entries under ASCII romaji names, listing the originals in romanize.json")
+            .action(ArgAction::SetTrue),
+        "rgb565-order" => Arg::new("rgb565-order")
+            .long("rgb565-order")
+            .value_name("ENDIAN")
+            .help("Byte order of --format rgb565 output")
+            .value_parser(["little", "big"])
+            .default_value("little"),
+        "orientation" => Arg::new("orientation")
+            .long("orientation")
+            .value_name("ORDER")
+            .help("Row order of exported images: display (top row first) or stored (as the compressed stream produces them)")
+            .value_parser(["display", "stored"])
+            .default_value("display"),
+        "palette-swap" => Arg::new("palette-swap")
+            .long("palette-swap")
+            .value_name("MAPPING")
+            .help("Remap LF2 palettes using a JSON mapping file and re-encode")
+            .value_parser(clap::value_parser!(PathBuf)),
+        _ => unreachable!("Unknown conversion option {}", id),
+    }
+}
+
+/// Value of flag `id`, false for commands that do not define it
+fn flag(matches: &clap::ArgMatches, id: &str) -> bool {
+    value::<bool>(matches, id).unwrap_or(false)
+}
+
+/// Value of option `id`, `None` for commands that do not define it
+fn value<T: Clone + Send + Sync + 'static>(matches: &clap::ArgMatches, id: &str) -> Option<T> {
+    matches.try_get_one::<T>(id).ok().flatten().cloned()
+}
+
+/// Every value of repeatable option `id`, empty for commands that do not
+/// define it
+fn values<T: Clone + Send + Sync + 'static>(matches: &clap::ArgMatches, id: &str) -> Vec<T> {
+    matches.try_get_many::<T>(id).ok().flatten().map_or_else(Vec::new, |v| v.cloned().collect())
+}
+
+/// Settings of the global options: `--no-atomic-writes` and the decode
+/// limits
+fn shared_config(matches: &clap::ArgMatches) -> Config {
+    Config {
+        direct_writes: flag(matches, "no-atomic-writes"),
+        limits: retro_decode::decoder::DecodeLimits {
+            max_pixels: value(matches, "max-pixels"),
+            max_steps: value(matches, "max-steps"),
+            max_output_bytes: value(matches, "max-output-bytes"),
+        },
+        ..Default::default()
+    }
+}
+
+/// Conversion settings from the legacy flags or a `decode` / `bench`
+/// subcommand over the `retro-decode.toml` defaults and the
+/// [`shared_config`]; the input is left to the caller
+fn config_from_matches(matches: &clap::ArgMatches) -> anyhow::Result<Config> {
+    use retro_decode::config_file::ConfigFile;
+
+    let mut config = Config {
+        output: value(matches, "output").unwrap_or_else(|| PathBuf::from("./")),
+        format: value(matches, "format").unwrap_or_else(|| "bmp".to_string()),
+        language: value(matches, "lang").unwrap_or_else(|| "rust".to_string()),
+        parallel: matches.try_contains_id("parallel").unwrap_or(false),
+        threads: value::<usize>(matches, "parallel").filter(|&n| n > 0),
+        gpu: flag(matches, "gpu"),
+        verbose: flag(matches, "verbose"),
+        step_by_step: flag(matches, "step-by-step"),
+        benchmark: flag(matches, "benchmark"),
+        json: flag(matches, "json"),
+        benchmark_format: value(matches, "benchmark-format").unwrap_or_default(),
+        palette_swap: value(matches, "palette-swap"),
+        sidecar: flag(matches, "sidecar"),
+        resume: flag(matches, "resume"),
+        error_report: value(matches, "report"),
+        fail_fast: flag(matches, "fail-fast"),
+        trace: value(matches, "trace"),
+        orientation: match value::<String>(matches, "orientation").as_deref() {
+            Some("stored") => retro_decode::decoder::Orientation::Stored,
+            _ => retro_decode::decoder::Orientation::Display,
+        },
+        rgb565_order: match value::<String>(matches, "rgb565-order").as_deref() {
+            Some("big") => retro_decode::output::Endianness::Big,
+            _ => retro_decode::output::Endianness::Little,
+        },
+        tiles: value(matches, "tiles"),
+        trim: flag(matches, "trim"),
+        romanize: flag(matches, "romanize"),
+        palettes: value(matches, "palettes"),
+        sequences: value(matches, "sequences"),
+        frame_delay_ms: value(matches, "frame-delay"),
+        also_indices: value::<String>(matches, "also-indices").map(|kind| match kind.as_str() {
+            "idx" => retro_decode::output::IndexPlane::Idx,
+            _ => retro_decode::output::IndexPlane::Pgm,
+        }),
+        export_palette: values(matches, "export-palette"),
+        progress_json: flag(matches, "progress-json"),
+        recursive: flag(matches, "recursive"),
+        include: values(matches, "include"),
+        exclude: values(matches, "exclude"),
+        low_memory: flag(matches, "low-memory"),
+        dry_run: flag(matches, "dry-run"),
+        quiet: flag(matches, "quiet"),
+        layout: value(matches, "layout").unwrap_or_default(),
+        output_template: value(matches, "output-template"),
+        ..shared_config(matches)
+    };
+    if let Some(path) = value::<PathBuf>(matches, "config").or_else(ConfigFile::discover) {
+        let file = ConfigFile::load(&path)?;
+        // Only options this command has and the command line left unset
+        file.apply(&mut config, |id| {
+            matches.try_contains_id(id).is_ok() && matches.value_source(id) != Some(clap::parser::ValueSource::CommandLine)
+        });
+        info!("Using defaults from {}", path.display());
+    }
+    Ok(config)
+}
+
+/// Progress bars go to stderr, and only when it is a terminal
+fn show_bars(quiet: bool) -> bool {
+    use std::io::IsTerminal;
+    !quiet && std::io::stderr().is_terminal()
+}
+
+/// [`run_config`], first appending `config` to the `--record` session
+fn run_recorded(matches: &clap::ArgMatches, config: Config) -> anyhow::Result<()> {
+    if let Some(session_path) = value::<PathBuf>(matches, "record") {
+        retro_decode::session::record(&session_path, &config)
+            .map_err(|e| anyhow::anyhow!("Failed to record session: {}", e))?;
+        info!("Recorded run in {}", session_path.display());
+    }
+    run_config(config)
+}
+
+/// Config for converting `input`, a file or a directory of files
+fn config_for_path(matches: &clap::ArgMatches, input: &std::path::Path) -> anyhow::Result<Config> {
+    let mut config = config_from_matches(matches)?;
+    if input.is_dir() {
+        config.input_dir = Some(input.to_path_buf());
+    } else {
+        config.input = Some(input.to_path_buf());
+    }
+    Ok(config)
+}
+
+fn run_decode(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    // Paths inside a ZIP have no format of their own; run_config unpacks them
+    if !input.is_dir() && matches!(FormatType::detect(input), Ok(FormatType::ToHeartPak)) {
+        return Err(anyhow::anyhow!("{} is an archive; unpack it with `retro-decode extract`", input.display()));
+    }
+    run_recorded(matches, config_for_path(matches, input)?)
+}
+
+fn run_bench(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let mut config = config_for_path(matches, input)?;
+    config.benchmark = true;
+    run_config(config)
+}
+
+fn run_extract(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let config = retro_decode::DecodeConfig {
+        step_by_step: matches.get_flag("step-by-step"),
+        romanize: matches.get_flag("romanize"),
+        progress: show_bars(matches.get_flag("quiet")).then(retro_decode::progress::ProgressCallback::bar),
+        ..shared.decode_config()
+    };
+    for archive in matches.get_many::<PathBuf>("archive").unwrap() {
+        if FormatType::detect(archive)? != FormatType::ToHeartPak {
+            return Err(anyhow::anyhow!("{} is not a PAK archive; convert images with `retro-decode decode`", archive.display()));
+        }
+        if matches.get_flag("list") {
+            let pak = retro_decode::formats::toheart::PakArchive::open(archive)?;
+            println!("{}:", archive.display());
+            for (index, entry) in pak.entries().enumerate() {
+                println!("{:>5}  {:<12}  {:#010x}  {:>10}", index, entry.name, entry.position, entry.length);
+            }
+            continue;
+        }
+        let entry = matches.get_one::<String>("entry");
+        let index = matches.get_one::<usize>("index").copied();
+        if entry.is_some() || index.is_some() {
+            let mut pak = retro_decode::formats::toheart::PakArchive::open(archive)?;
+            let index = match entry {
+                Some(name) => pak.index_of(name)
+                    .ok_or_else(|| anyhow::anyhow!("{} has no entry named {}", archive.display(), name))?,
+                None => index.unwrap(),
+            };
+            let path = pak.extract_entry(index, output, &config)?;
+            info!("Extracted {}", path.display());
+            continue;
+        }
+        retro_decode::formats::toheart::extract_pak(archive, output, &config)?;
+        info!("Extracted {} to {}", archive.display(), output.display());
+    }
+    Ok(())
+}
+
+fn run_pack(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let mut paths = Vec::new();
+    for input in matches.get_many::<PathBuf>("inputs").unwrap() {
+        if input.is_dir() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(input)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<_>>()?;
+            files.retain(|path| path.is_file());
+            files.sort();
+            paths.extend(files);
+        } else {
+            paths.push(input.clone());
+        }
+    }
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        files.push((name, std::fs::read(path)?));
+    }
+    // All-zero key: readers derive the key from the table, so any key works
+    let archive = retro_decode::formats::toheart::pak::write_archive(&files, &[0; retro_decode::formats::toheart::pak::KEY_LEN])?;
+    retro_decode::output::write_bytes(output, shared.direct_writes, &archive)?;
+    info!("Packed {} files into {}", files.len(), output.display());
+    Ok(())
+}
+
+/// Run a conversion described by `config` (from the command line or a
+/// recorded session)
+fn run_config(mut config: Config) -> anyhow::Result<()> {
+    let unzipped = match (config.input.clone(), config.input_dir.clone()) {
+        (Some(input), None) | (None, Some(input)) => Some(unzip_input(&input)?),
+        _ => None,
+    };
+    if let Some((path, Some(_))) = &unzipped {
+        if path.is_dir() {
+            (config.input, config.input_dir) = (None, Some(path.clone()));
+        } else {
+            (config.input, config.input_dir) = (Some(path.clone()), None);
+        }
+    }
+
+    if config.dry_run {
+        return run_dry_run(&config);
+    }
+    if let Some(mapping_path) = config.palette_swap.clone() {
+        return run_palette_swap(&config, &mapping_path);
+    }
+    if config.benchmark {
+        config.benchmark_format().print_header();
+    }
+
+    // Determine processing mode
+    match (config.input.clone(), config.input_dir.clone()) {
+        // Single file processing
+        (Some(input_path), None) => run_cli_single(config, input_path),
+        // Batch directory processing
+        (None, Some(input_dir)) => run_cli_batch(config, input_dir),
+        (None, None) => {
+            println!("RetroDecode - P⁴ (Pixel by pixel, past preserved)");
+            println!("Educational tool for analyzing retro game image formats");
+            println!("\nRun with --help for detailed usage information.");
+            Ok(())
+        }
+        (Some(_), Some(_)) => Err(anyhow::anyhow!("Cannot specify both --input and --input-dir")),
+    }
+}
+
+fn run_replay(sub: &clap::ArgMatches) -> anyhow::Result<()> {
+    let path = sub.get_one::<PathBuf>("session").unwrap();
+    let session = retro_decode::session::Session::load(path)?;
+    let start_dir = std::env::current_dir()?;
+
+    for (i, run) in session.runs.into_iter().enumerate() {
+        info!(
+            "Replaying run {} (recorded {} with version {}) in {}",
+            i + 1, run.recorded_at, run.tool_version, run.working_dir.display()
+        );
+        if run.tool_version != env!("CARGO_PKG_VERSION") {
+            warn!("Run {} was recorded with version {}, replaying with {}", i + 1, run.tool_version, env!("CARGO_PKG_VERSION"));
+        }
+        std::env::set_current_dir(&run.working_dir)?;
+        let result = run_config(run.config);
+        std::env::set_current_dir(&start_dir)?;
+        result?;
+    }
+    Ok(())
+}
+
+fn run_cli_single(config: Config, input_path: PathBuf) -> anyhow::Result<()> {
+    info!("Processing file: {:?}", input_path);
+    info!("Output directory: {:?}", config.output);
+    info!("Output format: {}", config.format);
+    info!("Engine: {}", config.language);
+    
+    if config.parallel {
         info!("Parallel processing enabled");
     }
-    
-    if config.gpu {
-        info!("GPU acceleration requested");
+    
+    if config.gpu {
+        info!("GPU acceleration requested");
+    }
+
+    // Detect format from the file extension, or its contents if that is unknown
+    let format_type = FormatType::detect(&input_path)?;
+    info!("Detected format: {}", format_type);
+
+    // Build output file path with format extension and create its directory
+    let input_dir = input_path.parent().unwrap_or(std::path::Path::new(""));
+    let output_file = config.output_file(input_dir, &input_path, &format_type)?;
+    if let Some(output_dir) = output_file.parent() {
+        std::fs::create_dir_all(output_dir)?;
+    }
+
+    // Process based on format and language
+    let (result, measured) = measure_if(config.benchmark, || -> anyhow::Result<()> {
+        match config.language.as_str() {
+            "rust" => {
+                info!("Using Rust engine");
+                // Step-by-step decoding of a large PDT takes long enough to want a pixel bar
+                let bar = (config.step_by_step && show_bars(config.quiet)).then(retro_decode::progress::ProgressCallback::bar);
+                retro_decode::progress::with_callback(bar.as_ref(), || {
+                    retro_decode::formats::process_rust(&input_path, &output_file, format_type.clone(), &config)
+                })?;
+            }
+            "python" => {
+                #[cfg(feature = "python-bridge")]
+                {
+                    info!("Using Python bridge");
+                    let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
+                    retro_decode::bridge::python::process(&input_path, &output_file, format_type, &bridge_config)?;
+                }
+                #[cfg(not(feature = "python-bridge"))]
+                {
+                    error!("Python bridge feature not enabled. Rebuild with --features python-bridge");
+                    std::process::exit(1);
+                }
+            }
+            "typescript" => {
+                #[cfg(feature = "bridges")]
+                {
+                    info!("Using TypeScript bridge");
+                    let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
+                    retro_decode::bridge::typescript::process(&input_path, &output_file, format_type.clone(), &bridge_config)?;
+                }
+                #[cfg(not(feature = "bridges"))]
+                {
+                    error!("TypeScript bridge feature not enabled. Rebuild with --features bridges");
+                    std::process::exit(1);
+                }
+            }
+            _ => unreachable!("Invalid language - should be caught by clap"),
+        }
+        Ok(())
+    });
+    result?;
+
+    // Output benchmark information if requested
+    if let Some(measured) = measured {
+        output_benchmark_info(&input_path, &format_type, &config, &measured)?;
+    }
+
+    info!("Processing completed successfully");
+    Ok(())
+}
+
+/// Supported files in `input_dir` (and below with --recursive), narrowed by
+/// --include/--exclude
+fn batch_files(config: &Config, input_dir: &std::path::Path) -> anyhow::Result<Vec<PathBuf>> {
+    let supported_extensions = ["lf2", "pdt", "g00", "pak", "scn"];
+    let filter = retro_decode::paths::FileFilter { include: config.include.clone(), exclude: config.exclude.clone() };
+    Ok(retro_decode::paths::files_in(input_dir, config.recursive)?
+        .into_iter()
+        .filter(|path| match retro_decode::paths::extension_lower(path) {
+            Some(ext) if supported_extensions.contains(&ext.as_str()) => true,
+            // Unknown or missing extension: sniff the contents
+            _ if FormatType::from_path(path).is_err() => FormatType::detect(path).is_ok_and(|format| {
+                matches!(format, FormatType::ToHeartLf2 | FormatType::KanonPdt | FormatType::KanonG00 | FormatType::ToHeartPak)
+            }),
+            _ => false,
+        })
+        .filter(|path| filter.accepts(input_dir, path))
+        .collect())
+}
+
+/// Print what `config` would produce, reading only headers
+fn run_dry_run(config: &Config) -> anyhow::Result<()> {
+    use retro_decode::plan::{describe, PlanSummary, PlannedOutput};
+
+    let (input_dir, files) = match (&config.input, &config.input_dir) {
+        (Some(input), None) => (input.parent().unwrap_or(std::path::Path::new("")).to_path_buf(), vec![input.clone()]),
+        (None, Some(input_dir)) => (input_dir.clone(), batch_files(config, input_dir)?),
+        (None, None) => return Err(anyhow::anyhow!("--dry-run needs an input")),
+        (Some(_), Some(_)) => return Err(anyhow::anyhow!("Cannot specify both --input and --input-dir")),
+    };
+    let plans: Vec<PlannedOutput> = files.iter().map(|file| PlannedOutput::for_file(config, &input_dir, file)).collect();
+    for plan in &plans {
+        println!("{}", describe(plan));
+    }
+    let summary = PlanSummary::of(&plans);
+    println!(
+        "Dry run: {} files, {} would fail, ~{} bytes of output; nothing was written",
+        summary.files, summary.failures, summary.estimated_bytes
+    );
+    Ok(())
+}
+
+fn run_cli_batch(config: Config, input_dir: PathBuf) -> anyhow::Result<()> {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+    use retro_decode::report::{files_per_second, BenchmarkSummary, ThreadThroughput};
+
+    info!("Batch processing directory: {:?}", input_dir);
+    info!("Output directory: {:?}", config.output);
+    info!("Output format: {}", config.format);
+    info!("Engine: {}", config.language);
+    
+    let threads = config.worker_threads();
+    if config.parallel {
+        info!("Parallel processing enabled ({} threads)", threads);
+    }
+    
+    if config.gpu {
+        info!("GPU acceleration requested");
+    }
+
+    // Create output directory
+    std::fs::create_dir_all(&config.output)?;
+    
+    let files_to_process = batch_files(&config, &input_dir)?;
+    
+    if files_to_process.is_empty() {
+        info!("No supported files found in directory");
+        let progress = retro_decode::progress::Progress::start(config.progress_json, 0);
+        if let Some(path) = &config.error_report {
+            progress.error_report().write(path, config.direct_writes)?;
+        }
+        progress.finish();
+        return Ok(());
+    }
+    
+    info!("Found {} files to process", files_to_process.len());
+    let mut progress = retro_decode::progress::Progress::start(config.progress_json, files_to_process.len());
+    if !config.progress_json && show_bars(config.quiet) {
+        progress = progress.with_bar();
+    }
+    let progress = Mutex::new(progress);
+
+    let journal = retro_decode::journal::Journal::open(&config.output, config.resume)?;
+    if config.resume {
+        info!("Resuming from {} ({} completed entries)", journal.path().display(), journal.len());
+    }
+    let journal = Mutex::new(journal);
+    
+    // Workers take the next unprocessed file until none are left
+    let threads = threads.min(files_to_process.len());
+    let next = AtomicUsize::new(0);
+    let started = Instant::now();
+    let per_thread = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let (config, files, next, input_dir) = (&config, &files_to_process, &next, &input_dir);
+                let (journal, progress) = (&journal, &progress);
+                scope.spawn(move || -> anyhow::Result<ThreadThroughput> {
+                    let mut done = 0;
+                    let mut busy = std::time::Duration::ZERO;
+                    while let Some(file_path) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if config.fail_fast && progress.lock().unwrap_or_else(|e| e.into_inner()).failed() > 0 {
+                            break;
+                        }
+                        let start = Instant::now();
+                        process_batch_file(config, input_dir, file_path, journal, progress)?;
+                        busy += start.elapsed();
+                        done += 1;
+                    }
+                    let busy_ms = busy.as_secs_f64() * 1000.0;
+                    Ok(ThreadThroughput { thread, files: done, busy_ms, files_per_second: files_per_second(done, busy_ms) })
+                })
+            })
+            .collect();
+        workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Batch worker panicked"))))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+
+    if config.benchmark {
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        let summary = BenchmarkSummary {
+            threads,
+            files: files_to_process.len(),
+            elapsed_ms,
+            files_per_second: files_per_second(files_to_process.len(), elapsed_ms),
+            per_thread,
+        };
+        match config.benchmark_format() {
+            BenchmarkFormat::Text => summary.print_text(),
+            BenchmarkFormat::Json => retro_decode::report::print_json_line(&summary)?,
+            // Rows would break the CSV table
+            BenchmarkFormat::Csv => info!(
+                "Benchmark: {} files in {:.1} ms ({:.1} files/s, {} threads)",
+                summary.files, summary.elapsed_ms, summary.files_per_second, summary.threads
+            ),
+        }
+    }
+
+    assemble_sequences(&config, &input_dir, &files_to_process);
+    let progress = progress.into_inner().unwrap_or_else(|e| e.into_inner());
+    let report = progress.error_report();
+    progress.finish();
+    if let Some(path) = &config.error_report {
+        report.write(path, config.direct_writes)?;
+        info!("Wrote error report to {}", path.display());
+    }
+
+    if report.failed > 0 {
+        if report.not_attempted > 0 {
+            warn!("Stopped after the first failure; {} files were not attempted", report.not_attempted);
+        }
+        return Err(anyhow::anyhow!("{} of {} files failed", report.failed, report.total));
+    }
+    info!("Batch processing completed successfully");
+    Ok(())
+}
+
+/// Convert one file of a batch run below `input_dir`; per-file failures are
+/// logged, only journal and benchmark output errors end the run
+fn process_batch_file(
+    config: &Config,
+    input_dir: &std::path::Path,
+    file_path: &std::path::Path,
+    journal: &std::sync::Mutex<retro_decode::journal::Journal>,
+    progress: &std::sync::Mutex<retro_decode::progress::Progress>,
+) -> anyhow::Result<()> {
+    use retro_decode::progress::FileStatus;
+
+    let report = |status: FileStatus, error: Option<&anyhow::Error>| {
+        progress.lock().unwrap_or_else(|e| e.into_inner()).file(file_path, status, error);
+    };
+
+    // Detect format from the file extension, or its contents if that is unknown
+    let format_type = match FormatType::detect(file_path) {
+        Ok(format_type) => format_type,
+        Err(e) => {
+            if config.benchmark {
+                output_benchmark_failure(file_path, &e, config)?;
+            } else {
+                error!("Unsupported file {}: {}", file_path.display(), e);
+            }
+            report(FileStatus::Unsupported, Some(&e));
+            return Ok(());
+        }
+    };
+
+    // Build output file path with format extension, in the subdirectory
+    // matching the input's for recursive runs
+    let output_file = match config.output_file(input_dir, file_path, &format_type) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("{}", e);
+            report(FileStatus::Failed, Some(&e));
+            return Ok(());
+        }
+    };
+
+    if journal.lock().unwrap_or_else(|e| e.into_inner()).is_done(file_path, &output_file) {
+        info!("Skipping {} (already converted)", file_path.display());
+        report(FileStatus::Skipped, None);
+        return Ok(());
+    }
+    let output_dir = output_file.parent().unwrap_or(std::path::Path::new(""));
+    if let Err(e) = std::fs::create_dir_all(output_dir) {
+        error!("Failed to create {}: {}", output_dir.display(), e);
+        report(FileStatus::Failed, Some(&e.into()));
+        return Ok(());
+    }
+
+    // Process based on format and language
+    let (result, measured) = measure_if(config.benchmark, || match config.language.as_str() {
+        "rust" => {
+            retro_decode::formats::process_rust(file_path, &output_file, format_type.clone(), config)
+        }
+        "python" => {
+            #[cfg(feature = "python-bridge")]
+            {
+                let bridge_config = retro_decode::bridge::BridgeConfig::from(config);
+                retro_decode::bridge::python::process(file_path, &output_file, format_type.clone(), &bridge_config)
+            }
+            #[cfg(not(feature = "python-bridge"))]
+            {
+                Err(anyhow::anyhow!("Python bridge feature not enabled"))
+            }
+        }
+        "typescript" => {
+            #[cfg(feature = "bridges")]
+            {
+                let bridge_config = retro_decode::bridge::BridgeConfig::from(config);
+                retro_decode::bridge::typescript::process(file_path, &output_file, format_type.clone(), &bridge_config)
+            }
+            #[cfg(not(feature = "bridges"))]
+            {
+                Err(anyhow::anyhow!("TypeScript bridge feature not enabled"))
+            }
+        }
+        _ => unreachable!("Invalid language - should be caught by clap"),
+    });
+
+    // Handle processing errors
+    match result {
+        Ok(()) => {
+            // Output benchmark information if requested
+            if let Some(measured) = measured {
+                output_benchmark_info(file_path, &format_type, config, &measured)?;
+            }
+            journal.lock().unwrap_or_else(|e| e.into_inner()).record(file_path, &output_file)?;
+            report(FileStatus::Converted, None);
+        }
+        Err(e) => {
+            if config.benchmark {
+                output_benchmark_failure(file_path, &e, config)?;
+            } else {
+                log_error(&format!("Failed to process {}: ", file_path.display()), &e);
+            }
+            report(FileStatus::Failed, Some(&e));
+        }
+    }
+    Ok(())
+}
+
+/// Turn numbered frame runs into animations, or point out that they exist.
+/// Frames only form a sequence when their converted files share a
+/// directory (as placed by --layout or --output-template), and the
+/// animation is written there.
+fn assemble_sequences(config: &Config, input_dir: &std::path::Path, files: &[PathBuf]) {
+    use std::collections::BTreeMap;
+    use retro_decode::sequences::{detect_sequences, write_animation, DEFAULT_FRAME_DELAY_MS};
+
+    let mut by_dir: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        // Where the batch writer put this frame
+        let output_dir = FormatType::detect(file)
+            .and_then(|format| config.output_file(input_dir, file, &format))
+            .ok()
+            .and_then(|path| path.parent().map(std::path::Path::to_path_buf));
+        if let Some(output_dir) = output_dir {
+            by_dir.entry(output_dir).or_default().push(file.clone());
+        }
+    }
+    let sequences: Vec<_> = by_dir.iter()
+        .flat_map(|(output_dir, files)| detect_sequences(files, 2).into_iter().map(move |s| (output_dir, s)))
+        .collect();
+    if sequences.is_empty() {
+        return;
+    }
+    let Some(format) = config.sequences else {
+        info!("Found {} numbered frame sequences; pass --sequences gif or --sequences apng to assemble them", sequences.len());
+        return;
+    };
+    let delay = config.frame_delay_ms.unwrap_or(DEFAULT_FRAME_DELAY_MS);
+    for (output_dir, sequence) in &sequences {
+        match write_animation(sequence, output_dir, format, delay, config.direct_writes) {
+            Ok(path) => info!("Assembled {} frames into {}", sequence.frames.len(), path.display()),
+            Err(e) => error!("Failed to assemble {}: {}", sequence.name, e),
+        }
+    }
+}
+
+fn run_reencode(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::reencode::{reencode_from_export_with, Lf2Encoder};
+    use retro_decode::quantize::QuantizeOptions;
+    use retro_decode::formats::sidecar::{sidecar_path, ImageMetadata};
+
+    let from = matches.get_one::<PathBuf>("from").unwrap();
+    let encoder = matches.get_one::<String>("encoder")
+        .or_else(|| matches.get_one::<String>("encode-profile"))
+        .map(|name| Lf2Encoder::from_name(name))
+        .transpose()?
+        .map(|encoder| match matches.get_one::<u64>("seed") {
+            Some(&seed) => encoder.with_seed(seed),
+            None => encoder,
+        });
+
+    let options = QuantizeOptions {
+        auto_quantize: matches.get_flag("auto-quantize"),
+        alpha_threshold: matches.get_one::<u8>("alpha-threshold").copied()
+            .unwrap_or(QuantizeOptions::default().alpha_threshold),
+        defringe: matches.get_flag("defringe"),
+    };
+    let outcome = reencode_from_export_with(from, encoder, &options)?;
+
+    let output = match matches.get_one::<PathBuf>("output") {
+        Some(path) => path.clone(),
+        None => {
+            let meta = ImageMetadata::load(&sidecar_path(from))?;
+            from.with_file_name(meta.source_file)
+        }
+    };
+    retro_decode::output::write_bytes(&output, shared.direct_writes, &outcome.bytes)?;
+
+    let encoder = outcome.encoder.map_or_else(|| outcome.format.to_string(), |e| format!("{} encoder", e));
+    match outcome.hash_match {
+        Some(true) => info!("{}: byte-identical to source ({})", output.display(), encoder),
+        Some(false) => info!("{}: pixel-identical, bytes differ from source ({})", output.display(), encoder),
+        None => info!("{}: written (no source hash recorded)", output.display()),
+    }
+    Ok(())
+}
+
+fn run_encode(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::encode::{encode_frames, Frame};
+    use retro_decode::formats::reencode::Lf2Encoder;
+    use retro_decode::quantize::QuantizeOptions;
+
+    let inputs: Vec<&PathBuf> = matches.get_many::<PathBuf>("inputs").unwrap().collect();
+    let encoder = Lf2Encoder::from_name(matches.get_one::<String>("encoder").map_or("exhaustive", String::as_str))?;
+    let options = QuantizeOptions {
+        auto_quantize: matches.get_flag("auto-quantize"),
+        alpha_threshold: matches.get_one::<u8>("alpha-threshold").copied()
+            .unwrap_or(QuantizeOptions::default().alpha_threshold),
+        defringe: matches.get_flag("defringe"),
+    };
+    let shared_palette = matches.get_flag("shared-palette");
+
+    let mut frames = inputs.iter().map(|path| Frame::open(path)).collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(&offset) = matches.get_one::<(u16, u16)>("offset") {
+        frames.iter_mut().for_each(|frame| frame.offset = offset);
+    }
+    let encoded = encode_frames(&frames, &options, shared_palette)?;
+
+    let output_dir = matches.get_one::<PathBuf>("output-dir");
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    println!("{:<24} {:>7} {:>8} {:>8}", "frame", "colours", "changed", "rmse");
+    for (input, frame) in inputs.iter().zip(&encoded) {
+        let name = std::path::Path::new(input.file_stem().unwrap_or_default()).with_extension("LF2");
+        let output = match output_dir {
+            Some(dir) => dir.join(name),
+            None => input.with_file_name(name),
+        };
+        retro_decode::output::write_bytes(&output, shared.direct_writes, &encoder.encode(&frame.image)?)?;
+        println!(
+            "{:<24} {:>7} {:>8} {:>8.2}",
+            output.file_name().unwrap_or_default().to_string_lossy(),
+            frame.image.palette.len() - 1, frame.error.changed, frame.error.rmse
+        );
+    }
+    if shared_palette {
+        info!(
+            "Encoded {} frames with one shared palette of {} colours ({} encoder)",
+            encoded.len(), encoded[0].image.palette.len() - 1, encoder
+        );
+    }
+    Ok(())
+}
+
+/// `X,Y` of `encode --offset`
+fn parse_offset(text: &str) -> Result<(u16, u16), String> {
+    let (x, y) = text.split_once(',').ok_or_else(|| format!("expected X,Y, got {}", text))?;
+    let parse = |v: &str| v.trim().parse::<u16>().map_err(|e| format!("{}: {}", v, e));
+    Ok((parse(x)?, parse(y)?))
+}
+
+/// `verify --scoreboard`: byte-identical counts of every encoder
+fn print_scoreboard(files: &[PathBuf], seed: u64) -> anyhow::Result<()> {
+    use retro_decode::formats::reencode::{scoreboard, Lf2Encoder};
+
+    let originals = files.iter().map(std::fs::read).collect::<Result<Vec<_>, _>>()?;
+    let encoders: Vec<Lf2Encoder> = Lf2Encoder::ALL.iter().map(|e| e.with_seed(seed)).collect();
+    println!("{:<24} {:>9} {:>7} {:>7}", "encoder", "identical", "differs", "errors");
+    for score in scoreboard(originals.iter().map(Vec::as_slice), &encoders) {
+        println!(
+            "{:<24} {:>9} {:>7} {:>7}",
+            score.encoder.to_string(), score.identical, score.differs, score.errors
+        );
+    }
+    Ok(())
+}
+
+/// Exit status of `verify --strict` when some file is not byte-identical
+const EXIT_VERIFY_MISMATCH: i32 = 3;
+
+fn run_verify(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::reencode::{localize_lf2_diff, verify_lf2, Lf2Encoder, VerifyOutcome};
+    use retro_decode::perceptual::PerceptualThresholds;
+    use retro_decode::report::{print_json_line, DiffSummary, VerifyRecord, VerifyStatus};
+
+    let encoder = matches.get_one::<String>("encoder")
+        .or_else(|| matches.get_one::<String>("encode-profile"))
+        .map_or(Ok(Lf2Encoder::Okumura), |name| Lf2Encoder::from_name(name))?;
+    let encoder = match matches.get_one::<u64>("seed") {
+        Some(&seed) => encoder.with_seed(seed),
+        None => encoder,
+    };
+    let strict = matches.get_flag("strict");
+    let regions = matches.get_flag("regions");
+    let mask_dir = matches.get_one::<PathBuf>("mask");
+    let repro_dir = matches.get_one::<PathBuf>("repro");
+    let audit_candidates = matches.get_flag("audit-candidates");
+    if cfg!(not(feature = "unstable")) && (repro_dir.is_some() || audit_candidates) {
+        return Err(anyhow::anyhow!("--repro and --audit-candidates are not available: rebuild with --features unstable"));
+    }
+    let json = matches.get_flag("json");
+    let mut search_space_misses = Vec::new();
+    let mut tie_break_mismatches = Vec::new();
+    for dir in mask_dir.iter().chain(&repro_dir) {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut files = Vec::new();
+    let inputs = matches.get_many::<PathBuf>("inputs").into_iter().flatten()
+        .chain(matches.get_many::<PathBuf>("input").into_iter().flatten());
+    for input in inputs {
+        if input.is_dir() {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(input)? {
+                let path = entry?.path();
+                if path.is_file() && matches!(FormatType::from_path(&path), Ok(FormatType::ToHeartLf2)) {
+                    entries.push(path);
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(input.clone());
+        }
+    }
+
+    if matches.get_flag("scoreboard") {
+        let seed = matches.get_one::<u64>("seed").copied().unwrap_or_default();
+        return print_scoreboard(&files, seed);
+    }
+
+    if let Some(dir) = matches.get_one::<PathBuf>("against") {
+        let thresholds = matches.get_flag("perceptual").then(|| PerceptualThresholds {
+            min_ssim: *matches.get_one::<f64>("min-ssim").unwrap(),
+            max_mean_delta_e: *matches.get_one::<f64>("max-delta-e").unwrap(),
+        });
+        return verify_pixels(&files, dir, thresholds, strict, json);
+    }
+
+    let mut failures = 0;
+    for file in &files {
+        let result = std::fs::read(file).map_err(anyhow::Error::from)
+            .and_then(|data| verify_lf2(&data, encoder));
+        let mut record = VerifyRecord::new(file, Some(encoder), VerifyStatus::Identical);
+        if let Ok(image) = retro_decode::formats::toheart::Lf2Image::open(file) {
+            record.warnings = retro_decode::colors::lint_lf2(&image);
+            if !json && !strict {
+                for warning in &record.warnings {
+                    warn!("{}: {}", file.display(), warning.message);
+                }
+            }
+        }
+        match result {
+            Ok(VerifyOutcome::Identical) => {
+                record.size_delta = Some(0);
+                if !strict && !json {
+                    info!("{}: byte-identical", file.display());
+                }
+            }
+            Ok(VerifyOutcome::Differs { first_diff, differing_bytes, size_delta }) => {
+                failures += 1;
+                record.status = VerifyStatus::Differs;
+                record.first_diff = Some(first_diff);
+                record.differing_bytes = Some(differing_bytes);
+                record.size_delta = Some(size_delta);
+                if !json && strict {
+                    println!("{}", file.display());
+                } else if !json {
+                    info!(
+                        "{}: differs at byte {:#x}, {} bytes differ, size {:+} bytes",
+                        file.display(), first_diff, differing_bytes, size_delta
+                    );
+                }
+                if regions || mask_dir.is_some() {
+                    let report = localize_lf2_diff(&std::fs::read(file)?, encoder)?;
+                    if regions && !json {
+                        println!(
+                            "{}: {} of {} tokens differ, {} header/palette bytes",
+                            file.display(), report.mismatched_tokens, report.tokens, report.header_bytes
+                        );
+                        for region in &report.regions {
+                            println!(
+                                "  rows {}-{} cols {}-{}: {} tokens in {} flag blocks, {} in the block ending the row",
+                                region.rows.0, region.rows.1, region.columns.0, region.columns.1,
+                                region.tokens, region.flag_blocks, region.at_row_end
+                            );
+                        }
+                    }
+                    if let Some(dir) = mask_dir {
+                        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+                        let path = dir.join(format!("{}.diff.png", stem));
+                        let mut png = std::io::Cursor::new(Vec::new());
+                        report.mask_image().write_to(&mut png, image::ImageOutputFormat::Png)?;
+                        retro_decode::output::write_bytes(&path, shared.direct_writes, png.get_ref())?;
+                    }
+                    if regions {
+                        record.diff = Some(DiffSummary::from(&report));
+                    }
+                }
+                if let Some(dir) = repro_dir {
+                    write_repro(dir, file, encoder, shared)?;
+                }
+                if audit_candidates {
+                    audit_candidates_of(file, encoder, json, &mut record)?;
+                    let audit = record.audit.as_ref();
+                    if audit.is_some_and(|a| a.is_search_space_miss()) {
+                        search_space_misses.push(file.clone());
+                    } else if audit.is_some_and(|a| a.is_tie_break_mismatch()) {
+                        tie_break_mismatches.push(file.clone());
+                    }
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                record.status = VerifyStatus::Error;
+                if !json && strict {
+                    println!("{}", file.display());
+                } else if !json {
+                    error!("{}: {}", file.display(), e);
+                }
+                record.error = Some(e.to_string());
+            }
+        }
+        if json {
+            print_json_line(&record)?;
+        }
+    }
+
+    if audit_candidates && !json {
+        println!("Search-space misses ({}):", search_space_misses.len());
+        for file in &search_space_misses {
+            println!("  {}", file.display());
+        }
+        println!("Tie-break mismatches ({}):", tie_break_mismatches.len());
+        for file in &tie_break_mismatches {
+            println!("  {}", file.display());
+        }
+    }
+
+    if !strict {
+        info!("{} of {} files byte-identical ({} encoder)", files.len() - failures, files.len(), encoder);
+    }
+    if strict && failures > 0 {
+        return Err(ExitStatus(EXIT_VERIFY_MISMATCH).into());
+    }
+    Ok(())
+}
+
+/// `verify --repro`: save the smallest input reproducing the first
+/// diverging token of `file`
+#[cfg(feature = "unstable")]
+fn write_repro(
+    dir: &std::path::Path,
+    file: &std::path::Path,
+    encoder: retro_decode::formats::reencode::Lf2Encoder,
+    shared: &Config,
+) -> anyhow::Result<()> {
+    use retro_decode::formats::repro::ReproFixture;
+
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(fixture) = ReproFixture::extract(&std::fs::read(file)?, encoder, &name)? {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        let path = dir.join(format!("{}.repro.json", stem));
+        retro_decode::output::write_bytes(&path, shared.direct_writes, fixture.to_json()?.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "unstable"))]
+fn write_repro(
+    _dir: &std::path::Path,
+    _file: &std::path::Path,
+    _encoder: retro_decode::formats::reencode::Lf2Encoder,
+    _shared: &Config,
+) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("--repro is not available: rebuild with --features unstable"))
+}
+
+/// `verify --audit-candidates`: classify the original references of `file`
+/// the encoder failed to reproduce, into `record.audit`
+#[cfg(feature = "unstable")]
+fn audit_candidates_of(
+    file: &std::path::Path,
+    encoder: retro_decode::formats::reencode::Lf2Encoder,
+    json: bool,
+    record: &mut retro_decode::report::VerifyRecord,
+) -> anyhow::Result<()> {
+    let audit = retro_decode::formats::candidate_audit::audit_lf2(&std::fs::read(file)?, encoder)?;
+    if !json {
+        println!(
+            "{}: {} references, {} reproduced, {} outranked, {} unaligned, {} absent from candidates",
+            file.display(), audit.matches, audit.reproduced, audit.outranked, audit.unaligned, audit.absent.len()
+        );
+        for absent in audit.absent.iter().take(8) {
+            println!("  @{} distance {} len {}: {:?}", absent.output, absent.distance, absent.len, absent.reason);
+        }
+    }
+    record.audit = Some(audit);
+    Ok(())
+}
+
+#[cfg(not(feature = "unstable"))]
+fn audit_candidates_of(
+    _file: &std::path::Path,
+    _encoder: retro_decode::formats::reencode::Lf2Encoder,
+    _json: bool,
+    _record: &mut retro_decode::report::VerifyRecord,
+) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("--audit-candidates is not available: rebuild with --features unstable"))
+}
+
+/// Unpack an `archive.zip!/member` path to a temporary copy; the guard
+/// removes it when dropped. Other paths are returned as they are.
+#[cfg(feature = "zip")]
+fn unzip_input(path: &std::path::Path) -> anyhow::Result<(PathBuf, Option<retro_decode::zip::Extracted>)> {
+    let extracted = retro_decode::zip::extract(path)?;
+    Ok((extracted.as_ref().map_or_else(|| path.to_path_buf(), |e| e.path.clone()), extracted))
+}
+
+#[cfg(not(feature = "zip"))]
+fn unzip_input(path: &std::path::Path) -> anyhow::Result<(PathBuf, Option<()>)> {
+    let lower = path.to_string_lossy().to_ascii_lowercase();
+    if lower.contains(".7z!") {
+        return Err(anyhow::anyhow!("{}: 7z archives are not supported; unpack it or repack it as ZIP", path.display()));
+    }
+    if lower.contains(".zip!") {
+        return Err(anyhow::anyhow!("{}: reading from ZIP archives needs --features zip", path.display()));
+    }
+    Ok((path.to_path_buf(), None))
+}
+
+#[cfg(feature = "serve")]
+fn run_serve_static(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    let server = retro_decode::serve::StaticServer::new(matches.get_one::<PathBuf>("dir").unwrap())?;
+    let addr = std::net::SocketAddr::new(
+        *matches.get_one::<std::net::IpAddr>("bind").unwrap(),
+        *matches.get_one::<u16>("port").unwrap(),
+    );
+    let listener = std::net::TcpListener::bind(addr)
+        .map_err(|e| anyhow::anyhow!("cannot listen on {}: {}", addr, e))?;
+    server.serve(listener)
+}
+
+#[cfg(not(feature = "serve"))]
+fn run_serve_static(_matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("serve-static is not available: rebuild with --features serve"))
+}
+
+/// `verify --against`: compare decoded pixels instead of re-encoded bytes
+fn verify_pixels(
+    files: &[PathBuf],
+    dir: &std::path::Path,
+    thresholds: Option<retro_decode::perceptual::PerceptualThresholds>,
+    strict: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    use retro_decode::perceptual::{compare, find_counterpart, RgbaImage};
+    use retro_decode::report::{print_json_line, VerifyRecord, VerifyStatus};
+
+    let mut failures = 0;
+    let mut similar = 0;
+    for file in files {
+        let result = find_counterpart(dir, file)
+            .ok_or_else(|| anyhow::anyhow!("no counterpart in {}", dir.display()))
+            .and_then(|other| compare(&RgbaImage::open(file)?, &RgbaImage::open(&other)?, &other, thresholds.is_some()));
+        let mut record = VerifyRecord::new(file, None, VerifyStatus::Identical);
+        match result {
+            Ok(comparison) => {
+                record.status = if comparison.is_exact() {
+                    VerifyStatus::Identical
+                } else if thresholds.is_some_and(|t| t.accepts(&comparison)) {
+                    similar += 1;
+                    VerifyStatus::Similar
+                } else {
+                    failures += 1;
+                    VerifyStatus::Differs
+                };
+                if !json && strict {
+                    if record.status == VerifyStatus::Differs {
+                        println!("{}", file.display());
+                    }
+                } else if !json {
+                    let metrics = match (comparison.ssim, comparison.mean_delta_e, comparison.max_delta_e) {
+                        (Some(ssim), Some(mean), Some(max)) => format!(", SSIM {:.4}, ΔE mean {:.2} max {:.2}", ssim, mean, max),
+                        _ => String::new(),
+                    };
+                    println!("{}: {} ({} pixels differ{})", file.display(), record.status.name(), comparison.differing_pixels, metrics);
+                }
+                record.pixels = Some(comparison);
+            }
+            Err(e) => {
+                failures += 1;
+                record.status = VerifyStatus::Error;
+                if !json && strict {
+                    println!("{}", file.display());
+                } else if !json {
+                    error!("{}: {}", file.display(), e);
+                }
+                record.error = Some(e.to_string());
+            }
+        }
+        if json {
+            print_json_line(&record)?;
+        }
+    }
+
+    if !strict {
+        info!(
+            "{} of {} files pixel-identical, {} within perceptual thresholds",
+            files.len() - failures - similar, files.len(), similar
+        );
+    }
+    if strict && failures > 0 {
+        return Err(ExitStatus(EXIT_VERIFY_MISMATCH).into());
     }
+    Ok(())
+}
 
-    // Create output directory
-    std::fs::create_dir_all(&config.output)?;
-    
-    // Find all supported files in the directory
-    let supported_extensions = ["lf2", "pdt", "g00", "pak", "scn"];
-    let mut files_to_process = Vec::new();
-    
-    for entry in std::fs::read_dir(&input_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file() {
-            if let Some(extension) = path.extension() {
-                let ext_str = extension.to_string_lossy().to_lowercase();
-                if supported_extensions.contains(&ext_str.as_str()) {
-                    files_to_process.push(path);
+fn run_planar(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::pc98::{self, planar, PlanarSpec, PlaneInterleave};
+
+    let spec = PlanarSpec {
+        width: *matches.get_one::<usize>("width").unwrap(),
+        height: *matches.get_one::<usize>("height").unwrap(),
+        interleave: PlaneInterleave::from_name(matches.get_one::<String>("interleave").unwrap())?,
+        ..Default::default()
+    };
+    let palette = match matches.get_one::<PathBuf>("palette") {
+        Some(path) => planar::parse_grb_palette(&std::fs::read(path)?)?,
+        None => planar::default_palette(),
+    };
+    let trace = matches.get_one::<PathBuf>("trace").cloned();
+    let config = retro_decode::DecodeConfig {
+        step_by_step: matches.get_flag("step-by-step") || trace.is_some(),
+        trace_output: trace,
+        ..shared.decode_config()
+    };
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    pc98::decode_planar_direct(
+        matches.get_one::<PathBuf>("input").unwrap(),
+        output,
+        *matches.get_one::<usize>("offset").unwrap(),
+        &spec,
+        palette,
+        &config,
+    )?;
+    info!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn run_inspect(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    use retro_decode::colors::{percent, ColorStats};
+
+    let db = matches.get_one::<PathBuf>("hashes")
+        .map(|path| retro_decode::hashdb::HashDatabase::open(path))
+        .transpose()?;
+    for input in matches.get_many::<PathBuf>("input").unwrap() {
+        let (path, _unzipped) = unzip_input(input)?;
+        let report = retro_decode::formats::header::inspect(&path)
+            .map_err(|e| anyhow::anyhow!("{}: {}", input.display(), e))?;
+        if matches.get_flag("json") {
+            retro_decode::report::print_json_line(&report)?;
+            continue;
+        }
+        report.print_text(input);
+
+        let format = report.format;
+        if let Some(db) = &db {
+            print_known_file(db, &std::fs::read(&path)?);
+            if format == FormatType::ToHeartPak {
+                print_known_entries(db, &path)?;
+            }
+        }
+        if !matches.get_flag("colors") || format == FormatType::ToHeartPak {
+            continue;
+        }
+
+        let data = std::fs::read(&path)?;
+        let image = retro_decode::decoder::decoder_for(&format)
+            .and_then(|decoder| decoder.decode(&data))
+            .map_err(|e| anyhow::anyhow!("{}: {}", input.display(), e))?;
+        if format == FormatType::ToHeartLf2 {
+            for warning in retro_decode::colors::lint_lf2(&retro_decode::formats::toheart::Lf2Image::from_data(&data)?) {
+                println!("  warning[{}]: {}", warning.code.name(), warning.message);
+            }
+        }
+
+        match ColorStats::of(&image) {
+            ColorStats::Indexed(usage) => {
+                println!("  used {} of {} palette entries", usage.used.len(), usage.palette_size);
+                for entry in &usage.used {
+                    let [r, g, b] = entry.color;
+                    println!(
+                        "  {:>3}  #{:02x}{:02x}{:02x}  {:>8} px  {:>5.1}%",
+                        entry.index, r, g, b, entry.pixels, percent(entry.pixels, usage.pixels)
+                    );
+                }
+                let unused: Vec<String> = usage.unused.iter().map(u8::to_string).collect();
+                println!("  unused: {}", if unused.is_empty() { "none".to_string() } else { unused.join(", ") });
+                for (index, pixels) in &usage.out_of_range {
+                    println!("  index {} is past the palette ({} px)", index, pixels);
+                }
+            }
+            ColorStats::Direct(counts) => {
+                println!("  {} distinct colours", counts.distinct);
+                for ([r, g, b, a], pixels) in &counts.top {
+                    println!(
+                        "  #{:02x}{:02x}{:02x}{:02x}  {:>8} px  {:>5.1}%",
+                        r, g, b, a, pixels, percent(*pixels, counts.pixels)
+                    );
                 }
             }
         }
     }
-    
-    if files_to_process.is_empty() {
-        info!("No supported files found in directory");
+    Ok(())
+}
+
+/// Releases whose hash database lists `data`
+fn print_known_file(db: &retro_decode::hashdb::HashDatabase, data: &[u8]) {
+    let found = db.lookup(&retro_decode::checksum::sha256_hex(data));
+    if found.is_empty() {
+        println!("  not in the hash database");
+    }
+    for (release, name) in found {
+        println!("  known: {} of {}", name, release.label());
+    }
+}
+
+/// Releases matching the entries of the PAK archive at `path`, best first
+fn print_known_entries(db: &retro_decode::hashdb::HashDatabase, path: &std::path::Path) -> anyhow::Result<()> {
+    let mut pak = retro_decode::formats::toheart::PakArchive::open(path)?;
+    let names: Vec<String> = pak.info().2.iter().map(|e| e.name.clone()).collect();
+    let mut files = Vec::with_capacity(names.len());
+    for (index, name) in names.into_iter().enumerate() {
+        files.push((name, retro_decode::checksum::sha256_hex(&pak.read_entry(index)?)));
+    }
+    let matches = db.identify(&files);
+    if matches.is_empty() {
+        println!("  no entries are in the hash database");
+    }
+    for m in matches {
+        println!(
+            "  release {}: {} of {} entries match, {} differ, {} unknown",
+            m.release.label(), m.matched, files.len(), m.differing.len(), m.unknown
+        );
+        for name in &m.differing {
+            println!("    differs: {}", name);
+        }
+    }
+    Ok(())
+}
+
+fn run_carve(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    let output = matches.get_one::<PathBuf>("output");
+    if let Some(dir) = output {
+        std::fs::create_dir_all(dir)?;
+    }
+    for input in matches.get_many::<PathBuf>("input").unwrap() {
+        use retro_decode::carve::{ScanState, CHUNK_SIZE};
+
+        let data = std::fs::read(input)?;
+        let size = data.len() as u64;
+        let state_path = output.map(|dir| ScanState::path_for(dir, input));
+        let mut state = match &state_path {
+            Some(path) if matches.get_flag("resume") => ScanState::resume(path, input, size)?,
+            _ => ScanState::new(input, size),
+        };
+        if state.scanned > 0 {
+            info!("{}: resuming at {:#x} with {} signatures", input.display(), state.scanned, state.hits.len());
+        }
+        let threads = retro_decode::threads::effective(None);
+        state.run(&data, threads, CHUNK_SIZE, |state| match &state_path {
+            Some(path) => state.save(path, shared.direct_writes),
+            None => Ok(()),
+        })?;
+        let hits = state.hits;
+        for hit in &hits {
+            let status = match &hit.error {
+                None => "ok".to_string(),
+                Some(e) => format!("invalid: {}", e),
+            };
+            println!(
+                "{} @{:#010x}: {} {}x{}, {} bytes, {}",
+                input.display(), hit.offset, hit.format, hit.width, hit.height, hit.length, status
+            );
+        }
+        let valid = hits.iter().filter(|h| h.valid).count();
+        info!("{}: {} signatures, {} decodable images", input.display(), hits.len(), valid);
+        if let Some(dir) = output {
+            let written = retro_decode::carve::extract(&data, &hits, input, dir, shared.direct_writes)?;
+            info!("Extracted {} images to {}", written, dir.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "unstable")]
+fn run_probe(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    use retro_decode::probe::{probe, ProbeOptions};
+
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let data = std::fs::read(input)?;
+    let mut options = ProbeOptions {
+        offsets: matches.get_many::<usize>("offset").unwrap().copied().collect(),
+        top: *matches.get_one::<usize>("top").unwrap(),
+    };
+
+    if let Some(ksy) = matches.get_one::<PathBuf>("ksy") {
+        let spec = retro_decode::ksy::KsySpec::open(ksy)?;
+        let fields = spec.parse(&data)?;
+        println!("{} header:", spec.id);
+        for field in &fields {
+            println!("  {:#06x} {:<20} {}", field.offset, field.id, field.value);
+        }
+        let header_end = spec.header_end(&fields);
+        if !options.offsets.contains(&header_end) {
+            options.offsets.push(header_end);
+        }
+    }
+
+    for result in probe(&data, &options) {
+        let spec = &result.spec;
+        let dims: Vec<String> = result.geometries.iter().take(4)
+            .map(|g| format!("{}x{}x{}", g.width, g.height, g.bytes_per_pixel))
+            .collect();
+        println!(
+            "score {} @{:#x}: window {:#x} fill {:#04x} start {:#x} min {} xor {:#04x} literal={} {:?} {:?} -> {} bytes (consumed {}/{}{}) [{}]",
+            result.score, result.offset, spec.window_size, spec.initial_fill, spec.initial_position,
+            spec.min_match, spec.xor_key, spec.literal_flag as u8, spec.flag_order, spec.reference,
+            result.output_size, result.consumed, data.len() - result.offset,
+            if result.clean_end { "" } else { ", truncated" },
+            dims.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "unstable"))]
+fn run_probe(_matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("probe is not available: rebuild with --features unstable"))
+}
+
+fn run_export_spec(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::spec_export::{export, find_layout, TemplateKind};
+
+    let layout = find_layout(matches.get_one::<String>("format").unwrap())?;
+    let kind = TemplateKind::parse(matches.get_one::<String>("as").unwrap())?;
+    let template = export(layout, kind);
+    match matches.get_one::<PathBuf>("output") {
+        Some(path) => {
+            retro_decode::output::write_bytes(path, shared.direct_writes, template.as_bytes())?;
+            info!("Wrote {} template for {} to {}", kind.extension(), layout.name, path.display());
+        }
+        None => print!("{}", template),
+    }
+    Ok(())
+}
+
+fn run_spec(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::formats::magic::LAYOUTS;
+    use retro_decode::formats::spec_export::{find_layout, markdown};
+
+    let spec = match matches.get_many::<String>("format") {
+        Some(names) => markdown(&names.map(|name| find_layout(name).copied()).collect::<anyhow::Result<Vec<_>>>()?),
+        None => markdown(LAYOUTS),
+    };
+    if let Some(path) = matches.get_one::<PathBuf>("check") {
+        let current = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        if current != spec {
+            return Err(anyhow::anyhow!("{} is out of date; regenerate it with `retro-decode spec -o {}`", path.display(), path.display()));
+        }
+        info!("{} matches the generated specification", path.display());
         return Ok(());
     }
-    
-    info!("Found {} files to process", files_to_process.len());
-    
-    // Process each file
-    for file_path in &files_to_process {
-        // Detect format from file extension
-        match FormatType::from_path(file_path) {
-            Ok(format_type) => {
-                // Build output file path with format extension
-                let output_file = config.output.join(
-                    file_path.file_stem().unwrap_or_default()
-                ).with_extension(&config.format);
-
-                // Process based on format and language
-                let result = match config.language.as_str() {
-                    "rust" => {
-                        retro_decode::formats::process_rust(file_path, &output_file, format_type.clone(), &config)
-                    }
-                    "python" => {
-                        #[cfg(feature = "python-bridge")]
-                        {
-                            let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
-                            retro_decode::bridge::python::process(file_path, &output_file, format_type.clone(), &bridge_config)
-                        }
-                        #[cfg(not(feature = "python-bridge"))]
-                        {
-                            Err(anyhow::anyhow!("Python bridge feature not enabled"))
-                        }
-                    }
-                    "typescript" => {
-                        let bridge_config = retro_decode::bridge::BridgeConfig::from(&config);
-                        retro_decode::bridge::typescript::process(file_path, &output_file, format_type.clone(), &bridge_config)
-                    }
-                    _ => unreachable!("Invalid language - should be caught by clap"),
-                };
-                
-                // Output benchmark information if requested
-                if config.benchmark {
-                    output_benchmark_info(file_path, &format_type, &config)?;
+    match matches.get_one::<PathBuf>("output") {
+        Some(path) => {
+            retro_decode::output::write_bytes(path, shared.direct_writes, spec.as_bytes())?;
+            info!("Wrote the format specification to {}", path.display());
+        }
+        None => print!("{}", spec),
+    }
+    Ok(())
+}
+
+fn run_export_vectors(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    let dir = matches.get_one::<PathBuf>("output").unwrap();
+    let manifest = retro_decode::vectors::export(dir, shared.direct_writes)?;
+    info!("Wrote {} conformance vectors to {}", manifest.vectors.len(), dir.display());
+    Ok(())
+}
+
+fn run_archive_game(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    let out = matches.get_one::<PathBuf>("out").unwrap();
+    let summary = retro_decode::archive::archive_game(matches.get_one::<PathBuf>("game-dir").unwrap(), out, shared.direct_writes)?;
+    info!(
+        "Archived {} files: {} archives unpacked, {} images decoded, {} failures",
+        summary.files, summary.archives, summary.images, summary.failures
+    );
+    info!("Open {}", out.join(retro_decode::archive::GALLERY_FILE).display());
+    Ok(())
+}
+
+fn run_progressive(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::progressive::ProgressiveOptions;
+
+    let options = ProgressiveOptions {
+        frames: *matches.get_one::<u32>("frames").unwrap() as usize,
+        format: *matches.get_one("format").unwrap(),
+        delay_ms: *matches.get_one::<u32>("frame-delay").unwrap(),
+    };
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let written = retro_decode::progressive::export(input, matches.get_one::<PathBuf>("output").unwrap(), &options, shared.direct_writes)?;
+    match written.as_slice() {
+        [animation] => info!("Wrote {}", animation.display()),
+        frames => info!("Wrote {} frames to {}", frames.len(), frames[0].parent().unwrap_or(std::path::Path::new(".")).display()),
+    }
+    Ok(())
+}
+
+fn run_stats(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::lzss::LzssSpec;
+    use retro_decode::stats::{StatsOptions, StatsReport};
+
+    let options = StatsOptions {
+        spec: match matches.get_one::<String>("spec").unwrap().as_str() {
+            "okumura" => LzssSpec::OKUMURA,
+            _ => LzssSpec::LF2,
+        },
+        offset: *matches.get_one::<usize>("offset").unwrap(),
+    };
+
+    let mut files = Vec::new();
+    for input in matches.get_many::<PathBuf>("inputs").unwrap() {
+        if input.is_dir() {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(input)? {
+                let path = entry?.path();
+                if path.is_file() {
+                    entries.push(path);
                 }
-                
-                // Handle processing errors
-                if let Err(e) = result {
-                    if config.benchmark {
-                        println!("file: {}", file_path.display());
-                        println!("error: {}", e);
-                        println!();
-                    } else {
-                        error!("Failed to process {}: {}", file_path.display(), e);
-                    }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(input.clone());
+        }
+    }
+
+    let mut report = StatsReport::new();
+    for file in &files {
+        let data = std::fs::read(file)?;
+        if let Err(e) = report.add_file(file, &data, &options) {
+            error!("{}", e);
+        }
+    }
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let charts = report.write(output, shared.direct_writes)?;
+    info!("Wrote {} ({} files) and {} charts", output.display(), report.files.len(), charts.len());
+    Ok(())
+}
+
+#[cfg(feature = "unstable")]
+fn run_repl(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use std::io::{BufRead, Write};
+    use retro_decode::repl::{Outcome, Session};
+
+    let mut session = Session { direct_writes: shared.direct_writes, ..Session::new() };
+    let state = matches.get_one::<PathBuf>("state");
+    if let Some(path) = state.filter(|path| path.exists()) {
+        session.load_state(path)?;
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("input") {
+        if let Outcome::Continue(text) = session.execute(&format!("load {}", path.display()))? {
+            println!("{}", text);
+        }
+    }
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else { break };
+        match session.execute(&line?) {
+            Ok(Outcome::Continue(text)) if !text.is_empty() => println!("{}", text),
+            Ok(Outcome::Continue(_)) => {}
+            Ok(Outcome::Quit) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    if let Some(path) = state {
+        session.save_state(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "unstable"))]
+fn run_repl(_matches: &clap::ArgMatches, _shared: &Config) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("repl is not available: rebuild with --features unstable"))
+}
+
+fn run_trace(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::trace::{TraceEncoding, TraceFile, TRACE_VERSION};
+
+    match matches.subcommand() {
+        Some(("migrate", sub)) => {
+            let input = sub.get_one::<PathBuf>("input").unwrap();
+            let output = sub.get_one::<PathBuf>("output").unwrap_or(input);
+            let encoding = match sub.get_one::<String>("to") {
+                Some(name) => TraceEncoding::from_name(name)?,
+                None => TraceEncoding::from_path(output),
+            };
+
+            let (trace, from) = TraceFile::load(input)?;
+            trace.save_as(output, encoding, shared.direct_writes)?;
+            info!(
+                "{}: trace version {} -> {} ({} steps)",
+                output.display(), from, TRACE_VERSION, trace.state.steps.len()
+            );
+        }
+        _ => unreachable!("Unknown trace subcommand - should be caught by clap"),
+    }
+    Ok(())
+}
+
+fn run_montage(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::montage::{collect_images, montage_files, MontageOptions};
+
+    let options = MontageOptions {
+        columns: *matches.get_one::<u32>("columns").unwrap(),
+        thumbnail: *matches.get_one::<u32>("thumb-size").unwrap(),
+        labels: !matches.get_flag("no-labels"),
+    };
+    let files = collect_images(matches.get_one::<PathBuf>("input-dir").unwrap())?;
+    let (sheet, count) = montage_files(&files, &options);
+
+    let out = matches.get_one::<PathBuf>("out").unwrap();
+    if let Some(parent) = out.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    retro_decode::output::write_rgba_image(&sheet, out, shared.direct_writes)?;
+    info!("Wrote {} ({} images, {}x{})", out.display(), count, sheet.width(), sheet.height());
+    Ok(())
+}
+
+#[cfg(feature = "unstable")]
+fn run_hypothesis(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    use retro_decode::experiments::hypothesis::{archive, builtin, run, CorpusFile};
+
+    let mut hypotheses = builtin();
+    if matches.get_flag("list") {
+        for hypothesis in &hypotheses {
+            println!("{:<24} {}", hypothesis.name(), hypothesis.description());
+        }
+        return Ok(());
+    }
+    if let Some(only) = matches.get_many::<String>("only") {
+        let only: Vec<&String> = only.collect();
+        if let Some(unknown) = only.iter().find(|name| !hypotheses.iter().any(|h| h.name() == name.as_str())) {
+            return Err(anyhow::anyhow!("Unknown hypothesis '{}' (see --list)", unknown));
+        }
+        hypotheses.retain(|h| only.iter().any(|name| name.as_str() == h.name()));
+    }
+
+    let is_lf2 = |path: &std::path::Path| {
+        matches!(retro_decode::paths::extension_lower(path).as_deref(), Some("lf2" | "scn"))
+    };
+    let roots: Vec<PathBuf> = matches.get_many::<PathBuf>("inputs").unwrap().cloned().collect();
+    let mut paths = Vec::new();
+    for input in &roots {
+        if input.is_dir() {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(input)? {
+                let path = entry?.path();
+                if path.is_file() && is_lf2(&path) {
+                    entries.push(path);
                 }
             }
-            Err(e) => {
-                if config.benchmark {
-                    println!("file: {}", file_path.display());
-                    println!("error: {}", e);
-                    println!();
-                } else {
-                    error!("Unsupported file {}: {}", file_path.display(), e);
+            entries.sort();
+            paths.extend(entries);
+        } else {
+            paths.push(input.clone());
+        }
+    }
+    let mut files = Vec::new();
+    for path in &paths {
+        match std::fs::read(path).map_err(anyhow::Error::from).and_then(|data| CorpusFile::from_data(path, &data)) {
+            Ok(file) => files.push(file),
+            Err(e) => warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    let results = run(&hypotheses, &files, &roots);
+    for result in &results {
+        println!(
+            "{:<24} {:<12} {}/{} files agree",
+            result.name, format!("{:?}", result.verdict).to_uppercase(), result.supporting, result.checked
+        );
+        for example in &result.counterexamples {
+            println!("    {}: {}", example.file.display(), example.detail);
+        }
+    }
+    let archive_path = matches.get_one::<PathBuf>("archive").unwrap();
+    archive(archive_path, &results)?;
+    info!("Appended {} results to {}", results.len(), archive_path.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "unstable"))]
+fn run_hypothesis(_matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("hypothesis is not available: rebuild with --features unstable"))
+}
+
+/// `dashboard`: before/after comparison of two verify runs
+fn run_dashboard(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::dashboard::{load, Change, Dashboard};
+
+    let before = matches.get_one::<PathBuf>("before").unwrap();
+    let after = matches.get_one::<PathBuf>("after").unwrap();
+    let dashboard = Dashboard::compare(load(before)?, load(after)?);
+    for file in dashboard.files.iter().filter(|f| matches!(f.change, Change::Regressed | Change::Improved)) {
+        println!("{:<9} {}", file.change.name(), file.file);
+    }
+    println!(
+        "identical: {} -> {} ({} improved, {} regressed)",
+        dashboard.before.identical,
+        dashboard.after.identical,
+        dashboard.count(Change::Improved),
+        dashboard.count(Change::Regressed)
+    );
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let html = dashboard.to_html(&before.display().to_string(), &after.display().to_string());
+    retro_decode::output::write_bytes(output, shared.direct_writes, html.as_bytes())?;
+    info!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn run_palette_report(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::palette_report::PaletteCollection;
+
+    let mut files = Vec::new();
+    for input in matches.get_many::<PathBuf>("inputs").unwrap() {
+        if input.is_dir() {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(input)? {
+                let path = entry?.path();
+                if path.is_file() && FormatType::from_path(&path).is_ok() {
+                    entries.push(path);
                 }
             }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(input.clone());
         }
     }
 
-    info!("Batch processing completed successfully");
+    let mut collection = PaletteCollection::new();
+    for file in &files {
+        if let Err(e) = collection.add_file(file) {
+            warn!("{}", e);
+        }
+    }
+    let report = collection.analyze(*matches.get_one::<f64>("threshold").unwrap());
+
+    for shared in &report.shared {
+        println!("{} colors shared by {} files: {}", shared.colors.len(), shared.files.len(), shared.files.join(", "));
+    }
+    for cluster in &report.clusters {
+        println!(
+            "cluster around {} ({} palettes, {} files)",
+            cluster.representative, cluster.palettes, cluster.members.len()
+        );
+        for member in &cluster.members[1..] {
+            println!("  {:>7.2}  {}", member.distance, member.file);
+        }
+    }
+
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let swatches = report.write(output, shared.direct_writes)?;
+    info!(
+        "Wrote {}{} ({} files with palettes, {} skipped)",
+        output.display(),
+        swatches.map(|p| format!(" and {}", p.display())).unwrap_or_default(),
+        report.files, report.skipped.len()
+    );
     Ok(())
 }
 
-fn output_benchmark_info(file_path: &std::path::Path, format_type: &FormatType, _config: &Config) -> anyhow::Result<()> {
-    use std::time::Instant;
-    
-    let start_time = Instant::now();
-    
-    // Get file metadata
-    let metadata = std::fs::metadata(file_path)?;
-    let file_size = metadata.len();
-    
-    // Read file to get dimensions (simplified version)
-    let (width, height) = match format_type {
-        FormatType::ToHeartLf2 => {
-            match retro_decode::formats::toheart::Lf2Image::open(file_path) {
-                Ok(img) => (img.width as u32, img.height as u32),
-                Err(_) => (0, 0)
+fn run_config_command(matches: &clap::ArgMatches, shared: &Config) -> anyhow::Result<()> {
+    use retro_decode::config_file::{template, user_path, CONFIG_FILE};
+
+    match matches.subcommand() {
+        Some(("init", sub)) => {
+            let path = if sub.get_flag("global") {
+                user_path().ok_or_else(|| anyhow::anyhow!("Cannot locate the user config directory; set HOME or XDG_CONFIG_HOME"))?
+            } else {
+                PathBuf::from(CONFIG_FILE)
+            };
+            if path.exists() && !sub.get_flag("force") {
+                return Err(anyhow::anyhow!("{} already exists; pass --force to overwrite it", path.display()));
+            }
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+            retro_decode::output::write_bytes(&path, shared.direct_writes, template().as_bytes())?;
+            info!("Wrote {}", path.display());
+        }
+        _ => unreachable!("Unknown config subcommand - should be caught by clap"),
+    }
+    Ok(())
+}
+
+fn run_project(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    use retro_decode::project::{JobState, Project};
+
+    let project_file = matches.get_one::<PathBuf>("file").unwrap();
+    let project = Project::load(project_file)?;
+
+    match matches.subcommand() {
+        Some(("run", sub)) => {
+            let summary = project.run(sub.get_flag("force"))?;
+            if summary.failed > 0 {
+                return Err(anyhow::anyhow!("{} files failed to convert", summary.failed));
+            }
+        }
+        Some(("status", _)) => {
+            let jobs = project.plan()?;
+            let done = jobs.iter().filter(|j| j.state == JobState::Done).count();
+            for job in jobs.iter().filter(|j| j.state == JobState::Pending) {
+                println!("pending_file: {}", job.input.display());
             }
+            println!("project: {}", project.project.name);
+            println!("total: {}", jobs.len());
+            println!("done: {}", done);
+            println!("pending: {}", jobs.len() - done);
+        }
+        Some(("clean", _)) => {
+            let removed = project.clean()?;
+            info!("Removed {} files", removed);
+        }
+        _ => unreachable!("Unknown project subcommand - should be caught by clap"),
+    }
+    Ok(())
+}
+
+fn print_format_list() {
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+
+    let mut rows = vec![["FORMAT", "EXTENSIONS", "VERSIONS", "DECODE", "ENCODE", "STEP_TRACE"].map(String::from)];
+    for cap in retro_decode::formats::capabilities() {
+        let list = |items: &[&str]| if items.is_empty() { "-".to_string() } else { items.join(",") };
+        rows.push([
+            cap.format.to_string(),
+            list(cap.extensions),
+            list(cap.versions),
+            yes_no(cap.decode),
+            yes_no(cap.encode),
+            yes_no(cap.step_trace),
+        ]);
+    }
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
         }
-        FormatType::KanonPdt => {
-            match retro_decode::formats::kanon::PdtImage::open(file_path) {
-                Ok(img) => (img.width, img.height),
-                Err(_) => (0, 0)
+    }
+    for row in &rows {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        println!("{}", cells.join(" ").trim_end());
+    }
+}
+
+fn run_palette_swap(config: &Config, mapping_path: &std::path::Path) -> anyhow::Result<()> {
+    use retro_decode::formats::toheart::palette_swap::{palette_swap_file, PaletteMapping};
+
+    let mapping = PaletteMapping::load(mapping_path)?;
+    info!("Palette mapping: {} index swaps, {} color swaps", mapping.indices.len(), mapping.colors.len());
+
+    let files: Vec<PathBuf> = match (&config.input, &config.input_dir) {
+        (Some(input), None) => vec![input.clone()],
+        (None, Some(dir)) => {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_file() && matches!(FormatType::from_path(&path), Ok(FormatType::ToHeartLf2)) {
+                    files.push(path);
+                }
             }
+            files.sort();
+            files
         }
-        _ => (0, 0), // Other formats not implemented yet
+        _ => return Err(anyhow::anyhow!("--palette-swap requires --input or --input-dir")),
     };
-    
-    let decode_time = start_time.elapsed();
-    
-    // Output structured benchmark information
-    println!("file: {}", file_path.display());
-    println!("size: {}", file_size);
-    println!("width: {}", width);
-    println!("height: {}", height);
-    println!("format: {}", format_type.to_string().to_lowercase().replace(" ", "_"));
-    println!("decode_time_ms: {:.2}", decode_time.as_millis() as f64);
-    println!("memory_kb: {}", (width * height * 4) / 1024); // Rough estimate
-    
-    // Format-specific information
-    match format_type {
-        FormatType::ToHeartLf2 => {
-            if let Ok(img) = retro_decode::formats::toheart::Lf2Image::open(file_path) {
-                let total_pixels = (img.width as usize) * (img.height as usize);
-                let transparent_pixels = img.pixels.iter()
-                    .filter(|&&pixel| pixel == img.transparent_color || (pixel as usize) >= img.palette.len())
-                    .count();
-                let compression_ratio = (file_size as f64 / (total_pixels * 3) as f64) * 100.0;
-                
-                println!("compression_ratio: {:.1}", compression_ratio);
-                println!("transparent_pixels: {}", transparent_pixels);
-            }
-        }
-        FormatType::KanonPdt => {
-            if let Ok(img) = retro_decode::formats::kanon::PdtImage::open(file_path) {
-                let total_pixels = (img.width * img.height) as usize;
-                let compression_ratio = (file_size as f64 / (total_pixels * 3) as f64) * 100.0;
-                let transparent_pixels = img.alpha_mask.iter().filter(|&&alpha| alpha < 255).count();
-                
-                println!("compression_ratio: {:.1}", compression_ratio);
-                println!("transparent_pixels: {}", transparent_pixels);
-            }
-        }
-        _ => {
-            println!("compression_ratio: 0.0");
-            println!("transparent_pixels: 0");
+
+    std::fs::create_dir_all(&config.output)?;
+
+    let mut failures = 0;
+    for file_path in &files {
+        let Some(file_name) = file_path.file_name() else {
+            error!("Cannot derive an output name from {}", file_path.display());
+            failures += 1;
+            continue;
+        };
+        let output_file = config.output.join(file_name);
+        match palette_swap_file(file_path, &output_file, &mapping, config.direct_writes) {
+            Ok(report) => info!(
+                "{}: {} pixels remapped, {} palette entries replaced",
+                file_path.display(), report.remapped_pixels, report.replaced_colors
+            ),
+            Err(e) => {
+                error!("Failed to palette-swap {}: {}", file_path.display(), e);
+                failures += 1;
+            }
         }
     }
-    
-    println!(); // Empty line separator
-    
+
+    info!("Palette swap completed: {} files, {} failures", files.len(), failures);
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{} files failed palette swap", failures));
+    }
     Ok(())
+}
+
+/// Run the conversion `f`, measuring it when `benchmark` is set
+fn measure_if<T>(benchmark: bool, f: impl FnOnce() -> T) -> (T, Option<retro_decode::timing::Measurements>) {
+    if benchmark {
+        let (result, measured) = retro_decode::timing::record(f);
+        (result, Some(measured))
+    } else {
+        (f(), None)
+    }
+}
+
+fn output_benchmark_info(
+    file_path: &std::path::Path,
+    format_type: &FormatType,
+    config: &Config,
+    measured: &retro_decode::timing::Measurements,
+) -> anyhow::Result<()> {
+    retro_decode::report::BenchmarkRecord::from_measurements(file_path, format_type, measured)?
+        .print(config.benchmark_format())
+}
+
+fn output_benchmark_failure(file_path: &std::path::Path, error: &anyhow::Error, config: &Config) -> anyhow::Result<()> {
+    retro_decode::report::BenchmarkFailure { file: file_path.display().to_string(), error: error.to_string() }
+        .print(config.benchmark_format())
 }
\ No newline at end of file
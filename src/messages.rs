@@ -0,0 +1,140 @@
+//! Message catalog for user-facing remediation hints
+//!
+//! [`decoder::Error::hint`](crate::decoder::Error::hint) says what the user
+//! can do about an error; this module words it. Every hint has an English
+//! and a Japanese text, matching the two READMEs. The CLI picks the language
+//! from the usual locale variables (`LC_ALL`, `LC_MESSAGES`, `LANG`);
+//! `Display` is always English.
+
+use std::fmt;
+
+use crate::decoder::Limit;
+use crate::formats::{self, FormatType};
+
+/// Language of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Ja,
+}
+
+impl Locale {
+    /// Locale from a POSIX locale name such as `ja_JP.UTF-8`; anything
+    /// without a translation falls back to English
+    pub fn from_name(name: &str) -> Self {
+        if name.starts_with("ja") {
+            Locale::Ja
+        } else {
+            Locale::En
+        }
+    }
+
+    /// Locale of the environment: the first non-empty of `LC_ALL`,
+    /// `LC_MESSAGES` and `LANG`
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Locale::En, |name| Self::from_name(&name))
+    }
+}
+
+/// What the user can do about a decoding error
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Hint {
+    /// The file ends early at byte `len`; the copy is incomplete
+    Redump { len: u64 },
+    /// A revision of `format` without a decoder
+    UnsupportedVersion { format: FormatType, version: String },
+    /// Archives are unpacked, not decoded
+    ExtractArchive,
+    /// The data does not look like `format`
+    CheckFormat { format: FormatType },
+    /// A caller-set [`DecodeLimits`](crate::decoder::DecodeLimits) cap was hit
+    RaiseLimit { limit: Limit },
+}
+
+impl Hint {
+    pub fn message(&self, locale: Locale) -> String {
+        match (self, locale) {
+            (Hint::Redump { len }, Locale::En) => format!(
+                "The file ends at byte {}, so the copy is probably incomplete; re-dump it from the original disc or archive.",
+                len
+            ),
+            (Hint::Redump { len }, Locale::Ja) => format!(
+                "ファイルが{}バイト目で途切れています。コピーが不完全な可能性があるので、元のディスクやアーカイブから取り直してください。",
+                len
+            ),
+            (Hint::UnsupportedVersion { format, version }, Locale::En) => format!(
+                "This file is {}, which retro-decode cannot decode yet (supported: {}). Please report it with a sample file.",
+                version,
+                supported_versions(format)
+            ),
+            (Hint::UnsupportedVersion { format, version }, Locale::Ja) => format!(
+                "このファイルは{}形式で、まだデコードできません（対応: {}）。サンプルファイルを添えて報告してください。",
+                version,
+                supported_versions(format)
+            ),
+            (Hint::ExtractArchive, Locale::En) => {
+                "PAK files are archives: list or unpack them with `retro-decode extract`.".to_string()
+            }
+            (Hint::ExtractArchive, Locale::Ja) => {
+                "PAKはアーカイブです。`retro-decode extract` で一覧表示・展開してください。".to_string()
+            }
+            (Hint::CheckFormat { format }, Locale::En) => format!(
+                "Check that the file really is {}: `retro-decode inspect` shows its header fields.",
+                format
+            ),
+            (Hint::CheckFormat { format }, Locale::Ja) => format!(
+                "ファイルが本当に{}か確認してください。`retro-decode inspect` でヘッダを確認できます。",
+                format
+            ),
+            (Hint::RaiseLimit { limit }, Locale::En) => format!(
+                "The file is over the configured {} limit; raise the limit only for trusted input.",
+                limit
+            ),
+            (Hint::RaiseLimit { limit }, Locale::Ja) => format!(
+                "設定された{}の上限を超えています。上限を引き上げるのは信頼できる入力に限ってください。",
+                match limit {
+                    Limit::Pixels => "ピクセル数",
+                    Limit::Steps => "ステップ数",
+                    Limit::OutputBytes => "出力サイズ",
+                }
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message(Locale::En))
+    }
+}
+
+fn supported_versions(format: &FormatType) -> String {
+    formats::capabilities()
+        .into_iter()
+        .find(|c| &c.format == format)
+        .map(|c| c.versions.join(", "))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints_are_worded_per_locale() {
+        assert_eq!(Locale::from_name("ja_JP.UTF-8"), Locale::Ja);
+        assert_eq!(Locale::from_name("C.UTF-8"), Locale::En);
+
+        let hint = Hint::UnsupportedVersion { format: FormatType::KanonPdt, version: "PDT11".to_string() };
+        assert!(hint.to_string().contains("PDT11"), "{}", hint);
+        assert!(hint.to_string().contains("PDT10"), "{}", hint);
+        assert!(hint.message(Locale::Ja).contains("PDT11"));
+        assert!(Hint::Redump { len: 42 }.message(Locale::Ja).contains("42バイト目"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Process-wide counters for education and performance investigations
+//!
+//! Once [`enable`]d, the decoders' [`timing`](crate::timing) hooks and the
+//! resume journal add to a set of counters: LZSS tokens decoded (literals
+//! versus back references), images and pixels decoded, journal hits and
+//! bytes written. Unlike [`timing`](crate::timing), which measures one file
+//! per thread, the counters add up over every thread of the process, so a
+//! batch run reports its totals. While disabled every hook is one relaxed
+//! atomic load.
+//!
+//! The CLI enables the registry with `--metrics` and prints it to stderr
+//! when the run ends; library users call [`enable`] and read [`snapshot`].
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use serde::Serialize;
+
+/// One counter of the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// LZSS streams decoded
+    Streams,
+    Literals,
+    /// Back references
+    Matches,
+    /// Output bytes produced by back references
+    MatchedBytes,
+    ImagesDecoded,
+    PixelsDecoded,
+    /// Inputs a resumed batch run skipped because the journal already had
+    /// their output
+    CacheHits,
+    OutputsWritten,
+    BytesWritten,
+}
+
+impl Counter {
+    const ALL: [Counter; 9] = [
+        Counter::Streams,
+        Counter::Literals,
+        Counter::Matches,
+        Counter::MatchedBytes,
+        Counter::ImagesDecoded,
+        Counter::PixelsDecoded,
+        Counter::CacheHits,
+        Counter::OutputsWritten,
+        Counter::BytesWritten,
+    ];
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static COUNTERS: [AtomicU64; Counter::ALL.len()] = [ZERO; Counter::ALL.len()];
+
+/// Start counting
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stop counting; the counts so far stay readable
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Add `n` to `counter` if the registry is enabled
+pub fn add(counter: Counter, n: u64) {
+    if enabled() {
+        COUNTERS[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// Set every counter back to zero
+pub fn reset() {
+    for counter in &COUNTERS {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counter values at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Metrics {
+    pub streams: u64,
+    pub literals: u64,
+    pub matches: u64,
+    pub matched_bytes: u64,
+    pub images_decoded: u64,
+    pub pixels_decoded: u64,
+    pub cache_hits: u64,
+    pub outputs_written: u64,
+    pub bytes_written: u64,
+}
+
+impl Metrics {
+    /// LZSS tokens decoded: literals plus back references
+    pub fn tokens(&self) -> u64 {
+        self.literals + self.matches
+    }
+
+    /// `key: value` lines, as printed by `--metrics`
+    pub fn to_text(&self) -> String {
+        let share = |part: u64| if self.tokens() == 0 { 0.0 } else { part as f64 * 100.0 / self.tokens() as f64 };
+        format!(
+            "tokens_decoded: {}\nliterals: {} ({:.1}%)\nmatches: {} ({:.1}%)\nmatched_bytes: {}\nstreams: {}\n\
+             images_decoded: {}\npixels_decoded: {}\ncache_hits: {}\noutputs_written: {}\nbytes_written: {}\n",
+            self.tokens(), self.literals, share(self.literals), self.matches, share(self.matches), self.matched_bytes,
+            self.streams, self.images_decoded, self.pixels_decoded, self.cache_hits, self.outputs_written,
+            self.bytes_written,
+        )
+    }
+}
+
+/// Current counter values
+pub fn snapshot() -> Metrics {
+    let get = |counter: Counter| COUNTERS[counter as usize].load(Ordering::Relaxed);
+    Metrics {
+        streams: get(Counter::Streams),
+        literals: get(Counter::Literals),
+        matches: get(Counter::Matches),
+        matched_bytes: get(Counter::MatchedBytes),
+        images_decoded: get(Counter::ImagesDecoded),
+        pixels_decoded: get(Counter::PixelsDecoded),
+        cache_hits: get(Counter::CacheHits),
+        outputs_written: get(Counter::OutputsWritten),
+        bytes_written: get(Counter::BytesWritten),
+    }
+}
+
+/// Prints the counters to stderr when dropped
+pub struct DumpGuard(());
+
+/// Enable the registry until the returned guard is dropped
+pub fn dump_on_drop() -> DumpGuard {
+    enable();
+    DumpGuard(())
+}
+
+impl Drop for DumpGuard {
+    fn drop(&mut self) {
+        disable();
+        eprint!("metrics:\n{}", snapshot().to_text().lines().map(|line| format!("  {}\n", line)).collect::<String>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_while_enabled() {
+        // The registry is process-wide: compare against a baseline
+        let before = snapshot();
+        add(Counter::Literals, 5);
+        enable();
+        let output = crate::lzss::LzssSpec::LF2.decompress_stream(&[0xff, 1, 2, 3, 4, 5, 6, 7, 8], 8);
+        crate::timing::tokens(&output);
+        crate::timing::image(4, 2, || unreachable!("not recording"));
+        let file = tempfile::NamedTempFile::new().unwrap();
+        crate::output::write_bytes(file.path(), true, &[0; 10]).unwrap();
+        disable();
+        add(Counter::Literals, 5);
+
+        let after = snapshot();
+        assert!(after.tokens() > before.tokens());
+        assert!(after.images_decoded > before.images_decoded);
+        assert!(after.pixels_decoded - before.pixels_decoded >= 8);
+        assert!(after.bytes_written - before.bytes_written >= 10);
+        assert!(after.to_text().contains("tokens_decoded: "));
+    }
+}
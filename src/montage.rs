@@ -0,0 +1,232 @@
+//! Labeled contact sheets of many images
+//!
+//! `retro-decode montage` lays out a thumbnail of every image in a
+//! directory on one sheet, file name underneath, for cataloging sprite sets
+//! in documentation. Thumbnails keep their aspect ratio and are only ever
+//! scaled down; transparent areas show a checkerboard. Labels use a
+//! built-in 5x7 ASCII font so no font file is needed; other characters are
+//! drawn as `?`.
+
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use image::{Rgba, RgbaImage};
+use tracing::warn;
+
+use crate::formats::FormatType;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// Horizontal advance per character
+const GLYPH_ADVANCE: u32 = GLYPH_WIDTH + 1;
+const PADDING: u32 = 4;
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT + PADDING;
+
+const BACKGROUND: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+const LABEL_COLOR: Rgba<u8> = Rgba([0x20, 0x20, 0x20, 0xff]);
+const CHECKER: [Rgba<u8>; 2] = [Rgba([0xcc, 0xcc, 0xcc, 0xff]), Rgba([0xee, 0xee, 0xee, 0xff])];
+const CHECKER_SIZE: u32 = 8;
+
+/// Image extensions picked up besides the retro formats
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "bmp", "gif", "jpg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MontageOptions {
+    pub columns: u32,
+    /// Largest thumbnail edge in pixels
+    pub thumbnail: u32,
+    pub labels: bool,
+}
+
+impl Default for MontageOptions {
+    fn default() -> Self {
+        Self { columns: 8, thumbnail: 128, labels: true }
+    }
+}
+
+/// Images in `dir` a contact sheet can show, sorted by name
+pub fn collect_images(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let known = FormatType::from_path(&path).is_ok()
+            || crate::paths::extension_lower(&path).is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()));
+        if path.is_file() && known {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Decode `files` and lay them out; files that fail to decode are skipped
+/// with a warning. Returns the sheet and the number of images on it.
+pub fn montage_files(files: &[PathBuf], options: &MontageOptions) -> (RgbaImage, usize) {
+    let items: Vec<(String, RgbaImage)> = files.iter()
+        .filter_map(|path| match crate::perceptual::RgbaImage::open(path) {
+            Ok(decoded) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                RgbaImage::from_raw(decoded.width, decoded.height, decoded.rgba).map(|img| (name, img))
+            }
+            Err(e) => {
+                warn!("Skipping {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+    (montage(&items, options), items.len())
+}
+
+/// Lay out `(label, image)` pairs row by row, `options.columns` per row
+pub fn montage(items: &[(String, RgbaImage)], options: &MontageOptions) -> RgbaImage {
+    let columns = options.columns.max(1);
+    let rows = ((items.len() as u32 + columns - 1) / columns).max(1);
+    let thumb = options.thumbnail.max(1);
+    let label_height = if options.labels { LABEL_HEIGHT } else { 0 };
+    let (cell_width, cell_height) = (thumb + PADDING, thumb + label_height + PADDING);
+
+    let mut sheet = RgbaImage::from_pixel(
+        columns.min(items.len().max(1) as u32) * cell_width + PADDING,
+        rows * cell_height + PADDING,
+        BACKGROUND,
+    );
+    for (i, (label, img)) in items.iter().enumerate() {
+        let x0 = PADDING + (i as u32 % columns) * cell_width;
+        let y0 = PADDING + (i as u32 / columns) * cell_height;
+
+        let small = if img.width() > thumb || img.height() > thumb {
+            let scale = thumb as f64 / img.width().max(img.height()) as f64;
+            let w = ((img.width() as f64 * scale).round() as u32).max(1);
+            let h = ((img.height() as f64 * scale).round() as u32).max(1);
+            image::imageops::thumbnail(img, w, h)
+        } else {
+            img.clone()
+        };
+        // Centre horizontally, align to the bottom of the thumbnail area
+        let (tx, ty) = (x0 + (thumb - small.width()) / 2, y0 + thumb - small.height());
+        for (x, y, px) in small.enumerate_pixels() {
+            let checker = CHECKER[(((x / CHECKER_SIZE) + (y / CHECKER_SIZE)) % 2) as usize];
+            sheet.put_pixel(tx + x, ty + y, blend(checker, *px));
+        }
+
+        if options.labels {
+            let max_chars = (thumb / GLYPH_ADVANCE) as usize;
+            draw_text(&mut sheet, x0, y0 + thumb + PADDING / 2, &fit_label(label, max_chars), LABEL_COLOR);
+        }
+    }
+    sheet
+}
+
+/// `px` composited over the opaque `under`
+fn blend(under: Rgba<u8>, px: Rgba<u8>) -> Rgba<u8> {
+    let a = px[3] as u32;
+    let mix = |u: u8, p: u8| ((p as u32 * a + u as u32 * (255 - a)) / 255) as u8;
+    Rgba([mix(under[0], px[0]), mix(under[1], px[1]), mix(under[2], px[2]), 0xff])
+}
+
+/// Shorten `label` to `max_chars`, keeping the start and the extension
+fn fit_label(label: &str, max_chars: usize) -> String {
+    let chars: Vec<char> = label.chars().collect();
+    if chars.len() <= max_chars {
+        return label.to_string();
+    }
+    if max_chars < 3 {
+        return chars[..max_chars].iter().collect();
+    }
+    let tail = label.rfind('.').map_or(0, |dot| label[dot..].chars().count()).min(max_chars / 2);
+    let head = max_chars - tail - 1;
+    chars[..head].iter().chain(['~'].iter()).chain(chars[chars.len() - tail..].iter()).collect()
+}
+
+/// Draw `text` with the built-in font, top-left at (`x`, `y`), clipped to
+/// the image
+pub fn draw_text(img: &mut RgbaImage, x: u32, y: u32, text: &str, color: Rgba<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+        let gx = x + i as u32 * GLYPH_ADVANCE;
+        for (col, bits) in glyph.iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                let (px, py) = (gx + col as u32, y + row);
+                if bits >> row & 1 == 1 && px < img.width() && py < img.height() {
+                    img.put_pixel(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+/// Column bitmaps (bit 0 = top row) for ASCII 0x20..=0x7e
+fn glyph(c: char) -> [u8; 5] {
+    let index = match c {
+        ' '..='~' => c as usize - 0x20,
+        _ => '?' as usize - 0x20,
+    };
+    FONT_5X7[index]
+}
+
+const FONT_5X7: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], [0x08, 0x2a, 0x1c, 0x2a, 0x08], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e], [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x01, 0x01], [0x3e, 0x41, 0x41, 0x51, 0x32],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x04, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f], [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x7f, 0x20, 0x18, 0x20, 0x7f],
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x08, 0x14, 0x54, 0x54, 0x3c],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00], [0x00, 0x7f, 0x10, 0x28, 0x44],
+    [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78], [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c], [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_thumbnails_and_labels() {
+        let red = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        let clear = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 255, 0]));
+        let items: Vec<(String, RgbaImage)> = (0..5)
+            .map(|i| (format!("C010{}.LF2", i), if i == 1 { clear.clone() } else { red.clone() }))
+            .collect();
+        let options = MontageOptions { columns: 3, thumbnail: 32, labels: true };
+        let sheet = montage(&items, &options);
+
+        let (cell_w, cell_h) = (32 + PADDING, 32 + LABEL_HEIGHT + PADDING);
+        assert_eq!(sheet.dimensions(), (3 * cell_w + PADDING, 2 * cell_h + PADDING));
+        // 40x20 scaled to 32x16, centred and bottom-aligned in the first cell
+        assert_eq!(sheet.get_pixel(PADDING, PADDING + 16).0, [255, 0, 0, 255]);
+        assert_eq!(*sheet.get_pixel(PADDING, PADDING + 15), BACKGROUND);
+        // Transparent thumbnail shows the checkerboard
+        assert_eq!(*sheet.get_pixel(PADDING + cell_w + 11, PADDING + 22), CHECKER[0]);
+        // Some label ink under each cell, none for the empty sixth cell
+        let ink = |cx: u32, cy: u32| (0..cell_w).any(|x| (0..LABEL_HEIGHT).any(|y| {
+            *sheet.get_pixel(PADDING + cx * cell_w + x, PADDING + cy * cell_h + 32 + y) == LABEL_COLOR
+        }));
+        assert!(ink(0, 0) && ink(1, 1));
+        assert!(!ink(2, 1));
+
+        let unlabeled = montage(&items[..2], &MontageOptions { labels: false, ..options });
+        assert_eq!(unlabeled.dimensions(), (2 * cell_w + PADDING, 32 + 2 * PADDING));
+    }
+
+    #[test]
+    fn labels_fit_the_cell() {
+        assert_eq!(fit_label("C0101.LF2", 20), "C0101.LF2");
+        assert_eq!(fit_label("VERY_LONG_NAME.LF2", 10), "VERY_~.LF2");
+        assert_eq!(fit_label("ab", 1), "a");
+        assert_eq!(glyph('あ'), glyph('?'));
+    }
+}
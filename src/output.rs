@@ -0,0 +1,405 @@
+//! Crash-safe output writing
+//!
+//! Writers used to create the destination file directly, so an interrupted
+//! conversion left a truncated PNG/BMP that looked finished. Outputs are now
+//! written to a temp file in the destination directory and renamed into place
+//! once complete. Filesystems without atomic rename (some network shares and
+//! FUSE mounts) can opt out with `direct = true`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use anyhow::{Result, anyhow};
+
+/// Temp file next to `path`: `dir/.name.<pid>.tmp`
+fn temp_path_for(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Write `path` through a buffered writer supplied to `write`.
+///
+/// With `direct = false` the data goes to a temp file that is renamed over
+/// `path` only after `write` succeeds and the data is flushed; on failure the
+/// temp file is removed and `path` is left untouched.
+#[tracing::instrument(level = "trace", skip_all)]
+pub fn write_with<F>(path: &Path, direct: bool, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    crate::timing::time(crate::timing::Phase::Write, || write_file(path, direct, write))?;
+    crate::timing::written(path);
+    Ok(())
+}
+
+fn write_file<F>(path: &Path, direct: bool, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    if direct {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let temp = temp_path_for(path);
+    let result = (|| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| anyhow!("Failed to flush {}: {}", temp.display(), e))?;
+        file.sync_all()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => std::fs::rename(&temp, path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            anyhow!("Failed to move {} into place: {}", path.display(), e)
+        }),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Write a complete byte buffer to `path` (see [`write_with`])
+pub fn write_bytes(path: &Path, direct: bool, bytes: &[u8]) -> Result<()> {
+    write_with(path, direct, |w| Ok(w.write_all(bytes)?))
+}
+
+/// Byte order of 16-bit pixel exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    /// Little-endian (GBA, DS, PSP and most ARM framebuffers)
+    #[default]
+    Little,
+    Big,
+}
+
+/// Pack 8-bit RGB into RGB565 by dropping the low bits
+pub fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+/// Write RGB pixels as headerless RGB565, two bytes per pixel in `order`
+pub fn write_rgb565<W, I>(w: &mut W, pixels: I, order: Endianness) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = [u8; 3]>,
+{
+    for [r, g, b] in pixels {
+        let packed = rgb565(r, g, b);
+        match order {
+            Endianness::Little => w.write_all(&packed.to_le_bytes())?,
+            Endianness::Big => w.write_all(&packed.to_be_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// Header of a top-down 32-bit BGRA BMP (BITMAPV4HEADER with alpha
+/// bitfields); `width * height` BGRA pixels follow, top row first, so rows
+/// can be written as they are decoded
+pub fn write_bmp32_header<W: Write>(w: &mut W, width: u32, height: u32) -> Result<()> {
+    const HEADER_SIZE: u32 = 14 + 108;
+    let image_size = width.checked_mul(height).and_then(|n| n.checked_mul(4))
+        .filter(|&n| n <= u32::MAX - HEADER_SIZE && width.max(height) <= i32::MAX as u32)
+        .ok_or_else(|| anyhow!("{}x{} is too large for a BMP", width, height))?;
+
+    w.write_all(b"BM")?;
+    w.write_all(&(HEADER_SIZE + image_size).to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(&HEADER_SIZE.to_le_bytes())?;
+
+    w.write_all(&108u32.to_le_bytes())?;
+    w.write_all(&(width as i32).to_le_bytes())?;
+    w.write_all(&(-(height as i32)).to_le_bytes())?; // negative: top-down
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&32u16.to_le_bytes())?;
+    w.write_all(&3u32.to_le_bytes())?; // BI_BITFIELDS
+    w.write_all(&image_size.to_le_bytes())?;
+    w.write_all(&[0; 16])?; // resolution, palette
+    for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+        w.write_all(&mask.to_le_bytes())?;
+    }
+    w.write_all(b"BGRs")?; // LCS_sRGB
+    w.write_all(&[0; 48])?; // endpoints, gamma
+    Ok(())
+}
+
+/// File type of `--also-indices` index planes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexPlane {
+    /// Binary greyscale PGM (P5), viewable and diffable by image tools
+    Pgm,
+    /// Headerless `width * height` bytes; the size is in the sidecar
+    Idx,
+}
+
+impl IndexPlane {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Pgm => "pgm",
+            Self::Idx => "idx",
+        }
+    }
+}
+
+/// Write palette indices, one byte per pixel, as `kind`
+pub fn write_index_plane<W: Write>(w: &mut W, width: u32, height: u32, indices: &[u8], kind: IndexPlane) -> Result<()> {
+    if indices.len() != width as usize * height as usize {
+        return Err(anyhow!("Index plane has {} bytes, expected {}x{}", indices.len(), width, height));
+    }
+    if kind == IndexPlane::Pgm {
+        write!(w, "P5\n{} {}\n255\n", width, height)?;
+    }
+    w.write_all(indices)?;
+    Ok(())
+}
+
+/// File type of `--export-palette` palettes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteFile {
+    /// Adobe Color Table: 256 RGB triples plus colour count and
+    /// transparent index (Photoshop, Aseprite, GraphicsGale)
+    Act,
+    /// GIMP palette text (GIMP, Krita, Inkscape)
+    Gpl,
+    /// `{"colors": [[r, g, b], ...], "transparent_index": n}`
+    Json,
+}
+
+impl PaletteFile {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Act => "act",
+            Self::Gpl => "gpl",
+            Self::Json => "palette.json",
+        }
+    }
+}
+
+impl std::str::FromStr for PaletteFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "act" => Ok(Self::Act),
+            "gpl" => Ok(Self::Gpl),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow!("unknown palette file '{}': expected act, gpl or json", other)),
+        }
+    }
+}
+
+/// Write `palette` as `kind`; `name` titles GIMP palettes
+pub fn write_palette<W: Write>(
+    w: &mut W,
+    palette: &[[u8; 3]],
+    transparent: Option<u8>,
+    name: &str,
+    kind: PaletteFile,
+) -> Result<()> {
+    if palette.is_empty() || palette.len() > 256 {
+        return Err(anyhow!("A palette has 1 to 256 colours, not {}", palette.len()));
+    }
+    match kind {
+        PaletteFile::Act => {
+            for index in 0..256 {
+                w.write_all(palette.get(index).unwrap_or(&[0; 3]))?;
+            }
+            w.write_all(&(palette.len() as u16).to_be_bytes())?;
+            w.write_all(&transparent.map_or(0xffff, u16::from).to_be_bytes())?;
+        }
+        PaletteFile::Gpl => {
+            writeln!(w, "GIMP Palette\nName: {}\nColumns: 16\n#", name)?;
+            for (index, [r, g, b]) in palette.iter().enumerate() {
+                let note = if transparent == Some(index as u8) { " (transparent)" } else { "" };
+                writeln!(w, "{:3} {:3} {:3}\tIndex {}{}", r, g, b, index, note)?;
+            }
+        }
+        PaletteFile::Json => {
+            let json = serde_json::json!({ "colors": palette, "transparent_index": transparent });
+            serde_json::to_writer_pretty(&mut *w, &json)?;
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+/// Output formats every writer supports
+pub const BUILTIN_FORMATS: &[&str] = &["bmp", "png", "raw", "rgba", "rgb565"];
+
+/// Encoder for an output format added with [`register_writer`]
+///
+/// Images reach it as RGBA in display order, whatever the source format;
+/// the output goes through [`write_with`], so it is crash-safe like the
+/// built-in formats.
+pub trait ImageWriter: Send + Sync {
+    fn write(&self, img: &image::RgbaImage, w: &mut dyn Write) -> Result<()>;
+}
+
+static WRITERS: RwLock<BTreeMap<String, Arc<dyn ImageWriter>>> = RwLock::new(BTreeMap::new());
+
+/// Make `name` an output format: outputs with that extension are encoded by
+/// `writer`, and `--format` accepts it. Registering a name again replaces
+/// its writer; the built-in formats cannot be replaced.
+pub fn register_writer<W: ImageWriter + 'static>(name: &str, writer: W) -> Result<()> {
+    let name = name.to_ascii_lowercase();
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+        return Err(anyhow!("Output format names are used as extensions: {:?} is not one", name));
+    }
+    if BUILTIN_FORMATS.contains(&name.as_str()) {
+        return Err(anyhow!("{} is a built-in output format", name));
+    }
+    WRITERS.write().unwrap_or_else(|e| e.into_inner()).insert(name, Arc::new(writer));
+    Ok(())
+}
+
+/// Writer registered for `name` (lowercase), if any
+pub fn registered_writer(name: &str) -> Option<Arc<dyn ImageWriter>> {
+    WRITERS.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+}
+
+/// Built-in formats followed by the registered ones
+pub fn output_formats() -> Vec<String> {
+    let registered = WRITERS.read().unwrap_or_else(|e| e.into_inner());
+    BUILTIN_FORMATS.iter().map(|f| f.to_string()).chain(registered.keys().cloned()).collect()
+}
+
+pub fn is_output_format(name: &str) -> bool {
+    BUILTIN_FORMATS.contains(&name) || registered_writer(name).is_some()
+}
+
+/// Save an RGBA image as png / bmp / raw (RGB) / rgba / rgb565
+/// (little-endian) or a registered format, chosen by extension
+pub fn write_rgba_image(img: &image::RgbaImage, path: &Path, direct: bool) -> Result<()> {
+    write_rgba_image_ordered(img, path, direct, Endianness::Little)
+}
+
+/// [`write_rgba_image`] with the byte order for `.rgb565` outputs
+pub fn write_rgba_image_ordered(img: &image::RgbaImage, path: &Path, direct: bool, rgb565_order: Endianness) -> Result<()> {
+    let extension = crate::paths::extension_lower(path)
+        .unwrap_or_else(|| "bmp".to_string());
+    if let Some(writer) = registered_writer(&extension) {
+        return write_with(path, direct, |w| writer.write(img, w));
+    }
+
+    write_with(path, direct, |w| {
+        match extension.as_str() {
+            "png" => img.write_to(w, image::ImageOutputFormat::Png)?,
+            "raw" => {
+                for px in img.pixels() {
+                    w.write_all(&px.0[..3])?;
+                }
+            }
+            "rgba" => w.write_all(img.as_raw())?,
+            "rgb565" => write_rgb565(w, img.pixels().map(|px| [px[0], px[1], px[2]]), rgb565_order)?,
+            _ => img.write_to(w, image::ImageOutputFormat::Bmp)?,
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_write_keeps_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.png");
+        std::fs::write(&path, b"previous").unwrap();
+
+        let result = write_with(&path, false, |w| {
+            w.write_all(b"partial")?;
+            Err(anyhow!("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        write_bytes(&path, false, b"complete").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"complete");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rgb565_packing_and_byte_order() {
+        assert_eq!(rgb565(0xff, 0xff, 0xff), 0xffff);
+        assert_eq!(rgb565(0xff, 0, 0), 0xf800);
+        assert_eq!(rgb565(0, 0x80, 0x08), 0x0401);
+
+        let mut little = Vec::new();
+        write_rgb565(&mut little, [[0xff, 0, 0]], Endianness::Little).unwrap();
+        assert_eq!(little, [0x00, 0xf8]);
+        let mut big = Vec::new();
+        write_rgb565(&mut big, [[0xff, 0, 0]], Endianness::Big).unwrap();
+        assert_eq!(big, [0xf8, 0x00]);
+    }
+
+    struct Dimensions;
+
+    impl ImageWriter for Dimensions {
+        fn write(&self, img: &image::RgbaImage, w: &mut dyn Write) -> Result<()> {
+            Ok(write!(w, "{}x{}", img.width(), img.height())?)
+        }
+    }
+
+    #[test]
+    fn registered_writer_handles_its_extension() {
+        assert!(register_writer("png", Dimensions).is_err());
+        assert!(register_writer("a.b", Dimensions).is_err());
+        register_writer("Dims", Dimensions).unwrap();
+        assert!(is_output_format("dims"));
+        assert!(output_formats().contains(&"dims".to_string()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.dims");
+        write_rgba_image(&image::RgbaImage::new(3, 2), &path, false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"3x2");
+    }
+
+    #[test]
+    fn palette_files() {
+        let palette = [[0, 0, 0], [255, 128, 1]];
+        let mut act = Vec::new();
+        write_palette(&mut act, &palette, Some(1), "C0101", PaletteFile::Act).unwrap();
+        assert_eq!(act.len(), 772);
+        assert_eq!(&act[3..6], [255, 128, 1]);
+        assert_eq!(&act[768..], [0, 2, 0, 1]);
+
+        let mut gpl = Vec::new();
+        write_palette(&mut gpl, &palette, Some(1), "C0101", PaletteFile::Gpl).unwrap();
+        let gpl = String::from_utf8(gpl).unwrap();
+        assert!(gpl.starts_with("GIMP Palette\nName: C0101\n"));
+        assert!(gpl.ends_with("255 128   1\tIndex 1 (transparent)\n"), "{}", gpl);
+
+        let mut json = Vec::new();
+        write_palette(&mut json, &palette, None, "C0101", PaletteFile::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["colors"][1], serde_json::json!([255, 128, 1]));
+        assert!(json["transparent_index"].is_null());
+        assert!(write_palette(&mut Vec::new(), &[], None, "", PaletteFile::Act).is_err());
+    }
+
+    #[test]
+    fn index_plane_headers() {
+        let mut pgm = Vec::new();
+        write_index_plane(&mut pgm, 3, 1, &[0, 7, 255], IndexPlane::Pgm).unwrap();
+        assert_eq!(pgm, b"P5\n3 1\n255\n\x00\x07\xff");
+        let mut idx = Vec::new();
+        write_index_plane(&mut idx, 3, 1, &[0, 7, 255], IndexPlane::Idx).unwrap();
+        assert_eq!(idx, [0, 7, 255]);
+        assert!(write_index_plane(&mut idx, 2, 2, &[0, 7, 255], IndexPlane::Idx).is_err());
+    }
+}
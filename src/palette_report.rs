@@ -0,0 +1,288 @@
+//! Palette reuse and similarity across a set of images
+//!
+//! Indexed images of one title rarely have a palette each: artists reuse
+//! one for every pose of a character, or tweak a few entries of a
+//! background palette for the evening version. The `palette-report`
+//! subcommand groups files with byte-identical palettes, then clusters the
+//! distinct palettes by colour distance, which shows how the palettes were
+//! organized and which existing palette new art can share.
+//!
+//! Palette distance ignores entry order: it is the mean, over the colours
+//! of both palettes, of the RGB distance to the nearest colour of the
+//! other palette. Clusters are single-linkage at a threshold, so a chain of
+//! gradual variations ends up in one cluster.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::formats::FormatType;
+
+/// Width and height of one colour in the swatch image
+const SWATCH: u32 = 8;
+
+/// A palette shared byte for byte by several files
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedPalette {
+    pub colors: Vec<[u8; 3]>,
+    pub files: Vec<String>,
+}
+
+/// A file of a cluster and how far its palette is from the representative
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterMember {
+    pub file: String,
+    pub distance: f64,
+}
+
+/// Palettes within the threshold of each other (transitively)
+#[derive(Debug, Clone, Serialize)]
+pub struct PaletteCluster {
+    /// File whose palette is closest to all others in the cluster
+    pub representative: String,
+    pub colors: Vec<[u8; 3]>,
+    /// Every file of the cluster, the representative first, then by distance
+    pub members: Vec<ClusterMember>,
+    /// Distinct palettes in the cluster
+    pub palettes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PaletteReport {
+    pub threshold: f64,
+    /// Files that had a palette
+    pub files: usize,
+    /// Files skipped because they have no palette or failed to decode
+    pub skipped: Vec<String>,
+    /// Identical palettes used by more than one file, most used first
+    pub shared: Vec<SharedPalette>,
+    /// Largest cluster first; palettes similar to no other are left out
+    pub clusters: Vec<PaletteCluster>,
+}
+
+/// Collects palettes, then [`analyze`](Self::analyze)s them
+#[derive(Debug, Clone, Default)]
+pub struct PaletteCollection {
+    /// Distinct palettes in first-seen order, each with its files
+    palettes: Vec<(Vec<[u8; 3]>, Vec<String>)>,
+    index: HashMap<Vec<[u8; 3]>, usize>,
+    skipped: Vec<String>,
+}
+
+impl PaletteCollection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: String, palette: Vec<[u8; 3]>) {
+        match self.index.get(&palette) {
+            Some(&i) => self.palettes[i].1.push(name),
+            None => {
+                self.index.insert(palette.clone(), self.palettes.len());
+                self.palettes.push((palette, vec![name]));
+            }
+        }
+    }
+
+    /// Decode `path` and add its palette; files without one are recorded
+    /// as skipped
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        let name = path.display().to_string();
+        let result = FormatType::from_path(path)
+            .and_then(|format| crate::decoder::decoder_for(&format).map_err(|e| anyhow!("{}", e)))
+            .and_then(|decoder| decoder.decode(&std::fs::read(path)?).map_err(|e| anyhow!("{}", e)));
+        match result {
+            Ok(image) => match image.palette {
+                Some(palette) if !palette.is_empty() => self.add(name, palette),
+                _ => self.skipped.push(name),
+            },
+            Err(e) => {
+                self.skipped.push(name);
+                return Err(anyhow!("{}: {}", path.display(), e));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn analyze(&self, threshold: f64) -> PaletteReport {
+        let n = self.palettes.len();
+        let mut distances = vec![0.0; n * n];
+        for i in 0..n {
+            for j in i + 1..n {
+                let d = palette_distance(&self.palettes[i].0, &self.palettes[j].0);
+                distances[i * n + j] = d;
+                distances[j * n + i] = d;
+            }
+        }
+
+        // Single-linkage clustering with union-find
+        let mut parent: Vec<usize> = (0..n).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for i in 0..n {
+            for j in i + 1..n {
+                if distances[i * n + j] <= threshold {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[a.max(b)] = a.min(b);
+                }
+            }
+        }
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of = HashMap::new();
+        for i in 0..n {
+            let r = root(&mut parent, i);
+            let g = *group_of.entry(r).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[g].push(i);
+        }
+
+        let mut clusters: Vec<PaletteCluster> = groups.into_iter()
+            .filter(|group| group.len() > 1)
+            .map(|group| {
+                // Medoid: smallest total distance to the other palettes,
+                // weighted by how many files use each
+                let weight = |p: usize| self.palettes[p].1.len() as f64;
+                let medoid = *group.iter()
+                    .min_by(|&&a, &&b| {
+                        let cost = |c: usize| group.iter().map(|&o| distances[c * n + o] * weight(o)).sum::<f64>();
+                        cost(a).total_cmp(&cost(b))
+                    })
+                    .unwrap();
+                let mut members: Vec<ClusterMember> = group.iter()
+                    .flat_map(|&p| self.palettes[p].1.iter().map(move |file| (p, file)))
+                    .map(|(p, file)| ClusterMember { file: file.clone(), distance: distances[medoid * n + p] })
+                    .collect();
+                members.sort_by(|a, b| a.distance.total_cmp(&b.distance).then_with(|| a.file.cmp(&b.file)));
+                PaletteCluster {
+                    representative: self.palettes[medoid].1[0].clone(),
+                    colors: self.palettes[medoid].0.clone(),
+                    members,
+                    palettes: group.len(),
+                }
+            })
+            .collect();
+        clusters.sort_by(|a, b| b.members.len().cmp(&a.members.len()).then_with(|| a.representative.cmp(&b.representative)));
+
+        let mut shared: Vec<SharedPalette> = self.palettes.iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|(colors, files)| SharedPalette { colors: colors.clone(), files: files.clone() })
+            .collect();
+        shared.sort_by(|a, b| b.files.len().cmp(&a.files.len()).then_with(|| a.files[0].cmp(&b.files[0])));
+
+        PaletteReport {
+            threshold,
+            files: self.palettes.iter().map(|(_, files)| files.len()).sum(),
+            skipped: self.skipped.clone(),
+            shared,
+            clusters,
+        }
+    }
+}
+
+impl PaletteReport {
+    /// Write the report as JSON to `json_path` and the cluster
+    /// representatives as `<stem>.swatches.png` next to it (one row per
+    /// cluster). Returns the swatch path when there is a cluster to draw.
+    pub fn write(&self, json_path: &Path, direct: bool) -> Result<Option<PathBuf>> {
+        crate::output::write_bytes(json_path, direct, serde_json::to_string_pretty(self)?.as_bytes())?;
+        if self.clusters.is_empty() {
+            return Ok(None);
+        }
+        let stem = json_path.file_stem().unwrap_or_default().to_string_lossy();
+        let path = json_path.with_file_name(format!("{}.swatches.png", stem));
+        let palettes: Vec<&[[u8; 3]]> = self.clusters.iter().map(|c| &c.colors[..]).collect();
+        crate::output::write_rgba_image(&swatches(&palettes), &path, direct)?;
+        Ok(Some(path))
+    }
+}
+
+/// Order-independent distance between two palettes: the mean RGB distance
+/// from each colour to the nearest colour of the other palette, over both
+/// palettes
+pub fn palette_distance(a: &[[u8; 3]], b: &[[u8; 3]]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return if a.len() == b.len() { 0.0 } else { f64::INFINITY };
+    }
+    let nearest_sum = |from: &[[u8; 3]], to: &[[u8; 3]]| -> f64 {
+        from.iter()
+            .map(|c| to.iter().map(|d| squared_distance(*c, *d)).min().unwrap_or(0))
+            .map(|d| (d as f64).sqrt())
+            .sum()
+    };
+    (nearest_sum(a, b) + nearest_sum(b, a)) / (a.len() + b.len()) as f64
+}
+
+fn squared_distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter().zip(&b).map(|(&x, &y)| (x as i32 - y as i32).pow(2) as u32).sum()
+}
+
+/// One row of `SWATCH`-pixel squares per palette
+pub fn swatches(palettes: &[&[[u8; 3]]]) -> RgbaImage {
+    let columns = palettes.iter().map(|p| p.len()).max().unwrap_or(0).max(1) as u32;
+    let mut img = RgbaImage::new(columns * SWATCH, palettes.len().max(1) as u32 * SWATCH);
+    for (row, palette) in palettes.iter().enumerate() {
+        for (column, &[r, g, b]) in palette.iter().enumerate() {
+            for y in 0..SWATCH {
+                for x in 0..SWATCH {
+                    img.put_pixel(column as u32 * SWATCH + x, row as u32 * SWATCH + y, Rgba([r, g, b, 0xff]));
+                }
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(offset: u8) -> Vec<[u8; 3]> {
+        (0..16u8).map(|i| [i * 16 + offset, i * 8, 255 - i * 16]).collect()
+    }
+
+    #[test]
+    fn groups_shared_and_similar_palettes() {
+        let mut collection = PaletteCollection::new();
+        collection.add("chara_a.lf2".to_string(), ramp(0));
+        collection.add("chara_b.lf2".to_string(), ramp(0));
+        // Evening variant: every entry slightly shifted, order reversed
+        collection.add("chara_evening.lf2".to_string(), ramp(3).into_iter().rev().collect());
+        collection.add("bg.lf2".to_string(), vec![[0, 200, 0], [0, 180, 20]]);
+
+        let report = collection.analyze(8.0);
+        assert_eq!(report.files, 4);
+        assert_eq!(report.shared.len(), 1);
+        assert_eq!(report.shared[0].files, ["chara_a.lf2", "chara_b.lf2"]);
+
+        assert_eq!(report.clusters.len(), 1);
+        let cluster = &report.clusters[0];
+        assert_eq!((cluster.representative.as_str(), cluster.palettes), ("chara_a.lf2", 2));
+        let files: Vec<&str> = cluster.members.iter().map(|m| m.file.as_str()).collect();
+        assert_eq!(files, ["chara_a.lf2", "chara_b.lf2", "chara_evening.lf2"]);
+        assert!((cluster.members[2].distance - 3.0).abs() < 1e-9);
+
+        assert!(collection.analyze(2.0).clusters.is_empty());
+    }
+
+    #[test]
+    fn distance_ignores_order() {
+        let a = ramp(0);
+        let reversed: Vec<_> = a.iter().rev().copied().collect();
+        assert_eq!(palette_distance(&a, &reversed), 0.0);
+        assert_eq!(palette_distance(&[[0, 0, 0]], &[[3, 4, 0]]), 5.0);
+
+        let img = swatches(&[&a, &[[1, 2, 3]]]);
+        assert_eq!(img.dimensions(), (16 * SWATCH, 2 * SWATCH));
+        assert_eq!(img.get_pixel(SWATCH, 0).0, [16, 8, 239, 0xff]);
+        assert_eq!(img.get_pixel(SWATCH, SWATCH).0, [0, 0, 0, 0]);
+    }
+}
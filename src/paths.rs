@@ -0,0 +1,417 @@
+//! Path helpers that survive real game dumps
+//!
+//! Retail discs and fan archives contain Shift-JIS directory names (which are
+//! not valid UTF-8 on Unix) and deeply nested trees that exceed `MAX_PATH` on
+//! Windows. Everything here stays in `OsStr`/`Path` land and only converts to
+//! strings for display.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Result, anyhow};
+
+/// Lowercased extension for format matching (`None` if the path has none).
+///
+/// Non-UTF-8 bytes are replaced lossily; every supported extension is ASCII,
+/// so this never turns a supported file into an unsupported one.
+pub fn extension_lower(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// Output path `<output_dir>/<input stem>.<extension>`.
+///
+/// Fails instead of silently producing `.<extension>` when the input has no
+/// file stem (e.g. `..` or a bare root), which previously made every such
+/// input overwrite the same hidden file.
+pub fn output_file_for(output_dir: &Path, input: &Path, extension: &str) -> Result<PathBuf> {
+    let stem = input.file_stem()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| anyhow!("Cannot derive an output name from {}", input.display()))?;
+    let mut name = OsString::from(stem);
+    name.push(".");
+    name.push(extension);
+    Ok(output_dir.join(name))
+}
+
+/// Files directly in `dir`, or anywhere below it when `recursive`, sorted
+/// so batch runs visit them in a stable order. Symlinked directories are not
+/// followed, so a link back up the tree cannot loop.
+pub fn files_in(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Output file name pattern of `--output-template`
+///
+/// The rendered path is relative to the output directory; `/` separates
+/// directories. Placeholders: `{stem}` and `{name}` (input file name
+/// without and with its extension), `{ext}` (output format), `{dir}` (input
+/// directory relative to the batch root, empty at the root), `{format}`
+/// (source format, e.g. `lf2`), `{width}` and `{height}` (read from the
+/// header, so only for formats whose header stores them).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct OutputTemplate(String);
+
+/// Input-side values of one [`OutputTemplate::render`]
+pub struct TemplateValues<'a> {
+    pub input: &'a Path,
+    /// Batch root `{dir}` is relative to
+    pub input_dir: &'a Path,
+    pub extension: &'a str,
+    pub format: &'a str,
+    pub dimensions: Option<(u64, u64)>,
+}
+
+enum TemplatePiece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+impl OutputTemplate {
+    pub const PLACEHOLDERS: &'static [&'static str] = &["stem", "name", "ext", "dir", "format", "width", "height"];
+
+    fn pieces(template: &str) -> Result<Vec<TemplatePiece<'_>>> {
+        let mut pieces = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest.as_bytes()[open] == b'}' {
+                return Err(anyhow!("Unmatched }} in output template {:?}", template));
+            }
+            let close = rest[open..].find('}')
+                .ok_or_else(|| anyhow!("Unclosed {{ in output template {:?}", template))? + open;
+            let name = &rest[open + 1..close];
+            if !Self::PLACEHOLDERS.contains(&name) {
+                return Err(anyhow!(
+                    "Unknown placeholder {{{}}} in output template; use {}",
+                    name,
+                    Self::PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+                ));
+            }
+            pieces.push(TemplatePiece::Text(&rest[..open]));
+            pieces.push(TemplatePiece::Placeholder(name));
+            rest = &rest[close + 1..];
+        }
+        pieces.push(TemplatePiece::Text(rest));
+        Ok(pieces)
+    }
+
+    pub fn parse(template: &str) -> Result<Self> {
+        if template.is_empty() || template.starts_with('/') {
+            return Err(anyhow!("Output template must be a relative path, got {:?}", template));
+        }
+        Self::pieces(template)?;
+        Ok(Self(template.to_string()))
+    }
+
+    /// Whether rendering reads the image size from the header
+    pub fn needs_dimensions(&self) -> bool {
+        self.0.contains("{width}") || self.0.contains("{height}")
+    }
+
+    /// Output path for `values` below `output_dir`
+    pub fn render(&self, output_dir: &Path, values: &TemplateValues) -> Result<PathBuf> {
+        let input = values.input;
+        let stem = input.file_stem()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| anyhow!("Cannot derive an output name from {}", input.display()))?;
+        let dimension = |pick: fn((u64, u64)) -> u64| {
+            values.dimensions.map(|d| pick(d).to_string()).ok_or_else(|| {
+                anyhow!("The header of {} has no image size for {{width}}/{{height}}", input.display())
+            })
+        };
+
+        let mut rendered = OsString::new();
+        for piece in Self::pieces(&self.0)? {
+            match piece {
+                TemplatePiece::Text(text) => rendered.push(text),
+                TemplatePiece::Placeholder("stem") => rendered.push(stem),
+                TemplatePiece::Placeholder("name") => rendered.push(input.file_name().unwrap_or(stem)),
+                TemplatePiece::Placeholder("ext") => rendered.push(values.extension),
+                TemplatePiece::Placeholder("format") => rendered.push(values.format),
+                TemplatePiece::Placeholder("width") => rendered.push(dimension(|(w, _)| w)?),
+                TemplatePiece::Placeholder("height") => rendered.push(dimension(|(_, h)| h)?),
+                // {dir}
+                TemplatePiece::Placeholder(_) => {
+                    let relative = input.parent().and_then(|parent| parent.strip_prefix(values.input_dir).ok());
+                    for (i, component) in relative.into_iter().flat_map(Path::components).enumerate() {
+                        if i > 0 {
+                            rendered.push("/");
+                        }
+                        rendered.push(component.as_os_str());
+                    }
+                }
+            }
+        }
+
+        // An empty {dir} leaves a leading or doubled separator, not a root
+        let mut path = output_dir.to_path_buf();
+        for component in Path::new(&rendered).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(anyhow!("Output template renders {:?}, outside the output directory", rendered));
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl TryFrom<String> for OutputTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        Self::parse(&template)
+    }
+}
+
+impl From<OutputTemplate> for String {
+    fn from(template: OutputTemplate) -> Self {
+        template.0
+    }
+}
+
+impl std::str::FromStr for OutputTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+/// `output_dir` extended with the directories between `root` and `input`,
+/// so `root/a/b/X.LF2` lands in `output_dir/a/b`
+pub fn mirrored_dir(output_dir: &Path, root: &Path, input: &Path) -> PathBuf {
+    match input.parent().and_then(|parent| parent.strip_prefix(root).ok()) {
+        Some(relative) => output_dir.join(relative),
+        None => output_dir.to_path_buf(),
+    }
+}
+
+/// Shell-style match of `name` against `pattern`: `*` is any run of
+/// characters, `?` any one character, letters match either case (game
+/// dumps mix `C0101.LF2` and `c0101.lf2`)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let name: Vec<char> = name.chars().map(|c| c.to_ascii_lowercase()).collect();
+    // Greedy with backtracking to the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// `--include`/`--exclude` selection of batch inputs. Patterns containing
+/// `/` match the path relative to the batch root, others the file name.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl FileFilter {
+    /// Whether `path` below `root` matches an include pattern (or there are
+    /// none) and no exclude pattern
+    pub fn accepts(&self, root: &Path, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        let matches = |pattern: &String| {
+            glob_match(pattern, if pattern.contains('/') { &relative } else { &name })
+        };
+        (self.include.is_empty() || self.include.iter().any(matches)) && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Extend `path` with the `\\?\` prefix on Windows so paths longer than
+/// `MAX_PATH` (260) can be opened. A no-op on other platforms and for paths
+/// that are already verbatim.
+pub fn long_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use path_absolutize::Absolutize;
+
+        const PREFIX_THRESHOLD: usize = 240;
+        let raw = path.as_os_str();
+        if raw.len() < PREFIX_THRESHOLD || raw.to_string_lossy().starts_with(r"\\?\") {
+            return path.to_path_buf();
+        }
+        let absolute = match path.absolutize() {
+            Ok(p) => p.into_owned(),
+            Err(_) => return path.to_path_buf(),
+        };
+        let text = absolute.as_os_str().to_string_lossy().into_owned();
+        let mut verbatim = OsString::new();
+        if let Some(unc) = text.strip_prefix(r"\\") {
+            verbatim.push(r"\\?\UNC\");
+            verbatim.push(unc);
+        } else {
+            verbatim.push(r"\\?\");
+            verbatim.push(absolute.as_os_str());
+        }
+        PathBuf::from(verbatim)
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_name_keeps_stem_and_rejects_empty() {
+        let out = output_file_for(Path::new("out"), Path::new("dir/C0101.LF2"), "png").unwrap();
+        assert_eq!(out, Path::new("out/C0101.png"));
+        // Only the last extension is replaced
+        let out = output_file_for(Path::new("out"), Path::new("a.b.lf2"), "bmp").unwrap();
+        assert_eq!(out, Path::new("out/a.b.bmp"));
+        assert!(output_file_for(Path::new("out"), Path::new(".."), "png").is_err());
+    }
+
+    #[test]
+    fn recursive_listing_mirrors_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        for name in ["top.lf2", "a/mid.pdt", "a/b/deep.lf2"] {
+            std::fs::write(root.join(name), b"").unwrap();
+        }
+
+        assert_eq!(files_in(root, false).unwrap(), vec![root.join("top.lf2")]);
+        let all = files_in(root, true).unwrap();
+        assert_eq!(all, vec![root.join("a/b/deep.lf2"), root.join("a/mid.pdt"), root.join("top.lf2")]);
+        assert_eq!(mirrored_dir(Path::new("out"), root, &all[0]), Path::new("out/a/b"));
+        assert_eq!(mirrored_dir(Path::new("out"), root, &all[2]), Path::new("out"));
+    }
+
+    #[test]
+    fn output_template_renders_placeholders() {
+        let template = OutputTemplate::parse("{dir}/{stem}_{width}x{height}.{ext}").unwrap();
+        assert!(template.needs_dimensions());
+        let values = |input: &'static str| TemplateValues {
+            input: Path::new(input),
+            input_dir: Path::new("game"),
+            extension: "png",
+            format: "lf2",
+            dimensions: Some((640, 480)),
+        };
+        let out = Path::new("out");
+        assert_eq!(template.render(out, &values("game/chr/C0101.LF2")).unwrap(), Path::new("out/chr/C0101_640x480.png"));
+        // An empty {dir} does not make the path absolute
+        assert_eq!(template.render(out, &values("game/C0101.LF2")).unwrap(), Path::new("out/C0101_640x480.png"));
+
+        let flat = OutputTemplate::parse("{format}/{name}.{ext}").unwrap();
+        assert_eq!(flat.render(out, &values("game/a/BG.LF2")).unwrap(), Path::new("out/lf2/BG.LF2.png"));
+        let no_size = TemplateValues { dimensions: None, ..values("game/C0101.LF2") };
+        assert!(template.render(out, &no_size).is_err());
+
+        assert!(OutputTemplate::parse("{size}.{ext}").is_err());
+        assert!(OutputTemplate::parse("{stem.{ext}").is_err());
+        assert!(OutputTemplate::parse("/abs/{stem}.{ext}").is_err());
+        assert!(OutputTemplate::parse("../{stem}.{ext}").unwrap().render(out, &values("game/C0101.LF2")).is_err());
+    }
+
+    #[test]
+    fn glob_filters_select_files() {
+        assert!(glob_match("C01*.LF2", "c0101.lf2"));
+        assert!(glob_match("*.lf2", "C0101.LF2"));
+        assert!(glob_match("C??01.*", "C0101.LF2"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("C01*.LF2", "C0201.LF2"));
+        assert!(!glob_match("?", ""));
+
+        let filter = FileFilter { include: vec!["C01*".into(), "bg/*.pdt".into()], exclude: vec!["*99.*".into()] };
+        let root = Path::new("game");
+        assert!(filter.accepts(root, Path::new("game/C0101.LF2")));
+        assert!(!filter.accepts(root, Path::new("game/C0199.LF2")));
+        assert!(!filter.accepts(root, Path::new("game/V0101.LF2")));
+        assert!(filter.accepts(root, Path::new("game/bg/SKY.PDT")));
+        assert!(!filter.accepts(root, Path::new("game/fg/SKY.PDT")));
+        assert!(FileFilter::default().accepts(root, Path::new("game/anything")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // "立ち絵" in Shift-JIS
+        let sjis = OsStr::from_bytes(b"\x97\xa7\x82\xbf\x8a\x47.LF2");
+        let input = Path::new("game").join(sjis);
+        assert_eq!(extension_lower(&input).as_deref(), Some("lf2"));
+
+        let out = output_file_for(Path::new("out"), &input, "png").unwrap();
+        assert_eq!(out.file_name().unwrap().as_bytes(), b"\x97\xa7\x82\xbf\x8a\x47.png");
+        assert_eq!(long_path(&out), out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decodes_file_with_non_utf8_name() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use crate::formats::{process_rust, FormatType};
+        use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+        let image = Lf2Image {
+            width: 2,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 0, b: 0 }],
+            pixels: vec![0, 1],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join(OsStr::from_bytes(b"\x83\x51\x81\x5b\x83\x80"));
+        std::fs::create_dir_all(&game).unwrap();
+        let input = game.join(OsStr::from_bytes(b"\x97\xa7\x82\xbf\x8a\x47.LF2"));
+        std::fs::write(&input, image.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let output = output_file_for(&game, &input, "png").unwrap();
+        let config = crate::Config { format: "png".to_string(), ..Default::default() };
+        process_rust(&input, &output, FormatType::from_path(&input).unwrap(), &config).unwrap();
+        assert!(output.exists());
+    }
+}
@@ -0,0 +1,279 @@
+//! Pixel and perceptual comparison of two images
+//!
+//! `verify --against DIR` compares decoded pixels instead of re-encoded
+//! bytes. An exact comparison only counts differing pixels; for assets that
+//! were re-quantized on purpose, `--perceptual` adds SSIM (structure, on
+//! luma) and CIE76 ΔE (colour, in CIELAB) so visual fidelity can be judged
+//! against thresholds. Pixels are alpha-composited onto black first, so a
+//! transparent pixel compares equal to black.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use crate::formats::FormatType;
+
+/// SSIM window edge and step, in pixels
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Extensions looked for when the counterpart has a different one
+const COUNTERPART_EXTENSIONS: [&str; 5] = ["png", "bmp", "lf2", "pdt", "scn"];
+
+/// An RGBA8 image in display order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Decode a retro format by extension, anything else through `image`
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Ok(format) = FormatType::from_path(path) {
+            if let Ok(decoder) = crate::decoder::decoder_for(&format) {
+                let decoded = decoder.decode(&std::fs::read(path)?)
+                    .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                return Ok(Self { width: decoded.width, height: decoded.height, rgba: decoded.rgba });
+            }
+        }
+        let image = image::open(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+            .to_rgba8();
+        Ok(Self { width: image.width(), height: image.height(), rgba: image.into_raw() })
+    }
+
+    /// RGB of each pixel composited onto black
+    fn composited(&self) -> impl Iterator<Item = [f64; 3]> + '_ {
+        self.rgba.chunks_exact(4).map(|px| {
+            let a = px[3] as f64 / 255.0;
+            [px[0] as f64 * a, px[1] as f64 * a, px[2] as f64 * a]
+        })
+    }
+}
+
+/// Outcome of comparing an image with its counterpart
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PixelComparison {
+    /// The counterpart file
+    pub against: String,
+    pub differing_pixels: usize,
+    /// Mean SSIM over 8x8 luma windows, 1.0 for identical images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssim: Option<f64>,
+    /// Mean and largest per-pixel CIE76 ΔE; about 2.3 is just noticeable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_delta_e: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_delta_e: Option<f64>,
+}
+
+impl PixelComparison {
+    pub fn is_exact(&self) -> bool {
+        self.differing_pixels == 0
+    }
+}
+
+/// Limits a perceptual comparison has to stay within to pass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerceptualThresholds {
+    pub min_ssim: f64,
+    pub max_mean_delta_e: f64,
+}
+
+impl Default for PerceptualThresholds {
+    fn default() -> Self {
+        Self { min_ssim: 0.98, max_mean_delta_e: 2.3 }
+    }
+}
+
+impl PerceptualThresholds {
+    /// Exact matches always pass; otherwise both metrics must be present
+    /// and within the limits
+    pub fn accepts(&self, comparison: &PixelComparison) -> bool {
+        comparison.is_exact()
+            || matches!(
+                (comparison.ssim, comparison.mean_delta_e),
+                (Some(ssim), Some(delta_e)) if ssim >= self.min_ssim && delta_e <= self.max_mean_delta_e
+            )
+    }
+}
+
+/// Compare `a` with `b`, computing SSIM and ΔE when `perceptual` is set
+pub fn compare(a: &RgbaImage, b: &RgbaImage, against: &Path, perceptual: bool) -> Result<PixelComparison> {
+    if (a.width, a.height) != (b.width, b.height) {
+        return Err(anyhow!(
+            "size mismatch: {}x{} vs {}x{} ({})",
+            a.width, a.height, b.width, b.height, against.display()
+        ));
+    }
+    let differing_pixels = a.rgba.chunks_exact(4).zip(b.rgba.chunks_exact(4))
+        .filter(|(p, q)| p != q)
+        .count();
+    let mut comparison = PixelComparison {
+        against: against.display().to_string(),
+        differing_pixels,
+        ssim: None,
+        mean_delta_e: None,
+        max_delta_e: None,
+    };
+    if perceptual {
+        let (mean, max) = delta_e(a, b);
+        comparison.ssim = Some(ssim(a, b));
+        comparison.mean_delta_e = Some(mean);
+        comparison.max_delta_e = Some(max);
+    }
+    Ok(comparison)
+}
+
+/// Mean SSIM over `SSIM_WINDOW`-pixel windows every `SSIM_STEP` pixels;
+/// images smaller than a window are one window
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let (width, height) = (a.width as usize, a.height as usize);
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let luma = |image: &RgbaImage| -> Vec<f64> {
+        image.composited().map(|[r, g, b]| 0.299 * r + 0.587 * g + 0.114 * b).collect()
+    };
+    let (la, lb) = (luma(a), luma(b));
+    let (win_w, win_h) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let n = (win_w * win_h) as f64;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - win_h).step_by(SSIM_STEP) {
+        for x in (0..=width - win_w).step_by(SSIM_STEP) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + win_h {
+                for i in row * width + x..row * width + x + win_w {
+                    let (p, q) = (la[i], lb[i]);
+                    sa += p;
+                    sb += q;
+                    saa += p * p;
+                    sbb += q * q;
+                    sab += p * q;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb, cov) = (saa / n - ma * ma, sbb / n - mb * mb, sab / n - ma * mb);
+            total += ((2.0 * ma * mb + SSIM_C1) * (2.0 * cov + SSIM_C2))
+                / ((ma * ma + mb * mb + SSIM_C1) * (va + vb + SSIM_C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// Mean and largest CIE76 ΔE between corresponding pixels
+pub fn delta_e(a: &RgbaImage, b: &RgbaImage) -> (f64, f64) {
+    let (mut sum, mut max, mut count) = (0.0, 0.0f64, 0usize);
+    for (p, q) in a.composited().zip(b.composited()) {
+        let (p, q) = (lab(p), lab(q));
+        let d = ((p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + (p[2] - q[2]).powi(2)).sqrt();
+        sum += d;
+        max = max.max(d);
+        count += 1;
+    }
+    (sum / count.max(1) as f64, max)
+}
+
+/// sRGB (0..255) to CIELAB, D65 white
+fn lab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let linear = |c: f64| {
+        let c = c / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The file in `dir` to compare `original` with: same file name, else the
+/// same stem (case-insensitive) with one of the usual image extensions
+pub fn find_counterpart(dir: &Path, original: &Path) -> Option<PathBuf> {
+    let name = original.file_name()?;
+    let same = dir.join(name);
+    if same.is_file() {
+        return Some(same);
+    }
+    let stem = original.file_stem()?.to_string_lossy().to_lowercase();
+    let mut candidates: Vec<PathBuf> = std::fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path.file_stem().is_some_and(|s| s.to_string_lossy().to_lowercase() == stem)
+                && crate::paths::extension_lower(path).is_some_and(|ext| COUNTERPART_EXTENSIONS.contains(&ext.as_str()))
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, noise: impl Fn(usize) -> i16) -> RgbaImage {
+        let rgba = (0..(width * height) as usize)
+            .flat_map(|i| {
+                let v = ((i % width as usize) * 255 / width as usize) as i16 + noise(i);
+                let v = v.clamp(0, 255) as u8;
+                [v, v / 2, 255 - v, 255]
+            })
+            .collect();
+        RgbaImage { width, height, rgba }
+    }
+
+    #[test]
+    fn metrics_rank_fidelity() {
+        let original = gradient(32, 16, |_| 0);
+        let path = Path::new("x.png");
+
+        let same = compare(&original, &original, path, true).unwrap();
+        assert!(same.is_exact());
+        assert!((same.ssim.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!((same.mean_delta_e, same.max_delta_e), (Some(0.0), Some(0.0)));
+
+        // Re-quantization noise of ±2 levels is close; inverted colours are not
+        let requantized = gradient(32, 16, |i| if i % 3 == 0 { 2 } else { -1 });
+        let close = compare(&original, &requantized, path, true).unwrap();
+        let far = compare(&original, &gradient(32, 16, |i| 255 - 2 * ((i % 32) * 255 / 32) as i16), path, true).unwrap();
+        assert!(close.differing_pixels > 0);
+        assert!(close.ssim.unwrap() > 0.95 && close.mean_delta_e.unwrap() < 2.3);
+        assert!(far.ssim.unwrap() < close.ssim.unwrap());
+        assert!(far.mean_delta_e.unwrap() > 10.0);
+
+        let thresholds = PerceptualThresholds::default();
+        assert!(thresholds.accepts(&close));
+        assert!(!thresholds.accepts(&far));
+        // Without the metrics only exact matches pass
+        assert!(!thresholds.accepts(&compare(&original, &requantized, path, false).unwrap()));
+
+        let black = RgbaImage { width: 1, height: 1, rgba: vec![0, 0, 0, 255] };
+        let white = RgbaImage { width: 1, height: 1, rgba: vec![255, 255, 255, 255] };
+        let transparent = RgbaImage { width: 1, height: 1, rgba: vec![255, 255, 255, 0] };
+        assert!((delta_e(&black, &white).0 - 100.0).abs() < 0.01);
+        assert_eq!(delta_e(&black, &transparent).0, 0.0);
+        assert!(compare(&black, &original, path, true).is_err());
+    }
+
+    #[test]
+    fn counterpart_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("c0101.png"), b"").unwrap();
+        std::fs::write(dir.path().join("c0101.txt"), b"").unwrap();
+        std::fs::write(dir.path().join("C0202.LF2"), b"").unwrap();
+
+        assert_eq!(find_counterpart(dir.path(), Path::new("orig/C0101.LF2")), Some(dir.path().join("c0101.png")));
+        assert_eq!(find_counterpart(dir.path(), Path::new("orig/C0202.LF2")), Some(dir.path().join("C0202.LF2")));
+        assert_eq!(find_counterpart(dir.path(), Path::new("orig/C0303.LF2")), None);
+    }
+}
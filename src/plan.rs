@@ -0,0 +1,179 @@
+//! Planned outputs of a conversion, for `--dry-run`
+//!
+//! Each input has its format detected and its header read, which is enough
+//! to say where the conversion would write, how large the image is and
+//! roughly how many bytes the output takes. No pixel data is decoded and
+//! nothing is written, so a batch of thousands of files can be checked in
+//! seconds before the real run.
+
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use crate::formats::header;
+use crate::formats::FormatType;
+use crate::Config;
+
+/// What converting one input would produce
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedOutput {
+    pub input: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<FormatType>,
+    /// Output file, or the directory archive members are extracted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    /// Output size in bytes; for PNG the uncompressed size, an upper bound
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_bytes: Option<u64>,
+    /// Members an archive would be extracted into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<usize>,
+    /// Why the input would fail, if its header already says so
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PlannedOutput {
+    /// Plan the conversion of `input`, found under `input_dir`, with `config`
+    pub fn for_file(config: &Config, input_dir: &Path, input: &Path) -> Self {
+        let mut plan = PlannedOutput {
+            input: input.to_path_buf(),
+            format: None,
+            output: None,
+            width: None,
+            height: None,
+            estimated_bytes: None,
+            entries: None,
+            error: None,
+        };
+        let format = match FormatType::detect(input) {
+            Ok(format) => format,
+            Err(e) => {
+                plan.error = Some(e.to_string());
+                return plan;
+            }
+        };
+        plan.format = Some(format.clone());
+        if matches!(format, FormatType::ToHeartPak | FormatType::SilkyMgr) {
+            plan.output = Some(config.layout.target_dir(&config.output, input_dir, input, &format));
+        } else {
+            match config.output_file(input_dir, input, &format) {
+                Ok(output) => plan.output = Some(output),
+                Err(e) => plan.error = Some(e.to_string()),
+            }
+        }
+
+        match header::inspect(input) {
+            Ok(report) => {
+                if matches!(format, FormatType::ToHeartPak | FormatType::SilkyMgr) {
+                    plan.entries = Some(report.entries.len());
+                } else if let Some((width, height)) = report.dimensions() {
+                    plan.width = Some(width);
+                    plan.height = Some(height);
+                    plan.estimated_bytes = estimated_size(&format, config.output_format(input), width, height);
+                }
+            }
+            Err(e) => plan.error = Some(e.to_string()),
+        }
+        plan
+    }
+}
+
+/// Bytes a `width` x `height` image from `format` takes as `output_format`
+pub fn estimated_size(format: &FormatType, output_format: &str, width: u64, height: u64) -> Option<u64> {
+    let pixels = width * height;
+    match output_format {
+        // LF2 and SCN keep their palette in an 8-bit BMP; the rest are 32-bit
+        "bmp" if matches!(format, FormatType::ToHeartLf2 | FormatType::ToHeartScn) => {
+            Some(54 + 256 * 4 + (width + 3) / 4 * 4 * height)
+        }
+        "bmp" => Some(54 + pixels * 4),
+        "raw" => Some(pixels * 3),
+        "rgba" | "png" => Some(pixels * 4),
+        "rgb565" => Some(pixels * 2),
+        _ => None,
+    }
+}
+
+/// Text line for one plan
+pub fn describe(plan: &PlannedOutput) -> String {
+    let mut line = format!("{}", plan.input.display());
+    if let Some(output) = &plan.output {
+        line.push_str(&format!(" -> {}", output.display()));
+    }
+    if let Some(format) = &plan.format {
+        line.push_str(&format!("  [{}]", format));
+    }
+    if let (Some(width), Some(height)) = (plan.width, plan.height) {
+        line.push_str(&format!("  {}x{}", width, height));
+    }
+    if let Some(bytes) = plan.estimated_bytes {
+        line.push_str(&format!("  ~{} bytes", bytes));
+    }
+    if let Some(entries) = plan.entries {
+        line.push_str(&format!("  {} entries", entries));
+    }
+    if let Some(error) = &plan.error {
+        line.push_str(&format!("  error: {}", error));
+    }
+    line
+}
+
+/// Totals over every plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PlanSummary {
+    pub files: usize,
+    pub failures: usize,
+    pub estimated_bytes: u64,
+}
+
+impl PlanSummary {
+    pub fn of(plans: &[PlannedOutput]) -> Self {
+        PlanSummary {
+            files: plans.len(),
+            failures: plans.iter().filter(|p| p.error.is_some()).count(),
+            estimated_bytes: plans.iter().filter_map(|p| p.estimated_bytes).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn plans_without_decoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = Lf2Image {
+            width: 10,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: vec![1; 40],
+        };
+        let input = dir.path().join("a.lf2");
+        std::fs::write(&input, image.to_lf2_bytes_okumura().unwrap()).unwrap();
+        std::fs::write(dir.path().join("bad.lf2"), b"LEAF256\0").unwrap();
+
+        let out = dir.path().join("out");
+        let config = Config { output: out.clone(), format: "bmp".to_string(), ..Default::default() };
+        let plan = PlannedOutput::for_file(&config, dir.path(), &input);
+        assert_eq!(plan.output, Some(out.join("a.bmp")));
+        assert_eq!((plan.width, plan.height), (Some(10), Some(4)));
+        assert_eq!(plan.estimated_bytes, Some(54 + 1024 + 12 * 4));
+        assert!(plan.error.is_none());
+        assert!(!out.exists());
+
+        let bad = PlannedOutput::for_file(&config, dir.path(), &dir.path().join("bad.lf2"));
+        assert!(bad.error.is_some());
+        assert_eq!(PlanSummary::of(&[plan, bad]).failures, 1);
+    }
+}
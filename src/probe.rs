@@ -0,0 +1,181 @@
+//! Unknown-LZSS probe
+//!
+//! For files no decoder recognises, try a matrix of common LZSS
+//! parameterizations and report those whose output looks like an image:
+//! the stream ends exactly at the end of the input and the output size
+//! factors into plausible dimensions.
+
+use serde::Serialize;
+
+use crate::lzss::{BitOrder, LzssSpec, ReferenceLayout};
+
+/// Output cap per attempt, generous for 640x480 32-bit
+const MAX_OUTPUT: usize = 4 << 20;
+
+/// Options for [`probe`]
+#[derive(Debug, Clone)]
+pub struct ProbeOptions {
+    /// Offsets at which the compressed stream may start
+    pub offsets: Vec<usize>,
+    /// Number of results to keep
+    pub top: usize,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self { offsets: vec![0], top: 10 }
+    }
+}
+
+/// Candidate image geometry for an output size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Geometry {
+    pub width: usize,
+    pub height: usize,
+    pub bytes_per_pixel: usize,
+}
+
+/// One scored parameterization
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub offset: usize,
+    pub spec: LzssSpec,
+    pub output_size: usize,
+    pub consumed: usize,
+    pub literals: usize,
+    pub matches: usize,
+    pub clean_end: bool,
+    pub geometries: Vec<Geometry>,
+    pub score: u32,
+}
+
+/// Every parameterization the probe tries
+pub fn spec_matrix() -> Vec<LzssSpec> {
+    let mut specs = Vec::new();
+    for window_size in [0x1000, 0x800] {
+        for initial_fill in [0x00, 0x20] {
+            for initial_position in [window_size - 18, 0] {
+                for min_match in [2, 3] {
+                    for xor_key in [0x00, 0xff] {
+                        for literal_flag in [true, false] {
+                            for flag_order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+                                for reference in [ReferenceLayout::LengthFirst, ReferenceLayout::PositionFirst] {
+                                    specs.push(LzssSpec {
+                                        window_size,
+                                        initial_fill,
+                                        initial_position,
+                                        min_match,
+                                        max_match: min_match + 15,
+                                        xor_key,
+                                        literal_flag,
+                                        flag_order,
+                                        reference,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    specs
+}
+
+/// Geometries with a common width whose pixel data is exactly `size` bytes
+pub fn plausible_geometries(size: usize) -> Vec<Geometry> {
+    let mut found = Vec::new();
+    for bytes_per_pixel in [1, 2, 3, 4] {
+        if size % bytes_per_pixel != 0 {
+            continue;
+        }
+        let pixels = size / bytes_per_pixel;
+        for width in (8..=1280).step_by(8) {
+            if pixels % width != 0 {
+                continue;
+            }
+            let height = pixels / width;
+            // Keep aspect ratios an image could plausibly have
+            if height >= 8 && height <= width * 4 && width <= height * 8 {
+                found.push(Geometry { width, height, bytes_per_pixel });
+            }
+        }
+    }
+    found
+}
+
+/// Try every parameterization at every offset and return the best results
+pub fn probe(data: &[u8], options: &ProbeOptions) -> Vec<ProbeResult> {
+    let mut results = Vec::new();
+    let specs = spec_matrix();
+
+    for &offset in &options.offsets {
+        let Some(input) = data.get(offset..).filter(|d| !d.is_empty()) else { continue };
+        for spec in &specs {
+            let out = spec.decompress_stream(input, MAX_OUTPUT);
+            let geometries = plausible_geometries(out.data.len());
+            let ratio = out.data.len() as f64 / input.len() as f64;
+
+            let mut score = 0;
+            if out.clean_end && out.consumed == input.len() {
+                score += 2;
+            }
+            if !geometries.is_empty() {
+                score += 2;
+            }
+            // Real image data compresses noticeably but not absurdly
+            if (1.1..=16.0).contains(&ratio) && out.matches > 0 {
+                score += 1;
+            }
+
+            results.push(ProbeResult {
+                offset,
+                spec: *spec,
+                output_size: out.data.len(),
+                consumed: out.consumed,
+                literals: out.literals,
+                matches: out.matches,
+                clean_end: out.clean_end,
+                geometries,
+                score,
+            });
+        }
+    }
+
+    results.sort_by(|a, b| b.score.cmp(&a.score).then(b.matches.cmp(&a.matches)));
+    results.truncate(options.top);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn finds_lf2_parameters() {
+        let (width, height) = (32u16, 16u16);
+        let image = Lf2Image {
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 4,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 4],
+            pixels: (0..width as usize * height as usize).map(|i| ((i / 8 + i / 64) % 4) as u8).collect(),
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+
+        let options = ProbeOptions { offsets: vec![0x18 + 4 * 3], top: 20 };
+        let results = probe(&data, &options);
+        let best = &results[0];
+        assert_eq!(best.score, 5);
+        assert!(results.iter().filter(|r| r.score == 5).any(|r| {
+            r.spec.xor_key == 0xff
+                && r.spec.reference == ReferenceLayout::LengthFirst
+                && r.spec.initial_fill == 0x20
+                && r.geometries.contains(&Geometry { width: 32, height: 16, bytes_per_pixel: 1 })
+        }));
+    }
+}
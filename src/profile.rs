@@ -0,0 +1,162 @@
+//! Folded-stack profiles from tracing spans
+//!
+//! Decode and encode hot paths carry `trace`-level spans. `--profile-out`
+//! installs [`FoldedLayer`], which times every span and writes one line per
+//! distinct span stack, `outer;inner <nanoseconds>`, counting only the time
+//! not spent in child spans. That is the format `inferno-flamegraph` and
+//! `flamegraph.pl` read (and the one tracing-flame writes), so profiles can
+//! be taken on Windows without perf or DTrace.
+//!
+//! Without `--profile-out` no layer enables the spans and they cost one
+//! disabled-callsite check each.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use tracing::span;
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+type Samples = Arc<Mutex<HashMap<String, u64>>>;
+
+/// Per-span timing stored in the registry extensions
+#[derive(Default)]
+struct Timing {
+    entered: Option<Instant>,
+    /// Time spent in child spans since the last enter
+    children: Duration,
+}
+
+/// Layer accumulating self time per span stack
+pub struct FoldedLayer {
+    samples: Samples,
+}
+
+/// Writes the profile when dropped (or on [`flush`](Self::flush))
+pub struct FlushGuard {
+    samples: Samples,
+    path: PathBuf,
+    direct: bool,
+}
+
+/// Create a layer and the guard that writes its profile to `path`
+pub fn folded_layer(path: &Path, direct: bool) -> (FoldedLayer, FlushGuard) {
+    let samples = Samples::default();
+    (
+        FoldedLayer { samples: samples.clone() },
+        FlushGuard { samples, path: path.to_path_buf(), direct },
+    )
+}
+
+/// Stack frame label: `module::span`
+fn label(metadata: &tracing::Metadata<'_>) -> String {
+    format!("{}::{}", metadata.target(), metadata.name())
+}
+
+impl<S> Layer<S> for FoldedLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timing::default());
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                timing.entered = Some(Instant::now());
+                timing.children = Duration::ZERO;
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let (elapsed, children) = {
+            let mut extensions = span.extensions_mut();
+            let Some(timing) = extensions.get_mut::<Timing>() else { return };
+            let Some(entered) = timing.entered.take() else { return };
+            (entered.elapsed(), timing.children)
+        };
+
+        let stack: Vec<String> = span.scope().from_root().map(|s| label(s.metadata())).collect();
+        let self_time = elapsed.saturating_sub(children).as_nanos() as u64;
+        if let Ok(mut samples) = self.samples.lock() {
+            *samples.entry(stack.join(";")).or_default() += self_time;
+        }
+        if let Some(parent) = span.parent() {
+            if let Some(timing) = parent.extensions_mut().get_mut::<Timing>() {
+                timing.children += elapsed;
+            }
+        }
+    }
+}
+
+impl FlushGuard {
+    /// Folded lines sorted by stack
+    pub fn folded(&self) -> Vec<String> {
+        let samples = self.samples.lock().map(|s| s.clone()).unwrap_or_default();
+        let mut lines: Vec<String> = samples.into_iter()
+            .map(|(stack, nanos)| format!("{} {}", stack, nanos))
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let lines = self.folded();
+        crate::output::write_with(&self.path, self.direct, |w| {
+            for line in &lines {
+                writeln!(w, "{}", line)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| anyhow!("Failed to write profile {}: {}", self.path.display(), e))
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn records_nested_span_stacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.folded");
+        let (layer, guard) = folded_layer(&path, false);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::trace_span!("outer");
+            let _outer = outer.enter();
+            for _ in 0..2 {
+                let _inner = tracing::trace_span!("inner").entered();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let lines = guard.folded();
+        let stacks: Vec<&str> = lines.iter().map(|l| l.rsplit_once(' ').unwrap().0).collect();
+        let prefix = module_path!();
+        assert_eq!(stacks, [format!("{0}::outer", prefix), format!("{0}::outer;{0}::inner", prefix)]);
+        let inner_nanos: u64 = lines[1].rsplit_once(' ').unwrap().1.parse().unwrap();
+        assert!(inner_nanos >= 2_000_000);
+
+        drop(guard);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+}
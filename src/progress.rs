@@ -0,0 +1,323 @@
+//! Progress of batch runs and long decodes
+//!
+//! With `--progress-json` a batch run writes one JSON object per line to
+//! stderr: `start` with the number of inputs, one `file` event per input and
+//! `done` with the totals. The human-oriented log lines keep going through
+//! `tracing`; these events are a stable format for GUI wrappers and scripts.
+//! On a terminal the CLI draws the same counts as a progress bar instead,
+//! unless `--quiet` is given.
+//!
+//! Library users get progress through a [`ProgressCallback`] in
+//! [`DecodeConfig::progress`](crate::DecodeConfig::progress): archive
+//! extraction reports entries, and PDT decoding reports pixels after every
+//! row. Like [`crate::timing`], decoders report to a per-thread hook, so the
+//! callback costs one thread-local check per row when nobody listens.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Serialize, Deserialize};
+
+use crate::report::{ErrorCategory, ErrorReport, FailedFile};
+
+/// What happened to one input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Converted,
+    /// Recorded in the journal of a resumed run
+    Skipped,
+    Failed,
+    /// Not a recognized format
+    Unsupported,
+}
+
+/// One stderr line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ProgressEvent {
+    Start {
+        total: usize,
+    },
+    File {
+        /// 1-based position of the input in the run
+        index: usize,
+        total: usize,
+        input: PathBuf,
+        status: FileStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// Remediation hint for `error`, in English
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hint: Option<String>,
+    },
+    Done {
+        total: usize,
+        converted: usize,
+        skipped: usize,
+        failed: usize,
+    },
+}
+
+/// Emits [`ProgressEvent`]s when enabled and keeps the totals for `done`
+/// and the failures for [`ErrorReport`]
+#[derive(Debug, Default)]
+pub struct Progress {
+    enabled: bool,
+    total: usize,
+    index: usize,
+    converted: usize,
+    skipped: usize,
+    failed: usize,
+    failures: Vec<FailedFile>,
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Start a run over `total` inputs
+    pub fn start(enabled: bool, total: usize) -> Self {
+        let progress = Self { enabled, total, ..Default::default() };
+        progress.emit(&ProgressEvent::Start { total });
+        progress
+    }
+
+    /// Also draw a per-file bar on stderr
+    pub fn with_bar(mut self) -> Self {
+        self.bar = Some(new_bar(self.total as u64, "files"));
+        self
+    }
+
+    /// Report the next input
+    pub fn file(&mut self, input: &Path, status: FileStatus, error: Option<&anyhow::Error>) {
+        self.index += 1;
+        match status {
+            FileStatus::Converted => self.converted += 1,
+            FileStatus::Skipped => self.skipped += 1,
+            FileStatus::Failed | FileStatus::Unsupported => self.failed += 1,
+        }
+        let hint = error.and_then(crate::decoder::find_error).and_then(|e| e.hint()).map(|hint| hint.to_string());
+        if let Some(error) = error.filter(|_| matches!(status, FileStatus::Failed | FileStatus::Unsupported)) {
+            self.failures.push(FailedFile {
+                file: input.display().to_string(),
+                status,
+                category: ErrorCategory::of(status, error),
+                error: error.to_string(),
+                hint: hint.clone(),
+            });
+        }
+        self.emit(&ProgressEvent::File {
+            index: self.index,
+            total: self.total,
+            input: input.to_path_buf(),
+            status,
+            error: error.map(|e| e.to_string()),
+            hint,
+        });
+        if let Some(bar) = &self.bar {
+            bar.set_message(input.file_name().unwrap_or_default().to_string_lossy().into_owned());
+            bar.inc(1);
+        }
+    }
+
+    /// Inputs that failed or were unsupported so far
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Totals and failures so far; inputs not reported yet count as not
+    /// attempted
+    pub fn error_report(&self) -> ErrorReport {
+        ErrorReport {
+            total: self.total,
+            converted: self.converted,
+            skipped: self.skipped,
+            failed: self.failed,
+            not_attempted: self.total - self.index,
+            failures: self.failures.clone(),
+        }
+    }
+
+    /// The closing event
+    pub fn summary(&self) -> ProgressEvent {
+        ProgressEvent::Done {
+            total: self.total,
+            converted: self.converted,
+            skipped: self.skipped,
+            failed: self.failed,
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        self.emit(&self.summary());
+    }
+
+    fn emit(&self, event: &ProgressEvent) {
+        if !self.enabled {
+            return;
+        }
+        if let Ok(line) = serde_json::to_string(event) {
+            // One write per line so concurrent log output cannot split it
+            let _ = std::io::stderr().lock().write_all(format!("{}\n", line).as_bytes());
+        }
+    }
+}
+
+/// What a [`ProgressTick`] counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressUnit {
+    /// Archive entries or batch inputs
+    Files,
+    /// Decoded pixels of one image
+    Pixels,
+}
+
+/// `done` of `total` units finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressTick {
+    pub unit: ProgressUnit,
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Receives [`ProgressTick`]s; cheap to clone
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&ProgressTick) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(f: impl Fn(&ProgressTick) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Callback drawing a bar on stderr. A bar appears at the first tick
+    /// and is cleared once `done` reaches `total`, so one callback can
+    /// serve several archives or images in turn.
+    pub fn bar() -> Self {
+        let current = std::sync::Mutex::new(None::<ProgressBar>);
+        Self::new(move |tick| {
+            let mut current = current.lock().unwrap_or_else(|e| e.into_inner());
+            let bar = current.get_or_insert_with(|| new_bar(tick.total, match tick.unit {
+                ProgressUnit::Files => "files",
+                ProgressUnit::Pixels => "pixels",
+            }));
+            bar.set_length(tick.total);
+            bar.set_position(tick.done);
+            if tick.done >= tick.total {
+                bar.finish_and_clear();
+                *current = None;
+            }
+        })
+    }
+
+    pub fn report(&self, tick: &ProgressTick) {
+        (self.0)(tick)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+thread_local! {
+    static CALLBACK: RefCell<Option<ProgressCallback>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with `callback` receiving the ticks reported on this thread.
+/// `None` keeps the callback of an enclosing call.
+pub fn with_callback<T>(callback: Option<&ProgressCallback>, f: impl FnOnce() -> T) -> T {
+    let Some(callback) = callback else { return f() };
+    let outer = CALLBACK.with(|current| current.replace(Some(callback.clone())));
+    let result = f();
+    CALLBACK.with(|current| current.replace(outer));
+    result
+}
+
+/// Report `done` of `total` units to the callback of this thread, if any
+pub fn tick(unit: ProgressUnit, done: u64, total: u64) {
+    CALLBACK.with(|current| {
+        if let Some(callback) = current.borrow().as_ref() {
+            callback.report(&ProgressTick { unit, done, total });
+        }
+    });
+}
+
+fn new_bar(total: u64, unit: &str) -> ProgressBar {
+    let bar = ProgressBar::new(total);
+    let template = format!("{{bar:40}} {{pos}}/{{len}} {} {{msg}}", unit);
+    if let Ok(style) = ProgressStyle::with_template(&template) {
+        bar.set_style(style);
+    }
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callbacks_nest_per_thread() {
+        use std::sync::Mutex;
+
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let sink = ticks.clone();
+        let callback = ProgressCallback::new(move |tick| sink.lock().unwrap().push((tick.done, tick.total)));
+        tick(ProgressUnit::Pixels, 1, 1);
+        with_callback(Some(&callback), || {
+            // An inner call without a callback keeps the outer one
+            with_callback(None, || tick(ProgressUnit::Pixels, 2, 4));
+            std::thread::spawn(|| tick(ProgressUnit::Pixels, 3, 4)).join().unwrap();
+        });
+        tick(ProgressUnit::Pixels, 4, 4);
+        assert_eq!(*ticks.lock().unwrap(), vec![(2, 4)]);
+
+        // PDT decoding reports once per row
+        let pdt = crate::formats::kanon::PdtImage {
+            width: 3,
+            height: 2,
+            file_length: 0,
+            layout: crate::formats::kanon::pdt::PdtLayout::Legacy,
+            mask_offset: 0,
+            pixels: vec![Default::default(); 6],
+            alpha_mask: vec![255; 6],
+        }
+        .to_pdt_bytes(crate::formats::kanon::pdt::PdtLayout::Legacy);
+        ticks.lock().unwrap().clear();
+        with_callback(Some(&callback), || crate::formats::kanon::PdtImage::from_data(&pdt).unwrap());
+        assert_eq!(*ticks.lock().unwrap(), vec![(3, 6), (6, 6)]);
+    }
+
+    #[test]
+    fn counts_and_event_shape() {
+        let mut progress = Progress::start(false, 3);
+        progress.file(Path::new("a.lf2"), FileStatus::Converted, None);
+        progress.file(Path::new("b.lf2"), FileStatus::Skipped, None);
+        progress.file(Path::new("c.txt"), FileStatus::Unsupported, Some(&anyhow::anyhow!("unknown extension")));
+        assert_eq!(
+            progress.summary(),
+            ProgressEvent::Done { total: 3, converted: 1, skipped: 1, failed: 1 }
+        );
+        let report = progress.error_report();
+        assert_eq!((report.failed, report.not_attempted), (1, 0));
+        assert_eq!(report.failures[0].category, ErrorCategory::UnsupportedFormat);
+
+        let event = ProgressEvent::File {
+            index: 3,
+            total: 3,
+            input: PathBuf::from("c.txt"),
+            status: FileStatus::Failed,
+            error: Some("boom".to_string()),
+            hint: None,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"event":"file","index":3,"total":3,"input":"c.txt","status":"failed","error":"boom"}"#);
+        assert_eq!(serde_json::from_str::<ProgressEvent>(&json).unwrap(), event);
+        assert_eq!(serde_json::to_string(&ProgressEvent::Start { total: 2 }).unwrap(), r#"{"event":"start","total":2}"#);
+    }
+}
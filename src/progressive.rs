@@ -0,0 +1,219 @@
+//! Progressive exports: the image as it appeared while loading
+//!
+//! Period hardware drew a sprite while it was being decompressed, so the
+//! picture filled in token by token rather than all at once. A progressive
+//! export replays the step trace of a decode (see [`crate::trace`]) over the
+//! finished image: frame `k` of `n` shows the pixels written by the first
+//! `k/n` of the tokens, and pixels not written yet stay transparent. A long
+//! match reveals many pixels in one frame and a run of literals only a few,
+//! so the pace follows the stream rather than the scanlines. LF2 and SCN
+//! streams start at the bottom row, and so does the reveal.
+//!
+//! Only formats whose trace records every token can be replayed; the others
+//! stop recording after the header and the first units.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use image::RgbaImage;
+
+use crate::decoder::Orientation;
+use crate::formats::toheart::Lf2Image;
+use crate::formats::{DecodingState, FormatType};
+
+/// Frames when none are requested
+pub const DEFAULT_FRAMES: usize = 48;
+/// Frame delay when none is configured
+pub const DEFAULT_FRAME_DELAY_MS: u32 = 40;
+
+/// Container of a progressive export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressiveFormat {
+    /// `<stem>.progressive.0001.png`, `.0002.png`, ...
+    Png,
+    /// One looping `<stem>.progressive.png`
+    Apng,
+}
+
+impl FromStr for ProgressiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "png" => Ok(Self::Png),
+            "apng" => Ok(Self::Apng),
+            _ => Err(anyhow!("Progressive format must be png or apng, got {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ProgressiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Png => "png",
+            Self::Apng => "apng",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressiveOptions {
+    /// Most frames to write; images with fewer tokens get one per token
+    pub frames: usize,
+    pub format: ProgressiveFormat,
+    /// APNG frame delay
+    pub delay_ms: u32,
+}
+
+impl Default for ProgressiveOptions {
+    fn default() -> Self {
+        Self { frames: DEFAULT_FRAMES, format: ProgressiveFormat::Apng, delay_ms: DEFAULT_FRAME_DELAY_MS }
+    }
+}
+
+/// Decode `data`, recording every token. Returns the image in display
+/// orientation with its trace.
+pub fn traced(format: &FormatType, data: &[u8]) -> Result<(RgbaImage, DecodingState)> {
+    match format {
+        FormatType::ToHeartLf2 | FormatType::ToHeartScn => {
+            let mut state = DecodingState::new();
+            let image = Lf2Image::from_data_with_steps(data, &mut state)?;
+            Ok((image.to_rgba_image(Orientation::Display), state))
+        }
+        other => Err(anyhow!("{} traces do not record every token; progressive export needs LF2 or SCN", other)),
+    }
+}
+
+/// Pixels decoded after each token that wrote any, in stream order
+pub fn token_progress(state: &DecodingState) -> Result<Vec<usize>> {
+    let mut progress: Vec<usize> = Vec::new();
+    for step in &state.steps {
+        let last = progress.last().copied().unwrap_or(0);
+        if step.pixels_decoded < last {
+            return Err(anyhow!(
+                "Step {} goes back from {} to {} decoded pixels",
+                step.step_number, last, step.pixels_decoded
+            ));
+        }
+        if step.pixels_decoded > last {
+            progress.push(step.pixels_decoded);
+        }
+    }
+    Ok(progress)
+}
+
+/// Pixels shown by each of at most `frames` frames, spread evenly over the
+/// tokens of `progress`. The last frame shows everything decoded.
+pub fn reveal_counts(progress: &[usize], frames: usize) -> Vec<usize> {
+    let frames = frames.min(progress.len());
+    (1..=frames).map(|k| progress[(k * progress.len() + frames - 1) / frames - 1]).collect()
+}
+
+/// Frames of `image` (display orientation) showing the first `counts[k]`
+/// pixels in stream order; `bottom_up` streams start at the last row
+pub fn reveal_frames(image: &RgbaImage, bottom_up: bool, counts: &[usize]) -> Vec<RgbaImage> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let total = width * height;
+    let mut canvas = RgbaImage::new(image.width(), image.height());
+    let mut shown = 0;
+    counts.iter()
+        .map(|&count| {
+            for i in shown..count.min(total) {
+                let (x, row) = ((i % width) as u32, i / width);
+                let y = if bottom_up { height - 1 - row } else { row } as u32;
+                canvas.put_pixel(x, y, *image.get_pixel(x, y));
+            }
+            shown = shown.max(count.min(total));
+            canvas.clone()
+        })
+        .collect()
+}
+
+/// Write the progressive export of `input` into `dir`. Returns the files
+/// written.
+pub fn export(input: &Path, dir: &Path, options: &ProgressiveOptions, direct: bool) -> Result<Vec<PathBuf>> {
+    let format = FormatType::from_path(input)?;
+    let (image, state) = traced(&format, &std::fs::read(input)?)?;
+    let progress = token_progress(&state)?;
+    if progress.is_empty() {
+        return Err(anyhow!("{} decodes no pixels", input.display()));
+    }
+    let frames = reveal_frames(&image, format.stores_bottom_up(), &reveal_counts(&progress, options.frames.max(1)));
+
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    std::fs::create_dir_all(dir)?;
+    match options.format {
+        ProgressiveFormat::Png => frames.iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let path = dir.join(format!("{}.progressive.{:04}.png", stem, i + 1));
+                crate::output::write_rgba_image(frame, &path, direct)?;
+                Ok(path)
+            })
+            .collect(),
+        ProgressiveFormat::Apng => {
+            let path = dir.join(format!("{}.progressive.png", stem));
+            crate::output::write_with(&path, direct, |w| crate::sequences::encode_apng(w, &frames, options.delay_ms))?;
+            Ok(vec![path])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+
+    fn sprite() -> Lf2Image {
+        // Repeating rows, so the stream mixes literals with long matches
+        let pixels = (0..8 * 6).map(|i| ((i % 8) / 2 + 1) as u8).collect();
+        Lf2Image {
+            width: 8,
+            height: 6,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 5,
+            palette: (0..5).map(|i| Rgb { r: i * 50, g: 0, b: 0 }).collect(),
+            pixels,
+        }
+    }
+
+    #[test]
+    fn spreads_frames_over_tokens() {
+        let progress = [1, 2, 3, 20, 21, 48];
+        assert_eq!(reveal_counts(&progress, 3), vec![2, 20, 48]);
+        assert_eq!(reveal_counts(&progress, 100), progress.to_vec());
+        assert_eq!(reveal_counts(&progress, 1), vec![48]);
+    }
+
+    #[test]
+    fn reveals_lf2_from_the_bottom_row() {
+        let data = sprite().to_lf2_bytes().unwrap();
+        let (image, state) = traced(&FormatType::ToHeartLf2, &data).unwrap();
+        let progress = token_progress(&state).unwrap();
+        assert_eq!(progress.last(), Some(&48));
+        assert!(progress.len() < 48, "expected back references in {:?}", progress);
+
+        let counts = reveal_counts(&progress, 4);
+        let frames = reveal_frames(&image, true, &counts);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].get_pixel(0, 5), image.get_pixel(0, 5));
+        assert_eq!(frames[0].get_pixel(0, 0).0[3], 0);
+        assert_eq!(frames.last(), Some(&image));
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("S01.LF2");
+        std::fs::write(&input, &data).unwrap();
+        let options = ProgressiveOptions { frames: 4, ..Default::default() };
+        let written = export(&input, dir.path(), &options, false).unwrap();
+        assert_eq!(written, vec![dir.path().join("S01.progressive.png")]);
+        let reader = png::Decoder::new(std::fs::File::open(&written[0]).unwrap()).read_info().unwrap();
+        assert_eq!(reader.info().animation_control().unwrap().num_frames, 4);
+
+        let options = ProgressiveOptions { format: ProgressiveFormat::Png, ..options };
+        assert_eq!(export(&input, dir.path(), &options, false).unwrap().len(), 4);
+        assert!(dir.path().join("S01.progressive.0004.png").exists());
+    }
+}
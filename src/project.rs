@@ -0,0 +1,386 @@
+//! Whole-game conversion projects (`project.toml`)
+//!
+//! A project file pins down the game directory, which formats to convert, the
+//! output layout and encoder settings, so a long archival effort can be re-run
+//! or resumed from the same description instead of shell history.
+//!
+//! ```toml
+//! [project]
+//! name = "ToHeart (1997)"
+//! input_dir = "game"
+//! output_dir = "converted"
+//!
+//! [conversion]
+//! format = "png"              # bmp / png / raw / rgba
+//! extensions = ["lf2", "pdt"] # default: every decodable image format
+//! layout = "mirror"           # mirror (keep subdirectories) / flat / versioned
+//! sidecar = true
+//!
+//! [encoder]
+//! lf2 = "okumura"             # default encoder hint for `reencode`
+//! seed = 42                   # for randomized encoders (default 0)
+//! ```
+//!
+//! Relative paths are resolved against the directory holding `project.toml`.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use tracing::{info, error};
+
+use crate::formats::{self, FormatType};
+use crate::formats::sidecar::{sidecar_path, ImageMetadata};
+
+/// Default project file name
+pub const PROJECT_FILE: &str = "project.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectSection {
+    pub name: String,
+    pub input_dir: PathBuf,
+    pub output_dir: PathBuf,
+}
+
+/// Output directory layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputLayout {
+    /// Keep the input subdirectory structure
+    #[default]
+    Mirror,
+    /// Put every output directly in `output_dir`
+    Flat,
+    /// Mirror below `output_dir/<retro-decode version>/<format>`, so runs of
+    /// successive releases sit side by side instead of overwriting each
+    /// other
+    Versioned,
+}
+
+impl OutputLayout {
+    /// Directory for the output of `input`, found below `input_dir`
+    pub fn target_dir(self, output_dir: &Path, input_dir: &Path, input: &Path, format: &FormatType) -> PathBuf {
+        match self {
+            OutputLayout::Mirror => crate::paths::mirrored_dir(output_dir, input_dir, input),
+            OutputLayout::Flat => output_dir.to_path_buf(),
+            OutputLayout::Versioned => {
+                let versioned = output_dir.join(env!("CARGO_PKG_VERSION")).join(format_dir(format));
+                crate::paths::mirrored_dir(&versioned, input_dir, input)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for OutputLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mirror" => Ok(OutputLayout::Mirror),
+            "flat" => Ok(OutputLayout::Flat),
+            "versioned" => Ok(OutputLayout::Versioned),
+            _ => Err(anyhow!("Layout must be mirror, flat or versioned, got {}", s)),
+        }
+    }
+}
+
+/// Directory name of `format` in the versioned layout: its main extension
+pub(crate) fn format_dir(format: &FormatType) -> String {
+    formats::capabilities()
+        .into_iter()
+        .find(|c| &c.format == format)
+        .and_then(|c| c.extensions.first().copied())
+        .unwrap_or("other")
+        .to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionSection {
+    #[serde(default = "default_format")]
+    pub format: String,
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub layout: OutputLayout,
+    #[serde(default)]
+    pub sidecar: bool,
+}
+
+impl Default for ConversionSection {
+    fn default() -> Self {
+        Self {
+            format: default_format(),
+            extensions: default_extensions(),
+            layout: OutputLayout::default(),
+            sidecar: false,
+        }
+    }
+}
+
+fn default_format() -> String {
+    "png".to_string()
+}
+
+fn default_extensions() -> Vec<String> {
+    ["lf2", "scn", "pdt"].iter().map(|s| s.to_string()).collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncoderSection {
+    /// LF2 encoder name recorded in sidecars as the `reencode` default
+    #[serde(default)]
+    pub lf2: Option<String>,
+    /// Seed for a randomized `lf2` encoder, recorded alongside it
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Parsed `project.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub project: ProjectSection,
+    #[serde(default)]
+    pub conversion: ConversionSection,
+    #[serde(default)]
+    pub encoder: EncoderSection,
+    /// Directory holding the project file (not serialized)
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+/// Conversion state of a single input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Done,
+    Pending,
+}
+
+/// One planned conversion
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub format: FormatType,
+    pub state: JobState,
+}
+
+/// Counts reported by `run`
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Project {
+    /// Load a project file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read project file {}: {}", path.display(), e))?;
+        let mut project: Project = toml::from_str(&text)
+            .map_err(|e| anyhow!("Invalid project file {}: {}", path.display(), e))?;
+        project.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        if !crate::output::is_output_format(&project.conversion.format) {
+            return Err(anyhow!("Unsupported output format: {}", project.conversion.format));
+        }
+        if let Some(name) = &project.encoder.lf2 {
+            formats::reencode::Lf2Encoder::from_name(name)?;
+        }
+        Ok(project)
+    }
+
+    pub fn input_dir(&self) -> PathBuf {
+        self.root.join(&self.project.input_dir)
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        self.root.join(&self.project.output_dir)
+    }
+
+    /// Enumerate every input and where its output goes
+    pub fn plan(&self) -> Result<Vec<Job>> {
+        let input_dir = self.input_dir();
+        let output_dir = self.output_dir();
+
+        let mut inputs = Vec::new();
+        collect_files(&input_dir, &mut inputs)?;
+        inputs.sort();
+
+        let mut jobs = Vec::new();
+        for input in inputs {
+            let ext = crate::paths::extension_lower(&input).unwrap_or_default();
+            if !self.conversion.extensions.iter().any(|e| e.eq_ignore_ascii_case(&ext)) {
+                continue;
+            }
+            let format = match FormatType::from_path(&input) {
+                Ok(f) => f,
+                Err(_) => continue,
+            };
+
+            let target_dir = self.conversion.layout.target_dir(&output_dir, &input_dir, &input, &format);
+            let output = crate::paths::output_file_for(&target_dir, &input, &self.conversion.format)?;
+
+            let state = if is_up_to_date(&input, &output) { JobState::Done } else { JobState::Pending };
+            jobs.push(Job { input, output, format, state });
+        }
+        Ok(jobs)
+    }
+
+    /// Convert every pending input; `force` re-converts finished ones too
+    pub fn run(&self, force: bool) -> Result<RunSummary> {
+        let mut summary = RunSummary::default();
+
+        for job in self.plan()? {
+            if job.state == JobState::Done && !force {
+                summary.skipped += 1;
+                continue;
+            }
+            match self.convert(&job) {
+                Ok(()) => summary.converted += 1,
+                Err(e) => {
+                    error!("Failed to convert {}: {}", job.input.display(), e);
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        info!(
+            "Project '{}': {} converted, {} skipped, {} failed",
+            self.project.name, summary.converted, summary.skipped, summary.failed
+        );
+        Ok(summary)
+    }
+
+    fn convert(&self, job: &Job) -> Result<()> {
+        if let Some(parent) = job.output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let config = crate::Config {
+            input: Some(job.input.clone()),
+            output: job.output.parent().map(Path::to_path_buf).unwrap_or_default(),
+            format: self.conversion.format.clone(),
+            language: "rust".to_string(),
+            sidecar: self.conversion.sidecar,
+            ..Default::default()
+        };
+        formats::process_rust(&job.input, &job.output, job.format.clone(), &config)?;
+
+        if self.conversion.sidecar {
+            if let Some(encoder) = &self.encoder.lf2 {
+                let path = sidecar_path(&job.output);
+                if path.exists() {
+                    let mut meta = ImageMetadata::load(&path)?;
+                    meta.encoder = Some(encoder.clone());
+                    meta.seed = self.encoder.seed;
+                    meta.save(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove outputs (and sidecars) produced by this project
+    pub fn clean(&self) -> Result<usize> {
+        let mut removed = 0;
+        for job in self.plan()? {
+            for path in [job.output.clone(), sidecar_path(&job.output)] {
+                if path.exists() {
+                    std::fs::remove_file(&path)?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// An output is up to date when it exists and is not older than its input
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(src), Some(dst)) => dst >= src,
+        _ => false,
+    }
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, out)?;
+        } else if path.is_file() {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn run_status_clean_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("game/sprites");
+        std::fs::create_dir_all(&game).unwrap();
+
+        let image = Lf2Image {
+            width: 2,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: 255, g: 255, b: 255 }],
+            pixels: vec![0, 1, 1, 0],
+        };
+        std::fs::write(game.join("A.LF2"), image.to_lf2_bytes_okumura().unwrap()).unwrap();
+        std::fs::write(game.join("notes.txt"), "ignored").unwrap();
+
+        let project_file = dir.path().join(PROJECT_FILE);
+        std::fs::write(&project_file, r#"
+[project]
+name = "test"
+input_dir = "game"
+output_dir = "out"
+
+[conversion]
+format = "png"
+sidecar = true
+"#).unwrap();
+
+        let project = Project::load(&project_file).unwrap();
+        let plan = project.plan().unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].output, dir.path().join("out/sprites/A.png"));
+        assert_eq!(plan[0].state, JobState::Pending);
+
+        let summary = project.run(false).unwrap();
+        assert_eq!((summary.converted, summary.failed), (1, 0));
+        assert_eq!(project.plan().unwrap()[0].state, JobState::Done);
+        assert_eq!(project.run(false).unwrap().skipped, 1);
+
+        assert_eq!(project.clean().unwrap(), 2);
+        assert_eq!(project.plan().unwrap()[0].state, JobState::Pending);
+    }
+
+    #[test]
+    fn layouts_place_outputs() {
+        let (out, root) = (Path::new("out"), Path::new("game"));
+        let input = Path::new("game/sprites/A.LF2");
+        let format = FormatType::ToHeartLf2;
+        assert_eq!(OutputLayout::Mirror.target_dir(out, root, input, &format), Path::new("out/sprites"));
+        assert_eq!(OutputLayout::Flat.target_dir(out, root, input, &format), out);
+        let versioned: OutputLayout = "versioned".parse().unwrap();
+        assert_eq!(
+            versioned.target_dir(out, root, input, &format),
+            out.join(env!("CARGO_PKG_VERSION")).join("lf2/sprites")
+        );
+        assert!("nested".parse::<OutputLayout>().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Conversion provenance in PNG text chunks
+//!
+//! PNG exports carry where they came from: tool version, source file name
+//! and SHA-256, container format and version, and the settings used. ASCII
+//! values go into `tEXt` chunks, anything else (Shift-JIS names decoded to
+//! UTF-8) into uncompressed `iTXt`. Chunks are inserted right after `IHDR`;
+//! the image data is not touched.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use crate::checksum::sha256_hex;
+use crate::formats::{self, FormatType};
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Keyword/text pairs to embed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    pub entries: Vec<(String, String)>,
+}
+
+impl Provenance {
+    /// Provenance of an export converted from `source` (`data` are its bytes)
+    pub fn for_conversion(source: &Path, data: &[u8], format: &FormatType, settings: &serde_json::Value) -> Self {
+        let version = formats::capabilities()
+            .into_iter()
+            .find(|c| &c.format == format)
+            .map(|c| c.versions.join(", "))
+            .unwrap_or_default();
+        let entries = vec![
+            ("Software".to_string(), format!("retro-decode {}", env!("CARGO_PKG_VERSION"))),
+            ("Source".to_string(), source.file_name().unwrap_or_default().to_string_lossy().into_owned()),
+            ("retro-decode:source-sha256".to_string(), sha256_hex(data)),
+            ("retro-decode:format".to_string(), format.to_string()),
+            ("retro-decode:format-version".to_string(), version),
+            ("retro-decode:settings".to_string(), settings.to_string()),
+        ];
+        Self { entries }
+    }
+
+    /// Rewrite the PNG at `path` with the text chunks added
+    pub fn stamp_file(&self, path: &Path, direct: bool) -> Result<()> {
+        let png = std::fs::read(path)?;
+        crate::output::write_bytes(path, direct, &embed(&png, self)?)
+    }
+}
+
+/// `png` with `provenance` inserted after `IHDR`
+pub fn embed(png: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    if png.len() < 33 || &png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        return Err(anyhow!("Not a PNG file"));
+    }
+    // Signature + IHDR (length, type, 13 data bytes, CRC)
+    let ihdr_end = 8 + 4 + 4 + 13 + 4;
+
+    let mut out = Vec::with_capacity(png.len() + 256);
+    out.extend_from_slice(&png[..ihdr_end]);
+    for (keyword, text) in &provenance.entries {
+        if keyword.is_empty() || keyword.len() > 79 || !keyword.is_ascii() {
+            return Err(anyhow!("Invalid PNG text keyword: {:?}", keyword));
+        }
+        let mut data = keyword.as_bytes().to_vec();
+        data.push(0);
+        if text.is_ascii() {
+            data.extend_from_slice(text.as_bytes());
+            write_chunk(&mut out, b"tEXt", &data);
+        } else {
+            // Uncompressed, no language tag, no translated keyword
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            data.extend_from_slice(text.as_bytes());
+            write_chunk(&mut out, b"iTXt", &data);
+        }
+    }
+    out.extend_from_slice(&png[ihdr_end..]);
+    Ok(out)
+}
+
+/// Text chunks (`tEXt` and uncompressed `iTXt`) of a PNG, in file order
+pub fn read_text(png: &[u8]) -> Result<Vec<(String, String)>> {
+    if png.len() < 8 || &png[..8] != PNG_SIGNATURE {
+        return Err(anyhow!("Not a PNG file"));
+    }
+    let mut entries = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= png.len() {
+        let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+        let kind = &png[pos + 4..pos + 8];
+        let data = png.get(pos + 8..pos + 8 + len).ok_or_else(|| anyhow!("Truncated PNG chunk"))?;
+        let split = |d: &[u8]| d.iter().position(|&b| b == 0).map(|i| (d[..i].to_vec(), d[i + 1..].to_vec()));
+        match kind {
+            b"tEXt" => {
+                if let Some((keyword, text)) = split(data) {
+                    // Latin-1
+                    let text = text.iter().map(|&b| b as char).collect();
+                    entries.push((String::from_utf8_lossy(&keyword).into_owned(), text));
+                }
+            }
+            b"iTXt" => {
+                if let Some((keyword, rest)) = split(data) {
+                    // Skip compression flag/method, then language and translated keyword
+                    if rest.len() >= 2 && rest[0] == 0 {
+                        let after_language = split(&rest[2..]).map(|(_, r)| r).unwrap_or_default();
+                        let text = split(&after_language).map(|(_, r)| r).unwrap_or_default();
+                        entries.push((String::from_utf8_lossy(&keyword).into_owned(), String::from_utf8_lossy(&text).into_owned()));
+                    }
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    Ok(entries)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 (ISO 3309), as used by PNG chunks
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_survive_decoding() {
+        let img = image::RgbaImage::from_pixel(3, 2, image::Rgba([1, 2, 3, 255]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageOutputFormat::Png).unwrap();
+
+        let provenance = Provenance::for_conversion(
+            Path::new("dir/志保.LF2"),
+            b"data",
+            &FormatType::ToHeartLf2,
+            &serde_json::json!({ "orientation": "display" }),
+        );
+        let stamped = embed(png.get_ref(), &provenance).unwrap();
+
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(read_text(&stamped).unwrap(), provenance.entries);
+        assert!(provenance.entries.contains(&("retro-decode:format-version".to_string(), "LEAF256".to_string())));
+        let decoded = image::load_from_memory(&stamped).unwrap().to_rgba8();
+        assert_eq!(decoded, img);
+    }
+}
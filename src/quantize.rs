@@ -0,0 +1,206 @@
+//! Colour counting and palette reduction for encode inputs
+//!
+//! Indexed formats hold at most 256 colours (an LF2 header 255, one of
+//! them the transparent index). An input with more colours is rejected with its
+//! actual count unless quantization was asked for (`--auto-quantize`), in
+//! which case a median-cut palette is built, or each colour is mapped to
+//! the nearest entry of a fixed palette.
+//!
+//! Those formats also have a single transparent index, so soft alpha edges
+//! are hardened first: pixels below `--alpha-threshold` become transparent
+//! and, with `--defringe`, the semi-transparent pixels that stay are
+//! recoloured from their opaque neighbours so no halo of half-blended
+//! colours is left around the sprite.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+
+/// How RGBA input is turned into palette indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizeOptions {
+    /// Map colours that do not fit instead of failing (`--auto-quantize`)
+    pub auto_quantize: bool,
+    /// Pixels with alpha below this become transparent (`--alpha-threshold`);
+    /// the default 1 only treats alpha 0 as transparent
+    pub alpha_threshold: u8,
+    /// Recolour kept semi-transparent pixels from opaque neighbours
+    /// (`--defringe`)
+    pub defringe: bool,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self { auto_quantize: false, alpha_threshold: 1, defringe: false }
+    }
+}
+
+impl QuantizeOptions {
+    /// `rgba` (rows of `width` pixels) with every alpha either 0 or 255
+    pub fn harden_alpha<'a>(&self, rgba: &'a [u8], width: usize) -> Cow<'a, [u8]> {
+        if self.alpha_threshold == 1 && !self.defringe {
+            return Cow::Borrowed(rgba);
+        }
+        let mut out = rgba.to_vec();
+        let height = (rgba.len() / 4).checked_div(width).unwrap_or(0);
+        for (i, px) in out.chunks_exact_mut(4).enumerate() {
+            if px[3] < self.alpha_threshold {
+                px[3] = 0;
+                continue;
+            }
+            if self.defringe && px[3] < 255 {
+                if let Some(color) = opaque_neighbour_color(rgba, width, height, i) {
+                    px[..3].copy_from_slice(&color);
+                }
+            }
+            px[3] = 255;
+        }
+        Cow::Owned(out)
+    }
+}
+
+/// Most common colour among the fully opaque 8-neighbours of pixel `i`
+/// (first in scan order on a tie)
+fn opaque_neighbour_color(rgba: &[u8], width: usize, height: usize, i: usize) -> Option<[u8; 3]> {
+    let (x, y) = (i % width, i / width);
+    let neighbours = (y.saturating_sub(1)..(y + 2).min(height))
+        .flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| ny * width + nx))
+        .filter(|&n| n != i && rgba[n * 4 + 3] == 255)
+        .map(|n| [rgba[n * 4], rgba[n * 4 + 1], rgba[n * 4 + 2]]);
+    let histogram = color_histogram(neighbours);
+    let best = histogram.iter().map(|&(_, n)| n).max()?;
+    histogram.iter().find(|&&(_, n)| n == best).map(|&(c, _)| c)
+}
+
+/// Distinct colours in order of first appearance, with pixel counts
+pub fn color_histogram(colors: impl IntoIterator<Item = [u8; 3]>) -> Vec<([u8; 3], u32)> {
+    let mut index: HashMap<[u8; 3], usize> = HashMap::new();
+    let mut histogram: Vec<([u8; 3], u32)> = Vec::new();
+    for color in colors {
+        let i = *index.entry(color).or_insert_with(|| {
+            histogram.push((color, 0));
+            histogram.len() - 1
+        });
+        histogram[i].1 += 1;
+    }
+    histogram
+}
+
+/// The error for an input with more colours than the target holds
+pub fn too_many_colors(unique: usize, max_colors: usize, target: &str) -> anyhow::Error {
+    anyhow!(
+        "Image has {} unique colours, but {} holds at most {}; reduce the palette \
+         in an editor or pass --auto-quantize",
+        unique, target, max_colors
+    )
+}
+
+/// Palette of at most `max_colors` entries for `histogram` by median cut
+///
+/// Returns the colours unchanged when they already fit. Otherwise the box
+/// with the widest channel range is split at its pixel-weighted median until
+/// there are `max_colors` boxes, and each box becomes its weighted mean.
+pub fn median_cut(histogram: &[([u8; 3], u32)], max_colors: usize) -> Vec<[u8; 3]> {
+    if histogram.len() <= max_colors {
+        return histogram.iter().map(|&(color, _)| color).collect();
+    }
+    let range = |colors: &[([u8; 3], u32)], channel: usize| {
+        let (min, max) = colors.iter().fold((255, 0), |(lo, hi), (c, _)| (c[channel].min(lo), c[channel].max(hi)));
+        max - min
+    };
+    // Ties go to the earlier channel (R, then G, then B)
+    let widest = |colors: &[([u8; 3], u32)]| (0..3).rev().max_by_key(|&ch| range(colors, ch)).unwrap_or(0);
+
+    let mut boxes = vec![histogram.to_vec()];
+    while boxes.len() < max_colors.max(1) {
+        let Some((i, _)) = boxes.iter().enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .max_by_key(|(_, b)| range(b, widest(b)))
+        else {
+            break;
+        };
+        let mut colors = boxes.swap_remove(i);
+        let channel = widest(&colors);
+        colors.sort_by_key(|(c, _)| (c[channel], *c));
+        let half = colors.iter().map(|&(_, n)| n as u64).sum::<u64>() / 2;
+        let mut seen = 0;
+        let split = colors.iter()
+            .position(|&(_, n)| {
+                seen += n as u64;
+                seen > half
+            })
+            .unwrap_or(0)
+            .clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    let mut palette: Vec<[u8; 3]> = boxes.iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum::<u64>().max(1);
+            let mean = |ch: usize| (colors.iter().map(|&(c, n)| c[ch] as u64 * n as u64).sum::<u64>() / total) as u8;
+            [mean(0), mean(1), mean(2)]
+        })
+        .collect();
+    palette.sort();
+    palette
+}
+
+/// Index of the entry of `palette` closest to `color` (squared RGB
+/// distance), skipping `exclude`; 0 for an empty palette
+pub fn nearest(palette: &[[u8; 3]], color: [u8; 3], exclude: Option<usize>) -> usize {
+    palette.iter()
+        .enumerate()
+        .filter(|&(i, _)| Some(i) != exclude)
+        .min_by_key(|(_, p)| {
+            (0..3).map(|ch| (p[ch] as i32 - color[ch] as i32).pow(2) as u32).sum::<u32>()
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+/// Check that `histogram` fits in `max_colors`, or quantize it when
+/// `auto_quantize` is set. Returns the palette to map pixels onto.
+pub fn fit_palette(histogram: &[([u8; 3], u32)], max_colors: usize, auto_quantize: bool, target: &str) -> Result<Vec<[u8; 3]>> {
+    if histogram.len() > max_colors && !auto_quantize {
+        return Err(too_many_colors(histogram.len(), max_colors, target));
+    }
+    Ok(median_cut(histogram, max_colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_cut_keeps_fitting_palettes_and_reduces_others() {
+        let pixels = [[0, 0, 0], [255, 0, 0], [0, 0, 0], [250, 0, 0], [0, 0, 255]];
+        let histogram = color_histogram(pixels);
+        assert_eq!(histogram, [([0, 0, 0], 2), ([255, 0, 0], 1), ([250, 0, 0], 1), ([0, 0, 255], 1)]);
+        assert_eq!(median_cut(&histogram, 4).len(), 4);
+
+        let reduced = median_cut(&histogram, 3);
+        assert_eq!(reduced.len(), 3);
+        // The two reds merge; black and blue survive
+        assert!(reduced.contains(&[0, 0, 0]) && reduced.contains(&[0, 0, 255]));
+        assert_eq!(reduced[nearest(&reduced, [255, 0, 0], None)], [252, 0, 0]);
+        assert_ne!(nearest(&reduced, [0, 0, 0], Some(0)), 0);
+
+        let error = fit_palette(&histogram, 3, false, "LF2").unwrap_err().to_string();
+        assert!(error.contains("4 unique colours") && error.contains("--auto-quantize"), "{}", error);
+        assert_eq!(fit_palette(&histogram, 3, true, "LF2").unwrap(), reduced);
+    }
+
+    #[test]
+    fn hardens_soft_alpha_edges() {
+        // 3x1 sprite: solid red, a half-transparent grey halo, a faint speck
+        let rgba = [200, 0, 0, 255, 120, 120, 120, 128, 90, 90, 90, 20];
+        assert_eq!(QuantizeOptions::default().harden_alpha(&rgba, 3), Cow::Borrowed(&rgba[..]));
+
+        let threshold = QuantizeOptions { alpha_threshold: 64, ..Default::default() };
+        assert_eq!(threshold.harden_alpha(&rgba, 3).as_ref(), [200, 0, 0, 255, 120, 120, 120, 255, 90, 90, 90, 0]);
+
+        let defringe = QuantizeOptions { defringe: true, ..threshold };
+        assert_eq!(defringe.harden_alpha(&rgba, 3).as_ref(), [200, 0, 0, 255, 200, 0, 0, 255, 90, 90, 90, 0]);
+    }
+}
@@ -0,0 +1,358 @@
+//! Format-hypothesis REPL
+//!
+//! A small command language for iterating on LZSS hypotheses against an
+//! unknown file: load it, tweak [`LzssSpec`] fields, decode, look at the
+//! result. [`Session::execute`] runs one command and returns its output, so
+//! the same session drives both `retro-decode repl` and tests. Parameter
+//! edits can be undone and redone, and `repl --state FILE` keeps the
+//! parameters and their history between runs.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::history::History;
+use crate::lzss::{BitOrder, LzssOutput, LzssSpec, ReferenceLayout};
+use crate::probe::{self, ProbeOptions};
+
+/// Output cap when no `set output` was given
+const DEFAULT_MAX_OUTPUT: usize = 4 << 20;
+
+pub const HELP: &str = "\
+load FILE              read FILE as the input
+set window N           ring buffer size (power of two)
+set init N             ring buffer fill byte
+set start N            first ring buffer write position
+set min N              minimum match length (length bias)
+set xor N              XOR key applied to every stream byte
+set literal 0|1        flag bit value that marks a literal
+set order msb|lsb      flag bit order
+set layout length|position
+                       reference packing (LF2 / Okumura)
+set offset N           where the compressed stream starts
+set output N           stop after N output bytes (0 = until input ends)
+preset lf2|okumura     load a known parameter set
+decode                 decompress with the current parameters
+show spec|stats|hex [N]
+probe [N]              rank common parameterizations at the current offset
+save FILE              write the last decoded output
+undo / redo            step back or forward through parameter changes
+help                   this text
+quit                   leave the REPL
+Numbers accept 0x prefixes.";
+
+/// State carried between REPL commands
+pub struct Session {
+    pub input: Option<(PathBuf, Vec<u8>)>,
+    pub spec: LzssSpec,
+    pub offset: usize,
+    /// Output limit in bytes; 0 decodes until the input ends
+    pub max_output: usize,
+    pub last: Option<LzssOutput>,
+    /// Earlier and undone [`Parameters`]
+    pub history: History<Parameters>,
+    /// `save` and [`save_state`](Self::save_state) write in place instead of
+    /// temp file + rename
+    pub direct_writes: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self { input: None, spec: LzssSpec::LF2, offset: 0, max_output: 0, last: None, history: History::default(), direct_writes: false }
+    }
+}
+
+/// The settings `set` and `preset` edit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Parameters {
+    pub spec: LzssSpec,
+    pub offset: usize,
+    pub max_output: usize,
+}
+
+/// What `repl --state` saves
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    parameters: Parameters,
+    history: History<Parameters>,
+}
+
+/// What the caller should do after a command
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Continue(String),
+    Quit,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        Parameters { spec: self.spec, offset: self.offset, max_output: self.max_output }
+    }
+
+    fn restore(&mut self, parameters: Parameters) {
+        self.spec = parameters.spec;
+        self.offset = parameters.offset;
+        self.max_output = parameters.max_output;
+    }
+
+    /// Restore the parameters and history saved by [`save_state`](Self::save_state)
+    pub fn load_state(&mut self, path: &Path) -> Result<()> {
+        let saved: SavedState = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        self.restore(saved.parameters);
+        self.history = saved.history;
+        Ok(())
+    }
+
+    pub fn save_state(&self, path: &Path) -> Result<()> {
+        let saved = SavedState { parameters: self.parameters(), history: self.history.clone() };
+        crate::output::write_bytes(path, self.direct_writes, &serde_json::to_vec_pretty(&saved)?)
+    }
+
+    /// Run one command line
+    pub fn execute(&mut self, line: &str) -> Result<Outcome> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let before = self.parameters();
+        let text = match words.as_slice() {
+            [] => String::new(),
+            ["quit"] | ["exit"] => return Ok(Outcome::Quit),
+            ["help"] => HELP.to_string(),
+            ["undo"] | ["redo"] => {
+                let mut parameters = before;
+                let moved = if words[0] == "undo" {
+                    self.history.undo(&mut parameters)
+                } else {
+                    self.history.redo(&mut parameters)
+                };
+                if !moved {
+                    return Err(anyhow!("Nothing to {}", words[0]));
+                }
+                self.restore(parameters);
+                return Ok(Outcome::Continue(self.show_spec()));
+            }
+            ["load", path] => self.load(Path::new(path))?,
+            ["set", key, value] => self.set(key, value)?,
+            ["preset", name] => {
+                self.spec = match *name {
+                    "lf2" => LzssSpec::LF2,
+                    "okumura" => LzssSpec::OKUMURA,
+                    _ => return Err(anyhow!("Unknown preset: {}", name)),
+                };
+                self.show_spec()
+            }
+            ["decode"] => self.decode()?,
+            ["show", "spec"] => self.show_spec(),
+            ["show", "stats"] => self.show_stats()?,
+            ["show", "hex"] => self.show_hex(256)?,
+            ["show", "hex", n] => self.show_hex(parse_number(n)?)?,
+            ["probe"] => self.probe(5)?,
+            ["probe", n] => self.probe(parse_number(n)?)?,
+            ["save", path] => {
+                let last = self.last.as_ref().ok_or_else(|| anyhow!("Nothing decoded yet"))?;
+                crate::output::write_bytes(Path::new(path), self.direct_writes, &last.data)?;
+                format!("Wrote {} bytes to {}", last.data.len(), path)
+            }
+            _ => return Err(anyhow!("Unknown command: {} (try `help`)", line.trim())),
+        };
+        if self.parameters() != before {
+            self.history.record(before);
+        }
+        Ok(Outcome::Continue(text))
+    }
+
+    fn data(&self) -> Result<&[u8]> {
+        let (_, data) = self.input.as_ref().ok_or_else(|| anyhow!("No file loaded (use `load FILE`)"))?;
+        data.get(self.offset..)
+            .ok_or_else(|| anyhow!("Offset {:#x} is past the end of the file ({} bytes)", self.offset, data.len()))
+    }
+
+    fn load(&mut self, path: &Path) -> Result<String> {
+        let data = std::fs::read(path)?;
+        let text = format!("Loaded {} ({} bytes)", path.display(), data.len());
+        self.input = Some((path.to_path_buf(), data));
+        self.last = None;
+        Ok(text)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<String> {
+        match key {
+            "window" => {
+                let size = parse_number(value)?;
+                if !size.is_power_of_two() || size > 0x1000 {
+                    return Err(anyhow!("Window must be a power of two up to 0x1000"));
+                }
+                self.spec.window_size = size;
+            }
+            "init" => self.spec.initial_fill = parse_byte(value)?,
+            "start" => self.spec.initial_position = parse_number(value)?,
+            "min" => {
+                self.spec.min_match = parse_number(value)?;
+                self.spec.max_match = self.spec.min_match + 15;
+            }
+            "xor" => self.spec.xor_key = parse_byte(value)?,
+            "literal" => self.spec.literal_flag = match value {
+                "0" => false,
+                "1" => true,
+                _ => return Err(anyhow!("literal takes 0 or 1")),
+            },
+            "order" => self.spec.flag_order = match value {
+                "msb" => BitOrder::MsbFirst,
+                "lsb" => BitOrder::LsbFirst,
+                _ => return Err(anyhow!("order takes msb or lsb")),
+            },
+            "layout" => self.spec.reference = match value {
+                "length" => ReferenceLayout::LengthFirst,
+                "position" => ReferenceLayout::PositionFirst,
+                _ => return Err(anyhow!("layout takes length or position")),
+            },
+            "offset" => self.offset = parse_number(value)?,
+            "output" => self.max_output = parse_number(value)?,
+            _ => return Err(anyhow!("Unknown setting: {}", key)),
+        }
+        Ok(format!("{} = {}", key, value))
+    }
+
+    fn decode(&mut self) -> Result<String> {
+        let max_output = if self.max_output == 0 { DEFAULT_MAX_OUTPUT } else { self.max_output };
+        let out = self.spec.decompress_stream(self.data()?, max_output);
+        let text = format!(
+            "{} bytes out, {} of {} input bytes consumed{}",
+            out.data.len(), out.consumed, self.data()?.len(),
+            if out.clean_end { "" } else { " (stream truncated)" }
+        );
+        self.last = Some(out);
+        Ok(text)
+    }
+
+    fn show_spec(&self) -> String {
+        let s = &self.spec;
+        format!(
+            "window {:#x}, init {:#04x}, start {:#x}, min {}, xor {:#04x}, literal {}, order {:?}, layout {:?}, offset {:#x}",
+            s.window_size, s.initial_fill, s.initial_position, s.min_match, s.xor_key,
+            s.literal_flag as u8, s.flag_order, s.reference, self.offset
+        )
+    }
+
+    fn show_stats(&self) -> Result<String> {
+        let last = self.last.as_ref().ok_or_else(|| anyhow!("Nothing decoded yet"))?;
+        let input_len = self.data()?.len();
+        let distinct = {
+            let mut seen = [false; 256];
+            last.data.iter().for_each(|&b| seen[b as usize] = true);
+            seen.iter().filter(|&&s| s).count()
+        };
+        let mut text = format!(
+            "output {} bytes, ratio {:.2}, {} literals, {} matches, {} distinct byte values",
+            last.data.len(),
+            last.data.len() as f64 / input_len.max(1) as f64,
+            last.literals, last.matches, distinct
+        );
+        let geometries = probe::plausible_geometries(last.data.len());
+        if !geometries.is_empty() {
+            let dims: Vec<String> = geometries.iter().take(6)
+                .map(|g| format!("{}x{}x{}", g.width, g.height, g.bytes_per_pixel))
+                .collect();
+            text.push_str(&format!("\nplausible geometry: {}", dims.join(", ")));
+        }
+        Ok(text)
+    }
+
+    fn show_hex(&self, len: usize) -> Result<String> {
+        let last = self.last.as_ref().ok_or_else(|| anyhow!("Nothing decoded yet"))?;
+        let lines: Vec<String> = last.data.chunks(16).take((len + 15) / 16).enumerate()
+            .map(|(i, row)| {
+                let bytes: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{:08x}  {}", i * 16, bytes.join(" "))
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn probe(&self, top: usize) -> Result<String> {
+        let (_, data) = self.input.as_ref().ok_or_else(|| anyhow!("No file loaded (use `load FILE`)"))?;
+        let results = probe::probe(data, &ProbeOptions { offsets: vec![self.offset], top });
+        let lines: Vec<String> = results.iter()
+            .map(|r| format!(
+                "score {}: window {:#x} init {:#04x} start {:#x} min {} xor {:#04x} literal {} {:?} {:?} -> {} bytes",
+                r.score, r.spec.window_size, r.spec.initial_fill, r.spec.initial_position, r.spec.min_match,
+                r.spec.xor_key, r.spec.literal_flag as u8, r.spec.flag_order, r.spec.reference, r.output_size
+            ))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| anyhow!("Not a number: {}", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8> {
+    u8::try_from(parse_number(text)?).map_err(|_| anyhow!("Byte value out of range: {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn session_decodes_lf2_stream() {
+        let image = Lf2Image {
+            width: 8,
+            height: 8,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: (0..64).map(|i| (i / 3 % 2) as u8).collect(),
+        };
+        let path = std::env::temp_dir().join(format!("retro-decode-repl-{}.lf2", std::process::id()));
+        std::fs::write(&path, image.to_lf2_bytes_okumura().unwrap()).unwrap();
+
+        let mut session = Session::new();
+        for line in [format!("load {}", path.display()), "set offset 0x1e".into(), "set output 64".into(), "decode".into()] {
+            session.execute(&line).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(session.last.as_ref().unwrap().data.len(), 64);
+        let Outcome::Continue(stats) = session.execute("show stats").unwrap() else { panic!() };
+        assert!(stats.contains("8x8x1"), "{}", stats);
+        assert!(session.execute("set window 1000").is_err());
+        assert_eq!(session.execute("quit").unwrap(), Outcome::Quit);
+    }
+
+    #[test]
+    fn undoes_parameter_edits_across_saved_sessions() {
+        let mut session = Session::new();
+        for line in ["set offset 0x20", "show spec", "set offset 0x20", "preset okumura"] {
+            session.execute(line).unwrap();
+        }
+        // Only the two edits that changed something were recorded
+        assert_eq!(session.history.undo_steps(), 2);
+        session.execute("undo").unwrap();
+        assert_eq!((session.spec, session.offset), (LzssSpec::LF2, 0x20));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repl.json");
+        session.save_state(&path).unwrap();
+        let mut resumed = Session::new();
+        resumed.load_state(&path).unwrap();
+        assert_eq!(resumed.parameters(), session.parameters());
+        resumed.execute("redo").unwrap();
+        assert_eq!(resumed.spec, LzssSpec::OKUMURA);
+        resumed.execute("undo").unwrap();
+        resumed.execute("undo").unwrap();
+        assert_eq!(resumed.offset, 0);
+        assert!(resumed.execute("undo").is_err());
+    }
+}
@@ -0,0 +1,928 @@
+//! Machine-readable records for `--benchmark`, `verify`, `stats` and batch
+//! failures
+//!
+//! `--benchmark --json` and `verify --json` print one JSON object per line
+//! (`--benchmark-format csv` prints benchmark records as CSV rows instead);
+//! `stats` writes a single [`StatsReport`] document and batch runs with
+//! `--report` an [`ErrorReport`]. Each shape has a JSON
+//! Schema, printed by `--dump-schema`, so dashboards can validate what they
+//! ingest instead of scraping the text output. Within a [`SCHEMA_VERSION`]
+//! fields are only added; renaming or removing one bumps the version.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::colors::PaletteWarning;
+use crate::formats::FormatType;
+use crate::formats::candidate_audit::CandidateAudit;
+use crate::formats::reencode::{DiffRegion, DiffReport, Lf2Encoder};
+use crate::perceptual::PixelComparison;
+use crate::progress::FileStatus;
+use crate::timing::{Measurements, TokenStats};
+#[cfg(doc)]
+use crate::stats::StatsReport;
+
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Output whose schema `--dump-schema` can print
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaKind {
+    Benchmark,
+    Verify,
+    Stats,
+    Errors,
+}
+
+impl SchemaKind {
+    pub const ALL: [SchemaKind; 4] = [SchemaKind::Benchmark, SchemaKind::Verify, SchemaKind::Stats, SchemaKind::Errors];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Benchmark => "benchmark",
+            Self::Verify => "verify",
+            Self::Stats => "stats",
+            Self::Errors => "errors",
+        }
+    }
+
+    /// JSON Schema (draft 2020-12) of one record
+    pub fn schema(self) -> Value {
+        let (title, root) = match self {
+            Self::Benchmark => ("retro-decode --benchmark --json record", benchmark_schema()),
+            Self::Verify => ("retro-decode verify --json record", verify_schema()),
+            Self::Stats => ("retro-decode stats report", stats_schema()),
+            Self::Errors => ("retro-decode batch --report document", errors_schema()),
+        };
+        let mut schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": format!("urn:retro-decode:{}:{}", self.name(), SCHEMA_VERSION),
+            "title": title,
+        });
+        schema.as_object_mut().unwrap().extend(root.as_object().unwrap().clone());
+        schema
+    }
+}
+
+impl fmt::Display for SchemaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SchemaKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == s)
+            .ok_or_else(|| anyhow!("unknown schema '{}': expected benchmark, verify, stats or errors", s))
+    }
+}
+
+/// How `--benchmark` prints its records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BenchmarkFormat {
+    /// `key: value` blocks separated by blank lines
+    #[default]
+    Text,
+    /// JSON Lines (schema: `--dump-schema benchmark`)
+    Json,
+    /// A header row, then one row per file; the batch summary goes to the log
+    Csv,
+}
+
+impl FromStr for BenchmarkFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            other => Err(anyhow!("unknown benchmark format '{}': expected text, json or csv", other)),
+        }
+    }
+}
+
+/// Columns of `--benchmark-format csv`
+pub const BENCHMARK_CSV_HEADER: &str = "file,size,width,height,format,decode_time_ms,parse_ms,decompress_ms,write_ms,\
+output_bytes,memory_kb,compression_ratio,transparent_pixels,literals,matches,matched_bytes,error";
+
+impl BenchmarkFormat {
+    /// Print what precedes the records: the CSV header
+    pub fn print_header(self) {
+        if self == Self::Csv {
+            println!("{}", BENCHMARK_CSV_HEADER);
+        }
+    }
+}
+
+/// One file of a `--benchmark` run
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkRecord {
+    pub file: String,
+    /// File size in bytes
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+    /// Reading, parsing and decompressing (`parse_ms + decompress_ms`)
+    pub decode_time_ms: f64,
+    /// Reading the file and its header, and everything else not counted below
+    pub parse_ms: f64,
+    pub decompress_ms: f64,
+    /// Encoding and writing the outputs
+    pub write_ms: f64,
+    /// Bytes of all outputs written
+    pub output_bytes: u64,
+    /// Rough RGBA memory estimate
+    pub memory_kb: u32,
+    /// File size as a percentage of the 24-bit pixel data
+    pub compression_ratio: f64,
+    pub transparent_pixels: usize,
+    /// LZSS tokens decoded (LF2 and SCN)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<TokenStats>,
+}
+
+/// A `--benchmark` file that could not be processed
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkFailure {
+    pub file: String,
+    pub error: String,
+}
+
+/// Last record of a `--benchmark` batch run
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkSummary {
+    /// Worker threads the batch actually ran on
+    pub threads: usize,
+    pub files: usize,
+    pub elapsed_ms: f64,
+    pub files_per_second: f64,
+    pub per_thread: Vec<ThreadThroughput>,
+}
+
+/// Work done by one batch worker
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadThroughput {
+    pub thread: usize,
+    pub files: usize,
+    /// Time spent on files, excluding waits for the next one
+    pub busy_ms: f64,
+    /// `files` over `busy_ms`
+    pub files_per_second: f64,
+}
+
+/// Files per second, 0 for no time
+pub fn files_per_second(files: usize, ms: f64) -> f64 {
+    if ms > 0.0 { files as f64 * 1000.0 / ms } else { 0.0 }
+}
+
+impl BenchmarkRecord {
+    /// Record of `path`, converted as `format_type` while `measured` was
+    /// taken. Conversions that report no image (other engines, archives
+    /// without images) take the size from the header, without decoding.
+    pub fn from_measurements(path: &Path, format_type: &FormatType, measured: &Measurements) -> Result<Self> {
+        let size = std::fs::metadata(path)?.len();
+        let (width, height, transparent_pixels) = match measured.image {
+            Some(image) => (image.width, image.height, image.transparent_pixels),
+            None => {
+                let (width, height) = header_dimensions(path).unwrap_or((0, 0));
+                (width, height, 0)
+            }
+        };
+        let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+        let total_pixels = width as u64 * height as u64;
+        Ok(Self {
+            file: path.display().to_string(),
+            size,
+            width,
+            height,
+            format: format_type.to_string().to_lowercase().replace(' ', "_"),
+            decode_time_ms: ms(measured.parse() + measured.decompress),
+            parse_ms: ms(measured.parse()),
+            decompress_ms: ms(measured.decompress),
+            write_ms: ms(measured.write),
+            output_bytes: measured.output_bytes,
+            memory_kb: (total_pixels * 4 / 1024) as u32,
+            compression_ratio: if total_pixels == 0 { 0.0 } else { size as f64 / (total_pixels * 3) as f64 * 100.0 },
+            transparent_pixels,
+            tokens: measured.tokens,
+        })
+    }
+
+    /// The `key: value` block of the text output
+    pub fn print_text(&self) {
+        // Batch workers print concurrently; keep the block together
+        let _stdout = std::io::stdout().lock();
+        println!("file: {}", self.file);
+        println!("size: {}", self.size);
+        println!("width: {}", self.width);
+        println!("height: {}", self.height);
+        println!("format: {}", self.format);
+        println!("decode_time_ms: {:.2}", self.decode_time_ms);
+        println!("parse_ms: {:.2}", self.parse_ms);
+        println!("decompress_ms: {:.2}", self.decompress_ms);
+        println!("write_ms: {:.2}", self.write_ms);
+        println!("output_bytes: {}", self.output_bytes);
+        println!("memory_kb: {}", self.memory_kb);
+        println!("compression_ratio: {:.1}", self.compression_ratio);
+        println!("transparent_pixels: {}", self.transparent_pixels);
+        if let Some(tokens) = &self.tokens {
+            println!("literals: {}", tokens.literals);
+            println!("matches: {}", tokens.matches);
+            println!("matched_bytes: {}", tokens.matched_bytes);
+        }
+        println!();
+    }
+
+    /// One row under [`BENCHMARK_CSV_HEADER`]
+    pub fn csv_row(&self) -> String {
+        let tokens = self.tokens.map_or(String::from(",,"), |t| format!("{},{},{}", t.literals, t.matches, t.matched_bytes));
+        format!(
+            "{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{},{:.1},{},{},",
+            csv_field(&self.file), self.size, self.width, self.height, self.format,
+            self.decode_time_ms, self.parse_ms, self.decompress_ms, self.write_ms,
+            self.output_bytes, self.memory_kb, self.compression_ratio, self.transparent_pixels, tokens
+        )
+    }
+
+    /// Print in `format`
+    pub fn print(&self, format: BenchmarkFormat) -> Result<()> {
+        match format {
+            BenchmarkFormat::Text => self.print_text(),
+            BenchmarkFormat::Json => print_json_line(self)?,
+            BenchmarkFormat::Csv => println!("{}", self.csv_row()),
+        }
+        Ok(())
+    }
+}
+
+/// Width and height fields of the header of `path`
+fn header_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let report = crate::formats::header::inspect(path).ok()?;
+    let field = |name: &str| report.fields.iter().find(|f| f.name == name)?.value.as_u64();
+    Some((field("width")? as u32, field("height")? as u32))
+}
+
+/// `text` quoted when it holds a comma, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+impl BenchmarkFailure {
+    pub fn print_text(&self) {
+        let _stdout = std::io::stdout().lock();
+        println!("file: {}", self.file);
+        println!("error: {}", self.error);
+        println!();
+    }
+
+    /// Print in `format`; CSV rows leave the measurements empty
+    pub fn print(&self, format: BenchmarkFormat) -> Result<()> {
+        match format {
+            BenchmarkFormat::Text => self.print_text(),
+            BenchmarkFormat::Json => print_json_line(self)?,
+            BenchmarkFormat::Csv => println!("{}{}{}", csv_field(&self.file), ",".repeat(16), csv_field(&self.error)),
+        }
+        Ok(())
+    }
+}
+
+impl BenchmarkSummary {
+    pub fn print_text(&self) {
+        println!("threads: {}", self.threads);
+        println!("files: {}", self.files);
+        println!("elapsed_ms: {:.2}", self.elapsed_ms);
+        println!("files_per_second: {:.2}", self.files_per_second);
+        for worker in &self.per_thread {
+            println!(
+                "thread_{}: {} files, {:.2} ms busy, {:.2} files/s",
+                worker.thread, worker.files, worker.busy_ms, worker.files_per_second
+            );
+        }
+        println!();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyStatus {
+    Identical,
+    Differs,
+    /// Pixels differ but stay within the perceptual thresholds
+    /// (`--against` with `--perceptual`)
+    Similar,
+    /// The file could not be read, decoded or re-encoded
+    Error,
+}
+
+impl VerifyStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Identical => "identical",
+            Self::Differs => "differs",
+            Self::Similar => "similar",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// One file of a `verify` run
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyRecord {
+    pub file: String,
+    /// Encoder the file was re-encoded with; absent for `--against`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    /// Seed of a randomized encoder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub status: VerifyStatus,
+    /// First differing byte offset (`differs` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_diff: Option<usize>,
+    /// Offsets whose bytes differ, including bytes past the shorter file
+    /// (`differs` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub differing_bytes: Option<usize>,
+    /// Re-encoded size minus original size in bytes (re-encoded files only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_delta: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Mismatch localization, with `--regions`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DiffSummary>,
+    /// Candidate audit, with `--audit-candidates`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit: Option<CandidateAudit>,
+    /// Pixel comparison with the counterpart file, with `--against`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixels: Option<PixelComparison>,
+    /// Palette / transparent index inconsistencies of the source
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<PaletteWarning>,
+}
+
+impl VerifyRecord {
+    pub fn new(path: &Path, encoder: Option<Lf2Encoder>, status: VerifyStatus) -> Self {
+        Self {
+            file: path.display().to_string(),
+            encoder: encoder.map(|e| e.name().to_string()),
+            seed: encoder.and_then(|e| e.seed()),
+            status,
+            first_diff: None,
+            differing_bytes: None,
+            size_delta: None,
+            error: None,
+            diff: None,
+            audit: None,
+            pixels: None,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// [`DiffReport`] without the pixel mask
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffSummary {
+    pub header_bytes: usize,
+    pub tokens: usize,
+    pub mismatched_tokens: usize,
+    pub regions: Vec<DiffRegion>,
+}
+
+impl From<&DiffReport> for DiffSummary {
+    fn from(report: &DiffReport) -> Self {
+        Self {
+            header_bytes: report.header_bytes,
+            tokens: report.tokens,
+            mismatched_tokens: report.mismatched_tokens,
+            regions: report.regions.clone(),
+        }
+    }
+}
+
+/// What kind of problem stopped a batch input, for sorting failures without
+/// parsing messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    /// The extension names no supported format
+    UnsupportedFormat,
+    /// A supported format without an image decoder
+    NotAnImage,
+    Invalid,
+    BadDimensions,
+    Truncated,
+    UnsupportedVersion,
+    LimitExceeded,
+    /// Reading the input or writing the output failed
+    Io,
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn of(status: FileStatus, error: &anyhow::Error) -> Self {
+        use crate::decoder::Error;
+
+        if status == FileStatus::Unsupported {
+            return Self::UnsupportedFormat;
+        }
+        match crate::decoder::find_error(error) {
+            Some(Error::Unsupported(_)) => Self::NotAnImage,
+            Some(Error::Invalid { .. }) => Self::Invalid,
+            Some(Error::BadDimensions { .. }) => Self::BadDimensions,
+            Some(Error::Truncated { .. }) => Self::Truncated,
+            Some(Error::UnsupportedVersion { .. }) => Self::UnsupportedVersion,
+            Some(Error::LimitExceeded { .. }) => Self::LimitExceeded,
+            None if error.chain().any(|cause| cause.is::<std::io::Error>()) => Self::Io,
+            None => Self::Other,
+        }
+    }
+}
+
+/// One input a batch run could not convert
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedFile {
+    pub file: String,
+    pub status: FileStatus,
+    pub category: ErrorCategory,
+    pub error: String,
+    /// Remediation hint, in English
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+/// `--report` document of a batch run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorReport {
+    pub total: usize,
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Inputs never started because `--fail-fast` stopped the run
+    pub not_attempted: usize,
+    pub failures: Vec<FailedFile>,
+}
+
+impl ErrorReport {
+    pub fn write(&self, path: &Path, direct: bool) -> Result<()> {
+        crate::output::write_bytes(path, direct, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+/// Print `record` as one line of JSON
+pub fn print_json_line<T: Serialize>(record: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(record)?);
+    Ok(())
+}
+
+fn count() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn counts() -> Value {
+    json!({ "type": "array", "items": count() })
+}
+
+/// Inclusive `[first, last]` pair
+fn span() -> Value {
+    json!({ "type": "array", "items": count(), "minItems": 2, "maxItems": 2 })
+}
+
+fn benchmark_schema() -> Value {
+    json!({
+        "oneOf": [{ "$ref": "#/$defs/record" }, { "$ref": "#/$defs/failure" }, { "$ref": "#/$defs/summary" }],
+        "$defs": {
+            "record": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["file", "size", "width", "height", "format", "decode_time_ms", "parse_ms", "decompress_ms", "write_ms",
+                    "output_bytes", "memory_kb", "compression_ratio", "transparent_pixels"],
+                "properties": {
+                    "file": { "type": "string" },
+                    "size": count(),
+                    "width": count(),
+                    "height": count(),
+                    "format": { "type": "string" },
+                    "decode_time_ms": { "type": "number", "minimum": 0 },
+                    "parse_ms": { "type": "number", "minimum": 0 },
+                    "decompress_ms": { "type": "number", "minimum": 0 },
+                    "write_ms": { "type": "number", "minimum": 0 },
+                    "output_bytes": count(),
+                    "memory_kb": count(),
+                    "compression_ratio": { "type": "number", "description": "File size as a percentage of the 24-bit pixel data" },
+                    "transparent_pixels": count(),
+                    "tokens": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["literals", "matches", "matched_bytes"],
+                        "properties": {
+                            "literals": count(),
+                            "matches": count(),
+                            "matched_bytes": count(),
+                        },
+                    },
+                },
+            },
+            "failure": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["file", "error"],
+                "properties": {
+                    "file": { "type": "string" },
+                    "error": { "type": "string" },
+                },
+            },
+            "summary": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["threads", "files", "elapsed_ms", "files_per_second", "per_thread"],
+                "properties": {
+                    "threads": count(),
+                    "files": count(),
+                    "elapsed_ms": { "type": "number", "minimum": 0 },
+                    "files_per_second": { "type": "number", "minimum": 0 },
+                    "per_thread": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "additionalProperties": false,
+                            "required": ["thread", "files", "busy_ms", "files_per_second"],
+                            "properties": {
+                                "thread": count(),
+                                "files": count(),
+                                "busy_ms": { "type": "number", "minimum": 0 },
+                                "files_per_second": { "type": "number", "minimum": 0 },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn verify_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["file", "status"],
+        "properties": {
+            "file": { "type": "string" },
+            "encoder": { "type": "string" },
+            "seed": count(),
+            "status": { "enum": ["identical", "differs", "similar", "error"] },
+            "first_diff": count(),
+            "differing_bytes": count(),
+            "size_delta": { "type": "integer", "description": "Re-encoded size minus original size in bytes" },
+            "error": { "type": "string" },
+            "diff": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["header_bytes", "tokens", "mismatched_tokens", "regions"],
+                "properties": {
+                    "header_bytes": count(),
+                    "tokens": count(),
+                    "mismatched_tokens": count(),
+                    "regions": { "type": "array", "items": { "$ref": "#/$defs/region" } },
+                },
+            },
+            "audit": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["matches", "reproduced", "outranked", "unaligned", "absent"],
+                "properties": {
+                    "matches": count(),
+                    "reproduced": count(),
+                    "outranked": count(),
+                    "unaligned": count(),
+                    "absent": { "type": "array", "items": { "$ref": "#/$defs/absent_match" } },
+                },
+            },
+            "pixels": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["against", "differing_pixels"],
+                "properties": {
+                    "against": { "type": "string" },
+                    "differing_pixels": count(),
+                    "ssim": { "type": "number", "maximum": 1 },
+                    "mean_delta_e": { "type": "number", "minimum": 0, "description": "CIE76" },
+                    "max_delta_e": { "type": "number", "minimum": 0 },
+                },
+            },
+            "warnings": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["code", "indices", "pixels", "message"],
+                    "properties": {
+                        "code": { "enum": ["transparent-out-of-range", "index-out-of-range", "transparent-color-shared", "transparent-unused"] },
+                        "indices": counts(),
+                        "pixels": count(),
+                        "message": { "type": "string" },
+                    },
+                },
+            },
+        },
+        "$defs": {
+            "region": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["rows", "columns", "tokens", "flag_blocks", "at_row_end"],
+                "properties": {
+                    "rows": span(),
+                    "columns": span(),
+                    "tokens": count(),
+                    "flag_blocks": count(),
+                    "at_row_end": count(),
+                },
+            },
+            "absent_match": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["output", "distance", "len", "reason"],
+                "properties": {
+                    "output": count(),
+                    "distance": count(),
+                    "len": count(),
+                    "reason": {
+                        "oneOf": [
+                            { "const": "outside-search-space" },
+                            {
+                                "type": "object",
+                                "additionalProperties": false,
+                                "required": ["shorter-than-available"],
+                                "properties": {
+                                    "shorter-than-available": {
+                                        "type": "object",
+                                        "additionalProperties": false,
+                                        "required": ["available"],
+                                        "properties": { "available": count() },
+                                    },
+                                },
+                            },
+                        ],
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn stats_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["files", "byte_histogram", "match_lengths", "match_distances"],
+        "properties": {
+            "files": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["path", "compressed_size", "decompressed_size", "ratio", "literals", "matches"],
+                    "properties": {
+                        "path": { "type": "string" },
+                        "compressed_size": count(),
+                        "decompressed_size": count(),
+                        "ratio": { "type": "number", "description": "Decompressed size over compressed size" },
+                        "literals": count(),
+                        "matches": count(),
+                    },
+                },
+            },
+            "byte_histogram": { "allOf": [counts(), { "minItems": 256, "maxItems": 256 }] },
+            "match_lengths": { "allOf": [counts(), { "description": "Matches per length, indexed by length" }] },
+            "match_distances": { "allOf": [counts(), { "description": "Matches per 64-byte distance bucket" }] },
+        },
+    })
+}
+
+fn errors_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["total", "converted", "skipped", "failed", "not_attempted", "failures"],
+        "properties": {
+            "total": count(),
+            "converted": count(),
+            "skipped": count(),
+            "failed": count(),
+            "not_attempted": count(),
+            "failures": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["file", "status", "category", "error"],
+                    "properties": {
+                        "file": { "type": "string" },
+                        "status": { "enum": ["failed", "unsupported"] },
+                        "category": {
+                            "enum": ["unsupported-format", "not-an-image", "invalid", "bad-dimensions", "truncated",
+                                "unsupported-version", "limit-exceeded", "io", "other"],
+                        },
+                        "error": { "type": "string" },
+                        "hint": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::candidate_audit::{Absence, AbsentMatch};
+    use crate::stats::{StatsOptions, StatsReport};
+
+    /// Check `value` against the subset of JSON Schema used above
+    fn validate(schema: &Value, value: &Value, root: &Value, at: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/$defs/");
+            return validate(&root["$defs"][name], value, root, at);
+        }
+        if let Some(all) = schema["allOf"].as_array() {
+            return all.iter().try_for_each(|s| validate(s, value, root, at));
+        }
+        if let Some(any) = schema["oneOf"].as_array() {
+            let passing = any.iter().filter(|s| validate(s, value, root, at).is_ok()).count();
+            return if passing == 1 { Ok(()) } else { Err(format!("{}: {} oneOf branches match", at, passing)) };
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            return if options.contains(value) { Ok(()) } else { Err(format!("{}: {} not in enum", at, value)) };
+        }
+        if !schema["const"].is_null() && schema["const"] != *value {
+            return Err(format!("{}: expected {}", at, schema["const"]));
+        }
+        let type_ok = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_u64() || value.is_i64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            _ => true,
+        };
+        if !type_ok {
+            return Err(format!("{}: {} is not {}", at, value, schema["type"]));
+        }
+        if let Some(object) = value.as_object() {
+            for key in schema["required"].as_array().into_iter().flatten() {
+                if !object.contains_key(key.as_str().unwrap()) {
+                    return Err(format!("{}: missing {}", at, key));
+                }
+            }
+            for (key, field) in object {
+                match schema["properties"].get(key) {
+                    Some(property) => validate(property, field, root, &format!("{}.{}", at, key))?,
+                    None if schema["additionalProperties"] == false => return Err(format!("{}: unexpected {}", at, key)),
+                    None => {}
+                }
+            }
+        }
+        if let Some(items) = value.as_array() {
+            let len = items.len() as u64;
+            if schema["minItems"].as_u64().is_some_and(|min| len < min) || schema["maxItems"].as_u64().is_some_and(|max| len > max) {
+                return Err(format!("{}: {} items", at, len));
+            }
+            if !schema["items"].is_null() {
+                for (i, item) in items.iter().enumerate() {
+                    validate(&schema["items"], item, root, &format!("{}[{}]", at, i))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_valid<T: Serialize>(kind: SchemaKind, record: &T) {
+        let schema = kind.schema();
+        let value = serde_json::to_value(record).unwrap();
+        if let Err(e) = validate(&schema, &value, &schema, kind.name()) {
+            panic!("{} does not match its schema: {}", value, e);
+        }
+    }
+
+    #[test]
+    fn records_match_their_schemas() {
+        let mut benchmark = BenchmarkRecord {
+            file: "C0101.LF2".to_string(),
+            size: 1234,
+            width: 640,
+            height: 480,
+            format: "toheart_lf2".to_string(),
+            decode_time_ms: 1.5,
+            parse_ms: 0.5,
+            decompress_ms: 1.0,
+            write_ms: 2.0,
+            output_bytes: 921654,
+            memory_kb: 1200,
+            compression_ratio: 12.5,
+            transparent_pixels: 10,
+            tokens: None,
+        };
+        assert_valid(SchemaKind::Benchmark, &benchmark);
+        benchmark.tokens = Some(TokenStats { literals: 100, matches: 20, matched_bytes: 300 });
+        assert_valid(SchemaKind::Benchmark, &benchmark);
+        assert_valid(SchemaKind::Benchmark, &BenchmarkFailure { file: "x.pdt".to_string(), error: "bad".to_string() });
+        assert_valid(SchemaKind::Benchmark, &BenchmarkSummary {
+            threads: 2,
+            files: 3,
+            elapsed_ms: 10.0,
+            files_per_second: files_per_second(3, 10.0),
+            per_thread: vec![
+                ThreadThroughput { thread: 0, files: 2, busy_ms: 8.0, files_per_second: files_per_second(2, 8.0) },
+                ThreadThroughput { thread: 1, files: 1, busy_ms: 0.0, files_per_second: files_per_second(1, 0.0) },
+            ],
+        });
+
+        let mut verify = VerifyRecord::new(Path::new("C0101.LF2"), Some(Lf2Encoder::Okumura), VerifyStatus::Differs);
+        verify.first_diff = Some(0x20);
+        verify.differing_bytes = Some(12);
+        verify.size_delta = Some(-3);
+        verify.diff = Some(DiffSummary {
+            header_bytes: 0,
+            tokens: 9,
+            mismatched_tokens: 1,
+            regions: vec![DiffRegion { rows: (0, 1), columns: (2, 3), tokens: 1, flag_blocks: 1, at_row_end: 0 }],
+        });
+        verify.audit = Some(CandidateAudit {
+            matches: 3,
+            reproduced: 1,
+            outranked: 0,
+            unaligned: 0,
+            absent: vec![
+                AbsentMatch { output: 4, distance: 4000, len: 3, reason: Absence::OutsideSearchSpace },
+                AbsentMatch { output: 8, distance: 8, len: 3, reason: Absence::ShorterThanAvailable { available: 5 } },
+            ],
+        });
+        assert_valid(SchemaKind::Verify, &verify);
+        verify.warnings = crate::colors::lint(&[[0, 0, 0]], &[0, 3], 0);
+        assert_valid(SchemaKind::Verify, &verify);
+        let mut failed = VerifyRecord::new(Path::new("bad.LF2"), Some(Lf2Encoder::Okumura), VerifyStatus::Error);
+        failed.error = Some("truncated".to_string());
+        assert_valid(SchemaKind::Verify, &failed);
+        let mut similar = VerifyRecord::new(Path::new("C0101.LF2"), Some(Lf2Encoder::Okumura), VerifyStatus::Similar);
+        similar.encoder = None;
+        similar.pixels = Some(PixelComparison {
+            against: "requantized/C0101.png".to_string(),
+            differing_pixels: 12,
+            ssim: Some(0.995),
+            mean_delta_e: Some(0.4),
+            max_delta_e: Some(3.1),
+        });
+        assert_valid(SchemaKind::Verify, &similar);
+
+        let mut stats = StatsReport::new();
+        stats.add_file(Path::new("a.bin"), &[0xff, b'a', b'b', b'c', b'd'], &StatsOptions::default()).unwrap();
+        assert_valid(SchemaKind::Stats, &stats);
+
+        let truncated = anyhow::Error::new(crate::decoder::Error::Truncated { format: FormatType::KanonPdt, len: 10, needed: 40 });
+        let missing = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::NotFound)).context("Failed to read a.lf2");
+        assert_eq!(ErrorCategory::of(FileStatus::Failed, &truncated), ErrorCategory::Truncated);
+        assert_eq!(ErrorCategory::of(FileStatus::Failed, &missing), ErrorCategory::Io);
+        assert_valid(SchemaKind::Errors, &ErrorReport {
+            total: 3,
+            converted: 1,
+            skipped: 0,
+            failed: 1,
+            not_attempted: 1,
+            failures: vec![FailedFile {
+                file: "a.pdt".to_string(),
+                status: FileStatus::Failed,
+                category: ErrorCategory::Truncated,
+                error: truncated.to_string(),
+                hint: Some("re-dump".to_string()),
+            }],
+        });
+
+        // The validator does reject drift
+        let schema = SchemaKind::Verify.schema();
+        assert!(validate(&schema, &json!({ "file": "a", "encoder": "okumura", "status": "same" }), &schema, "").is_err());
+        assert!(validate(&schema, &json!({ "file": "a", "encoder": "okumura", "status": "identical", "extra": 1 }), &schema, "").is_err());
+    }
+
+    #[test]
+    fn schema_names_round_trip() {
+        for kind in SchemaKind::ALL {
+            assert_eq!(kind.name().parse::<SchemaKind>().unwrap(), kind);
+            assert_eq!(kind.schema()["$id"], format!("urn:retro-decode:{}:{}", kind, SCHEMA_VERSION));
+        }
+        assert!("catalog".parse::<SchemaKind>().is_err());
+    }
+}
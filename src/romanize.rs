@@ -0,0 +1,267 @@
+//! Shift-JIS file name romanization (`--romanize`)
+//!
+//! Archive entry names are raw Shift-JIS. Some filesystems and toolchains
+//! mangle them, so extraction can instead write ASCII-only names: kana become
+//! Hepburn romaji, full-width letters and digits become ASCII, and anything
+//! without a reading (kanji, symbols) becomes `_xxxx` with its Shift-JIS code
+//! in hex. Every renamed entry is listed in a `romanize.json` manifest with
+//! its original bytes, so the mapping can always be undone.
+
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+/// Manifest file written next to the extracted entries
+pub const MANIFEST_FILE: &str = "romanize.json";
+
+/// Hiragana U+3041..=U+3096 (katakana U+30A1..=U+30F6 share the order).
+/// Small kana are picked out by `Kana::from_table`.
+const KANA: [&str; 86] = [
+    "a", "a", "i", "i", "u", "u", "e", "e", "o", "o",
+    "ka", "ga", "ki", "gi", "ku", "gu", "ke", "ge", "ko", "go",
+    "sa", "za", "shi", "ji", "su", "zu", "se", "ze", "so", "zo",
+    "ta", "da", "chi", "ji", "tsu", "tsu", "zu", "te", "de", "to", "do",
+    "na", "ni", "nu", "ne", "no",
+    "ha", "ba", "pa", "hi", "bi", "pi", "fu", "bu", "pu", "he", "be", "pe", "ho", "bo", "po",
+    "ma", "mi", "mu", "me", "mo",
+    "ya", "ya", "yu", "yu", "yo", "yo",
+    "ra", "ri", "ru", "re", "ro",
+    "wa", "wa", "wi", "we", "wo", "n", "vu", "ka", "ke",
+];
+
+/// Half-width katakana 0xA6..=0xDD
+const HALF_WIDTH: [&str; 56] = [
+    "wo", "a", "i", "u", "e", "o", "ya", "yu", "yo", "tsu", "-",
+    "a", "i", "u", "e", "o", "ka", "ki", "ku", "ke", "ko",
+    "sa", "shi", "su", "se", "so", "ta", "chi", "tsu", "te", "to",
+    "na", "ni", "nu", "ne", "no", "ha", "hi", "fu", "he", "ho",
+    "ma", "mi", "mu", "me", "mo", "ya", "yu", "yo",
+    "ra", "ri", "ru", "re", "ro", "wa", "n",
+];
+
+/// One decoded unit of a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kana {
+    Syllable(&'static str),
+    /// Small kana (ぁ, ゃ, ...): modifies the previous syllable
+    Small(&'static str),
+    /// Small tsu: doubles the next consonant
+    Geminate,
+    /// ー
+    Long,
+    /// Half-width voicing marks ﾞ and ﾟ
+    Voiced,
+    SemiVoiced,
+    Ascii(char),
+    /// Double-byte character without a reading
+    Other(u16),
+}
+
+impl Kana {
+    /// Entry of [`KANA`] at `index`
+    fn from_table(index: usize) -> Kana {
+        match index {
+            // っ
+            0x22 => Kana::Geminate,
+            // ぁぃぅぇぉ ゃゅょ ゎ
+            0 | 2 | 4 | 6 | 8 | 0x42 | 0x44 | 0x46 | 0x4d => Kana::Small(KANA[index]),
+            _ => Kana::Syllable(KANA[index]),
+        }
+    }
+}
+
+/// Split Shift-JIS `bytes` into [`Kana`] units
+fn decode(bytes: &[u8]) -> Vec<Kana> {
+    let mut units = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let double = matches!(b, 0x81..=0x9f | 0xe0..=0xfc) && i + 1 < bytes.len();
+        if !double {
+            units.push(match b {
+                0x00..=0x7f => Kana::Ascii(b as char),
+                0xaf => Kana::Geminate,
+                0xa7..=0xae => Kana::Small(HALF_WIDTH[(b - 0xa6) as usize]),
+                0xb0 => Kana::Long,
+                0xa6..=0xdd => Kana::Syllable(HALF_WIDTH[(b - 0xa6) as usize]),
+                0xde => Kana::Voiced,
+                0xdf => Kana::SemiVoiced,
+                _ => Kana::Other(b as u16),
+            });
+            i += 1;
+            continue;
+        }
+        let code = (b as u16) << 8 | bytes[i + 1] as u16;
+        units.push(match code {
+            0x829f..=0x82f1 => Kana::from_table((code - 0x829f) as usize),
+            0x8340..=0x837e => Kana::from_table((code - 0x8340) as usize),
+            0x8380..=0x8396 => Kana::from_table((code - 0x8341) as usize),
+            0x815b => Kana::Long,
+            0x8140 => Kana::Ascii(' '),
+            0x824f..=0x8258 => Kana::Ascii((b'0' + (code - 0x824f) as u8) as char),
+            0x8260..=0x8279 => Kana::Ascii((b'A' + (code - 0x8260) as u8) as char),
+            0x8281..=0x829a => Kana::Ascii((b'a' + (code - 0x8281) as u8) as char),
+            _ => Kana::Other(code),
+        });
+        i += 2;
+    }
+    units
+}
+
+/// ASCII-only, filesystem-safe name for Shift-JIS `bytes`
+pub fn romanize_sjis(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    // Start of the last syllable in `out`, for small kana and voicing marks
+    let mut last = None;
+    let mut geminate = false;
+
+    for unit in decode(bytes) {
+        match unit {
+            Kana::Syllable(s) => {
+                let start = out.len();
+                if geminate && s != "n" && !s.starts_with(['a', 'i', 'u', 'e', 'o']) {
+                    out.push(if s.starts_with("ch") { 't' } else { s.as_bytes()[0] as char });
+                }
+                out.push_str(s);
+                last = Some(start);
+                geminate = false;
+            }
+            Kana::Small(s) => match last {
+                // しゃ -> sha, きゃ -> kya, ふぁ -> fa, ちぇ -> che
+                Some(start) if out.len() - start >= 2 && out.ends_with(['i', 'u']) => {
+                    out.pop();
+                    let stem = &out[start..];
+                    let palatal = stem.ends_with("sh") || stem.ends_with("ch") || stem.ends_with('j');
+                    if s.starts_with('y') && palatal {
+                        out.push_str(&s[1..]);
+                    } else {
+                        out.push_str(s);
+                    }
+                }
+                _ => {
+                    out.push_str(s);
+                    last = None;
+                }
+            },
+            Kana::Geminate => geminate = true,
+            Kana::Long => match out.chars().last() {
+                Some(v) if "aiueo".contains(v) => out.push(v),
+                _ => out.push('-'),
+            },
+            Kana::Voiced | Kana::SemiVoiced => {
+                if let Some(start) = last {
+                    let voiced = voice(&out[start..], unit == Kana::SemiVoiced);
+                    out.truncate(start);
+                    out.push_str(&voiced);
+                }
+            }
+            Kana::Ascii(c) => {
+                let safe = c.is_ascii_graphic() && !"<>:\"/\\|?*".contains(c);
+                out.push(if safe { c } else { '_' });
+                last = None;
+            }
+            Kana::Other(code) => {
+                out.push_str(&format!("_{:x}", code));
+                last = None;
+            }
+        }
+    }
+    if out.is_empty() {
+        out.push('_');
+    }
+    out
+}
+
+/// Apply a half-width voicing mark to a romanized syllable
+fn voice(syllable: &str, semi: bool) -> String {
+    let (head, tail) = syllable.split_at(syllable.len() - 1);
+    let head = match (head, semi) {
+        ("h" | "f", true) => "p",
+        ("h" | "f", false) => "b",
+        ("k", false) => "g",
+        ("s", false) => "z",
+        ("sh" | "ch", false) => "j",
+        ("ts", false) => "z",
+        ("t", false) => "d",
+        ("", false) if tail == "u" => "v",
+        _ => head,
+    };
+    format!("{}{}", head, tail)
+}
+
+/// One renamed entry in the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomanizedName {
+    /// Original Shift-JIS bytes in hex
+    pub original_sjis: String,
+    pub romanized: String,
+}
+
+/// Assigns unique romanized names within one output directory
+#[derive(Debug, Default)]
+pub struct NameMapper {
+    used: HashSet<String>,
+    pub entries: Vec<RomanizedName>,
+}
+
+impl NameMapper {
+    /// Romanized name for `raw`, suffixed `_2`, `_3`, ... if already taken
+    /// (compared case-insensitively for Windows/macOS)
+    pub fn map(&mut self, raw: &[u8]) -> String {
+        let base = romanize_sjis(raw);
+        let (stem, ext) = match base.rfind('.') {
+            Some(dot) if dot > 0 => base.split_at(dot),
+            _ => (base.as_str(), ""),
+        };
+        let mut name = base.clone();
+        let mut n = 2;
+        while !self.used.insert(name.to_ascii_lowercase()) {
+            name = format!("{}_{}{}", stem, n, ext);
+            n += 1;
+        }
+        if name.as_bytes() != raw {
+            self.entries.push(RomanizedName {
+                original_sjis: raw.iter().map(|b| format!("{:02x}", b)).collect(),
+                romanized: name.clone(),
+            });
+        }
+        name
+    }
+
+    /// Write [`MANIFEST_FILE`] into `dir` (skipped if nothing was renamed)
+    pub fn write_manifest(&self, dir: &Path, direct: bool) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        crate::output::write_bytes(&dir.join(MANIFEST_FILE), direct, json.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kana_become_romaji() {
+        // しほ_01.LF2, ちゃっきー, ﾊﾟｯｸ, 立ち絵
+        assert_eq!(romanize_sjis(b"\x82\xb5\x82\xd9_01.LF2"), "shiho_01.LF2");
+        assert_eq!(romanize_sjis(b"\x82\xbf\x82\xe1\x82\xc1\x82\xab\x81\x5b"), "chakkii");
+        assert_eq!(romanize_sjis(b"\xca\xdf\xaf\xb8"), "pakku");
+        assert_eq!(romanize_sjis(b"\x97\xa7\x82\xbf\x8a\x47"), "_97a7chi_8a47");
+        // フィ, キャ (katakana), full-width Ａ１
+        assert_eq!(romanize_sjis(b"\x83\x74\x83\x42\x83\x4c\x83\x83"), "fikya");
+        assert_eq!(romanize_sjis(b"\x82\x60\x82\x50"), "A1");
+    }
+
+    #[test]
+    fn duplicates_get_suffixes() {
+        let mut mapper = NameMapper::default();
+        assert_eq!(mapper.map(b"C0101.LF2"), "C0101.LF2");
+        assert_eq!(mapper.map(b"\x82\xa0.LF2"), "a.LF2");
+        assert_eq!(mapper.map(b"\x83\x41.LF2"), "a_2.LF2");
+        assert_eq!(mapper.entries.len(), 2);
+        assert_eq!(mapper.entries[1].original_sjis, "83412e4c4632");
+    }
+}
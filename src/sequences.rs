@@ -0,0 +1,265 @@
+//! Numbered sprite sequences and their animated previews
+//!
+//! Animation frames are stored as separate files numbered in order
+//! (`C0101`, `C0102`, ...). Batch runs find them by grouping file names that
+//! differ only in a trailing number and splitting each group into runs of
+//! consecutive numbers. With `--sequences gif|apng` every run is assembled
+//! into one animated preview. Frames are placed on a shared canvas at their
+//! header offsets (LF2 `x_offset`/`y_offset`), so sprites drawn at different
+//! positions of the screen stay aligned.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+
+use crate::formats::FormatType;
+use crate::formats::toheart::Lf2Image;
+
+/// Frame delay when none is configured
+pub const DEFAULT_FRAME_DELAY_MS: u32 = 100;
+
+/// Animated preview container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    /// 256-colour GIF, one bit of transparency
+    Gif,
+    /// Animated PNG with full RGBA
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Apng => "png",
+        }
+    }
+}
+
+impl FromStr for AnimationFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gif" => Ok(Self::Gif),
+            "apng" => Ok(Self::Apng),
+            _ => Err(anyhow!("Animation format must be gif or apng, got {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gif => "gif",
+            Self::Apng => "apng",
+        })
+    }
+}
+
+/// Files forming one animation, in frame order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    /// `<first stem>-<last number>`, e.g. `C0101-0104`
+    pub name: String,
+    pub frames: Vec<PathBuf>,
+}
+
+/// Files that can belong to one sequence: (prefix, digit count, extension)
+type SequenceKey = (String, usize, String);
+
+/// Split a file name into (prefix, number, digit count, extension)
+fn numbered(path: &Path) -> Option<(String, u64, usize, String)> {
+    let stem = path.file_stem()?.to_str()?;
+    let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &stem[prefix.len()..];
+    if digits.is_empty() || digits.len() > 18 {
+        return None;
+    }
+    let extension = crate::paths::extension_lower(path).unwrap_or_default();
+    Some((prefix.to_string(), digits.parse().ok()?, digits.len(), extension))
+}
+
+/// Runs of at least `min_frames` consecutively numbered files with the same
+/// prefix, digit count and extension
+pub fn detect_sequences(paths: &[PathBuf], min_frames: usize) -> Vec<Sequence> {
+    let mut groups: BTreeMap<SequenceKey, Vec<(u64, &PathBuf)>> = BTreeMap::new();
+    for path in paths {
+        if let Some((prefix, number, width, extension)) = numbered(path) {
+            groups.entry((prefix, width, extension)).or_default().push((number, path));
+        }
+    }
+
+    let mut sequences = Vec::new();
+    for ((_, width, _), mut members) in groups {
+        members.sort();
+        members.dedup_by_key(|(number, _)| *number);
+        let mut start = 0;
+        for i in 1..=members.len() {
+            if i == members.len() || members[i].0 != members[i - 1].0 + 1 {
+                let run = &members[start..i];
+                if run.len() >= min_frames.max(2) {
+                    let first = run[0].1.file_stem().unwrap_or_default().to_string_lossy();
+                    let last = run[run.len() - 1].0;
+                    sequences.push(Sequence {
+                        name: format!("{}-{:0width$}", first, last, width = width),
+                        frames: run.iter().map(|(_, path)| (*path).clone()).collect(),
+                    });
+                }
+                start = i;
+            }
+        }
+    }
+    sequences
+}
+
+/// A decoded frame and where it sits on the screen
+struct PlacedFrame {
+    image: RgbaImage,
+    x: i64,
+    y: i64,
+}
+
+fn load_frame(path: &Path) -> Result<PlacedFrame> {
+    let format = FormatType::from_path(path).ok();
+    let (x, y) = match format {
+        Some(FormatType::ToHeartLf2) => {
+            let lf2 = Lf2Image::from_data(&std::fs::read(path)?)?;
+            (lf2.x_offset as i64, lf2.y_offset as i64)
+        }
+        _ => (0, 0),
+    };
+    let decoded = crate::perceptual::RgbaImage::open(path)?;
+    let image = RgbaImage::from_raw(decoded.width, decoded.height, decoded.rgba)
+        .ok_or_else(|| anyhow!("{}: decoded image has the wrong size", path.display()))?;
+    Ok(PlacedFrame { image, x, y })
+}
+
+/// Decode the frames and draw each on a transparent canvas covering all of
+/// them, at its offset
+pub fn aligned_frames(sequence: &Sequence) -> Result<Vec<RgbaImage>> {
+    let frames = sequence.frames.iter().map(|p| load_frame(p)).collect::<Result<Vec<_>>>()?;
+    let left = frames.iter().map(|f| f.x).min().unwrap_or(0);
+    let top = frames.iter().map(|f| f.y).min().unwrap_or(0);
+    let right = frames.iter().map(|f| f.x + f.image.width() as i64).max().unwrap_or(0);
+    let bottom = frames.iter().map(|f| f.y + f.image.height() as i64).max().unwrap_or(0);
+
+    Ok(frames.into_iter()
+        .map(|frame| {
+            let mut canvas = RgbaImage::new((right - left) as u32, (bottom - top) as u32);
+            image::imageops::replace(&mut canvas, &frame.image, frame.x - left, frame.y - top);
+            canvas
+        })
+        .collect())
+}
+
+/// Assemble `sequence` into `<dir>/<name>.gif` or `.png` (APNG), looping
+/// forever. Returns the path written.
+pub fn write_animation(
+    sequence: &Sequence,
+    dir: &Path,
+    format: AnimationFormat,
+    delay_ms: u32,
+    direct: bool,
+) -> Result<PathBuf> {
+    let frames = aligned_frames(sequence)?;
+    let path = dir.join(format!("{}.{}", sequence.name, format.extension()));
+    crate::output::write_with(&path, direct, |w| {
+        match format {
+            AnimationFormat::Gif => {
+                use image::codecs::gif::{GifEncoder, Repeat};
+
+                let mut encoder = GifEncoder::new(w);
+                encoder.set_repeat(Repeat::Infinite)?;
+                let delay = image::Delay::from_numer_denom_ms(delay_ms, 1);
+                encoder.encode_frames(frames.into_iter().map(|f| image::Frame::from_parts(f, 0, 0, delay)))?;
+            }
+            AnimationFormat::Apng => encode_apng(w, &frames, delay_ms)?,
+        }
+        Ok(())
+    })?;
+    Ok(path)
+}
+
+/// Encode same-sized `frames` as an APNG that loops forever
+pub fn encode_apng<W: std::io::Write>(w: W, frames: &[RgbaImage], delay_ms: u32) -> Result<()> {
+    let (width, height) = frames.first().ok_or_else(|| anyhow!("An animation needs at least one frame"))?.dimensions();
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::Rgb;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn detects_consecutive_runs() {
+        let files = paths(&[
+            "in/C0103.LF2", "in/C0101.LF2", "in/C0102.LF2", "in/C0105.LF2",
+            "in/C0201.LF2", "in/C0202.LF2", "in/C0102.png", "in/BG01.LF2", "in/TITLE.LF2",
+        ]);
+        let sequences = detect_sequences(&files, 2);
+        assert_eq!(sequences, vec![
+            Sequence { name: "C0101-0103".to_string(), frames: paths(&["in/C0101.LF2", "in/C0102.LF2", "in/C0103.LF2"]) },
+            Sequence { name: "C0201-0202".to_string(), frames: paths(&["in/C0201.LF2", "in/C0202.LF2"]) },
+        ]);
+        assert_eq!(detect_sequences(&files, 3).len(), 1);
+    }
+
+    #[test]
+    fn aligns_frames_by_offset_and_writes_animations() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = |x_offset: u16, color: u8| Lf2Image {
+            width: 2,
+            height: 2,
+            x_offset,
+            y_offset: 10,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }, Rgb { r: color, g: 0, b: 0 }],
+            pixels: vec![1; 4],
+        };
+        let mut frames = Vec::new();
+        for (i, x) in [(1, 4u16), (2, 5)] {
+            let path = dir.path().join(format!("S0{}.LF2", i));
+            std::fs::write(&path, frame(x, 100 * i as u8).to_lf2_bytes_okumura().unwrap()).unwrap();
+            frames.push(path);
+        }
+        let sequence = detect_sequences(&frames, 2).remove(0);
+        assert_eq!(sequence.name, "S01-02");
+
+        let aligned = aligned_frames(&sequence).unwrap();
+        assert_eq!(aligned[0].dimensions(), (3, 2));
+        assert_eq!(aligned[0].get_pixel(0, 0).0, [100, 0, 0, 255]);
+        assert_eq!(aligned[0].get_pixel(2, 0).0[3], 0);
+        assert_eq!(aligned[1].get_pixel(0, 0).0[3], 0);
+        assert_eq!(aligned[1].get_pixel(2, 1).0, [200, 0, 0, 255]);
+
+        let gif = write_animation(&sequence, dir.path(), AnimationFormat::Gif, 80, false).unwrap();
+        assert_eq!(gif, dir.path().join("S01-02.gif"));
+        let apng = write_animation(&sequence, dir.path(), AnimationFormat::Apng, 80, false).unwrap();
+        let decoder = png::Decoder::new(std::fs::File::open(&apng).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let control = reader.info().animation_control().unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (2, 0));
+    }
+}
@@ -0,0 +1,339 @@
+//! Static file server for browsing conversion results
+//!
+//! `retro-decode serve-static` serves an output directory over plain
+//! HTTP/1.1 so exported images, galleries and reports can be opened in a
+//! browser without setting up a web server. It handles `GET` and `HEAD`
+//! only, one thread per connection, and never leaves the served directory:
+//! paths with `..` components and symlinks pointing outside are refused.
+//! Directories without an `index.html` get a generated listing.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Result, anyhow};
+use tracing::{debug, info, warn};
+
+/// Largest request head read before the connection is dropped
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// A response ready to be written to the socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// `Location` header for redirects
+    pub location: Option<String>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self { status, content_type, body, location: None }
+    }
+
+    fn error(status: u16) -> Self {
+        let text = format!("{} {}\n", status, reason(status));
+        Self::new(status, "text/plain; charset=utf-8", text.into_bytes())
+    }
+
+    fn write_to(&self, stream: &mut impl Write, head_only: bool) -> std::io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n",
+            self.status, reason(self.status), self.content_type, self.body.len()
+        );
+        if let Some(location) = &self.location {
+            let _ = write!(head, "Location: {}\r\n", location);
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        if !head_only {
+            stream.write_all(&self.body)?;
+        }
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Serves the files below one directory
+#[derive(Debug, Clone)]
+pub struct StaticServer {
+    root: PathBuf,
+}
+
+impl StaticServer {
+    pub fn new(root: &Path) -> Result<Self> {
+        let root = dunce::canonicalize(root)
+            .map_err(|e| anyhow!("{}: {}", root.display(), e))?;
+        if !root.is_dir() {
+            return Err(anyhow!("{} is not a directory", root.display()));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Accept connections until the listener fails
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        info!("Serving {} at http://{}/", self.root.display(), listener.local_addr()?);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = self.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = server.handle(stream) {
+                            debug!("connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("accept failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Drain the headers; nothing in them changes the response
+        let mut read = request_line.len();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            read += n;
+            if n == 0 || line == "\r\n" || line == "\n" || read > MAX_REQUEST_HEAD {
+                break;
+            }
+        }
+
+        let head_only = request_line.starts_with("HEAD ");
+        let response = self.respond(request_line.trim_end());
+        debug!("{} -> {}", request_line.trim_end(), response.status);
+        response.write_to(&mut stream, head_only)
+    }
+
+    /// Response to one request line (`GET /path HTTP/1.1`)
+    pub fn respond(&self, request_line: &str) -> Response {
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Response::error(400);
+        };
+        if method != "GET" && method != "HEAD" {
+            return Response::error(405);
+        }
+        let url_path = target.split(['?', '#']).next().unwrap_or("/");
+        let Some(path) = percent_decode(url_path) else {
+            return Response::error(400);
+        };
+        let Some(file) = self.resolve(&path) else {
+            return Response::error(403);
+        };
+
+        if file.is_dir() {
+            if !path.ends_with('/') {
+                let mut response = Response::new(301, "text/plain; charset=utf-8", Vec::new());
+                response.location = Some(format!("{}/", url_path));
+                return response;
+            }
+            let index = file.join("index.html");
+            if index.is_file() {
+                return self.file_response(&index);
+            }
+            return match directory_listing(&file, &path) {
+                Ok(html) => Response::new(200, "text/html; charset=utf-8", html.into_bytes()),
+                Err(_) => Response::error(500),
+            };
+        }
+        self.file_response(&file)
+    }
+
+    fn file_response(&self, file: &Path) -> Response {
+        match std::fs::read(file) {
+            Ok(body) => Response::new(200, content_type(file), body),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Response::error(404),
+            Err(_) => Response::error(500),
+        }
+    }
+
+    /// Map a decoded URL path to a path inside the root, `None` if it would
+    /// leave it. Missing files resolve to a path that does not exist.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for component in Path::new(url_path.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        match dunce::canonicalize(&path) {
+            Ok(real) if !real.starts_with(&self.root) => None,
+            _ => Some(path),
+        }
+    }
+}
+
+/// Decode `%XX` escapes; `None` for malformed escapes or non-UTF-8 results
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok().filter(|s| !s.contains('\0'))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match crate::paths::extension_lower(path).as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("jsonl") => "application/jsonl",
+        Some("txt" | "md" | "log") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("bmp") => "image/bmp",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// HTML index of `dir`, subdirectories first, each group sorted by name
+fn directory_listing(dir: &Path, url_path: &str) -> std::io::Result<String> {
+    let mut entries: Vec<(bool, String)> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| (!entry.path().is_dir(), entry.file_name().to_string_lossy().into_owned()))
+        .collect();
+    entries.sort();
+
+    let title = html_escape(url_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body><h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    if url_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let suffix = if is_file { "" } else { "/" };
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            percent_encode(&name), suffix, html_escape(&name), suffix
+        );
+    }
+    html.push_str("</ul></body></html>\n");
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn fixture() -> (tempfile::TempDir, StaticServer) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("report.json"), b"{}").unwrap();
+        std::fs::create_dir(dir.path().join("gallery")).unwrap();
+        std::fs::write(dir.path().join("gallery/index.html"), b"<h1>gallery</h1>").unwrap();
+        std::fs::create_dir(dir.path().join("png")).unwrap();
+        std::fs::write(dir.path().join("png/C 01<&>.png"), b"\x89PNG").unwrap();
+        let server = StaticServer::new(dir.path()).unwrap();
+        (dir, server)
+    }
+
+    #[test]
+    fn serves_files_indexes_and_listings() {
+        let (_dir, server) = fixture();
+
+        let json = server.respond("GET /report.json?v=1 HTTP/1.1");
+        assert_eq!((json.status, json.content_type, &json.body[..]), (200, "application/json", &b"{}"[..]));
+        assert_eq!(server.respond("GET /gallery/ HTTP/1.1").body, b"<h1>gallery</h1>");
+        assert_eq!(server.respond("GET /gallery HTTP/1.1").location.as_deref(), Some("/gallery/"));
+
+        let png = server.respond("GET /png/C%2001%3C%26%3E.png HTTP/1.1");
+        assert_eq!((png.status, png.content_type), (200, "image/png"));
+        let listing = String::from_utf8(server.respond("GET /png/ HTTP/1.1").body).unwrap();
+        assert!(listing.contains("<a href=\"C%2001%3C%26%3E.png\">C 01&lt;&amp;&gt;.png</a>"));
+        let root = String::from_utf8(server.respond("GET / HTTP/1.1").body).unwrap();
+        assert!(root.find("gallery/").unwrap() < root.find("report.json").unwrap());
+
+        assert_eq!(server.respond("GET /missing.png HTTP/1.1").status, 404);
+        assert_eq!(server.respond("POST / HTTP/1.1").status, 405);
+        assert_eq!(server.respond("GET /%zz HTTP/1.1").status, 400);
+    }
+
+    #[test]
+    fn stays_inside_the_root() {
+        let (dir, server) = fixture();
+        assert_eq!(server.respond("GET /../secret HTTP/1.1").status, 403);
+        assert_eq!(server.respond("GET /png/%2e%2e/%2e%2e/etc/passwd HTTP/1.1").status, 403);
+
+        #[cfg(unix)]
+        {
+            let outside = tempfile::tempdir().unwrap();
+            std::fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+            std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+            assert_eq!(server.respond("GET /link/secret.txt HTTP/1.1").status, 403);
+        }
+    }
+
+    #[test]
+    fn answers_over_tcp() {
+        let (_dir, server) = fixture();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /report.json HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 2\r\n"));
+        assert!(response.ends_with("\r\n\r\n{}"));
+    }
+}
@@ -0,0 +1,110 @@
+//! Session recordings (`--record session.yaml`)
+//!
+//! Each recorded run stores the effective [`Config`] exactly as the
+//! conversion saw it, plus where and with which version it ran, so
+//! `retro-decode replay session.yaml` can redo the same conversions later.
+//! Recording into an existing file appends a run.
+//!
+//! ```yaml
+//! session_version: 1
+//! runs:
+//!   - recorded_at: 2024-05-01T12:00:00Z
+//!     tool_version: 0.1.0
+//!     working_dir: /home/me/toheart
+//!     config:
+//!       input_dir: game
+//!       output: converted
+//!       format: png
+//!       ...
+//! ```
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::Config;
+
+/// Current session file schema
+pub const SESSION_VERSION: u32 = 1;
+
+/// One recorded invocation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRun {
+    pub recorded_at: DateTime<Utc>,
+    pub tool_version: String,
+    /// Relative paths in `config` are resolved against this directory
+    pub working_dir: PathBuf,
+    pub config: Config,
+}
+
+/// Contents of a session file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub session_version: u32,
+    pub runs: Vec<SessionRun>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self { session_version: SESSION_VERSION, runs: Vec::new() }
+    }
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let session: Session = serde_yaml::from_str(&text)?;
+        if session.session_version > SESSION_VERSION {
+            return Err(anyhow!(
+                "{} uses session version {}, this build reads up to {}",
+                path.display(), session.session_version, SESSION_VERSION
+            ));
+        }
+        Ok(session)
+    }
+
+    pub fn save(&self, path: &Path, direct: bool) -> Result<()> {
+        crate::output::write_bytes(path, direct, serde_yaml::to_string(self)?.as_bytes())
+    }
+}
+
+/// Append `config` as a new run to the session at `path`, creating it if needed
+pub fn record(path: &Path, config: &Config) -> Result<()> {
+    let mut session = if path.exists() { Session::load(path)? } else { Session::default() };
+    session.runs.push(SessionRun {
+        recorded_at: Utc::now(),
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        working_dir: std::env::current_dir()?,
+        config: config.clone(),
+    });
+    session.save(path, config.direct_writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_append_and_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.yaml");
+        let config = Config {
+            input: Some(PathBuf::from("C0101.LF2")),
+            output: PathBuf::from("out"),
+            format: "png".to_string(),
+            orientation: crate::decoder::Orientation::Stored,
+            tiles: Some("16x8".parse().unwrap()),
+            ..Default::default()
+        };
+        record(&path, &config).unwrap();
+        record(&path, &Config { format: "bmp".to_string(), ..config.clone() }).unwrap();
+
+        let session = Session::load(&path).unwrap();
+        assert_eq!(session.runs.len(), 2);
+        assert_eq!(session.runs[0].config.input, config.input);
+        assert_eq!(session.runs[0].config.orientation, config.orientation);
+        assert_eq!(session.runs[0].config.tiles, config.tiles);
+        assert_eq!(session.runs[1].config.format, "bmp");
+    }
+}
@@ -0,0 +1,228 @@
+//! Compression statistics and charts
+//!
+//! Collects per-file and aggregate LZSS statistics (compressed byte
+//! histogram, match length and distance distributions, compression ratio)
+//! for the `stats` subcommand, and renders each distribution as a PNG bar
+//! chart so a report directory is readable without further tooling. The
+//! charts need the `charts` feature; without it only the JSON is written.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+#[cfg(feature = "charts")]
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::formats::magic::{LF2_HEADER_SIZE, LF2_MAGIC};
+use crate::lzss::LzssSpec;
+
+/// Output cap for streams whose decompressed size is unknown
+const MAX_OUTPUT: usize = 4 << 20;
+
+/// Match distances are grouped into buckets of this many bytes
+pub const DISTANCE_BUCKET: usize = 64;
+
+#[cfg(feature = "charts")]
+const CHART_WIDTH: u32 = 640;
+#[cfg(feature = "charts")]
+const CHART_HEIGHT: u32 = 320;
+#[cfg(feature = "charts")]
+const CHART_MARGIN: u32 = 16;
+
+/// Where to find the compressed stream in files that are not LF2
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    pub spec: LzssSpec,
+    pub offset: usize,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        Self { spec: LzssSpec::LF2, offset: 0 }
+    }
+}
+
+/// Statistics for one input file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStats {
+    pub path: String,
+    pub compressed_size: usize,
+    pub decompressed_size: usize,
+    pub ratio: f64,
+    pub literals: usize,
+    pub matches: usize,
+}
+
+/// Statistics over a set of files
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    pub files: Vec<FileStats>,
+    /// Occurrences of each byte value in the compressed streams
+    pub byte_histogram: Vec<u64>,
+    /// Matches per length, indexed by length
+    pub match_lengths: Vec<u64>,
+    /// Matches per distance bucket of [`DISTANCE_BUCKET`] bytes
+    pub match_distances: Vec<u64>,
+}
+
+impl Default for StatsReport {
+    fn default() -> Self {
+        Self { files: Vec::new(), byte_histogram: vec![0; 256], match_lengths: Vec::new(), match_distances: Vec::new() }
+    }
+}
+
+impl StatsReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one file. LF2 files are recognised by their magic and use the
+    /// header's stream offset and pixel count; anything else is decoded with
+    /// `options` until the input ends.
+    pub fn add_file(&mut self, path: &Path, data: &[u8], options: &StatsOptions) -> Result<()> {
+        let (spec, offset, max_output) = if data.starts_with(LF2_MAGIC) && data.len() >= LF2_HEADER_SIZE {
+            let width = u16::from_le_bytes([data[12], data[13]]) as usize;
+            let height = u16::from_le_bytes([data[14], data[15]]) as usize;
+            (LzssSpec::LF2, LF2_HEADER_SIZE + data[0x16] as usize * 3, width * height)
+        } else {
+            (options.spec, options.offset, MAX_OUTPUT)
+        };
+        let stream = data.get(offset..)
+            .ok_or_else(|| anyhow!("{}: stream offset {:#x} is past the end of the file", path.display(), offset))?;
+
+        for &byte in stream {
+            self.byte_histogram[byte as usize] += 1;
+        }
+        let (lengths, distances) = (&mut self.match_lengths, &mut self.match_distances);
+        let out = spec.decompress_with(stream, max_output, |distance, length| {
+            bump(lengths, length);
+            bump(distances, distance / DISTANCE_BUCKET);
+        });
+
+        self.files.push(FileStats {
+            path: path.display().to_string(),
+            compressed_size: stream.len(),
+            decompressed_size: out.data.len(),
+            ratio: out.data.len() as f64 / stream.len().max(1) as f64,
+            literals: out.literals,
+            matches: out.matches,
+        });
+        Ok(())
+    }
+
+    /// Write the report as JSON to `json_path` and one PNG chart per
+    /// distribution next to it (`<stem>.<chart>.png`). Returns the chart paths,
+    /// none without the `charts` feature.
+    pub fn write(&self, json_path: &Path, direct: bool) -> Result<Vec<PathBuf>> {
+        crate::output::write_bytes(json_path, direct, serde_json::to_string_pretty(self)?.as_bytes())?;
+        self.write_charts(json_path, direct)
+    }
+
+    #[cfg(not(feature = "charts"))]
+    fn write_charts(&self, _json_path: &Path, _direct: bool) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    #[cfg(feature = "charts")]
+    fn write_charts(&self, json_path: &Path, direct: bool) -> Result<Vec<PathBuf>> {
+        let stem = json_path.file_stem().unwrap_or_default().to_string_lossy();
+        // Ratios are charted in hundredths so they share the integer renderer
+        let ratios: Vec<u64> = self.files.iter().map(|f| (f.ratio * 100.0).round() as u64).collect();
+        let charts: [(&str, &[u64], [u8; 3]); 4] = [
+            ("byte_histogram", &self.byte_histogram, [0x3b, 0x6e, 0xa8]),
+            ("match_lengths", &self.match_lengths, [0xd9, 0x7b, 0x29]),
+            ("match_distances", &self.match_distances, [0x4c, 0x9a, 0x5a]),
+            ("compression_ratio", &ratios, [0x8e, 0x4c, 0x9a]),
+        ];
+
+        let mut written = Vec::new();
+        for (name, values, color) in charts {
+            let path = json_path.with_file_name(format!("{}.{}.png", stem, name));
+            crate::output::write_rgba_image(&bar_chart(values, color), &path, direct)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+fn bump(counts: &mut Vec<u64>, index: usize) {
+    if counts.len() <= index {
+        counts.resize(index + 1, 0);
+    }
+    counts[index] += 1;
+}
+
+/// Render `values` as a bar chart scaled to the largest value
+#[cfg(feature = "charts")]
+#[cfg_attr(docsrs, doc(cfg(feature = "charts")))]
+pub fn bar_chart(values: &[u64], color: [u8; 3]) -> RgbaImage {
+    let mut img = RgbaImage::from_pixel(CHART_WIDTH, CHART_HEIGHT, Rgba([0xff, 0xff, 0xff, 0xff]));
+    let plot_width = CHART_WIDTH - 2 * CHART_MARGIN;
+    let plot_height = CHART_HEIGHT - 2 * CHART_MARGIN;
+    let baseline = CHART_HEIGHT - CHART_MARGIN;
+
+    let max = values.iter().copied().max().unwrap_or(0);
+    if max > 0 {
+        let bar = Rgba([color[0], color[1], color[2], 0xff]);
+        let count = values.len() as u32;
+        for (i, &value) in values.iter().enumerate() {
+            let x0 = CHART_MARGIN + i as u32 * plot_width / count;
+            let x1 = (CHART_MARGIN + (i as u32 + 1) * plot_width / count).max(x0 + 1);
+            let height = (value as f64 / max as f64 * plot_height as f64).round() as u32;
+            for x in x0..x1.min(CHART_WIDTH - CHART_MARGIN) {
+                for y in baseline - height..baseline {
+                    img.put_pixel(x, y, bar);
+                }
+            }
+        }
+    }
+
+    let axis = Rgba([0x40, 0x40, 0x40, 0xff]);
+    for x in CHART_MARGIN..=CHART_WIDTH - CHART_MARGIN {
+        img.put_pixel(x, baseline, axis);
+    }
+    for y in CHART_MARGIN..=baseline {
+        img.put_pixel(CHART_MARGIN - 1, y, axis);
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+    #[test]
+    fn lf2_stats_and_charts() {
+        let image = Lf2Image {
+            width: 16,
+            height: 8,
+            x_offset: 0,
+            y_offset: 0,
+            transparent_color: 0,
+            color_count: 2,
+            palette: vec![Rgb { r: 0, g: 0, b: 0 }; 2],
+            pixels: (0..128).map(|i| (i / 4 % 2) as u8).collect(),
+        };
+        let data = image.to_lf2_bytes_okumura().unwrap();
+
+        let mut report = StatsReport::new();
+        report.add_file(Path::new("a.lf2"), &data, &StatsOptions::default()).unwrap();
+        let file = &report.files[0];
+        assert_eq!(file.decompressed_size, 128);
+        assert_eq!(file.compressed_size, data.len() - 0x18 - 2 * 3);
+        assert_eq!(report.byte_histogram.iter().sum::<u64>(), file.compressed_size as u64);
+        assert_eq!(report.match_lengths.iter().sum::<u64>(), file.matches as u64);
+        assert!(file.matches > 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let charts = report.write(&dir.path().join("stats.json"), false).unwrap();
+        assert!(dir.path().join("stats.json").is_file());
+        if cfg!(not(feature = "charts")) {
+            assert!(charts.is_empty());
+            return;
+        }
+        assert_eq!(charts.len(), 4);
+        assert!(charts.iter().all(|c| image::open(c).is_ok()));
+        assert!(charts[0].ends_with("stats.byte_histogram.png"));
+    }
+}
@@ -0,0 +1,41 @@
+//! Worker thread count
+//!
+//! `--parallel 4` asks for four workers; a bare `--parallel` takes
+//! `RAYON_NUM_THREADS` if it is set (the variable shared build servers
+//! already use to cap Rust tools) and every available core otherwise. The
+//! multi-threaded encoders and the benchmark's parallel LZSS decode follow
+//! the same variable.
+
+/// Environment variable that caps the worker count
+pub const THREADS_ENV: &str = "RAYON_NUM_THREADS";
+
+/// Positive worker count from [`THREADS_ENV`]
+pub fn from_env() -> Option<usize> {
+    parse_count(&std::env::var(THREADS_ENV).ok()?)
+}
+
+fn parse_count(value: &str) -> Option<usize> {
+    value.trim().parse().ok().filter(|&n| n > 0)
+}
+
+/// `requested`, else [`THREADS_ENV`], else the available cores
+pub fn effective(requested: Option<usize>) -> usize {
+    requested
+        .filter(|&n| n > 0)
+        .or_else(from_env)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_count_wins() {
+        assert_eq!(effective(Some(3)), 3);
+        assert!(effective(None) >= 1);
+        assert_eq!(parse_count(" 8\n"), Some(8));
+        assert_eq!(parse_count("0"), None);
+        assert_eq!(parse_count("all"), None);
+    }
+}
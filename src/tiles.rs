@@ -0,0 +1,159 @@
+//! Tileset export
+//!
+//! Cuts a decoded image into fixed-size tiles, keeps one copy of each
+//! distinct tile and records which tile goes where, the layout tile-based
+//! hardware (GBA/NDS backgrounds, PC Engine, custom engines) loads directly.
+//! Tiles are stacked in a one-tile-wide strip so each tile's pixels are
+//! contiguous in raw outputs.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+
+/// Tile dimensions in pixels, written `WxH` (e.g. `16x16`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for TileSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (w, h) = s.split_once(['x', 'X'])
+            .ok_or_else(|| anyhow!("Tile size must look like 16x16, got {}", s))?;
+        let width: u32 = w.parse().map_err(|_| anyhow!("Invalid tile width: {}", w))?;
+        let height: u32 = h.parse().map_err(|_| anyhow!("Invalid tile height: {}", h))?;
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Tile size must be non-zero, got {}", s));
+        }
+        Ok(Self { width, height })
+    }
+}
+
+impl fmt::Display for TileSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Deduplicated tiles plus the map that rebuilds the image from them
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub tile_size: TileSize,
+    /// Distinct tiles in first-seen order, each `width * height` RGBA pixels
+    pub tiles: Vec<RgbaImage>,
+    /// Map width in tiles
+    pub columns: u32,
+    /// Map height in tiles
+    pub rows: u32,
+    /// Tile index per map cell, row-major
+    pub map: Vec<u32>,
+}
+
+/// Map file contents (`<stem>.map.json`)
+#[derive(Debug, Serialize)]
+struct MapFile<'a> {
+    tile_width: u32,
+    tile_height: u32,
+    columns: u32,
+    rows: u32,
+    tile_count: usize,
+    map: &'a [u32],
+}
+
+impl Tileset {
+    /// Cut `img` into tiles. Edge tiles that run past the image are padded
+    /// with transparent pixels.
+    pub fn from_image(img: &RgbaImage, tile_size: TileSize) -> Self {
+        let columns = (img.width() + tile_size.width - 1) / tile_size.width;
+        let rows = (img.height() + tile_size.height - 1) / tile_size.height;
+
+        let mut tiles = Vec::new();
+        let mut seen: HashMap<Vec<u8>, u32> = HashMap::new();
+        let mut map = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                let tile = RgbaImage::from_fn(tile_size.width, tile_size.height, |x, y| {
+                    let (sx, sy) = (column * tile_size.width + x, row * tile_size.height + y);
+                    if sx < img.width() && sy < img.height() {
+                        *img.get_pixel(sx, sy)
+                    } else {
+                        image::Rgba([0, 0, 0, 0])
+                    }
+                });
+                let index = *seen.entry(tile.as_raw().clone()).or_insert_with(|| {
+                    tiles.push(tile);
+                    tiles.len() as u32 - 1
+                });
+                map.push(index);
+            }
+        }
+
+        Self { tile_size, tiles, columns, rows, map }
+    }
+
+    /// All tiles stacked vertically in a strip one tile wide
+    pub fn to_strip(&self) -> RgbaImage {
+        let TileSize { width, height } = self.tile_size;
+        let mut strip = RgbaImage::new(width, height * self.tiles.len() as u32);
+        for (i, tile) in self.tiles.iter().enumerate() {
+            image::imageops::replace(&mut strip, tile, 0, (i as u32 * height) as i64);
+        }
+        strip
+    }
+
+    /// Write the strip to `image_path` (format by extension, see
+    /// [`write_rgba_image_ordered`](crate::output::write_rgba_image_ordered))
+    /// and the map next to it as `<stem>.map.json`. Returns the map path.
+    pub fn write(
+        &self,
+        image_path: &Path,
+        direct: bool,
+        rgb565_order: crate::output::Endianness,
+    ) -> Result<PathBuf> {
+        crate::output::write_rgba_image_ordered(&self.to_strip(), image_path, direct, rgb565_order)?;
+
+        let stem = image_path.file_stem().unwrap_or_default().to_string_lossy();
+        let map_path = image_path.with_file_name(format!("{}.map.json", stem));
+        let map = MapFile {
+            tile_width: self.tile_size.width,
+            tile_height: self.tile_size.height,
+            columns: self.columns,
+            rows: self.rows,
+            tile_count: self.tiles.len(),
+            map: &self.map,
+        };
+        crate::output::write_bytes(&map_path, direct, serde_json::to_string_pretty(&map)?.as_bytes())?;
+        Ok(map_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_tiles_are_shared() {
+        // 5x4 image with 2x2 tiles: a checkerboard of two colors per tile
+        // column, plus a partial right-hand column
+        let img = RgbaImage::from_fn(5, 4, |x, _| {
+            if x < 2 { image::Rgba([255, 0, 0, 255]) } else { image::Rgba([0, 0, 255, 255]) }
+        });
+        let tileset = Tileset::from_image(&img, "2x2".parse().unwrap());
+
+        assert_eq!((tileset.columns, tileset.rows), (3, 2));
+        assert_eq!(tileset.tiles.len(), 3);
+        assert_eq!(tileset.map, vec![0, 1, 2, 0, 1, 2]);
+        assert_eq!(tileset.tiles[2].get_pixel(1, 0)[3], 0);
+        assert_eq!(tileset.to_strip().dimensions(), (2, 6));
+
+        assert!("16".parse::<TileSize>().is_err());
+        assert!("0x8".parse::<TileSize>().is_err());
+    }
+}
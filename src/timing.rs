@@ -0,0 +1,186 @@
+//! Phase timings of the file being converted, for `--benchmark`
+//!
+//! While [`record`] runs a conversion, decoders report the time spent
+//! decompressing, the image they produced and their LZSS token counts, and
+//! [`output::write_with`](crate::output::write_with) reports the time spent
+//! encoding and writing outputs. The benchmark record is built from these
+//! instead of decoding the file a second time. Recording is per thread, so
+//! batch workers each measure their own file; outside [`record`] every hook
+//! is one thread-local check. The same hooks feed the process-wide
+//! [`metrics`](crate::metrics) counters when those are enabled.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::lzss::LzssOutput;
+use crate::metrics::{self, Counter};
+
+/// What a hook is timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Pixel stream decompression (LZSS, MAG flags, ...)
+    Decompress,
+    /// Encoding and writing an output file
+    Write,
+}
+
+/// Tokens of the LZSS streams decoded for one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct TokenStats {
+    pub literals: usize,
+    /// Back references
+    pub matches: usize,
+    /// Output bytes produced by back references
+    pub matched_bytes: usize,
+}
+
+/// The first image decoded for the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub transparent_pixels: usize,
+}
+
+/// Everything measured while converting one file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measurements {
+    pub total: Duration,
+    pub decompress: Duration,
+    pub write: Duration,
+    /// Bytes of all outputs written
+    pub output_bytes: u64,
+    pub image: Option<ImageInfo>,
+    /// `None` for formats without an LZSS stream
+    pub tokens: Option<TokenStats>,
+}
+
+impl Measurements {
+    /// Reading and parsing: what is left of the total after decompression
+    /// and writing
+    pub fn parse(&self) -> Duration {
+        self.total.saturating_sub(self.decompress + self.write)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Measurements>> = const { RefCell::new(None) };
+}
+
+fn update(f: impl FnOnce(&mut Measurements)) {
+    CURRENT.with(|current| {
+        if let Some(m) = current.borrow_mut().as_mut() {
+            f(m);
+        }
+    });
+}
+
+fn recording() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Run `f`, collecting what the hooks report on this thread
+pub fn record<T>(f: impl FnOnce() -> T) -> (T, Measurements) {
+    let outer = CURRENT.with(|current| current.replace(Some(Measurements::default())));
+    let start = Instant::now();
+    let result = f();
+    let mut measured = CURRENT.with(|current| current.replace(outer)).unwrap_or_default();
+    measured.total = start.elapsed();
+    (result, measured)
+}
+
+/// Adds the time until it is dropped to its phase
+pub struct Timer {
+    phase: Phase,
+    start: Option<Instant>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let elapsed = start.elapsed();
+            update(|m| match self.phase {
+                Phase::Decompress => m.decompress += elapsed,
+                Phase::Write => m.write += elapsed,
+            });
+        }
+    }
+}
+
+/// Start timing `phase` for code that does not fit in a closure
+pub fn start(phase: Phase) -> Timer {
+    Timer { phase, start: recording().then(Instant::now) }
+}
+
+/// Run `f`, adding its duration to `phase`
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let _timer = start(phase);
+    f()
+}
+
+/// Count the size of the output just written to `path`
+pub fn written(path: &Path) {
+    if recording() || metrics::enabled() {
+        let bytes = std::fs::metadata(path).map_or(0, |m| m.len());
+        update(|m| m.output_bytes += bytes);
+        metrics::add(Counter::OutputsWritten, 1);
+        metrics::add(Counter::BytesWritten, bytes);
+    }
+}
+
+/// Note a decoded `width` x `height` image; `transparent` counts its
+/// transparent pixels and only runs while recording. Archives report the
+/// first image.
+pub fn image(width: u32, height: u32, transparent: impl FnOnce() -> usize) {
+    metrics::add(Counter::ImagesDecoded, 1);
+    metrics::add(Counter::PixelsDecoded, width as u64 * height as u64);
+    if recording() {
+        let transparent_pixels = transparent();
+        update(|m| {
+            m.image.get_or_insert(ImageInfo { width, height, transparent_pixels });
+        });
+    }
+}
+
+/// Add the tokens of a decoded LZSS stream
+pub fn tokens(output: &LzssOutput) {
+    let matched_bytes = output.data.len().saturating_sub(output.literals);
+    metrics::add(Counter::Streams, 1);
+    metrics::add(Counter::Literals, output.literals as u64);
+    metrics::add(Counter::Matches, output.matches as u64);
+    metrics::add(Counter::MatchedBytes, matched_bytes as u64);
+    update(|m| {
+        let tokens = m.tokens.get_or_insert_with(TokenStats::default);
+        tokens.literals += output.literals;
+        tokens.matches += output.matches;
+        tokens.matched_bytes += matched_bytes;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_inside_record() {
+        image(1, 1, || unreachable!("not recording"));
+        let ((), measured) = record(|| {
+            time(Phase::Decompress, || std::thread::sleep(Duration::from_millis(2)));
+            image(4, 2, || 3);
+            image(8, 8, || 0);
+            let file = tempfile::NamedTempFile::new().unwrap();
+            std::fs::write(file.path(), [0; 100]).unwrap();
+            written(file.path());
+            let output = crate::lzss::LzssSpec::LF2.decompress_stream(&[0xff, 1, 2, 3, 4, 5, 6, 7, 8], 8);
+            tokens(&output);
+        });
+        assert!(measured.decompress >= Duration::from_millis(2));
+        assert!(measured.total >= measured.decompress);
+        assert_eq!(measured.image, Some(ImageInfo { width: 4, height: 2, transparent_pixels: 3 }));
+        assert_eq!(measured.output_bytes, 100);
+        assert_eq!(measured.tokens.map(|t| t.literals + t.matched_bytes), Some(8));
+        assert!(!recording());
+    }
+}
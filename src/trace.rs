@@ -0,0 +1,223 @@
+//! Saved step-by-step decode traces
+//!
+//! A trace file wraps a [`DecodingState`] in a versioned envelope so traces
+//! kept as teaching material or experiment logs can still be read after the
+//! step layout changes. Traces are JSON by default and CBOR when the file name
+//! ends in `.cbor`; loading detects the encoding from the content.
+//!
+//! Version history:
+//! - 0: bare `DecodingState` (or a bare `steps` array), no envelope
+//! - 1: `{ trace_version, tool_version, format, source_file, state }`
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::DecodeConfig;
+use crate::formats::{DecodeStep, DecodingState, FormatType};
+
+/// Trace schema version written by this build
+pub const TRACE_VERSION: u32 = 1;
+
+/// On-disk encoding of a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEncoding {
+    Json,
+    Cbor,
+}
+
+impl TraceEncoding {
+    /// `.cbor` selects CBOR, anything else JSON
+    pub fn from_path(path: &Path) -> Self {
+        match crate::paths::extension_lower(path).as_deref() {
+            Some("cbor") => Self::Cbor,
+            _ => Self::Json,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "json" => Ok(Self::Json),
+            "cbor" => Ok(Self::Cbor),
+            _ => Err(anyhow!("Unknown trace encoding: {}", name)),
+        }
+    }
+}
+
+/// Versioned trace envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceFile {
+    pub trace_version: u32,
+    /// Crate version that wrote (or migrated) the trace
+    pub tool_version: String,
+    pub format: Option<FormatType>,
+    pub source_file: Option<String>,
+    pub state: DecodingState,
+}
+
+impl TraceFile {
+    pub fn new(format: FormatType, source: &Path, state: DecodingState) -> Self {
+        Self {
+            trace_version: TRACE_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            format: Some(format),
+            source_file: source.file_name().map(|n| n.to_string_lossy().into_owned()),
+            state,
+        }
+    }
+
+    /// Write the trace, choosing the encoding from the file extension
+    pub fn save(&self, path: &Path, direct: bool) -> Result<()> {
+        self.save_as(path, TraceEncoding::from_path(path), direct)
+    }
+
+    pub fn save_as(&self, path: &Path, encoding: TraceEncoding, direct: bool) -> Result<()> {
+        crate::output::write_with(path, direct, |w| {
+            match encoding {
+                TraceEncoding::Json => serde_json::to_writer_pretty(w, self)?,
+                TraceEncoding::Cbor => ciborium::into_writer(self, w)
+                    .map_err(|e| anyhow!("Failed to encode CBOR trace: {}", e))?,
+            }
+            Ok(())
+        })
+    }
+
+    /// Load a trace of any known version, migrating it in memory
+    pub fn load(path: &Path) -> Result<(Self, u32)> {
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow!("Failed to read trace {}: {}", path.display(), e))?;
+        let value = parse_value(&data)
+            .map_err(|e| anyhow!("Invalid trace {}: {}", path.display(), e))?;
+        migrate(value)
+    }
+}
+
+/// Save `state` to `config.trace_output`, if one was requested
+pub fn save_requested(config: &DecodeConfig, format: FormatType, source: &Path, state: DecodingState) -> Result<()> {
+    if let Some(path) = &config.trace_output {
+        TraceFile::new(format, source, state).save(path, config.direct_writes)?;
+        info!("Saved decode trace to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Decode JSON or CBOR into a generic value
+fn parse_value(data: &[u8]) -> Result<Value> {
+    let first = data.iter().copied().find(|b| !b.is_ascii_whitespace());
+    match first {
+        Some(b'{') | Some(b'[') => Ok(serde_json::from_slice(data)?),
+        Some(_) => ciborium::from_reader(data).map_err(|e| anyhow!("{}", e)),
+        None => Err(anyhow!("Empty trace")),
+    }
+}
+
+/// Upgrade a trace value to the current schema.
+///
+/// Returns the migrated trace and the version it was read as.
+pub fn migrate(value: Value) -> Result<(TraceFile, u32)> {
+    let version = match &value {
+        Value::Object(map) => match map.get("trace_version") {
+            Some(v) => v.as_u64().ok_or_else(|| anyhow!("trace_version is not a number"))? as u32,
+            None => 0,
+        },
+        Value::Array(_) => 0,
+        _ => return Err(anyhow!("Trace must be a JSON object or array")),
+    };
+
+    if version > TRACE_VERSION {
+        return Err(anyhow!(
+            "Trace version {} is newer than this build supports ({})",
+            version, TRACE_VERSION
+        ));
+    }
+
+    let trace = match version {
+        0 => migrate_v0(value)?,
+        _ => serde_json::from_value(value)?,
+    };
+    Ok((trace, version))
+}
+
+/// v0: a bare `DecodingState`, or just its `steps` array
+fn migrate_v0(value: Value) -> Result<TraceFile> {
+    let state = match value {
+        Value::Array(_) => {
+            let steps: Vec<DecodeStep> = serde_json::from_value(value)?;
+            let decoded = steps.last().map(|s| s.pixels_decoded).unwrap_or(0);
+            DecodingState {
+                current_step: 0,
+                total_pixels: decoded,
+                decoded_pixels: decoded,
+                steps,
+                ..DecodingState::new()
+            }
+        }
+        other => serde_json::from_value(other)?,
+    };
+    Ok(TraceFile {
+        trace_version: TRACE_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        format: None,
+        source_file: None,
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::StepOperationType;
+
+    fn step(n: usize) -> DecodeStep {
+        DecodeStep {
+            step_number: n,
+            description: format!("step {}", n),
+            explanation: String::new(),
+            operation_type: StepOperationType::FlagByte,
+            raw_bytes: vec![0xff],
+            data_offset: n,
+            data_length: 1,
+            pixels_decoded: n * 8,
+            memory_state: Vec::new(),
+            ring_position: 0xfee,
+            partial_image: None,
+        }
+    }
+
+    #[test]
+    fn migrates_unversioned_traces() {
+        let steps = serde_json::to_value(vec![step(1), step(2)]).unwrap();
+        let (trace, from) = migrate(steps).unwrap();
+        assert_eq!(from, 0);
+        assert_eq!(trace.trace_version, TRACE_VERSION);
+        assert_eq!(trace.state.steps.len(), 2);
+        assert_eq!(trace.state.decoded_pixels, 16);
+
+        let mut state = DecodingState::new();
+        state.add_step(step(1));
+        let (trace, from) = migrate(serde_json::to_value(&state).unwrap()).unwrap();
+        assert_eq!((from, trace.state.steps.len()), (0, 1));
+
+        let future = serde_json::json!({ "trace_version": TRACE_VERSION + 1 });
+        assert!(migrate(future).is_err());
+    }
+
+    #[test]
+    fn json_and_cbor_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = DecodingState::new();
+        state.add_step(step(3));
+        let trace = TraceFile::new(FormatType::ToHeartLf2, Path::new("C0101.LF2"), state);
+
+        for name in ["t.json", "t.cbor"] {
+            let path = dir.path().join(name);
+            trace.save(&path, false).unwrap();
+            let (loaded, from) = TraceFile::load(&path).unwrap();
+            assert_eq!(from, TRACE_VERSION);
+            assert_eq!(loaded.format, Some(FormatType::ToHeartLf2));
+            assert_eq!(loaded.state.steps[0].raw_bytes, vec![0xff]);
+        }
+    }
+}
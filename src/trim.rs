@@ -0,0 +1,100 @@
+//! Bounding-box trim of sprites stored on transparent canvases
+//!
+//! Character sprites are often stored at full screen size with most pixels
+//! transparent. `--trim` crops the export to the box around the pixels with
+//! non-zero alpha and records the box in the sidecar; `reencode` pads the
+//! edited crop back onto a transparent canvas of the original size.
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+/// Cropped area within the original canvas, in display coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrimRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TrimRect {
+    /// Smallest box holding every pixel with non-zero alpha; the whole
+    /// canvas when no pixel is visible, so the export is never empty
+    pub fn bounding_box(rgba: &[u8], width: u32, height: u32) -> Self {
+        let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+        for (i, px) in rgba.chunks_exact(4).enumerate() {
+            if px[3] != 0 {
+                let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x + 1);
+                y1 = y1.max(y + 1);
+            }
+        }
+        if x0 >= x1 {
+            return Self { x: 0, y: 0, width, height };
+        }
+        Self { x: x0, y: y0, width: x1 - x0, height: y1 - y0 }
+    }
+
+    /// Cut this box out of a `canvas_width`-wide image with `bytes_per_pixel`
+    pub fn crop(&self, pixels: &[u8], canvas_width: u32, bytes_per_pixel: usize) -> Vec<u8> {
+        let row = canvas_width as usize * bytes_per_pixel;
+        let (start, len) = (self.x as usize * bytes_per_pixel, self.width as usize * bytes_per_pixel);
+        (self.y..self.y + self.height)
+            .flat_map(|y| &pixels[y as usize * row + start..][..len])
+            .copied()
+            .collect()
+    }
+
+    /// Place a cropped RGBA image back on a transparent canvas
+    pub fn untrim(&self, rgba: &[u8], canvas_width: u32, canvas_height: u32) -> Result<Vec<u8>> {
+        if rgba.len() != self.width as usize * self.height as usize * 4 {
+            return Err(anyhow!(
+                "Trimmed image size mismatch: sidecar says {}x{}, got {} RGBA bytes",
+                self.width, self.height, rgba.len()
+            ));
+        }
+        if self.x + self.width > canvas_width || self.y + self.height > canvas_height {
+            return Err(anyhow!(
+                "Trim box {}x{}+{}+{} does not fit the {}x{} canvas",
+                self.width, self.height, self.x, self.y, canvas_width, canvas_height
+            ));
+        }
+        let mut canvas = vec![0; canvas_width as usize * canvas_height as usize * 4];
+        let row = self.width as usize * 4;
+        for (y, line) in rgba.chunks_exact(row).enumerate() {
+            let start = ((self.y as usize + y) * canvas_width as usize + self.x as usize) * 4;
+            canvas[start..start + row].copy_from_slice(line);
+        }
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_and_untrim() {
+        // 5x4 canvas, visible pixels at (1,1) and (3,2)
+        let mut rgba = vec![0u8; 5 * 4 * 4];
+        rgba[(5 + 1) * 4..][..4].copy_from_slice(&[10, 20, 30, 255]);
+        rgba[(2 * 5 + 3) * 4..][..4].copy_from_slice(&[40, 50, 60, 128]);
+
+        let rect = TrimRect::bounding_box(&rgba, 5, 4);
+        assert_eq!(rect, TrimRect { x: 1, y: 1, width: 3, height: 2 });
+        let cropped = rect.crop(&rgba, 5, 4);
+        assert_eq!(cropped.len(), 3 * 2 * 4);
+        assert_eq!(&cropped[..4], &[10, 20, 30, 255]);
+        assert_eq!(&cropped[20..], &[40, 50, 60, 128]);
+        assert_eq!(rect.untrim(&cropped, 5, 4).unwrap(), rgba);
+
+        let indices: Vec<u8> = (0..20).collect();
+        assert_eq!(rect.crop(&indices, 5, 1), [6, 7, 8, 11, 12, 13]);
+
+        assert!(rect.untrim(&cropped[4..], 5, 4).is_err());
+        assert!(rect.untrim(&cropped, 3, 4).is_err());
+        assert_eq!(TrimRect::bounding_box(&[0; 16], 2, 2), TrimRect { x: 0, y: 0, width: 2, height: 2 });
+    }
+}
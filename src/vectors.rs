@@ -0,0 +1,319 @@
+//! Conformance test vectors
+//!
+//! `export-vectors -o DIR` writes a few small files per decoded format
+//! together with what this crate decodes them to, so other implementations
+//! (the TypeScript engine, third-party decoders) can check themselves
+//! against it. Every input is synthesized here, never cut from game data,
+//! so the set can be redistributed freely.
+//!
+//! Each vector is `<name>.<ext>` plus `<name>.rgba` (RGBA8, top row first)
+//! and, where the decoder records steps, `<name>.trace.json`. PAK vectors
+//! get a `<name>/` folder with the extracted files instead of pixels.
+//! `manifest.json` lists everything with sizes and SHA-256.
+
+use std::path::Path;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::checksum::sha256_hex;
+use crate::decoder::{decoder_for, Orientation};
+use crate::formats::{DecodingState, FormatType};
+use crate::formats::kanon::pdt::{PdtImage, PdtLayout, RgbColor};
+use crate::formats::magic::MAG_MAGIC;
+use crate::formats::toheart::lf2::{Lf2Image, Rgb};
+
+/// One synthetic input
+#[derive(Debug, Clone)]
+pub struct Vector {
+    pub name: &'static str,
+    pub format: FormatType,
+    pub extension: &'static str,
+    /// What the vector exercises
+    pub description: &'static str,
+    pub input: Vec<u8>,
+}
+
+/// One file extracted from an archive vector
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExtractedFile {
+    pub name: String,
+    pub size: usize,
+    pub sha256: String,
+}
+
+/// Manifest record of one vector
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VectorRecord {
+    pub name: String,
+    pub format: FormatType,
+    pub description: String,
+    pub input: String,
+    pub input_sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Expected RGBA8 output, top row first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgba: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgba_sha256: Option<String>,
+    /// SHA-256 of the palette indices (top row first) of indexed formats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indices_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<ExtractedFile>,
+}
+
+/// `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    pub tool_version: String,
+    pub trace_version: u32,
+    pub vectors: Vec<VectorRecord>,
+}
+
+fn lf2(width: u16, height: u16, offset: (u16, u16), transparent: u8, palette: &[[u8; 3]], pixels: Vec<u8>) -> Result<Vec<u8>> {
+    Lf2Image {
+        width,
+        height,
+        x_offset: offset.0,
+        y_offset: offset.1,
+        transparent_color: transparent,
+        color_count: palette.len() as u8,
+        palette: palette.iter().map(|&[r, g, b]| Rgb { r, g, b }).collect(),
+        pixels,
+    }
+    .to_lf2_bytes()
+}
+
+fn pdt(width: u32, height: u32, layout: PdtLayout) -> Vec<u8> {
+    let pixels = (0..width * height)
+        .map(|i| RgbColor { r: (i * 40) as u8, g: if i % 3 == 0 { 255 } else { 0 }, b: (i / width * 60) as u8 })
+        .collect();
+    let alpha_mask = match layout {
+        PdtLayout::Standard => (0..width * height).map(|i| if i % 4 == 0 { 0 } else { 255 }).collect(),
+        PdtLayout::Legacy => vec![255; (width * height) as usize],
+    };
+    PdtImage { width, height, file_length: 0, layout, mask_offset: 0, pixels, alpha_mask }.to_pdt_bytes(layout)
+}
+
+/// 16x2, 16 grey levels; line 0 copies one unit from its left neighbour,
+/// line 1 copies line 0 unit by unit
+fn mag() -> Vec<u8> {
+    let mut data = MAG_MAGIC.to_vec();
+    data.extend_from_slice(b"PC98");
+    data.extend_from_slice(b"retro-decode      ");
+    data.extend_from_slice(b"vector");
+    data.push(0x1a);
+
+    let flag_a = [0b0111_0000];
+    let flag_b = [0x10, 0x44, 0x54];
+    let pixels = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab];
+    let flag_a_offset = 32 + 48;
+    let flag_b_offset = flag_a_offset + flag_a.len();
+    let pixel_offset = flag_b_offset + flag_b.len();
+
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    for v in [0u16, 0, 15, 1] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    for v in [flag_a_offset, flag_b_offset, flag_b.len(), pixel_offset, pixels.len()] {
+        data.extend_from_slice(&(v as u32).to_le_bytes());
+    }
+    for i in 0..16u8 {
+        data.extend_from_slice(&[i * 16, i * 16, i * 16]);
+    }
+    data.extend_from_slice(&flag_a);
+    data.extend_from_slice(&flag_b);
+    data.extend_from_slice(&pixels);
+    data
+}
+
+/// Single-entry MGR holding a 4x2 24-bit BMP, packed as literal runs
+fn mgr() -> Result<Vec<u8>> {
+    let image = image::RgbImage::from_fn(4, 2, |x, y| image::Rgb([x as u8 * 60, y as u8 * 200, 128]));
+    let mut bmp = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(image).write_to(&mut bmp, image::ImageOutputFormat::Bmp)?;
+    let bmp = bmp.into_inner();
+
+    let mut data = vec![1, 0];
+    data.extend_from_slice(&(bmp.len() as u32).to_le_bytes());
+    let mut packed = Vec::new();
+    for chunk in bmp.chunks(32) {
+        packed.push((chunk.len() - 1) as u8);
+        packed.extend_from_slice(chunk);
+    }
+    data.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+    data.extend_from_slice(&packed);
+    Ok(data)
+}
+
+/// Every vector, in manifest order
+pub fn vectors() -> Result<Vec<Vector>> {
+    let grey = [[0, 0, 0], [255, 255, 255], [128, 128, 128]];
+    let runs: Vec<u8> = (0..32 * 4).map(|i| ((i / 5) % 4) as u8).collect();
+    let files: Vec<(String, Vec<u8>)> = vec![
+        ("C0101.LF2".to_string(), lf2(2, 2, (0, 0), 0, &grey, vec![0, 1, 2, 1])?),
+        ("NOTE.TXT".to_string(), b"synthetic".to_vec()),
+        ("EMPTY.DAT".to_string(), Vec::new()),
+    ];
+    Ok(vec![
+        Vector {
+            name: "lf2-literals",
+            format: FormatType::ToHeartLf2,
+            extension: "lf2",
+            description: "3x2, 3 colours, no repeats: literals only, rows stored bottom-up",
+            input: lf2(3, 2, (0, 0), 2, &grey, vec![0, 1, 2, 2, 1, 0])?,
+        },
+        Vector {
+            name: "lf2-references",
+            format: FormatType::ToHeartLf2,
+            extension: "lf2",
+            description: "32x4 runs of 5 pixels over 4 colours: ring buffer references across rows, x/y offset, transparent index 3",
+            input: lf2(32, 4, (12, 34), 3, &[[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 0, 255]], runs)?,
+        },
+        Vector {
+            name: "pdt-standard",
+            format: FormatType::KanonPdt,
+            extension: "pdt",
+            description: "5x3 PDT10 with the 32-byte header and an alpha mask (every 4th pixel transparent)",
+            input: pdt(5, 3, PdtLayout::Standard),
+        },
+        Vector {
+            name: "pdt-legacy",
+            format: FormatType::KanonPdt,
+            extension: "pdt",
+            description: "5x3 PDT10 with the 28-byte legacy header and no mask",
+            input: pdt(5, 3, PdtLayout::Legacy),
+        },
+        Vector {
+            name: "mag-units",
+            format: FormatType::Pc98Mag,
+            extension: "mag",
+            description: "16x2 MAG: literal units, a copy from the left and whole-line copies",
+            input: mag(),
+        },
+        Vector {
+            name: "mgr-single",
+            format: FormatType::SilkyMgr,
+            extension: "mgr",
+            description: "Single-entry MGR with a 4x2 BMP stored as literal runs",
+            input: mgr()?,
+        },
+        Vector {
+            name: "pak-three",
+            format: FormatType::ToHeartPak,
+            extension: "pak",
+            description: "LEAFPACK with three files (one empty) under a non-zero key",
+            input: crate::formats::toheart::pak::write_archive(&files, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb])?,
+        },
+    ])
+}
+
+/// Step trace of `vector`, for decoders that record steps from memory
+fn trace(vector: &Vector) -> Result<Option<DecodingState>> {
+    let mut state = DecodingState::new();
+    match vector.format {
+        FormatType::ToHeartLf2 => { Lf2Image::from_data_with_steps(&vector.input, &mut state)?; }
+        FormatType::Pc98Mag => { crate::formats::pc98::MagImage::from_data_with_steps(&vector.input, &mut state)?; }
+        _ => return Ok(None),
+    }
+    Ok(Some(state))
+}
+
+/// Write every vector and `manifest.json` into `dir`
+pub fn export(dir: &Path, direct: bool) -> Result<Manifest> {
+    std::fs::create_dir_all(dir)?;
+    let mut records = Vec::new();
+    for vector in vectors()? {
+        let input = format!("{}.{}", vector.name, vector.extension);
+        let input_path = dir.join(&input);
+        crate::output::write_bytes(&input_path, direct, &vector.input)?;
+        let mut record = VectorRecord {
+            name: vector.name.to_string(),
+            format: vector.format.clone(),
+            description: vector.description.to_string(),
+            input,
+            input_sha256: sha256_hex(&vector.input),
+            width: None,
+            height: None,
+            rgba: None,
+            rgba_sha256: None,
+            indices_sha256: None,
+            trace: None,
+            entries: Vec::new(),
+        };
+
+        if vector.format == FormatType::ToHeartPak {
+            let mut pak = crate::formats::toheart::PakArchive::open(&input_path)?;
+            let out = dir.join(vector.name);
+            std::fs::create_dir_all(&out)?;
+            let names: Vec<String> = pak.info().2.iter().map(|e| e.name.clone()).collect();
+            for (index, name) in names.into_iter().enumerate() {
+                let data = pak.read_entry(index)?;
+                crate::output::write_bytes(&out.join(&name), direct, &data)?;
+                record.entries.push(ExtractedFile { name, size: data.len(), sha256: sha256_hex(&data) });
+            }
+        } else {
+            let image = decoder_for(&vector.format)?.decode(&vector.input)?.with_orientation(Orientation::Display);
+            let rgba = format!("{}.rgba", vector.name);
+            crate::output::write_bytes(&dir.join(&rgba), direct, &image.rgba)?;
+            record.width = Some(image.width);
+            record.height = Some(image.height);
+            record.rgba_sha256 = Some(sha256_hex(&image.rgba));
+            record.rgba = Some(rgba);
+            record.indices_sha256 = image.indices.as_deref().map(sha256_hex);
+        }
+
+        if let Some(state) = trace(&vector)? {
+            let name = format!("{}.trace.json", vector.name);
+            crate::trace::TraceFile::new(vector.format.clone(), Path::new(&record.input), state)
+                .save(&dir.join(&name), direct)?;
+            record.trace = Some(name);
+        }
+        records.push(record);
+    }
+
+    let manifest = Manifest {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        trace_version: crate::trace::TRACE_VERSION,
+        vectors: records,
+    };
+    crate::output::write_bytes(&dir.join("manifest.json"), direct, &serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_every_vector() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = export(dir.path(), false).unwrap();
+        assert_eq!(manifest.vectors.len(), vectors().unwrap().len());
+
+        for record in &manifest.vectors {
+            let input = std::fs::read(dir.path().join(&record.input)).unwrap();
+            assert_eq!(sha256_hex(&input), record.input_sha256);
+            match &record.rgba {
+                Some(rgba) => {
+                    let rgba = std::fs::read(dir.path().join(rgba)).unwrap();
+                    let (w, h) = (record.width.unwrap(), record.height.unwrap());
+                    assert_eq!(rgba.len(), (w * h * 4) as usize, "{}", record.name);
+                }
+                None => assert_eq!(record.entries.len(), 3),
+            }
+        }
+
+        let lf2 = &manifest.vectors[1];
+        assert_eq!((lf2.width, lf2.height), (Some(32), Some(4)));
+        let (trace, _) = crate::trace::TraceFile::load(&dir.path().join(lf2.trace.as_ref().unwrap())).unwrap();
+        assert!(!trace.state.steps.is_empty());
+        assert!(manifest.vectors.iter().all(|r| r.format != FormatType::KanonPdt || r.trace.is_none()));
+        assert!(dir.path().join("pak-three/NOTE.TXT").exists());
+    }
+}
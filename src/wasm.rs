@@ -32,14 +32,9 @@ pub struct RgbColor {
 /// Decode LF2 file with step-by-step recording for visualization
 #[wasm_bindgen]
 pub fn decode_lf2_with_steps(data: &[u8]) -> Result<JsValue, JsValue> {
-    let image = Lf2Image::from_data(data)
-        .map_err(|e| JsValue::from_str(&format!("Decode error: {}", e)))?;
-
     let mut state = DecodingState::new();
-
-    // Perform decoding with step recording
-    // Note: This requires modifying Lf2Image::from_bytes to accept DecodingState
-    // For now, we'll create a simplified version
+    let image = Lf2Image::from_data_with_steps(data, &mut state)
+        .map_err(|e| JsValue::from_str(&format!("Decode error: {}", e)))?;
 
     let palette: Vec<RgbColor> = image.palette.iter()
         .map(|c| RgbColor { r: c.r, g: c.g, b: c.b })
@@ -96,3 +91,11 @@ pub fn render_step_image(
 
     rgba
 }
+
+/// Magic numbers, header fields and token grammars of every format with a
+/// fixed signature (see [`crate::formats::magic`])
+#[wasm_bindgen]
+pub fn format_layouts() -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(crate::formats::magic::LAYOUTS)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+}
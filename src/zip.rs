@@ -0,0 +1,389 @@
+//! Reading inputs from inside ZIP archives
+//!
+//! Preserved game dumps are often distributed as a ZIP of the data
+//! directory. Any input path may name a member of such an archive as
+//! `archive.zip!/GRAPH/C0101.LF2`, or a directory inside it as
+//! `archive.zip!/GRAPH`; a bare `archive.zip` stands for its whole contents.
+//!
+//! [`extract`] unpacks the named members into a private temporary
+//! directory that is removed when the returned [`Extracted`] is dropped, so
+//! the decode, inspect and batch code paths run unchanged on real files.
+//! Only stored and deflated members are read; ZIP64, encryption and
+//! multi-disk archives are rejected. 7z archives are not read at all, as
+//! there is no LZMA decoder here: `dump.7z!/...` paths fail with an error
+//! asking for the archive to be unpacked or repacked as ZIP.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use anyhow::{Context, Result, anyhow};
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+
+/// One member of a [`ZipArchive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// Path inside the archive, always `/`-separated
+    pub name: String,
+    /// 0 for stored, 8 for deflate
+    pub method: u16,
+    pub compressed_size: u32,
+    pub size: u32,
+    pub crc32: u32,
+    header_offset: u32,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// A ZIP file held in memory
+pub struct ZipArchive {
+    data: Vec<u8>,
+    entries: Vec<ZipEntry>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("ZIP structure runs past the end of the file"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("ZIP structure runs past the end of the file"))
+}
+
+impl ZipArchive {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Self::from_bytes(data).with_context(|| format!("{} is not a readable ZIP archive", path.display()))
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        // The end record is 22 bytes plus a comment of at most 64 KiB
+        let search_start = data.len().saturating_sub(22 + 0xffff);
+        let eocd = (search_start..data.len().saturating_sub(21))
+            .rev()
+            .find(|&i| u32_at(&data, i).is_ok_and(|sig| sig == END_OF_CENTRAL_DIRECTORY))
+            .ok_or_else(|| anyhow!("no end of central directory record"))?;
+        if u16_at(&data, eocd + 4)? != 0 || u16_at(&data, eocd + 6)? != 0 {
+            return Err(anyhow!("multi-disk archives are not supported"));
+        }
+        let count = u16_at(&data, eocd + 10)? as usize;
+        let mut offset = u32_at(&data, eocd + 16)? as usize;
+        if count == 0xffff || offset == 0xffff_ffff {
+            return Err(anyhow!("ZIP64 archives are not supported"));
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if u32_at(&data, offset)? != CENTRAL_DIRECTORY_HEADER {
+                return Err(anyhow!("bad central directory header at {:#x}", offset));
+            }
+            let flags = u16_at(&data, offset + 8)?;
+            let name_len = u16_at(&data, offset + 28)? as usize;
+            let extra_len = u16_at(&data, offset + 30)? as usize;
+            let comment_len = u16_at(&data, offset + 32)? as usize;
+            let name_bytes = data
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or_else(|| anyhow!("ZIP structure runs past the end of the file"))?;
+            // Bit 11 marks UTF-8 names; older tools wrote the local code
+            // page, which for Japanese dumps is Shift_JIS
+            let name = match std::str::from_utf8(name_bytes) {
+                Ok(name) if flags & 0x0800 != 0 || name.is_ascii() => name.to_string(),
+                _ => crate::romanize::romanize_sjis(name_bytes),
+            };
+            if flags & 0x0001 != 0 {
+                return Err(anyhow!("{} is encrypted", name));
+            }
+            entries.push(ZipEntry {
+                name: name.replace('\\', "/"),
+                method: u16_at(&data, offset + 10)?,
+                crc32: u32_at(&data, offset + 16)?,
+                compressed_size: u32_at(&data, offset + 20)?,
+                size: u32_at(&data, offset + 24)?,
+                header_offset: u32_at(&data, offset + 42)?,
+            });
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(ZipArchive { data, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// The member called `name`, matched exactly or else ignoring ASCII case
+    /// (DOS-era dumps mix `GRAPH/C0101.LF2` and `graph/c0101.lf2`)
+    pub fn entry(&self, name: &str) -> Option<&ZipEntry> {
+        let name = name.trim_start_matches('/');
+        self.entries
+            .iter()
+            .find(|e| e.name == name)
+            .or_else(|| self.entries.iter().find(|e| e.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// Decompressed contents of `entry`
+    pub fn read(&self, entry: &ZipEntry) -> Result<Vec<u8>> {
+        let offset = entry.header_offset as usize;
+        if u32_at(&self.data, offset)? != LOCAL_FILE_HEADER {
+            return Err(anyhow!("{}: bad local file header", entry.name));
+        }
+        let start = offset + 30 + u16_at(&self.data, offset + 26)? as usize + u16_at(&self.data, offset + 28)? as usize;
+        let stored = self
+            .data
+            .get(start..start + entry.compressed_size as usize)
+            .ok_or_else(|| anyhow!("{}: data runs past the end of the archive", entry.name))?;
+        let data = match entry.method {
+            0 => stored.to_vec(),
+            8 => {
+                let mut out = Vec::with_capacity(entry.size as usize);
+                std::io::Read::read_to_end(&mut flate2::read::DeflateDecoder::new(stored), &mut out)
+                    .with_context(|| format!("{}: corrupt deflate stream", entry.name))?;
+                out
+            }
+            method => return Err(anyhow!("{}: compression method {} is not supported", entry.name, method)),
+        };
+        if data.len() != entry.size as usize {
+            return Err(anyhow!("{}: expected {} bytes, got {}", entry.name, entry.size, data.len()));
+        }
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        if crc.sum() != entry.crc32 {
+            return Err(anyhow!("{}: CRC mismatch", entry.name));
+        }
+        Ok(data)
+    }
+}
+
+fn has_extension(path: &Path, wanted: &str) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+}
+
+/// Split `text` at the first `!` that follows an archive with extension
+/// `ext` and starts a member path (or ends the string)
+fn split_archive<'a>(text: &'a str, ext: &str) -> Option<(&'a Path, &'a str)> {
+    let mut search = 0;
+    while let Some(found) = text[search..].find('!').map(|i| search + i) {
+        let archive = Path::new(&text[..found]);
+        let rest = &text[found + 1..];
+        if has_extension(archive, ext) && (rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\')) {
+            return Some((archive, rest));
+        }
+        search = found + 1;
+    }
+    None
+}
+
+/// Split `archive.zip!/GRAPH/C0101.LF2` into the archive and the member path
+///
+/// A bare path to a `.zip` file yields an empty member path. Other paths,
+/// including files that merely contain `!`, return `None`.
+pub fn split_path(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    if let Some((archive, rest)) = split_archive(text, "zip") {
+        let member = rest.replace('\\', "/").trim_matches('/').to_string();
+        return Some((archive.to_path_buf(), member));
+    }
+    (has_extension(path, "zip") && path.is_file()).then(|| (path.to_path_buf(), String::new()))
+}
+
+/// The 7z archive named by `dump.7z!/member` or a bare `dump.7z`, if any
+fn seven_zip_archive(path: &Path) -> Option<PathBuf> {
+    let text = path.to_str()?;
+    match split_archive(text, "7z") {
+        Some((archive, _)) => Some(archive.to_path_buf()),
+        None => (has_extension(path, "7z") && path.is_file()).then(|| path.to_path_buf()),
+    }
+}
+
+/// Members unpacked by [`extract`]; the temporary directory is removed on drop
+#[derive(Debug)]
+pub struct Extracted {
+    root: PathBuf,
+    /// The extracted file, or the directory standing for a folder inside the
+    /// archive
+    pub path: PathBuf,
+}
+
+impl Drop for Extracted {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Where `name` lands under the extraction root, `None` if it would escape it
+fn safe_relative(name: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(name);
+    relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then_some(relative)
+}
+
+/// Unpack the member or folder named by an `archive.zip!/member` path
+///
+/// Returns `None` for ordinary paths, which callers use as they are, and an
+/// error for 7z archives, which are recognised but cannot be read.
+pub fn extract(path: &Path) -> Result<Option<Extracted>> {
+    if let Some(archive) = seven_zip_archive(path) {
+        return Err(anyhow!(
+            "{}: 7z archives are not supported; unpack it or repack it as ZIP",
+            archive.display()
+        ));
+    }
+    let Some((archive_path, member)) = split_path(path) else {
+        return Ok(None);
+    };
+    let archive = ZipArchive::open(&archive_path)?;
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "retro-decode-zip-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&root)?;
+    let mut extracted = Extracted { path: root.clone(), root };
+
+    if let Some(entry) = archive.entry(&member).filter(|e| !e.is_dir()) {
+        let relative = safe_relative(&entry.name)
+            .ok_or_else(|| anyhow!("{}: unsafe member path", entry.name))?;
+        extracted.path = extracted.root.join(relative);
+        std::fs::create_dir_all(extracted.path.parent().unwrap_or(&extracted.root))?;
+        std::fs::write(&extracted.path, archive.read(entry)?)?;
+        return Ok(Some(extracted));
+    }
+
+    let prefix = if member.is_empty() { String::new() } else { format!("{}/", member) };
+    let mut folder = None;
+    for entry in archive.entries().iter().filter(|e| !e.is_dir()) {
+        if entry.name.len() < prefix.len() || !entry.name[..prefix.len()].eq_ignore_ascii_case(&prefix) {
+            continue;
+        }
+        let Some(relative) = safe_relative(&entry.name) else {
+            log::warn!("{}: skipping unsafe member path {}", archive_path.display(), entry.name);
+            continue;
+        };
+        let target = extracted.root.join(relative);
+        std::fs::create_dir_all(target.parent().unwrap_or(&extracted.root))?;
+        std::fs::write(&target, archive.read(entry)?)?;
+        // Keep the folder's spelling from the archive, whatever the case typed
+        folder.get_or_insert_with(|| entry.name[..prefix.len()].trim_end_matches('/').to_string());
+    }
+    match folder {
+        Some(folder) => extracted.path = extracted.root.join(folder),
+        None => return Err(anyhow!("{} has no member {}", archive_path.display(), member)),
+    }
+    Ok(Some(extracted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A ZIP of `(name, data, deflate)` members
+    fn build_zip(members: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for (name, data, deflate) in members {
+            let stored = if *deflate {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                data.to_vec()
+            };
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let method: u16 = if *deflate { 8 } else { 0 };
+            let offset = out.len() as u32;
+
+            let mut common = Vec::new();
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+            common.extend_from_slice(&method.to_le_bytes());
+            common.extend_from_slice(&[0; 4]);
+            common.extend_from_slice(&crc.sum().to_le_bytes());
+            common.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+
+            out.extend_from_slice(&LOCAL_FILE_HEADER.to_le_bytes());
+            out.extend_from_slice(&common);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&stored);
+
+            central.extend_from_slice(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes());
+            central.extend_from_slice(&common);
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&central_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn extracts_members_and_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("dump.zip");
+        let lf2 = b"LEAF256\0 pretend image data, repeated repeated repeated".to_vec();
+        std::fs::write(
+            &zip_path,
+            build_zip(&[
+                ("GRAPH/C0101.LF2", &lf2, true),
+                ("GRAPH/C0102.LF2", b"stored", false),
+                ("../escape.lf2", b"no", false),
+                ("README.TXT", b"hi", false),
+            ]),
+        )
+        .unwrap();
+
+        let archive = ZipArchive::open(&zip_path).unwrap();
+        assert_eq!(archive.entries().len(), 4);
+        assert_eq!(archive.read(archive.entry("graph/c0101.lf2").unwrap()).unwrap(), lf2);
+
+        let member = PathBuf::from(format!("{}!/GRAPH/C0101.LF2", zip_path.display()));
+        assert_eq!(split_path(&member), Some((zip_path.clone(), "GRAPH/C0101.LF2".to_string())));
+        assert_eq!(split_path(Path::new("odd!name.lf2")), None);
+        let file = extract(&member).unwrap().unwrap();
+        assert_eq!(file.path.file_name().unwrap(), "C0101.LF2");
+        assert_eq!(std::fs::read(&file.path).unwrap(), lf2);
+
+        let folder = extract(&PathBuf::from(format!("{}!/graph", zip_path.display()))).unwrap().unwrap();
+        assert!(folder.path.ends_with("GRAPH"));
+        assert!(folder.path.join("C0102.LF2").is_file());
+        assert!(!folder.path.join("../README.TXT").exists());
+
+        let whole = extract(&zip_path).unwrap().unwrap();
+        assert!(whole.path.join("README.TXT").is_file());
+        assert!(!whole.path.parent().unwrap().join("escape.lf2").exists());
+        let root = whole.path.clone();
+        drop(whole);
+        assert!(!root.exists());
+
+        assert!(extract(Path::new("plain.lf2")).unwrap().is_none());
+        assert!(extract(&PathBuf::from(format!("{}!/MISSING", zip_path.display()))).is_err());
+
+        let error = extract(Path::new("dump.7z!/GRAPH/C0101.LF2")).unwrap_err().to_string();
+        assert!(error.starts_with("dump.7z: 7z archives are not supported"), "{}", error);
+    }
+}
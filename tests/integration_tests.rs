@@ -4,8 +4,7 @@ use tempfile::TempDir;
 
 /// Helper function to run retro-decode command and capture output
 fn run_retro_decode(args: &[&str]) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-    let output = Command::new("cargo")
-        .args(["run", "--"])
+    let output = Command::new(env!("CARGO_BIN_EXE_retro-decode"))
         .args(args)
         .output()?;
     Ok(output)
@@ -276,6 +275,63 @@ fn test_conflicting_input_options() {
     assert!(!output.status.success(), "Command should fail with conflicting input options");
 }
 
+/// Test that `pack` and `extract` round-trip and that `decode` refuses archives
+#[test]
+fn test_pack_extract_subcommands() {
+    let temp_dir = TempDir::new().unwrap();
+    let input_dir = temp_dir.path().join("files");
+    std::fs::create_dir_all(&input_dir).unwrap();
+    let files = [("A.TXT", &b"leaf"[..]), ("B.DAT", &[0u8, 1, 2, 255][..]), ("C.TXT", &b""[..])];
+    for (name, data) in files {
+        std::fs::write(input_dir.join(name), data).unwrap();
+    }
+    let archive = temp_dir.path().join("TEST.PAK");
+    let extracted = temp_dir.path().join("extracted");
+
+    let output = run_retro_decode(&["pack", input_dir.to_str().unwrap(), "-o", archive.to_str().unwrap()]).unwrap();
+    assert!(output.status.success(), "pack failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run_retro_decode(&["extract", archive.to_str().unwrap(), "-o", extracted.to_str().unwrap()]).unwrap();
+    assert!(output.status.success(), "extract failed: {}", String::from_utf8_lossy(&output.stderr));
+    for (name, data) in files {
+        assert_eq!(std::fs::read(extracted.join(name)).unwrap(), data, "{} differs", name);
+    }
+
+    let output = run_retro_decode(&["decode", archive.to_str().unwrap(), "-o", extracted.to_str().unwrap()]).unwrap();
+    assert!(!output.status.success(), "decode should point archives to extract");
+}
+
+#[test]
+fn test_recursive_batch_mirrors_layout() {
+    let temp_dir = TempDir::new().unwrap();
+    let input_dir = temp_dir.path().join("game");
+    let nested = input_dir.join("data/sprites");
+    let output_dir = temp_dir.path().join("out");
+
+    let output = run_retro_decode(&["export-vectors", "-o", nested.to_str().unwrap()]).unwrap();
+    assert!(output.status.success(), "export-vectors failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run_retro_decode(&["decode", input_dir.to_str().unwrap(), "-o", output_dir.to_str().unwrap()]).unwrap();
+    assert!(output.status.success());
+    assert!(!output_dir.join("data").exists(), "subdirectories need --recursive");
+
+    let output = run_retro_decode(&["decode", input_dir.to_str().unwrap(), "-r", "-o", output_dir.to_str().unwrap()]).unwrap();
+    assert!(output.status.success(), "recursive decode failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(output_dir.join("data/sprites/lf2-literals.bmp").exists());
+    assert!(output_dir.join("data/sprites/pdt-standard.bmp").exists());
+    assert!(!output_dir.join("lf2-literals.bmp").exists());
+
+    let filtered = temp_dir.path().join("filtered");
+    let output = run_retro_decode(&[
+        "decode", input_dir.to_str().unwrap(), "-r", "--include", "LF2-*", "--exclude", "*references*",
+        "-o", filtered.to_str().unwrap(),
+    ]).unwrap();
+    assert!(output.status.success(), "filtered decode failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(filtered.join("data/sprites/lf2-literals.bmp").exists());
+    assert!(!filtered.join("data/sprites/lf2-references.bmp").exists());
+    assert!(!filtered.join("data/sprites/pdt-standard.bmp").exists());
+}
+
 /// Test different output formats
 #[test]
 fn test_output_formats() {
@@ -0,0 +1,96 @@
+//! Malformed header regression suite
+//!
+//! Decoders must turn corrupt or hostile headers into errors, never panics
+//! or multi-gigabyte allocations. The hand-written cases pin the structured
+//! error for absurd dimensions; the mutation sweep overwrites each header
+//! byte of the conformance vectors with boundary values, the way a fuzzer
+//! would, and keeps every combination that once panicked covered.
+
+use retro_decode::decoder::{decoder_for, DecodeLimits, Error, MAX_DIMENSION};
+use retro_decode::formats::toheart::lf2::{Lf2Image, Rgb};
+use retro_decode::FormatType;
+use std::panic::AssertUnwindSafe;
+
+/// Header bytes worth mutating: enough to cover every fixed header and the
+/// start of the MAG header behind its comment
+const HEADER_BYTES: usize = 96;
+const BOUNDARY_VALUES: [u8; 6] = [0x00, 0x01, 0x7f, 0x80, 0xfe, 0xff];
+
+fn lf2(width: u16, height: u16) -> Vec<u8> {
+    let mut data = Lf2Image {
+        width: 1,
+        height: 1,
+        x_offset: 0,
+        y_offset: 0,
+        transparent_color: 0,
+        color_count: 1,
+        palette: vec![Rgb { r: 0, g: 0, b: 0 }],
+        pixels: vec![0],
+    }
+    .to_lf2_bytes()
+    .unwrap();
+    data[12..14].copy_from_slice(&width.to_le_bytes());
+    data[14..16].copy_from_slice(&height.to_le_bytes());
+    data
+}
+
+fn pdt(width: u32, height: u32) -> Vec<u8> {
+    let mut data = b"PDT10\0\0\0".to_vec();
+    data.extend_from_slice(&32u32.to_le_bytes());
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.resize(32, 0);
+    data
+}
+
+fn assert_bad_dimensions(format: FormatType, data: &[u8], expected: (u64, u64)) {
+    match decoder_for(&format).unwrap().decode(data) {
+        Err(Error::BadDimensions { width, height, .. }) => assert_eq!((width, height), expected, "{}", format),
+        other => panic!("{} {:?}: expected BadDimensions, got {:?}", format, expected, other.map(|i| (i.width, i.height))),
+    }
+}
+
+#[test]
+fn absurd_dimensions_are_reported() {
+    assert_bad_dimensions(FormatType::ToHeartLf2, &lf2(0, 10), (0, 10));
+    assert_bad_dimensions(FormatType::ToHeartLf2, &lf2(10, 0), (10, 0));
+    assert_bad_dimensions(FormatType::ToHeartLf2, &lf2(0xffff, 0xffff), (0xffff, 0xffff));
+    assert_bad_dimensions(FormatType::KanonPdt, &pdt(0, 0), (0, 0));
+    assert_bad_dimensions(FormatType::KanonPdt, &pdt(640, 16385), (640, 16385));
+    assert_bad_dimensions(FormatType::KanonPdt, &pdt(u32::MAX, u32::MAX), (u32::MAX as u64, u32::MAX as u64));
+    assert!(decoder_for(&FormatType::KanonPdt).unwrap().decode(&pdt(16384, 1)).is_ok());
+
+    let message = decoder_for(&FormatType::ToHeartLf2).unwrap().decode(&lf2(0, 10)).unwrap_err().to_string();
+    assert!(message.contains("0x10"), "{}", message);
+    assert!(message.contains(&MAX_DIMENSION.to_string()), "{}", message);
+}
+
+#[test]
+fn mutated_headers_never_panic() {
+    let limits = DecodeLimits { max_output_bytes: Some(64 << 20), ..Default::default() };
+    for vector in retro_decode::vectors::vectors().unwrap() {
+        let Ok(decoder) = decoder_for(&vector.format) else { continue };
+        for offset in 0..HEADER_BYTES.min(vector.input.len()) {
+            for value in BOUNDARY_VALUES {
+                let mut data = vector.input.clone();
+                data[offset] = value;
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| decoder.decode_with_limits(&data, &limits)));
+                let Ok(result) = result else {
+                    panic!("{}: byte {:#x} = {:#04x} panicked", vector.name, offset, value);
+                };
+                if let Ok(image) = result {
+                    assert!((1..=MAX_DIMENSION).contains(&(image.width as u64)), "{} byte {:#x}", vector.name, offset);
+                    assert!((1..=MAX_DIMENSION).contains(&(image.height as u64)), "{} byte {:#x}", vector.name, offset);
+                    assert_eq!(image.rgba.len(), image.width as usize * image.height as usize * 4, "{} byte {:#x}", vector.name, offset);
+                }
+            }
+        }
+        // Truncation at every length inside the header
+        for len in 0..HEADER_BYTES.min(vector.input.len()) {
+            let data = &vector.input[..len];
+            if std::panic::catch_unwind(AssertUnwindSafe(|| decoder.decode_with_limits(data, &limits))).is_err() {
+                panic!("{}: truncation to {} bytes panicked", vector.name, len);
+            }
+        }
+    }
+}
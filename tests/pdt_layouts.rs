@@ -0,0 +1,47 @@
+//! Regression fixtures for both PDT10 header layouts.
+//!
+//! The fixtures in `test_assets/pdt/synthetic_*.pdt` are produced by
+//! `cargo run --example generate_test_assets`. Early releases ship PDT files
+//! without the mask offset field; these used to be mis-parsed as the standard
+//! layout with a garbage mask offset.
+
+use std::path::PathBuf;
+
+use retro_decode::formats::kanon::pdt::{PdtImage, PdtLayout};
+
+fn fixtures() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir("test_assets/pdt")
+        .expect("test_assets/pdt")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with("synthetic_")))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdt")))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn synthetic_fixtures_decode_with_detected_layout() {
+    let files = fixtures();
+    assert!(files.len() >= 2, "expected standard and legacy fixtures");
+
+    for path in files {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let expected = if name.contains("legacy") { PdtLayout::Legacy } else { PdtLayout::Standard };
+
+        let image = PdtImage::open(&path).unwrap();
+        assert_eq!(image.layout, expected, "{}", name);
+        assert_eq!((image.width, image.height), (8, 8), "{}", name);
+
+        // Gradient: pixel (x, y) = (x*32, y*32, (x+y)*16)
+        let last = image.pixels[63];
+        assert_eq!((last.r, last.g, last.b), (224, 224, 224), "{}", name);
+
+        let transparent = image.alpha_mask.iter().filter(|&&a| a == 0).count();
+        match expected {
+            PdtLayout::Standard => assert_eq!(transparent, 8, "{}", name),
+            PdtLayout::Legacy => assert_eq!(transparent, 0, "{}", name),
+        }
+    }
+}
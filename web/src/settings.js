@@ -0,0 +1,40 @@
+/**
+ * Display settings - theme and UI scale, kept in localStorage
+ */
+
+import { writable } from 'svelte/store';
+
+const STORAGE_KEY = 'retro-decode.display';
+
+export const THEMES = ['light', 'dark'];
+export const SCALES = [100, 125, 150, 175, 200];
+
+function load() {
+  const defaults = {
+    theme: window.matchMedia?.('(prefers-color-scheme: dark)').matches ? 'dark' : 'light',
+    scale: 100,
+  };
+  try {
+    const saved = JSON.parse(localStorage.getItem(STORAGE_KEY) || '{}');
+    return {
+      theme: THEMES.includes(saved.theme) ? saved.theme : defaults.theme,
+      scale: SCALES.includes(saved.scale) ? saved.scale : defaults.scale,
+    };
+  } catch {
+    return defaults;
+  }
+}
+
+export const display = writable(load());
+
+// 変更を保存し、ルート要素に反映（全スタイルは rem 単位なので font-size で拡大）
+display.subscribe(({ theme, scale }) => {
+  const root = document.documentElement;
+  root.dataset.theme = theme;
+  root.style.fontSize = `${scale}%`;
+  try {
+    localStorage.setItem(STORAGE_KEY, JSON.stringify({ theme, scale }));
+  } catch {
+    // プライベートモードなどで保存できなくても表示は切り替える
+  }
+});